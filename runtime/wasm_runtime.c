@@ -4,23 +4,47 @@
  * This runtime provides support functions for WASM programs compiled by waq.
  */
 
+/* mmap flags and sigaction are POSIX extensions hidden by -std=c11 */
+#define _DEFAULT_SOURCE
+
 #include "wasm_runtime.h"
 #include <stdlib.h>
 #include <string.h>
 #include <stdio.h>
 #include <math.h>
 #include <fenv.h>
+#include <signal.h>
+#include <unistd.h>
+#include <sys/mman.h>
+#include <setjmp.h>
 
 /* ============== Memory state ============== */
 
 uint8_t* __wasm_memory = NULL;
 uint32_t __wasm_memory_size = 0;
+uint64_t __wasm_memory_size_bytes = 0;
 static uint32_t memory_pages = 0;
+static uint32_t memory_max_pages = WASM_MAX_PAGES;  /* declared max of memory 0 */
 
 /* ============== Table state ============== */
 
 void** __wasm_table = NULL;
 uint32_t __wasm_table_size = 0;
+int32_t* __wasm_table_sigs = NULL;  /* canonical type id per entry, -1 if none */
+
+/* Every table; table 0 is mirrored in the __wasm_table globals */
+#define MAX_TABLES 16
+#define MAX_TABLE_SIZE 65536
+
+typedef struct {
+    void** elems;
+    int32_t* sigs;
+    uint32_t size;
+    uint32_t max;
+    int has_max;
+} Table;
+
+static Table tables[MAX_TABLES];
 
 /* ============== Integer intrinsics ============== */
 
@@ -182,15 +206,86 @@ int32_t __wasm_memory_size_pages(void) {
     return (int32_t)memory_pages;
 }
 
+
+/* Guard region: 4 GiB index space + 4 GiB offset guard (--bounds-checks=guard) */
+#define WASM_GUARD_REGION_SIZE ((size_t)8 << 30)
+
+static uint8_t* guard_region = NULL;
+
+static void guard_fault_handler(int sig, siginfo_t* info, void* ucontext) {
+    (void)ucontext;
+    uint8_t* addr = (uint8_t*)info->si_addr;
+    if (guard_region && addr >= guard_region &&
+        addr < guard_region + WASM_GUARD_REGION_SIZE) {
+        /* Only async-signal-safe calls here; abort like waq_runtime.c does */
+        static const char msg[] = "wasm trap: out of bounds memory access\n";
+        ssize_t written = write(STDERR_FILENO, msg, sizeof(msg) - 1);
+        (void)written;
+        abort();
+    }
+    signal(sig, SIG_DFL);
+    raise(sig);
+}
+
+void __wasm_memory_guard_init(void) {
+    if (guard_region) return;
+
+    void* region = mmap(NULL, WASM_GUARD_REGION_SIZE, PROT_NONE,
+                        MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+    if (region == MAP_FAILED) {
+        fprintf(stderr, "wasm: failed to reserve guard region\n");
+        exit(1);
+    }
+
+    /* Carry over memory allocated by __wasm_init */
+    size_t size = (size_t)memory_pages * WASM_PAGE_SIZE;
+    if (size > 0) {
+        if (mprotect(region, size, PROT_READ | PROT_WRITE) != 0) {
+            fprintf(stderr, "wasm: failed to commit memory\n");
+            exit(1);
+        }
+        memcpy(region, __wasm_memory, size);
+    }
+    free(__wasm_memory);
+
+    guard_region = (uint8_t*)region;
+    __wasm_memory = guard_region;
+
+    struct sigaction sa;
+    memset(&sa, 0, sizeof(sa));
+    sa.sa_sigaction = guard_fault_handler;
+    sa.sa_flags = SA_SIGINFO;
+    sigemptyset(&sa.sa_mask);
+    sigaction(SIGSEGV, &sa, NULL);
+    sigaction(SIGBUS, &sa, NULL);
+}
+
 int32_t __wasm_memory_grow(int32_t pages) {
     if (pages < 0) return -1;
 
     uint32_t old_pages = memory_pages;
+    if (old_pages > memory_max_pages ||
+        (uint32_t)pages > memory_max_pages - old_pages) {
+        return -1;
+    }
     uint32_t new_pages = old_pages + (uint32_t)pages;
 
-    if (new_pages > WASM_MAX_PAGES) return -1;
-
     size_t new_size = (size_t)new_pages * WASM_PAGE_SIZE;
+
+    if (guard_region) {
+        /* Commit in place; the memory never moves */
+        size_t old_size = (size_t)old_pages * WASM_PAGE_SIZE;
+        if (pages > 0 &&
+            mprotect(guard_region + old_size, new_size - old_size,
+                     PROT_READ | PROT_WRITE) != 0) {
+            return -1;
+        }
+        __wasm_memory_size = (uint32_t)new_size;
+        __wasm_memory_size_bytes = (uint64_t)new_size;
+        memory_pages = new_pages;
+        return (int32_t)old_pages;
+    }
+
     uint8_t* new_mem = realloc(__wasm_memory, new_size);
     if (!new_mem) return -1;
 
@@ -200,83 +295,397 @@ int32_t __wasm_memory_grow(int32_t pages) {
 
     __wasm_memory = new_mem;
     __wasm_memory_size = (uint32_t)new_size;
+    __wasm_memory_size_bytes = (uint64_t)new_size;
     memory_pages = new_pages;
 
     return (int32_t)old_pages;
 }
 
-void __wasm_memory_init_seg(int32_t seg, int32_t dest, int32_t src, int32_t len) {
-    /* TODO: Implement data segment handling */
-    (void)seg; (void)dest; (void)src; (void)len;
+/*
+ * Memories other than memory 0, which stays in the __wasm_memory globals.
+ * Each has its own buffer, size and maximum; slot 0 is unused.
+ */
+#define MAX_MEMORIES 16
+
+typedef struct {
+    uint8_t* data;
+    uint32_t pages;
+    uint32_t max_pages;
+    int has_max;
+} Memory;
+
+static Memory memories[MAX_MEMORIES];
+
+static Memory* memory_at(int32_t mem_idx) {
+    if (mem_idx < 1 || mem_idx >= MAX_MEMORIES) {
+        fprintf(stderr, "wasm: memory index %d out of range\n", mem_idx);
+        exit(1);
+    }
+    return &memories[mem_idx];
 }
 
-void __wasm_data_drop(int32_t seg) {
-    /* TODO: Implement data segment dropping */
-    (void)seg;
+void __wasm_memory_set_max(int32_t mem_idx, int32_t max_pages) {
+    uint32_t max = (uint32_t)max_pages;
+    if (max > WASM_MAX_PAGES) max = WASM_MAX_PAGES;
+    if (mem_idx == 0) {
+        memory_max_pages = max;
+        return;
+    }
+    Memory* mem = memory_at(mem_idx);
+    mem->max_pages = max;
+    mem->has_max = 1;
+}
+
+uint8_t* __wasm_memory_base_idx(int32_t mem_idx) {
+    if (mem_idx == 0) return __wasm_memory;
+    return memory_at(mem_idx)->data;
 }
 
-void __wasm_memory_copy(int32_t dest, int32_t src, int32_t len) {
-    if (len <= 0) return;
-    if ((uint32_t)(dest + len) > __wasm_memory_size ||
-        (uint32_t)(src + len) > __wasm_memory_size) {
+int32_t __wasm_memory_size_pages_idx(int32_t mem_idx) {
+    if (mem_idx == 0) return (int32_t)memory_pages;
+    return (int32_t)memory_at(mem_idx)->pages;
+}
+
+uint64_t __wasm_memory_size_bytes_idx(int32_t mem_idx) {
+    if (mem_idx == 0) return __wasm_memory_size_bytes;
+    return (uint64_t)memory_at(mem_idx)->pages * WASM_PAGE_SIZE;
+}
+
+int32_t __wasm_memory_grow_idx(int32_t mem_idx, int32_t pages) {
+    if (mem_idx == 0) return __wasm_memory_grow(pages);
+    if (pages < 0) return -1;
+
+    Memory* mem = memory_at(mem_idx);
+    uint32_t max = mem->has_max ? mem->max_pages : WASM_MAX_PAGES;
+    uint32_t old_pages = mem->pages;
+    if (old_pages > max || (uint32_t)pages > max - old_pages) return -1;
+
+    uint32_t new_pages = old_pages + (uint32_t)pages;
+    size_t new_size = (size_t)new_pages * WASM_PAGE_SIZE;
+    uint8_t* new_mem = realloc(mem->data, new_size);
+    if (!new_mem && new_size > 0) return -1;
+
+    /* Zero new pages */
+    if (pages > 0) {
+        memset(new_mem + (size_t)old_pages * WASM_PAGE_SIZE, 0,
+               (size_t)pages * WASM_PAGE_SIZE);
+    }
+
+    mem->data = new_mem;
+    mem->pages = new_pages;
+    return (int32_t)old_pages;
+}
+
+int64_t __wasm_memory_size_pages64(int32_t mem_idx) {
+    return (int64_t)__wasm_memory_size_pages_idx(mem_idx);
+}
+
+int64_t __wasm_memory_grow64(int32_t mem_idx, int64_t pages) {
+    if (pages < 0 || pages > WASM_MAX_PAGES) return -1;
+    return (int64_t)__wasm_memory_grow_idx(mem_idx, (int32_t)pages);
+}
+
+/* Passive data segments, registered at startup */
+typedef struct {
+    const uint8_t* data;
+    size_t size;
+} DataSegment;
+
+#define MAX_DATA_SEGMENTS 256
+static DataSegment data_segments[MAX_DATA_SEGMENTS];
+
+void __wasm_register_data_segment(int32_t seg, const uint8_t* data, size_t size) {
+    if (seg < 0 || seg >= MAX_DATA_SEGMENTS) return;
+    data_segments[seg].data = data;
+    data_segments[seg].size = size;
+}
+
+/* Bytes left in a data segment (none once dropped) */
+static size_t data_segment_size(int32_t seg) {
+    if (seg < 0 || seg >= MAX_DATA_SEGMENTS) return 0;
+    return data_segments[seg].size;
+}
+
+/* A range of a memory must lie within its current size */
+static void check_memory_range(int32_t mem_idx, uint64_t addr, uint64_t len) {
+    uint64_t size = __wasm_memory_size_bytes_idx(mem_idx);
+    if (addr > size || len > size - addr) {
         __wasm_trap_out_of_bounds();
     }
-    memmove(__wasm_memory + dest, __wasm_memory + src, (size_t)len);
 }
 
-void __wasm_memory_fill(int32_t dest, int32_t val, int32_t len) {
-    if (len <= 0) return;
-    if ((uint32_t)(dest + len) > __wasm_memory_size) {
+/* Address of a checked range of a memory, e.g. for an active data segment */
+uint8_t* __wasm_memory_buffer(int32_t mem_idx, uint64_t addr, uint64_t len) {
+    check_memory_range(mem_idx, addr, len);
+    return __wasm_memory_base_idx(mem_idx) + addr;
+}
+
+void __wasm_memory_init_seg_idx(int32_t mem_idx, int32_t seg, uint64_t dest,
+                                uint32_t src, uint32_t len) {
+    if ((uint64_t)src + len > data_segment_size(seg)) {
         __wasm_trap_out_of_bounds();
     }
-    memset(__wasm_memory + dest, val & 0xFF, (size_t)len);
+    check_memory_range(mem_idx, dest, len);
+    if (len == 0) return;
+    memcpy(__wasm_memory_base_idx(mem_idx) + dest, data_segments[seg].data + src, len);
+}
+
+void __wasm_memory_init_seg(int32_t seg, uint64_t dest, uint32_t src, uint32_t len) {
+    __wasm_memory_init_seg_idx(0, seg, dest, src, len);
+}
+
+void __wasm_data_drop(int32_t seg) {
+    if (seg < 0 || seg >= MAX_DATA_SEGMENTS) return;
+    data_segments[seg].data = NULL;
+    data_segments[seg].size = 0;
+}
+
+void __wasm_memory_copy_idx(int32_t dest_mem, int32_t src_mem, uint64_t dest,
+                            uint64_t src, uint64_t len) {
+    check_memory_range(dest_mem, dest, len);
+    check_memory_range(src_mem, src, len);
+    if (len == 0) return;
+    memmove(__wasm_memory_base_idx(dest_mem) + dest,
+            __wasm_memory_base_idx(src_mem) + src, (size_t)len);
+}
+
+void __wasm_memory_copy(uint64_t dest, uint64_t src, uint64_t len) {
+    __wasm_memory_copy_idx(0, 0, dest, src, len);
+}
+
+void __wasm_memory_fill_idx(int32_t mem_idx, uint64_t dest, int32_t val, uint64_t len) {
+    check_memory_range(mem_idx, dest, len);
+    if (len == 0) return;
+    memset(__wasm_memory_base_idx(mem_idx) + dest, val & 0xFF, (size_t)len);
+}
+
+void __wasm_memory_fill(uint64_t dest, int32_t val, uint64_t len) {
+    __wasm_memory_fill_idx(0, dest, val, len);
+}
+
+/* ============== Function signature registry ============== */
+
+/* Function pointer -> canonical type id, open addressing (power-of-two size) */
+typedef struct {
+    void* func;
+    int32_t sig;
+} FuncSig;
+
+static FuncSig* func_sigs = NULL;
+static size_t func_sigs_capacity = 0;
+static size_t func_sigs_count = 0;
+
+static size_t func_sig_slot(void* func, size_t capacity) {
+    uintptr_t h = (uintptr_t)func;
+    h ^= h >> 17;
+    h *= 0x9E3779B97F4A7C15ULL;
+    return (size_t)(h >> 7) & (capacity - 1);
+}
+
+void __wasm_func_sig_register(void* func, int32_t sig) {
+    if (!func) return;
+
+    if ((func_sigs_count + 1) * 2 > func_sigs_capacity) {
+        size_t new_capacity = func_sigs_capacity ? func_sigs_capacity * 2 : 64;
+        FuncSig* new_sigs = calloc(new_capacity, sizeof(FuncSig));
+        if (!new_sigs) {
+            fprintf(stderr, "wasm: out of memory registering signatures\n");
+            exit(1);
+        }
+        for (size_t i = 0; i < func_sigs_capacity; i++) {
+            if (!func_sigs[i].func) continue;
+            size_t slot = func_sig_slot(func_sigs[i].func, new_capacity);
+            while (new_sigs[slot].func) slot = (slot + 1) & (new_capacity - 1);
+            new_sigs[slot] = func_sigs[i];
+        }
+        free(func_sigs);
+        func_sigs = new_sigs;
+        func_sigs_capacity = new_capacity;
+    }
+
+    size_t slot = func_sig_slot(func, func_sigs_capacity);
+    while (func_sigs[slot].func && func_sigs[slot].func != func) {
+        slot = (slot + 1) & (func_sigs_capacity - 1);
+    }
+    if (!func_sigs[slot].func) func_sigs_count++;
+    func_sigs[slot].func = func;
+    func_sigs[slot].sig = sig;
+}
+
+static int32_t func_sig_lookup(void* func) {
+    if (!func || func_sigs_capacity == 0) return -1;
+    size_t slot = func_sig_slot(func, func_sigs_capacity);
+    while (func_sigs[slot].func) {
+        if (func_sigs[slot].func == func) return func_sigs[slot].sig;
+        slot = (slot + 1) & (func_sigs_capacity - 1);
+    }
+    return -1;
 }
 
 /* ============== Table operations ============== */
 
-void* __wasm_table_get(int32_t idx) {
-    if (idx < 0 || (uint32_t)idx >= __wasm_table_size) {
-        __wasm_trap_out_of_bounds();
+static Table* table_at(int32_t table) {
+    if (table < 0 || table >= MAX_TABLES) {
+        fprintf(stderr, "wasm: table index %d out of range\n", table);
+        exit(1);
     }
-    return __wasm_table[idx];
+    return &tables[table];
 }
 
-void __wasm_table_set(int32_t idx, void* val) {
-    if (idx < 0 || (uint32_t)idx >= __wasm_table_size) {
-        __wasm_trap_out_of_bounds();
+/* Refresh the table 0 globals after its storage changed */
+static void sync_table(int32_t table) {
+    if (table != 0) return;
+    __wasm_table = tables[0].elems;
+    __wasm_table_sigs = tables[0].sigs;
+    __wasm_table_size = tables[0].size;
+}
+
+void __wasm_trap_table_out_of_bounds(void) {
+    fprintf(stderr, "wasm trap: out of bounds table access\n");
+    exit(1);
+}
+
+static void check_table_range(Table* t, int32_t offset, int32_t len) {
+    if ((uint64_t)(uint32_t)offset + (uint32_t)len > t->size) {
+        __wasm_trap_table_out_of_bounds();
     }
-    __wasm_table[idx] = val;
 }
 
-void __wasm_table_init(int32_t table, int32_t elem, int32_t dest, int32_t src, int32_t len) {
-    /* TODO: Implement element segment initialization */
-    (void)table; (void)elem; (void)dest; (void)src; (void)len;
+void __wasm_table_set_max(int32_t table, int32_t max) {
+    Table* t = table_at(table);
+    t->max = (uint32_t)max;
+    t->has_max = 1;
+}
+
+void* __wasm_table_get(int32_t table, int32_t idx) {
+    Table* t = table_at(table);
+    if ((uint32_t)idx >= t->size) __wasm_trap_table_out_of_bounds();
+    return t->elems[(uint32_t)idx];
+}
+
+void __wasm_table_set(int32_t table, int32_t idx, void* val) {
+    Table* t = table_at(table);
+    if ((uint32_t)idx >= t->size) __wasm_trap_table_out_of_bounds();
+    t->elems[(uint32_t)idx] = val;
+    t->sigs[(uint32_t)idx] = func_sig_lookup(val);
+}
+
+void** __wasm_table_elems(int32_t table) {
+    return table_at(table)->elems;
+}
+
+int32_t* __wasm_table_sigs_idx(int32_t table) {
+    return table_at(table)->sigs;
+}
+
+/* Passive element segments, registered at startup */
+typedef struct {
+    void** funcs;
+    int32_t count;
+} ElemSegment;
+
+#define MAX_ELEM_SEGMENTS 256
+static ElemSegment elem_segments[MAX_ELEM_SEGMENTS];
+
+void __wasm_register_elem_segment(int32_t elem, int32_t count) {
+    if (elem < 0 || elem >= MAX_ELEM_SEGMENTS || count <= 0) return;
+    void** funcs = calloc((size_t)count, sizeof(void*));
+    if (!funcs) {
+        fprintf(stderr, "wasm: out of memory registering element segments\n");
+        exit(1);
+    }
+    free(elem_segments[elem].funcs);
+    elem_segments[elem].funcs = funcs;
+    elem_segments[elem].count = count;
+}
+
+void __wasm_elem_segment_set(int32_t elem, int32_t i, void* func) {
+    if (elem < 0 || elem >= MAX_ELEM_SEGMENTS) return;
+    if (i >= 0 && i < elem_segments[elem].count) {
+        elem_segments[elem].funcs[i] = func;
+    }
+}
+
+/* Functions left in an element segment (none once dropped) */
+static uint32_t elem_segment_size(int32_t elem) {
+    if (elem < 0 || elem >= MAX_ELEM_SEGMENTS) return 0;
+    return (uint32_t)elem_segments[elem].count;
 }
 
 void __wasm_elem_drop(int32_t elem) {
-    /* TODO: Implement element segment dropping */
-    (void)elem;
+    if (elem < 0 || elem >= MAX_ELEM_SEGMENTS) return;
+    free(elem_segments[elem].funcs);
+    elem_segments[elem].funcs = NULL;
+    elem_segments[elem].count = 0;
+}
+
+void __wasm_table_init(int32_t table, int32_t elem, int32_t dest, int32_t src, int32_t len) {
+    Table* t = table_at(table);
+    if ((uint64_t)(uint32_t)src + (uint32_t)len > elem_segment_size(elem)) {
+        __wasm_trap_table_out_of_bounds();
+    }
+    check_table_range(t, dest, len);
+    for (uint32_t i = 0; i < (uint32_t)len; i++) {
+        void* func = elem_segments[elem].funcs[(uint32_t)src + i];
+        t->elems[(uint32_t)dest + i] = func;
+        t->sigs[(uint32_t)dest + i] = func_sig_lookup(func);
+    }
 }
 
 void __wasm_table_copy(int32_t dest_table, int32_t src_table, int32_t dest, int32_t src, int32_t len) {
-    /* TODO: Implement table copy */
-    (void)dest_table; (void)src_table; (void)dest; (void)src; (void)len;
+    Table* dst = table_at(dest_table);
+    Table* from = table_at(src_table);
+    check_table_range(dst, dest, len);
+    check_table_range(from, src, len);
+    if (len == 0) return;
+    memmove(&dst->elems[(uint32_t)dest], &from->elems[(uint32_t)src],
+            (uint32_t)len * sizeof(void*));
+    memmove(&dst->sigs[(uint32_t)dest], &from->sigs[(uint32_t)src],
+            (uint32_t)len * sizeof(int32_t));
 }
 
 int32_t __wasm_table_grow(int32_t table, void* val, int32_t delta) {
-    /* TODO: Implement table grow */
-    (void)table; (void)val; (void)delta;
-    return -1;
+    Table* t = table_at(table);
+    uint32_t old_size = t->size;
+    uint32_t delta_u = (uint32_t)delta;
+    uint32_t max = MAX_TABLE_SIZE;
+    if (t->has_max && t->max < max) max = t->max;
+    if (old_size > max || delta_u > max - old_size) return -1;
+    if (delta_u == 0) return (int32_t)old_size;
+
+    uint32_t new_size = old_size + delta_u;
+    void** elems = realloc(t->elems, new_size * sizeof(void*));
+    if (!elems) return -1;
+    t->elems = elems;
+    int32_t* sigs = realloc(t->sigs, new_size * sizeof(int32_t));
+    if (!sigs) {
+        sync_table(table);
+        return -1;
+    }
+    t->sigs = sigs;
+
+    int32_t sig = func_sig_lookup(val);
+    for (uint32_t i = old_size; i < new_size; i++) {
+        elems[i] = val;
+        sigs[i] = sig;
+    }
+    t->size = new_size;
+    sync_table(table);
+    return (int32_t)old_size;
 }
 
 int32_t __wasm_table_size_op(int32_t table) {
-    (void)table;
-    return (int32_t)__wasm_table_size;
+    return (int32_t)table_at(table)->size;
 }
 
 void __wasm_table_fill(int32_t table, int32_t dest, void* val, int32_t len) {
-    /* TODO: Implement table fill */
-    (void)table; (void)dest; (void)val; (void)len;
+    Table* t = table_at(table);
+    check_table_range(t, dest, len);
+    int32_t sig = func_sig_lookup(val);
+    for (uint32_t i = 0; i < (uint32_t)len; i++) {
+        t->elems[(uint32_t)dest + i] = val;
+        t->sigs[(uint32_t)dest + i] = sig;
+    }
 }
 
 /* ============== Traps ============== */
@@ -311,20 +720,45 @@ void __wasm_trap_null_reference(void) {
     exit(1);
 }
 
+void __wasm_trap_undefined_element(void) {
+    fprintf(stderr, "wasm trap: undefined element\n");
+    exit(1);
+}
+
+void __wasm_trap_uninitialized_element(void) {
+    fprintf(stderr, "wasm trap: uninitialized element\n");
+    exit(1);
+}
+
+void __wasm_trap_indirect_call_mismatch(void) {
+    fprintf(stderr, "wasm trap: indirect call type mismatch\n");
+    exit(1);
+}
+
 /* ============== Exception handling ============== */
 
-/* Simple exception handler stack */
+/*
+ * Handlers are kept on a stack; each try block calls setjmp() on the jump
+ * buffer returned by __wasm_push_exception_handler in its own frame.
+ */
 #define MAX_EXCEPTION_HANDLERS 256
-static void* exception_handlers[MAX_EXCEPTION_HANDLERS];
+#define WASM_EXCEPTION_PAYLOAD_MAX 64
+
+static jmp_buf exception_handlers[MAX_EXCEPTION_HANDLERS];
 static int exception_handler_count = 0;
-static void* current_exception = NULL;
+/* GC shadow stack top when each handler was pushed (see GC operations) */
+static struct GCFrame* exception_gc_frames[MAX_EXCEPTION_HANDLERS];
+static struct GCFrame* gc_frames = NULL;
+static int32_t current_tag = 0;
+static uint8_t current_payload[WASM_EXCEPTION_PAYLOAD_MAX];
 
-void __wasm_push_exception_handler(void* label) {
+void* __wasm_push_exception_handler(void) {
     if (exception_handler_count >= MAX_EXCEPTION_HANDLERS) {
         fprintf(stderr, "wasm trap: exception handler stack overflow\n");
         exit(1);
     }
-    exception_handlers[exception_handler_count++] = label;
+    exception_gc_frames[exception_handler_count] = gc_frames;
+    return exception_handlers[exception_handler_count++];
 }
 
 void __wasm_pop_exception_handler(void) {
@@ -333,79 +767,681 @@ void __wasm_pop_exception_handler(void) {
     }
 }
 
-void __wasm_throw(int32_t tag, void* values) {
-    (void)tag;
-    current_exception = values;
+void __wasm_begin_catch(void) {
+    /* Drop the shadow stack frames of the functions that were unwound */
     if (exception_handler_count > 0) {
-        /* In a real implementation, we'd longjmp to the handler */
-        fprintf(stderr, "wasm trap: unhandled exception (tag=%d)\n", tag);
-        exit(1);
+        gc_frames = exception_gc_frames[exception_handler_count - 1];
     }
-    fprintf(stderr, "wasm trap: unhandled exception (tag=%d)\n", tag);
-    exit(1);
+    __wasm_pop_exception_handler();
 }
 
 void __wasm_rethrow(void) {
-    fprintf(stderr, "wasm trap: rethrow with no exception\n");
-    exit(1);
+    if (exception_handler_count == 0) {
+        fprintf(stderr, "wasm trap: unhandled exception (tag=%d)\n", current_tag);
+        exit(1);
+    }
+    longjmp(exception_handlers[exception_handler_count - 1], 1);
+}
+
+void __wasm_throw(int32_t tag) {
+    current_tag = tag;
+    __wasm_rethrow();
+}
+
+void __wasm_throw_with_payload(int32_t tag, void* payload, size_t size) {
+    if (size > WASM_EXCEPTION_PAYLOAD_MAX) {
+        fprintf(stderr, "wasm trap: exception payload too large\n");
+        exit(1);
+    }
+    memcpy(current_payload, payload, size);
+    __wasm_throw(tag);
 }
 
-void* __wasm_get_exception(void) {
-    return current_exception;
+int32_t __wasm_get_exception_tag(void) {
+    return current_tag;
+}
+
+typedef struct {
+    int32_t tag;
+    uint8_t payload[WASM_EXCEPTION_PAYLOAD_MAX];
+} WasmExnRef;
+
+static WasmExnRef* exn_alloc(void);
+
+/* Captured exceptions are GC objects, collected once no longer referenced */
+void* __wasm_exn_capture(void) {
+    WasmExnRef* exn = exn_alloc();
+    exn->tag = current_tag;
+    memcpy(exn->payload, current_payload, sizeof(current_payload));
+    return exn;
+}
+
+void __wasm_throw_ref(void* ref) {
+    WasmExnRef* exn = ref;
+    if (!exn) {
+        __wasm_trap_null_reference();
+    }
+    memcpy(current_payload, exn->payload, sizeof(current_payload));
+    __wasm_throw(exn->tag);
+}
+
+void* __wasm_get_exception_payload(void) {
+    return current_payload;
 }
 
 /* ============== GC operations ============== */
 
-/* Simple header for GC objects */
+/*
+ * Non-moving mark-sweep collector over segregated size classes, as in the
+ * waq runtime. Small objects are carved from 64 KiB chunks of equal-sized
+ * cells; larger objects get a chunk of their own. Roots are the shadow stack
+ * frames pushed by compiled functions, registered globals, the table and the
+ * exception being delivered; objects are traced with the per-type layouts
+ * registered by __wasm_memory_init, and exception payloads (of captured
+ * exceptions too) with the per-tag layouts registered alongside.
+ */
+
+#define GC_CHUNK_SIZE ((size_t)64 * 1024)
+#define GC_CHUNK_HEADER 64
+
+/* Collect once this many bytes are allocated (at least twice the live size) */
+#define GC_MIN_THRESHOLD ((size_t)4 * 1024 * 1024)
+
+/* Object header flags */
+#define GC_ALLOCATED 1u
+#define GC_MARKED 2u
+
+/* Composite kinds passed to __wasm_gc_register_type */
+#define GC_KIND_STRUCT 0
+#define GC_KIND_ARRAY 1
+
+/* Type index in the header of a captured exception */
+#define GC_EXN_TYPE UINT32_MAX
+
+/* Object header for GC objects */
 typedef struct {
-    int32_t type_idx;
-    int32_t length;  /* For arrays */
+    uint32_t type_index;  /* Type index for runtime type checking */
+    uint32_t flags;       /* GC_ALLOCATED, GC_MARKED */
 } GCHeader;
 
-void* __wasm_struct_new(int32_t type_idx, int32_t num_fields) {
-    size_t size = sizeof(GCHeader) + (size_t)num_fields * sizeof(int64_t);
-    GCHeader* obj = calloc(1, size);
-    if (!obj) {
-        fprintf(stderr, "wasm trap: out of memory\n");
-        exit(1);
+#define GC_HEADER_SIZE sizeof(GCHeader)
+
+/* Array header: type_index (4) + flags (4) + length (4) + padding (4) = 16 bytes */
+typedef struct {
+    uint32_t type_index;
+    uint32_t flags;
+    uint32_t length;
+    uint32_t _padding;
+} GCArrayHeader;
+
+#define GC_ARRAY_HEADER_SIZE sizeof(GCArrayHeader)
+
+/* Chunk header; cells start GC_CHUNK_HEADER bytes into the chunk */
+typedef struct {
+    size_t cell_size;   /* 0 for a large object chunk */
+    size_t cell_count;
+    size_t size;        /* Bytes allocated for the chunk */
+} GCChunk;
+
+static const size_t gc_size_classes[] = {
+    16, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536, 2048,
+};
+
+#define GC_NUM_CLASSES \
+    (sizeof(gc_size_classes) / sizeof(gc_size_classes[0]))
+
+/* Free cells have cleared flags and are linked through their first field */
+typedef struct GCFreeCell {
+    GCHeader header;
+    struct GCFreeCell* next;
+} GCFreeCell;
+
+static GCFreeCell* gc_free_lists[GC_NUM_CLASSES];
+
+/* All chunks, sorted by address */
+static GCChunk** gc_chunks = NULL;
+static size_t gc_chunk_count = 0;
+static size_t gc_chunk_capacity = 0;
+
+/* Bytes held by allocated objects, and the size that triggers a collection */
+static size_t gc_heap_bytes = 0;
+static size_t gc_threshold = GC_MIN_THRESHOLD;
+
+/* Per-type field layouts, indexed by type index */
+typedef struct {
+    int32_t kind;          /* GC_KIND_*, or -1 if not registered */
+    int32_t count;         /* Number of fields (1 for arrays) */
+    const uint8_t* refs;   /* Nonzero for each field holding a reference */
+    int32_t supertype;     /* Declared supertype, or -1 */
+} GCTypeLayout;
+
+static GCTypeLayout* gc_types = NULL;
+static size_t gc_type_count = 0;
+
+/* Per-tag payload layouts, indexed by tag; only tags with reference params */
+typedef struct {
+    int32_t count;         /* Number of params */
+    const uint8_t* refs;   /* Nonzero for each param holding a reference */
+} GCTagLayout;
+
+static GCTagLayout* gc_tags = NULL;
+static size_t gc_tag_count = 0;
+
+/* Shadow stack frame, allocated on the native stack by compiled functions */
+typedef struct GCFrame {
+    struct GCFrame* prev;
+    uint64_t count;
+    int64_t slots[];
+} GCFrame;
+
+
+/* Addresses of reference globals */
+static int64_t** gc_roots = NULL;
+static size_t gc_root_count = 0;
+static size_t gc_root_capacity = 0;
+
+/* Objects marked but not yet traced */
+static GCHeader** gc_mark_stack = NULL;
+static size_t gc_mark_count = 0;
+static size_t gc_mark_capacity = 0;
+
+static void gc_out_of_memory(void) __attribute__((noreturn));
+static void gc_out_of_memory(void) {
+    fprintf(stderr, "wasm trap: GC heap exhausted\n");
+    exit(1);
+}
+
+/* Grow a runtime-owned array so it can hold `needed` items */
+static void* gc_reserve(void* items, size_t* capacity, size_t needed,
+                        size_t item_size) {
+    if (needed <= *capacity) return items;
+    size_t new_capacity = *capacity ? *capacity * 2 : 64;
+    while (new_capacity < needed) new_capacity *= 2;
+    void* new_items = realloc(items, new_capacity * item_size);
+    if (!new_items) gc_out_of_memory();
+    *capacity = new_capacity;
+    return new_items;
+}
+
+/* Register the field layout and supertype of a struct or array type */
+void __wasm_gc_register_type(int32_t type_idx, int32_t kind, int32_t count,
+                             const uint8_t* refs, int32_t supertype) {
+    if (type_idx < 0) return;
+    size_t needed = (size_t)type_idx + 1;
+    if (needed > gc_type_count) {
+        GCTypeLayout* types = realloc(gc_types, needed * sizeof(GCTypeLayout));
+        if (!types) gc_out_of_memory();
+        for (size_t i = gc_type_count; i < needed; i++) {
+            types[i].kind = -1;
+            types[i].count = 0;
+            types[i].refs = NULL;
+            types[i].supertype = -1;
+        }
+        gc_types = types;
+        gc_type_count = needed;
     }
-    obj->type_idx = type_idx;
-    obj->length = num_fields;
-    return (void*)(obj + 1);  /* Return pointer after header */
+    gc_types[type_idx].kind = kind;
+    gc_types[type_idx].count = count;
+    gc_types[type_idx].refs = refs;
+    gc_types[type_idx].supertype = supertype;
 }
 
-void* __wasm_struct_new_default(int32_t type_idx, int32_t num_fields) {
-    return __wasm_struct_new(type_idx, num_fields);  /* Already zeroed by calloc */
+/* Register which params of an exception tag hold references */
+void __wasm_gc_register_tag(int32_t tag_idx, int32_t count, const uint8_t* refs) {
+    if (tag_idx < 0) return;
+    size_t needed = (size_t)tag_idx + 1;
+    if (needed > gc_tag_count) {
+        GCTagLayout* tags = realloc(gc_tags, needed * sizeof(GCTagLayout));
+        if (!tags) gc_out_of_memory();
+        for (size_t i = gc_tag_count; i < needed; i++) {
+            tags[i].count = 0;
+            tags[i].refs = NULL;
+        }
+        gc_tags = tags;
+        gc_tag_count = needed;
+    }
+    gc_tags[tag_idx].count = count;
+    gc_tags[tag_idx].refs = refs;
 }
 
-void* __wasm_array_new(int32_t type_idx, int32_t init_value, int32_t length) {
-    size_t size = sizeof(GCHeader) + (size_t)length * sizeof(int64_t);
-    GCHeader* obj = malloc(size);
-    if (!obj) {
-        fprintf(stderr, "wasm trap: out of memory\n");
-        exit(1);
+/* Register the address of a global holding a reference */
+void __wasm_gc_add_root(int64_t* root) {
+    gc_roots = gc_reserve(gc_roots, &gc_root_capacity, gc_root_count + 1,
+                          sizeof(int64_t*));
+    gc_roots[gc_root_count++] = root;
+}
+
+/* Link a compiled function's shadow stack frame and clear its slots */
+void __wasm_gc_push_frame(void* frame_ptr, int32_t count) {
+    GCFrame* frame = frame_ptr;
+    /* Self tail calls jump back to the entry block with the frame linked */
+    if (frame == gc_frames) return;
+    frame->prev = gc_frames;
+    frame->count = (uint64_t)count;
+    memset(frame->slots, 0, (size_t)count * sizeof(int64_t));
+    gc_frames = frame;
+}
+
+/* Unlink a shadow stack frame when its function returns */
+void __wasm_gc_pop_frame(void* frame_ptr) {
+    gc_frames = ((GCFrame*)frame_ptr)->prev;
+}
+
+/* Index of the first chunk at or above `addr` */
+static size_t gc_chunk_position(uintptr_t addr) {
+    size_t lo = 0;
+    size_t hi = gc_chunk_count;
+    while (lo < hi) {
+        size_t mid = lo + (hi - lo) / 2;
+        if ((uintptr_t)gc_chunks[mid] < addr) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    return lo;
+}
+
+static GCChunk* gc_new_chunk(size_t size) {
+    GCChunk* chunk = aligned_alloc(GC_CHUNK_SIZE, size);
+    if (!chunk) gc_out_of_memory();
+    chunk->size = size;
+
+    gc_chunks = gc_reserve(gc_chunks, &gc_chunk_capacity, gc_chunk_count + 1,
+                           sizeof(GCChunk*));
+    size_t pos = gc_chunk_position((uintptr_t)chunk);
+    memmove(&gc_chunks[pos + 1], &gc_chunks[pos],
+            (gc_chunk_count - pos) * sizeof(GCChunk*));
+    gc_chunks[pos] = chunk;
+    gc_chunk_count++;
+    return chunk;
+}
+
+static void gc_release_chunk(size_t pos) {
+    free(gc_chunks[pos]);
+    gc_chunk_count--;
+    memmove(&gc_chunks[pos], &gc_chunks[pos + 1],
+            (gc_chunk_count - pos) * sizeof(GCChunk*));
+}
+
+static uint8_t* gc_chunk_cells(GCChunk* chunk) {
+    return (uint8_t*)chunk + GC_CHUNK_HEADER;
+}
+
+/*
+ * Header of the live object `ref` points to, or NULL if it is null, an i31
+ * or anything else that is not a GC object (a function or host pointer).
+ */
+static GCHeader* gc_object(int64_t ref) {
+    uintptr_t addr = (uintptr_t)ref;
+    if (addr == 0 || (addr & 7) != 0) return NULL;
+
+    uintptr_t base = addr & ~(uintptr_t)(GC_CHUNK_SIZE - 1);
+    size_t pos = gc_chunk_position(base);
+    if (pos == gc_chunk_count || (uintptr_t)gc_chunks[pos] != base) {
+        return NULL;
+    }
+    GCChunk* chunk = gc_chunks[pos];
+
+    uintptr_t cells = (uintptr_t)gc_chunk_cells(chunk);
+    uintptr_t cell = addr - GC_HEADER_SIZE;
+    if (addr < cells + GC_HEADER_SIZE) return NULL;
+    if (chunk->cell_size == 0) {
+        if (cell != cells) return NULL;
+    } else {
+        size_t offset = cell - cells;
+        if (offset % chunk->cell_size != 0) return NULL;
+        if (offset / chunk->cell_size >= chunk->cell_count) return NULL;
+    }
+
+    GCHeader* header = (GCHeader*)cell;
+    return (header->flags & GC_ALLOCATED) ? header : NULL;
+}
+
+static void gc_mark(int64_t ref) {
+    GCHeader* header = gc_object(ref);
+    if (!header || (header->flags & GC_MARKED)) return;
+    header->flags |= GC_MARKED;
+    gc_mark_stack = gc_reserve(gc_mark_stack, &gc_mark_capacity,
+                               gc_mark_count + 1, sizeof(GCHeader*));
+    gc_mark_stack[gc_mark_count++] = header;
+}
+
+/* Mark the objects referenced by the payload slots of a tag's reference params */
+static void gc_mark_payload(int32_t tag, const uint8_t* payload) {
+    if (tag < 0 || (size_t)tag >= gc_tag_count) return;
+    GCTagLayout* layout = &gc_tags[tag];
+    for (int32_t i = 0; i < layout->count; i++) {
+        if ((size_t)(i + 1) * 8 > WASM_EXCEPTION_PAYLOAD_MAX) break;
+        if (!layout->refs[i]) continue;
+        int64_t value;
+        memcpy(&value, &payload[(size_t)i * 8], sizeof(value));
+        gc_mark(value);
     }
-    obj->type_idx = type_idx;
-    obj->length = length;
+}
+
+/* Mark the objects referenced by the fields of a marked object */
+static void gc_trace(GCHeader* header) {
+    if (header->type_index == GC_EXN_TYPE) {
+        WasmExnRef* exn = (WasmExnRef*)(header + 1);
+        gc_mark_payload(exn->tag, exn->payload);
+        return;
+    }
+    if (header->type_index >= gc_type_count) return;
+    GCTypeLayout* layout = &gc_types[header->type_index];
+    int64_t* fields = (int64_t*)(header + 1);
+
+    if (layout->kind == GC_KIND_STRUCT) {
+        for (int32_t i = 0; i < layout->count; i++) {
+            if (layout->refs[i]) gc_mark(fields[i]);
+        }
+    } else if (layout->kind == GC_KIND_ARRAY && layout->refs[0]) {
+        GCArrayHeader* array = (GCArrayHeader*)header;
+        int64_t* elems = (int64_t*)(array + 1);
+        for (uint32_t i = 0; i < array->length; i++) {
+            gc_mark(elems[i]);
+        }
+    }
+}
+
+static void gc_mark_roots(void) {
+    for (GCFrame* frame = gc_frames; frame; frame = frame->prev) {
+        for (uint64_t i = 0; i < frame->count; i++) {
+            gc_mark(frame->slots[i]);
+        }
+    }
+    for (size_t i = 0; i < gc_root_count; i++) {
+        gc_mark(*gc_roots[i]);
+    }
+    for (int t = 0; t < MAX_TABLES; t++) {
+        for (uint32_t i = 0; i < tables[t].size; i++) {
+            gc_mark((int64_t)(uintptr_t)tables[t].elems[i]);
+        }
+    }
+    gc_mark_payload(current_tag, current_payload);
+}
 
-    /* Initialize all elements */
-    int64_t* data = (int64_t*)(obj + 1);
-    for (int32_t i = 0; i < length; i++) {
+/* Free unmarked objects, rebuild the free lists and release empty chunks */
+static void gc_sweep(void) {
+    memset(gc_free_lists, 0, sizeof(gc_free_lists));
+    gc_heap_bytes = 0;
+
+    size_t pos = 0;
+    while (pos < gc_chunk_count) {
+        GCChunk* chunk = gc_chunks[pos];
+        uint8_t* cells = gc_chunk_cells(chunk);
+
+        if (chunk->cell_size == 0) {
+            GCHeader* header = (GCHeader*)cells;
+            if (header->flags & GC_MARKED) {
+                header->flags &= ~GC_MARKED;
+                gc_heap_bytes += chunk->size;
+                pos++;
+            } else {
+                gc_release_chunk(pos);
+            }
+            continue;
+        }
+
+        size_t live = 0;
+        for (size_t i = 0; i < chunk->cell_count; i++) {
+            GCHeader* header = (GCHeader*)(cells + i * chunk->cell_size);
+            if (header->flags & GC_MARKED) {
+                header->flags &= ~GC_MARKED;
+                live++;
+            } else {
+                header->flags = 0;
+            }
+        }
+        if (live == 0) {
+            gc_release_chunk(pos);
+            continue;
+        }
+
+        size_t cls = 0;
+        while (gc_size_classes[cls] != chunk->cell_size) cls++;
+        for (size_t i = chunk->cell_count; i-- > 0;) {
+            GCFreeCell* cell = (GCFreeCell*)(cells + i * chunk->cell_size);
+            if (cell->header.flags == 0) {
+                cell->next = gc_free_lists[cls];
+                gc_free_lists[cls] = cell;
+            }
+        }
+        gc_heap_bytes += live * chunk->cell_size;
+        pos++;
+    }
+}
+
+/* Run a full collection */
+void __wasm_gc_collect(void) {
+    gc_mark_roots();
+    while (gc_mark_count > 0) {
+        gc_trace(gc_mark_stack[--gc_mark_count]);
+    }
+    gc_sweep();
+
+    gc_threshold = gc_heap_bytes * 2;
+    if (gc_threshold < GC_MIN_THRESHOLD) {
+        gc_threshold = GC_MIN_THRESHOLD;
+    }
+}
+
+/* Bytes currently held by GC objects (live, or dead but not yet collected) */
+size_t __wasm_gc_heap_size(void) {
+    return gc_heap_bytes;
+}
+
+/* Add a fresh chunk of cells to a size class's free list */
+static void gc_refill(size_t cls) {
+    size_t cell_size = gc_size_classes[cls];
+    GCChunk* chunk = gc_new_chunk(GC_CHUNK_SIZE);
+    chunk->cell_size = cell_size;
+    chunk->cell_count = (GC_CHUNK_SIZE - GC_CHUNK_HEADER) / cell_size;
+
+    uint8_t* cells = gc_chunk_cells(chunk);
+    for (size_t i = chunk->cell_count; i-- > 0;) {
+        GCFreeCell* cell = (GCFreeCell*)(cells + i * cell_size);
+        cell->header.flags = 0;
+        cell->next = gc_free_lists[cls];
+        gc_free_lists[cls] = cell;
+    }
+}
+
+/*
+ * Allocate a zeroed GC object of `size` bytes, header included.
+ * Returns the header, marked as allocated. May run a collection first.
+ */
+static void* gc_alloc(size_t size) {
+    if (gc_heap_bytes >= gc_threshold) {
+        __wasm_gc_collect();
+    }
+
+    uint8_t* cell;
+    size_t cell_size;
+    size_t cls = 0;
+    while (cls < GC_NUM_CLASSES && gc_size_classes[cls] < size) cls++;
+
+    if (cls == GC_NUM_CLASSES) {
+        if (size > SIZE_MAX - GC_CHUNK_HEADER - GC_CHUNK_SIZE) {
+            gc_out_of_memory();
+        }
+        size_t chunk_size = (GC_CHUNK_HEADER + size + GC_CHUNK_SIZE - 1)
+                            & ~(GC_CHUNK_SIZE - 1);
+        GCChunk* chunk = gc_new_chunk(chunk_size);
+        chunk->cell_size = 0;
+        chunk->cell_count = 1;
+        cell = gc_chunk_cells(chunk);
+        cell_size = size;
+        gc_heap_bytes += chunk_size;
+    } else {
+        if (!gc_free_lists[cls]) gc_refill(cls);
+        GCFreeCell* free_cell = gc_free_lists[cls];
+        gc_free_lists[cls] = free_cell->next;
+        cell = (uint8_t*)free_cell;
+        cell_size = gc_size_classes[cls];
+        gc_heap_bytes += cell_size;
+    }
+
+    memset(cell, 0, cell_size);
+    ((GCHeader*)cell)->flags = GC_ALLOCATED;
+    return cell;
+}
+
+static WasmExnRef* exn_alloc(void) {
+    GCHeader* header = gc_alloc(GC_HEADER_SIZE + sizeof(WasmExnRef));
+    header->type_index = GC_EXN_TYPE;
+    return (WasmExnRef*)(header + 1);
+}
+
+/* Allocate a struct */
+void* __wasm_struct_new(int32_t type_idx, int32_t num_fields) {
+    size_t size = GC_HEADER_SIZE + (size_t)(uint32_t)num_fields * 8;
+    GCHeader* header = gc_alloc(size);
+    header->type_index = (uint32_t)type_idx;
+
+    /* Return pointer past header (to field data) */
+    return header + 1;
+}
+
+/* Allocate a struct with default (zero) values */
+void* __wasm_struct_new_default(int32_t type_idx, int32_t num_fields) {
+    /* Same as struct_new, memory is already zeroed */
+    return __wasm_struct_new(type_idx, num_fields);
+}
+
+/* Allocate an array */
+void* __wasm_array_new(int32_t type_idx, int32_t length, int64_t init_value) {
+    size_t elem_size = 8;  /* All elements stored as 64-bit for simplicity */
+    size_t data_size = (size_t)(uint32_t)length * elem_size;
+    GCArrayHeader* header = gc_alloc(GC_ARRAY_HEADER_SIZE + data_size);
+    header->type_index = (uint32_t)type_idx;
+    header->length = (uint32_t)length;
+
+    /* Initialize elements */
+    int64_t* data = (int64_t*)(header + 1);
+    for (uint32_t i = 0; i < header->length; i++) {
         data[i] = init_value;
     }
-    return (void*)data;
+
+    /* Return pointer to length field (for array.len to work) */
+    return &header->length;
 }
 
+/* Allocate an array with default values */
 void* __wasm_array_new_default(int32_t type_idx, int32_t length) {
-    size_t size = sizeof(GCHeader) + (size_t)length * sizeof(int64_t);
-    GCHeader* obj = calloc(1, size);
-    if (!obj) {
-        fprintf(stderr, "wasm trap: out of memory\n");
+    return __wasm_array_new(type_idx, length, 0);
+}
+
+static void trap_array_out_of_bounds(void) __attribute__((noreturn));
+static void trap_array_out_of_bounds(void) {
+    fprintf(stderr, "wasm trap: out of bounds array access\n");
+    exit(1);
+}
+
+/* Length of a non-null array (traps on null) */
+static uint32_t array_length(void* array) {
+    if (!array) __wasm_trap_null_reference();
+    return *(uint32_t*)array;
+}
+
+/* Element slots of an array, which follow its length field */
+static int64_t* array_data(void* array) {
+    return (int64_t*)((uint8_t*)array + 8);
+}
+
+/* Check that [offset, offset + count) is within a sequence of `size` items */
+static int range_ok(int32_t offset, int32_t count, uint64_t size) {
+    return (uint64_t)(uint32_t)offset + (uint32_t)count <= size;
+}
+
+void __wasm_array_fill(void* array, int32_t offset, int64_t value, int32_t count) {
+    uint32_t length = array_length(array);
+    if (!range_ok(offset, count, length)) trap_array_out_of_bounds();
+    int64_t* data = array_data(array);
+    for (uint32_t i = 0; i < (uint32_t)count; i++) {
+        data[(uint32_t)offset + i] = value;
+    }
+}
+
+void __wasm_array_copy(void* dest, int32_t dest_offset, void* src,
+                       int32_t src_offset, int32_t count) {
+    uint32_t dest_length = array_length(dest);
+    uint32_t src_length = array_length(src);
+    if (!range_ok(dest_offset, count, dest_length) ||
+        !range_ok(src_offset, count, src_length)) {
+        trap_array_out_of_bounds();
+    }
+    memmove(array_data(dest) + (uint32_t)dest_offset,
+            array_data(src) + (uint32_t)src_offset,
+            (size_t)(uint32_t)count * sizeof(int64_t));
+}
+
+static void check_data_range(int32_t seg, int32_t offset, int32_t count,
+                             int32_t elem_size) {
+    uint64_t bytes = (uint64_t)(uint32_t)count * (uint32_t)elem_size;
+    if ((uint64_t)(uint32_t)offset + bytes > data_segment_size(seg)) {
+        __wasm_trap_out_of_bounds();
+    }
+}
+
+static void check_elem_range(int32_t elem, int32_t offset, int32_t count) {
+    if (!range_ok(offset, count, elem_segment_size(elem))) {
+        fprintf(stderr, "wasm trap: out of bounds table access\n");
         exit(1);
     }
-    obj->type_idx = type_idx;
-    obj->length = length;
-    return (void*)(obj + 1);
+}
+
+/* Copy checked elements from a data segment into array slots */
+static void array_load_data(int64_t* data, int32_t seg, int32_t offset,
+                            int32_t count, int32_t elem_size) {
+    const uint8_t* src = data_segments[seg].data + (uint32_t)offset;
+    for (uint32_t i = 0; i < (uint32_t)count; i++) {
+        /* Elements occupy the low bytes of their 8-byte slot */
+        data[i] = 0;
+        memcpy(&data[i], src + (size_t)i * (uint32_t)elem_size, (size_t)elem_size);
+    }
+}
+
+/* Copy checked function references from an element segment into array slots */
+static void array_load_elem(int64_t* data, int32_t elem, int32_t offset,
+                            int32_t count) {
+    void** src = elem_segments[elem].funcs;
+    for (uint32_t i = 0; i < (uint32_t)count; i++) {
+        data[i] = (int64_t)(intptr_t)src[(uint32_t)offset + i];
+    }
+}
+
+void* __wasm_array_new_data(int32_t type_idx, int32_t seg, int32_t offset,
+                            int32_t length, int32_t elem_size) {
+    check_data_range(seg, offset, length, elem_size);
+    void* array = __wasm_array_new_default(type_idx, length);
+    array_load_data(array_data(array), seg, offset, length, elem_size);
+    return array;
+}
+
+void* __wasm_array_new_elem(int32_t type_idx, int32_t elem, int32_t offset,
+                            int32_t length) {
+    check_elem_range(elem, offset, length);
+    void* array = __wasm_array_new_default(type_idx, length);
+    array_load_elem(array_data(array), elem, offset, length);
+    return array;
+}
+
+void __wasm_array_init_data(void* array, int32_t dest, int32_t seg,
+                            int32_t offset, int32_t count, int32_t elem_size) {
+    uint32_t length = array_length(array);
+    if (!range_ok(dest, count, length)) trap_array_out_of_bounds();
+    check_data_range(seg, offset, count, elem_size);
+    array_load_data(array_data(array) + (uint32_t)dest, seg, offset, count,
+                    elem_size);
+}
+
+void __wasm_array_init_elem(void* array, int32_t dest, int32_t elem,
+                            int32_t offset, int32_t count) {
+    uint32_t length = array_length(array);
+    if (!range_ok(dest, count, length)) trap_array_out_of_bounds();
+    check_elem_range(elem, offset, count);
+    array_load_elem(array_data(array) + (uint32_t)dest, elem, offset, count);
 }
 
 /* i31ref: tagged pointer with low bit set */
@@ -427,34 +1463,81 @@ int32_t __wasm_i31_get_u(int64_t ref) {
     return (int32_t)((ref >> 1) & 0x7FFFFFFF);
 }
 
-int32_t __wasm_ref_test(void* ref, int32_t type_idx) {
+/* Heap types: a canonical type index, or the negative s33 code of an abstract type */
+#define HEAP_NOEXN (-0x0C)
+#define HEAP_NOFUNC (-0x0D)
+#define HEAP_NOEXTERN (-0x0E)
+#define HEAP_NONE (-0x0F)
+#define HEAP_FUNC (-0x10)
+#define HEAP_EXTERN (-0x11)
+#define HEAP_ANY (-0x12)
+#define HEAP_EQ (-0x13)
+#define HEAP_I31 (-0x14)
+#define HEAP_STRUCT (-0x15)
+#define HEAP_ARRAY (-0x16)
+#define HEAP_EXN (-0x17)
+
+/* Check if an object's type is `type_idx` or one of its declared subtypes */
+static int gc_is_subtype(uint32_t type_index, int32_t type_idx) {
+    int64_t current = type_index;
+    for (size_t depth = 0; depth <= gc_type_count; depth++) {
+        if (current == type_idx) return 1;
+        if (current < 0 || (uint64_t)current >= gc_type_count) return 0;
+        current = gc_types[current].supertype;
+    }
+    return 0;
+}
+
+static int32_t gc_kind(GCHeader* header) {
+    if (header->type_index >= gc_type_count) return -1;
+    return gc_types[header->type_index].kind;
+}
+
+int32_t __wasm_ref_test(void* ref, int32_t heap_type) {
     if (!ref) return 0;
-    GCHeader* header = ((GCHeader*)ref) - 1;
-    return header->type_idx == type_idx;
+    int64_t value = (int64_t)(intptr_t)ref;
+    int is_i31 = (value & 1) != 0;
+    GCHeader* header = gc_object(value);
+    switch (heap_type) {
+    case HEAP_ANY:
+    case HEAP_FUNC:
+    case HEAP_EXTERN:
+    case HEAP_EXN:
+        return 1;
+    case HEAP_EQ:
+        return is_i31 || header != NULL;
+    case HEAP_I31:
+        return is_i31;
+    case HEAP_STRUCT:
+        return header && gc_kind(header) == GC_KIND_STRUCT;
+    case HEAP_ARRAY:
+        return header && gc_kind(header) == GC_KIND_ARRAY;
+    default:
+        break;
+    }
+    if (heap_type < 0 || !header) return 0;
+    return gc_is_subtype(header->type_index, heap_type);
 }
 
-int32_t __wasm_ref_test_null(void* ref, int32_t type_idx) {
+int32_t __wasm_ref_test_null(void* ref, int32_t heap_type) {
     if (!ref) return 1;
-    GCHeader* header = ((GCHeader*)ref) - 1;
-    return header->type_idx == type_idx;
+    return __wasm_ref_test(ref, heap_type);
 }
 
-void* __wasm_ref_cast(void* ref, int32_t type_idx) {
+void* __wasm_ref_cast(void* ref, int32_t heap_type) {
     if (!ref) {
         __wasm_trap_null_reference();
     }
-    GCHeader* header = ((GCHeader*)ref) - 1;
-    if (header->type_idx != type_idx) {
+    if (!__wasm_ref_test(ref, heap_type)) {
         fprintf(stderr, "wasm trap: ref.cast failed\n");
         exit(1);
     }
     return ref;
 }
 
-void* __wasm_ref_cast_null(void* ref, int32_t type_idx) {
+void* __wasm_ref_cast_null(void* ref, int32_t heap_type) {
     if (!ref) return NULL;
-    GCHeader* header = ((GCHeader*)ref) - 1;
-    if (header->type_idx != type_idx) {
+    if (!__wasm_ref_test(ref, heap_type)) {
         fprintf(stderr, "wasm trap: ref.cast failed\n");
         exit(1);
     }
@@ -469,24 +1552,36 @@ void __wasm_init(int32_t initial_pages) {
     memory_pages = 0;
     __wasm_memory = NULL;
     __wasm_memory_size = 0;
+    __wasm_memory_size_bytes = 0;
 
     if (__wasm_memory_grow(initial_pages) < 0) {
         fprintf(stderr, "wasm: failed to initialize memory\n");
         exit(1);
     }
 
-    /* Initialize table (default size) */
-    __wasm_table_size = 64;
-    __wasm_table = calloc(__wasm_table_size, sizeof(void*));
 }
 
 void __wasm_fini(void) {
-    free(__wasm_memory);
+    if (guard_region) {
+        munmap(guard_region, WASM_GUARD_REGION_SIZE);
+        guard_region = NULL;
+    } else {
+        free(__wasm_memory);
+    }
     __wasm_memory = NULL;
     __wasm_memory_size = 0;
+    __wasm_memory_size_bytes = 0;
     memory_pages = 0;
+    memory_max_pages = WASM_MAX_PAGES;
+    for (int i = 1; i < MAX_MEMORIES; i++) {
+        free(memories[i].data);
+        memset(&memories[i], 0, sizeof(Memory));
+    }
 
-    free(__wasm_table);
-    __wasm_table = NULL;
-    __wasm_table_size = 0;
+    for (int i = 0; i < MAX_TABLES; i++) {
+        free(tables[i].elems);
+        free(tables[i].sigs);
+        memset(&tables[i], 0, sizeof(Table));
+    }
+    sync_table(0);
 }
@@ -22,10 +22,12 @@
 /* Memory exports */
 extern uint8_t* __wasm_memory;
 extern uint32_t __wasm_memory_size;  /* in bytes */
+extern uint64_t __wasm_memory_size_bytes;  /* read by inline bounds checks */
 
 /* Table exports */
 extern void** __wasm_table;
 extern uint32_t __wasm_table_size;
+extern int32_t* __wasm_table_sigs;  /* signature tags for call_indirect */
 
 /* ============== Integer intrinsics ============== */
 
@@ -80,15 +82,38 @@ uint64_t __wasm_i64_trunc_sat_f64_u(double x);
 
 int32_t __wasm_memory_size_pages(void);
 int32_t __wasm_memory_grow(int32_t pages);
-void __wasm_memory_init_seg(int32_t seg, int32_t dest, int32_t src, int32_t len);
+void __wasm_memory_guard_init(void);
+void __wasm_register_data_segment(int32_t seg, const uint8_t* data, size_t size);
+void __wasm_memory_init_seg(int32_t seg, uint64_t dest, uint32_t src, uint32_t len);
 void __wasm_data_drop(int32_t seg);
-void __wasm_memory_copy(int32_t dest, int32_t src, int32_t len);
-void __wasm_memory_fill(int32_t dest, int32_t val, int32_t len);
+void __wasm_memory_copy(uint64_t dest, uint64_t src, uint64_t len);
+void __wasm_memory_fill(uint64_t dest, int32_t val, uint64_t len);
+
+/* Multi-memory: index 0 is the memory above, others have their own buffers */
+void __wasm_memory_set_max(int32_t mem_idx, int32_t max_pages);
+uint8_t* __wasm_memory_base_idx(int32_t mem_idx);
+uint8_t* __wasm_memory_buffer(int32_t mem_idx, uint64_t addr, uint64_t len);
+int32_t __wasm_memory_size_pages_idx(int32_t mem_idx);
+uint64_t __wasm_memory_size_bytes_idx(int32_t mem_idx);
+int32_t __wasm_memory_grow_idx(int32_t mem_idx, int32_t pages);
+int64_t __wasm_memory_size_pages64(int32_t mem_idx);
+int64_t __wasm_memory_grow64(int32_t mem_idx, int64_t pages);
+void __wasm_memory_init_seg_idx(int32_t mem_idx, int32_t seg, uint64_t dest,
+                                uint32_t src, uint32_t len);
+void __wasm_memory_copy_idx(int32_t dest_mem, int32_t src_mem, uint64_t dest,
+                            uint64_t src, uint64_t len);
+void __wasm_memory_fill_idx(int32_t mem_idx, uint64_t dest, int32_t val, uint64_t len);
 
 /* ============== Table operations ============== */
 
-void* __wasm_table_get(int32_t idx);
-void __wasm_table_set(int32_t idx, void* val);
+void __wasm_func_sig_register(void* func, int32_t sig);
+void __wasm_table_set_max(int32_t table, int32_t max);
+void* __wasm_table_get(int32_t table, int32_t idx);
+void __wasm_table_set(int32_t table, int32_t idx, void* val);
+void** __wasm_table_elems(int32_t table);
+int32_t* __wasm_table_sigs_idx(int32_t table);
+void __wasm_register_elem_segment(int32_t elem, int32_t count);
+void __wasm_elem_segment_set(int32_t elem, int32_t i, void* func);
 void __wasm_table_init(int32_t table, int32_t elem, int32_t dest, int32_t src, int32_t len);
 void __wasm_elem_drop(int32_t elem);
 void __wasm_table_copy(int32_t dest_table, int32_t src_table, int32_t dest, int32_t src, int32_t len);
@@ -103,29 +128,58 @@ void __wasm_trap_div_by_zero(void) __attribute__((noreturn));
 void __wasm_trap_integer_overflow(void) __attribute__((noreturn));
 void __wasm_trap_invalid_conversion(void) __attribute__((noreturn));
 void __wasm_trap_out_of_bounds(void) __attribute__((noreturn));
+void __wasm_trap_table_out_of_bounds(void) __attribute__((noreturn));
 void __wasm_trap_null_reference(void) __attribute__((noreturn));
+void __wasm_trap_undefined_element(void) __attribute__((noreturn));
+void __wasm_trap_uninitialized_element(void) __attribute__((noreturn));
+void __wasm_trap_indirect_call_mismatch(void) __attribute__((noreturn));
 
 /* ============== Exception handling ============== */
 
-void __wasm_push_exception_handler(void* label);
+void* __wasm_push_exception_handler(void);
 void __wasm_pop_exception_handler(void);
-void __wasm_throw(int32_t tag, void* values) __attribute__((noreturn));
+void __wasm_begin_catch(void);
+void __wasm_throw(int32_t tag) __attribute__((noreturn));
+void __wasm_throw_with_payload(int32_t tag, void* payload, size_t size)
+    __attribute__((noreturn));
 void __wasm_rethrow(void) __attribute__((noreturn));
-void* __wasm_get_exception(void);
+int32_t __wasm_get_exception_tag(void);
+void* __wasm_get_exception_payload(void);
+void* __wasm_exn_capture(void);
+void __wasm_throw_ref(void* exn) __attribute__((noreturn));
 
 /* ============== GC operations ============== */
 
+void __wasm_gc_register_type(int32_t type_idx, int32_t kind, int32_t count,
+                             const uint8_t* refs, int32_t supertype);
+void __wasm_gc_register_tag(int32_t tag_idx, int32_t count, const uint8_t* refs);
+void __wasm_gc_add_root(int64_t* root);
+void __wasm_gc_push_frame(void* frame, int32_t count);
+void __wasm_gc_pop_frame(void* frame);
+void __wasm_gc_collect(void);
+size_t __wasm_gc_heap_size(void);
 void* __wasm_struct_new(int32_t type_idx, int32_t num_fields);
 void* __wasm_struct_new_default(int32_t type_idx, int32_t num_fields);
-void* __wasm_array_new(int32_t type_idx, int32_t init_value, int32_t length);
+void* __wasm_array_new(int32_t type_idx, int32_t length, int64_t init_value);
 void* __wasm_array_new_default(int32_t type_idx, int32_t length);
+void* __wasm_array_new_data(int32_t type_idx, int32_t seg, int32_t offset,
+                            int32_t length, int32_t elem_size);
+void* __wasm_array_new_elem(int32_t type_idx, int32_t elem, int32_t offset,
+                            int32_t length);
+void __wasm_array_fill(void* array, int32_t offset, int64_t value, int32_t count);
+void __wasm_array_copy(void* dest, int32_t dest_offset, void* src,
+                       int32_t src_offset, int32_t count);
+void __wasm_array_init_data(void* array, int32_t dest, int32_t seg,
+                            int32_t offset, int32_t count, int32_t elem_size);
+void __wasm_array_init_elem(void* array, int32_t dest, int32_t elem,
+                            int32_t offset, int32_t count);
 int64_t __wasm_ref_i31(int32_t value);
 int32_t __wasm_i31_get_s(int64_t ref);
 int32_t __wasm_i31_get_u(int64_t ref);
-int32_t __wasm_ref_test(void* ref, int32_t type_idx);
-int32_t __wasm_ref_test_null(void* ref, int32_t type_idx);
-void* __wasm_ref_cast(void* ref, int32_t type_idx);
-void* __wasm_ref_cast_null(void* ref, int32_t type_idx);
+int32_t __wasm_ref_test(void* ref, int32_t heap_type);
+int32_t __wasm_ref_test_null(void* ref, int32_t heap_type);
+void* __wasm_ref_cast(void* ref, int32_t heap_type);
+void* __wasm_ref_cast_null(void* ref, int32_t heap_type);
 
 /* ============== Initialization ============== */
 
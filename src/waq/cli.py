@@ -4,15 +4,33 @@ from __future__ import annotations
 
 import argparse
 import platform
+import re
 import subprocess
 import sys
 import tempfile
+from dataclasses import dataclass, field
 from pathlib import Path
-
-from waq.compiler import compile_module
-from waq.errors import CompileError, ParseError, ValidationError
-from waq.parser.module import parse_module
+from typing import TYPE_CHECKING
+
+from waq.compiler import (
+    BOUNDS_CHECK_MODES,
+    IMPORT_NAMES,
+    compile_module,
+    link_modules,
+)
+from waq.compiler.context import WASI_MODULE, ModuleContext
+from waq.compiler.imports import load_import_manifest
+from waq.errors import CompileError, LinkError, ParseError, ValidationError
+from waq.parser.module import ExportKind, ImportKind, WasmModule, parse_module
+from waq.parser.types import FuncType, GlobalType, MemoryType, TableType, ValueType
+from waq.parser.wat import parse_wat
 from waq.runtime import RUNTIME_C_SOURCE
+from waq.validator import validate_module
+
+if TYPE_CHECKING:
+    from collections.abc import Collection
+
+    from waq.compiler.imports import ImportBindings
 
 
 def detect_target() -> str:
@@ -35,6 +53,125 @@ def detect_target() -> str:
     return "amd64_sysv"
 
 
+@dataclass(frozen=True)
+class Preopen:
+    """A host directory made visible to a WASI program."""
+
+    host: str
+    guest: str
+    read_only: bool = False
+
+
+@dataclass
+class WasiConfig:
+    """Directories, sockets and environment a WASI executable is linked with.
+
+    Nothing is preopened and the environment is empty unless asked for.
+    Listening sockets take the first fds after stdio, then directories follow.
+    A deterministic program sees virtual clocks and random bytes drawn from
+    `seed`.
+    """
+
+    dirs: list[Preopen] = field(default_factory=list)
+    env: list[tuple[str, str]] = field(default_factory=list)
+    inherit_env: bool = False
+    listen: list[str] = field(default_factory=list)
+    deterministic: bool = False
+    seed: int = 0
+
+
+def _parse_dir(value: str, *, read_only: bool = False) -> Preopen:
+    """Parse a `HOST[:GUEST]` directory argument."""
+    host, _, guest = value.partition(":")
+    if not host:
+        raise argparse.ArgumentTypeError(f"invalid directory: {value!r}")
+    return Preopen(host, guest or host, read_only)
+
+
+def _parse_ro_dir(value: str) -> Preopen:
+    """Parse a read-only `HOST[:GUEST]` directory argument."""
+    return _parse_dir(value, read_only=True)
+
+
+def _parse_listen(value: str) -> str:
+    """Check a `HOST:PORT` socket address argument (`[::1]:PORT` for IPv6)."""
+    host, sep, port = value.rpartition(":")
+    if not sep or not port.isdigit() or int(port) > 65535:
+        raise argparse.ArgumentTypeError(f"expected HOST:PORT, got {value!r}")
+    if ":" in host and not (host.startswith("[") and host.endswith("]")):
+        raise argparse.ArgumentTypeError(
+            f"IPv6 addresses must be bracketed ([::1]:PORT), got {value!r}"
+        )
+    return value
+
+
+def _parse_seed(value: str) -> int:
+    """Parse a `--seed` argument, an unsigned 64-bit integer."""
+    try:
+        seed = int(value, 0)
+    except ValueError:
+        seed = -1
+    if not 0 <= seed < 2**64:
+        raise argparse.ArgumentTypeError(
+            f"expected an unsigned 64-bit integer, got {value!r}"
+        )
+    return seed
+
+
+def _parse_imports(value: str) -> ImportBindings:
+    """Load an `--imports` manifest."""
+    try:
+        return load_import_manifest(Path(value))
+    except OSError as e:
+        raise argparse.ArgumentTypeError(f"cannot read {value}: {e.strerror}") from e
+    except ValueError as e:
+        raise argparse.ArgumentTypeError(str(e)) from e
+
+
+def _parse_input(value: str) -> tuple[str, Path]:
+    """Parse a `[NAME=]PATH` input argument.
+
+    NAME is what other linked modules import the module by (default: the
+    file name without its extension).
+    """
+    name, sep, path = value.partition("=")
+    if not sep:
+        return Path(value).stem, Path(value)
+    if not name or not path:
+        raise argparse.ArgumentTypeError(f"expected [NAME=]PATH, got {value!r}")
+    return name, Path(path)
+
+
+def _read_module(path: Path, verbose: bool) -> WasmModule:
+    """Read a WASM binary (.wasm) or text (.wat) file."""
+    if verbose:
+        print(f"Reading {path}")
+
+    if path.suffix == ".wat":
+        if verbose:
+            print("Parsing WAT module")
+        wasm_module = parse_wat(path.read_text(encoding="utf-8"))
+    else:
+        wasm_bytes = path.read_bytes()
+        if verbose:
+            print("Parsing WASM module")
+        wasm_module = parse_module(wasm_bytes)
+
+    if verbose:
+        print(f"  Types: {len(wasm_module.types)}")
+        print(f"  Functions: {len(wasm_module.func_types)}")
+        print(f"  Exports: {len(wasm_module.exports)}")
+    return wasm_module
+
+
+def _parse_env(value: str) -> tuple[str, str]:
+    """Parse a `KEY=VALUE` environment argument."""
+    key, sep, val = value.partition("=")
+    if not key or not sep:
+        raise argparse.ArgumentTypeError(f"expected KEY=VALUE, got {value!r}")
+    return key, val
+
+
 def main(argv: list[str] | None = None) -> int:
     """Main entry point for the CLI."""
     parser = argparse.ArgumentParser(
@@ -43,16 +180,21 @@ def main(argv: list[str] | None = None) -> int:
     )
 
     parser.add_argument(
-        "input",
-        type=Path,
-        help="Input WASM file",
+        "inputs",
+        nargs="+",
+        type=_parse_input,
+        metavar="[NAME=]INPUT",
+        help="Input WASM binary (.wasm) or text (.wat) file. Several inputs "
+        "are linked into one program: imports from NAME (default: the file "
+        "name without extension) resolve to that module's exports, and the "
+        "first input is the main module",
     )
 
     parser.add_argument(
         "-o",
         "--output",
         type=Path,
-        help="Output file (default: input with appropriate extension)",
+        help="Output file (default: first input with appropriate extension)",
     )
 
     default_target = detect_target()
@@ -66,15 +208,16 @@ def main(argv: list[str] | None = None) -> int:
 
     parser.add_argument(
         "--emit",
-        choices=["qbe", "asm", "obj", "exe"],
+        choices=["qbe", "asm", "obj", "exe", "header"],
         default="qbe",
-        help="Output format (default: qbe)",
+        help="Output format (default: qbe); header is a C header declaring "
+        "the module's exports and imports for embedding its object file",
     )
 
     parser.add_argument(
         "--entry",
-        default="main",
-        help="Entry function name for exe output (default: main)",
+        help="Entry function name for exe output (default: _start if the "
+        "module exports it, otherwise main)",
     )
 
     parser.add_argument(
@@ -83,6 +226,106 @@ def main(argv: list[str] | None = None) -> int:
         help="Don't print result in exe output (for void functions)",
     )
 
+    parser.add_argument(
+        "--dir",
+        dest="dirs",
+        action="append",
+        default=[],
+        type=_parse_dir,
+        metavar="HOST[:GUEST]",
+        help="Give a WASI executable access to a host directory, seen by the "
+        "program as GUEST (default: the host path)",
+    )
+
+    parser.add_argument(
+        "--dir-ro",
+        dest="dirs",
+        action="append",
+        type=_parse_ro_dir,
+        metavar="HOST[:GUEST]",
+        help="Like --dir, but the program can only read the directory",
+    )
+
+    parser.add_argument(
+        "--listen",
+        "--tcplisten",
+        action="append",
+        default=[],
+        type=_parse_listen,
+        metavar="HOST:PORT",
+        help="Give a WASI executable a TCP socket listening on HOST:PORT, as "
+        "fd 3 for the first one, 4 for the next, ... (before any --dir)",
+    )
+
+    parser.add_argument(
+        "--env",
+        action="append",
+        default=[],
+        type=_parse_env,
+        metavar="KEY=VALUE",
+        help="Set an environment variable for a WASI executable",
+    )
+
+    parser.add_argument(
+        "--inherit-env",
+        action="store_true",
+        help="Pass the host environment to a WASI executable (default: only "
+        "--env variables)",
+    )
+
+    parser.add_argument(
+        "--deterministic",
+        action="store_true",
+        help="Make runs reproducible: canonical NaNs from float operations, "
+        "and for WASI executables virtual clocks and seeded random_get",
+    )
+
+    parser.add_argument(
+        "--seed",
+        type=_parse_seed,
+        default=0,
+        help="Seed for random_get with --deterministic (default: 0)",
+    )
+
+    parser.add_argument(
+        "--bounds-checks",
+        choices=BOUNDS_CHECK_MODES,
+        default="none",
+        help="Linear memory bounds checking: none, inline compare-and-trap "
+        "on every access, or guard pages (default: none)",
+    )
+
+    parser.add_argument(
+        "--instance-mode",
+        action="store_true",
+        help="Keep the module's memory, table and globals in a runtime "
+        "instance passed to every export, so a host program can create "
+        "several independent instances",
+    )
+
+    parser.add_argument(
+        "--import-names",
+        choices=IMPORT_NAMES,
+        default="qualified",
+        help="Symbols of imported functions: qualified with their module "
+        "(Z_envZ_log for env.log), or bare names (log) as in earlier versions "
+        "(default: qualified)",
+    )
+
+    parser.add_argument(
+        "--imports",
+        type=_parse_imports,
+        metavar="FILE",
+        help="TOML or JSON manifest binding imported functions to host C "
+        "functions; every non-WASI import must then be bound",
+    )
+
+    parser.add_argument(
+        "--no-validate",
+        action="store_true",
+        help="Skip module validation before compiling",
+    )
+
     parser.add_argument(
         "-v",
         "--verbose",
@@ -97,38 +340,61 @@ def main(argv: list[str] | None = None) -> int:
     )
 
     args = parser.parse_args(argv)
+    if args.deterministic and args.inherit_env:
+        parser.error("--inherit-env cannot be used with --deterministic")
+    if args.instance_mode and len(args.inputs) > 1:
+        parser.error("--instance-mode cannot be used with several inputs")
+    if args.emit == "header" and len(args.inputs) > 1:
+        parser.error("--emit header cannot be used with several inputs")
 
     # Determine output file with appropriate extension
     if args.output is None:
-        ext_map = {"qbe": ".ssa", "asm": ".s", "obj": ".o", "exe": ""}
-        args.output = args.input.with_suffix(ext_map[args.emit])
+        ext_map = {"qbe": ".ssa", "asm": ".s", "obj": ".o", "exe": "", "header": ".h"}
+        args.output = args.inputs[0][1].with_suffix(ext_map[args.emit])
 
     try:
-        # Read input
-        if args.verbose:
-            print(f"Reading {args.input}")
-
-        if args.input.suffix == ".wat":
-            if args.verbose:
-                print("Detected .wat file, converting to WASM format")
-            wasm_bytes = convert_wat_to_wasm(args.input.read_text())
-        else:
-            wasm_bytes = args.input.read_bytes()
-
-        # Parse
-        if args.verbose:
-            print("Parsing WASM module")
-        wasm_module = parse_module(wasm_bytes)
-
-        if args.verbose:
-            print(f"  Types: {len(wasm_module.types)}")
-            print(f"  Functions: {len(wasm_module.func_types)}")
-            print(f"  Exports: {len(wasm_module.exports)}")
+        # Read and parse inputs
+        modules = [
+            (name, _read_module(path, args.verbose)) for name, path in args.inputs
+        ]
+        # The main module provides the entry function
+        wasm_module = modules[0][1]
+
+        # Validate
+        if not args.no_validate:
+            for _name, module in modules:
+                if args.verbose:
+                    print("Validating module")
+                result = validate_module(module)
+                for warning in result.warnings:
+                    print(f"Validation {warning}", file=sys.stderr)
+                if not result.is_valid:
+                    raise ValidationError.from_result(result)
 
         # Compile
         if args.verbose:
             print("Compiling to QBE IL")
-        qbe_module = compile_module(wasm_module, target=args.target)
+        if len(modules) > 1:
+            qbe_module = link_modules(
+                modules,
+                target=args.target,
+                bounds_checks=args.bounds_checks,
+                deterministic=args.deterministic,
+                import_names=args.import_names,
+                imports=args.imports,
+                validate=False,
+            )
+        else:
+            qbe_module = compile_module(
+                wasm_module,
+                target=args.target,
+                bounds_checks=args.bounds_checks,
+                deterministic=args.deterministic,
+                instance_mode=args.instance_mode,
+                import_names=args.import_names,
+                imports=args.imports,
+                validate=False,
+            )
 
         # Write output
         if args.verbose:
@@ -146,17 +412,45 @@ def main(argv: list[str] | None = None) -> int:
             asm_code = run_qbe(qbe_il, args.target, args.verbose)
             obj_bytes = run_assembler(asm_code, args.target, args.verbose)
             args.output.write_bytes(obj_bytes)
+        elif args.emit == "header":
+            header = generate_header(
+                wasm_module,
+                args.inputs[0][0],
+                instance_mode=args.instance_mode,
+                import_names=args.import_names,
+                imports=args.imports,
+            )
+            args.output.write_text(header)
         elif args.emit == "exe":
+            entry = args.entry or default_entry(wasm_module)
+            wasi = None
+            if any(is_wasi_module(module) for _name, module in modules):
+                wasi = WasiConfig(
+                    args.dirs,
+                    args.env,
+                    args.inherit_env,
+                    args.listen,
+                    args.deterministic,
+                    args.seed,
+                )
+            # Reactors set themselves up in _initialize before any export runs
+            initialize = entry != "_start" and exports_function(
+                wasm_module, "_initialize"
+            )
             qbe_il = qbe_module.emit()
             asm_code = run_qbe(qbe_il, args.target, args.verbose)
             obj_bytes = run_assembler(asm_code, args.target, args.verbose)
             link_executable(
                 obj_bytes,
                 args.output,
-                args.entry,
+                entry,
+                entry_func_type(wasm_module, entry),
                 args.target,
                 args.verbose,
                 print_result=not args.no_print,
+                wasi=wasi,
+                initialize=initialize,
+                instance_mode=args.instance_mode,
             )
 
         if args.verbose:
@@ -173,6 +467,9 @@ def main(argv: list[str] | None = None) -> int:
     except CompileError as e:
         print(f"Compile error: {e}", file=sys.stderr)
         return 1
+    except LinkError as e:
+        print(f"Link error: {e}", file=sys.stderr)
+        return 1
     except FileNotFoundError as e:
         print(f"File not found: {e}", file=sys.stderr)
         return 1
@@ -259,63 +556,684 @@ def run_assembler(asm_code: str, target: str, verbose: bool = False) -> bytes:
 def mangle_export_name(name: str) -> str:
     """Get the native symbol name for a WASM export.
 
-    WASM exports are prefixed with wasm_ to avoid conflicts with C symbols,
-    except for _start which is the WASI entry point, and names already
-    prefixed with wasm_ or __wasm_ to avoid double-prefixing.
+    WASM exports are prefixed with wasm_ to avoid conflicts with C symbols
+    (WASI's _start becomes wasm__start, leaving _start to the C startup
+    code), except names already prefixed with wasm_ or __wasm_ to avoid
+    double-prefixing.
     """
-    if name == "_start" or name.startswith(("wasm_", "__wasm_")):
+    if name.startswith(("wasm_", "__wasm_")):
         return name
     return f"wasm_{name}"
 
 
-def generate_main_stub(entry_function: str, *, print_result: bool = True) -> str:
+# C types and printf formats of WASM values in the generated main stub
+# (references are passed and printed as pointers)
+_STUB_C_TYPES = {
+    ValueType.I32: "int32_t",
+    ValueType.I64: "int64_t",
+    ValueType.F32: "float",
+    ValueType.F64: "double",
+}
+_STUB_PRINTF = {
+    ValueType.I32: ('"%" PRId32 "\\n"', ""),
+    ValueType.I64: ('"%" PRId64 "\\n"', ""),
+    ValueType.F32: ('"%.9g\\n"', "(double)"),
+    ValueType.F64: ('"%.17g\\n"', ""),
+}
+
+# Command-line argument parsers, emitted for the parameter types in use
+_STUB_ARG_PARSERS = {
+    ValueType.I32: """\
+static int32_t arg_i32(const char *s, int i) {
+    char *end;
+    errno = 0;
+    long long v = strtoll(s, &end, 0);
+    if (errno || end == s || *end || v < INT32_MIN || v > (long long)UINT32_MAX) {
+        bad_arg(s, i, "i32");
+    }
+    return (int32_t)(uint32_t)v;
+}
+""",
+    ValueType.I64: """\
+static int64_t arg_i64(const char *s, int i) {
+    char *end;
+    errno = 0;
+    int64_t v = s[0] == '-' ? (int64_t)strtoll(s, &end, 0)
+                            : (int64_t)strtoull(s, &end, 0);
+    if (errno || end == s || *end) bad_arg(s, i, "i64");
+    return v;
+}
+""",
+    ValueType.F32: """\
+static float arg_f32(const char *s, int i) {
+    char *end;
+    float v = strtof(s, &end);
+    if (end == s || *end) bad_arg(s, i, "f32");
+    return v;
+}
+""",
+    ValueType.F64: """\
+static double arg_f64(const char *s, int i) {
+    char *end;
+    double v = strtod(s, &end);
+    if (end == s || *end) bad_arg(s, i, "f64");
+    return v;
+}
+""",
+}
+
+
+def _stub_c_type(vtype: ValueType) -> str:
+    """C type of a WASM value in the main stub."""
+    if vtype.is_reference():
+        return "void *"
+    if vtype not in _STUB_C_TYPES:
+        raise ValueError(f"unsupported entry function value type: {vtype}")
+    return _STUB_C_TYPES[vtype]
+
+
+def _stub_c_decl(ctype: str, name: str) -> str:
+    """Declare `name` with a C type, keeping `*` next to the name."""
+    return f"{ctype}{name}" if ctype.endswith("*") else f"{ctype} {name}"
+
+
+def _c_string(value: str) -> str:
+    """Quote a string as a C string literal."""
+    chars = []
+    for byte in value.encode():
+        char = chr(byte)
+        if char.isascii() and char.isprintable() and char not in '"\\?':
+            chars.append(char)
+        else:
+            chars.append(f"\\{byte:03o}")
+    return f'"{"".join(chars)}"'
+
+
+def _stub_wasi_setup(wasi: WasiConfig) -> tuple[list[str], list[str]]:
+    """Declarations and main() statements setting up a WASI program."""
+    decls = [
+        "extern void __wasi_init(int, char **, char **);",
+        "extern int __wasi_preopen(const char *, const char *, int);",
+    ]
+    if wasi.listen:
+        decls.append("extern int __wasi_listen(const char *);")
+    if wasi.deterministic:
+        decls.append("extern void __wasi_set_deterministic(uint64_t);")
+    stmts = []
+    if wasi.inherit_env:
+        decls.append("extern char **environ;")
+        stmts += [
+            f"    setenv({_c_string(key)}, {_c_string(val)}, 1);"
+            for key, val in wasi.env
+        ]
+        env = "environ"
+    else:
+        entries = [_c_string(f"{key}={val}") for key, val in wasi.env]
+        decls.append(f"static char *wasi_env[] = {{{', '.join([*entries, 'NULL'])}}};")
+        env = "wasi_env"
+    stmts.append(f"    __wasi_init(argc, argv, {env});")
+    if wasi.deterministic:
+        stmts.append(f"    __wasi_set_deterministic({wasi.seed}ULL);")
+    for address in wasi.listen:
+        stmts += [
+            f"    if (__wasi_listen({_c_string(address)}) < 0) {{",
+            f"        perror({_c_string(address)});",
+            "        return 1;",
+            "    }",
+        ]
+    for preopen in wasi.dirs:
+        host = _c_string(preopen.host)
+        preopen_args = f"{host}, {_c_string(preopen.guest)}, {int(preopen.read_only)}"
+        stmts += [
+            f"    if (__wasi_preopen({preopen_args}) < 0) {{",
+            f"        perror({host});",
+            "        return 1;",
+            "    }",
+        ]
+    return decls, stmts
+
+
+def generate_main_stub(
+    entry_function: str,
+    func_type: FuncType,
+    *,
+    print_result: bool = True,
+    wasi: WasiConfig | None = None,
+    initialize: bool = False,
+    instance_mode: bool = False,
+) -> str:
     """Generate a C main() stub that calls the WASM entry function.
 
     The entry_function should be the WASM export name; it will be mangled
-    to match the compiled symbol name.
+    to match the compiled symbol name. Parameters are parsed from the
+    command line (`./prog 3 4.5`) and each result is printed on its own
+    line. Results after the first come back through out-pointers, following
+    the compiled multi-value ABI. Without printing, an i32 result becomes
+    the exit status.
+
+    For WASI modules the stub hands argv, the configured environment and
+    preopened directories to the runtime first, and extra arguments are
+    left for the program; `proc_exit` ends the process with its own status.
+    With `initialize`, the reactor's `_initialize` export runs before the
+    entry function.
+
+    In instance mode the stub creates one instance of the module and passes
+    it to the exports it calls.
     """
     # Apply name mangling to match compiled output
     native_name = mangle_export_name(entry_function)
+    params = func_type.params
+    results = func_type.results
+    for vtype in params:
+        if vtype not in _STUB_ARG_PARSERS:
+            raise ValueError(
+                f"entry function parameter of type {vtype} cannot be passed "
+                "on the command line"
+            )
+
+    # Prototype: WASM parameters, then one out-pointer per extra result
+    proto = [_stub_c_type(vtype) for vtype in params]
+    proto += [_stub_c_decl(_stub_c_type(vtype), "*") for vtype in results[1:]]
+    ret_type = _stub_c_type(results[0]) if results else "void"
+    # In instance mode the instance comes first
+    instance = ["WasmInstance *"] if instance_mode else []
+    proto = instance + proto
+
+    lines = ["/* Generated main stub for WAQ */"]
+    if wasi is not None and wasi.inherit_env:
+        # setenv() is POSIX, not ISO C
+        lines.append("#define _POSIX_C_SOURCE 200809L")
+    lines += [
+        "#include <errno.h>",
+        "#include <inttypes.h>",
+        "#include <stdio.h>",
+        "#include <stdlib.h>",
+        "",
+    ]
+    if instance_mode:
+        lines += [
+            "typedef struct WasmInstance WasmInstance;",
+            "extern WasmInstance *waq_instance_new(void (*)(WasmInstance *));",
+        ]
+    lines += [
+        f"extern void __wasm_memory_init({''.join(instance) or 'void'});",
+        f"extern {_stub_c_decl(ret_type, native_name)}({', '.join(proto) or 'void'});",
+    ]
+    wasi_decls, wasi_stmts = _stub_wasi_setup(wasi) if wasi else ([], [])
+    lines += wasi_decls
+    if initialize:
+        lines.append(
+            f"extern void {mangle_export_name('_initialize')}"
+            f"({''.join(instance) or 'void'});"
+        )
+    lines.append("")
+    if params:
+        lines += [
+            "static void bad_arg(const char *s, int i, const char *type) {",
+            '    fprintf(stderr, "argument %d is not a valid %s: %s\\n", i, type, s);',
+            "    exit(2);",
+            "}",
+            "",
+        ]
+        lines += [_STUB_ARG_PARSERS[vtype] for vtype in dict.fromkeys(params)]
+
+    usage = "".join(f" <{vtype}>" for vtype in params)
+    lines.append("int main(int argc, char **argv) {")
+    if params or wasi is None:
+        lines += [
+            f"    if (argc {'!=' if wasi is None else '<'} {len(params) + 1}) {{",
+            f'        fprintf(stderr, "usage: %s{usage}\\n", argv[0]);',
+            "        return 2;",
+            "    }",
+        ]
+    args = ["instance"] if instance_mode else []
+    args += [f"arg_{vtype}(argv[{i}], {i})" for i, vtype in enumerate(params, 1)]
+    for i, vtype in enumerate(results[1:], 1):
+        lines.append(f"    {_stub_c_decl(_stub_c_type(vtype), f'r{i}')};")
+        args.append(f"&r{i}")
+    lines += wasi_stmts
+    if instance_mode:
+        lines += [
+            "    WasmInstance *instance = waq_instance_new(__wasm_memory_init);",
+            "    if (instance == NULL) {",
+            '        fprintf(stderr, "cannot create the module instance\\n");',
+            "        return 1;",
+            "    }",
+        ]
+    else:
+        lines.append("    __wasm_memory_init();")
+    if initialize:
+        initialize_args = "instance" if instance_mode else ""
+        lines.append(f"    {mangle_export_name('_initialize')}({initialize_args});")
+    call = f"{native_name}({', '.join(args)})"
+    if results:
+        lines.append(f"    {_stub_c_decl(ret_type, 'r0')} = {call};")
+    else:
+        lines.append(f"    {call};")
+
     if print_result:
-        return f"""\
-/* Generated main stub for WAQ */
-#include <stdio.h>
+        for i, vtype in enumerate(results):
+            fmt, cast = _STUB_PRINTF.get(vtype, ('"%p\\n"', ""))
+            lines.append(f"    printf({fmt}, {cast}r{i});")
+    elif results and results[0] == ValueType.I32:
+        lines += ["    return r0;", "}", ""]
+        return "\n".join(lines)
+    lines += ["    return 0;", "}", ""]
+    return "\n".join(lines)
+
+
+# Characters that cannot appear in a C identifier
+_NON_C_IDENTIFIER = re.compile(r"[^A-Za-z0-9_]")
+
+# Binds a declaration to an assembler symbol, with the target's label prefix
+# (quoted, as the symbol may have characters the assembler would misread)
+_HEADER_SYMBOL_MACRO = [
+    "#ifndef WAQ_SYMBOL",
+    "#define WAQ_STR(x) #x",
+    "#define WAQ_XSTR(x) WAQ_STR(x)",
+    "#define WAQ_SYMBOL(name) \\",
+    '    __asm__("\\"" WAQ_XSTR(__USER_LABEL_PREFIX__) name "\\"")',
+    "#endif",
+]
+
+
+def generate_header(
+    module: WasmModule,
+    name: str,
+    *,
+    instance_mode: bool = False,
+    import_names: str = "qualified",
+    imports: ImportBindings | None = None,
+) -> str:
+    """Generate a C header for embedding a compiled module in a host program.
+
+    The header declares every exported function with its C prototype
+    (results after the first come back through trailing out-pointers, as in
+    the main stub), inline `_data`/`_size` accessors for exported memories
+    and `_get`/`_set` accessors for exported globals, and the functions and
+    globals the host must define for the module's imports. Its opening
+    comment gives the initialization and teardown sequence. Exports whose
+    symbols are not C identifiers get a C name with the other characters
+    replaced by `_`, functions being bound to their symbol by an asm label
+    (`WAQ_SYMBOL`). Imported functions are declared under their symbols for
+    `import_names`, or as the C functions an `imports` manifest binds them
+    to.
+    """
+    mod_ctx = ModuleContext(
+        module=module,
+        instance_mode=instance_mode,
+        import_names=import_names,
+        imports=imports,
+    )
+    guard = "WAQ_" + re.sub(r"\W", "_", name).upper() + "_H"
+
+    lines = [
+        "/*",
+        f" * C interface of the WebAssembly module {name}, compiled by waq.",
+        " *",
+        *_header_usage(module, instance_mode),
+        " */",
+        f"#ifndef {guard}",
+        f"#define {guard}",
+        "",
+        "#include <stdint.h>",
+        "",
+        "#ifdef __cplusplus",
+        'extern "C" {',
+        "#endif",
+        "",
+        "/* Runtime */",
+    ]
+    if instance_mode:
+        lines += [
+            "typedef struct WasmInstance WasmInstance;",
+            "extern void __wasm_memory_init(WasmInstance *);",
+            "extern WasmInstance *waq_instance_new(void (*)(WasmInstance *));",
+            "extern void waq_instance_reset(WasmInstance *);",
+            "extern void waq_instance_free(WasmInstance *);",
+            "extern uint8_t *waq_instance_memory(WasmInstance *, int32_t);",
+            "extern uint64_t waq_instance_memory_size(WasmInstance *, int32_t);",
+            "extern int64_t *waq_instance_globals(WasmInstance *);",
+        ]
+    else:
+        lines += [
+            "extern void __wasm_memory_init(void);",
+            "extern void __wasm_runtime_cleanup(void);",
+            "extern uint8_t *__wasm_memory_base_idx(int32_t);",
+            "extern uint64_t __wasm_memory_size_bytes_idx(int32_t);",
+        ]
+    if is_wasi_module(module):
+        lines.append("extern void __wasi_init(int, char **, char **);")
+
+    imports = _header_imports(mod_ctx)
+    if imports:
+        lines += ["", "/* Imports, defined by the host */", *imports]
+
+    funcs, accessors = _header_exports(mod_ctx)
+    if funcs:
+        lines += ["", "/* Exported functions */", *funcs]
+    if accessors:
+        lines += ["", "/* Exported memories and globals */", *accessors]
+
+    lines += [
+        "",
+        "#ifdef __cplusplus",
+        "}",
+        "#endif",
+        "",
+        f"#endif /* {guard} */",
+        "",
+    ]
+    return "\n".join(lines)
+
+
+def _header_usage(module: WasmModule, instance_mode: bool) -> list[str]:
+    """Comment lines of a header explaining how to set up the module."""
+    wasi = is_wasi_module(module)
+    reactor = exports_function(module, "_initialize")
+    if instance_mode:
+        lines = [
+            " * Link with the module's object file (waq --emit obj --instance-mode)",
+            " * and the waq runtime compiled with -DWAQ_INSTANCE_MODE. Each",
+            " * instance of the module is created with",
+            " *",
+        ]
+        if wasi:
+            lines.append(" *     __wasi_init(argc, argv, environ);  (once)")
+        lines.append(
+            " *     WasmInstance *instance = waq_instance_new(__wasm_memory_init);"
+        )
+        if reactor:
+            lines.append(" *     wasm__initialize(instance);")
+        lines += [
+            " *",
+            " * and passed to every export (waq_instance_new returns NULL when out of",
+            " * memory). waq_instance_reset(instance) puts it back in its initial",
+            " * state, and waq_instance_free(instance) releases it.",
+        ]
+        return lines
+    lines = [
+        " * Link with the module's object file (waq --emit obj) and the waq",
+        " * runtime. Before calling any export:",
+        " *",
+    ]
+    if wasi:
+        lines.append(" *     __wasi_init(argc, argv, environ);")
+    lines.append(" *     __wasm_memory_init();")
+    if reactor:
+        lines.append(" *     wasm__initialize();")
+    lines += [
+        " *",
+        " * __wasm_memory_init sets up the module's memories, tables and globals",
+        " * and runs its start function; __wasm_runtime_cleanup() releases them.",
+    ]
+    return lines
+
+
+def _header_func_decl(
+    symbol: str,
+    func_type: FuncType,
+    params: list[str],
+    pointers: Collection[int] = (),
+) -> str:
+    """C prototype of a compiled function, after any hidden parameters.
+
+    The parameters at the indices in `pointers` are native buffer pointers.
+    """
+    params = params + [
+        "uint8_t *" if i in pointers else _stub_c_type(vtype)
+        for i, vtype in enumerate(func_type.params)
+    ]
+    params += [
+        _stub_c_decl(_stub_c_type(vtype), f"*result{i}")
+        for i, vtype in enumerate(func_type.results[1:], 1)
+    ]
+    results = func_type.results
+    ret_type = _stub_c_type(results[0]) if results else "void"
+    return f"extern {_stub_c_decl(ret_type, symbol)}({', '.join(params) or 'void'});"
+
+
+def _header_imports(mod_ctx: ModuleContext) -> list[str]:
+    """Header declarations of what the host provides for a module's imports."""
+    module = mod_ctx.module
+    lines = []
+    wasi_funcs = []
+    counts = dict.fromkeys(ImportKind, 0)
+    for imp in module.imports:
+        idx = counts[imp.kind]
+        counts[imp.kind] += 1
+        qualified = f"{imp.module}.{imp.name}"
+        if imp.kind == ImportKind.FUNC:
+            binding = mod_ctx.import_binding(idx)
+            if binding is None and imp.module == WASI_MODULE:
+                wasi_funcs.append(imp.name)
+                continue
+            # In instance mode host functions get the calling instance first,
+            # unless their binding asks for another context
+            hidden = {
+                "instance": ["WasmInstance *"],
+                "memory": ["uint8_t *"],
+                "none": [],
+            }[mod_ctx.import_context(idx)]
+            symbol = mod_ctx.get_func_name(idx)
+            pointers: set[int] = set()
+            if binding is not None:
+                symbol = binding.symbol
+                pointers = {ptr_idx for ptr_idx, _len_idx in binding.buffers}
+            func_type = module.get_func_type(idx)
+            lines.append(f"/* {qualified} */")
+            lines.append(_header_func_decl(symbol, func_type, hidden, pointers))
+        elif imp.kind == ImportKind.GLOBAL:
+            assert isinstance(imp.desc, GlobalType)
+            ctype = _stub_c_type(imp.desc.value_type)
+            symbol = mod_ctx.get_global_name(idx)
+            lines.append(f"/* {qualified} ({imp.desc}) */")
+            lines.append(f"extern {_stub_c_decl(ctype, symbol)};")
+        elif imp.kind == ImportKind.MEMORY:
+            assert isinstance(imp.desc, MemoryType)
+            pages = imp.desc.limits.min
+            mem_idx = mod_ctx.memory_index(idx)
+            lines += [
+                f"/* {qualified}: memory {mem_idx}, which the host grows to at least",
+                f"   {pages} pages (__wasm_memory_grow_idx({mem_idx}, {pages})) "
+                "before the module uses it */",
+            ]
+        elif imp.kind == ImportKind.TABLE:
+            assert isinstance(imp.desc, TableType)
+            size = imp.desc.limits.min
+            table_idx = mod_ctx.table_index(idx)
+            lines += [
+                f"/* {qualified}: table {table_idx}, which the host grows to at "
+                f"least {size}",
+                f"   entries (__wasm_table_grow({table_idx}, NULL, {size})) "
+                "before the module uses it */",
+            ]
+    if wasi_funcs:
+        lines.append(f"/* {WASI_MODULE}: {', '.join(wasi_funcs)} (in the runtime) */")
+    return lines
+
+
+def _header_exports(mod_ctx: ModuleContext) -> tuple[list[str], list[str]]:
+    """Header prototypes of exported functions, and accessors of the rest."""
+    module = mod_ctx.module
+    c_names = _header_export_names(module)
+    funcs: list[str] = []
+    accessors: list[str] = []
+    hidden = ["WasmInstance *"] if mod_ctx.instance_mode else []
+    labelled = False
+    for export in module.exports:
+        symbol = mangle_export_name(export.name)
+        c_name = c_names[export.name]
+        if export.kind == ExportKind.FUNC:
+            if export.index < module.num_imported_funcs():
+                imp = mod_ctx.get_func_name(export.index)
+                funcs.append(f"/* {export.name} re-exports the import {imp} */")
+                continue
+            func_type = module.get_func_type(export.index)
+            decl = _header_func_decl(c_name, func_type, hidden)
+            if c_name != symbol:
+                # Declared under its C name, bound to the real symbol
+                labelled = True
+                decl = f"{decl[:-1]} WAQ_SYMBOL({_c_string(symbol)});"
+            funcs.append(decl)
+        elif export.kind == ExportKind.MEMORY:
+            mem_idx = mod_ctx.memory_index(export.index)
+            accessors += _header_memory_accessors(mod_ctx, c_name, mem_idx)
+        elif export.kind == ExportKind.GLOBAL:
+            accessors += _header_global_accessors(mod_ctx, c_name, export.index)
+    if labelled:
+        funcs = [*_HEADER_SYMBOL_MACRO, *funcs]
+    return funcs, accessors
+
+
+def _header_export_names(module: WasmModule) -> dict[str, str]:
+    """C names of a module's exports in a header, by export name.
+
+    Raises:
+        CompileError: If exports whose symbols are not C identifiers end up
+            with the same C name as another export
+    """
+    c_names = {
+        export.name: _NON_C_IDENTIFIER.sub("_", mangle_export_name(export.name))
+        for export in module.exports
+    }
+    by_c_name: dict[str, list[str]] = {}
+    for name, c_name in c_names.items():
+        by_c_name.setdefault(c_name, []).append(name)
+    clashes = [
+        f"{c_name} ({', '.join(repr(name) for name in names)})"
+        for c_name, names in by_c_name.items()
+        if len(names) > 1
+        and any(mangle_export_name(name) != c_name for name in names)
+    ]
+    if clashes:
+        raise CompileError(
+            f"exports with the same C name in the header: {'; '.join(clashes)}"
+        )
+    return c_names
+
+
+def _header_memory_accessors(
+    mod_ctx: ModuleContext, symbol: str, mem_idx: int
+) -> list[str]:
+    """Inline functions returning an exported memory's base and size."""
+    instance = mod_ctx.instance_mode
+    params = "WasmInstance *instance" if instance else "void"
+    if instance:
+        base = f"waq_instance_memory(instance, {mem_idx})"
+        size = f"waq_instance_memory_size(instance, {mem_idx})"
+    else:
+        base = f"__wasm_memory_base_idx({mem_idx})"
+        size = f"__wasm_memory_size_bytes_idx({mem_idx})"
+    return [
+        f"static inline uint8_t *{symbol}_data({params}) {{",
+        f"    return {base};",
+        "}",
+        f"static inline uint64_t {symbol}_size({params}) {{",
+        f"    return {size};",
+        "}",
+    ]
+
+
+def _header_global_accessors(
+    mod_ctx: ModuleContext, symbol: str, global_idx: int
+) -> list[str]:
+    """Inline functions reading and writing an exported global."""
+    # Accessors take the instance in instance mode
+    instance = ["WasmInstance *instance"] if mod_ctx.instance_mode else []
+    num_imports = mod_ctx.module.num_imported_globals()
+    if global_idx < num_imports:
+        imports = [i for i in mod_ctx.module.imports if i.kind == ImportKind.GLOBAL]
+        global_type = imports[global_idx].desc
+        assert isinstance(global_type, GlobalType)
+    else:
+        global_type = mod_ctx.module.globals[global_idx - num_imports].type
+    ctype = _stub_c_type(global_type.value_type)
+
+    lines = []
+    if instance and global_idx >= num_imports:
+        # A slot of the instance
+        slot = global_idx - num_imports
+        pointer = _stub_c_decl(ctype, "*")
+        value = f"*({pointer})&waq_instance_globals(instance)[{slot}]"
+        unused = []
+    else:
+        value = mod_ctx.get_global_name(global_idx)
+        lines.append(f"extern {_stub_c_decl(ctype, value)};")
+        # Imported globals are shared by every instance
+        unused = ["    (void)instance;"] if instance else []
+    lines += [
+        f"static inline {_stub_c_decl(ctype, f'{symbol}_get')}"
+        f"({', '.join(instance) or 'void'}) {{",
+        *unused,
+        f"    return {value};",
+        "}",
+    ]
+    if global_type.mutable:
+        params = ", ".join([*instance, _stub_c_decl(ctype, "value")])
+        lines += [
+            f"static inline void {symbol}_set({params}) {{",
+            *unused,
+            f"    {value} = value;",
+            "}",
+        ]
+    return lines
+
+
+def is_wasi_module(module: WasmModule) -> bool:
+    """Check whether a module imports from WASI preview 1."""
+    return any(imp.module == WASI_MODULE for imp in module.imports)
+
+
+def exports_function(module: WasmModule, name: str) -> bool:
+    """Check whether a module exports a function under the given name."""
+    return any(
+        export.kind == ExportKind.FUNC and export.name == name
+        for export in module.exports
+    )
+
+
+def default_entry(module: WasmModule) -> str:
+    """Get the entry function of an executable when --entry is not given.
 
-extern void __wasm_memory_init(void);
-extern int {native_name}(void);
+    WASI commands start from `_start`; anything else from `main`.
+    """
+    return "_start" if exports_function(module, "_start") else "main"
 
-int main(void) {{
-    __wasm_memory_init();
-    int result = {native_name}();
-    printf("%d\\n", result);
-    return 0;
-}}
-"""
-    return f"""\
-/* Generated main stub for WAQ */
 
-extern void __wasm_memory_init(void);
-extern int {native_name}(void);
+def entry_func_type(module: WasmModule, entry_function: str) -> FuncType:
+    """Get the type of the exported function an executable starts from.
 
-int main(void) {{
-    __wasm_memory_init();
-    return {native_name}();
-}}
-"""
+    Export names are compared after mangling, so `--entry main` finds an
+    export named either `main` or `wasm_main`.
+    """
+    native_name = mangle_export_name(entry_function)
+    for export in module.exports:
+        if (
+            export.kind == ExportKind.FUNC
+            and mangle_export_name(export.name) == native_name
+        ):
+            return module.get_func_type(export.index)
+    raise ValueError(f"entry function '{entry_function}' is not exported")
 
 
 def link_executable(
     obj_bytes: bytes,
     output_path: Path,
     entry_function: str,
+    func_type: FuncType,
     target: str,
     verbose: bool = False,
     *,
     print_result: bool = True,
+    wasi: WasiConfig | None = None,
+    initialize: bool = False,
+    instance_mode: bool = False,
 ) -> None:
     """Link object file with runtime to create executable.
 
     Uses TemporaryDirectory for reliable cleanup even on process termination.
+    In instance mode the runtime is compiled with WAQ_INSTANCE_MODE.
     """
     try:
         with tempfile.TemporaryDirectory(prefix="waq_") as tmpdir:
@@ -326,7 +1244,14 @@ def link_executable(
             temp_obj_path.write_bytes(obj_bytes)
 
             # Generate and write the main stub
-            main_stub = generate_main_stub(entry_function, print_result=print_result)
+            main_stub = generate_main_stub(
+                entry_function,
+                func_type,
+                print_result=print_result,
+                wasi=wasi,
+                initialize=initialize,
+                instance_mode=instance_mode,
+            )
             temp_main_path = tmpdir_path / "main.c"
             temp_main_path.write_text(main_stub, encoding="utf-8")
 
@@ -354,6 +1279,8 @@ def link_executable(
                     str(RUNTIME_C_SOURCE),
                     "-lm",
                 ]
+            if instance_mode:
+                cmd.append("-DWAQ_INSTANCE_MODE")
 
             subprocess.run(cmd, capture_output=True, text=True, check=True)
 
@@ -365,38 +1292,5 @@ def link_executable(
         ) from e
 
 
-def convert_wat_to_wasm(wat_content: str) -> bytes:
-    """Convert WAT text format to WASM binary format using wat2wasm.
-
-    Uses TemporaryDirectory for reliable cleanup even on process termination.
-    """
-    try:
-        with tempfile.TemporaryDirectory(prefix="waq_") as tmpdir:
-            tmpdir_path = Path(tmpdir)
-
-            temp_wat_path = tmpdir_path / "input.wat"
-            temp_wasm_path = tmpdir_path / "output.wasm"
-
-            temp_wat_path.write_text(wat_content, encoding="utf-8")
-
-            # Convert WAT to WASM
-            subprocess.run(
-                ["wat2wasm", str(temp_wat_path), "-o", str(temp_wasm_path)],
-                capture_output=True,
-                text=True,
-                check=True,
-            )
-
-            # Read the resulting WASM binary
-            return temp_wasm_path.read_bytes()
-
-    except (subprocess.CalledProcessError, FileNotFoundError) as e:
-        raise RuntimeError(
-            "Failed to convert WAT to WASM. "
-            "Ensure wat2wasm is installed (part of WebAssembly binary toolkit). "
-            f"Error: {e}"
-        ) from e
-
-
 if __name__ == "__main__":
     sys.exit(main())
@@ -2,6 +2,8 @@
 
 from __future__ import annotations
 
-from .codegen import compile_module
+from .codegen import BOUNDS_CHECK_MODES, compile_module
+from .imports import IMPORT_NAMES
+from .linker import link_modules
 
-__all__ = ["compile_module"]
+__all__ = ["BOUNDS_CHECK_MODES", "IMPORT_NAMES", "compile_module", "link_modules"]
@@ -2,6 +2,7 @@
 
 from __future__ import annotations
 
+import struct
 from typing import TYPE_CHECKING
 
 from qbepy import Function, Module
@@ -10,14 +11,15 @@ from qbepy.ir import (
     BinaryOp,
     Branch,
     Call,
+    Conversion,
     D,
     DataDef,
     Global,
-    Halt,
     IntConst,
     Jump,
     L,
     Label,
+    Load,
     Phi,
     Return,
     S,
@@ -26,19 +28,27 @@ from qbepy.ir import (
     W,
 )
 
-from waq.errors import CompileError
+from waq.errors import CompileError, ValidationError
 from waq.parser.binary import BinaryReader
-from waq.parser.module import ExportKind, WasmModule
-from waq.parser.types import ValueType
+from waq.parser.module import ExportKind, ImportKind, WasmModule
+from waq.parser.types import ArrayType, StructType, ValueType
+from waq.validator import validate_module
 
-from .context import FunctionContext, ModuleContext
-from .instructions.control import compile_control_instruction
+from .context import VMCTX, WASI_MODULE, FunctionContext, ModuleContext
+from .imports import IMPORT_NAMES
+from .instructions.control import compile_br_on_cast, compile_control_instruction
 from .instructions.conversion import (
     compile_conversion_instruction,
     compile_saturating_conversion,
 )
-from .instructions.exceptions import compile_exception_instruction
-from .instructions.gc import compile_gc_instruction
+from .instructions.exceptions import compile_exception_instruction, emit_local_escapes
+from .instructions.gc import (
+    compile_gc_instruction,
+    emit_gc_frame_pop,
+    emit_gc_frame_push,
+    gc_field_is_reference,
+    gc_slot_address,
+)
 from .instructions.memory import (
     compile_bulk_memory_instruction,
     compile_memory_instruction,
@@ -55,12 +65,97 @@ from .stack import ValueStack
 if TYPE_CHECKING:
     from qbepy.ir import Block
 
+    from .imports import ImportBindings
+
+
+BOUNDS_CHECK_MODES = ("none", "inline", "guard")
+
+# Limits of the runtime (see waq_runtime.c)
+WASM_MAX_MEMORIES = 16
+WASM_MAX_TABLES = 16
+WASM_MAX_TABLE_SIZE = 65536
+
+
+def compile_module(
+    wasm_module: WasmModule,
+    target: str = "amd64_sysv",
+    *,
+    bounds_checks: str = "none",
+    deterministic: bool = False,
+    instance_mode: bool = False,
+    import_names: str = "qualified",
+    imports: ImportBindings | None = None,
+    validate: bool = True,
+) -> Module:
+    """Compile a WASM module to a QBE module.
+
+    `bounds_checks` selects how linear memory accesses are sandboxed:
+    "none" emits raw accesses, "inline" compares every access against the
+    current memory size and traps with __wasm_trap_out_of_bounds, and
+    "guard" relies on the runtime's PROT_NONE guard region for 32-bit
+    memories (falling back to inline checks where it cannot help).
+
+    With `deterministic`, every float operation that can produce a NaN
+    returns the canonical one (through the runtime's `__wasm_canon_nan_*`
+    and `*_deterministic` helpers), so results do not depend on the host.
+
+    With `instance_mode`, the module's state (memory 0, table 0 and defined
+    globals) lives in a runtime `WasmInstance` instead of process-wide
+    symbols. Every function takes the instance as a hidden first parameter,
+    and exported functions make it the running instance for the runtime
+    while they execute, so one program can hold several independent
+    instances of the module. The runtime must be compiled with
+    WAQ_INSTANCE_MODE.
+
+    Imported functions are called by symbols qualified with their module
+    (`Z_envZ_log` for `env.log`, see waq.compiler.imports.mangle_import).
+    With `import_names="bare"` they are called by their name alone (`log`),
+    as hand-written host code for earlier versions expects; WASI functions
+    keep the runtime's symbols.
+
+    `imports` binds imported functions to host C functions (see
+    waq.compiler.imports). When it is given, every imported function must
+    be bound, except WASI's, and a CompileError lists the ones that aren't.
+
+    With `validate` (the default) the module is checked first and a
+    ValidationError listing every error is raised if it is invalid.
+    """
+    if bounds_checks not in BOUNDS_CHECK_MODES:
+        raise ValueError(f"unknown bounds check mode: {bounds_checks}")
+    if import_names not in IMPORT_NAMES:
+        raise ValueError(f"unknown import naming: {import_names}")
+
+    if validate:
+        result = validate_module(wasm_module)
+        if not result.is_valid:
+            raise ValidationError.from_result(result)
 
-def compile_module(wasm_module: WasmModule, target: str = "amd64_sysv") -> Module:
-    """Compile a WASM module to a QBE module."""
     qbe_module = Module()
 
-    mod_ctx = ModuleContext(module=wasm_module, qbe_module=qbe_module)
+    mod_ctx = ModuleContext(
+        module=wasm_module,
+        qbe_module=qbe_module,
+        bounds_checks=bounds_checks,
+        deterministic=deterministic,
+        instance_mode=instance_mode,
+        import_names=import_names,
+        imports=imports,
+    )
+    compile_module_context(mod_ctx)
+    return qbe_module
+
+
+def compile_module_context(mod_ctx: ModuleContext) -> None:
+    """Compile the module of `mod_ctx` into its QBE module."""
+    qbe_module = mod_ctx.qbe_module
+    assert qbe_module is not None
+    wasm_module = mod_ctx.module
+
+    if mod_ctx.imports is not None:
+        _check_imports(mod_ctx)
+    if mod_ctx.instance_mode:
+        _check_instance_mode(mod_ctx)
+    _check_runtime_limits(mod_ctx)
 
     # Compile globals
     _compile_globals(mod_ctx, qbe_module)
@@ -73,12 +168,97 @@ def compile_module(wasm_module: WasmModule, target: str = "amd64_sysv") -> Modul
     for i, body in enumerate(wasm_module.code):
         func_idx = num_imports + i
         _compile_function(mod_ctx, qbe_module, func_idx, body)
+    for func_idx in range(num_imports):
+        if mod_ctx.needs_adapter(func_idx):
+            _compile_import_adapter(mod_ctx, qbe_module, func_idx)
 
     # Always generate memory/table initialization function
     # (main stub always calls it)
     _compile_memory_init(mod_ctx, qbe_module)
 
-    return qbe_module
+
+def _check_imports(mod_ctx: ModuleContext) -> None:
+    """Check the host bindings of the imported functions.
+
+    Every import needs a binding, except WASI's and those another linked
+    module resolves; all the missing ones are reported at once.
+    """
+    module = mod_ctx.module
+    num_memories = module.num_imported_memories() + len(module.memories)
+    missing = []
+    func_imports = [i for i in module.imports if i.kind == ImportKind.FUNC]
+    for func_idx, imp in enumerate(func_imports):
+        if mod_ctx.link is not None and func_idx in mod_ctx.link.funcs:
+            continue
+        binding = mod_ctx.import_binding(func_idx)
+        if binding is None:
+            if imp.module != WASI_MODULE:
+                missing.append(f"  {imp.module}.{imp.name}")
+            continue
+
+        where = f"binding of {imp.module}.{imp.name}"
+        context = mod_ctx.import_context(func_idx)
+        if context == "instance" and not mod_ctx.instance_mode:
+            raise CompileError(f"{where}: the instance context needs instance mode")
+        if (context == "memory" or binding.buffers) and num_memories == 0:
+            raise CompileError(f"{where}: the module has no memory")
+        params = module.get_func_type(func_idx).params
+        for pair in binding.buffers:
+            for i in pair:
+                if i >= len(params) or params[i] not in (ValueType.I32, ValueType.I64):
+                    raise CompileError(
+                        f"{where}: buffer parameter {i} is not an integer parameter"
+                    )
+
+    if missing:
+        noun = "function" if len(missing) == 1 else "functions"
+        header = f"{len(missing)} imported {noun} without a binding in the manifest"
+        raise CompileError("\n".join([header, *missing]))
+
+
+def _check_instance_mode(mod_ctx: ModuleContext) -> None:
+    """Reject modules instance mode cannot compile.
+
+    WASI functions don't take the instance, so they can only be called
+    directly, never through a table or from the host.
+    """
+    for func_idx in _referenced_functions(mod_ctx.module):
+        if mod_ctx.vmctx_args(func_idx):
+            continue
+        name = mod_ctx.get_func_name(func_idx)
+        raise CompileError(
+            f"WASI function {name} cannot be referenced in instance mode",
+            func_idx=func_idx,
+        )
+
+
+def _check_runtime_limits(mod_ctx: ModuleContext) -> None:
+    """Reject modules that exceed the runtime's memory and table limits.
+
+    Raises:
+        CompileError: If a limit is exceeded
+    """
+    module = mod_ctx.module
+    num_memories = module.num_imported_memories() + len(module.memories)
+    for memory_idx in range(num_memories):
+        if mod_ctx.memory_index(memory_idx) >= WASM_MAX_MEMORIES:
+            raise CompileError(
+                f"memory {memory_idx}: the runtime supports "
+                f"{WASM_MAX_MEMORIES} memories"
+            )
+    num_tables = module.num_imported_tables() + len(module.tables)
+    for table_idx in range(num_tables):
+        if mod_ctx.table_index(table_idx) >= WASM_MAX_TABLES:
+            raise CompileError(
+                f"table {table_idx}: the runtime supports {WASM_MAX_TABLES} tables"
+            )
+    for i, table in enumerate(module.tables):
+        if table.limits.min > WASM_MAX_TABLE_SIZE:
+            raise CompileError(
+                f"table {module.num_imported_tables() + i}: initial size "
+                f"{table.limits.min} exceeds the runtime maximum of "
+                f"{WASM_MAX_TABLE_SIZE} entries"
+            )
 
 
 def _compile_data_segments(mod_ctx: ModuleContext, qbe_module: Module) -> None:
@@ -86,10 +266,10 @@ def _compile_data_segments(mod_ctx: ModuleContext, qbe_module: Module) -> None:
     for i, segment in enumerate(mod_ctx.module.data):
         if segment.memory_idx == -1:
             # Passive segment - just store the data, will be used by memory.init
-            data_name = f"__wasm_data_{i}"
+            data_name = mod_ctx.symbol(f"__wasm_data_{i}")
         else:
             # Active segment - will be copied to memory at init
-            data_name = f"__wasm_data_{i}"
+            data_name = mod_ctx.symbol(f"__wasm_data_{i}")
 
         data_def = DataDef(data_name)
         # Add raw bytes
@@ -104,55 +284,79 @@ def _compile_memory_init(mod_ctx: ModuleContext, qbe_module: Module) -> None:
     # 2. Copies active data segments to memory
     # 3. Initializes tables and element segments
 
-    init_func = Function("__wasm_memory_init", return_type=None, params=[], export=True)
+    # In instance mode the runtime calls it with the instance being set up
+    params = [(L, VMCTX)] if mod_ctx.instance_mode else []
+    init_func = Function(
+        mod_ctx.init_function, return_type=None, params=params, export=True
+    )
     entry_block = init_func.add_block("entry")
 
-    # Initialize memory with initial pages
-    if mod_ctx.module.memories:
-        mem = mod_ctx.module.memories[0]
-        initial_pages = mem.limits.min
-        if initial_pages > 0:
+    if mod_ctx.instance_mode:
+        _init_instance_globals(mod_ctx, entry_block)
+
+    # Initialize every memory with its limits and initial pages (defined
+    # memories follow the imported ones in the index space)
+    num_imported_memories = mod_ctx.module.num_imported_memories()
+    memory_indices = [
+        mod_ctx.memory_index(num_imported_memories + i)
+        for i in range(len(mod_ctx.module.memories))
+    ]
+    if 0 in memory_indices and mod_ctx.bounds_checks == "guard":
+        # Move memory 0 into the reserved guard region before it grows
+        entry_block.instructions.append(
+            Call(target=Global("__wasm_memory_guard_init"), args=[])
+        )
+    for mem_idx, mem in zip(memory_indices, mod_ctx.module.memories, strict=True):
+        if mem.limits.max is not None:
             entry_block.instructions.append(
                 Call(
-                    target=Global("__wasm_memory_grow"),
-                    args=[(W, IntConst(initial_pages))],
+                    target=Global("__wasm_memory_set_max"),
+                    args=[(W, IntConst(mem_idx)), (W, IntConst(mem.limits.max))],
                 )
             )
+        if mem.limits.min == 0:
+            continue
+        if mem_idx == 0:
+            grow = Call(
+                target=Global("__wasm_memory_grow"),
+                args=[(W, IntConst(mem.limits.min))],
+            )
+        else:
+            grow = Call(
+                target=Global("__wasm_memory_grow_idx"),
+                args=[(W, IntConst(mem_idx)), (W, IntConst(mem.limits.min))],
+            )
+        entry_block.instructions.append(grow)
 
     # Copy active data segments
+    memory_types = mod_ctx.module.memory_types()
     for i, segment in enumerate(mod_ctx.module.data):
         if segment.memory_idx == -1:
             continue  # Skip passive segments
 
-        # Evaluate offset expression
-        offset = _eval_init_expr(segment.offset_expr, mod_ctx)
+        # Offsets are unsigned; i64 constants already have the right bits
+        offset = int(eval_init_expr(segment.offset_expr, mod_ctx))
+        if not memory_types[segment.memory_idx].is_memory64:
+            offset &= 0xFFFFFFFF
         data_len = len(segment.data)
 
-        if data_len == 0:
-            continue
-
-        # Get memory base pointer
-        mem_base = f"mem_base_{i}"
-        entry_block.instructions.append(
-            Call(
-                target=Global("__wasm_memory_base"),
-                args=[],
-                result=Temporary(mem_base),
-                result_type=L,
-            )
-        )
-
-        # Calculate destination address: mem_base + offset
+        # The runtime traps unless the segment fits in the memory (even an
+        # empty one must not start past its end)
         dest_addr = f"dest_{i}"
         entry_block.instructions.append(
-            BinaryOp(
+            Call(
+                target=Global("__wasm_memory_buffer"),
+                args=[
+                    (W, IntConst(mod_ctx.memory_index(segment.memory_idx))),
+                    (L, IntConst(offset)),
+                    (L, IntConst(data_len)),
+                ],
                 result=Temporary(dest_addr),
                 result_type=L,
-                op="add",
-                left=Temporary(mem_base),
-                right=IntConst(int(offset)),
             )
         )
+        if data_len == 0:
+            continue
 
         # Call memcpy to copy data segment
         # void *memcpy(void *dest, const void *src, size_t n)
@@ -161,56 +365,125 @@ def _compile_memory_init(mod_ctx: ModuleContext, qbe_module: Module) -> None:
                 target=Global("memcpy"),
                 args=[
                     (L, Temporary(dest_addr)),
-                    (L, Global(f"__wasm_data_{i}")),
+                    (L, Global(mod_ctx.symbol(f"__wasm_data_{i}"))),
                     (L, IntConst(data_len)),
                 ],
             )
         )
 
-    # Initialize tables
-    if mod_ctx.module.tables:
-        table = mod_ctx.module.tables[0]
-        initial_size = table.limits.min
-        if initial_size > 0:
+    # Register passive data segments for memory.init and array.new_data
+    # (active segments count as dropped once copied)
+    for i, segment in enumerate(mod_ctx.module.data):
+        if segment.memory_idx != -1:
+            continue
+        entry_block.instructions.append(
+            Call(
+                target=Global("__wasm_register_data_segment"),
+                args=[
+                    (W, IntConst(mod_ctx.data_index(i))),
+                    (L, Global(mod_ctx.symbol(f"__wasm_data_{i}"))),
+                    (L, IntConst(len(segment.data))),
+                ],
+            )
+        )
+
+    # Register signature tags for functions that can end up in a table
+    # (call_indirect checks them against the expected type)
+    for func_idx in _referenced_functions(mod_ctx.module):
+        type_idx = _func_type_index(mod_ctx.module, func_idx)
+        entry_block.instructions.append(
+            Call(
+                target=Global("__wasm_func_sig_register"),
+                args=[
+                    (L, Global(mod_ctx.get_func_name(func_idx))),
+                    (W, IntConst(mod_ctx.canonical_type_id(type_idx))),
+                ],
+            )
+        )
+
+    # Function addresses are only known at link time, so globals initialized
+    # with ref.func are set here (instance globals are set above)
+    if not mod_ctx.instance_mode:
+        num_imported_globals = mod_ctx.module.num_imported_globals()
+        for i, glob in enumerate(mod_ctx.module.globals):
+            func_idx = _init_expr_func(mod_ctx.module, glob.init_expr)
+            if func_idx is None:
+                continue
+            global_name = mod_ctx.get_global_name(num_imported_globals + i)
+            entry_block.instructions.append(
+                Store(
+                    store_type="storel",
+                    value=Global(mod_ctx.get_func_name(func_idx)),
+                    address=Global(global_name),
+                )
+            )
+
+    # Initialize every table with its limits and initial entries (null
+    # unless the table has an init expression)
+    num_imported_tables = mod_ctx.module.num_imported_tables()
+    for i, table in enumerate(mod_ctx.module.tables):
+        table_idx = mod_ctx.table_index(num_imported_tables + i)
+        if table.limits.max is not None:
+            # Table64 maximums beyond 32 bits cannot be reached anyway
+            max_size = min(table.limits.max, 0xFFFFFFFF)
+            entry_block.instructions.append(
+                Call(
+                    target=Global("__wasm_table_set_max"),
+                    args=[(W, IntConst(table_idx)), (W, IntConst(max_size))],
+                )
+            )
+        if table.limits.min > 0:
+            init_value = _table_init_value(mod_ctx, i)
             entry_block.instructions.append(
                 Call(
                     target=Global("__wasm_table_grow"),
-                    args=[(W, IntConst(initial_size)), (L, IntConst(0))],
+                    args=[
+                        (W, IntConst(table_idx)),
+                        (L, init_value),
+                        (W, IntConst(table.limits.min)),
+                    ],
                 )
             )
 
     # Initialize element segments
-    for elem_seg in mod_ctx.module.elements:
+    for i, elem_seg in enumerate(mod_ctx.module.elements):
+        if elem_seg.declarative:
+            continue  # Only declares ref.func targets
         if elem_seg.table_idx < 0:
-            continue  # Skip passive segments
+            # Passive segment - kept by the runtime for array.new_elem
+            _register_elem_segment(mod_ctx, entry_block, i, elem_seg.func_indices)
+            continue
 
         # Evaluate offset expression
-        offset = _eval_init_expr(elem_seg.offset_expr, mod_ctx)
+        offset = eval_init_expr(elem_seg.offset_expr, mod_ctx)
+        table_idx = mod_ctx.table_index(elem_seg.table_idx)
 
         # For each function index, set the table entry
         for j, func_idx in enumerate(elem_seg.func_indices):
-            # Calculate table index
-            table_idx = int(offset) + j
             func_name = mod_ctx.get_func_name(func_idx)
 
-            # Set table[table_idx] = func_ptr
+            # Set table[offset + j] = func_ptr
             entry_block.instructions.append(
                 Call(
                     target=Global("__wasm_table_set"),
                     args=[
                         (W, IntConst(table_idx)),
+                        (W, IntConst(int(offset) + j)),
                         (L, Global(func_name)),
                     ],
                 )
             )
 
+    if mod_ctx.uses_gc:
+        _register_gc_types(mod_ctx, qbe_module, entry_block)
+
     # Call start function if specified
     if mod_ctx.module.start is not None:
         start_func_name = mod_ctx.get_func_name(mod_ctx.module.start)
         entry_block.instructions.append(
             Call(
                 target=Global(start_func_name),
-                args=[],
+                args=mod_ctx.vmctx_args(mod_ctx.module.start),
             )
         )
 
@@ -218,11 +491,246 @@ def _compile_memory_init(mod_ctx: ModuleContext, qbe_module: Module) -> None:
     qbe_module.add_function(init_func)
 
 
+def _init_instance_globals(mod_ctx: ModuleContext, entry_block: Block) -> None:
+    """Allocate and initialize the instance's slots for the defined globals.
+
+    Values are stored as their bit patterns, so float NaN payloads survive.
+    """
+    globals_ = mod_ctx.module.globals
+    if not globals_:
+        return
+    entry_block.instructions.append(
+        Call(
+            target=Global("__wasm_instance_globals"),
+            args=[(W, IntConst(len(globals_)))],
+            result=Temporary("globals"),
+            result_type=L,
+        )
+    )
+    evaluated_globals: dict[int, int | float] = {}
+    num_imports = mod_ctx.module.num_imported_globals()
+    for i, glob in enumerate(globals_):
+        vtype = glob.type.value_type
+        value = eval_init_expr(glob.init_expr, mod_ctx, evaluated_globals)
+        evaluated_globals[num_imports + i] = value
+        if vtype == ValueType.F32:
+            bits = struct.unpack("<I", struct.pack("<f", value))[0]
+        elif vtype == ValueType.F64:
+            bits = struct.unpack("<q", struct.pack("<d", value))[0]
+        else:
+            bits = int(value)
+        slot_value: IntConst | Global = IntConst(bits)
+        func_idx = _init_expr_func(mod_ctx.module, glob.init_expr)
+        if func_idx is not None:
+            slot_value = Global(mod_ctx.get_func_name(func_idx))
+        elif bits == 0:
+            continue  # The slots start zeroed
+        slot = Temporary(f"global_{i}" if i else "globals")
+        if i:
+            entry_block.instructions.append(
+                BinaryOp(
+                    result=slot,
+                    result_type=L,
+                    op="add",
+                    left=Temporary("globals"),
+                    right=IntConst(8 * i),
+                )
+            )
+        size = _vtype_size(vtype)
+        entry_block.instructions.append(
+            Store(
+                store_type="storew" if size == 4 else "storel",
+                value=slot_value,
+                address=slot,
+            )
+        )
+
+
+def _register_elem_segment(
+    mod_ctx: ModuleContext, entry_block: Block, idx: int, func_indices: list[int]
+) -> None:
+    """Register the functions of a passive element segment with the runtime."""
+    if not func_indices:
+        return
+    idx = mod_ctx.elem_index(idx)
+    entry_block.instructions.append(
+        Call(
+            target=Global("__wasm_register_elem_segment"),
+            args=[(W, IntConst(idx)), (W, IntConst(len(func_indices)))],
+        )
+    )
+    for j, func_idx in enumerate(func_indices):
+        entry_block.instructions.append(
+            Call(
+                target=Global("__wasm_elem_segment_set"),
+                args=[
+                    (W, IntConst(idx)),
+                    (W, IntConst(j)),
+                    (L, Global(mod_ctx.get_func_name(func_idx))),
+                ],
+            )
+        )
+
+
+def _register_gc_types(
+    mod_ctx: ModuleContext, qbe_module: Module, entry_block: Block
+) -> None:
+    """Register struct/array/tag layouts and reference globals with the collector.
+
+    Each layout is a byte per field (one for an array's elements), nonzero
+    when the field can hold a GC reference the collector must trace. Objects
+    are tagged with canonical type ids, so casts can follow the declared
+    supertype chain. Tags get a byte per param, so the collector traces the
+    reference slots of exception payloads; tags without any are left out.
+    """
+    module = mod_ctx.module
+    for type_idx, type_def in enumerate(module.types):
+        if isinstance(type_def, StructType):
+            kind = 0
+            fields = type_def.fields
+        elif isinstance(type_def, ArrayType):
+            kind = 1
+            fields = (type_def.element_type,)
+        else:
+            continue
+
+        refs = [int(gc_field_is_reference(module, f.storage_type)) for f in fields]
+        if refs:
+            layout_name = mod_ctx.symbol(f"__wasm_gc_layout_{type_idx}")
+            layout = DataDef(layout_name)
+            layout.items.append(("b", refs))
+            qbe_module.add_data(layout)
+            layout_ref = Global(layout_name)
+        else:
+            layout_ref = IntConst(0)
+
+        supertype = module.supertypes.get(type_idx)
+        if supertype is not None:
+            supertype = mod_ctx.canonical_type_id(supertype)
+
+        entry_block.instructions.append(
+            Call(
+                target=Global("__wasm_gc_register_type"),
+                args=[
+                    (W, IntConst(mod_ctx.type_index(type_idx))),
+                    (W, IntConst(kind)),
+                    (W, IntConst(len(fields))),
+                    (L, layout_ref),
+                    (W, IntConst(-1 if supertype is None else supertype)),
+                ],
+            )
+        )
+
+    for tag_idx in range(module.num_imported_tags() + len(module.tags)):
+        params = module.get_tag_type(tag_idx).params
+        refs = [int(p.is_gc_reference()) for p in params]
+        if not any(refs):
+            continue
+        layout_name = mod_ctx.symbol(f"__wasm_gc_tag_layout_{tag_idx}")
+        layout = DataDef(layout_name)
+        layout.items.append(("b", refs))
+        qbe_module.add_data(layout)
+        entry_block.instructions.append(
+            Call(
+                target=Global("__wasm_gc_register_tag"),
+                args=[
+                    (W, IntConst(mod_ctx.tag_index(tag_idx))),
+                    (W, IntConst(len(refs))),
+                    (L, Global(layout_name)),
+                ],
+            )
+        )
+
+    num_imports = module.num_imported_globals()
+    for i, glob in enumerate(module.globals):
+        if not glob.type.value_type.is_gc_reference():
+            continue
+        if mod_ctx.instance_mode:
+            # A slot of the instance (see _init_instance_globals)
+            root = Temporary(f"root_{i}")
+            entry_block.instructions.append(
+                BinaryOp(
+                    result=root,
+                    result_type=L,
+                    op="add",
+                    left=Temporary("globals"),
+                    right=IntConst(8 * i),
+                )
+            )
+        else:
+            root = Global(mod_ctx.get_global_name(num_imports + i))
+        entry_block.instructions.append(
+            Call(target=Global("__wasm_gc_add_root"), args=[(L, root)])
+        )
+
+
+def _referenced_functions(module: WasmModule) -> list[int]:
+    """Functions whose references can be stored in a table.
+
+    These are the functions listed in element segments (including
+    declarative ones, which declare ref.func targets), exported functions
+    and the targets of ref.func in global and table initializers.
+    """
+    funcs = {idx for seg in module.elements for idx in seg.func_indices}
+    funcs.update(e.index for e in module.exports if e.kind == ExportKind.FUNC)
+    init_exprs = [glob.init_expr for glob in module.globals]
+    init_exprs += module.table_inits.values()
+    for expr in init_exprs:
+        func_idx = _init_expr_func(module, expr)
+        if func_idx is not None:
+            funcs.add(func_idx)
+    return sorted(funcs)
+
+
+def _table_init_value(mod_ctx: ModuleContext, position: int) -> IntConst | Global:
+    """Get the initial element of the defined table at `position`."""
+    expr = mod_ctx.module.table_inits.get(position, b"")
+    func_idx = _init_expr_func(mod_ctx.module, expr)
+    if func_idx is not None:
+        return Global(mod_ctx.get_func_name(func_idx))
+    return IntConst(int(eval_init_expr(expr, mod_ctx)))
+
+
+def _init_expr_func(module: WasmModule, expr: bytes) -> int | None:
+    """Get the function an init expression refers to with ref.func, if any.
+
+    global.get of a defined global is followed to that global's initializer.
+    """
+    if not expr:
+        return None
+    reader = BinaryReader(expr)
+    opcode = reader.read_byte()
+    if opcode == 0xD2:  # ref.func
+        return reader.read_u32_leb128()
+    if opcode == 0x23:  # global.get
+        local_idx = reader.read_u32_leb128() - module.num_imported_globals()
+        # Globals can only refer to earlier globals, so this terminates
+        if 0 <= local_idx < len(module.globals):
+            return _init_expr_func(module, module.globals[local_idx].init_expr)
+    return None
+
+
+def _func_type_index(module: WasmModule, func_idx: int) -> int:
+    """Get the type index of an imported or defined function."""
+    num_imports = module.num_imported_funcs()
+    if func_idx >= num_imports:
+        return module.func_types[func_idx - num_imports]
+    func_imports = [imp for imp in module.imports if imp.kind == ImportKind.FUNC]
+    type_idx = func_imports[func_idx].desc
+    assert isinstance(type_idx, int)
+    return type_idx
+
+
 def _compile_globals(mod_ctx: ModuleContext, qbe_module: Module) -> None:
     """Compile global variable definitions.
 
     Handles global.get references by evaluating globals in dependency order.
+    In instance mode the globals are slots of the instance instead (see
+    _init_instance_globals).
     """
+    if mod_ctx.instance_mode:
+        return
+
     # Track evaluated global values for handling global.get references
     evaluated_globals: dict[int, int | float] = {}
     num_imports = mod_ctx.module.num_imported_globals()
@@ -234,7 +742,7 @@ def _compile_globals(mod_ctx: ModuleContext, qbe_module: Module) -> None:
 
         # Evaluate init expression to get initial value
         # Pass evaluated_globals to handle global.get references
-        init_value = _eval_init_expr(glob.init_expr, mod_ctx, evaluated_globals)
+        init_value = eval_init_expr(glob.init_expr, mod_ctx, evaluated_globals)
 
         # Store the evaluated value for potential references by later globals
         evaluated_globals[global_idx] = init_value
@@ -249,10 +757,12 @@ def _compile_globals(mod_ctx: ModuleContext, qbe_module: Module) -> None:
             data.add_singles(float(init_value))
         elif vtype == ValueType.F64:
             data.add_doubles(float(init_value))
+        elif vtype.is_reference():
+            data.add_longs(int(init_value))
         qbe_module.add_data(data)
 
 
-def _eval_init_expr(
+def eval_init_expr(
     expr: bytes,
     mod_ctx: ModuleContext,
     evaluated_globals: dict[int, int | float] | None = None,
@@ -292,6 +802,11 @@ def _eval_init_expr(
         if global_idx in evaluated_globals:
             return evaluated_globals[global_idx]
 
+        # Imported globals are known when another linked module exports them
+        link = mod_ctx.link
+        if link is not None and global_idx in link.global_values:
+            return link.global_values[global_idx]
+
         # For imported globals, we can't know the value at compile time
         # Return 0 as a placeholder (runtime will need to handle this)
         num_imports = mod_ctx.module.num_imported_globals()
@@ -304,7 +819,7 @@ def _eval_init_expr(
         local_idx = global_idx - num_imports
         if local_idx < len(mod_ctx.module.globals):
             glob = mod_ctx.module.globals[local_idx]
-            value = _eval_init_expr(glob.init_expr, mod_ctx, evaluated_globals)
+            value = eval_init_expr(glob.init_expr, mod_ctx, evaluated_globals)
             evaluated_globals[global_idx] = value
             return value
 
@@ -412,10 +927,16 @@ def _compile_function(
     else:
         ret_type = _vtype_to_ir_type(func_type.results[0])
 
-    # Create QBE function
-    qbe_func = Function(
-        func_name, return_type=ret_type, params=params, export=is_exported
-    )
+    # In instance mode the instance comes first, and exports go through a
+    # wrapper (see _compile_export_wrapper)
+    if mod_ctx.instance_mode:
+        qbe_func = Function(
+            func_name, return_type=ret_type, params=[(L, VMCTX), *params]
+        )
+    else:
+        qbe_func = Function(
+            func_name, return_type=ret_type, params=params, export=is_exported
+        )
 
     # Set up function context
     locals_list = list(func_type.params) + body.all_locals()
@@ -433,9 +954,19 @@ def _compile_function(
     # Create entry block
     entry_block = qbe_func.add_block("entry")
 
+    # GC modules root references in a shadow stack frame (see instructions.gc)
+    if mod_ctx.uses_gc:
+        func_ctx.gc_frame = "gc_frame"
+
     # Allocate stack space for ALL locals (including parameters)
     # This allows locals to be mutable across loop iterations
     for i, vtype in enumerate(locals_list):
+        if func_ctx.gc_frame is not None and vtype.is_gc_reference():
+            # Reference locals live in the shadow stack frame
+            addr_name = gc_slot_address(func_ctx, entry_block, func_ctx.gc_local_slots)
+            func_ctx.gc_local_slots += 1
+            func_ctx.set_local_addr(i, addr_name)
+            continue
         addr_name = f"local_addr{i}"
         size = _vtype_size(vtype)
         align = 8 if size == 8 else 4
@@ -443,6 +974,7 @@ def _compile_function(
             Alloc(result=Temporary(addr_name), size=IntConst(size), align=align)
         )
         func_ctx.set_local_addr(i, addr_name)
+    func_ctx.gc_frame_slots = func_ctx.gc_local_slots
 
     # Store WASM parameters into their stack slots
     # (skip out-parameters for multi-value returns)
@@ -473,7 +1005,7 @@ def _compile_function(
 
     # Compile function body
     current_block = entry_block
-    reader = BinaryReader(body.code)
+    reader = BinaryReader(body.code, types=mod_ctx.module.types)
 
     while not reader.at_end:
         try:
@@ -490,6 +1022,7 @@ def _compile_function(
 
     # Add implicit return if needed (only if block doesn't already have a terminator)
     if current_block.terminator is None:
+        emit_gc_frame_pop(func_ctx, current_block)
         if not func_type.results:
             current_block.terminator = Return(value=None)
         elif len(func_type.results) == 1 and func_ctx.stack.depth > 0:
@@ -521,8 +1054,136 @@ def _compile_function(
         else:
             current_block.terminator = Return(value=None)
 
+    emit_local_escapes(func_ctx, entry_block)
+    emit_gc_frame_push(func_ctx, entry_block)
+
     # Add function to module
     qbe_module.add_function(qbe_func)
+    if mod_ctx.instance_mode:
+        _compile_export_wrapper(mod_ctx, qbe_module, func_idx, ret_type, params)
+
+
+def _compile_export_wrapper(
+    mod_ctx: ModuleContext,
+    qbe_module: Module,
+    func_idx: int,
+    ret_type,
+    params: list,
+) -> None:
+    """Export a function compiled in instance mode.
+
+    The wrapper makes the instance it is called with the running one while
+    the function executes, and restores the previous one afterwards so that
+    calls into other instances can nest.
+    """
+    export_name = mod_ctx.export_name(func_idx)
+    if export_name is None:
+        return
+    wrapper = Function(
+        export_name, return_type=ret_type, params=[(L, VMCTX), *params], export=True
+    )
+    block = wrapper.add_block("entry")
+    block.instructions.append(
+        Call(
+            target=Global("__wasm_instance_enter"),
+            args=[(L, Temporary(VMCTX))],
+            result=Temporary("prev"),
+            result_type=L,
+        )
+    )
+    result = None if ret_type is None else Temporary("result")
+    block.instructions.append(
+        Call(
+            target=Global(mod_ctx.get_func_name(func_idx)),
+            args=[(L, Temporary(VMCTX))]
+            + [(qbe_type, Temporary(name)) for qbe_type, name in params],
+            result=result,
+            result_type=ret_type,
+        )
+    )
+    block.instructions.append(
+        Call(
+            target=Global("__wasm_instance_leave"), args=[(L, Temporary("prev"))]
+        )
+    )
+    block.terminator = Return(value=result)
+    qbe_module.add_function(wrapper)
+
+
+def _compile_import_adapter(
+    mod_ctx: ModuleContext, qbe_module: Module, func_idx: int
+) -> None:
+    """Call the C function bound to an import (see waq.compiler.imports).
+
+    The adapter has the signature of the import as compiled code calls it.
+    It passes the binding's context first, then the arguments, with each
+    buffer's address replaced by its native pointer once the runtime has
+    checked that the buffer lies in memory 0.
+    """
+    binding = mod_ctx.import_binding(func_idx)
+    assert binding is not None
+    func_type = mod_ctx.module.get_func_type(func_idx)
+    params = [(_vtype_to_ir_type(t), f"p{i}") for i, t in enumerate(func_type.params)]
+    params += [(L, f"retptr{i}") for i in range(1, len(func_type.results))]
+    ret_type = _vtype_to_ir_type(func_type.results[0]) if func_type.results else None
+    hidden = [(L, VMCTX)] if mod_ctx.vmctx_args(func_idx) else []
+    adapter = Function(
+        mod_ctx.get_func_name(func_idx), return_type=ret_type, params=hidden + params
+    )
+    block = adapter.add_block("entry")
+    memory_idx = IntConst(mod_ctx.memory_index(0))
+
+    args: list = [(qbe_type, Temporary(name)) for qbe_type, name in params]
+    for ptr_idx, len_idx in binding.buffers:
+        # 64-bit address and length for the runtime
+        operands = []
+        for i in (ptr_idx, len_idx):
+            if func_type.params[i] == ValueType.I64:
+                operands.append(Temporary(f"p{i}"))
+                continue
+            wide = Temporary(f"wide{i}_{ptr_idx}")
+            block.instructions.append(
+                Conversion(
+                    op="extuw", result=wide, result_type=L, operand=Temporary(f"p{i}")
+                )
+            )
+            operands.append(wide)
+        pointer = Temporary(f"buffer{ptr_idx}")
+        block.instructions.append(
+            Call(
+                target=Global("__wasm_memory_buffer"),
+                args=[(W, memory_idx), (L, operands[0]), (L, operands[1])],
+                result=pointer,
+                result_type=L,
+            )
+        )
+        args[ptr_idx] = (L, pointer)
+
+    context = mod_ctx.import_context(func_idx)
+    if context == "instance":
+        args.insert(0, (L, Temporary(VMCTX)))
+    elif context == "memory":
+        block.instructions.append(
+            Call(
+                target=Global("__wasm_memory_base_idx"),
+                args=[(W, memory_idx)],
+                result=Temporary("memory"),
+                result_type=L,
+            )
+        )
+        args.insert(0, (L, Temporary("memory")))
+
+    result = None if ret_type is None else Temporary("result")
+    block.instructions.append(
+        Call(
+            target=Global(binding.symbol),
+            args=args,
+            result=result,
+            result_type=ret_type,
+        )
+    )
+    block.terminator = Return(value=result)
+    qbe_module.add_function(adapter)
 
 
 def _compile_instruction(
@@ -542,6 +1203,8 @@ def _compile_instruction(
 
     def read_operand(kind: str):
         """Read instruction operand."""
+        if kind == "u8":
+            return reader.read_byte()
         if kind == "u32":
             return reader.read_u32_leb128()
         if kind == "s32":
@@ -556,9 +1219,9 @@ def _compile_instruction(
             return reader.read_block_type()
         raise ValueError(f"unknown operand kind: {kind}")
 
-    # Try exception instructions first (0x06-0x09, 0x18-0x19)
+    # Try exception instructions first (0x06-0x0A, 0x18-0x19, 0x1F)
     # These opcodes overlap with control flow range, so check them first
-    if opcode in (0x06, 0x07, 0x08, 0x09, 0x18, 0x19):
+    if opcode in (0x06, 0x07, 0x08, 0x09, 0x0A, 0x18, 0x19, 0x1F):
         exc_result = compile_exception_instruction(
             opcode, func_ctx, mod_ctx, qbe_func, block, read_operand
         )
@@ -577,19 +1240,22 @@ def _compile_instruction(
         return None
 
     # Try numeric instructions
-    if compile_numeric_instruction(opcode, func_ctx, block, read_operand):
+    if compile_numeric_instruction(opcode, func_ctx, mod_ctx, block, read_operand):
         return None
 
-    # Try memory instructions
-    if compile_memory_instruction(opcode, func_ctx, mod_ctx, block, read_operand):
-        return None
+    # Try memory instructions (bounds checks may start a new block)
+    mem_block = compile_memory_instruction(
+        opcode, func_ctx, mod_ctx, qbe_func, block, read_operand
+    )
+    if mem_block is not None:
+        return mem_block
 
     # Try table instructions (table.get 0x25, table.set 0x26)
     if compile_table_instruction(opcode, func_ctx, mod_ctx, block, read_operand):
         return None
 
     # Try conversion instructions
-    if compile_conversion_instruction(opcode, func_ctx, block):
+    if compile_conversion_instruction(opcode, func_ctx, mod_ctx, block):
         return None
 
     # Try reference instructions (ref.null 0xD0, ref.is_null 0xD1, ref.func 0xD2)
@@ -685,117 +1351,13 @@ def _compile_instruction(
         )
         return merge_block
 
-    # ref.as_non_null (0xD4) - assert reference is non-null, trap if null
-    if opcode == 0xD4:
-        ref = func_ctx.stack.pop()
-        # Check if ref is null
-        is_null = func_ctx.stack.new_temp_no_push(ValueType.I32)
-        block.instructions.append(
-            BinaryOp(
-                result=Temporary(is_null.name),
-                result_type=W,
-                op="ceql",
-                left=Temporary(ref.name),
-                right=IntConst(0),
-            )
-        )
-
-        # Branch: if null, trap; otherwise continue
-        trap_label = func_ctx.new_label("ref_null_trap")
-        cont_label = func_ctx.new_label("ref_non_null")
-
-        block.terminator = Branch(
-            condition=Temporary(is_null.name),
-            if_true=Label(trap_label),
-            if_false=Label(cont_label),
-        )
-
-        # Trap block
-        trap_block = qbe_func.add_block(trap_label[1:])
-        trap_block.instructions.append(
-            Call(target=Global("__wasm_trap_null_reference"), args=[])
-        )
-        trap_block.terminator = Halt()
-
-        # Continue block - ref is non-null, push it back
-        cont_block = qbe_func.add_block(cont_label[1:])
-        func_ctx.stack.push(ref)
-        return cont_block
-
-    # br_on_null (0xD5) - branch if reference is null
-    if opcode == 0xD5:
-        depth = read_operand("u32")
-        ref = func_ctx.stack.pop()
-
-        # Check if ref is null
-        is_null = func_ctx.stack.new_temp_no_push(ValueType.I32)
-        block.instructions.append(
-            BinaryOp(
-                result=Temporary(is_null.name),
-                result_type=W,
-                op="ceql",
-                left=Temporary(ref.name),
-                right=IntConst(0),
-            )
-        )
-
-        target = func_ctx.get_branch_target(depth)
-        branch_label = func_ctx.new_label("br_on_null_branch")
-        cont_label = func_ctx.new_label("br_on_null_cont")
-
-        block.terminator = Branch(
-            condition=Temporary(is_null.name),
-            if_true=Label(branch_label),
-            if_false=Label(cont_label),
-        )
-
-        # Branch block - jump to target
-        branch_block = qbe_func.add_block(branch_label[1:])
-        branch_block.terminator = Jump(target=Label(target.label_name))
-
-        # Continue block - ref is non-null, push it back
-        cont_block = qbe_func.add_block(cont_label[1:])
-        func_ctx.stack.push(ref)
-        return cont_block
-
-    # br_on_non_null (0xD6) - branch if reference is non-null
-    if opcode == 0xD6:
-        depth = read_operand("u32")
-        ref = func_ctx.stack.pop()
-
-        # Check if ref is non-null
-        is_not_null = func_ctx.stack.new_temp_no_push(ValueType.I32)
-        block.instructions.append(
-            BinaryOp(
-                result=Temporary(is_not_null.name),
-                result_type=W,
-                op="cnel",  # compare not equal (long)
-                left=Temporary(ref.name),
-                right=IntConst(0),
-            )
-        )
-
-        target = func_ctx.get_branch_target(depth)
-        branch_label = func_ctx.new_label("br_on_non_null_branch")
-        cont_label = func_ctx.new_label("br_on_non_null_cont")
-
-        block.terminator = Branch(
-            condition=Temporary(is_not_null.name),
-            if_true=Label(branch_label),
-            if_false=Label(cont_label),
-        )
-
-        # Branch block - push ref and jump to target
-        branch_block = qbe_func.add_block(branch_label[1:])
-        # Note: When branching, the non-null ref is passed to the target
-        branch_block.terminator = Jump(target=Label(target.label_name))
-
-        # Continue block - ref was null, don't push anything
-        return qbe_func.add_block(cont_label[1:])
-
     # 0xFB prefix: GC instructions (struct, array, i31, ref.cast, ref.test)
     if opcode == 0xFB:
         sub_opcode = reader.read_u32_leb128()
+        if sub_opcode in (0x18, 0x19):
+            return compile_br_on_cast(
+                sub_opcode, func_ctx, mod_ctx, qbe_func, block, read_operand
+            )
         if compile_gc_instruction(sub_opcode, func_ctx, mod_ctx, block, read_operand):
             return None
         raise func_ctx.make_error(f"unhandled 0xFB sub-opcode: 0x{sub_opcode:02x}")
@@ -806,10 +1368,11 @@ def _compile_instruction(
         # Saturating conversions (0x00-0x07)
         if compile_saturating_conversion(sub_opcode, func_ctx, block):
             return None
-        if compile_bulk_memory_instruction(
-            sub_opcode, func_ctx, mod_ctx, block, read_operand
-        ):
-            return None
+        bulk_block = compile_bulk_memory_instruction(
+            sub_opcode, func_ctx, mod_ctx, qbe_func, block, read_operand
+        )
+        if bulk_block is not None:
+            return bulk_block
         if compile_table_bulk_instruction(
             sub_opcode, func_ctx, mod_ctx, block, read_operand
         ):
@@ -5,20 +5,46 @@ from __future__ import annotations
 from dataclasses import dataclass, field
 from typing import TYPE_CHECKING
 
-from waq.parser.module import ExportKind, ImportKind, WasmModule
-from waq.parser.types import FuncType, ValueType
+from qbepy.ir import BinaryOp, Global, IntConst, L, Load, Temporary
 
+from waq.parser.module import ExportKind, Import, ImportKind, WasmModule
+from waq.parser.types import ArrayType, FuncType, StructType, ValueType
+
+from . import signatures
+from .imports import mangle_import
 from .stack import ValueStack
 
 if TYPE_CHECKING:
     from qbepy import Block, Function, Module
 
+    from .imports import ImportBinding, ImportBindings
+
+# Import module of WASI preview 1, whose functions the runtime implements
+WASI_MODULE = "wasi_snapshot_preview1"
+
+# Hidden first parameter of every function compiled in instance mode, pointing
+# to the runtime's WasmInstance
+VMCTX = "vmctx"
+
+# Offsets of the WasmInstance fields that instance-mode code reads in place of
+# the runtime globals of the same name
+VMCTX_FIELDS = {
+    "__wasm_memory": 0,
+    "__wasm_memory_size_bytes": 8,
+    "__wasm_table": 16,
+    "__wasm_table_sigs": 24,
+    "__wasm_table_size": 32,
+}
+
+# Offset of the WasmInstance field pointing to the slots of the globals
+VMCTX_GLOBALS = 40
+
 
 @dataclass
 class ControlFrame:
     """Represents a control flow structure (block, loop, if, try)."""
 
-    kind: str  # "block", "loop", "if", "try", "catch"
+    kind: str  # "block", "loop", "if", "try", "catch", "try_table"
     start_depth: int  # Stack depth at entry
     result_types: tuple[ValueType, ...]  # Expected result types
     label_name: str  # QBE block label for branch target
@@ -32,6 +58,13 @@ class ControlFrame:
     catch_all_label: str | None = None  # For try: the catch_all block label
     delegate_depth: int | None = None  # For delegate: outer try depth
     exception_tag: int | None = None  # For catch: the tag being caught
+    # For try/catch with results: (block name, temp names) per clause end
+    clause_values: list[tuple[str, list[str]]] | None = None
+    # For block/try_table with results: temps each branch to the label (and
+    # the fallthrough) copies its values into
+    result_temps: list[str] | None = None
+    # For try_table: (clause kind, tag index or None, target frame)
+    catch_clauses: list[tuple[int, int | None, ControlFrame]] | None = None
 
 
 @dataclass
@@ -78,6 +111,17 @@ class FunctionContext:
     # Function name (for error reporting)
     func_name: str | None = None
 
+    # GC shadow stack frame (QBE temp holding its address), for functions of
+    # modules that allocate GC objects
+    gc_frame: str | None = None
+
+    # Shadow stack slots: reference locals first, then operand stack spills
+    gc_local_slots: int = 0
+    gc_frame_slots: int = 0
+
+    # Whether the function registers exception handlers (try, try_table)
+    has_handlers: bool = False
+
     def new_label(self, prefix: str = "L") -> str:
         """Generate a new unique block label.
 
@@ -135,6 +179,41 @@ class FunctionContext:
         )
 
 
+@dataclass
+class ModuleLink:
+    """Where a module sits in a program linked from several modules.
+
+    Linked modules share the runtime's memories, tables, exception tags and
+    segments, so the module's indices are mapped to runtime indices. The
+    functions, globals and data it defines get `prefix` prepended to their
+    symbols, and imports resolved to another module use that module's.
+    """
+
+    # Prepended to the symbols the module defines
+    prefix: str
+
+    # Runtime index of each memory, table and tag (imports first)
+    memories: list[int] = field(default_factory=list)
+    tables: list[int] = field(default_factory=list)
+    tags: list[int] = field(default_factory=list)
+
+    # Runtime index of the module's first data and element segment
+    data_base: int = 0
+    elem_base: int = 0
+
+    # Canonical type ids, shared by all linked modules, and the id of the
+    # module's first type
+    type_ids: list[int] = field(default_factory=list)
+    type_base: int = 0
+
+    # Symbols of imported functions and globals another module exports
+    funcs: dict[int, str] = field(default_factory=dict)
+    globals: dict[int, str] = field(default_factory=dict)
+
+    # Initial values of imported globals another module defines
+    global_values: dict[int, int | float] = field(default_factory=dict)
+
+
 @dataclass
 class ModuleContext:
     """Context for compiling a complete module."""
@@ -157,6 +236,113 @@ class ModuleContext:
     # Memory size name (no $ prefix - qbepy adds it)
     memory_size: str = "__wasm_memory_size"
 
+    # Linear memory bounds checking mode ("none", "inline" or "guard")
+    bounds_checks: str = "none"
+
+    # Deterministic profile: float operations produce canonical NaNs
+    deterministic: bool = False
+
+    # Instance mode: functions take the runtime instance holding the module's
+    # state as a hidden first parameter (see VMCTX)
+    instance_mode: bool = False
+
+    # Symbols of imported functions: "qualified" with their module, or "bare"
+    # (see waq.compiler.imports)
+    import_names: str = "qualified"
+
+    # Host bindings of imported functions (see waq.compiler.imports), or None
+    # to call every import by its default symbol
+    imports: ImportBindings | None = None
+
+    # Place in a linked program (see waq.compiler.linker), or None when the
+    # module is compiled on its own
+    link: ModuleLink | None = None
+
+    # Canonical type ids (signature tags for call_indirect), built on demand
+    _type_ids: list[int] | None = None
+
+    @property
+    def uses_gc(self) -> bool:
+        """Whether the module defines struct or array types, or exception tags.
+
+        Exnrefs captured from caught exceptions are GC objects, so modules
+        with tags root them like any other reference.
+        """
+        module = self.module
+        if module.tags or module.num_imported_tags():
+            return True
+        return any(isinstance(t, StructType | ArrayType) for t in module.types)
+
+    def canonical_type_id(self, type_idx: int) -> int:
+        """Get the canonical type id used as a signature tag for a type."""
+        return self._canonical_type_ids()[type_idx]
+
+    def matching_type_ids(self, type_idx: int) -> list[int]:
+        """Get the signature tags accepted where the given type is expected."""
+        return signatures.matching_type_ids(
+            self.module, self._canonical_type_ids(), type_idx
+        )
+
+    def _canonical_type_ids(self) -> list[int]:
+        if self._type_ids is None:
+            if self.link is not None:
+                self._type_ids = self.link.type_ids
+            else:
+                self._type_ids = signatures.canonical_type_ids(self.module)
+        return self._type_ids
+
+    @property
+    def init_function(self) -> str:
+        """Name of the function setting up the module's memories and tables."""
+        if self.link is None:
+            return "__wasm_memory_init"
+        return f"{self.link.prefix}__wasm_module_init"
+
+    def symbol(self, name: str) -> str:
+        """Get the symbol of something the module defines, such as a data item.
+
+        Note: Do not include $ prefix - qbepy adds it automatically.
+        """
+        if self.link is None:
+            return name
+        return self.link.prefix + name
+
+    def memory_index(self, memory_idx: int) -> int:
+        """Get the runtime index of a memory."""
+        if self.link is None:
+            return memory_idx
+        return self.link.memories[memory_idx]
+
+    def table_index(self, table_idx: int) -> int:
+        """Get the runtime index of a table."""
+        if self.link is None:
+            return table_idx
+        return self.link.tables[table_idx]
+
+    def tag_index(self, tag_idx: int) -> int:
+        """Get the runtime index of an exception tag."""
+        if self.link is None:
+            return tag_idx
+        return self.link.tags[tag_idx]
+
+    def data_index(self, data_idx: int) -> int:
+        """Get the runtime index of a data segment."""
+        if self.link is None:
+            return data_idx
+        return self.link.data_base + data_idx
+
+    def elem_index(self, elem_idx: int) -> int:
+        """Get the runtime index of an element segment."""
+        if self.link is None:
+            return elem_idx
+        return self.link.elem_base + elem_idx
+
+    def type_index(self, type_idx: int) -> int:
+        """Get the runtime index of a type (its GC layout's slot)."""
+        if self.link is None:
+            return type_idx
+        return self.link.type_base + type_idx
+
     def get_func_name(self, func_idx: int) -> str:
         """Get the QBE function name for a WASM function index.
 
@@ -165,41 +351,155 @@ class ModuleContext:
         if func_idx in self.func_names:
             return self.func_names[func_idx]
 
-        # Check if it's an imported function
-        num_imports = self.module.num_imported_funcs()
-        if func_idx < num_imports:
-            # Find the import
-            import_idx = 0
-            for imp in self.module.imports:
-                if imp.kind == ImportKind.FUNC:
-                    if import_idx == func_idx:
-                        # Use module$name format for imported functions
-                        # This allows linking with external C functions
-                        name = imp.name
-                        self.func_names[func_idx] = name
-                        return name
-                    import_idx += 1
-            raise ValueError(f"imported function {func_idx} not found")
+        if self.link is not None and func_idx in self.link.funcs:
+            return self.link.funcs[func_idx]
+
+        # Imported functions link with external C functions under their
+        # qualified symbol (the runtime's for WASI), unless the manifest binds
+        # them to another C function, which may have to be called through an
+        # adapter
+        imp = self._func_import(func_idx)
+        if imp is not None:
+            binding = self.import_binding(func_idx)
+            if binding is not None and self.needs_adapter(func_idx):
+                name = self.symbol(f"__wasm_import_{func_idx}")
+            elif binding is not None:
+                name = binding.symbol
+            elif self.import_names == "bare" and imp.module != WASI_MODULE:
+                name = imp.name
+            else:
+                name = mangle_import(imp.module, imp.name)
+            self.func_names[func_idx] = name
+            return name
+
+        # In instance mode, exported functions are called through a wrapper
+        # entering the instance (see export_name), which the module itself
+        # has no need for
+        name = None if self.instance_mode else self.export_name(func_idx)
+        if name is None:
+            # Internal function
+            wasm_name = self.module.get_func_name(func_idx)
+            name = self.symbol(f"__wasm_{wasm_name}")
+        self.func_names[func_idx] = name
+        return name
 
-        # Check if exported
+    def export_name(self, func_idx: int) -> str | None:
+        """Get the symbol of a defined function's export, if it is exported.
+
+        Note: Do not include $ prefix - qbepy adds it automatically.
+        """
         for exp in self.module.exports:
             if exp.kind == ExportKind.FUNC and exp.index == func_idx:
                 # Prefix exported functions with wasm_ to avoid conflicts with C symbols
-                # Exceptions:
-                # - _start is a special WASI entry point
-                # - Names already prefixed with wasm_ or __wasm_ to avoid double-prefixing
-                if exp.name == "_start" or exp.name.startswith(("wasm_", "__wasm_")):
+                # (including WASI's _start, which would clash with the C startup
+                # code), except names already prefixed with wasm_ or __wasm_
+                if exp.name.startswith(("wasm_", "__wasm_")):
                     name = exp.name
                 else:
                     name = f"wasm_{exp.name}"
-                self.func_names[func_idx] = name
-                return name
+                return self.symbol(name)
+        return None
 
-        # Internal function
-        wasm_name = self.module.get_func_name(func_idx)
-        name = f"__wasm_{wasm_name}"
-        self.func_names[func_idx] = name
-        return name
+    def import_binding(self, func_idx: int) -> ImportBinding | None:
+        """Get the manifest's binding of an imported function, if any."""
+        imp = self._func_import(func_idx)
+        if imp is None or self.imports is None:
+            return None
+        return self.imports.get((imp.module, imp.name))
+
+    def import_context(self, func_idx: int) -> str:
+        """Get the hidden argument the C function of an import takes.
+
+        That is "instance", "memory" or "none"; bindings default to what an
+        unbound import gets, the instance in instance mode.
+        """
+        binding = self.import_binding(func_idx)
+        if binding is not None and binding.context is not None:
+            return binding.context
+        return "instance" if self.vmctx_args(func_idx) else "none"
+
+    def needs_adapter(self, func_idx: int) -> bool:
+        """Whether calls to a bound import go through a generated adapter.
+
+        Adapters pass a context other than the default and translate buffer
+        arguments (see waq.compiler.imports).
+        """
+        binding = self.import_binding(func_idx)
+        if binding is None:
+            return False
+        if binding.buffers:
+            return True
+        default = "instance" if self.vmctx_args(func_idx) else "none"
+        return binding.context not in (None, default)
+
+    def _func_import(self, func_idx: int) -> Import | None:
+        """Get the import of a function, or None for a defined function."""
+        if func_idx >= self.module.num_imported_funcs():
+            return None
+        func_imports = [i for i in self.module.imports if i.kind == ImportKind.FUNC]
+        return func_imports[func_idx]
+
+    def vmctx_args(self, func_idx: int | None = None) -> list:
+        """Get the hidden arguments a call passes before the WASM ones.
+
+        In instance mode that is vmctx, except for the WASI functions of the
+        runtime, which it implements for the running instance. `func_idx` is
+        None for calls through a function reference.
+        """
+        if not self.instance_mode:
+            return []
+        if func_idx is not None:
+            imp = self._func_import(func_idx)
+            if (
+                imp is not None
+                and imp.module == WASI_MODULE
+                and self.import_binding(func_idx) is None
+            ):
+                return []
+        return [(L, Temporary(VMCTX))]
+
+    def state_address(
+        self, ctx: FunctionContext | None, block: Block, name: str
+    ) -> Global | Temporary:
+        """Get the address of a runtime global compiled code reads directly.
+
+        These hold memory 0 and table 0 (see VMCTX_FIELDS). In instance mode
+        they are fields of the instance instead, and the address is computed
+        in `block` (only the first field can be reached without `ctx`).
+        """
+        if not self.instance_mode:
+            return Global(name)
+        return self._vmctx_field(ctx, block, VMCTX_FIELDS[name])
+
+    def global_address(
+        self, ctx: FunctionContext, block: Block, global_idx: int
+    ) -> Global | Temporary:
+        """Get the address of a global's value.
+
+        In instance mode the globals the module defines are 8-byte slots of
+        the instance, and the address is computed in `block`. Imported globals
+        are always external symbols.
+        """
+        num_imports = self.module.num_imported_globals()
+        if not self.instance_mode or global_idx < num_imports:
+            return Global(self.get_global_name(global_idx))
+        slots = ctx.stack.new_temp_no_push(ValueType.I64).name
+        block.instructions.append(
+            Load(
+                result=Temporary(slots),
+                result_type=L,
+                address=self._vmctx_field(ctx, block, VMCTX_GLOBALS),
+            )
+        )
+        return _offset_address(ctx, block, slots, 8 * (global_idx - num_imports))
+
+    def _vmctx_field(
+        self, ctx: FunctionContext | None, block: Block, offset: int
+    ) -> Temporary:
+        if offset == 0:
+            return Temporary(VMCTX)
+        assert ctx is not None
+        return _offset_address(ctx, block, VMCTX, offset)
 
     def get_global_name(self, global_idx: int) -> str:
         """Get the QBE data name for a WASM global index.
@@ -209,14 +509,36 @@ class ModuleContext:
         if global_idx in self.global_names:
             return self.global_names[global_idx]
 
+        if self.link is not None and global_idx in self.link.globals:
+            return self.link.globals[global_idx]
+
         # Check if exported
         for exp in self.module.exports:
             if exp.kind == ExportKind.GLOBAL and exp.index == global_idx:
-                name = exp.name
+                name = self.symbol(exp.name)
                 self.global_names[global_idx] = name
                 return name
 
         # Internal global
-        name = f"__wasm_global_{global_idx}"
+        name = self.symbol(f"__wasm_global_{global_idx}")
         self.global_names[global_idx] = name
         return name
+
+
+def _offset_address(
+    ctx: FunctionContext, block: Block, base: str, offset: int
+) -> Temporary:
+    """Compute `base + offset` in `block`, unless the offset is 0."""
+    if offset == 0:
+        return Temporary(base)
+    addr = ctx.stack.new_temp_no_push(ValueType.I64).name
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(addr),
+            result_type=L,
+            op="add",
+            left=Temporary(base),
+            right=IntConst(offset),
+        )
+    )
+    return Temporary(addr)
@@ -0,0 +1,150 @@
+"""Symbols and host bindings of imported functions.
+
+An imported function is called by a symbol qualified with its module:
+`Z_` + module + `Z_` + name (`env.log` is `Z_envZ_log`), in which `Z` is an
+escape character. A `Z` in the module or name is doubled, and any byte other
+than an ASCII letter, digit or underscore becomes `Z` and two uppercase hex
+digits (`env.log-v2` is `Z_envZ_logZ2Dv2`), so different imports can't get
+the same symbol, nor one of the C library (`write`, `exit`, ...). The WASI
+functions of the runtime are defined under these symbols. The "bare" naming
+calls other imports by their name alone, as earlier versions of waq did.
+
+A manifest (`--imports`) binds imports, by module and name, to C functions
+of other names. In TOML:
+
+    env.log = "host_log"
+    env.write = { symbol = "host_write", context = "memory", buffers = [[0, 1]] }
+
+or in JSON:
+
+    {"env": {"log": "host_log", "write": {"symbol": "host_write", ...}}}
+
+`context` selects a hidden first argument for the C function: "memory" (the
+base address of memory 0), "instance" (the running WasmInstance, only in
+instance mode) or "none". It defaults to what unbound imports get: the
+instance in instance mode, nothing otherwise.
+
+Each `buffers` pair names a pointer parameter and its length parameter. The
+C function receives a native pointer in place of the pointer, after a check
+that the whole range lies in memory 0. Bindings needing a context other than
+the default or buffers are called through a small generated adapter.
+"""
+
+from __future__ import annotations
+
+import json
+import re
+import tomllib
+from dataclasses import dataclass
+from typing import TYPE_CHECKING
+
+if TYPE_CHECKING:
+    from pathlib import Path
+
+# Naming schemes of imported functions the host defines
+IMPORT_NAMES = ("qualified", "bare")
+
+CONTEXTS = ("none", "memory", "instance")
+
+_C_IDENTIFIER = re.compile(r"[A-Za-z_][A-Za-z0-9_]*\Z")
+
+
+@dataclass(frozen=True)
+class ImportBinding:
+    """The C function an imported function is bound to."""
+
+    symbol: str
+
+    # Hidden first argument ("none", "memory" or "instance"), or None for the
+    # default
+    context: str | None = None
+
+    # (pointer, length) parameter index pairs
+    buffers: tuple[tuple[int, int], ...] = ()
+
+
+# Bindings by (module, name) of the import
+ImportBindings = dict[tuple[str, str], ImportBinding]
+
+
+def mangle_import(module: str, name: str) -> str:
+    """Get the qualified symbol of an imported function."""
+    return f"Z_{_escape(module)}Z_{_escape(name)}"
+
+
+def _escape(part: str) -> str:
+    escaped = []
+    for byte in part.encode("utf-8"):
+        char = chr(byte)
+        if char == "Z":
+            escaped.append("ZZ")
+        elif char.isascii() and (char.isalnum() or char == "_"):
+            escaped.append(char)
+        else:
+            escaped.append(f"Z{byte:02X}")
+    return "".join(escaped)
+
+
+def load_import_manifest(path: Path) -> ImportBindings:
+    """Read a manifest, in TOML if its name ends with .toml, else in JSON.
+
+    Raises ValueError if the manifest is malformed.
+    """
+    text = path.read_text(encoding="utf-8")
+    try:
+        if path.suffix == ".toml":
+            data = tomllib.loads(text)
+        else:
+            data = json.loads(text)
+    except (tomllib.TOMLDecodeError, json.JSONDecodeError) as e:
+        raise ValueError(f"{path}: {e}") from e
+    return parse_import_manifest(data)
+
+
+def parse_import_manifest(data: object) -> ImportBindings:
+    """Get the bindings of a decoded manifest.
+
+    Raises ValueError if the manifest is malformed.
+    """
+    if not isinstance(data, dict):
+        raise ValueError("an import manifest maps module names to tables")
+    bindings: ImportBindings = {}
+    for module, names in data.items():
+        if not isinstance(names, dict):
+            raise ValueError(f"module '{module}' must map import names to bindings")
+        for name, entry in names.items():
+            try:
+                bindings[module, name] = _parse_binding(entry)
+            except ValueError as e:
+                raise ValueError(f"binding of {module}.{name}: {e}") from e
+    return bindings
+
+
+def _parse_binding(entry: object) -> ImportBinding:
+    if isinstance(entry, str):
+        entry = {"symbol": entry}
+    if not isinstance(entry, dict):
+        raise ValueError("expected a C symbol or a table")
+
+    unknown = set(entry) - {"symbol", "context", "buffers"}
+    if unknown:
+        raise ValueError(f"unknown keys {', '.join(sorted(unknown))}")
+
+    symbol = entry.get("symbol")
+    if not isinstance(symbol, str) or not _C_IDENTIFIER.match(symbol):
+        raise ValueError(f"symbol must be a C identifier, not {symbol!r}")
+
+    context = entry.get("context")
+    if context is not None and context not in CONTEXTS:
+        raise ValueError(f"context must be one of {', '.join(CONTEXTS)}")
+
+    buffers = []
+    for pair in entry.get("buffers", []):
+        if (
+            not isinstance(pair, list)
+            or len(pair) != 2
+            or not all(isinstance(i, int) and i >= 0 for i in pair)
+        ):
+            raise ValueError("buffers must be [pointer, length] parameter indices")
+        buffers.append((pair[0], pair[1]))
+    return ImportBinding(symbol, context, tuple(buffers))
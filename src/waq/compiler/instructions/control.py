@@ -9,6 +9,7 @@ from qbepy.ir import (
     Branch,
     Call,
     Comparison,
+    Copy,
     Global,
     Halt,
     IntConst,
@@ -24,6 +25,18 @@ from qbepy.ir import (
 )
 
 from waq.compiler.context import ControlFrame, ModuleContext
+from waq.compiler.instructions.exceptions import (
+    emit_catch_dispatch,
+    emit_handler_pops,
+    finish_try,
+)
+from waq.compiler.instructions.gc import (
+    emit_gc_frame_pop,
+    emit_gc_spills,
+    gc_heap_type_tag,
+)
+from waq.compiler.instructions.table import emit_table_operand, is_table64
+from waq.compiler.stack import StackValue
 from waq.parser.types import BlockType, FuncType, ValueType
 
 if TYPE_CHECKING:
@@ -84,6 +97,7 @@ def compile_control_instruction(
             start_depth=ctx.stack.depth,
             result_types=result_types,
             label_name=end_label,
+            result_temps=_new_result_temps(ctx, result_types),
         )
         ctx.push_control(frame)
         return None
@@ -178,6 +192,12 @@ def compile_control_instruction(
 
         frame = ctx.pop_control()
 
+        if frame.kind in ("try", "catch"):
+            return finish_try(ctx, func, block, frame)
+
+        if frame.result_temps is not None or frame.kind == "try_table":
+            return _end_labelled_block(ctx, mod_ctx, func, block, frame)
+
         if frame.kind == "if" and frame.else_label:
             # If without else - else just falls through
             # Need to emit the else label pointing to end
@@ -332,9 +352,8 @@ def compile_control_instruction(
     # call_indirect
     if opcode == 0x11:
         type_idx = read_operand("u32")
-        _table_idx = read_operand("u32")  # Always 0 in WASM 1.0
-        _emit_call_indirect(ctx, block, type_idx)
-        return None
+        table_idx = read_operand("u32")
+        return _emit_call_indirect(ctx, mod_ctx, func, block, type_idx, table_idx)
 
     # return_call (0x12) - tail call to direct function
     if opcode == 0x12:
@@ -344,8 +363,10 @@ def compile_control_instruction(
     # return_call_indirect (0x13) - tail call through table
     if opcode == 0x13:
         type_idx = read_operand("u32")
-        _table_idx = read_operand("u32")  # Table index (usually 0)
-        return _emit_return_call_indirect(ctx, func, block, type_idx)
+        table_idx = read_operand("u32")
+        return _emit_return_call_indirect(
+            ctx, mod_ctx, func, block, type_idx, table_idx
+        )
 
     # call_ref (0x14) - call via typed function reference
     if opcode == 0x14:
@@ -355,11 +376,135 @@ def compile_control_instruction(
     # return_call_ref (0x15) - tail call via typed function reference
     if opcode == 0x15:
         type_idx = read_operand("u32")
-        return _emit_return_call_ref(ctx, func, block, type_idx)
+        return _emit_return_call_ref(ctx, mod_ctx, func, block, type_idx)
+
+    # ref.as_non_null (0xD4) - trap on null, the reference stays on the stack
+    if opcode == 0xD4:
+        non_null = _emit_non_null_test(ctx, block, ctx.stack.peek_at(0))
+        return _emit_trap_unless(
+            ctx,
+            func,
+            block,
+            non_null,
+            "__wasm_trap_null_reference",
+            "ref_as_non_null",
+        )
+
+    # br_on_null (0xD5), br_on_non_null (0xD6)
+    if opcode in (0xD5, 0xD6):
+        depth = read_operand("u32")
+        return _emit_br_on_null(opcode, ctx, func, block, depth)
 
     return None
 
 
+def compile_br_on_cast(
+    sub_opcode: int,
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    func: Function,
+    block: Block,
+    read_operand: Callable[[str], Any],
+) -> Block:
+    """Compile br_on_cast (0xFB 0x18) or br_on_cast_fail (0xFB 0x19).
+
+    The reference stays on the stack: it is the last value passed to the
+    target label and, on the fallthrough path, the operand of what follows.
+    """
+    flags = read_operand("u8")
+    depth = read_operand("u32")
+    read_operand("s32")  # source heap type
+    heap_type = read_operand("s32")
+    target = ctx.get_branch_target(depth)
+
+    # Flag bit 1: the target type is nullable, so null passes the cast
+    test = "__wasm_ref_test_null" if flags & 2 else "__wasm_ref_test"
+    ref = ctx.stack.peek_at(0)
+    matches = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Call(
+            target=Global(test),
+            args=[
+                (L, Temporary(ref.name)),
+                (W, IntConst(gc_heap_type_tag(mod_ctx, heap_type))),
+            ],
+            result=Temporary(matches.name),
+            result_type=W,
+        )
+    )
+
+    name = "br_on_cast" if sub_opcode == 0x18 else "br_on_cast_fail"
+    branch_label = ctx.new_label(f"{name}_branch")
+    cont_label = ctx.new_label(f"{name}_cont")
+    if sub_opcode == 0x18:
+        if_true, if_false = branch_label, cont_label
+    else:
+        if_true, if_false = cont_label, branch_label
+    block.terminator = Branch(
+        condition=Temporary(matches.name),
+        if_true=Label(if_true),
+        if_false=Label(if_false),
+    )
+
+    branch_block = func.add_block(branch_label.removeprefix("@"))
+    _emit_branch(ctx, branch_block, target)
+
+    return func.add_block(cont_label.removeprefix("@"))
+
+
+def _emit_non_null_test(ctx: FunctionContext, block: Block, ref: StackValue) -> str:
+    """Emit a test of a reference against null; returns the (w) result temp."""
+    non_null = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Comparison(
+            result=Temporary(non_null.name),
+            result_type=W,
+            op="cnel",
+            left=Temporary(ref.name),
+            right=IntConst(0),
+        )
+    )
+    return non_null.name
+
+
+def _emit_br_on_null(
+    opcode: int, ctx: FunctionContext, func: Function, block: Block, depth: int
+) -> Block:
+    """Compile br_on_null (0xD5) or br_on_non_null (0xD6).
+
+    br_on_null passes the values below the reference to the target and keeps
+    the reference on the fallthrough path. br_on_non_null passes the reference
+    as the target's last value and drops it on the fallthrough path.
+    """
+    target = ctx.get_branch_target(depth)
+    ref = ctx.stack.peek_at(0)
+    non_null = _emit_non_null_test(ctx, block, ref)
+
+    name = "br_on_null" if opcode == 0xD5 else "br_on_non_null"
+    branch_label = ctx.new_label(f"{name}_branch")
+    cont_label = ctx.new_label(f"{name}_cont")
+    if opcode == 0xD5:
+        if_true, if_false = cont_label, branch_label
+    else:
+        if_true, if_false = branch_label, cont_label
+    block.terminator = Branch(
+        condition=Temporary(non_null),
+        if_true=Label(if_true),
+        if_false=Label(if_false),
+    )
+
+    branch_block = func.add_block(branch_label.removeprefix("@"))
+    if opcode == 0xD5:
+        ctx.stack.pop()
+        _emit_branch(ctx, branch_block, target)
+        ctx.stack.push(ref)
+    else:
+        _emit_branch(ctx, branch_block, target)
+        ctx.stack.pop()
+
+    return func.add_block(cont_label.removeprefix("@"))
+
+
 def _block_type_to_results(
     block_type: BlockType, ctx: FunctionContext
 ) -> tuple[ValueType, ...]:
@@ -373,8 +518,73 @@ def _block_type_to_results(
     return func_type.results
 
 
+def _new_result_temps(
+    ctx: FunctionContext, result_types: tuple[ValueType, ...]
+) -> list[str] | None:
+    """Allocate the temps carrying a block's results to its end label."""
+    if not result_types:
+        return None
+    return [ctx.stack.new_temp_no_push(vtype).name for vtype in result_types]
+
+
+def _emit_result_copies(
+    ctx: FunctionContext, block: Block, frame: ControlFrame
+) -> None:
+    """Copy the values on top of the stack into a frame's result temps."""
+    assert frame.result_temps is not None
+    n = len(frame.result_temps)
+    if ctx.stack.depth < n:
+        # Only reachable with validation disabled
+        return
+    for i, (temp, vtype) in enumerate(
+        zip(frame.result_temps, frame.result_types, strict=True)
+    ):
+        value = ctx.stack.peek_at(n - 1 - i)
+        block.instructions.append(
+            Copy(
+                result=Temporary(temp),
+                result_type=_vtype_to_ir_type(vtype),
+                value=Temporary(value.name),
+            )
+        )
+
+
+def _end_labelled_block(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    func: Function,
+    block: Block,
+    frame: ControlFrame,
+) -> Block:
+    """End a block or try_table whose results arrive through result temps."""
+    if block.terminator is None:
+        if frame.kind == "try_table":
+            emit_handler_pops(block, [frame])
+        if frame.result_temps is not None:
+            _emit_result_copies(ctx, block, frame)
+        block.terminator = Jump(target=Label(frame.label_name))
+    ctx.stack.truncate(frame.start_depth)
+
+    if frame.kind == "try_table":
+        emit_catch_dispatch(ctx, mod_ctx, func, frame, _emit_branch)
+
+    end_block = func.add_block(frame.label_name.removeprefix("@"))
+    for temp, vtype in zip(frame.result_temps or [], frame.result_types, strict=True):
+        ctx.stack.push(StackValue(temp, vtype))
+    return end_block
+
+
 def _emit_branch(ctx: FunctionContext, block: Block, target: ControlFrame) -> None:
     """Emit a branch to a control frame."""
+    if target.result_temps is not None:
+        _emit_result_copies(ctx, block, target)
+
+    # Leaving a try body unregisters its exception handler
+    for i in range(len(ctx.control_stack) - 1, -1, -1):
+        if ctx.control_stack[i] is target:
+            emit_handler_pops(block, ctx.control_stack[i:])
+            break
+
     # For loop, branch goes to start (no results needed at branch point)
     # For block/if, branch goes to end with results
     block.terminator = Jump(target=Label(target.label_name))
@@ -382,6 +592,8 @@ def _emit_branch(ctx: FunctionContext, block: Block, target: ControlFrame) -> No
 
 def _emit_return(ctx: FunctionContext, block: Block) -> None:
     """Emit a function return."""
+    emit_handler_pops(block, ctx.control_stack)
+    emit_gc_frame_pop(ctx, block)
     result_types = ctx.func_type.results
     if not result_types:
         block.terminator = Return(value=None)
@@ -421,7 +633,7 @@ def _vtype_to_store_type(vtype: ValueType) -> str:
         return "stores"
     if vtype == ValueType.F64:
         return "stored"
-    if vtype in (ValueType.FUNCREF, ValueType.EXTERNREF):
+    if vtype.is_reference():
         return "storel"
     raise ValueError(f"unknown value type: {vtype}")
 
@@ -438,7 +650,7 @@ def _vtype_to_ir_type(vtype: ValueType):
         return S
     if vtype == ValueType.F64:
         return D
-    if vtype in (ValueType.FUNCREF, ValueType.EXTERNREF):
+    if vtype.is_reference():
         return L  # Reference types are pointers (64-bit)
     raise ValueError(f"unknown value type: {vtype}")
 
@@ -455,8 +667,11 @@ def _emit_call(
     # Pop arguments (in reverse order)
     args = ctx.stack.pop_n(len(func_type.params))
 
+    # The callee may collect; it roots its own arguments
+    emit_gc_spills(ctx, block)
+
     # Build argument list for Call instruction
-    call_args = []
+    call_args = mod_ctx.vmctx_args(func_idx)
     for arg, ptype in zip(args, func_type.params, strict=True):
         qbe_type = _vtype_to_ir_type(ptype)
         call_args.append((qbe_type, Temporary(arg.name)))
@@ -541,8 +756,11 @@ def _emit_call_ref(
     # Pop arguments (in reverse order)
     args = ctx.stack.pop_n(len(func_type.params))
 
+    # The callee may collect; it roots its own arguments
+    emit_gc_spills(ctx, block)
+
     # Build argument list
-    call_args = []
+    call_args = mod_ctx.vmctx_args()
     for arg, ptype in zip(args, func_type.params, strict=True):
         qbe_type = _vtype_to_ir_type(ptype)
         call_args.append((qbe_type, Temporary(arg.name)))
@@ -611,7 +829,7 @@ def _vtype_size(vtype: ValueType) -> int:
         return 4
     if vtype == ValueType.F64:
         return 8
-    if vtype in (ValueType.FUNCREF, ValueType.EXTERNREF):
+    if vtype.is_reference():
         return 8
     raise ValueError(f"unknown value type: {vtype}")
 
@@ -626,53 +844,145 @@ def _vtype_to_load_type(vtype: ValueType) -> str:
         return "loads"
     if vtype == ValueType.F64:
         return "loadd"
-    if vtype in (ValueType.FUNCREF, ValueType.EXTERNREF):
+    if vtype.is_reference():
         return "loadl"
     raise ValueError(f"unknown value type: {vtype}")
 
 
-def _emit_call_indirect(ctx: FunctionContext, block: Block, type_idx: int) -> None:
-    """Emit an indirect function call through a table."""
+def _emit_trap_unless(
+    ctx: FunctionContext,
+    func: Function,
+    block: Block,
+    condition: str,
+    trap: str,
+    label_prefix: str,
+) -> Block:
+    """Branch to a block calling the `trap` runtime function unless `condition`.
 
-    # Get function type from type index
-    func_type = _get_func_type(ctx.module, type_idx)
+    Returns the block where compilation continues when the condition holds.
+    """
+    ok_label = ctx.new_label(f"{label_prefix}_ok")
+    trap_label = ctx.new_label(f"{label_prefix}_trap")
+    block.terminator = Branch(
+        condition=Temporary(condition),
+        if_true=Label(ok_label),
+        if_false=Label(trap_label),
+    )
 
-    # Pop table index (i32) from stack
-    table_idx = ctx.stack.pop()
+    trap_block = func.add_block(trap_label)
+    trap_block.instructions.append(Call(target=Global(trap), args=[]))
+    trap_block.terminator = Halt()
 
-    # Pop arguments (in reverse order)
-    args = ctx.stack.pop_n(len(func_type.params))
+    return func.add_block(ok_label)
 
-    # Build argument list for Call instruction
-    call_args = []
-    for arg, ptype in zip(args, func_type.params, strict=True):
-        qbe_type = _vtype_to_ir_type(ptype)
-        call_args.append((qbe_type, Temporary(arg.name)))
 
-    # Load table base pointer
-    table_base = ctx.stack.new_temp_no_push(ValueType.I64)
-    block.instructions.append(
-        Load(
-            result=Temporary(table_base.name),
-            result_type=L,
-            address=Global("__wasm_table"),
-        )
+def _emit_table_state_load(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    block: Block,
+    table_idx: int,
+    global_name: str,
+    runtime_func: str,
+    *,
+    is_size: bool = False,
+) -> str:
+    """Load a table's size, entries or signature tags.
+
+    Table 0 is read from its `__wasm_table` globals (the instance's fields in
+    instance mode); other tables are looked up through the runtime.
+    """
+    result = ctx.stack.new_temp_no_push(
+        ValueType.I32 if is_size else ValueType.I64
     )
+    result_type = W if is_size else L
+    if table_idx == 0:
+        block.instructions.append(
+            Load(
+                result=Temporary(result.name),
+                result_type=result_type,
+                address=mod_ctx.state_address(ctx, block, global_name),
+                load_type="loadw" if is_size else "loadl",
+            )
+        )
+    else:
+        block.instructions.append(
+            Call(
+                target=Global(runtime_func),
+                args=[(W, IntConst(table_idx))],
+                result=Temporary(result.name),
+                result_type=result_type,
+            )
+        )
+    return result.name
+
 
-    # Calculate offset: table_idx * 8 (pointer size)
-    # First extend table_idx to 64-bit
+def _emit_table_entry_load(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    func: Function,
+    block: Block,
+    elem_idx: str,
+    type_idx: int,
+    table_idx: int,
+) -> tuple[str, Block]:
+    """Load and check the function pointer for an indirect call.
+
+    Traps with "undefined element" when the index is outside the table,
+    "uninitialized element" when the entry is null, and "indirect call type
+    mismatch" when the entry's signature tag is neither the expected type nor
+    one of its subtypes. Returns the temporary holding the function pointer
+    and the block in which the call should be emitted.
+    """
     from qbepy.ir import Conversion  # noqa: PLC0415
 
+    elem_idx = emit_table_operand(
+        ctx, block, elem_idx, is_64=is_table64(ctx, table_idx)
+    )
+    table_idx = mod_ctx.table_index(table_idx)
+
+    # Index must be below the current table size
+    table_size = _emit_table_state_load(
+        ctx,
+        mod_ctx,
+        block,
+        table_idx,
+        "__wasm_table_size",
+        "__wasm_table_size_op",
+        is_size=True,
+    )
+    in_bounds = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Comparison(
+            result=Temporary(in_bounds.name),
+            result_type=W,
+            op="cultw",
+            left=Temporary(elem_idx),
+            right=Temporary(table_size),
+        )
+    )
+    block = _emit_trap_unless(
+        ctx,
+        func,
+        block,
+        in_bounds.name,
+        "__wasm_trap_undefined_element",
+        "elem_index",
+    )
+
     idx64 = ctx.stack.new_temp_no_push(ValueType.I64)
     block.instructions.append(
         Conversion(
             op="extuw",
             result=Temporary(idx64.name),
             result_type=L,
-            operand=Temporary(table_idx.name),
+            operand=Temporary(elem_idx),
         )
     )
 
+    # Load function pointer: __wasm_table[idx]
+    table_base = _emit_table_state_load(
+        ctx, mod_ctx, block, table_idx, "__wasm_table", "__wasm_table_elems"
+    )
     offset = ctx.stack.new_temp_no_push(ValueType.I64)
     block.instructions.append(
         BinaryOp(
@@ -683,20 +993,16 @@ def _emit_call_indirect(ctx: FunctionContext, block: Block, type_idx: int) -> No
             right=IntConst(8),  # sizeof(void*)
         )
     )
-
-    # Calculate address: table_base + offset
     addr = ctx.stack.new_temp_no_push(ValueType.I64)
     block.instructions.append(
         BinaryOp(
             result=Temporary(addr.name),
             result_type=L,
             op="add",
-            left=Temporary(table_base.name),
+            left=Temporary(table_base),
             right=Temporary(offset.name),
         )
     )
-
-    # Load function pointer
     func_ptr = ctx.stack.new_temp_no_push(ValueType.I64)
     block.instructions.append(
         Load(
@@ -706,15 +1012,145 @@ def _emit_call_indirect(ctx: FunctionContext, block: Block, type_idx: int) -> No
         )
     )
 
+    # Entry must be non-null
+    non_null = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Comparison(
+            result=Temporary(non_null.name),
+            result_type=W,
+            op="cnel",
+            left=Temporary(func_ptr.name),
+            right=IntConst(0),
+        )
+    )
+    block = _emit_trap_unless(
+        ctx,
+        func,
+        block,
+        non_null.name,
+        "__wasm_trap_uninitialized_element",
+        "elem_init",
+    )
+
+    # Signature tag must match: __wasm_table_sigs[idx]
+    sigs_base = _emit_table_state_load(
+        ctx, mod_ctx, block, table_idx, "__wasm_table_sigs", "__wasm_table_sigs_idx"
+    )
+    sig_offset = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(sig_offset.name),
+            result_type=L,
+            op="mul",
+            left=Temporary(idx64.name),
+            right=IntConst(4),  # sizeof(int32_t)
+        )
+    )
+    sig_addr = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(sig_addr.name),
+            result_type=L,
+            op="add",
+            left=Temporary(sigs_base),
+            right=Temporary(sig_offset.name),
+        )
+    )
+    sig = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Load(
+            result=Temporary(sig.name),
+            result_type=W,
+            address=Temporary(sig_addr.name),
+            load_type="loadw",
+        )
+    )
+
+    # Accept the expected type or any of its subtypes
+    matched: str | None = None
+    for type_id in mod_ctx.matching_type_ids(type_idx):
+        is_match = ctx.stack.new_temp_no_push(ValueType.I32)
+        block.instructions.append(
+            Comparison(
+                result=Temporary(is_match.name),
+                result_type=W,
+                op="ceqw",
+                left=Temporary(sig.name),
+                right=IntConst(type_id),
+            )
+        )
+        if matched is None:
+            matched = is_match.name
+        else:
+            either = ctx.stack.new_temp_no_push(ValueType.I32)
+            block.instructions.append(
+                BinaryOp(
+                    result=Temporary(either.name),
+                    result_type=W,
+                    op="or",
+                    left=Temporary(matched),
+                    right=Temporary(is_match.name),
+                )
+            )
+            matched = either.name
+    assert matched is not None
+    block = _emit_trap_unless(
+        ctx,
+        func,
+        block,
+        matched,
+        "__wasm_trap_indirect_call_mismatch",
+        "elem_sig",
+    )
+
+    return func_ptr.name, block
+
+
+def _emit_call_indirect(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    func: Function,
+    block: Block,
+    type_idx: int,
+    table_idx: int,
+) -> Block:
+    """Emit an indirect function call through a table.
+
+    Returns the block following the call (the table entry checks split the
+    current block).
+    """
+
+    # Get function type from type index
+    func_type = _get_func_type(ctx.module, type_idx)
+
+    # Pop element index (i32, or i64 for table64) from stack
+    elem_idx = ctx.stack.pop()
+
+    # Pop arguments (in reverse order)
+    args = ctx.stack.pop_n(len(func_type.params))
+
+    # The callee may collect; it roots its own arguments
+    emit_gc_spills(ctx, block)
+
+    # Build argument list for Call instruction
+    call_args = mod_ctx.vmctx_args()
+    for arg, ptype in zip(args, func_type.params, strict=True):
+        qbe_type = _vtype_to_ir_type(ptype)
+        call_args.append((qbe_type, Temporary(arg.name)))
+
+    func_ptr, block = _emit_table_entry_load(
+        ctx, mod_ctx, func, block, elem_idx.name, type_idx, table_idx
+    )
+
     # Emit indirect call
     if not func_type.results:
-        block.instructions.append(Call(target=Temporary(func_ptr.name), args=call_args))
+        block.instructions.append(Call(target=Temporary(func_ptr), args=call_args))
     elif len(func_type.results) == 1:
         result = ctx.stack.new_temp(func_type.results[0])
         qbe_type = _vtype_to_ir_type(func_type.results[0])
         block.instructions.append(
             Call(
-                target=Temporary(func_ptr.name),
+                target=Temporary(func_ptr),
                 args=call_args,
                 result=Temporary(result.name),
                 result_type=qbe_type,
@@ -741,7 +1177,7 @@ def _emit_call_indirect(ctx: FunctionContext, block: Block, type_idx: int) -> No
         qbe_type = _vtype_to_ir_type(func_type.results[0])
         block.instructions.append(
             Call(
-                target=Temporary(func_ptr.name),
+                target=Temporary(func_ptr),
                 args=call_args,
                 result=Temporary(first_result.name),
                 result_type=qbe_type,
@@ -759,6 +1195,8 @@ def _emit_call_indirect(ctx: FunctionContext, block: Block, type_idx: int) -> No
                 )
             )
 
+    return block
+
 
 def _emit_return_call(
     ctx: FunctionContext,
@@ -777,6 +1215,9 @@ def _emit_return_call(
     # Pop arguments (in reverse order)
     args = ctx.stack.pop_n(len(target_func_type.params))
 
+    # The tail call leaves any enclosing try body
+    emit_handler_pops(block, ctx.control_stack)
+
     # Check for self-recursion
     if target_func_idx == ctx.func_idx:
         # Self-tail-call optimization: update parameters and jump to entry
@@ -798,9 +1239,12 @@ def _emit_return_call(
         block.terminator = Jump(target=Label("entry"))
         return None
 
+    # The callee replaces this function's shadow stack frame
+    emit_gc_frame_pop(ctx, block)
+
     # Non-self tail call: emit regular call + return
     # Build argument list for Call instruction
-    call_args = []
+    call_args = mod_ctx.vmctx_args(target_func_idx)
     for arg, ptype in zip(args, target_func_type.params, strict=True):
         qbe_type = _vtype_to_ir_type(ptype)
         call_args.append((qbe_type, Temporary(arg.name)))
@@ -879,94 +1323,50 @@ def _emit_return_call(
 
 def _emit_return_call_indirect(
     ctx: FunctionContext,
+    mod_ctx: ModuleContext,
     func: Function,
     block: Block,
     type_idx: int,
+    table_idx: int,
 ) -> Block | None:
     """Emit a tail call through a table.
 
     Since we can't know the target at compile time, this falls back
     to an indirect call + return.
     """
-    from qbepy.ir import Conversion  # noqa: PLC0415
-
     func_type = _get_func_type(ctx.module, type_idx)
 
-    # Pop table index
-    table_idx = ctx.stack.pop()
+    # Pop element index
+    elem_idx = ctx.stack.pop()
 
     # Pop arguments (in reverse order)
     args = ctx.stack.pop_n(len(func_type.params))
 
+    # The tail call leaves any enclosing try body, and this function's
+    # shadow stack frame
+    emit_handler_pops(block, ctx.control_stack)
+    emit_gc_frame_pop(ctx, block)
+
     # Build argument list
-    call_args = []
+    call_args = mod_ctx.vmctx_args()
     for arg, ptype in zip(args, func_type.params, strict=True):
         qbe_type = _vtype_to_ir_type(ptype)
         call_args.append((qbe_type, Temporary(arg.name)))
 
-    # Load table base pointer
-    table_base = ctx.stack.new_temp_no_push(ValueType.I64)
-    block.instructions.append(
-        Load(
-            result=Temporary(table_base.name),
-            result_type=L,
-            address=Global("__wasm_table"),
-        )
-    )
-
-    # Calculate offset: table_idx * 8
-    idx64 = ctx.stack.new_temp_no_push(ValueType.I64)
-    block.instructions.append(
-        Conversion(
-            op="extuw",
-            result=Temporary(idx64.name),
-            result_type=L,
-            operand=Temporary(table_idx.name),
-        )
-    )
-
-    offset = ctx.stack.new_temp_no_push(ValueType.I64)
-    block.instructions.append(
-        BinaryOp(
-            result=Temporary(offset.name),
-            result_type=L,
-            op="mul",
-            left=Temporary(idx64.name),
-            right=IntConst(8),
-        )
-    )
-
-    addr = ctx.stack.new_temp_no_push(ValueType.I64)
-    block.instructions.append(
-        BinaryOp(
-            result=Temporary(addr.name),
-            result_type=L,
-            op="add",
-            left=Temporary(table_base.name),
-            right=Temporary(offset.name),
-        )
-    )
-
-    # Load function pointer
-    func_ptr = ctx.stack.new_temp_no_push(ValueType.I64)
-    block.instructions.append(
-        Load(
-            result=Temporary(func_ptr.name),
-            result_type=L,
-            address=Temporary(addr.name),
-        )
+    func_ptr, block = _emit_table_entry_load(
+        ctx, mod_ctx, func, block, elem_idx.name, type_idx, table_idx
     )
 
     # Emit indirect call and return result
     if not func_type.results:
-        block.instructions.append(Call(target=Temporary(func_ptr.name), args=call_args))
+        block.instructions.append(Call(target=Temporary(func_ptr), args=call_args))
         block.terminator = Return(value=None)
     elif len(func_type.results) == 1:
         result = ctx.stack.new_temp_no_push(func_type.results[0])
         qbe_type = _vtype_to_ir_type(func_type.results[0])
         block.instructions.append(
             Call(
-                target=Temporary(func_ptr.name),
+                target=Temporary(func_ptr),
                 args=call_args,
                 result=Temporary(result.name),
                 result_type=qbe_type,
@@ -993,7 +1393,7 @@ def _emit_return_call_indirect(
         qbe_type = _vtype_to_ir_type(func_type.results[0])
         block.instructions.append(
             Call(
-                target=Temporary(func_ptr.name),
+                target=Temporary(func_ptr),
                 args=call_args,
                 result=Temporary(first_result.name),
                 result_type=qbe_type,
@@ -1021,11 +1421,12 @@ def _emit_return_call_indirect(
 
         block.terminator = Return(value=Temporary(first_result.name))
 
-    return None
+    return block
 
 
 def _emit_return_call_ref(
     ctx: FunctionContext,
+    mod_ctx: ModuleContext,
     func: Function,
     block: Block,
     type_idx: int,
@@ -1042,8 +1443,13 @@ def _emit_return_call_ref(
     # Pop arguments (in reverse order)
     args = ctx.stack.pop_n(len(func_type.params))
 
+    # The tail call leaves any enclosing try body, and this function's
+    # shadow stack frame
+    emit_handler_pops(block, ctx.control_stack)
+    emit_gc_frame_pop(ctx, block)
+
     # Build argument list
-    call_args = []
+    call_args = mod_ctx.vmctx_args()
     for arg, ptype in zip(args, func_type.params, strict=True):
         qbe_type = _vtype_to_ir_type(ptype)
         call_args.append((qbe_type, Temporary(arg.name)))
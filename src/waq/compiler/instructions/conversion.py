@@ -16,17 +16,19 @@ from qbepy.ir import (
     W,
 )
 
+from waq.compiler.instructions.numeric import canonicalize_nan
 from waq.parser.types import ValueType
 
 if TYPE_CHECKING:
     from qbepy.ir import Block
 
-    from waq.compiler.context import FunctionContext
+    from waq.compiler.context import FunctionContext, ModuleContext
 
 
 def compile_conversion_instruction(
     opcode: int,
     ctx: FunctionContext,
+    mod_ctx: ModuleContext,
     block: Block,
 ) -> bool:
     """Compile a type conversion instruction.
@@ -261,6 +263,8 @@ def compile_conversion_instruction(
                 operand=Temporary(a.name),
             )
         )
+        if mod_ctx.deterministic:
+            canonicalize_nan(ctx, block, ValueType.F32)
         return True
 
     # f64.convert_i32_s (0xB7)
@@ -331,6 +335,8 @@ def compile_conversion_instruction(
                 operand=Temporary(a.name),
             )
         )
+        if mod_ctx.deterministic:
+            canonicalize_nan(ctx, block, ValueType.F64)
         return True
 
     # i32.reinterpret_f32 (0xBC)
@@ -1,17 +1,41 @@
-"""Exception handling instruction compilation (WASM 3.0 proposal)."""
+"""Exception handling instruction compilation (WASM 3.0 proposal).
+
+Each `try` registers a handler with the runtime and calls `setjmp` on the
+handler's jump buffer from the compiled function itself, so that a `throw`
+anywhere below can `longjmp` back into a live frame. When an exception
+arrives, control goes to a dispatch chain that compares the thrown tag with
+each `catch` clause in turn and rethrows to the next handler if none match.
+
+`try_table` uses the same handler registration; its catch clauses branch
+to enclosing labels instead of running handler code inline. `catch_ref` and
+`catch_all_ref` capture the exception as an exnref (a copy on the GC heap)
+that `throw_ref` can throw again.
+
+Exception payloads are passed as 8-byte slots, one per tag parameter, in
+the runtime's fixed-size payload buffer.
+"""
 
 from __future__ import annotations
 
 from typing import TYPE_CHECKING, Any
 
 from qbepy.ir import (
+    Alloc,
+    BinaryOp,
+    Branch,
     Call,
+    Comparison,
+    D,
     Global,
     Halt,
     IntConst,
     Jump,
     L,
     Label,
+    Load,
+    Phi,
+    S,
+    Store,
     Temporary,
     W,
 )
@@ -20,12 +44,24 @@ from waq.compiler.context import ControlFrame
 from waq.parser.types import ValueType
 
 if TYPE_CHECKING:
-    from collections.abc import Callable
+    from collections.abc import Callable, Iterable
 
     from qbepy import Function
     from qbepy.ir import Block
 
     from waq.compiler.context import FunctionContext, ModuleContext
+    from waq.parser.types import FuncType
+
+
+# Must match WASM_EXCEPTION_PAYLOAD_MAX in the runtime
+PAYLOAD_MAX = 64
+PAYLOAD_SLOT_SIZE = 8
+
+# try_table catch clause kinds
+CATCH = 0x00
+CATCH_REF = 0x01
+CATCH_ALL = 0x02
+CATCH_ALL_REF = 0x03
 
 
 def compile_exception_instruction(
@@ -46,103 +82,144 @@ def compile_exception_instruction(
         block_type = read_operand("block_type")
         result_types = _block_type_to_results(block_type, ctx)
 
-        # Create labels for try structure
         try_body_label = ctx.new_label("try_body")
-        catch_label = ctx.new_label("catch")
+        dispatch_label = ctx.new_label("try_dispatch")
         end_label = ctx.new_label("try_end")
+        _emit_handler_setup(ctx, block, dispatch_label, try_body_label)
 
-        # Jump to try body
-        block.terminator = Jump(target=Label(try_body_label))
-
-        # Create try body block
-        try_block = func.add_block(try_body_label[1:])
-
-        # Push try control frame
         frame = ControlFrame(
             kind="try",
             start_depth=ctx.stack.depth,
             result_types=result_types,
             label_name=end_label,  # Branch target for br
-            catch_label=catch_label,
+            catch_label=dispatch_label,
+            end_label=end_label,
+            clause_values=[],
+        )
+        ctx.push_control(frame)
+
+        return func.add_block(try_body_label)
+
+    # try_table (0x1F)
+    if opcode == 0x1F:
+        block_type = read_operand("block_type")
+        result_types = _block_type_to_results(block_type, ctx)
+
+        # Clause labels are relative to the enclosing blocks
+        clauses: list[tuple[int, int | None, ControlFrame]] = []
+        for _ in range(read_operand("u32")):
+            kind = read_operand("u8")
+            tag_idx = None
+            if kind in (CATCH, CATCH_REF):
+                tag_idx = read_operand("u32")
+                _tag_type(ctx, tag_idx)
+            elif kind not in (CATCH_ALL, CATCH_ALL_REF):
+                raise ctx.make_error(f"invalid catch clause kind 0x{kind:02x}")
+            target = ctx.get_branch_target(read_operand("u32"))
+            clauses.append((kind, tag_idx, target))
+
+        body_label = ctx.new_label("try_table_body")
+        dispatch_label = ctx.new_label("try_table_dispatch")
+        end_label = ctx.new_label("try_table_end")
+        _emit_handler_setup(ctx, block, dispatch_label, body_label)
+
+        frame = ControlFrame(
+            kind="try_table",
+            start_depth=ctx.stack.depth,
+            result_types=result_types,
+            label_name=end_label,
+            catch_label=dispatch_label,
             end_label=end_label,
+            result_temps=[
+                ctx.stack.new_temp_no_push(vtype).name for vtype in result_types
+            ]
+            or None,
+            catch_clauses=clauses,
         )
         ctx.push_control(frame)
 
-        # Set up exception handler in runtime
-        # Call __wasm_push_exception_handler(catch_label_addr)
-        handler_id = ctx.stack.new_temp_no_push(ValueType.I32)
-        try_block.instructions.append(
+        return func.add_block(body_label)
+
+    # throw_ref (0x0A)
+    if opcode == 0x0A:
+        exn = ctx.stack.pop()
+        block.instructions.append(
             Call(
-                target=Global("__wasm_push_exception_handler"),
-                args=[],
-                result=Temporary(handler_id.name),
-                result_type=W,
+                target=Global("__wasm_throw_ref"),
+                args=[(L, Temporary(exn.name))],
             )
         )
-
-        return try_block
+        block.terminator = Halt()
+        return None
 
     # catch (0x07)
     if opcode == 0x07:
         tag_idx = read_operand("u32")
 
-        # Get the try frame
         if not ctx.control_stack or ctx.control_stack[-1].kind not in ("try", "catch"):
             raise ValueError("catch without matching try")
-
         frame = ctx.control_stack[-1]
+        if frame.catch_label is None:
+            raise ctx.make_error("catch after catch_all")
+        tag_type = _tag_type(ctx, tag_idx)
 
-        # End the previous block (try body or previous catch)
-        if block.terminator is None:
-            # Pop exception handler before leaving try
-            block.instructions.append(
-                Call(target=Global("__wasm_pop_exception_handler"), args=[])
-            )
-            assert frame.end_label is not None, "end_label must be set for try/catch"
-            block.terminator = Jump(target=Label(frame.end_label))
+        dispatch_block = _begin_clause(ctx, func, block, frame)
 
-        # Create catch block
-        catch_label = frame.catch_label
-        if frame.kind == "catch":
-            # Multiple catches - need new label
-            catch_label = ctx.new_label("catch")
-
-        assert catch_label is not None, "catch_label must be set"
-        catch_block = func.add_block(catch_label[1:])
+        catch_label = ctx.new_label("catch")
+        next_label = ctx.new_label("catch_next")
+        _emit_tag_test(
+            ctx, mod_ctx, dispatch_block, tag_idx, catch_label, next_label
+        )
 
-        # Update frame to catch mode
         frame.kind = "catch"
         frame.exception_tag = tag_idx
-        frame.catch_label = ctx.new_label("catch_next")  # For next catch
-
-        # Get exception value from runtime
-        # The exception parameters are pushed to the stack
-        # For now, we'll get the exception object and extract params
-        exc_ref = ctx.stack.new_temp(ValueType.EXTERNREF)
-        catch_block.instructions.append(
-            Call(
-                target=Global("__wasm_get_exception"),
-                args=[],
-                result=Temporary(exc_ref.name),
-                result_type=L,
-            )
-        )
+        frame.catch_label = next_label
 
+        catch_block = func.add_block(catch_label)
+        _push_payload(ctx, catch_block, tag_type)
         return catch_block
 
     # throw (0x08)
     if opcode == 0x08:
         tag_idx = read_operand("u32")
+        tag_type = _tag_type(ctx, tag_idx)
+        values = ctx.stack.pop_n(len(tag_type.params))
 
-        # Pop exception parameters from stack based on tag type
-        # For now, we support simple exceptions without parameters
-        # TODO: Look up tag signature from module and pop appropriate values
+        if not values:
+            block.instructions.append(
+                Call(
+                    target=Global("__wasm_throw"),
+                    args=[(W, IntConst(mod_ctx.tag_index(tag_idx)))],
+                )
+            )
+            block.terminator = Halt()
+            return None
 
-        # Call runtime to throw exception
+        # Marshal the parameters into 8-byte slots
+        size = len(values) * PAYLOAD_SLOT_SIZE
+        payload = ctx.stack.new_temp_no_push(ValueType.I64)
+        block.instructions.append(
+            Alloc(result=Temporary(payload.name), size=IntConst(size), align=8)
+        )
+        for i, (value, vtype) in enumerate(
+            zip(values, tag_type.params, strict=True)
+        ):
+            addr = _payload_slot(ctx, block, payload.name, i)
+            block.instructions.append(
+                Store(
+                    store_type=_vtype_to_store_type(vtype),
+                    value=Temporary(value.name),
+                    address=Temporary(addr),
+                )
+            )
         block.instructions.append(
             Call(
-                target=Global("__wasm_throw"),
-                args=[(W, IntConst(tag_idx))],
+                target=Global("__wasm_throw_with_payload"),
+                args=[
+                    (W, IntConst(mod_ctx.tag_index(tag_idx))),
+                    (L, Temporary(payload.name)),
+                    (L, IntConst(size)),
+                ],
             )
         )
 
@@ -169,50 +246,26 @@ def compile_exception_instruction(
     if opcode == 0x18:
         depth = read_operand("u32")
 
-        # Get the try frame
         if not ctx.control_stack or ctx.control_stack[-1].kind != "try":
             raise ValueError("delegate without matching try")
 
         frame = ctx.pop_control()
-
-        # End try body
-        assert frame.end_label is not None, "end_label must be set for try"
-        if block.terminator is None:
-            block.instructions.append(
-                Call(target=Global("__wasm_pop_exception_handler"), args=[])
-            )
-            block.terminator = Jump(target=Label(frame.end_label))
-
-        # Create end block
-        return func.add_block(frame.end_label[1:])
-
-        # Delegate forwards exceptions to outer handler at 'depth'
-        # This is handled by the runtime - we just mark the delegation
-        # The runtime will pop our handler and re-throw to the outer one
+        return finish_try(ctx, func, block, frame, delegate_depth=depth)
 
     # catch_all (0x19)
     if opcode == 0x19:
-        # Get the try/catch frame
         if not ctx.control_stack or ctx.control_stack[-1].kind not in ("try", "catch"):
             raise ValueError("catch_all without matching try")
-
         frame = ctx.control_stack[-1]
+        if frame.catch_label is None:
+            raise ctx.make_error("catch_all after catch_all")
 
-        # End the previous block
-        assert frame.end_label is not None, "end_label must be set for try/catch"
-        if block.terminator is None:
-            block.instructions.append(
-                Call(target=Global("__wasm_pop_exception_handler"), args=[])
-            )
-            block.terminator = Jump(target=Label(frame.end_label))
-
-        # Create catch_all block
-        catch_all_label = ctx.new_label("catch_all")
-        catch_all_block = func.add_block(catch_all_label[1:])
-
-        # Update frame
+        # The dispatch block is the catch_all body: there is nothing left
+        # to rethrow to at the end
+        catch_all_block = _begin_clause(ctx, func, block, frame)
         frame.kind = "catch"
-        frame.catch_all_label = catch_all_label
+        frame.catch_all_label = frame.catch_label
+        frame.catch_label = None
         frame.exception_tag = None  # catch_all catches all tags
 
         return catch_all_block
@@ -221,6 +274,355 @@ def compile_exception_instruction(
     return False  # type: ignore[return-value]
 
 
+def finish_try(
+    ctx: FunctionContext,
+    func: Function,
+    block: Block,
+    frame: ControlFrame,
+    delegate_depth: int | None = None,
+) -> Block:
+    """Close a try/catch frame already popped from the control stack.
+
+    Exceptions no clause matched (or all of them, for `delegate`) are
+    rethrown to the enclosing handler. Returns the block after the try.
+    """
+    _end_clause(ctx, block, frame)
+    ctx.stack.truncate(frame.start_depth)
+
+    if frame.catch_label is not None:
+        dispatch_block = func.add_block(frame.catch_label)
+        if frame.kind == "try":
+            _emit_begin_catch(dispatch_block)
+        if delegate_depth is not None:
+            # Skip the handlers of the try blocks between this one and the
+            # delegate target
+            if delegate_depth < len(ctx.control_stack):
+                skipped = ctx.control_stack[len(ctx.control_stack) - delegate_depth :]
+            else:
+                skipped = ctx.control_stack
+            emit_handler_pops(dispatch_block, skipped)
+        dispatch_block.instructions.append(
+            Call(target=Global("__wasm_rethrow"), args=[])
+        )
+        dispatch_block.terminator = Halt()
+
+    assert frame.end_label is not None, "end_label must be set for try"
+    end_block = func.add_block(frame.end_label)
+
+    # Merge the clause results
+    clause_values = frame.clause_values or []
+    for i, vtype in enumerate(frame.result_types):
+        result = ctx.stack.new_temp(vtype)
+        if clause_values:
+            end_block.phis.append(
+                Phi(
+                    result=Temporary(result.name),
+                    result_type=_vtype_to_ir_type(vtype),
+                    incoming=[
+                        (Label(label), Temporary(values[i]))
+                        for label, values in clause_values
+                    ],
+                )
+            )
+
+    return end_block
+
+
+def emit_catch_dispatch(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    func: Function,
+    frame: ControlFrame,
+    emit_branch: Callable[[FunctionContext, Block, ControlFrame], None],
+) -> None:
+    """Emit the handler of a try_table already popped from the control stack.
+
+    Each clause that matches pushes its values and branches to its label
+    with `emit_branch`; unmatched exceptions are rethrown.
+    """
+    assert frame.catch_label is not None, "catch_label must be set"
+    block = func.add_block(frame.catch_label)
+    _emit_begin_catch(block)
+
+    for kind, tag_idx, target in frame.catch_clauses or []:
+        clause_block = block
+        if tag_idx is not None:
+            match_label = ctx.new_label("catch")
+            next_label = ctx.new_label("catch_next")
+            _emit_tag_test(ctx, mod_ctx, block, tag_idx, match_label, next_label)
+            clause_block = func.add_block(match_label)
+
+        depth = ctx.stack.depth
+        if tag_idx is not None:
+            _push_payload(ctx, clause_block, _tag_type(ctx, tag_idx))
+        if kind in (CATCH_REF, CATCH_ALL_REF):
+            exn = ctx.stack.new_temp(ValueType.EXNREF)
+            clause_block.instructions.append(
+                Call(
+                    target=Global("__wasm_exn_capture"),
+                    args=[],
+                    result=Temporary(exn.name),
+                    result_type=L,
+                )
+            )
+        emit_branch(ctx, clause_block, target)
+        ctx.stack.truncate(depth)
+
+        if tag_idx is None:
+            # catch_all: later clauses are unreachable
+            return
+        block = func.add_block(next_label)
+
+    block.instructions.append(Call(target=Global("__wasm_rethrow"), args=[]))
+    block.terminator = Halt()
+
+
+def emit_handler_pops(block: Block, frames: Iterable[ControlFrame]) -> None:
+    """Unregister the handlers of the try bodies being exited."""
+    for frame in frames:
+        if frame.kind in ("try", "try_table"):
+            block.instructions.append(
+                Call(target=Global("__wasm_pop_exception_handler"), args=[])
+            )
+
+
+def _begin_clause(
+    ctx: FunctionContext, func: Function, block: Block, frame: ControlFrame
+) -> Block:
+    """End the current try body or catch clause and start the next dispatch."""
+    _end_clause(ctx, block, frame)
+    ctx.stack.truncate(frame.start_depth)
+
+    assert frame.catch_label is not None, "catch_label must be set"
+    dispatch_block = func.add_block(frame.catch_label)
+    if frame.kind == "try":
+        # First clause: take the exception from its handler
+        _emit_begin_catch(dispatch_block)
+    return dispatch_block
+
+
+def _end_clause(ctx: FunctionContext, block: Block, frame: ControlFrame) -> None:
+    """Jump from the end of a try body or catch clause to the try's end."""
+    if block.terminator is not None:
+        return
+    if frame.result_types:
+        n = len(frame.result_types)
+        values = [ctx.stack.peek_at(n - 1 - i).name for i in range(n)]
+        assert frame.clause_values is not None
+        frame.clause_values.append((block.name, values))
+        ctx.stack.pop_n(n)
+    if frame.kind == "try":
+        emit_handler_pops(block, [frame])
+    assert frame.end_label is not None, "end_label must be set for try/catch"
+    block.terminator = Jump(target=Label(frame.end_label))
+
+
+def _emit_handler_setup(
+    ctx: FunctionContext, block: Block, dispatch_label: str, body_label: str
+) -> None:
+    """Register a handler and arm its jump buffer in this frame.
+
+    setjmp returns non-zero when an exception is thrown to the handler.
+    """
+    ctx.has_handlers = True
+    env = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        Call(
+            target=Global("__wasm_push_exception_handler"),
+            args=[],
+            result=Temporary(env.name),
+            result_type=L,
+        )
+    )
+    thrown = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Call(
+            target=Global("setjmp"),
+            args=[(L, Temporary(env.name))],
+            result=Temporary(thrown.name),
+            result_type=W,
+        )
+    )
+    block.terminator = Branch(
+        condition=Temporary(thrown.name),
+        if_true=Label(dispatch_label),
+        if_false=Label(body_label),
+    )
+
+
+def emit_local_escapes(ctx: FunctionContext, entry_block: Block) -> None:
+    """Keep the locals of a function with handlers in memory.
+
+    longjmp restores registers to their values at the handler's setjmp, so
+    locals QBE promoted to temporaries would lose assignments made in the try
+    body. Storing the address of every local slot makes it escape, and QBE
+    leaves escaping slots in memory.
+    """
+    if not ctx.has_handlers:
+        return
+    escapes = [Alloc(result=Temporary("local_escape"), size=IntConst(8), align=8)]
+    for addr_name in ctx.local_addrs.values():
+        escapes.append(
+            Store(
+                store_type="storel",
+                value=Temporary(addr_name),
+                address=Temporary("local_escape"),
+            )
+        )
+    # After the allocations of the local slots
+    pos = 0
+    while pos < len(entry_block.instructions) and isinstance(
+        entry_block.instructions[pos], Alloc
+    ):
+        pos += 1
+    entry_block.instructions[pos:pos] = escapes
+
+
+def _emit_tag_test(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    block: Block,
+    tag_idx: int,
+    match_label: str,
+    next_label: str,
+) -> None:
+    """Branch on whether the caught exception has the given tag."""
+    thrown_tag = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Call(
+            target=Global("__wasm_get_exception_tag"),
+            args=[],
+            result=Temporary(thrown_tag.name),
+            result_type=W,
+        )
+    )
+    is_match = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Comparison(
+            result=Temporary(is_match.name),
+            result_type=W,
+            op="ceqw",
+            left=Temporary(thrown_tag.name),
+            right=IntConst(mod_ctx.tag_index(tag_idx)),
+        )
+    )
+    block.terminator = Branch(
+        condition=Temporary(is_match.name),
+        if_true=Label(match_label),
+        if_false=Label(next_label),
+    )
+
+
+def _push_payload(ctx: FunctionContext, block: Block, tag_type: FuncType) -> None:
+    """Load the caught exception's payload values onto the stack."""
+    if not tag_type.params:
+        return
+    payload = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        Call(
+            target=Global("__wasm_get_exception_payload"),
+            args=[],
+            result=Temporary(payload.name),
+            result_type=L,
+        )
+    )
+    for i, vtype in enumerate(tag_type.params):
+        addr = _payload_slot(ctx, block, payload.name, i)
+        value = ctx.stack.new_temp(vtype)
+        block.instructions.append(
+            Load(
+                result=Temporary(value.name),
+                result_type=_vtype_to_ir_type(vtype),
+                address=Temporary(addr),
+                load_type=_vtype_to_load_type(vtype),
+            )
+        )
+
+
+def _emit_begin_catch(block: Block) -> None:
+    block.instructions.append(Call(target=Global("__wasm_begin_catch"), args=[]))
+
+
+def _payload_slot(ctx: FunctionContext, block: Block, payload: str, i: int) -> str:
+    """Address of the i-th payload slot."""
+    if i == 0:
+        return payload
+    addr = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(addr.name),
+            result_type=L,
+            op="add",
+            left=Temporary(payload),
+            right=IntConst(i * PAYLOAD_SLOT_SIZE),
+        )
+    )
+    return addr.name
+
+
+def _tag_type(ctx: FunctionContext, tag_idx: int) -> FuncType:
+    """Parameter signature of a tag, checked against the payload buffer."""
+    try:
+        tag_type = ctx.module.get_tag_type(tag_idx)
+    except ValueError as e:
+        raise ctx.make_error(str(e)) from None
+    if len(tag_type.params) * PAYLOAD_SLOT_SIZE > PAYLOAD_MAX:
+        raise ctx.make_error(
+            f"tag {tag_idx} has {len(tag_type.params)} parameters, "
+            f"at most {PAYLOAD_MAX // PAYLOAD_SLOT_SIZE} are supported"
+        )
+    if ValueType.V128 in tag_type.params:
+        raise ctx.make_error(
+            f"tag {tag_idx}: v128 exception payloads are not supported"
+        )
+    return tag_type
+
+
+def _vtype_to_ir_type(vtype: ValueType):
+    """Convert WASM ValueType to qbepy IR type."""
+    if vtype == ValueType.I32:
+        return W
+    if vtype == ValueType.I64:
+        return L
+    if vtype == ValueType.F32:
+        return S
+    if vtype == ValueType.F64:
+        return D
+    if vtype.is_reference():
+        return L  # All reference types are pointers (64-bit)
+    raise ValueError(f"unknown value type: {vtype}")
+
+
+def _vtype_to_load_type(vtype: ValueType) -> str:
+    """Convert WASM ValueType to QBE load type suffix."""
+    if vtype == ValueType.I32:
+        return "loadw"
+    if vtype == ValueType.I64:
+        return "loadl"
+    if vtype == ValueType.F32:
+        return "loads"
+    if vtype == ValueType.F64:
+        return "loadd"
+    if vtype.is_reference():
+        return "loadl"  # All reference types are 64-bit pointers
+    raise ValueError(f"unknown value type: {vtype}")
+
+
+def _vtype_to_store_type(vtype: ValueType) -> str:
+    """Convert WASM ValueType to QBE store type suffix."""
+    if vtype == ValueType.I32:
+        return "storew"
+    if vtype == ValueType.I64:
+        return "storel"
+    if vtype == ValueType.F32:
+        return "stores"
+    if vtype == ValueType.F64:
+        return "stored"
+    if vtype.is_reference():
+        return "storel"  # All reference types are 64-bit pointers
+    raise ValueError(f"unknown value type: {vtype}")
+
+
 def _block_type_to_results(block_type, ctx: FunctionContext) -> tuple[ValueType, ...]:
     """Convert block type to result types."""
     if block_type is None:
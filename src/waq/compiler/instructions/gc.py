@@ -1,12 +1,22 @@
-"""GC instruction compilation (WASM GC proposal)."""
+"""GC instruction compilation (WASM GC proposal).
+
+Objects are allocated by the runtime's mark-sweep collector, which may run
+on any allocation. Functions of modules that allocate keep their GC
+references where the collector can find them: a shadow stack frame, pushed
+on entry and popped on return, holds reference locals plus a slot per
+operand stack position that references are spilled to before calls and
+allocations.
+"""
 
 from __future__ import annotations
 
 from typing import TYPE_CHECKING, Any
 
 from qbepy.ir import (
+    Alloc,
     BinaryOp,
     Call,
+    Conversion,
     Copy,
     Global,
     IntConst,
@@ -17,7 +27,7 @@ from qbepy.ir import (
     W,
 )
 
-from waq.parser.types import ValueType
+from waq.parser.types import ArrayType, FuncType, StructType, ValueType
 
 if TYPE_CHECKING:
     from collections.abc import Callable
@@ -25,6 +35,11 @@ if TYPE_CHECKING:
     from qbepy.ir import Block
 
     from waq.compiler.context import FunctionContext, ModuleContext
+    from waq.compiler.stack import StackValue
+    from waq.parser.module import WasmModule
+
+# Shadow stack frame layout: previous frame, slot count, then 8-byte slots
+GC_FRAME_HEADER = 16
 
 
 def compile_gc_instruction(
@@ -42,6 +57,7 @@ def compile_gc_instruction(
     if sub_opcode == 0x00:
         type_idx = read_operand("u32")
         struct_type = ctx.module.get_struct_type(type_idx)
+        emit_gc_spills(ctx, block)
 
         # Pop field values from stack (in reverse order)
         field_values = []
@@ -53,7 +69,10 @@ def compile_gc_instruction(
         block.instructions.append(
             Call(
                 target=Global("__wasm_struct_new"),
-                args=[(W, IntConst(type_idx)), (W, IntConst(len(struct_type.fields)))],
+                args=[
+                    (W, IntConst(mod_ctx.canonical_type_id(type_idx))),
+                    (W, IntConst(len(struct_type.fields))),
+                ],
                 result=Temporary(result.name),
                 result_type=L,
             )
@@ -87,13 +106,17 @@ def compile_gc_instruction(
     if sub_opcode == 0x01:
         type_idx = read_operand("u32")
         struct_type = ctx.module.get_struct_type(type_idx)
+        emit_gc_spills(ctx, block)
 
         # Allocate struct with default (zero) values
         result = ctx.stack.new_temp(ValueType.STRUCTREF)
         block.instructions.append(
             Call(
                 target=Global("__wasm_struct_new_default"),
-                args=[(W, IntConst(type_idx)), (W, IntConst(len(struct_type.fields)))],
+                args=[
+                    (W, IntConst(mod_ctx.canonical_type_id(type_idx))),
+                    (W, IntConst(len(struct_type.fields))),
+                ],
                 result=Temporary(result.name),
                 result_type=L,
             )
@@ -178,18 +201,20 @@ def compile_gc_instruction(
     # array.new (0xFB 0x06)
     if sub_opcode == 0x06:
         type_idx = read_operand("u32")
+        emit_gc_spills(ctx, block)
 
         length = ctx.stack.pop()
         init_value = ctx.stack.pop()
+        init_slot = _slot_value(ctx, block, init_value)
 
         result = ctx.stack.new_temp(ValueType.ARRAYREF)
         block.instructions.append(
             Call(
                 target=Global("__wasm_array_new"),
                 args=[
-                    (W, IntConst(type_idx)),
+                    (W, IntConst(mod_ctx.canonical_type_id(type_idx))),
                     (W, Temporary(length.name)),
-                    (L, Temporary(init_value.name)),
+                    (L, Temporary(init_slot)),
                 ],
                 result=Temporary(result.name),
                 result_type=L,
@@ -200,6 +225,7 @@ def compile_gc_instruction(
     # array.new_default (0xFB 0x07)
     if sub_opcode == 0x07:
         type_idx = read_operand("u32")
+        emit_gc_spills(ctx, block)
 
         length = ctx.stack.pop()
 
@@ -207,7 +233,10 @@ def compile_gc_instruction(
         block.instructions.append(
             Call(
                 target=Global("__wasm_array_new_default"),
-                args=[(W, IntConst(type_idx)), (W, Temporary(length.name))],
+                args=[
+                    (W, IntConst(mod_ctx.canonical_type_id(type_idx))),
+                    (W, Temporary(length.name)),
+                ],
                 result=Temporary(result.name),
                 result_type=L,
             )
@@ -218,6 +247,7 @@ def compile_gc_instruction(
     if sub_opcode == 0x08:
         type_idx = read_operand("u32")
         length = read_operand("u32")
+        emit_gc_spills(ctx, block)
 
         # Pop 'length' values from stack
         values = [ctx.stack.pop() for _ in range(length)]
@@ -227,7 +257,10 @@ def compile_gc_instruction(
         block.instructions.append(
             Call(
                 target=Global("__wasm_array_new_default"),
-                args=[(W, IntConst(type_idx)), (W, IntConst(length))],
+                args=[
+                    (W, IntConst(mod_ctx.canonical_type_id(type_idx))),
+                    (W, IntConst(length)),
+                ],
                 result=Temporary(result.name),
                 result_type=L,
             )
@@ -254,6 +287,39 @@ def compile_gc_instruction(
             )
         return True
 
+    # array.new_data (0xFB 0x09), array.new_elem (0xFB 0x0A)
+    if sub_opcode in (0x09, 0x0A):
+        type_idx = read_operand("u32")
+        seg_idx = read_operand("u32")
+        seg_index = mod_ctx.data_index if sub_opcode == 0x09 else mod_ctx.elem_index
+        emit_gc_spills(ctx, block)
+
+        length = ctx.stack.pop()
+        offset = ctx.stack.pop()
+
+        args = [
+            (W, IntConst(mod_ctx.canonical_type_id(type_idx))),
+            (W, IntConst(seg_index(seg_idx))),
+            (W, Temporary(offset.name)),
+            (W, Temporary(length.name)),
+        ]
+        if sub_opcode == 0x09:
+            args.append((W, IntConst(_array_element_size(ctx.module, type_idx))))
+            target = "__wasm_array_new_data"
+        else:
+            target = "__wasm_array_new_elem"
+
+        result = ctx.stack.new_temp(ValueType.ARRAYREF)
+        block.instructions.append(
+            Call(
+                target=Global(target),
+                args=args,
+                result=Temporary(result.name),
+                result_type=L,
+            )
+        )
+        return True
+
     # array.get (0xFB 0x0B)
     if sub_opcode == 0x0B:
         type_idx = read_operand("u32")
@@ -347,6 +413,96 @@ def compile_gc_instruction(
         )
         return True
 
+    # array.fill (0xFB 0x10)
+    if sub_opcode == 0x10:
+        read_operand("u32")  # type index
+
+        count = ctx.stack.pop()
+        value = ctx.stack.pop()
+        offset = ctx.stack.pop()
+        array_ref = ctx.stack.pop()
+        value_slot = _slot_value(ctx, block, value)
+
+        block.instructions.append(
+            Call(
+                target=Global("__wasm_array_fill"),
+                args=[
+                    (L, Temporary(array_ref.name)),
+                    (W, Temporary(offset.name)),
+                    (L, Temporary(value_slot)),
+                    (W, Temporary(count.name)),
+                ],
+            )
+        )
+        return True
+
+    # array.copy (0xFB 0x11)
+    if sub_opcode == 0x11:
+        read_operand("u32")  # destination type index
+        read_operand("u32")  # source type index
+
+        count = ctx.stack.pop()
+        src_offset = ctx.stack.pop()
+        src_ref = ctx.stack.pop()
+        dest_offset = ctx.stack.pop()
+        dest_ref = ctx.stack.pop()
+
+        block.instructions.append(
+            Call(
+                target=Global("__wasm_array_copy"),
+                args=[
+                    (L, Temporary(dest_ref.name)),
+                    (W, Temporary(dest_offset.name)),
+                    (L, Temporary(src_ref.name)),
+                    (W, Temporary(src_offset.name)),
+                    (W, Temporary(count.name)),
+                ],
+            )
+        )
+        return True
+
+    # array.init_data (0xFB 0x12), array.init_elem (0xFB 0x13)
+    if sub_opcode in (0x12, 0x13):
+        type_idx = read_operand("u32")
+        seg_idx = read_operand("u32")
+        seg_index = mod_ctx.data_index if sub_opcode == 0x12 else mod_ctx.elem_index
+
+        count = ctx.stack.pop()
+        src_offset = ctx.stack.pop()
+        dest_offset = ctx.stack.pop()
+        array_ref = ctx.stack.pop()
+
+        args = [
+            (L, Temporary(array_ref.name)),
+            (W, Temporary(dest_offset.name)),
+            (W, IntConst(seg_index(seg_idx))),
+            (W, Temporary(src_offset.name)),
+            (W, Temporary(count.name)),
+        ]
+        if sub_opcode == 0x12:
+            args.append((W, IntConst(_array_element_size(ctx.module, type_idx))))
+            target = "__wasm_array_init_data"
+        else:
+            target = "__wasm_array_init_elem"
+
+        block.instructions.append(Call(target=Global(target), args=args))
+        return True
+
+    # any.convert_extern (0xFB 0x1A), extern.convert_any (0xFB 0x1B)
+    # Internal and external references share one representation
+    if sub_opcode in (0x1A, 0x1B):
+        ref = ctx.stack.pop()
+        vtype = ValueType.ANYREF if sub_opcode == 0x1A else ValueType.EXTERNREF
+        result = ctx.stack.new_temp(vtype)
+        block.instructions.append(
+            Copy(
+                result=Temporary(result.name),
+                result_type=L,
+                value=Temporary(ref.name),
+            )
+        )
+        return True
+
     # ref.i31 (0xFB 0x1C)
     if sub_opcode == 0x1C:
         value = ctx.stack.pop()
@@ -391,67 +547,155 @@ def compile_gc_instruction(
         )
         return True
 
-    # ref.test (0xFB 0x14)
-    if sub_opcode == 0x14:
-        type_idx = read_operand("u32")
+    # ref.test (0xFB 0x14), ref.test null (0xFB 0x15)
+    if sub_opcode in (0x14, 0x15):
+        heap_type = read_operand("s32")
         ref = ctx.stack.pop()
         result = ctx.stack.new_temp(ValueType.I32)
+        target = "__wasm_ref_test" if sub_opcode == 0x14 else "__wasm_ref_test_null"
         block.instructions.append(
             Call(
-                target=Global("__wasm_ref_test"),
-                args=[(L, Temporary(ref.name)), (W, IntConst(type_idx))],
+                target=Global(target),
+                args=[
+                    (L, Temporary(ref.name)),
+                    (W, IntConst(gc_heap_type_tag(mod_ctx, heap_type))),
+                ],
                 result=Temporary(result.name),
                 result_type=W,
             )
         )
         return True
 
-    # ref.test null (0xFB 0x15)
-    if sub_opcode == 0x15:
-        type_idx = read_operand("u32")
+    # ref.cast (0xFB 0x16), ref.cast null (0xFB 0x17)
+    if sub_opcode in (0x16, 0x17):
+        heap_type = read_operand("s32")
         ref = ctx.stack.pop()
-        result = ctx.stack.new_temp(ValueType.I32)
+        result = ctx.stack.new_temp(gc_heap_value_type(ctx.module, heap_type))
+        target = "__wasm_ref_cast" if sub_opcode == 0x16 else "__wasm_ref_cast_null"
         block.instructions.append(
             Call(
-                target=Global("__wasm_ref_test_null"),
-                args=[(L, Temporary(ref.name)), (W, IntConst(type_idx))],
+                target=Global(target),
+                args=[
+                    (L, Temporary(ref.name)),
+                    (W, IntConst(gc_heap_type_tag(mod_ctx, heap_type))),
+                ],
                 result=Temporary(result.name),
-                result_type=W,
+                result_type=L,
             )
         )
         return True
 
-    # ref.cast (0xFB 0x16)
-    if sub_opcode == 0x16:
-        type_idx = read_operand("u32")
-        ref = ctx.stack.pop()
-        result = ctx.stack.new_temp(ValueType.STRUCTREF)  # Could be any ref type
-        block.instructions.append(
-            Call(
-                target=Global("__wasm_ref_cast"),
-                args=[(L, Temporary(ref.name)), (W, IntConst(type_idx))],
-                result=Temporary(result.name),
-                result_type=L,
-            )
+    return False
+
+
+def gc_heap_type_tag(mod_ctx: ModuleContext, heap_type: int) -> int:
+    """Runtime operand for a heap type in ref.test, ref.cast and br_on_cast.
+
+    Concrete types are passed as the canonical type id objects are tagged
+    with; abstract heap types keep their negative s33 encoding.
+    """
+    if heap_type < 0:
+        return heap_type
+    return mod_ctx.canonical_type_id(heap_type)
+
+
+def gc_heap_value_type(module: WasmModule, heap_type: int) -> ValueType:
+    """Value type of a reference to the given heap type."""
+    if heap_type < 0:
+        try:
+            return ValueType(heap_type & 0x7F)
+        except ValueError:
+            return ValueType.ANYREF
+    if heap_type < len(module.types):
+        match module.types[heap_type]:
+            case ArrayType():
+                return ValueType.ARRAYREF
+            case FuncType():
+                return ValueType.FUNCREF
+    return ValueType.STRUCTREF
+
+
+def gc_field_is_reference(module: WasmModule, storage_type: ValueType | int) -> bool:
+    """Check if a struct field or array element can point to a GC object."""
+    if isinstance(storage_type, ValueType):
+        return storage_type.is_gc_reference()
+    if storage_type < 0:
+        # Abstract heap type (negative s33 encoding of its type code)
+        try:
+            return ValueType(storage_type & 0x7F).is_gc_reference()
+        except ValueError:
+            return False
+    if storage_type >= len(module.types):
+        return False
+    return isinstance(module.types[storage_type], StructType | ArrayType)
+
+
+def gc_slot_address(ctx: FunctionContext, block: Block, slot: int) -> str:
+    """Emit the address of a shadow stack slot, returning its temp name."""
+    assert ctx.gc_frame is not None
+    addr = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(addr.name),
+            result_type=L,
+            op="add",
+            left=Temporary(ctx.gc_frame),
+            right=IntConst(GC_FRAME_HEADER + slot * 8),
         )
-        return True
+    )
+    return addr.name
 
-    # ref.cast null (0xFB 0x17)
-    if sub_opcode == 0x17:
-        type_idx = read_operand("u32")
-        ref = ctx.stack.pop()
-        result = ctx.stack.new_temp(ValueType.STRUCTREF)
+
+def emit_gc_frame_push(ctx: FunctionContext, entry_block: Block) -> None:
+    """Allocate and link the shadow stack frame at the start of the function.
+
+    Emitted once the body is compiled, when the number of spill slots is known.
+    """
+    if ctx.gc_frame is None:
+        return
+    entry_block.instructions[0:0] = [
+        Alloc(
+            result=Temporary(ctx.gc_frame),
+            size=IntConst(GC_FRAME_HEADER + ctx.gc_frame_slots * 8),
+            align=8,
+        ),
+        Call(
+            target=Global("__wasm_gc_push_frame"),
+            args=[(L, Temporary(ctx.gc_frame)), (W, IntConst(ctx.gc_frame_slots))],
+        ),
+    ]
+
+
+def emit_gc_frame_pop(ctx: FunctionContext, block: Block) -> None:
+    """Unlink the shadow stack frame before the function returns."""
+    if ctx.gc_frame is None:
+        return
+    block.instructions.append(
+        Call(target=Global("__wasm_gc_pop_frame"), args=[(L, Temporary(ctx.gc_frame))])
+    )
+
+
+def emit_gc_spills(ctx: FunctionContext, block: Block) -> None:
+    """Spill the GC references on the operand stack to their frame slots.
+
+    Called before anything that may collect (calls and allocations), so
+    references held only in temporaries stay reachable.
+    """
+    if ctx.gc_frame is None:
+        return
+    for pos, value in enumerate(ctx.stack):
+        if not value.type.is_gc_reference():
+            continue
+        slot = ctx.gc_local_slots + pos
+        ctx.gc_frame_slots = max(ctx.gc_frame_slots, slot + 1)
+        addr = gc_slot_address(ctx, block, slot)
         block.instructions.append(
-            Call(
-                target=Global("__wasm_ref_cast_null"),
-                args=[(L, Temporary(ref.name)), (W, IntConst(type_idx))],
-                result=Temporary(result.name),
-                result_type=L,
+            Store(
+                store_type="storel",
+                address=Temporary(addr),
+                value=Temporary(value.name),
             )
         )
-        return True
-
-    return False
 
 
 def _compile_struct_get(
@@ -555,9 +799,66 @@ def _compile_array_get(
     )
 
 
+def _array_element_size(module: WasmModule, type_idx: int) -> int:
+    """Size in bytes of an array element as stored in a data segment."""
+    storage_type = module.get_array_type(type_idx).element_type.storage_type
+    match storage_type:
+        case ValueType.I8:
+            return 1
+        case ValueType.I16:
+            return 2
+        case ValueType.I32 | ValueType.F32:
+            return 4
+        case _:
+            return 8
+
+
+def _slot_value(ctx: FunctionContext, block: Block, value: StackValue) -> str:
+    """Widen a value to the 64-bit element slot passed to the runtime.
+
+    Returns the temp holding the slot bits: 32-bit values are zero-extended,
+    floats are passed as their bit patterns.
+    """
+    name = value.name
+    if value.type == ValueType.F32:
+        bits = ctx.stack.new_temp_no_push(ValueType.I32)
+        block.instructions.append(
+            Conversion(
+                op="cast",
+                result=Temporary(bits.name),
+                result_type=W,
+                operand=Temporary(name),
+            )
+        )
+        name = bits.name
+    if value.type in (ValueType.I32, ValueType.F32):
+        wide = ctx.stack.new_temp_no_push(ValueType.I64)
+        block.instructions.append(
+            Conversion(
+                op="extuw",
+                result=Temporary(wide.name),
+                result_type=L,
+                operand=Temporary(name),
+            )
+        )
+        return wide.name
+    if value.type == ValueType.F64:
+        bits = ctx.stack.new_temp_no_push(ValueType.I64)
+        block.instructions.append(
+            Conversion(
+                op="cast",
+                result=Temporary(bits.name),
+                result_type=L,
+                operand=Temporary(name),
+            )
+        )
+        return bits.name
+    return name
+
+
 def _storage_type_to_value_type(storage_type: ValueType | int) -> ValueType:
     """Convert storage type to value type for stack operations."""
-    if isinstance(storage_type, int):
+    if not isinstance(storage_type, ValueType):
         # Type index - treat as reference type
         return ValueType.EQREF
     if storage_type in (ValueType.I8, ValueType.I16):
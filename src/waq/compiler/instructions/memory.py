@@ -6,11 +6,15 @@ from typing import TYPE_CHECKING, Any
 
 from qbepy.ir import (
     BinaryOp,
+    Branch,
     Call,
+    Comparison,
     Conversion,
     Global,
+    Halt,
     IntConst,
     L,
+    Label,
     Load,
     Store,
     Temporary,
@@ -22,6 +26,7 @@ from waq.parser.types import ValueType
 if TYPE_CHECKING:
     from collections.abc import Callable
 
+    from qbepy import Function
     from qbepy.ir import Block
 
     from waq.compiler.context import FunctionContext, ModuleContext
@@ -46,38 +51,40 @@ def _vtype_to_ir_type(vtype: ValueType):
 
 def _is_memory64(ctx: FunctionContext, memory_idx: int = 0) -> bool:
     """Check if memory at given index is Memory64."""
-    if memory_idx < len(ctx.module.memories):
-        return ctx.module.memories[memory_idx].is_memory64
+    memories = ctx.module.memory_types()
+    if memory_idx < len(memories):
+        return memories[memory_idx].is_memory64
     return False
 
 
 def _get_memory_base(
     ctx: FunctionContext,
+    mod_ctx: ModuleContext,
     block: Block,
     memory_idx: int = 0,
 ) -> str:
     """Get the memory base pointer for the given memory index.
 
     Returns the name of a temporary holding the base pointer.
-    For single memory (idx=0), uses __wasm_memory global.
-    For multiple memories (idx>0), calls __wasm_memory_base(idx).
+    Memory 0 is read from the __wasm_memory global (the instance's field in
+    instance mode); other memories call __wasm_memory_base_idx(idx).
     """
     base_temp = ctx.stack.new_temp_no_push(ValueType.I64)
+    memory_idx = mod_ctx.memory_index(memory_idx)
 
-    if memory_idx == 0 and len(ctx.module.memories) <= 1:
-        # Optimization: single memory case, use direct global
+    if memory_idx == 0:
+        # Fast path: memory 0 lives in a global
         block.instructions.append(
             Load(
                 result=Temporary(base_temp.name),
                 result_type=L,
-                address=Global("__wasm_memory"),
+                address=mod_ctx.state_address(ctx, block, "__wasm_memory"),
             )
         )
     else:
-        # Multiple memories: call runtime to get base pointer
         block.instructions.append(
             Call(
-                target=Global("__wasm_memory_base"),
+                target=Global("__wasm_memory_base_idx"),
                 args=[(W, IntConst(memory_idx))],
                 result=Temporary(base_temp.name),
                 result_type=L,
@@ -87,24 +94,198 @@ def _get_memory_base(
     return base_temp.name
 
 
+def _memory_size_bytes(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    block: Block,
+    memory_idx: int = 0,
+) -> str:
+    """Get the current size in bytes of the given memory.
+
+    Returns the name of a temporary holding the size. Memory 0 reads the
+    __wasm_memory_size_bytes global, which the runtime keeps in sync on
+    grow; other memories call __wasm_memory_size_bytes_idx(idx).
+    """
+    size_temp = ctx.stack.new_temp_no_push(ValueType.I64)
+    memory_idx = mod_ctx.memory_index(memory_idx)
+
+    if memory_idx == 0:
+        block.instructions.append(
+            Load(
+                result=Temporary(size_temp.name),
+                result_type=L,
+                address=mod_ctx.state_address(
+                    ctx, block, "__wasm_memory_size_bytes"
+                ),
+            )
+        )
+    else:
+        block.instructions.append(
+            Call(
+                target=Global("__wasm_memory_size_bytes_idx"),
+                args=[(W, IntConst(memory_idx))],
+                result=Temporary(size_temp.name),
+                result_type=L,
+            )
+        )
+
+    return size_temp.name
+
+
+def _extend_to_i64(
+    ctx: FunctionContext, block: Block, value: str, *, is_mem64: bool
+) -> str:
+    """Zero-extend a memory32 address or length to 64 bits.
+
+    Memory64 operands are already i64 and are returned unchanged.
+    """
+    if is_mem64:
+        return value
+    ext = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        Conversion(
+            op="extuw",  # Extend unsigned word to long
+            result=Temporary(ext.name),
+            result_type=L,
+            operand=Temporary(value),
+        )
+    )
+    return ext.name
+
+
+def _emit_bounds_check(
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    qbe_func: Function,
+    block: Block,
+    memory_idx: int,
+    start: str,
+    extent: IntConst | Temporary,
+) -> Block:
+    """Trap unless the byte range [start, start + extent) lies inside memory.
+
+    `start` is an i64 temporary holding the memory address and `extent` the
+    number of bytes accessed (including any static offset). Terminates
+    `block` with a branch to a trap block and returns the block in which
+    compilation continues.
+    """
+    end = ctx.stack.new_temp_no_push(ValueType.I64)
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(end.name),
+            result_type=L,
+            op="add",
+            left=Temporary(start),
+            right=extent,
+        )
+    )
+
+    size_name = _memory_size_bytes(ctx, mod_ctx, block, memory_idx)
+    out_of_bounds = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Comparison(
+            result=Temporary(out_of_bounds.name),
+            result_type=W,
+            op="cugtl",
+            left=Temporary(end.name),
+            right=Temporary(size_name),
+        )
+    )
+
+    if _is_memory64(ctx, memory_idx):
+        # Memory64 addresses can wrap around when the extent is added
+        wrapped = ctx.stack.new_temp_no_push(ValueType.I32)
+        block.instructions.append(
+            Comparison(
+                result=Temporary(wrapped.name),
+                result_type=W,
+                op="cultl",
+                left=Temporary(end.name),
+                right=Temporary(start),
+            )
+        )
+        combined = ctx.stack.new_temp_no_push(ValueType.I32)
+        block.instructions.append(
+            BinaryOp(
+                result=Temporary(combined.name),
+                result_type=W,
+                op="or",
+                left=Temporary(out_of_bounds.name),
+                right=Temporary(wrapped.name),
+            )
+        )
+        out_of_bounds = combined
+
+    trap_label = ctx.new_label("oob_trap")
+    cont_label = ctx.new_label("oob_ok")
+    block.terminator = Branch(
+        condition=Temporary(out_of_bounds.name),
+        if_true=Label(trap_label),
+        if_false=Label(cont_label),
+    )
+
+    trap_block = qbe_func.add_block(trap_label)
+    trap_block.instructions.append(
+        Call(target=Global("__wasm_trap_out_of_bounds"), args=[])
+    )
+    trap_block.terminator = Halt()
+
+    return qbe_func.add_block(cont_label)
+
+
+def _needs_access_check(
+    ctx: FunctionContext, mod_ctx: ModuleContext, memory_idx: int
+) -> bool:
+    """Check whether a load or store needs an explicit bounds check.
+
+    In guard mode, accesses to a single 32-bit memory are caught by the
+    runtime's guard region; memory64 and multi-memory accesses still need
+    an inline check.
+    """
+    if mod_ctx.bounds_checks == "inline":
+        return True
+    if mod_ctx.bounds_checks == "guard":
+        return (
+            mod_ctx.memory_index(memory_idx) != 0
+            or len(ctx.module.memory_types()) > 1
+            or _is_memory64(ctx, memory_idx)
+        )
+    return False
+
+
+def _read_memarg(read_operand: Callable[[str], Any]) -> tuple[int, int]:
+    """Read a memarg, returning (memory_idx, offset).
+
+    With multi-memory, bit 6 of the alignment field signals that an explicit
+    memory index follows.
+    """
+    align = read_operand("u32")
+    memory_idx = read_operand("u32") if align & 0x40 else 0
+    offset = read_operand("u32")
+    return memory_idx, offset
+
+
 def compile_memory_instruction(
     opcode: int,
     ctx: FunctionContext,
     mod_ctx: ModuleContext,
+    qbe_func: Function,
     block: Block,
     read_operand: Callable[[str], Any],
-) -> bool:
+) -> Block | None:
     """Compile a memory instruction.
 
-    Returns True if the instruction was handled.
+    Returns the block in which compilation continues (a new block when a
+    bounds check was emitted), or None if the opcode is not a memory
+    instruction.
     """
     # Load instructions: 0x28-0x35
     if 0x28 <= opcode <= 0x35:
-        return _compile_load(opcode, ctx, block, read_operand)
+        return _compile_load(opcode, ctx, mod_ctx, qbe_func, block, read_operand)
 
     # Store instructions: 0x36-0x3E
     if 0x36 <= opcode <= 0x3E:
-        return _compile_store(opcode, ctx, block, read_operand)
+        return _compile_store(opcode, ctx, mod_ctx, qbe_func, block, read_operand)
 
     # memory.size (0x3F)
     if opcode == 0x3F:
@@ -117,7 +298,7 @@ def compile_memory_instruction(
             block.instructions.append(
                 Call(
                     target=Global("__wasm_memory_size_pages64"),
-                    args=[(W, IntConst(memory_idx))],
+                    args=[(W, IntConst(mod_ctx.memory_index(memory_idx)))],
                     result=Temporary(result.name),
                     result_type=L,
                 )
@@ -127,19 +308,20 @@ def compile_memory_instruction(
             result = ctx.stack.new_temp(ValueType.I32)
             block.instructions.append(
                 Call(
-                    target=Global("__wasm_memory_size_pages"),
-                    args=[(W, IntConst(memory_idx))],
+                    target=Global("__wasm_memory_size_pages_idx"),
+                    args=[(W, IntConst(mod_ctx.memory_index(memory_idx)))],
                     result=Temporary(result.name),
                     result_type=W,
                 )
             )
-        return True
+        return block
 
     # memory.grow (0x40)
     if opcode == 0x40:
         memory_idx = read_operand("u32")
         is_mem64 = _is_memory64(ctx, memory_idx)
         pages = ctx.stack.pop()
+        runtime_idx = mod_ctx.memory_index(memory_idx)
 
         if is_mem64:
             # Memory64: takes/returns i64
@@ -147,7 +329,7 @@ def compile_memory_instruction(
             block.instructions.append(
                 Call(
                     target=Global("__wasm_memory_grow64"),
-                    args=[(W, IntConst(memory_idx)), (L, Temporary(pages.name))],
+                    args=[(W, IntConst(runtime_idx)), (L, Temporary(pages.name))],
                     result=Temporary(result.name),
                     result_type=L,
                 )
@@ -157,53 +339,49 @@ def compile_memory_instruction(
             result = ctx.stack.new_temp(ValueType.I32)
             block.instructions.append(
                 Call(
-                    target=Global("__wasm_memory_grow"),
-                    args=[(W, IntConst(memory_idx)), (W, Temporary(pages.name))],
+                    target=Global("__wasm_memory_grow_idx"),
+                    args=[(W, IntConst(runtime_idx)), (W, Temporary(pages.name))],
                     result=Temporary(result.name),
                     result_type=W,
                 )
             )
-        return True
+        return block
 
-    return False
+    return None
 
 
-def _compile_load(
-    opcode: int,
+def _effective_address(
     ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    qbe_func: Function,
     block: Block,
-    read_operand: Callable[[str], Any],
-    memory_idx: int = 0,
-) -> bool:
-    """Compile a load instruction."""
-    # Read memarg: align (unused), offset
-    _align = read_operand("u32")
-    offset = read_operand("u32")
-
-    # Pop address from stack
-    addr = ctx.stack.pop()
-
-    # Check if this is memory64
+    addr: str,
+    offset: int,
+    access_size: int,
+    memory_idx: int,
+) -> tuple[str, Block]:
+    """Compute base + addr + offset for a load or store.
+
+    With inline bounds checks enabled, the access is checked first. Returns
+    the temporary holding the effective address and the block in which the
+    access should be emitted.
+    """
     is_mem64 = _is_memory64(ctx, memory_idx)
+    addr64 = _extend_to_i64(ctx, block, addr, is_mem64=is_mem64)
+
+    if _needs_access_check(ctx, mod_ctx, memory_idx):
+        block = _emit_bounds_check(
+            ctx,
+            mod_ctx,
+            qbe_func,
+            block,
+            memory_idx,
+            addr64,
+            IntConst(offset + access_size),
+        )
 
-    # Calculate effective address: base + addr + offset
     # Get memory base pointer (handles multiple memories)
-    base_temp_name = _get_memory_base(ctx, block, memory_idx)
-
-    if is_mem64:
-        # Memory64: address is already i64
-        addr64_temp = addr
-    else:
-        # Memory32: extend address to 64-bit (it's i32)
-        addr64_temp = ctx.stack.new_temp_no_push(ValueType.I64)
-        block.instructions.append(
-            Conversion(
-                op="extuw",  # Extend unsigned word to long
-                result=Temporary(addr64_temp.name),
-                result_type=L,
-                operand=Temporary(addr.name),
-            )
-        )
+    base_temp_name = _get_memory_base(ctx, mod_ctx, block, memory_idx)
 
     # Add base + addr
     eff_addr = ctx.stack.new_temp_no_push(ValueType.I64)
@@ -213,7 +391,7 @@ def _compile_load(
             result_type=L,
             op="add",
             left=Temporary(base_temp_name),
-            right=Temporary(addr64_temp.name),
+            right=Temporary(addr64),
         )
     )
 
@@ -231,12 +409,40 @@ def _compile_load(
         )
         eff_addr = eff_addr2
 
+    return eff_addr.name, block
+
+
+def _compile_load(
+    opcode: int,
+    ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    qbe_func: Function,
+    block: Block,
+    read_operand: Callable[[str], Any],
+) -> Block | None:
+    """Compile a load instruction."""
+    memory_idx, offset = _read_memarg(read_operand)
+
     # Determine load type based on opcode
     load_info = _LOAD_OPCODES.get(opcode)
     if load_info is None:
-        return False
-
+        return None
     result_type, load_op = load_info
+
+    # Pop address from stack
+    addr = ctx.stack.pop()
+
+    eff_addr, block = _effective_address(
+        ctx,
+        mod_ctx,
+        qbe_func,
+        block,
+        addr.name,
+        offset,
+        _ACCESS_SIZES[load_op],
+        memory_idx,
+    )
+
     result = ctx.stack.new_temp(result_type)
     qbe_type = _vtype_to_ir_type(result_type)
 
@@ -244,88 +450,52 @@ def _compile_load(
         Load(
             result=Temporary(result.name),
             result_type=qbe_type,
-            address=Temporary(eff_addr.name),
+            address=Temporary(eff_addr),
             load_type=load_op,
         )
     )
-    return True
+    return block
 
 
 def _compile_store(
     opcode: int,
     ctx: FunctionContext,
+    mod_ctx: ModuleContext,
+    qbe_func: Function,
     block: Block,
     read_operand: Callable[[str], Any],
-    memory_idx: int = 0,
-) -> bool:
+) -> Block | None:
     """Compile a store instruction."""
-    # Read memarg: align (unused), offset
-    _align = read_operand("u32")
-    offset = read_operand("u32")
+    memory_idx, offset = _read_memarg(read_operand)
+
+    # Determine store type based on opcode
+    store_op = _STORE_OPCODES.get(opcode)
+    if store_op is None:
+        return None
 
     # Pop value and address from stack
     value = ctx.stack.pop()
     addr = ctx.stack.pop()
 
-    # Check if this is memory64
-    is_mem64 = _is_memory64(ctx, memory_idx)
-
-    # Calculate effective address (same as load)
-    # Get memory base pointer (handles multiple memories)
-    base_temp_name = _get_memory_base(ctx, block, memory_idx)
-
-    if is_mem64:
-        # Memory64: address is already i64
-        addr64_temp = addr
-    else:
-        # Memory32: extend address to 64-bit
-        addr64_temp = ctx.stack.new_temp_no_push(ValueType.I64)
-        block.instructions.append(
-            Conversion(
-                op="extuw",
-                result=Temporary(addr64_temp.name),
-                result_type=L,
-                operand=Temporary(addr.name),
-            )
-        )
-
-    eff_addr = ctx.stack.new_temp_no_push(ValueType.I64)
-    block.instructions.append(
-        BinaryOp(
-            result=Temporary(eff_addr.name),
-            result_type=L,
-            op="add",
-            left=Temporary(base_temp_name),
-            right=Temporary(addr64_temp.name),
-        )
+    eff_addr, block = _effective_address(
+        ctx,
+        mod_ctx,
+        qbe_func,
+        block,
+        addr.name,
+        offset,
+        _ACCESS_SIZES[store_op],
+        memory_idx,
     )
 
-    if offset > 0:
-        eff_addr2 = ctx.stack.new_temp_no_push(ValueType.I64)
-        block.instructions.append(
-            BinaryOp(
-                result=Temporary(eff_addr2.name),
-                result_type=L,
-                op="add",
-                left=Temporary(eff_addr.name),
-                right=IntConst(offset),
-            )
-        )
-        eff_addr = eff_addr2
-
-    # Determine store type based on opcode
-    store_op = _STORE_OPCODES.get(opcode)
-    if store_op is None:
-        return False
-
     block.instructions.append(
         Store(
-            address=Temporary(eff_addr.name),
+            address=Temporary(eff_addr),
             value=Temporary(value.name),
             store_type=store_op,
         )
     )
-    return True
+    return block
 
 
 # Load opcode mappings: opcode -> (result_type, load_op)
@@ -359,40 +529,84 @@ _STORE_OPCODES = {
     0x3E: "storew",  # i64.store32
 }
 
+# Bytes touched by each QBE load/store operation (for bounds checks)
+_ACCESS_SIZES = {
+    "loadsb": 1,
+    "loadub": 1,
+    "loadsh": 2,
+    "loaduh": 2,
+    "loadsw": 4,
+    "loaduw": 4,
+    "loadw": 4,
+    "loads": 4,
+    "loadl": 8,
+    "loadd": 8,
+    "storeb": 1,
+    "storeh": 2,
+    "storew": 4,
+    "stores": 4,
+    "storel": 8,
+    "stored": 8,
+}
+
+
+def _bulk_operand(
+    ctx: FunctionContext, block: Block, value: str, *, is_64: bool
+) -> Temporary:
+    """Get a bulk memory address or length as the i64 the runtime takes."""
+    return Temporary(_extend_to_i64(ctx, block, value, is_mem64=is_64))
+
 
 def compile_bulk_memory_instruction(
     sub_opcode: int,
     ctx: FunctionContext,
     mod_ctx: ModuleContext,
+    qbe_func: Function,
     block: Block,
     read_operand: Callable[[str], Any],
-) -> bool:
+) -> Block | None:
     """Compile a bulk memory instruction (0xFC prefix).
 
-    Returns True if the instruction was handled.
+    Returns the block in which compilation continues, or None if the
+    sub-opcode is not a bulk memory instruction.
     """
+    # Bulk operations must not partially complete before trapping, so they
+    # are checked up front even when guard pages are in use
+    inline_checks = mod_ctx.bounds_checks != "none"
+
     # memory.init (0xFC 0x08)
     if sub_opcode == 0x08:
         data_idx = read_operand("u32")
-        _mem_idx = read_operand("u32")  # Always 0 in WASM 1.0
+        mem_idx = read_operand("u32")
 
         # Stack: [dest, src_offset, len] -> []
         length = ctx.stack.pop()
         src_offset = ctx.stack.pop()
         dest = ctx.stack.pop()
 
-        block.instructions.append(
-            Call(
-                target=Global("__wasm_memory_init_seg"),
-                args=[
-                    (W, IntConst(data_idx)),
-                    (W, Temporary(dest.name)),
-                    (W, Temporary(src_offset.name)),
-                    (W, Temporary(length.name)),
-                ],
-            )
+        # The source range is checked against the segment by the runtime
+        dest64 = _bulk_operand(
+            ctx, block, dest.name, is_64=_is_memory64(ctx, mem_idx)
         )
-        return True
+        if inline_checks:
+            length64 = _bulk_operand(ctx, block, length.name, is_64=False)
+            block = _emit_bounds_check(
+                ctx, mod_ctx, qbe_func, block, mem_idx, dest64.name, length64
+            )
+
+        args = [
+            (W, IntConst(mod_ctx.data_index(data_idx))),
+            (L, dest64),
+            (W, Temporary(src_offset.name)),
+            (W, Temporary(length.name)),
+        ]
+        target = "__wasm_memory_init_seg"
+        runtime_idx = mod_ctx.memory_index(mem_idx)
+        if runtime_idx != 0:
+            target = "__wasm_memory_init_seg_idx"
+            args.insert(0, (W, IntConst(runtime_idx)))
+        block.instructions.append(Call(target=Global(target), args=args))
+        return block
 
     # data.drop (0xFC 0x09)
     if sub_opcode == 0x09:
@@ -401,52 +615,71 @@ def compile_bulk_memory_instruction(
         block.instructions.append(
             Call(
                 target=Global("__wasm_data_drop"),
-                args=[(W, IntConst(data_idx))],
+                args=[(W, IntConst(mod_ctx.data_index(data_idx)))],
             )
         )
-        return True
+        return block
 
     # memory.copy (0xFC 0x0A)
     if sub_opcode == 0x0A:
-        _dest_mem = read_operand("u32")  # Always 0 in WASM 1.0
-        _src_mem = read_operand("u32")  # Always 0 in WASM 1.0
+        dest_mem = read_operand("u32")
+        src_mem = read_operand("u32")
 
         # Stack: [dest, src, len] -> []
         length = ctx.stack.pop()
         src = ctx.stack.pop()
         dest = ctx.stack.pop()
 
-        block.instructions.append(
-            Call(
-                target=Global("__wasm_memory_copy"),
-                args=[
-                    (W, Temporary(dest.name)),
-                    (W, Temporary(src.name)),
-                    (W, Temporary(length.name)),
-                ],
-            )
+        # len is i64 only when both memories are memory64
+        dest_is_64 = _is_memory64(ctx, dest_mem)
+        src_is_64 = _is_memory64(ctx, src_mem)
+        dest64 = _bulk_operand(ctx, block, dest.name, is_64=dest_is_64)
+        src64 = _bulk_operand(ctx, block, src.name, is_64=src_is_64)
+        length64 = _bulk_operand(
+            ctx, block, length.name, is_64=dest_is_64 and src_is_64
         )
-        return True
+        if inline_checks:
+            block = _emit_bounds_check(
+                ctx, mod_ctx, qbe_func, block, dest_mem, dest64.name, length64
+            )
+            block = _emit_bounds_check(
+                ctx, mod_ctx, qbe_func, block, src_mem, src64.name, length64
+            )
+
+        args = [(L, dest64), (L, src64), (L, length64)]
+        target = "__wasm_memory_copy"
+        dest_idx = mod_ctx.memory_index(dest_mem)
+        src_idx = mod_ctx.memory_index(src_mem)
+        if dest_idx != 0 or src_idx != 0:
+            target = "__wasm_memory_copy_idx"
+            args[:0] = [(W, IntConst(dest_idx)), (W, IntConst(src_idx))]
+        block.instructions.append(Call(target=Global(target), args=args))
+        return block
 
     # memory.fill (0xFC 0x0B)
     if sub_opcode == 0x0B:
-        _mem_idx = read_operand("u32")  # Always 0 in WASM 1.0
+        mem_idx = read_operand("u32")
 
         # Stack: [dest, val, len] -> []
         length = ctx.stack.pop()
         val = ctx.stack.pop()
         dest = ctx.stack.pop()
 
-        block.instructions.append(
-            Call(
-                target=Global("__wasm_memory_fill"),
-                args=[
-                    (W, Temporary(dest.name)),
-                    (W, Temporary(val.name)),
-                    (W, Temporary(length.name)),
-                ],
+        is_64 = _is_memory64(ctx, mem_idx)
+        dest64 = _bulk_operand(ctx, block, dest.name, is_64=is_64)
+        length64 = _bulk_operand(ctx, block, length.name, is_64=is_64)
+        if inline_checks:
+            block = _emit_bounds_check(
+                ctx, mod_ctx, qbe_func, block, mem_idx, dest64.name, length64
             )
-        )
-        return True
 
-    return False
+        args = [(L, dest64), (W, Temporary(val.name)), (L, length64)]
+        target = "__wasm_memory_fill"
+        runtime_idx = mod_ctx.memory_index(mem_idx)
+        if runtime_idx != 0:
+            target = "__wasm_memory_fill_idx"
+            args.insert(0, (W, IntConst(runtime_idx)))
+        block.instructions.append(Call(target=Global(target), args=args))
+        return block
+
+    return None
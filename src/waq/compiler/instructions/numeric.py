@@ -26,7 +26,7 @@ if TYPE_CHECKING:
 
     from qbepy.ir import Block
 
-    from waq.compiler.context import FunctionContext
+    from waq.compiler.context import FunctionContext, ModuleContext
 
 
 def _vtype_to_ir_type(vtype: ValueType):
@@ -54,6 +54,7 @@ F64_OPS_START = 0x44
 def compile_numeric_instruction(
     opcode: int,
     ctx: FunctionContext,
+    mod_ctx: ModuleContext,
     block: Block,
     read_operand: Callable[[str], Any],
 ) -> bool:
@@ -61,6 +62,8 @@ def compile_numeric_instruction(
 
     Returns True if the instruction was handled.
     """
+    deterministic = mod_ctx.deterministic
+
     # Constants
     if opcode == 0x41:  # i32.const
         value = read_operand("s32")
@@ -104,11 +107,19 @@ def compile_numeric_instruction(
 
     # f32 operations: comparisons (0x5B-0x60) and unary/arithmetic (0x8B-0x98)
     if 0x5B <= opcode <= 0x60 or 0x8B <= opcode <= 0x98:
-        return _compile_f32_op(opcode, ctx, block)
+        if not _compile_f32_op(opcode, ctx, block, deterministic):
+            return False
+        if deterministic and opcode in _f32_nan_results:
+            canonicalize_nan(ctx, block, ValueType.F32)
+        return True
 
     # f64 operations: comparisons (0x61-0x66) and unary/arithmetic (0x99-0xA6)
     if 0x61 <= opcode <= 0x66 or 0x99 <= opcode <= 0xA6:
-        return _compile_f64_op(opcode, ctx, block)
+        if not _compile_f64_op(opcode, ctx, block, deterministic):
+            return False
+        if deterministic and opcode in _f64_nan_results:
+            canonicalize_nan(ctx, block, ValueType.F64)
+        return True
 
     # Sign extension operations (WASM 2.0)
     if 0xC0 <= opcode <= 0xC4:
@@ -117,6 +128,22 @@ def compile_numeric_instruction(
     return False
 
 
+def canonicalize_nan(ctx: FunctionContext, block: Block, vtype: ValueType) -> None:
+    """Replace a NaN on top of the stack with the canonical NaN."""
+    ir_type = S if vtype == ValueType.F32 else D
+    suffix = "f32" if vtype == ValueType.F32 else "f64"
+    a = ctx.stack.pop()
+    result = ctx.stack.new_temp(vtype)
+    block.instructions.append(
+        Call(
+            target=Global(f"__wasm_canon_nan_{suffix}"),
+            args=[(ir_type, Temporary(a.name))],
+            result=Temporary(result.name),
+            result_type=ir_type,
+        )
+    )
+
+
 def _compile_i32_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
     """Compile i32 operations."""
     stack = ctx.stack
@@ -371,7 +398,9 @@ def _compile_i64_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
     return False
 
 
-def _compile_f32_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
+def _compile_f32_op(
+    opcode: int, ctx: FunctionContext, block: Block, deterministic: bool
+) -> bool:
     """Compile f32 operations."""
     stack = ctx.stack
 
@@ -478,7 +507,11 @@ def _compile_f32_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
         result = stack.new_temp(ValueType.F32)
         block.instructions.append(
             Call(
-                target=Global("__wasm_f32_sqrt"),
+                target=Global(
+                    "__wasm_f32_sqrt_deterministic"
+                    if deterministic
+                    else "__wasm_f32_sqrt"
+                ),
                 args=[(S, Temporary(a.name))],
                 result=Temporary(result.name),
                 result_type=S,
@@ -494,7 +527,9 @@ def _compile_f32_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
         op = _f32_arith_ops.get(opcode)
         if op is None:
             return False
-        if op.startswith("$"):
+        if deterministic:
+            op = _f32_deterministic_ops.get(opcode, op)
+        if op.startswith("__wasm_"):
             # Runtime function
             block.instructions.append(
                 Call(
@@ -519,7 +554,9 @@ def _compile_f32_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
     return False
 
 
-def _compile_f64_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
+def _compile_f64_op(
+    opcode: int, ctx: FunctionContext, block: Block, deterministic: bool
+) -> bool:
     """Compile f64 operations."""
     stack = ctx.stack
 
@@ -626,7 +663,11 @@ def _compile_f64_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
         result = stack.new_temp(ValueType.F64)
         block.instructions.append(
             Call(
-                target=Global("__wasm_f64_sqrt"),
+                target=Global(
+                    "__wasm_f64_sqrt_deterministic"
+                    if deterministic
+                    else "__wasm_f64_sqrt"
+                ),
                 args=[(D, Temporary(a.name))],
                 result=Temporary(result.name),
                 result_type=D,
@@ -642,7 +683,9 @@ def _compile_f64_op(opcode: int, ctx: FunctionContext, block: Block) -> bool:
         op = _f64_arith_ops.get(opcode)
         if op is None:
             return False
-        if op.startswith("$"):
+        if deterministic:
+            op = _f64_deterministic_ops.get(opcode, op)
+        if op.startswith("__wasm_"):
             # Runtime function
             block.instructions.append(
                 Call(
@@ -750,6 +793,17 @@ _f32_arith_ops = {
     0x98: "__wasm_f32_copysign",  # f32.copysign
 }
 
+# With --deterministic, operations that can produce a NaN either go through a
+# runtime helper returning the canonical NaN, or have their result
+# canonicalized afterwards
+_f32_deterministic_ops = {
+    0x95: "__wasm_f32_div_deterministic",  # f32.div
+    0x96: "__wasm_f32_min_deterministic",  # f32.min
+    0x97: "__wasm_f32_max_deterministic",  # f32.max
+}
+
+_f32_nan_results = {0x8D, 0x8E, 0x8F, 0x90, 0x92, 0x93, 0x94}
+
 _f64_cmp_ops = [
     "ceqd",  # 0x61: f64.eq
     "cned",  # 0x62: f64.ne
@@ -769,6 +823,14 @@ _f64_arith_ops = {
     0xA6: "__wasm_f64_copysign",  # f64.copysign
 }
 
+_f64_deterministic_ops = {
+    0xA3: "__wasm_f64_div_deterministic",  # f64.div
+    0xA4: "__wasm_f64_min_deterministic",  # f64.min
+    0xA5: "__wasm_f64_max_deterministic",  # f64.max
+}
+
+_f64_nan_results = {0x9B, 0x9C, 0x9D, 0x9E, 0xA0, 0xA1, 0xA2}
+
 
 def _compile_sign_extension(opcode: int, ctx: FunctionContext, block: Block) -> bool:
     """Compile sign extension operations (WASM 2.0).
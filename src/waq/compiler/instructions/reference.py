@@ -105,6 +105,6 @@ def compile_reference_instruction(
         return True
 
     # Note: ref.as_non_null (0xD4), br_on_null (0xD5), br_on_non_null (0xD6)
-    # are handled in codegen.py as they involve control flow
+    # are compiled with the control instructions as they branch
 
     return False
@@ -1,11 +1,14 @@
-"""Table instruction compilation (WASM 2.0)."""
+"""Table instruction compilation (WASM 2.0, table64 from WASM 3.0)."""
 
 from __future__ import annotations
 
 from typing import TYPE_CHECKING, Any
 
 from qbepy.ir import (
+    BinaryOp,
     Call,
+    Comparison,
+    Conversion,
     Global,
     IntConst,
     L,
@@ -21,6 +24,81 @@ if TYPE_CHECKING:
     from qbepy.ir import Block
 
     from waq.compiler.context import FunctionContext, ModuleContext
+    from waq.compiler.stack import StackValue
+
+
+def is_table64(ctx: FunctionContext, table_idx: int) -> bool:
+    """Check if the table at the given index is a table64 (i64 indices)."""
+    if table_idx < len(ctx.module.tables):
+        return ctx.module.tables[table_idx].is_table64
+    return False
+
+
+def emit_table_operand(
+    ctx: FunctionContext, block: Block, value: str, *, is_64: bool
+) -> str:
+    """Narrow a table index or length to the runtime's 32-bit operands.
+
+    Table32 operands are returned unchanged. Table64 operands that do not
+    fit in 32 bits become 0xFFFFFFFF, which is past the end of any table.
+    """
+    if not is_64:
+        return value
+    too_big = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        Comparison(
+            result=Temporary(too_big.name),
+            result_type=W,
+            op="cugtl",
+            left=Temporary(value),
+            right=IntConst(0xFFFFFFFF),
+        )
+    )
+    mask = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(mask.name),
+            result_type=W,
+            op="sub",
+            left=IntConst(0),
+            right=Temporary(too_big.name),
+        )
+    )
+    # Word operations read the low 32 bits of a long
+    narrowed = ctx.stack.new_temp_no_push(ValueType.I32)
+    block.instructions.append(
+        BinaryOp(
+            result=Temporary(narrowed.name),
+            result_type=W,
+            op="or",
+            left=Temporary(value),
+            right=Temporary(mask.name),
+        )
+    )
+    return narrowed.name
+
+
+def _push_table_result(
+    ctx: FunctionContext,
+    block: Block,
+    value: StackValue,
+    *,
+    is_64: bool,
+    signed: bool,
+) -> None:
+    """Push a 32-bit runtime result, extended to i64 for table64."""
+    if not is_64:
+        ctx.stack.push(value)
+        return
+    result = ctx.stack.new_temp(ValueType.I64)
+    block.instructions.append(
+        Conversion(
+            op="extsw" if signed else "extuw",
+            result=Temporary(result.name),
+            result_type=L,
+            operand=Temporary(value.name),
+        )
+    )
 
 
 def compile_table_instruction(
@@ -38,14 +116,17 @@ def compile_table_instruction(
     if opcode == 0x25:
         table_idx = read_operand("u32")
         elem_idx = ctx.stack.pop()
+        index = emit_table_operand(
+            ctx, block, elem_idx.name, is_64=is_table64(ctx, table_idx)
+        )
         # Result type is funcref/externref - we represent as i64 (pointer)
         result = ctx.stack.new_temp(ValueType.I64)
         block.instructions.append(
             Call(
                 target=Global("__wasm_table_get"),
                 args=[
-                    (W, IntConst(table_idx)),
-                    (W, Temporary(elem_idx.name)),
+                    (W, IntConst(mod_ctx.table_index(table_idx))),
+                    (W, Temporary(index)),
                 ],
                 result=Temporary(result.name),
                 result_type=L,
@@ -58,12 +139,15 @@ def compile_table_instruction(
         table_idx = read_operand("u32")
         ref = ctx.stack.pop()
         elem_idx = ctx.stack.pop()
+        index = emit_table_operand(
+            ctx, block, elem_idx.name, is_64=is_table64(ctx, table_idx)
+        )
         block.instructions.append(
             Call(
                 target=Global("__wasm_table_set"),
                 args=[
-                    (W, IntConst(table_idx)),
-                    (W, Temporary(elem_idx.name)),
+                    (W, IntConst(mod_ctx.table_index(table_idx))),
+                    (W, Temporary(index)),
                     (L, Temporary(ref.name)),
                 ],
             )
@@ -93,14 +177,18 @@ def compile_table_bulk_instruction(
         length = ctx.stack.pop()
         src = ctx.stack.pop()
         dest = ctx.stack.pop()
+        # Only the table offset follows the table's index type
+        dest_idx = emit_table_operand(
+            ctx, block, dest.name, is_64=is_table64(ctx, table_idx)
+        )
 
         block.instructions.append(
             Call(
                 target=Global("__wasm_table_init"),
                 args=[
-                    (W, IntConst(table_idx)),
-                    (W, IntConst(elem_idx)),
-                    (W, Temporary(dest.name)),
+                    (W, IntConst(mod_ctx.table_index(table_idx))),
+                    (W, IntConst(mod_ctx.elem_index(elem_idx))),
+                    (W, Temporary(dest_idx)),
                     (W, Temporary(src.name)),
                     (W, Temporary(length.name)),
                 ],
@@ -114,7 +202,7 @@ def compile_table_bulk_instruction(
         block.instructions.append(
             Call(
                 target=Global("__wasm_elem_drop"),
-                args=[(W, IntConst(elem_idx))],
+                args=[(W, IntConst(mod_ctx.elem_index(elem_idx)))],
             )
         )
         return True
@@ -128,16 +216,24 @@ def compile_table_bulk_instruction(
         length = ctx.stack.pop()
         src = ctx.stack.pop()
         dest = ctx.stack.pop()
+        dest_is_64 = is_table64(ctx, dest_table)
+        src_is_64 = is_table64(ctx, src_table)
+        dest_idx = emit_table_operand(ctx, block, dest.name, is_64=dest_is_64)
+        src_idx = emit_table_operand(ctx, block, src.name, is_64=src_is_64)
+        # len is i64 only when both tables are table64
+        count = emit_table_operand(
+            ctx, block, length.name, is_64=dest_is_64 and src_is_64
+        )
 
         block.instructions.append(
             Call(
                 target=Global("__wasm_table_copy"),
                 args=[
-                    (W, IntConst(dest_table)),
-                    (W, IntConst(src_table)),
-                    (W, Temporary(dest.name)),
-                    (W, Temporary(src.name)),
-                    (W, Temporary(length.name)),
+                    (W, IntConst(mod_ctx.table_index(dest_table))),
+                    (W, IntConst(mod_ctx.table_index(src_table))),
+                    (W, Temporary(dest_idx)),
+                    (W, Temporary(src_idx)),
+                    (W, Temporary(count)),
                 ],
             )
         )
@@ -150,35 +246,42 @@ def compile_table_bulk_instruction(
         # Stack: [ref, delta] -> [old_size]
         delta = ctx.stack.pop()
         ref = ctx.stack.pop()
-        result = ctx.stack.new_temp(ValueType.I32)
+        is_64 = is_table64(ctx, table_idx)
+        count = emit_table_operand(ctx, block, delta.name, is_64=is_64)
+        result = ctx.stack.new_temp_no_push(ValueType.I32)
 
         block.instructions.append(
             Call(
                 target=Global("__wasm_table_grow"),
                 args=[
-                    (W, IntConst(table_idx)),
+                    (W, IntConst(mod_ctx.table_index(table_idx))),
                     (L, Temporary(ref.name)),
-                    (W, Temporary(delta.name)),
+                    (W, Temporary(count)),
                 ],
                 result=Temporary(result.name),
                 result_type=W,
             )
         )
+        # -1 (failure) stays -1 as an i64
+        _push_table_result(ctx, block, result, is_64=is_64, signed=True)
         return True
 
     # table.size (0xFC 0x10)
     if sub_opcode == 0x10:
         table_idx = read_operand("u32")
-        result = ctx.stack.new_temp(ValueType.I32)
+        result = ctx.stack.new_temp_no_push(ValueType.I32)
 
         block.instructions.append(
             Call(
                 target=Global("__wasm_table_size_op"),
-                args=[(W, IntConst(table_idx))],
+                args=[(W, IntConst(mod_ctx.table_index(table_idx)))],
                 result=Temporary(result.name),
                 result_type=W,
             )
         )
+        _push_table_result(
+            ctx, block, result, is_64=is_table64(ctx, table_idx), signed=False
+        )
         return True
 
     # table.fill (0xFC 0x11)
@@ -189,15 +292,18 @@ def compile_table_bulk_instruction(
         length = ctx.stack.pop()
         ref = ctx.stack.pop()
         dest = ctx.stack.pop()
+        is_64 = is_table64(ctx, table_idx)
+        dest_idx = emit_table_operand(ctx, block, dest.name, is_64=is_64)
+        count = emit_table_operand(ctx, block, length.name, is_64=is_64)
 
         block.instructions.append(
             Call(
                 target=Global("__wasm_table_fill"),
                 args=[
-                    (W, IntConst(table_idx)),
-                    (W, Temporary(dest.name)),
+                    (W, IntConst(mod_ctx.table_index(table_idx))),
+                    (W, Temporary(dest_idx)),
                     (L, Temporary(ref.name)),
-                    (W, Temporary(length.name)),
+                    (W, Temporary(count)),
                 ],
             )
         )
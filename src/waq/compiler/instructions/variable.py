@@ -6,7 +6,6 @@ from typing import TYPE_CHECKING, Any
 
 from qbepy.ir import (
     D,
-    Global as QbeGlobal,
     L,
     Load,
     S,
@@ -136,7 +135,7 @@ def compile_variable_instruction(
         idx = read_operand("u32")
         global_def = _get_global_def(mod_ctx, idx)
         vtype = global_def.type.value_type
-        global_name = mod_ctx.get_global_name(idx)
+        address = mod_ctx.global_address(ctx, block, idx)
         temp = ctx.stack.new_temp(vtype)
         qbe_type = _vtype_to_ir_type(vtype)
         load_type = _vtype_to_load_type(vtype)
@@ -145,7 +144,7 @@ def compile_variable_instruction(
             Load(
                 result=Temporary(temp.name),
                 result_type=qbe_type,
-                address=QbeGlobal(global_name),
+                address=address,
                 load_type=load_type,
             )
         )
@@ -157,14 +156,14 @@ def compile_variable_instruction(
         value = ctx.stack.pop()
         global_def = _get_global_def(mod_ctx, idx)
         vtype = global_def.type.value_type
-        global_name = mod_ctx.get_global_name(idx)
+        address = mod_ctx.global_address(ctx, block, idx)
         store_type = _vtype_to_store_type(vtype)
         # Store to global data
         block.instructions.append(
             Store(
                 store_type=store_type,
                 value=Temporary(value.name),
-                address=QbeGlobal(global_name),
+                address=address,
             )
         )
         return True
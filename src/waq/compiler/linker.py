@@ -0,0 +1,457 @@
+"""Linking several WASM modules into one program.
+
+Every module is compiled into the same QBE module. An import whose module
+name is the name of a linked module resolves to that module's export; all
+other imports stay external, as when a module is compiled on its own.
+
+The linked modules share the runtime: their memories, tables and exception
+tags are numbered in one runtime index space, their data and element
+segments follow each other, and equivalent types get one canonical id. The
+first module is the main one. Its symbols are those it has on its own and
+its memory 0 is the runtime's memory 0; the symbols of the other modules
+are prefixed with their name.
+"""
+
+from __future__ import annotations
+
+import re
+from typing import TYPE_CHECKING
+
+from qbepy import Function, Module
+from qbepy.ir import Call, Global, Return
+
+from waq.errors import LinkError, ValidationError
+from waq.parser.binary import BinaryReader
+from waq.parser.module import ExportKind, ImportKind
+from waq.parser.types import GlobalType, Limits, MemoryType, TableType
+from waq.validator import validate_module
+
+from . import signatures
+from .codegen import BOUNDS_CHECK_MODES, compile_module_context, eval_init_expr
+from .context import WASI_MODULE, ModuleContext, ModuleLink
+from .imports import IMPORT_NAMES
+
+if TYPE_CHECKING:
+    from collections.abc import Hashable
+
+    from waq.parser.module import Import, WasmModule
+
+    from .imports import ImportBindings
+
+_KIND_NAMES = {
+    ImportKind.FUNC: "function",
+    ImportKind.TABLE: "table",
+    ImportKind.MEMORY: "memory",
+    ImportKind.GLOBAL: "global",
+    ImportKind.TAG: "tag",
+}
+
+
+def link_modules(
+    modules: list[tuple[str, WasmModule]],
+    target: str = "amd64_sysv",
+    *,
+    bounds_checks: str = "none",
+    deterministic: bool = False,
+    import_names: str = "qualified",
+    imports: ImportBindings | None = None,
+    validate: bool = True,
+) -> Module:
+    """Compile named WASM modules into one QBE module.
+
+    `modules` pairs each module with the name other modules import it by;
+    the first one is the main module. The options are those of
+    `compile_module`, applied to every module; the `imports` manifest binds
+    the imports left external. The generated
+    `__wasm_memory_init` initializes the modules so that each comes after
+    the modules it imports from.
+
+    Raises LinkError when an import names a linked module that does not
+    export it, or exports it with a different type, and when a module other
+    than the main one imports WASI while having its own memory (the WASI
+    functions of the runtime only access runtime memory 0).
+    """
+    if bounds_checks not in BOUNDS_CHECK_MODES:
+        raise ValueError(f"unknown bounds check mode: {bounds_checks}")
+    if import_names not in IMPORT_NAMES:
+        raise ValueError(f"unknown import naming: {import_names}")
+    if not modules:
+        raise LinkError("no modules to link")
+
+    if validate:
+        for _name, wasm_module in modules:
+            result = validate_module(wasm_module)
+            if not result.is_valid:
+                raise ValidationError.from_result(result)
+
+    qbe_module = Module()
+    linker = _Linker(
+        modules, qbe_module, bounds_checks, deterministic, import_names, imports
+    )
+    linker.resolve()
+    for ctx in linker.contexts:
+        compile_module_context(ctx)
+    _compile_init(qbe_module, linker.init_order())
+    return qbe_module
+
+
+def _symbol_prefix(name: str) -> str:
+    """Prefix of the symbols of a (non-main) module."""
+    return re.sub(r"[^A-Za-z0-9_]", "_", name) + "__"
+
+
+def _compile_init(qbe_module: Module, contexts: list[ModuleContext]) -> None:
+    """Generate __wasm_memory_init, calling each module's init in order."""
+    init_func = Function("__wasm_memory_init", return_type=None, params=[], export=True)
+    entry_block = init_func.add_block("entry")
+    for ctx in contexts:
+        entry_block.instructions.append(
+            Call(target=Global(ctx.init_function), args=[])
+        )
+    entry_block.terminator = Return()
+    qbe_module.add_function(init_func)
+
+
+class _Linker:
+    """Resolves the imports of linked modules and numbers their items."""
+
+    def __init__(
+        self,
+        modules: list[tuple[str, WasmModule]],
+        qbe_module: Module,
+        bounds_checks: str,
+        deterministic: bool,
+        import_names: str,
+        imports: ImportBindings | None,
+    ) -> None:
+        self.names = [name for name, _module in modules]
+        self.contexts: list[ModuleContext] = []
+        self.by_name: dict[str, ModuleContext] = {}
+        prefixes: dict[str, str] = {}
+        for i, (name, wasm_module) in enumerate(modules):
+            if name in self.by_name:
+                raise LinkError(f"duplicate module name '{name}'")
+            prefix = "" if i == 0 else _symbol_prefix(name)
+            if prefix in prefixes:
+                raise LinkError(
+                    f"modules '{prefixes[prefix]}' and '{name}' "
+                    "would get the same symbols"
+                )
+            prefixes[prefix] = name
+            ctx = ModuleContext(
+                module=wasm_module,
+                qbe_module=qbe_module,
+                bounds_checks=bounds_checks,
+                deterministic=deterministic,
+                import_names=import_names,
+                imports=imports,
+                link=ModuleLink(prefix=prefix),
+            )
+            self.contexts.append(ctx)
+            self.by_name[name] = ctx
+
+        # Runtime indices of memories, tables and tags, by defining module
+        # and index (or by import name for imports from outside)
+        self._runtime: dict[ImportKind, dict[Hashable, int]] = {
+            kind: {} for kind in (ImportKind.MEMORY, ImportKind.TABLE, ImportKind.TAG)
+        }
+        self._global_values: dict[tuple[int, int], int | float] = {}
+        self._evaluating: set[tuple[int, int]] = set()
+
+    def resolve(self) -> None:
+        """Fill in the ModuleLink of every module."""
+        seen: dict[Hashable, int] = {}
+        type_base = data_base = elem_base = 0
+        for ctx in self.contexts:
+            link = self._link(ctx)
+            link.type_ids = signatures.canonical_type_ids(ctx.module, seen, type_base)
+            link.type_base = type_base
+            link.data_base = data_base
+            link.elem_base = elem_base
+            type_base += len(ctx.module.types)
+            data_base += len(ctx.module.data)
+            elem_base += len(ctx.module.elements)
+
+        for ctx in self.contexts:
+            link = self._link(ctx)
+            module = ctx.module
+            link.memories = self._runtime_indices(
+                ctx, ImportKind.MEMORY, len(module.memories)
+            )
+            link.tables = self._runtime_indices(
+                ctx, ImportKind.TABLE, len(module.tables)
+            )
+            link.tags = self._runtime_indices(ctx, ImportKind.TAG, len(module.tags))
+            if link.memories and link.memories[0] != 0 and any(
+                imp.module == WASI_MODULE for imp in module.imports
+            ):
+                raise LinkError(
+                    f"module '{self._name(ctx)}' imports WASI, but its memory 0 "
+                    "is not the memory of the main module"
+                )
+
+        for ctx in self.contexts:
+            link = self._link(ctx)
+            for func_idx, imp in enumerate(_imports(ctx.module, ImportKind.FUNC)):
+                owner, idx = self._definition(ctx, ImportKind.FUNC, func_idx)
+                if owner is ctx:
+                    continue
+                self._check_types(
+                    ctx,
+                    imp,
+                    _func_type_idx(ctx, func_idx),
+                    owner,
+                    _func_type_idx(owner, idx),
+                )
+                link.funcs[func_idx] = owner.get_func_name(idx)
+            for global_idx, imp in enumerate(_imports(ctx.module, ImportKind.GLOBAL)):
+                owner, idx = self._definition(ctx, ImportKind.GLOBAL, global_idx)
+                if owner is ctx:
+                    continue
+                if imp.desc != _global_type(owner, idx):
+                    raise LinkError(
+                        f"module '{self._name(ctx)}' imports global "
+                        f"{imp.module}.{imp.name} as {imp.desc}, but it is "
+                        f"{_global_type(owner, idx)}"
+                    )
+                link.globals[global_idx] = owner.get_global_name(idx)
+                value = self._global_value(owner, idx)
+                if value is not None:
+                    link.global_values[global_idx] = value
+            for memory_idx, imp in enumerate(_imports(ctx.module, ImportKind.MEMORY)):
+                owner, idx = self._definition(ctx, ImportKind.MEMORY, memory_idx)
+                if owner is not ctx:
+                    self._check_limits(ctx, imp, _memory_type(owner, idx))
+            for table_idx, imp in enumerate(_imports(ctx.module, ImportKind.TABLE)):
+                owner, idx = self._definition(ctx, ImportKind.TABLE, table_idx)
+                if owner is not ctx:
+                    self._check_limits(ctx, imp, _table_type(owner, idx))
+            for tag_idx, imp in enumerate(_imports(ctx.module, ImportKind.TAG)):
+                owner, idx = self._definition(ctx, ImportKind.TAG, tag_idx)
+                if owner is not ctx:
+                    self._check_types(
+                        ctx,
+                        imp,
+                        _tag_type_idx(ctx, tag_idx),
+                        owner,
+                        _tag_type_idx(owner, idx),
+                    )
+
+    def init_order(self) -> list[ModuleContext]:
+        """Modules in initialization order: dependencies first.
+
+        Dependency cycles are broken in command-line order.
+        """
+        order: list[ModuleContext] = []
+        visited: set[int] = set()
+
+        def visit(ctx: ModuleContext) -> None:
+            if id(ctx) in visited:
+                return
+            visited.add(id(ctx))
+            for imp in ctx.module.imports:
+                dependency = self.by_name.get(imp.module)
+                if dependency is not None:
+                    visit(dependency)
+            order.append(ctx)
+
+        for ctx in self.contexts:
+            visit(ctx)
+        return order
+
+    def _runtime_indices(
+        self, ctx: ModuleContext, kind: ImportKind, num_defined: int
+    ) -> list[int]:
+        """Runtime index of each item of a kind, imports first."""
+        indices = []
+        num_imports = len(_imports(ctx.module, kind))
+        for idx in range(num_imports + num_defined):
+            owner, owner_idx = self._definition(ctx, kind, idx)
+            key: Hashable
+            if owner_idx < len(_imports(owner.module, kind)):
+                # Imported from outside: one item per import name
+                imp = _imports(owner.module, kind)[owner_idx]
+                key = (imp.module, imp.name)
+            else:
+                key = (id(owner), owner_idx)
+            runtime = self._runtime[kind]
+            indices.append(runtime.setdefault(key, len(runtime)))
+        return indices
+
+    def _definition(
+        self, ctx: ModuleContext, kind: ImportKind, idx: int
+    ) -> tuple[ModuleContext, int]:
+        """Follow an item through imports and re-exports to where it is defined.
+
+        Returns the defining module and the item's index there. Items
+        imported from outside the linked modules are their import.
+        """
+        visited = set()
+        while True:
+            imports = _imports(ctx.module, kind)
+            if idx >= len(imports):
+                return ctx, idx
+            imp = imports[idx]
+            exporter = self.by_name.get(imp.module)
+            if exporter is None:
+                return ctx, idx
+            if (id(ctx), idx) in visited:
+                raise LinkError(
+                    f"{_KIND_NAMES[kind]} {imp.module}.{imp.name} is imported "
+                    "in a cycle and never defined"
+                )
+            visited.add((id(ctx), idx))
+            for exp in exporter.module.exports:
+                if exp.name == imp.name and exp.kind == ExportKind(kind):
+                    ctx, idx = exporter, exp.index
+                    break
+            else:
+                raise LinkError(
+                    f"module '{self._name(ctx)}' imports {_KIND_NAMES[kind]} "
+                    f"{imp.module}.{imp.name}, which module '{imp.module}' "
+                    "does not export"
+                )
+
+    def _global_value(self, ctx: ModuleContext, global_idx: int) -> int | float | None:
+        """Initial value of a defined global, or None for one from outside."""
+        num_imports = ctx.module.num_imported_globals()
+        if global_idx < num_imports:
+            return None
+        key = (id(ctx), global_idx)
+        if key in self._global_values:
+            return self._global_values[key]
+        if key in self._evaluating:
+            raise LinkError(
+                f"global {global_idx} of module '{self._name(ctx)}' is "
+                "initialized from itself"
+            )
+        self._evaluating.add(key)
+
+        # An initializer reading an imported global needs that global first
+        init_expr = ctx.module.globals[global_idx - num_imports].init_expr
+        if init_expr[:1] == b"\x23":  # global.get
+            ref = BinaryReader(init_expr[1:]).read_u32_leb128()
+            if ref < num_imports:
+                owner, idx = self._definition(ctx, ImportKind.GLOBAL, ref)
+                value = self._global_value(owner, idx)
+                if value is not None:
+                    self._link(ctx).global_values[ref] = value
+
+        value = eval_init_expr(init_expr, ctx)
+        self._global_values[key] = value
+        return value
+
+    def _check_types(
+        self,
+        ctx: ModuleContext,
+        imp: Import,
+        type_idx: int,
+        owner: ModuleContext,
+        owner_type_idx: int,
+    ) -> None:
+        """Check that an imported function or tag has the type it is defined with."""
+        if ctx.canonical_type_id(type_idx) != owner.canonical_type_id(
+            owner_type_idx
+        ):
+            raise LinkError(
+                f"module '{self._name(ctx)}' imports {_KIND_NAMES[imp.kind]} "
+                f"{imp.module}.{imp.name} as {ctx.module.types[type_idx]}, but "
+                f"it is {owner.module.types[owner_type_idx]}"
+            )
+
+    def _check_limits(
+        self, ctx: ModuleContext, imp: Import, actual: MemoryType | TableType
+    ) -> None:
+        """Check that an imported memory or table matches its definition.
+
+        The index types and table element types must be equal, and the limits
+        of the definition must lie within the imported ones.
+        """
+        expected = imp.desc
+        assert isinstance(expected, MemoryType | TableType)
+        if isinstance(expected, MemoryType):
+            assert isinstance(actual, MemoryType)
+            matches = expected.is_memory64 == actual.is_memory64
+        else:
+            assert isinstance(actual, TableType)
+            matches = (
+                expected.is_table64 == actual.is_table64
+                and expected.elem_type == actual.elem_type
+            )
+        if not matches or not _limits_match(actual.limits, expected.limits):
+            raise LinkError(
+                f"module '{self._name(ctx)}' imports {_KIND_NAMES[imp.kind]} "
+                f"{imp.module}.{imp.name} as {expected}, but it is {actual}"
+            )
+
+    def _name(self, ctx: ModuleContext) -> str:
+        return self.names[self.contexts.index(ctx)]
+
+    @staticmethod
+    def _link(ctx: ModuleContext) -> ModuleLink:
+        assert ctx.link is not None
+        return ctx.link
+
+
+def _imports(module: WasmModule, kind: ImportKind) -> list[Import]:
+    """Imports of one kind, in index order."""
+    return [imp for imp in module.imports if imp.kind == kind]
+
+
+def _func_type_idx(ctx: ModuleContext, func_idx: int) -> int:
+    """Type index of a function."""
+    imports = _imports(ctx.module, ImportKind.FUNC)
+    if func_idx < len(imports):
+        type_idx = imports[func_idx].desc
+        assert isinstance(type_idx, int)
+        return type_idx
+    return ctx.module.func_types[func_idx - len(imports)]
+
+
+def _tag_type_idx(ctx: ModuleContext, tag_idx: int) -> int:
+    """Type index of a tag's parameters."""
+    imports = _imports(ctx.module, ImportKind.TAG)
+    if tag_idx < len(imports):
+        type_idx = imports[tag_idx].desc
+        assert isinstance(type_idx, int)
+        return type_idx
+    return ctx.module.tags[tag_idx - len(imports)]
+
+
+def _memory_type(ctx: ModuleContext, memory_idx: int) -> MemoryType:
+    """Type of a memory."""
+    imports = _imports(ctx.module, ImportKind.MEMORY)
+    if memory_idx < len(imports):
+        memory_type = imports[memory_idx].desc
+        assert isinstance(memory_type, MemoryType)
+        return memory_type
+    return ctx.module.memories[memory_idx - len(imports)]
+
+
+def _table_type(ctx: ModuleContext, table_idx: int) -> TableType:
+    """Type of a table."""
+    imports = _imports(ctx.module, ImportKind.TABLE)
+    if table_idx < len(imports):
+        table_type = imports[table_idx].desc
+        assert isinstance(table_type, TableType)
+        return table_type
+    return ctx.module.tables[table_idx - len(imports)]
+
+
+def _limits_match(actual: Limits, expected: Limits) -> bool:
+    """Whether limits lie within those an import expects."""
+    if actual.min < expected.min:
+        return False
+    if expected.max is None:
+        return True
+    return actual.max is not None and actual.max <= expected.max
+
+
+def _global_type(ctx: ModuleContext, global_idx: int) -> GlobalType:
+    """Type of a global."""
+    imports = _imports(ctx.module, ImportKind.GLOBAL)
+    if global_idx < len(imports):
+        global_type = imports[global_idx].desc
+        assert isinstance(global_type, GlobalType)
+        return global_type
+    return ctx.module.globals[global_idx - len(imports)].type
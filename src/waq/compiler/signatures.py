@@ -0,0 +1,112 @@
+"""Canonical type ids for indirect call signature checks.
+
+Each table entry carries a signature tag alongside the function pointer, and
+call_indirect compares it with the type expected at the call site. Tags are
+canonical type ids: types that are equivalent under the GC proposal's
+iso-recursive rules (same shape, same recursion group structure, same
+supertype) share one id, namely the index of the first such type.
+"""
+
+from __future__ import annotations
+
+from typing import TYPE_CHECKING
+
+from waq.parser.types import ArrayType, FieldType, FuncType, StructType, ValueType
+
+if TYPE_CHECKING:
+    from collections.abc import Hashable
+
+    from waq.parser.module import WasmModule
+
+
+def canonical_type_ids(
+    module: WasmModule,
+    seen: dict[Hashable, int] | None = None,
+    first_id: int = 0,
+) -> list[int]:
+    """Map every type index to its canonical type id.
+
+    Linked modules share `seen`, so equivalent types of different modules
+    get the same id; each module's types are numbered from `first_id`.
+    """
+    if seen is None:
+        seen = {}
+    groups: dict[int, tuple[int, int]] = {}
+    for start, count in module.rec_groups:
+        for idx in range(start, start + count):
+            groups[idx] = (start, count)
+
+    ids: list[int] = []
+    for idx in range(len(module.types)):
+        start, count = groups.get(idx, (idx, 1))
+        key = (
+            idx - start,
+            tuple(
+                _member_key(module, ids, start, count, member)
+                for member in range(start, start + count)
+            ),
+        )
+        ids.append(seen.setdefault(key, first_id + idx))
+    return ids
+
+
+def matching_type_ids(
+    module: WasmModule, ids: list[int], type_idx: int
+) -> list[int]:
+    """Canonical ids accepted where `type_idx` is expected.
+
+    These are the type's own id followed by the ids of all its declared
+    (transitive) subtypes.
+    """
+    expected = ids[type_idx]
+    matches = [expected]
+    for idx in range(len(module.types)):
+        if ids[idx] in matches:
+            continue
+        current = module.supertypes.get(idx)
+        visited = set()
+        while current is not None and current not in visited:
+            if ids[current] == expected:
+                matches.append(ids[idx])
+                break
+            visited.add(current)
+            current = module.supertypes.get(current)
+    return matches
+
+
+def _member_key(
+    module: WasmModule, ids: list[int], start: int, count: int, idx: int
+) -> Hashable:
+    """Structural key for one member of a recursion group.
+
+    References to types inside the group are recorded relative to the group
+    start; references outside it use the target's canonical id.
+    """
+
+    def ref(target: int) -> Hashable:
+        if start <= target < start + count:
+            return ("rec", target - start)
+        if target < len(ids):
+            return ("id", ids[target])
+        return ("idx", target)
+
+    def field(field_type: FieldType) -> Hashable:
+        storage = field_type.storage_type
+        if not isinstance(storage, ValueType):
+            # Concrete reference to a type index
+            return (ref(storage), field_type.mutable)
+        return (storage, field_type.mutable)
+
+    composite = module.types[idx]
+    match composite:
+        case FuncType():
+            shape: Hashable = ("func", composite.params, composite.results)
+        case StructType():
+            shape = ("struct", tuple(field(f) for f in composite.fields))
+        case ArrayType():
+            shape = ("array", field(composite.element_type))
+        case _:
+            shape = ("other", composite)
+
+    supertype = module.supertypes.get(idx)
+    return (shape, None if supertype is None else ref(supertype))
@@ -3,10 +3,14 @@
 from __future__ import annotations
 
 from dataclasses import dataclass, field
+from typing import TYPE_CHECKING
 
 from waq.errors import CompileError
 from waq.parser.types import ValueType
 
+if TYPE_CHECKING:
+    from collections.abc import Iterator
+
 
 @dataclass(frozen=True, slots=True)
 class StackValue:
@@ -47,8 +51,9 @@ class ValueStack:
         """Pop n values from the stack (in order: first popped is last in list)."""
         if len(self._stack) < n:
             raise CompileError(f"stack underflow: need {n}, have {len(self._stack)}")
-        result = self._stack[-n:]
-        self._stack = self._stack[:-n]
+        split = len(self._stack) - n
+        result = self._stack[split:]
+        self._stack = self._stack[:split]
         return result
 
     def peek(self) -> StackValue:
@@ -106,6 +111,10 @@ class ValueStack:
     def __len__(self) -> int:
         return len(self._stack)
 
+    def __iter__(self) -> Iterator[StackValue]:
+        """Iterate over the values, bottom of the stack first."""
+        return iter(self._stack)
+
     def __repr__(self) -> str:
         items = ", ".join(str(v) for v in self._stack)
         return f"ValueStack([{items}])"
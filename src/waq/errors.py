@@ -2,23 +2,60 @@
 
 from __future__ import annotations
 
+from typing import TYPE_CHECKING
+
+if TYPE_CHECKING:
+    from waq.validator import ValidationResult
+
 
 class WasmError(Exception):
     """Base class for all WASM compiler errors."""
 
 
 class ParseError(WasmError):
-    """Error during WASM binary parsing."""
+    """Error during WASM binary or text parsing.
+
+    Binary errors carry a byte offset; text format errors carry a
+    1-based line and column instead.
+    """
 
-    def __init__(self, message: str, offset: int | None = None) -> None:
+    def __init__(
+        self,
+        message: str,
+        offset: int | None = None,
+        *,
+        line: int | None = None,
+        column: int | None = None,
+    ) -> None:
         self.offset = offset
-        if offset is not None:
+        self.line = line
+        self.column = column
+        if line is not None:
+            message = f"at line {line}, column {column}: {message}"
+        elif offset is not None:
             message = f"at offset 0x{offset:x}: {message}"
         super().__init__(message)
 
 
 class ValidationError(WasmError):
-    """Error during WASM module validation."""
+    """Error during WASM module validation.
+
+    Attributes:
+        result: Full validation result (errors and warnings), if available
+    """
+
+    def __init__(self, message: str, result: ValidationResult | None = None) -> None:
+        self.result = result
+        super().__init__(message)
+
+    @classmethod
+    def from_result(cls, result: ValidationResult) -> ValidationError:
+        """Build an error listing every error-level issue in `result`."""
+        errors = result.errors
+        noun = "error" if len(errors) == 1 else "errors"
+        lines = [f"{len(errors)} {noun}"]
+        lines.extend(f"  {issue}" for issue in errors)
+        return cls("\n".join(lines), result)
 
 
 class CompileError(WasmError):
@@ -61,3 +98,7 @@ class TrapError(WasmError):
     def __init__(self, trap_type: str, message: str = "") -> None:
         self.trap_type = trap_type
         super().__init__(f"{trap_type}: {message}" if message else trap_type)
+
+
+class LinkError(WasmError):
+    """Error while linking several WASM modules into one program."""
@@ -1,4 +1,4 @@
-"""WASM binary parser."""
+"""WASM binary and text format parsers."""
 
 from __future__ import annotations
 
@@ -12,6 +12,7 @@ from .types import (
     TableType,
     ValueType,
 )
+from .wat import parse_wat
 
 __all__ = [
     "DEFAULT_LIMITS",
@@ -24,4 +25,5 @@ __all__ = [
     "TableType",
     "ValueType",
     "parse_module",
+    "parse_wat",
 ]
@@ -31,6 +31,11 @@ if TYPE_CHECKING:
 WASM_MAGIC = b"\x00asm"
 WASM_VERSION = 1
 
+# Single-byte value types allowed as block types
+_BLOCK_VALUE_TYPES = frozenset(
+    vtype for vtype in ValueType if vtype not in (ValueType.I8, ValueType.I16)
+)
+
 
 @dataclass(frozen=True, slots=True)
 class ParserLimits:
@@ -85,11 +90,17 @@ class BinaryReader:
     """Reader for WASM binary format with LEB128 support."""
 
     def __init__(
-        self, data: bytes, limits: ParserLimits | None = None, pos: int = 0
+        self,
+        data: bytes,
+        limits: ParserLimits | None = None,
+        pos: int = 0,
+        types: list[CompositeType] | None = None,
     ) -> None:
         self.data = data
         self.pos = pos
         self.limits = limits or DEFAULT_LIMITS
+        # Module types known so far, to fold `(ref $t)` into an abstract type
+        self.types = types if types is not None else []
 
     @property
     def remaining(self) -> int:
@@ -199,8 +210,14 @@ class BinaryReader:
             raise ParseError(f"invalid UTF-8 in name: {e}", self.pos - length) from e
 
     def read_value_type(self) -> ValueType:
-        """Read a value type."""
+        """Read a value type.
+
+        Typed references `(ref null? ht)` (0x63/0x64) are folded into the
+        abstract reference type of their heap type, as the text parser does.
+        """
         byte = self.read_byte()
+        if byte in (0x63, 0x64):
+            return self.read_heap_value_type()
         try:
             return ValueType(byte)
         except ValueError:
@@ -208,13 +225,36 @@ class BinaryReader:
                 f"invalid value type: 0x{byte:02x}", self.pos - 1
             ) from None
 
+    def read_heap_value_type(self) -> ValueType:
+        """Read a heap type and return the abstract reference type for it."""
+        pos = self.pos
+        heap_type = self.read_s32_leb128()
+        if heap_type < 0:
+            try:
+                vtype = ValueType(heap_type & 0x7F)
+            except ValueError:
+                vtype = None
+            if vtype is None or not vtype.is_reference():
+                raise ParseError(f"invalid heap type: {heap_type}", pos)
+            return vtype
+        if heap_type < len(self.types):
+            match self.types[heap_type]:
+                case FuncType():
+                    return ValueType.FUNCREF
+                case StructType():
+                    return ValueType.STRUCTREF
+                case ArrayType():
+                    return ValueType.ARRAYREF
+        # Forward reference within a rec group
+        return ValueType.ANYREF
+
     def read_block_type(self) -> BlockType:
         """Read a block type (empty, value type, or type index)."""
         byte = self.peek_byte()
         if byte == 0x40:
             self.read_byte()
             return None
-        if byte in (0x7F, 0x7E, 0x7D, 0x7C, 0x70, 0x6F):
+        if byte in _BLOCK_VALUE_TYPES or byte in (0x63, 0x64):
             return self.read_value_type()
         # Type index (signed LEB128 for negative values)
         return self.read_s32_leb128()
@@ -273,12 +313,16 @@ class BinaryReader:
         return result
 
     def read_table_type(self) -> TableType:
-        """Read a table type."""
+        """Read a table type.
+
+        Table64 (WASM 3.0) uses flag bit 0x04 to indicate 64-bit indices.
+        """
         elem_type = self.read_value_type()
         if elem_type not in (ValueType.FUNCREF, ValueType.EXTERNREF):
             raise ParseError(f"invalid table element type: {elem_type}", self.pos)
-        limits = self.read_limits()
-        return TableType(limits, elem_type)
+        is_table64 = bool(self.peek_byte() & 0x04)
+        limits = self.read_limits(is_memory64=is_table64)
+        return TableType(limits, elem_type, is_table64=is_table64)
 
     def read_global_type(self) -> GlobalType:
         """Read a global type."""
@@ -445,4 +489,4 @@ class BinaryReader:
     def slice(self, length: int) -> BinaryReader:
         """Create a new reader for a slice of the data."""
         data = self.read_bytes(length)
-        return BinaryReader(data, limits=self.limits)
+        return BinaryReader(data, limits=self.limits, types=self.types)
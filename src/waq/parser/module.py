@@ -36,6 +36,7 @@ class SectionId(IntEnum):
     CODE = 10
     DATA = 11
     DATA_COUNT = 12
+    TAG = 13
 
 
 class ExportKind(IntEnum):
@@ -45,6 +46,7 @@ class ExportKind(IntEnum):
     TABLE = 1
     MEMORY = 2
     GLOBAL = 3
+    TAG = 4
 
 
 class ImportKind(IntEnum):
@@ -54,6 +56,7 @@ class ImportKind(IntEnum):
     TABLE = 1
     MEMORY = 2
     GLOBAL = 3
+    TAG = 4
 
 
 @dataclass
@@ -67,6 +70,7 @@ class Import:
     # For TABLE: TableType
     # For MEMORY: MemoryType
     # For GLOBAL: GlobalType
+    # For TAG: type index of the exception's parameters
     desc: int | TableType | MemoryType | GlobalType
 
 
@@ -118,6 +122,8 @@ class ElementSegment:
     table_idx: int
     offset_expr: bytes  # Raw offset expression
     func_indices: list[int]
+    # Declarative segments only declare the functions ref.func may refer to
+    declarative: bool = False
 
 
 @dataclass
@@ -127,6 +133,12 @@ class WasmModule:
     # Type section (composite types: functions, structs, arrays)
     types: list[CompositeType] = field(default_factory=list)
 
+    # GC subtyping: declared supertype index for each type that has one
+    supertypes: dict[int, int] = field(default_factory=dict)
+
+    # GC recursion groups with more than one member, as (first index, count)
+    rec_groups: list[tuple[int, int]] = field(default_factory=list)
+
     # Import section
     imports: list[Import] = field(default_factory=list)
 
@@ -136,9 +148,15 @@ class WasmModule:
     # Table section
     tables: list[TableType] = field(default_factory=list)
 
+    # Init expressions of defined tables that have one, by position in tables
+    table_inits: dict[int, bytes] = field(default_factory=dict)
+
     # Memory section
     memories: list[MemoryType] = field(default_factory=list)
 
+    # Tag section (type indices of exception tags)
+    tags: list[int] = field(default_factory=list)
+
     # Global section
     globals: list[Global] = field(default_factory=list)
 
@@ -179,6 +197,33 @@ class WasmModule:
         """Count of imported globals."""
         return sum(1 for imp in self.imports if imp.kind == ImportKind.GLOBAL)
 
+    def num_imported_tags(self) -> int:
+        """Count of imported tags."""
+        return sum(1 for imp in self.imports if imp.kind == ImportKind.TAG)
+
+    def memory_types(self) -> list[MemoryType]:
+        """Types of all memories in index order, imported memories first."""
+        imported = [
+            imp.desc for imp in self.imports if imp.kind == ImportKind.MEMORY
+        ]
+        return [t for t in imported if isinstance(t, MemoryType)] + self.memories
+
+    def get_tag_type(self, tag_idx: int) -> FuncType:
+        """Get the parameter signature of a tag by tag index."""
+        type_indices = [
+            imp.desc for imp in self.imports if imp.kind == ImportKind.TAG
+        ] + self.tags
+        if tag_idx >= len(type_indices):
+            raise ValueError(f"tag {tag_idx} not found")
+        type_idx = type_indices[tag_idx]
+        assert isinstance(type_idx, int)
+        if type_idx >= len(self.types):
+            raise ValueError(f"type index {type_idx} out of range")
+        type_def = self.types[type_idx]
+        if not isinstance(type_def, FuncType):
+            raise ValueError(f"type {type_idx} is not a function type")
+        return type_def
+
     def get_func_type(self, func_idx: int) -> FuncType:
         """Get function type by function index."""
         num_imports = self.num_imported_funcs()
@@ -250,7 +295,8 @@ def parse_module(
     Raises:
         ParseError: If parsing fails or limits are exceeded
     """
-    reader = BinaryReader(data, limits=limits)
+    module = WasmModule()
+    reader = BinaryReader(data, limits=limits, types=module.types)
 
     # Check magic number
     magic = reader.read_bytes(4)
@@ -262,8 +308,6 @@ def parse_module(
     if version != WASM_VERSION:
         raise ParseError(f"unsupported version: {version}", 4)
 
-    module = WasmModule()
-
     # Parse sections
     while not reader.at_end:
         section_id = reader.read_byte()
@@ -313,6 +357,8 @@ def _parse_section(
         case SectionId.DATA_COUNT:
             # Just validation, we don't need to store this
             _count = reader.read_u32_leb128()
+        case SectionId.TAG:
+            _parse_tag_section(module, reader)
 
 
 def _parse_custom_section(module: WasmModule, reader: BinaryReader) -> None:
@@ -345,9 +391,37 @@ def _parse_name_section(module: WasmModule, reader: BinaryReader) -> None:
 def _parse_type_section(module: WasmModule, reader: BinaryReader) -> None:
     """Parse type section.
 
-    Supports both WASM 1.0 function types and WASM GC composite types.
+    Supports both WASM 1.0 function types and WASM GC composite types,
+    including recursion groups (0x4E) and subtype declarations (0x50/0x4F).
     """
-    module.types = reader.read_vector(reader.read_composite_type)
+    count = reader.read_u32_leb128()
+    reader.check_limit(count, reader.limits.max_vector_size, "type count")
+    for _ in range(count):
+        if reader.peek_byte() == 0x4E:
+            reader.read_byte()
+            group_size = reader.read_u32_leb128()
+            reader.check_limit(
+                group_size, reader.limits.max_vector_size, "rec group size"
+            )
+            start = len(module.types)
+            for _ in range(group_size):
+                _read_sub_type(module, reader)
+            if group_size > 1:
+                module.rec_groups.append((start, group_size))
+        else:
+            _read_sub_type(module, reader)
+
+
+def _read_sub_type(module: WasmModule, reader: BinaryReader) -> None:
+    """Read a (possibly final) subtype declaration and its composite type."""
+    if reader.peek_byte() in (0x50, 0x4F):
+        reader.read_byte()
+        supers = reader.read_vector(reader.read_u32_leb128)
+        if len(supers) > 1:
+            raise ParseError("type has more than one supertype", reader.pos)
+        if supers:
+            module.supertypes[len(module.types)] = supers[0]
+    module.types.append(reader.read_composite_type())
 
 
 def _parse_import_section(module: WasmModule, reader: BinaryReader) -> None:
@@ -367,6 +441,8 @@ def _parse_import_section(module: WasmModule, reader: BinaryReader) -> None:
                 desc = reader.read_memory_type()
             case ImportKind.GLOBAL:
                 desc = reader.read_global_type()
+            case ImportKind.TAG:
+                desc = _read_tag_type(reader)
 
         module.imports.append(Import(mod_name, field_name, kind, desc))
 
@@ -377,8 +453,20 @@ def _parse_function_section(module: WasmModule, reader: BinaryReader) -> None:
 
 
 def _parse_table_section(module: WasmModule, reader: BinaryReader) -> None:
-    """Parse table section."""
-    module.tables = reader.read_vector(reader.read_table_type)
+    """Parse table section.
+
+    A table prefixed with 0x40 0x00 has an init expression for its elements.
+    """
+    count = reader.read_u32_leb128()
+    for i in range(count):
+        has_init = reader.peek_byte() == 0x40
+        if has_init:
+            reader.read_byte()
+            if reader.read_byte() != 0x00:
+                raise ParseError("expected 0x00 after table prefix", reader.pos - 1)
+        module.tables.append(reader.read_table_type())
+        if has_init:
+            module.table_inits[i] = _read_init_expr(reader)
 
 
 def _parse_memory_section(module: WasmModule, reader: BinaryReader) -> None:
@@ -386,6 +474,19 @@ def _parse_memory_section(module: WasmModule, reader: BinaryReader) -> None:
     module.memories = reader.read_vector(reader.read_memory_type)
 
 
+def _parse_tag_section(module: WasmModule, reader: BinaryReader) -> None:
+    """Parse tag section (exception handling)."""
+    module.tags = reader.read_vector(lambda: _read_tag_type(reader))
+
+
+def _read_tag_type(reader: BinaryReader) -> int:
+    """Read a tag type, returning its type index."""
+    attribute = reader.read_byte()
+    if attribute != 0:
+        raise ParseError(f"invalid tag attribute: {attribute}", reader.pos - 1)
+    return reader.read_u32_leb128()
+
+
 def _parse_global_section(module: WasmModule, reader: BinaryReader) -> None:
     """Parse global section."""
     count = reader.read_u32_leb128()
@@ -411,19 +512,51 @@ def _parse_start_section(module: WasmModule, reader: BinaryReader) -> None:
 
 
 def _parse_element_section(module: WasmModule, reader: BinaryReader) -> None:
-    """Parse element section."""
+    """Parse element section.
+
+    Flag bit 0 marks a passive or declarative segment (bit 1 set for the
+    latter), bit 1 of an active segment an explicit table index, and bit 2
+    element expressions instead of function indices.
+    """
     count = reader.read_u32_leb128()
     for _ in range(count):
-        # Simplified: only handle basic active segments (flags=0)
         flags = reader.read_u32_leb128()
-        if flags == 0:
-            # Active segment, table 0
+        if flags > 7:
+            raise ParseError(f"unsupported element segment flags: {flags}", reader.pos)
+
+        table_idx = -1
+        offset_expr = b""
+        if not flags & 1:
+            table_idx = reader.read_u32_leb128() if flags & 2 else 0
             offset_expr = _read_init_expr(reader)
-            func_indices = reader.read_vector(reader.read_u32_leb128)
-            module.elements.append(ElementSegment(0, offset_expr, func_indices))
+        if flags & 4 and flags & 3:
+            reader.read_storage_type()  # element reference type
+        elif flags & 3:
+            reader.read_byte()  # element kind (0x00 = funcref)
+
+        if flags & 4:
+            func_indices = reader.read_vector(lambda: _read_elem_expr(reader))
         else:
-            # Skip complex segment types for now
-            raise ParseError(f"unsupported element segment flags: {flags}", reader.pos)
+            func_indices = reader.read_vector(reader.read_u32_leb128)
+
+        module.elements.append(
+            ElementSegment(
+                table_idx, offset_expr, func_indices, declarative=flags & 3 == 3
+            )
+        )
+
+
+def _read_elem_expr(reader: BinaryReader) -> int:
+    """Read a `ref.func` element expression, returning the function index."""
+    opcode = reader.read_byte()
+    if opcode != 0xD2:
+        raise ParseError(
+            f"unsupported element expression opcode: 0x{opcode:02x}", reader.pos
+        )
+    func_idx = reader.read_u32_leb128()
+    if reader.read_byte() != 0x0B:
+        raise ParseError("expected end of element expression", reader.pos)
+    return func_idx
 
 
 def _parse_code_section(module: WasmModule, reader: BinaryReader) -> None:
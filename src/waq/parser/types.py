@@ -30,6 +30,9 @@ class ValueType(IntEnum):
     NULLFUNCREF = 0x73
     NULLEXTERNREF = 0x72
     NULLREF = 0x71
+    # Exception references (exception handling proposal)
+    EXNREF = 0x69
+    NULLEXNREF = 0x74
     # Packed types for struct/array fields
     I8 = 0x78
     I16 = 0x77
@@ -56,6 +59,8 @@ class ValueType(IntEnum):
                 | ValueType.NULLFUNCREF
                 | ValueType.NULLEXTERNREF
                 | ValueType.NULLREF
+                | ValueType.EXNREF
+                | ValueType.NULLEXNREF
             ):
                 return "l"  # All reference types are pointers
             case ValueType.I8:
@@ -79,6 +84,23 @@ class ValueType(IntEnum):
             ValueType.NULLFUNCREF,
             ValueType.NULLEXTERNREF,
             ValueType.NULLREF,
+            ValueType.EXNREF,
+            ValueType.NULLEXNREF,
+        )
+
+    def is_gc_reference(self) -> bool:
+        """Check if values of this type can point to GC heap objects.
+
+        Externrefs count, since an externalized anyref is still a GC object,
+        and so do exnrefs, since captured exceptions are allocated on the heap.
+        """
+        return self in (
+            ValueType.EXTERNREF,
+            ValueType.ANYREF,
+            ValueType.EQREF,
+            ValueType.STRUCTREF,
+            ValueType.ARRAYREF,
+            ValueType.EXNREF,
         )
 
     def __str__(self) -> str:
@@ -129,9 +151,11 @@ class TableType:
 
     limits: Limits
     elem_type: ValueType  # FUNCREF or EXTERNREF
+    is_table64: bool = False  # WASM 3.0
 
     def __str__(self) -> str:
-        return f"table {self.limits} {self.elem_type}"
+        suffix = " (table64)" if self.is_table64 else ""
+        return f"table {self.limits} {self.elem_type}{suffix}"
 
 
 @dataclass(frozen=True, slots=True)
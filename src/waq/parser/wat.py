@@ -0,0 +1,1913 @@
+"""WebAssembly text format (WAT) parser.
+
+Parses the text format directly into a `WasmModule`, so `.wat` inputs go
+through the same compilation pipeline as binary modules without requiring
+an external assembler.
+
+Supported syntax:
+- Folded and flat instructions, named identifiers for every index space
+- Inline imports/exports, inline element and data segments
+- Implicit type uses (`(param ...)`/`(result ...)` without `(type ...)`)
+- GC types (`rec`, `sub`, `struct`, `array`, `ref`) and GC instructions
+- Exception handling: `try_table`/`throw_ref`, and the legacy
+  `try`/`catch`/`catch_all`/`delegate` form
+- `(module binary ...)` and `(module quote ...)` forms
+
+Errors are reported as `ParseError` with 1-based line and column numbers.
+"""
+
+from __future__ import annotations
+
+import math
+import struct
+from dataclasses import dataclass, field
+
+from waq.errors import ParseError
+
+from .module import (
+    DataSegment,
+    ElementSegment,
+    Export,
+    ExportKind,
+    FunctionBody,
+    Global,
+    Import,
+    ImportKind,
+    WasmModule,
+    parse_module,
+)
+from .types import (
+    ArrayType,
+    CompositeType,
+    FieldType,
+    FuncType,
+    GlobalType,
+    Limits,
+    MemoryType,
+    StructType,
+    TableType,
+    ValueType,
+)
+
+# ============================================================================
+# Lexer
+# ============================================================================
+
+
+@dataclass(slots=True)
+class Token:
+    """A WAT token: keyword, identifier, number or string."""
+
+    value: str | bytes  # bytes for string literals
+    line: int
+    column: int
+
+    @property
+    def is_string(self) -> bool:
+        return isinstance(self.value, bytes)
+
+    @property
+    def text(self) -> str:
+        """Token text (empty for string literals)."""
+        return self.value if isinstance(self.value, str) else ""
+
+
+@dataclass(slots=True)
+class SExpr:
+    """A parenthesized list of tokens and nested lists."""
+
+    items: list[Token | SExpr]
+    line: int
+    column: int
+
+    @property
+    def head(self) -> str:
+        """Keyword at the start of the list, or '' if there is none."""
+        if self.items and isinstance(self.items[0], Token):
+            return self.items[0].text
+        return ""
+
+
+Node = Token | SExpr
+
+_ID_CHARS = frozenset(
+    "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ"
+    "!#$%&'*+-./:<=>?@\\^_`|~"
+)
+
+_STRING_ESCAPES = {
+    "n": 0x0A,
+    "t": 0x09,
+    "r": 0x0D,
+    '"': 0x22,
+    "'": 0x27,
+    "\\": 0x5C,
+}
+
+
+def _error(message: str, node: Node | None) -> ParseError:
+    if node is None:
+        return ParseError(message)
+    return ParseError(message, line=node.line, column=node.column)
+
+
+def tokenize(text: str) -> list[Node]:
+    """Tokenize WAT source and group it into top-level S-expressions.
+
+    Comments and annotations (`(@name ...)`) are discarded.
+
+    Raises:
+        ParseError: On unbalanced parentheses or malformed tokens
+    """
+    pos = 0
+    line = 1
+    line_start = 0
+    length = len(text)
+    stack: list[SExpr] = [SExpr([], 1, 1)]
+
+    def column_at(p: int) -> int:
+        return p - line_start + 1
+
+    while pos < length:
+        ch = text[pos]
+
+        if ch == "\n":
+            pos += 1
+            line += 1
+            line_start = pos
+            continue
+        if ch in " \t\r":
+            pos += 1
+            continue
+
+        # Line comment
+        if text.startswith(";;", pos):
+            end = text.find("\n", pos)
+            pos = length if end < 0 else end
+            continue
+
+        # Block comment (nestable)
+        if text.startswith("(;", pos):
+            start_line, start_col = line, column_at(pos)
+            depth = 0
+            while pos < length:
+                if text.startswith("(;", pos):
+                    depth += 1
+                    pos += 2
+                elif text.startswith(";)", pos):
+                    depth -= 1
+                    pos += 2
+                    if depth == 0:
+                        break
+                else:
+                    if text[pos] == "\n":
+                        line += 1
+                        line_start = pos + 1
+                    pos += 1
+            if depth != 0:
+                raise ParseError(
+                    "unterminated block comment", line=start_line, column=start_col
+                )
+            continue
+
+        if ch == "(":
+            stack.append(SExpr([], line, column_at(pos)))
+            pos += 1
+            continue
+
+        if ch == ")":
+            if len(stack) == 1:
+                raise ParseError("unexpected ')'", line=line, column=column_at(pos))
+            node = stack.pop()
+            pos += 1
+            # Drop annotations
+            if not node.head.startswith("@"):
+                stack[-1].items.append(node)
+            continue
+
+        if ch == '"':
+            col = column_at(pos)
+            value, pos = _read_string(text, pos, line, col)
+            stack[-1].items.append(Token(value, line, col))
+            continue
+
+        start = pos
+        while pos < length and text[pos] in _ID_CHARS:
+            pos += 1
+        if pos == start:
+            raise ParseError(
+                f"unexpected character {ch!r}", line=line, column=column_at(pos)
+            )
+        stack[-1].items.append(Token(text[start:pos], line, column_at(start)))
+
+    if len(stack) > 1:
+        unclosed = stack[-1]
+        raise ParseError("unclosed '('", line=unclosed.line, column=unclosed.column)
+    return stack[0].items
+
+
+def _read_string(text: str, pos: int, line: int, col: int) -> tuple[bytes, int]:
+    """Read a string literal starting at the opening quote."""
+    out = bytearray()
+    pos += 1
+    length = len(text)
+    while True:
+        if pos >= length or text[pos] == "\n":
+            raise ParseError("unterminated string", line=line, column=col)
+        ch = text[pos]
+        if ch == '"':
+            return bytes(out), pos + 1
+        if ch != "\\":
+            out += ch.encode("utf-8")
+            pos += 1
+            continue
+        esc = text[pos + 1 : pos + 2]
+        if esc in _STRING_ESCAPES:
+            out.append(_STRING_ESCAPES[esc])
+            pos += 2
+        elif esc == "u":
+            end = text.find("}", pos)
+            if text[pos + 2 : pos + 3] != "{" or end < 0:
+                raise ParseError("malformed unicode escape", line=line, column=col)
+            try:
+                code_point = int(text[pos + 3 : end].replace("_", ""), 16)
+                out += chr(code_point).encode("utf-8")
+            except ValueError:
+                raise ParseError(
+                    "malformed unicode escape", line=line, column=col
+                ) from None
+            pos = end + 1
+        else:
+            digits = text[pos + 1 : pos + 3]
+            if len(digits) != 2 or any(
+                c not in "0123456789abcdefABCDEF" for c in digits
+            ):
+                raise ParseError(
+                    f"invalid string escape '\\{esc}'", line=line, column=col
+                )
+            out.append(int(digits, 16))
+            pos += 3
+
+
+# ============================================================================
+# Numbers
+# ============================================================================
+
+
+def _parse_int(token: Token, bits: int) -> int:
+    """Parse an integer literal, returning its two's complement signed value.
+
+    Both signed and unsigned spellings are accepted, as in the spec.
+    """
+    text = token.text.replace("_", "")
+    negative = text.startswith("-")
+    if text[:1] in "+-":
+        text = text[1:]
+    try:
+        value = int(text, 16) if text.lower().startswith("0x") else int(text, 10)
+    except ValueError:
+        raise _error(f"invalid integer literal '{token.text}'", token) from None
+    if negative:
+        value = -value
+    if not -(1 << (bits - 1)) <= value < (1 << bits):
+        raise _error(f"i{bits} constant out of range: {token.text}", token)
+    if value >= 1 << (bits - 1):
+        value -= 1 << bits
+    return value
+
+
+def _parse_nat(token: Token) -> int:
+    """Parse an unsigned natural number (indices, limits, offsets)."""
+    text = token.text.replace("_", "")
+    try:
+        if text.lower().startswith("0x"):
+            return int(text, 16)
+        if text.isdigit():
+            return int(text, 10)
+    except ValueError:
+        pass
+    raise _error(f"expected a natural number, got '{token.text}'", token)
+
+
+def _is_nat(text: str) -> bool:
+    text = text.replace("_", "")
+    if text.lower().startswith("0x"):
+        return len(text) > 2 and all(c in "0123456789abcdefABCDEF" for c in text[2:])
+    return text.isdigit()
+
+
+def _parse_float(token: Token, bits: int) -> bytes:
+    """Parse a float literal into its little-endian IEEE 754 encoding."""
+    text = token.text.replace("_", "")
+    sign = 1 if text.startswith("-") else 0
+    if text[:1] in "+-":
+        text = text[1:]
+
+    exp_bits, mant_bits, fmt, int_fmt = (
+        (8, 23, "<f", "<I") if bits == 32 else (11, 52, "<d", "<Q")
+    )
+
+    if text.startswith("nan"):
+        if text == "nan":
+            payload = 1 << (mant_bits - 1)
+        elif text.startswith("nan:0x"):
+            try:
+                payload = int(text[6:], 16)
+            except ValueError:
+                raise _error(f"invalid NaN payload '{token.text}'", token) from None
+            if not 0 < payload < (1 << mant_bits):
+                raise _error(f"NaN payload out of range: {token.text}", token)
+        else:
+            raise _error(f"invalid float literal '{token.text}'", token)
+        raw = (sign << (bits - 1)) | (((1 << exp_bits) - 1) << mant_bits) | payload
+        return struct.pack(int_fmt, raw)
+
+    try:
+        if text == "inf":
+            value = math.inf
+        elif text.lower().startswith("0x"):
+            value = float.fromhex(text)
+        else:
+            value = float(text)
+    except ValueError:
+        raise _error(f"invalid float literal '{token.text}'", token) from None
+    if sign:
+        value = -value
+    try:
+        return struct.pack(fmt, value)
+    except OverflowError:
+        raise _error(f"f{bits} constant out of range: {token.text}", token) from None
+
+
+# ============================================================================
+# Encoding helpers
+# ============================================================================
+
+
+def _uleb(value: int) -> bytes:
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if value:
+            out.append(byte | 0x80)
+        else:
+            out.append(byte)
+            return bytes(out)
+
+
+def _sleb(value: int) -> bytes:
+    out = bytearray()
+    while True:
+        byte = value & 0x7F
+        value >>= 7
+        if (value == 0 and not byte & 0x40) or (value == -1 and byte & 0x40):
+            out.append(byte)
+            return bytes(out)
+        out.append(byte | 0x80)
+
+
+# ============================================================================
+# Instruction tables
+# ============================================================================
+
+# Instructions without immediates
+_PLAIN: dict[str, bytes] = {
+    "unreachable": b"\x00",
+    "nop": b"\x01",
+    "throw_ref": b"\x0a",
+    "return": b"\x0f",
+    "drop": b"\x1a",
+    "ref.is_null": b"\xd1",
+    "ref.eq": b"\xd3",
+    "ref.as_non_null": b"\xd4",
+    "array.len": b"\xfb\x0f",
+    "any.convert_extern": b"\xfb\x1a",
+    "extern.convert_any": b"\xfb\x1b",
+    "ref.i31": b"\xfb\x1c",
+    "i31.get_s": b"\xfb\x1d",
+    "i31.get_u": b"\xfb\x1e",
+}
+
+
+def _add_plain(start: int, names: str) -> None:
+    for offset, name in enumerate(names.split()):
+        _PLAIN[name] = bytes([start + offset])
+
+
+_ICMP = "eq ne lt_s lt_u gt_s gt_u le_s le_u ge_s ge_u"
+_FCMP = "eq ne lt gt le ge"
+_IARITH = (
+    "clz ctz popcnt add sub mul div_s div_u rem_s rem_u and or xor shl shr_s shr_u "
+    "rotl rotr"
+)
+_FARITH = "abs neg ceil floor trunc nearest sqrt add sub mul div min max copysign"
+
+_add_plain(0x45, "i32.eqz " + " ".join(f"i32.{op}" for op in _ICMP.split()))
+_add_plain(0x50, "i64.eqz " + " ".join(f"i64.{op}" for op in _ICMP.split()))
+_add_plain(0x5B, " ".join(f"f32.{op}" for op in _FCMP.split()))
+_add_plain(0x61, " ".join(f"f64.{op}" for op in _FCMP.split()))
+_add_plain(0x67, " ".join(f"i32.{op}" for op in _IARITH.split()))
+_add_plain(0x79, " ".join(f"i64.{op}" for op in _IARITH.split()))
+_add_plain(0x8B, " ".join(f"f32.{op}" for op in _FARITH.split()))
+_add_plain(0x99, " ".join(f"f64.{op}" for op in _FARITH.split()))
+_add_plain(
+    0xA7,
+    "i32.wrap_i64 i32.trunc_f32_s i32.trunc_f32_u i32.trunc_f64_s i32.trunc_f64_u "
+    "i64.extend_i32_s i64.extend_i32_u i64.trunc_f32_s i64.trunc_f32_u "
+    "i64.trunc_f64_s i64.trunc_f64_u f32.convert_i32_s f32.convert_i32_u "
+    "f32.convert_i64_s f32.convert_i64_u f32.demote_f64 f64.convert_i32_s "
+    "f64.convert_i32_u f64.convert_i64_s f64.convert_i64_u f64.promote_f32 "
+    "i32.reinterpret_f32 i64.reinterpret_f64 f32.reinterpret_i32 "
+    "f64.reinterpret_i64 i32.extend8_s i32.extend16_s i64.extend8_s "
+    "i64.extend16_s i64.extend32_s",
+)
+
+for _i, _name in enumerate(
+    "i32.trunc_sat_f32_s i32.trunc_sat_f32_u i32.trunc_sat_f64_s "
+    "i32.trunc_sat_f64_u i64.trunc_sat_f32_s i64.trunc_sat_f32_u "
+    "i64.trunc_sat_f64_s i64.trunc_sat_f64_u".split()
+):
+    _PLAIN[_name] = bytes([0xFC, _i])
+
+# Memory access instructions: name -> (opcode, natural alignment in bytes)
+_MEMORY_ACCESS: dict[str, tuple[int, int]] = {
+    "i32.load": (0x28, 4),
+    "i64.load": (0x29, 8),
+    "f32.load": (0x2A, 4),
+    "f64.load": (0x2B, 8),
+    "i32.load8_s": (0x2C, 1),
+    "i32.load8_u": (0x2D, 1),
+    "i32.load16_s": (0x2E, 2),
+    "i32.load16_u": (0x2F, 2),
+    "i64.load8_s": (0x30, 1),
+    "i64.load8_u": (0x31, 1),
+    "i64.load16_s": (0x32, 2),
+    "i64.load16_u": (0x33, 2),
+    "i64.load32_s": (0x34, 4),
+    "i64.load32_u": (0x35, 4),
+    "i32.store": (0x36, 4),
+    "i64.store": (0x37, 8),
+    "f32.store": (0x38, 4),
+    "f64.store": (0x39, 8),
+    "i32.store8": (0x3A, 1),
+    "i32.store16": (0x3B, 2),
+    "i64.store8": (0x3C, 1),
+    "i64.store16": (0x3D, 2),
+    "i64.store32": (0x3E, 4),
+}
+
+# GC instructions taking a single type index
+_GC_TYPE_OPS: dict[str, int] = {
+    "struct.new": 0x00,
+    "struct.new_default": 0x01,
+    "array.new": 0x06,
+    "array.new_default": 0x07,
+    "array.get": 0x0B,
+    "array.get_s": 0x0C,
+    "array.get_u": 0x0D,
+    "array.set": 0x0E,
+    "array.fill": 0x10,
+}
+
+# GC instructions taking a type index and a field index
+_GC_FIELD_OPS: dict[str, int] = {
+    "struct.get": 0x02,
+    "struct.get_s": 0x03,
+    "struct.get_u": 0x04,
+    "struct.set": 0x05,
+}
+
+_BLOCK_OPS = {"block": 0x02, "loop": 0x03, "if": 0x04, "try": 0x06}
+
+# try_table catch clauses: name -> (encoding, has tag index)
+_CATCH_CLAUSES = {
+    "catch": (0x00, True),
+    "catch_ref": (0x01, True),
+    "catch_all": (0x02, False),
+    "catch_all_ref": (0x03, False),
+}
+
+_VALUE_TYPES: dict[str, ValueType] = {
+    "i32": ValueType.I32,
+    "i64": ValueType.I64,
+    "f32": ValueType.F32,
+    "f64": ValueType.F64,
+    "v128": ValueType.V128,
+    "funcref": ValueType.FUNCREF,
+    "externref": ValueType.EXTERNREF,
+    "anyref": ValueType.ANYREF,
+    "eqref": ValueType.EQREF,
+    "i31ref": ValueType.I31REF,
+    "structref": ValueType.STRUCTREF,
+    "arrayref": ValueType.ARRAYREF,
+    "nullref": ValueType.NULLREF,
+    "nullfuncref": ValueType.NULLFUNCREF,
+    "nullexternref": ValueType.NULLEXTERNREF,
+    "exnref": ValueType.EXNREF,
+    "nullexnref": ValueType.NULLEXNREF,
+}
+
+# Abstract heap types: name -> (value type of a reference to it, binary encoding)
+_HEAP_TYPES: dict[str, tuple[ValueType, int]] = {
+    "func": (ValueType.FUNCREF, 0x70),
+    "extern": (ValueType.EXTERNREF, 0x6F),
+    "any": (ValueType.ANYREF, 0x6E),
+    "eq": (ValueType.EQREF, 0x6D),
+    "i31": (ValueType.I31REF, 0x6C),
+    "struct": (ValueType.STRUCTREF, 0x6B),
+    "array": (ValueType.ARRAYREF, 0x6A),
+    "none": (ValueType.NULLREF, 0x71),
+    "nofunc": (ValueType.NULLFUNCREF, 0x73),
+    "noextern": (ValueType.NULLEXTERNREF, 0x72),
+    "exn": (ValueType.EXNREF, 0x69),
+    "noexn": (ValueType.NULLEXNREF, 0x74),
+}
+
+# Bottom heap types of the legacy `ref.null <type>ref` spelling
+_LEGACY_NULL_HEAP_TYPES = {
+    "nullref": "none",
+    "nullfuncref": "nofunc",
+    "nullexternref": "noextern",
+    "nullexnref": "noexn",
+}
+
+_IMPORT_KINDS = {
+    "func": ImportKind.FUNC,
+    "table": ImportKind.TABLE,
+    "memory": ImportKind.MEMORY,
+    "global": ImportKind.GLOBAL,
+    "tag": ImportKind.TAG,
+}
+
+_EXPORT_KINDS = {
+    "func": ExportKind.FUNC,
+    "table": ExportKind.TABLE,
+    "memory": ExportKind.MEMORY,
+    "global": ExportKind.GLOBAL,
+    "tag": ExportKind.TAG,
+}
+
+_PAGE_SIZE = 65536
+
+
+# ============================================================================
+# Parser
+# ============================================================================
+
+
+class _Cursor:
+    """Sequential reader over the items of an S-expression."""
+
+    def __init__(self, items: list[Node], parent: Node | None, start: int = 0) -> None:
+        self.items = items
+        self.parent = parent
+        self.pos = start
+
+    @property
+    def at_end(self) -> bool:
+        return self.pos >= len(self.items)
+
+    def peek(self) -> Node | None:
+        return self.items[self.pos] if self.pos < len(self.items) else None
+
+    def peek_atom(self) -> str:
+        """Text of the next token if it is a keyword/identifier/number."""
+        node = self.peek()
+        if isinstance(node, Token) and not node.is_string:
+            return node.text
+        return ""
+
+    def peek_list(self, *heads: str) -> SExpr | None:
+        """Next node if it is a list starting with one of the given keywords."""
+        node = self.peek()
+        if isinstance(node, SExpr) and node.head in heads:
+            return node
+        return None
+
+    def next(self) -> Node:
+        node = self.peek()
+        if node is None:
+            raise _error("unexpected end of expression", self.last())
+        self.pos += 1
+        return node
+
+    def next_token(self, what: str = "token") -> Token:
+        node = self.next()
+        if not isinstance(node, Token):
+            raise _error(f"expected {what}, got '('", node)
+        return node
+
+    def next_atom(self, what: str = "keyword") -> Token:
+        token = self.next_token(what)
+        if token.is_string:
+            raise _error(f"expected {what}, got string", token)
+        return token
+
+    def next_string(self) -> bytes:
+        token = self.next_token("string")
+        if not isinstance(token.value, bytes):
+            raise _error(f"expected string, got '{token.text}'", token)
+        return token.value
+
+    def next_list(self, what: str) -> SExpr:
+        node = self.next()
+        if not isinstance(node, SExpr):
+            raise _error(f"expected {what}", node)
+        return node
+
+    def optional_id(self) -> str | None:
+        text = self.peek_atom()
+        if text.startswith("$"):
+            self.pos += 1
+            return text
+        return None
+
+    def expect_end(self) -> None:
+        node = self.peek()
+        if node is not None:
+            text = node.text if isinstance(node, Token) else f"({node.head}"
+            raise _error(f"unexpected '{text}'", node)
+
+    def last(self) -> Node | None:
+        """Most recently consumed node, for error locations."""
+        if self.pos > 0:
+            return self.items[min(self.pos, len(self.items)) - 1]
+        return self.parent
+
+
+@dataclass
+class _Namespace:
+    """Index space with optional symbolic names."""
+
+    kind: str
+    names: dict[str, int] = field(default_factory=dict)
+    count: int = 0
+
+    def define(self, name: str | None, node: Node) -> int:
+        idx = self.count
+        if name is not None:
+            if name in self.names:
+                raise _error(f"duplicate {self.kind} identifier {name}", node)
+            self.names[name] = idx
+        self.count += 1
+        return idx
+
+    def resolve(self, token: Token) -> int:
+        text = token.text
+        if text.startswith("$"):
+            if text not in self.names:
+                raise _error(f"unknown {self.kind} {text}", token)
+            return self.names[text]
+        if _is_nat(text):
+            return _parse_nat(token)
+        raise _error(f"expected {self.kind} index, got '{text}'", token)
+
+
+@dataclass
+class _FuncDef:
+    """A defined function awaiting body compilation."""
+
+    node: SExpr
+    cursor: _Cursor
+    type_idx: int
+    param_names: list[str | None]
+
+
+@dataclass
+class _BodyContext:
+    """Per-function state used while encoding instructions."""
+
+    locals: _Namespace
+    labels: list[str | None] = field(default_factory=list)
+
+    def resolve_label(self, token: Token) -> int:
+        text = token.text
+        if text.startswith("$"):
+            for depth, name in enumerate(reversed(self.labels)):
+                if name == text:
+                    return depth
+            raise _error(f"unknown label {text}", token)
+        return _parse_nat(token)
+
+
+class _WatParser:
+    """Two-pass WAT module parser.
+
+    The first pass assigns indices to every named entity so that forward
+    references resolve; the second pass builds the module contents.
+    """
+
+    def __init__(self) -> None:
+        self.module = WasmModule()
+        self.types = _Namespace("type")
+        self.funcs = _Namespace("function")
+        self.tables = _Namespace("table")
+        self.memories = _Namespace("memory")
+        self.globals = _Namespace("global")
+        self.elems = _Namespace("elem segment")
+        self.datas = _Namespace("data segment")
+        self.tags = _Namespace("tag")
+        self.field_names: dict[int, dict[str, int]] = {}
+        self.type_kinds: list[str] = []
+        self.func_defs: list[_FuncDef] = []
+        self.seen_definition = False
+
+    # ------------------------------------------------------------------
+    # Entry point
+    # ------------------------------------------------------------------
+
+    def parse(self, fields: list[Node]) -> WasmModule:
+        for node in fields:
+            if not isinstance(node, SExpr):
+                raise _error("expected module field", node)
+
+        # Types first: type uses may refer to any type definition.
+        type_nodes: list[tuple[SExpr, _Cursor]] = []
+        for node in fields:
+            assert isinstance(node, SExpr)
+            if node.head == "type":
+                type_nodes.append((node, _Cursor(node.items, node, 1)))
+            elif node.head == "rec":
+                members = self._rec_members(node)
+                if len(members) > 1:
+                    self.module.rec_groups.append((len(type_nodes), len(members)))
+                type_nodes.extend(
+                    (sub, _Cursor(sub.items, sub, 1)) for sub in members
+                )
+        for node, cur in type_nodes:
+            self._declare_type(node, cur)
+        for _node, cur in type_nodes:
+            self.module.types.append(self._parse_type_def(cur))
+
+        # Pass 1: assign indices to imports and definitions.
+        for node in fields:
+            assert isinstance(node, SExpr)
+            self._declare_field(node)
+
+        # Pass 2: build everything else in textual order.
+        for node in fields:
+            assert isinstance(node, SExpr)
+            self._build_field(node)
+
+        for func_def in self.func_defs:
+            self.module.code.append(self._compile_function(func_def))
+
+        return self.module
+
+    def _rec_members(self, node: SExpr) -> list[SExpr]:
+        members = []
+        for sub in node.items[1:]:
+            if not isinstance(sub, SExpr) or sub.head != "type":
+                raise _error("expected (type ...) inside (rec ...)", sub)
+            members.append(sub)
+        return members
+
+    # ------------------------------------------------------------------
+    # Types
+    # ------------------------------------------------------------------
+
+    def _declare_type(self, node: SExpr, cur: _Cursor) -> None:
+        name = cur.optional_id()
+        self.types.define(name, node)
+        body = cur.peek_list("func", "struct", "array", "sub")
+        while body is not None and body.head == "sub":
+            inner = [item for item in body.items[1:] if isinstance(item, SExpr)]
+            body = inner[-1] if inner else None
+        if body is None or body.head not in ("func", "struct", "array"):
+            raise _error("expected func, struct or array type", node)
+        self.type_kinds.append(body.head)
+
+    def _parse_type_def(self, cur: _Cursor) -> CompositeType:
+        cur.optional_id()
+        body = cur.next_list("composite type")
+        cur.expect_end()
+        return self._parse_composite(body)
+
+    def _parse_composite(self, body: SExpr) -> CompositeType:
+        cur = _Cursor(body.items, body, 1)
+        match body.head:
+            case "sub":
+                if cur.peek_atom() == "final":
+                    cur.next()
+                supers = []
+                while not cur.at_end and isinstance(cur.peek(), Token):
+                    supers.append(self.types.resolve(cur.next_atom("type index")))
+                if len(supers) > 1:
+                    raise _error("type has more than one supertype", body)
+                if supers:
+                    self.module.supertypes[len(self.module.types)] = supers[0]
+                inner = cur.next_list("composite type")
+                cur.expect_end()
+                return self._parse_composite(inner)
+            case "func":
+                params, _names, results = self._parse_signature(cur)
+                cur.expect_end()
+                return FuncType(tuple(params), tuple(results))
+            case "struct":
+                fields: list[FieldType] = []
+                names: dict[str, int] = {}
+                while not cur.at_end:
+                    fnode = cur.next_list("(field ...)")
+                    if fnode.head != "field":
+                        raise _error(f"expected (field ...), got ({fnode.head}", fnode)
+                    fcur = _Cursor(fnode.items, fnode, 1)
+                    fname = fcur.optional_id()
+                    if fname is not None:
+                        names[fname] = len(fields)
+                        fields.append(self._parse_field_type(fcur))
+                        fcur.expect_end()
+                    else:
+                        while not fcur.at_end:
+                            fields.append(self._parse_field_type(fcur))
+                self.field_names[len(self.module.types)] = names
+                return StructType(tuple(fields))
+            case "array":
+                element = self._parse_field_type(cur)
+                cur.expect_end()
+                return ArrayType(element)
+            case _:
+                raise _error(f"unknown type constructor '{body.head}'", body)
+
+    def _parse_field_type(self, cur: _Cursor) -> FieldType:
+        mut = cur.peek_list("mut")
+        if mut is not None:
+            cur.next()
+            inner = _Cursor(mut.items, mut, 1)
+            storage = self._parse_storage_type(inner)
+            inner.expect_end()
+            return FieldType(storage, mutable=True)
+        return FieldType(self._parse_storage_type(cur), mutable=False)
+
+    def _parse_storage_type(self, cur: _Cursor) -> ValueType | int:
+        text = cur.peek_atom()
+        if text == "i8":
+            cur.next()
+            return ValueType.I8
+        if text == "i16":
+            cur.next()
+            return ValueType.I16
+        ref = cur.peek_list("ref")
+        if ref is not None:
+            cur.next()
+            heap, _nullable = self._parse_ref(ref)
+            # Concrete references are stored as type indices, like the
+            # binary reader does.
+            return heap if isinstance(heap, int) else _HEAP_TYPES[heap][0]
+        return self._parse_value_type(cur)
+
+    def _parse_ref(self, node: SExpr) -> tuple[str | int, bool]:
+        """Parse `(ref null? heaptype)`, returning (heap type, nullable)."""
+        cur = _Cursor(node.items, node, 1)
+        nullable = False
+        if cur.peek_atom() == "null":
+            cur.next()
+            nullable = True
+        heap = self._parse_heap_type(cur)
+        cur.expect_end()
+        return heap, nullable
+
+    def _parse_heap_type(self, cur: _Cursor) -> str | int:
+        token = cur.next_atom("heap type")
+        if token.text in _HEAP_TYPES:
+            return token.text
+        return self.types.resolve(token)
+
+    def _parse_value_type(self, cur: _Cursor) -> ValueType:
+        node = cur.next()
+        if isinstance(node, Token) and node.text in _VALUE_TYPES:
+            return _VALUE_TYPES[node.text]
+        if isinstance(node, SExpr) and node.head == "ref":
+            heap, _nullable = self._parse_ref(node)
+            return self._heap_value_type(heap)
+        text = node.text if isinstance(node, Token) else f"({node.head}"
+        raise _error(f"unknown value type '{text}'", node)
+
+    def _heap_value_type(self, heap: str | int) -> ValueType:
+        """Map a heap type to the ValueType used for references to it.
+
+        Concrete type references collapse to the abstract type of their
+        composite kind, since WasmModule value types carry no type index.
+        """
+        if isinstance(heap, str):
+            return _HEAP_TYPES[heap][0]
+        if heap >= len(self.type_kinds):
+            return ValueType.ANYREF
+        return {
+            "func": ValueType.FUNCREF,
+            "struct": ValueType.STRUCTREF,
+            "array": ValueType.ARRAYREF,
+        }[self.type_kinds[heap]]
+
+    def _encode_heap_type(self, heap: str | int) -> bytes:
+        if isinstance(heap, str):
+            return bytes([_HEAP_TYPES[heap][1]])
+        return _sleb(heap)
+
+    def _parse_signature(
+        self, cur: _Cursor, *, allow_names: bool = True
+    ) -> tuple[list[ValueType], list[str | None], list[ValueType]]:
+        """Parse `(param ...)* (result ...)*`."""
+        params: list[ValueType] = []
+        names: list[str | None] = []
+        results: list[ValueType] = []
+        while (node := cur.peek_list("param")) is not None:
+            cur.next()
+            pcur = _Cursor(node.items, node, 1)
+            name = pcur.optional_id()
+            if name is not None:
+                if not allow_names:
+                    raise _error("parameter names are not allowed here", node)
+                params.append(self._parse_value_type(pcur))
+                names.append(name)
+                pcur.expect_end()
+            else:
+                while not pcur.at_end:
+                    params.append(self._parse_value_type(pcur))
+                    names.append(None)
+        while (node := cur.peek_list("result")) is not None:
+            cur.next()
+            rcur = _Cursor(node.items, node, 1)
+            while not rcur.at_end:
+                results.append(self._parse_value_type(rcur))
+        return params, names, results
+
+    def _parse_type_use(self, cur: _Cursor) -> tuple[int, list[str | None]]:
+        """Parse a type use, creating an implicit function type if needed.
+
+        Returns:
+            (type index, parameter names)
+        """
+        type_node = cur.peek_list("type")
+        type_idx: int | None = None
+        if type_node is not None:
+            cur.next()
+            tcur = _Cursor(type_node.items, type_node, 1)
+            type_idx = self.types.resolve(tcur.next_atom("type index"))
+            tcur.expect_end()
+        params, names, results = self._parse_signature(cur)
+
+        if type_idx is not None:
+            if type_idx >= len(self.module.types):
+                raise _error(f"unknown type {type_idx}", type_node)
+            func_type = self.module.types[type_idx]
+            if not isinstance(func_type, FuncType):
+                raise _error(f"type {type_idx} is not a function type", type_node)
+            if (params or results) and (
+                tuple(params) != func_type.params
+                or tuple(results) != func_type.results
+            ):
+                raise _error(
+                    "inline function type does not match (type ...)", type_node
+                )
+            if not names:
+                names = [None] * len(func_type.params)
+            return type_idx, names
+
+        return self._intern_func_type(FuncType(tuple(params), tuple(results))), names
+
+    def _intern_func_type(self, func_type: FuncType) -> int:
+        for idx, existing in enumerate(self.module.types):
+            if existing == func_type:
+                return idx
+        self.module.types.append(func_type)
+        self.type_kinds.append("func")
+        self.types.count += 1
+        return len(self.module.types) - 1
+
+    def _func_type(self, type_idx: int) -> FuncType:
+        func_type = self.module.types[type_idx]
+        assert isinstance(func_type, FuncType)
+        return func_type
+
+    # ------------------------------------------------------------------
+    # Pass 1: index assignment
+    # ------------------------------------------------------------------
+
+    def _namespace_for(self, kind: str) -> _Namespace:
+        return {
+            "func": self.funcs,
+            "table": self.tables,
+            "memory": self.memories,
+            "global": self.globals,
+            "tag": self.tags,
+        }[kind]
+
+    def _declare_field(self, node: SExpr) -> None:
+        cur = _Cursor(node.items, node, 1)
+        head = node.head
+        match head:
+            case "type" | "rec" | "export" | "start":
+                return
+            case "import":
+                cur.next_string()
+                cur.next_string()
+                desc = cur.next_list("import description")
+                if desc.head not in ("func", "table", "memory", "global", "tag"):
+                    raise _error(f"unknown import kind '{desc.head}'", desc)
+                self._check_import_order(desc)
+                self._namespace_for(desc.head).define(
+                    _Cursor(desc.items, desc, 1).optional_id(), desc
+                )
+            case "func" | "table" | "memory" | "global" | "tag":
+                name = cur.optional_id()
+                is_import = self._skip_exports(cur) is not None
+                if is_import:
+                    self._check_import_order(node)
+                else:
+                    self.seen_definition = True
+                self._namespace_for(head).define(name, node)
+            case "elem":
+                self.seen_definition = True
+                self.elems.define(cur.optional_id(), node)
+            case "data":
+                self.seen_definition = True
+                self.datas.define(cur.optional_id(), node)
+            case _:
+                raise _error(f"unknown module field '{head}'", node)
+
+        # Inline segments get implicit indices in their own spaces
+        if head == "table" and self._inline_segment(node, "elem") is not None:
+            self.elems.define(None, node)
+        if head == "memory" and self._inline_segment(node, "data") is not None:
+            self.datas.define(None, node)
+
+    def _check_import_order(self, node: SExpr) -> None:
+        if self.seen_definition:
+            raise _error("imports must occur before all non-import definitions", node)
+
+    def _skip_exports(self, cur: _Cursor) -> SExpr | None:
+        """Skip inline `(export ...)` forms; return an inline `(import ...)`."""
+        while cur.peek_list("export") is not None:
+            cur.next()
+        imp = cur.peek_list("import")
+        if imp is not None:
+            cur.next()
+        return imp
+
+    def _inline_segment(self, node: SExpr, head: str) -> SExpr | None:
+        for item in node.items[1:]:
+            if isinstance(item, SExpr) and item.head == head:
+                return item
+        return None
+
+    # ------------------------------------------------------------------
+    # Pass 2: module contents
+    # ------------------------------------------------------------------
+
+    def _build_field(self, node: SExpr) -> None:
+        cur = _Cursor(node.items, node, 1)
+        match node.head:
+            case "type" | "rec":
+                return
+            case "import":
+                self._build_import(cur)
+            case "func":
+                self._build_func(node, cur)
+            case "table":
+                self._build_table(node, cur)
+            case "memory":
+                self._build_memory(node, cur)
+            case "global":
+                self._build_global(node, cur)
+            case "tag":
+                self._build_tag(node, cur)
+            case "export":
+                self._build_export(cur)
+            case "start":
+                self.module.start = self.funcs.resolve(cur.next_atom("function index"))
+                cur.expect_end()
+            case "elem":
+                self._build_elem(node, cur)
+            case "data":
+                self._build_data(node, cur)
+
+    def _inline_exports(self, cur: _Cursor, kind: str, index: int) -> None:
+        while (exp := cur.peek_list("export")) is not None:
+            cur.next()
+            ecur = _Cursor(exp.items, exp, 1)
+            name = self._decode_name(ecur)
+            ecur.expect_end()
+            self.module.exports.append(Export(name, _EXPORT_KINDS[kind], index))
+
+    def _decode_name(self, cur: _Cursor) -> str:
+        token = cur.peek()
+        raw = cur.next_string()
+        try:
+            return raw.decode("utf-8")
+        except UnicodeDecodeError:
+            raise _error("malformed UTF-8 in name", token) from None
+
+    def _add_import(self, module: str, name: str, desc: SExpr, cur: _Cursor) -> None:
+        """Parse an import description from `cur` and record it."""
+        kind = desc.head
+        import_desc: int | TableType | MemoryType | GlobalType
+        match kind:
+            case "func" | "tag":
+                import_desc, _names = self._parse_type_use(cur)
+            case "table":
+                import_desc = self._parse_table_type(cur)
+            case "memory":
+                import_desc = self._parse_memory_type(cur)
+            case _:
+                import_desc = self._parse_global_type(cur)
+        cur.expect_end()
+        self.module.imports.append(
+            Import(module, name, _IMPORT_KINDS[kind], import_desc)
+        )
+
+    def _build_import(self, cur: _Cursor) -> None:
+        module = self._decode_name(cur)
+        name = self._decode_name(cur)
+        desc = cur.next_list("import description")
+        cur.expect_end()
+        dcur = _Cursor(desc.items, desc, 1)
+        dcur.optional_id()
+        self._add_import(module, name, desc, dcur)
+
+    def _inline_import(self, node: SExpr, cur: _Cursor, index: int) -> bool:
+        """Handle inline exports and an optional inline import.
+
+        Returns True if the definition is an import.
+        """
+        self._inline_exports(cur, node.head, index)
+        imp = cur.peek_list("import")
+        if imp is None:
+            return False
+        cur.next()
+        icur = _Cursor(imp.items, imp, 1)
+        module = self._decode_name(icur)
+        name = self._decode_name(icur)
+        icur.expect_end()
+        self._add_import(module, name, node, cur)
+        return True
+
+    def _build_func(self, node: SExpr, cur: _Cursor) -> None:
+        name = cur.optional_id()
+        index = self.funcs.names[name] if name else self._next_index("func")
+        if self._inline_import(node, cur, index):
+            return
+        type_idx, param_names = self._parse_type_use(cur)
+        self.module.func_types.append(type_idx)
+        self.func_defs.append(_FuncDef(node, cur, type_idx, param_names))
+
+    def _next_index(self, kind: str) -> int:
+        """Index of the next unnamed entity of the given kind in pass 2."""
+        counts = {
+            "func": self.module.num_imported_funcs() + len(self.module.func_types),
+            "table": self.module.num_imported_tables() + len(self.module.tables),
+            "memory": self.module.num_imported_memories() + len(self.module.memories),
+            "global": self.module.num_imported_globals() + len(self.module.globals),
+            "tag": self.module.num_imported_tags() + len(self.module.tags),
+        }
+        return counts[kind]
+
+    def _parse_limits(self, cur: _Cursor) -> Limits:
+        minimum = _parse_nat(cur.next_atom("limit"))
+        maximum = None
+        if _is_nat(cur.peek_atom()):
+            maximum = _parse_nat(cur.next_atom("limit"))
+        return Limits(minimum, maximum)
+
+    def _parse_table_type(self, cur: _Cursor) -> TableType:
+        is_table64 = False
+        if cur.peek_atom() in ("i32", "i64"):
+            is_table64 = cur.next_atom().text == "i64"
+        limits = self._parse_limits(cur)
+        elem_type = self._parse_value_type(cur)
+        return TableType(limits, elem_type, is_table64=is_table64)
+
+    def _parse_memory_type(self, cur: _Cursor) -> MemoryType:
+        is_memory64 = False
+        if cur.peek_atom() in ("i32", "i64"):
+            is_memory64 = cur.next_atom().text == "i64"
+        limits = self._parse_limits(cur)
+        if cur.peek_atom() == "shared":
+            cur.next()
+        return MemoryType(limits, is_memory64=is_memory64)
+
+    def _parse_global_type(self, cur: _Cursor) -> GlobalType:
+        mut = cur.peek_list("mut")
+        if mut is not None:
+            cur.next()
+            mcur = _Cursor(mut.items, mut, 1)
+            value_type = self._parse_value_type(mcur)
+            mcur.expect_end()
+            return GlobalType(value_type, mutable=True)
+        return GlobalType(self._parse_value_type(cur), mutable=False)
+
+    def _build_table(self, node: SExpr, cur: _Cursor) -> None:
+        name = cur.optional_id()
+        index = self.tables.names[name] if name else self._next_index("table")
+        if self._inline_import(node, cur, index):
+            return
+
+        is_table64 = False
+        # An address type directly followed by reftype (elem ...)
+        if cur.peek_atom() in ("i32", "i64") and not _is_nat(
+            self._lookahead_atom(cur, 1)
+        ):
+            is_table64 = cur.next_atom().text == "i64"
+        elif _is_nat(cur.peek_atom()) or cur.peek_atom() in ("i32", "i64"):
+            table_type = self._parse_table_type(cur)
+            if not cur.at_end:
+                position = len(self.module.tables)
+                self.module.table_inits[position] = self._compile_const_expr(cur)
+            cur.expect_end()
+            self.module.tables.append(table_type)
+            return
+
+        # Abbreviation: reftype (elem ...)
+        elem_type = self._parse_value_type(cur)
+        elem = cur.next_list("(elem ...)")
+        cur.expect_end()
+        ecur = _Cursor(elem.items, elem, 1)
+        func_indices = self._parse_elem_items(ecur, elem_type_known=True)
+        size = len(func_indices)
+        self.module.tables.append(
+            TableType(Limits(size, size), elem_type, is_table64=is_table64)
+        )
+        offset_expr = b"\x42\x00\x0b" if is_table64 else b"\x41\x00\x0b"
+        self.module.elements.append(ElementSegment(index, offset_expr, func_indices))
+
+    def _build_memory(self, node: SExpr, cur: _Cursor) -> None:
+        name = cur.optional_id()
+        index = self.memories.names[name] if name else self._next_index("memory")
+        if self._inline_import(node, cur, index):
+            return
+
+        is_memory64 = False
+        # An address type directly followed by (data ...)
+        if cur.peek_atom() in ("i32", "i64") and not _is_nat(
+            self._lookahead_atom(cur, 1)
+        ):
+            is_memory64 = cur.next_atom().text == "i64"
+
+        data = cur.peek_list("data")
+        if data is None:
+            self.module.memories.append(self._parse_memory_type(cur))
+            cur.expect_end()
+            return
+
+        cur.next()
+        cur.expect_end()
+        dcur = _Cursor(data.items, data, 1)
+        contents = bytearray()
+        while not dcur.at_end:
+            contents += dcur.next_string()
+        pages = (len(contents) + _PAGE_SIZE - 1) // _PAGE_SIZE
+        self.module.memories.append(
+            MemoryType(Limits(pages, pages), is_memory64=is_memory64)
+        )
+        offset = b"\x42\x00\x0b" if is_memory64 else b"\x41\x00\x0b"
+        self.module.data.append(DataSegment(index, offset, bytes(contents)))
+
+    def _lookahead_atom(self, cur: _Cursor, ahead: int) -> str:
+        pos = cur.pos + ahead
+        if pos < len(cur.items):
+            node = cur.items[pos]
+            if isinstance(node, Token):
+                return node.text
+        return ""
+
+    def _build_global(self, node: SExpr, cur: _Cursor) -> None:
+        name = cur.optional_id()
+        index = self.globals.names[name] if name else self._next_index("global")
+        if self._inline_import(node, cur, index):
+            return
+        global_type = self._parse_global_type(cur)
+        init_expr = self._compile_const_expr(cur)
+        self.module.globals.append(Global(global_type, init_expr))
+
+    def _build_tag(self, node: SExpr, cur: _Cursor) -> None:
+        name = cur.optional_id()
+        index = self.tags.names[name] if name else self._next_index("tag")
+        if self._inline_import(node, cur, index):
+            return
+        type_idx, _names = self._parse_type_use(cur)
+        cur.expect_end()
+        self.module.tags.append(type_idx)
+
+    def _build_export(self, cur: _Cursor) -> None:
+        name = self._decode_name(cur)
+        desc = cur.next_list("export description")
+        cur.expect_end()
+        if desc.head not in _EXPORT_KINDS:
+            raise _error(f"unsupported export kind '{desc.head}'", desc)
+        dcur = _Cursor(desc.items, desc, 1)
+        index = self._namespace_for(desc.head).resolve(dcur.next_atom("index"))
+        dcur.expect_end()
+        self.module.exports.append(Export(name, _EXPORT_KINDS[desc.head], index))
+
+    def _parse_offset(self, cur: _Cursor) -> bytes:
+        """Parse `(offset expr)` or a single folded instruction."""
+        node = cur.next_list("offset expression")
+        if node.head == "offset":
+            return self._compile_const_expr(_Cursor(node.items, node, 1))
+        return self._compile_const_expr(_Cursor([node], node))
+
+    def _build_elem(self, node: SExpr, cur: _Cursor) -> None:
+        cur.optional_id()
+        if cur.peek_atom() == "declare":
+            cur.next()
+            func_indices = self._parse_elem_items(cur, elem_type_known=False)
+            self.module.elements.append(
+                ElementSegment(-1, b"", func_indices, declarative=True)
+            )
+            return
+
+        table_idx = -1
+        offset_expr = b""
+        table_node = cur.peek_list("table")
+        if table_node is not None:
+            cur.next()
+            tcur = _Cursor(table_node.items, table_node, 1)
+            table_idx = self.tables.resolve(tcur.next_atom("table index"))
+            tcur.expect_end()
+        if isinstance(cur.peek(), SExpr) and cur.peek_list(
+            "item", "ref.func", "ref.null"
+        ) is None:
+            if table_idx < 0:
+                table_idx = 0
+            offset_expr = self._parse_offset(cur)
+        elif table_node is not None:
+            raise _error("expected offset expression", node)
+
+        func_indices = self._parse_elem_items(cur, elem_type_known=False)
+        self.module.elements.append(
+            ElementSegment(table_idx, offset_expr, func_indices)
+        )
+
+    def _parse_elem_items(self, cur: _Cursor, *, elem_type_known: bool) -> list[int]:
+        """Parse an element list into function indices."""
+        text = cur.peek_atom()
+        if text == "func":
+            cur.next()
+        elif not elem_type_known and (
+            text in _VALUE_TYPES or cur.peek_list("ref") is not None
+        ):
+            self._parse_value_type(cur)
+
+        indices: list[int] = []
+        while not cur.at_end:
+            node = cur.next()
+            if isinstance(node, Token):
+                indices.append(self.funcs.resolve(node))
+                continue
+            expr = node
+            if expr.head == "item":
+                items = expr.items[1:]
+                if len(items) == 1 and isinstance(items[0], SExpr):
+                    expr = items[0]
+                else:
+                    expr = SExpr(items, expr.line, expr.column)
+            ecur = _Cursor(expr.items, expr, 0 if expr.head == "" else 1)
+            if expr.head != "ref.func":
+                raise _error(
+                    "only ref.func element expressions are supported", node
+                )
+            indices.append(self.funcs.resolve(ecur.next_atom("function index")))
+            ecur.expect_end()
+        return indices
+
+    def _build_data(self, node: SExpr, cur: _Cursor) -> None:
+        cur.optional_id()
+        memory_idx = -1
+        offset_expr = b""
+        mem_node = cur.peek_list("memory")
+        if mem_node is not None:
+            cur.next()
+            mcur = _Cursor(mem_node.items, mem_node, 1)
+            memory_idx = self.memories.resolve(mcur.next_atom("memory index"))
+            mcur.expect_end()
+        if isinstance(cur.peek(), SExpr):
+            if memory_idx < 0:
+                memory_idx = 0
+            offset_expr = self._parse_offset(cur)
+        elif mem_node is not None:
+            raise _error("expected offset expression", node)
+
+        contents = bytearray()
+        while not cur.at_end:
+            contents += cur.next_string()
+        self.module.data.append(DataSegment(memory_idx, offset_expr, bytes(contents)))
+
+    # ------------------------------------------------------------------
+    # Function bodies
+    # ------------------------------------------------------------------
+
+    def _compile_function(self, func_def: _FuncDef) -> FunctionBody:
+        cur = func_def.cursor
+        ctx = _BodyContext(_Namespace("local"))
+        for pname in func_def.param_names:
+            ctx.locals.define(pname, func_def.node)
+
+        locals_list: list[tuple[int, ValueType]] = []
+        while (node := cur.peek_list("local")) is not None:
+            cur.next()
+            lcur = _Cursor(node.items, node, 1)
+            lname = lcur.optional_id()
+            if lname is not None:
+                ctx.locals.define(lname, node)
+                self._append_local(locals_list, self._parse_value_type(lcur))
+                lcur.expect_end()
+            else:
+                while not lcur.at_end:
+                    ctx.locals.define(None, node)
+                    self._append_local(locals_list, self._parse_value_type(lcur))
+
+        out = bytearray()
+        self._compile_instrs(cur, ctx, out)
+        out.append(0x0B)
+        return FunctionBody(locals_list, bytes(out))
+
+    @staticmethod
+    def _append_local(
+        locals_list: list[tuple[int, ValueType]], vtype: ValueType
+    ) -> None:
+        if locals_list and locals_list[-1][1] == vtype:
+            count, _ = locals_list[-1]
+            locals_list[-1] = (count + 1, vtype)
+        else:
+            locals_list.append((1, vtype))
+
+    def _compile_const_expr(self, cur: _Cursor) -> bytes:
+        out = bytearray()
+        self._compile_instrs(cur, _BodyContext(_Namespace("local")), out)
+        out.append(0x0B)
+        return bytes(out)
+
+    def _compile_instrs(self, cur: _Cursor, ctx: _BodyContext, out: bytearray) -> None:
+        """Compile a sequence of flat and folded instructions."""
+        while not cur.at_end:
+            node = cur.peek()
+            if isinstance(node, SExpr):
+                cur.next()
+                self._compile_folded(node, ctx, out)
+            else:
+                self._compile_flat(cur, ctx, out)
+
+    def _compile_flat(self, cur: _Cursor, ctx: _BodyContext, out: bytearray) -> None:
+        token = cur.next_atom("instruction")
+        name = token.text
+
+        if name in _BLOCK_OPS:
+            label = cur.optional_id()
+            out.append(_BLOCK_OPS[name])
+            out += self._parse_block_type(cur)
+            ctx.labels.append(label)
+            return
+        if name == "try_table":
+            label = cur.optional_id()
+            out.append(0x1F)
+            out += self._parse_block_type(cur)
+            out += self._parse_catch_clauses(cur, ctx)
+            ctx.labels.append(label)
+            return
+        if name in ("else", "catch", "catch_all"):
+            if not ctx.labels:
+                raise _error(f"'{name}' outside of a block", token)
+            self._check_label_id(cur, ctx)
+            if name == "else":
+                out.append(0x05)
+            elif name == "catch":
+                out.append(0x07)
+                out += _uleb(self.tags.resolve(cur.next_atom("tag index")))
+            else:
+                out.append(0x19)
+            return
+        if name == "end":
+            if not ctx.labels:
+                raise _error("'end' without matching block", token)
+            self._check_label_id(cur, ctx)
+            ctx.labels.pop()
+            out.append(0x0B)
+            return
+        if name == "delegate":
+            if not ctx.labels:
+                raise _error("'delegate' outside of a try block", token)
+            ctx.labels.pop()
+            out.append(0x18)
+            out += _uleb(ctx.resolve_label(cur.next_atom("label")))
+            return
+
+        self._compile_plain(token, cur, ctx, out)
+
+    def _check_label_id(self, cur: _Cursor, ctx: _BodyContext) -> None:
+        """Consume an optional trailing label on else/end/catch."""
+        text = cur.peek_atom()
+        if text.startswith("$"):
+            token = cur.next_atom()
+            if text != ctx.labels[-1]:
+                raise _error(f"mismatching label {text}", token)
+
+    def _compile_folded(self, node: SExpr, ctx: _BodyContext, out: bytearray) -> None:
+        name = node.head
+        cur = _Cursor(node.items, node, 1)
+        if not name:
+            raise _error("expected instruction", node)
+
+        if name in ("block", "loop"):
+            label = cur.optional_id()
+            out.append(_BLOCK_OPS[name])
+            out += self._parse_block_type(cur)
+            ctx.labels.append(label)
+            self._compile_instrs(cur, ctx, out)
+            ctx.labels.pop()
+            out.append(0x0B)
+            return
+
+        if name == "if":
+            label = cur.optional_id()
+            block_type = self._parse_block_type(cur)
+            # Condition operands come before (then ...)
+            while (cond := cur.peek()) is not None and not (
+                isinstance(cond, SExpr) and cond.head in ("then", "else")
+            ):
+                cur.next()
+                if not isinstance(cond, SExpr):
+                    raise _error("expected folded condition or (then ...)", cond)
+                self._compile_folded(cond, ctx, out)
+            out.append(0x04)
+            out += block_type
+            ctx.labels.append(label)
+            then = cur.next_list("(then ...)")
+            if then.head != "then":
+                raise _error("expected (then ...)", then)
+            self._compile_instrs(_Cursor(then.items, then, 1), ctx, out)
+            els = cur.peek_list("else")
+            if els is not None:
+                cur.next()
+                out.append(0x05)
+                self._compile_instrs(_Cursor(els.items, els, 1), ctx, out)
+            cur.expect_end()
+            ctx.labels.pop()
+            out.append(0x0B)
+            return
+
+        if name == "try_table":
+            label = cur.optional_id()
+            out.append(0x1F)
+            out += self._parse_block_type(cur)
+            out += self._parse_catch_clauses(cur, ctx)
+            ctx.labels.append(label)
+            self._compile_instrs(cur, ctx, out)
+            ctx.labels.pop()
+            out.append(0x0B)
+            return
+
+        if name == "try":
+            label = cur.optional_id()
+            out.append(0x06)
+            out += self._parse_block_type(cur)
+            ctx.labels.append(label)
+            body = cur.next_list("(do ...)")
+            if body.head != "do":
+                raise _error("expected (do ...)", body)
+            self._compile_instrs(_Cursor(body.items, body, 1), ctx, out)
+            delegate = cur.peek_list("delegate")
+            if delegate is not None:
+                cur.next()
+                cur.expect_end()
+                ctx.labels.pop()
+                dcur = _Cursor(delegate.items, delegate, 1)
+                out.append(0x18)
+                out += _uleb(ctx.resolve_label(dcur.next_atom("label")))
+                dcur.expect_end()
+                return
+            while (handler := cur.peek_list("catch", "catch_all")) is not None:
+                cur.next()
+                hcur = _Cursor(handler.items, handler, 1)
+                if handler.head == "catch":
+                    out.append(0x07)
+                    out += _uleb(self.tags.resolve(hcur.next_atom("tag index")))
+                else:
+                    out.append(0x19)
+                self._compile_instrs(hcur, ctx, out)
+            cur.expect_end()
+            ctx.labels.pop()
+            out.append(0x0B)
+            return
+
+        # Plain instruction: immediates, then folded operands, then opcode
+        op = bytearray()
+        token = node.items[0]
+        assert isinstance(token, Token)
+        self._compile_plain(token, cur, ctx, op)
+        while not cur.at_end:
+            operand = cur.next()
+            if not isinstance(operand, SExpr):
+                text = operand.text if isinstance(operand, Token) else ""
+                raise _error(f"unexpected '{text}' in folded instruction", operand)
+            self._compile_folded(operand, ctx, out)
+        out += op
+
+    def _parse_block_type(self, cur: _Cursor) -> bytes:
+        if cur.peek_list("type") is None:
+            params, _names, results = self._parse_signature(cur, allow_names=False)
+            if not params and len(results) <= 1:
+                return bytes([results[0]]) if results else b"\x40"
+            type_idx = self._intern_func_type(FuncType(tuple(params), tuple(results)))
+            return _sleb(type_idx)
+        type_idx, _names = self._parse_type_use(cur)
+        return _sleb(type_idx)
+
+    def _parse_catch_clauses(self, cur: _Cursor, ctx: _BodyContext) -> bytes:
+        """Parse try_table catch clauses.
+
+        Their labels are resolved outside the try_table's own label.
+        """
+        clauses = bytearray()
+        count = 0
+        while (node := cur.peek_list(*_CATCH_CLAUSES)) is not None:
+            cur.next()
+            ccur = _Cursor(node.items, node, 1)
+            code, has_tag = _CATCH_CLAUSES[node.head]
+            clauses.append(code)
+            if has_tag:
+                clauses += _uleb(self.tags.resolve(ccur.next_atom("tag index")))
+            clauses += _uleb(ctx.resolve_label(ccur.next_atom("label")))
+            ccur.expect_end()
+            count += 1
+        return _uleb(count) + bytes(clauses)
+
+    def _optional_index(self, cur: _Cursor, space: _Namespace) -> int | None:
+        text = cur.peek_atom()
+        if text.startswith("$") or _is_nat(text):
+            return space.resolve(cur.next_atom())
+        return None
+
+    def _compile_plain(
+        self, token: Token, cur: _Cursor, ctx: _BodyContext, out: bytearray
+    ) -> None:
+        """Encode a non-structured instruction and its immediates."""
+        name = token.text
+
+        if name in _PLAIN:
+            out += _PLAIN[name]
+            return
+
+        if name in _MEMORY_ACCESS:
+            opcode, natural = _MEMORY_ACCESS[name]
+            out.append(opcode)
+            out += self._parse_memarg(cur, natural)
+            return
+
+        if name in _GC_TYPE_OPS:
+            out += bytes([0xFB, _GC_TYPE_OPS[name]])
+            out += _uleb(self.types.resolve(cur.next_atom("type index")))
+            return
+
+        if name in _GC_FIELD_OPS:
+            type_idx = self.types.resolve(cur.next_atom("type index"))
+            field_token = cur.next_atom("field index")
+            if field_token.text.startswith("$"):
+                names = self.field_names.get(type_idx, {})
+                if field_token.text not in names:
+                    raise _error(f"unknown field {field_token.text}", field_token)
+                field_idx = names[field_token.text]
+            else:
+                field_idx = _parse_nat(field_token)
+            out += bytes([0xFB, _GC_FIELD_OPS[name]])
+            out += _uleb(type_idx) + _uleb(field_idx)
+            return
+
+        match name:
+            case "i32.const":
+                out.append(0x41)
+                out += _sleb(_parse_int(cur.next_atom("i32 literal"), 32))
+            case "i64.const":
+                out.append(0x42)
+                out += _sleb(_parse_int(cur.next_atom("i64 literal"), 64))
+            case "f32.const":
+                out.append(0x43)
+                out += _parse_float(cur.next_atom("f32 literal"), 32)
+            case "f64.const":
+                out.append(0x44)
+                out += _parse_float(cur.next_atom("f64 literal"), 64)
+            case "local.get" | "local.set" | "local.tee":
+                opcodes = {"local.get": 0x20, "local.set": 0x21, "local.tee": 0x22}
+                out.append(opcodes[name])
+                out += _uleb(ctx.locals.resolve(cur.next_atom("local index")))
+            case "global.get" | "global.set":
+                out.append(0x23 if name == "global.get" else 0x24)
+                out += _uleb(self.globals.resolve(cur.next_atom("global index")))
+            case "br" | "br_if" | "br_on_null" | "br_on_non_null":
+                opcodes = {
+                    "br": 0x0C,
+                    "br_if": 0x0D,
+                    "br_on_null": 0xD5,
+                    "br_on_non_null": 0xD6,
+                }
+                out.append(opcodes[name])
+                out += _uleb(ctx.resolve_label(cur.next_atom("label")))
+            case "br_table":
+                labels = []
+                while (text := cur.peek_atom()).startswith("$") or _is_nat(text):
+                    labels.append(ctx.resolve_label(cur.next_atom()))
+                if not labels:
+                    raise _error("br_table requires at least one label", token)
+                out.append(0x0E)
+                out += _uleb(len(labels) - 1)
+                for depth in labels:
+                    out += _uleb(depth)
+            case "call" | "return_call":
+                out.append(0x10 if name == "call" else 0x12)
+                out += _uleb(self.funcs.resolve(cur.next_atom("function index")))
+            case "call_indirect" | "return_call_indirect":
+                table_idx = self._optional_index(cur, self.tables) or 0
+                type_idx, _names = self._parse_type_use(cur)
+                out.append(0x11 if name == "call_indirect" else 0x13)
+                out += _uleb(type_idx) + _uleb(table_idx)
+            case "call_ref" | "return_call_ref":
+                out.append(0x14 if name == "call_ref" else 0x15)
+                out += _uleb(self.types.resolve(cur.next_atom("type index")))
+            case "select":
+                results: list[ValueType] = []
+                while (node := cur.peek_list("result")) is not None:
+                    cur.next()
+                    rcur = _Cursor(node.items, node, 1)
+                    while not rcur.at_end:
+                        results.append(self._parse_value_type(rcur))
+                if results:
+                    out.append(0x1C)
+                    out += _uleb(len(results)) + bytes(results)
+                else:
+                    out.append(0x1B)
+            case "table.get" | "table.set":
+                out.append(0x25 if name == "table.get" else 0x26)
+                out += _uleb(self._optional_index(cur, self.tables) or 0)
+            case "memory.size" | "memory.grow":
+                out.append(0x3F if name == "memory.size" else 0x40)
+                out += _uleb(self._optional_index(cur, self.memories) or 0)
+            case "ref.null":
+                out.append(0xD0)
+                token = cur.next_atom("heap type")
+                if token.text in _VALUE_TYPES and token.text.endswith("ref"):
+                    # Legacy spelling: ref.null funcref
+                    heap = _LEGACY_NULL_HEAP_TYPES.get(token.text, token.text[:-3])
+                    if heap not in _HEAP_TYPES:
+                        raise _error(f"unknown heap type '{token.text}'", token)
+                    out += self._encode_heap_type(heap)
+                elif token.text in _HEAP_TYPES:
+                    out += self._encode_heap_type(token.text)
+                else:
+                    out += self._encode_heap_type(self.types.resolve(token))
+            case "ref.func":
+                out.append(0xD2)
+                out += _uleb(self.funcs.resolve(cur.next_atom("function index")))
+            case "memory.init":
+                first = cur.next_atom("data segment index")
+                second = self._optional_token(cur)
+                if second is None:
+                    mem_idx, data_idx = 0, self.datas.resolve(first)
+                else:
+                    mem_idx = self.memories.resolve(first)
+                    data_idx = self.datas.resolve(second)
+                out += b"\xfc\x08" + _uleb(data_idx) + _uleb(mem_idx)
+            case "data.drop":
+                out += b"\xfc\x09"
+                out += _uleb(self.datas.resolve(cur.next_atom("data segment index")))
+            case "memory.copy":
+                dst = self._optional_index(cur, self.memories) or 0
+                src = self._optional_index(cur, self.memories) or 0
+                out += b"\xfc\x0a" + _uleb(dst) + _uleb(src)
+            case "memory.fill":
+                mem_idx = self._optional_index(cur, self.memories) or 0
+                out += b"\xfc\x0b" + _uleb(mem_idx)
+            case "table.init":
+                first = cur.next_atom("elem segment index")
+                second = self._optional_token(cur)
+                if second is None:
+                    table_idx, elem_idx = 0, self.elems.resolve(first)
+                else:
+                    table_idx = self.tables.resolve(first)
+                    elem_idx = self.elems.resolve(second)
+                out += b"\xfc\x0c" + _uleb(elem_idx) + _uleb(table_idx)
+            case "elem.drop":
+                out += b"\xfc\x0d"
+                out += _uleb(self.elems.resolve(cur.next_atom("elem segment index")))
+            case "table.copy":
+                dst = self._optional_index(cur, self.tables) or 0
+                src = self._optional_index(cur, self.tables) or 0
+                out += b"\xfc\x0e" + _uleb(dst) + _uleb(src)
+            case "table.grow" | "table.size" | "table.fill":
+                sub = {"table.grow": 0x0F, "table.size": 0x10, "table.fill": 0x11}[name]
+                out += bytes([0xFC, sub])
+                out += _uleb(self._optional_index(cur, self.tables) or 0)
+            case "array.new_fixed":
+                type_idx = self.types.resolve(cur.next_atom("type index"))
+                length = _parse_nat(cur.next_atom("array length"))
+                out += b"\xfb\x08" + _uleb(type_idx) + _uleb(length)
+            case "array.new_data" | "array.init_data":
+                type_idx = self.types.resolve(cur.next_atom("type index"))
+                data_idx = self.datas.resolve(cur.next_atom("data segment index"))
+                sub = 0x09 if name == "array.new_data" else 0x12
+                out += bytes([0xFB, sub]) + _uleb(type_idx) + _uleb(data_idx)
+            case "array.new_elem" | "array.init_elem":
+                type_idx = self.types.resolve(cur.next_atom("type index"))
+                elem_idx = self.elems.resolve(cur.next_atom("elem segment index"))
+                sub = 0x0A if name == "array.new_elem" else 0x13
+                out += bytes([0xFB, sub]) + _uleb(type_idx) + _uleb(elem_idx)
+            case "array.copy":
+                dst = self.types.resolve(cur.next_atom("type index"))
+                src = self.types.resolve(cur.next_atom("type index"))
+                out += b"\xfb\x11" + _uleb(dst) + _uleb(src)
+            case "ref.test" | "ref.cast":
+                heap, nullable = self._parse_ref_type_immediate(cur)
+                sub = (0x14 if name == "ref.test" else 0x16) + int(nullable)
+                out += bytes([0xFB, sub]) + self._encode_heap_type(heap)
+            case "br_on_cast" | "br_on_cast_fail":
+                depth = ctx.resolve_label(cur.next_atom("label"))
+                heap1, null1 = self._parse_ref_type_immediate(cur)
+                heap2, null2 = self._parse_ref_type_immediate(cur)
+                sub = 0x18 if name == "br_on_cast" else 0x19
+                flags = int(null1) | (int(null2) << 1)
+                out += bytes([0xFB, sub, flags]) + _uleb(depth)
+                out += self._encode_heap_type(heap1) + self._encode_heap_type(heap2)
+            case "throw":
+                out.append(0x08)
+                out += _uleb(self.tags.resolve(cur.next_atom("tag index")))
+            case "rethrow":
+                out.append(0x09)
+                out += _uleb(ctx.resolve_label(cur.next_atom("label")))
+            case _:
+                raise _error(f"unknown instruction '{name}'", token)
+
+    def _optional_token(self, cur: _Cursor) -> Token | None:
+        text = cur.peek_atom()
+        if text.startswith("$") or _is_nat(text):
+            return cur.next_atom()
+        return None
+
+    def _parse_ref_type_immediate(self, cur: _Cursor) -> tuple[str | int, bool]:
+        """Parse a reference type operand: `(ref null? ht)` or `xxxref`."""
+        ref = cur.peek_list("ref")
+        if ref is not None:
+            cur.next()
+            return self._parse_ref(ref)
+        token = cur.next_atom("reference type")
+        for heap, (vtype, _code) in _HEAP_TYPES.items():
+            if _VALUE_TYPES.get(token.text) == vtype:
+                return heap, True
+        raise _error(f"expected reference type, got '{token.text}'", token)
+
+    def _parse_memarg(self, cur: _Cursor, natural: int) -> bytes:
+        mem_idx = self._optional_index(cur, self.memories) or 0
+        offset = 0
+        align = natural
+        for key in ("offset=", "align="):
+            text = cur.peek_atom()
+            if text.startswith(key):
+                token = cur.next_atom()
+                value = _parse_nat(Token(text[len(key) :], token.line, token.column))
+                if key == "offset=":
+                    offset = value
+                else:
+                    align = value
+        if align <= 0 or align & (align - 1):
+            raise _error(f"alignment must be a power of two: {align}", cur.last())
+        align_log2 = align.bit_length() - 1
+        if mem_idx:
+            return _uleb(align_log2 | 0x40) + _uleb(mem_idx) + _uleb(offset)
+        return _uleb(align_log2) + _uleb(offset)
+
+
+def parse_wat(text: str) -> WasmModule:
+    """Parse a WebAssembly text format module.
+
+    Accepts either a single `(module ...)` form or a bare sequence of
+    module fields.
+
+    Args:
+        text: WAT source text
+
+    Returns:
+        Parsed WasmModule
+
+    Raises:
+        ParseError: With line/column information if the text is malformed
+    """
+    nodes = tokenize(text)
+    fields: list[Node] = nodes
+    if len(nodes) == 1 and isinstance(nodes[0], SExpr) and nodes[0].head == "module":
+        module_node = nodes[0]
+        cur = _Cursor(module_node.items, module_node, 1)
+        cur.optional_id()
+        mode = cur.peek_atom()
+        if mode in ("binary", "quote"):
+            cur.next()
+            chunks = bytearray()
+            while not cur.at_end:
+                chunks += cur.next_string()
+            if mode == "binary":
+                return parse_module(bytes(chunks))
+            try:
+                source = chunks.decode("utf-8")
+            except UnicodeDecodeError:
+                raise _error("malformed UTF-8 in quoted module", module_node) from None
+            return parse_wat(source)
+        fields = module_node.items[cur.pos :]
+    elif any(isinstance(n, SExpr) and n.head == "module" for n in nodes):
+        raise _error("expected a single module", nodes[0])
+    return _WatParser().parse(fields)
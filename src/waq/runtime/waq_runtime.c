@@ -17,15 +17,198 @@
 #include <stddef.h>  /* For SIZE_MAX */
 #include <math.h>
 #include <string.h>
+#include <signal.h>
+#include <unistd.h>
+#include <sys/mman.h>
 
 /* WASM memory (64KB pages) */
 #define WASM_PAGE_SIZE 65536
 #define WASM_MAX_PAGES 65536
 
+/* ============================================================================
+ * INSTANCE STATE
+ * ============================================================================
+ * Everything a running module owns (memories, tables, segments, the GC heap)
+ * lives in a WasmInstance. The runtime works on the instance running on the
+ * calling thread, which is the default one unless code compiled with
+ * --instance-mode entered another (see INSTANCES). Each piece of state keeps
+ * the name it would have as a global: the macros below map it to a field of
+ * the running instance.
+ *
+ * Built with -DWAQ_INSTANCE_MODE, for --instance-mode code, memory 0 and
+ * table 0 live in the instance as well, where compiled code finds them
+ * through its vmctx argument. Otherwise they are the exported __wasm_memory
+ * and __wasm_table globals, which standalone code reads directly.
+ */
+
+#define WASM_MAX_MEMORIES 16
+#define WASM_MAX_TABLES 16
+#define WASM_MAX_DATA_SEGMENTS 256
+#define WASM_MAX_ELEM_SEGMENTS 256
+#define WASM_GC_NUM_CLASSES 14
+
+/*
+ * Memories other than memory 0, which stays in the __wasm_memory globals so
+ * compiled code can reach it without a call. Each has its own buffer, size
+ * and maximum; slot 0 of the instance's array is unused.
+ */
+typedef struct {
+    uint8_t *data;
+    uint32_t pages;
+    uint32_t max_pages;
+    int has_max;
+} WasmMemory;
+
+/*
+ * Every table, indexed by table index. Table 0 is mirrored in the
+ * __wasm_table globals so call_indirect can reach it without a call.
+ */
+typedef struct {
+    void **elems;
+    int32_t *sigs;
+    uint32_t size;
+    uint32_t max;
+    int has_max;
+} WasmTable;
+
+/* Data segments, and element segments (passive ones, registered at startup) */
+typedef struct {
+    uint8_t *data;
+    size_t size;
+    int dropped;
+} WasmDataSegment;
+
+typedef struct {
+    void **funcs;
+    int32_t count;
+} WasmElemSegment;
+
+/* Defined with the code that uses them */
+typedef struct WasmFuncSig WasmFuncSig;
+typedef struct WasmGCHeader WasmGCHeader;
+typedef struct WasmGCChunk WasmGCChunk;
+typedef struct WasmGCFreeCell WasmGCFreeCell;
+typedef struct WasmGCTypeLayout WasmGCTypeLayout;
+typedef struct WasmGCTagLayout WasmGCTagLayout;
+typedef struct WasmGCFrame WasmGCFrame;
+
+typedef struct WasmInstance WasmInstance;
+
+struct WasmInstance {
+    /* Read by --instance-mode code at fixed offsets: keep these first */
+    uint8_t *memory;             /* 0: memory 0 */
+    uint64_t memory_size_bytes;  /* 8 */
+    void **table;                /* 16: table 0 entries */
+    int32_t *table_sigs;         /* 24 */
+    uint32_t table_size;         /* 32 */
+    uint32_t memory_size_pages;  /* 36 */
+    int64_t *globals;            /* 40: an 8-byte slot per defined global */
+
+    /* Sets the instance up; run again by waq_instance_reset */
+    void (*init)(WasmInstance *);
+
+    uint32_t memory_max_pages;
+    uint8_t *guard_region;
+    WasmMemory memories[WASM_MAX_MEMORIES];
+    WasmTable tables[WASM_MAX_TABLES];
+
+    WasmFuncSig *func_sigs;
+    size_t func_sigs_capacity;
+    size_t func_sigs_count;
+
+    WasmDataSegment data_segments[WASM_MAX_DATA_SEGMENTS];
+    int data_segment_count;
+    WasmElemSegment elem_segments[WASM_MAX_ELEM_SEGMENTS];
+
+    /* GC heap (see GARBAGE COLLECTION) */
+    WasmGCFreeCell *gc_free_lists[WASM_GC_NUM_CLASSES];
+
+    /* All chunks, sorted by address */
+    WasmGCChunk **gc_chunks;
+    size_t gc_chunk_count;
+    size_t gc_chunk_capacity;
+
+    /* Bytes held by allocated objects, and the size that triggers a
+       collection */
+    size_t gc_heap_bytes;
+    size_t gc_threshold;
+
+    WasmGCTypeLayout *gc_types;
+    size_t gc_type_count;
+    WasmGCTagLayout *gc_tags;
+    size_t gc_tag_count;
+
+    /* Top of the shadow stack */
+    WasmGCFrame *gc_frames;
+
+    /* Addresses of reference globals */
+    int64_t **gc_roots;
+    size_t gc_root_count;
+    size_t gc_root_capacity;
+
+    /* Objects marked but not yet traced */
+    WasmGCHeader **gc_mark_stack;
+    size_t gc_mark_count;
+    size_t gc_mark_capacity;
+};
+
+/* The instance running on this thread (defined with the default instance) */
+static __thread WasmInstance *__wasm_instance;
+
+#define __wasm_memory_max_pages (__wasm_instance->memory_max_pages)
+#define guard_region (__wasm_instance->guard_region)
+#define __wasm_memories (__wasm_instance->memories)
+#define __wasm_tables (__wasm_instance->tables)
+#define func_sigs (__wasm_instance->func_sigs)
+#define func_sigs_capacity (__wasm_instance->func_sigs_capacity)
+#define func_sigs_count (__wasm_instance->func_sigs_count)
+#define __wasm_data_segments (__wasm_instance->data_segments)
+#define __wasm_data_segment_count (__wasm_instance->data_segment_count)
+#define __wasm_elem_segments (__wasm_instance->elem_segments)
+#define __wasm_gc_free_lists (__wasm_instance->gc_free_lists)
+#define __wasm_gc_chunks (__wasm_instance->gc_chunks)
+#define __wasm_gc_chunk_count (__wasm_instance->gc_chunk_count)
+#define __wasm_gc_chunk_capacity (__wasm_instance->gc_chunk_capacity)
+#define __wasm_gc_heap_bytes (__wasm_instance->gc_heap_bytes)
+#define __wasm_gc_threshold (__wasm_instance->gc_threshold)
+#define __wasm_gc_types (__wasm_instance->gc_types)
+#define __wasm_gc_type_count (__wasm_instance->gc_type_count)
+#define __wasm_gc_tags (__wasm_instance->gc_tags)
+#define __wasm_gc_tag_count (__wasm_instance->gc_tag_count)
+#define __wasm_gc_frames (__wasm_instance->gc_frames)
+#define __wasm_gc_roots (__wasm_instance->gc_roots)
+#define __wasm_gc_root_count (__wasm_instance->gc_root_count)
+#define __wasm_gc_root_capacity (__wasm_instance->gc_root_capacity)
+#define __wasm_gc_mark_stack (__wasm_instance->gc_mark_stack)
+#define __wasm_gc_mark_count (__wasm_instance->gc_mark_count)
+#define __wasm_gc_mark_capacity (__wasm_instance->gc_mark_capacity)
+
+#ifdef WAQ_INSTANCE_MODE
+
+#define __wasm_memory (__wasm_instance->memory)
+#define __wasm_memory_size_pages (__wasm_instance->memory_size_pages)
+#define __wasm_memory_size_bytes (__wasm_instance->memory_size_bytes)
+
+#else
+
 /* Exported memory pointer - accessed by compiled WASM code */
 uint8_t *__wasm_memory = NULL;
 uint32_t __wasm_memory_size_pages = 0;
 
+/* Current memory size in bytes - read by inline bounds checks */
+uint64_t __wasm_memory_size_bytes = 0;
+
+#endif /* WAQ_INSTANCE_MODE */
+
+static void __wasm_memories_cleanup(void);
+static void __wasm_tables_cleanup(void);
+static void __wasm_func_sigs_cleanup(void);
+static void __wasm_segments_cleanup(void);
+static void __wasm_gc_cleanup(void);
+
+void __wasm_trap_out_of_bounds(void);
+void __wasm_trap_null_reference(void);
+
 /*
  * Defense-in-depth bounds checking.
  * Enable by compiling with -DWAQ_RUNTIME_BOUNDS_CHECK
@@ -204,11 +387,15 @@ float __wasm_f32_sqrt(float x) {
 
 float __wasm_f32_min(float a, float b) {
     if (isnan(a) || isnan(b)) return NAN;
+    /* fminf may return either zero; wasm orders -0 below +0 */
+    if (a == b) return signbit(a) ? a : b;
     return fminf(a, b);
 }
 
 float __wasm_f32_max(float a, float b) {
     if (isnan(a) || isnan(b)) return NAN;
+    /* fmaxf may return either zero; wasm orders -0 below +0 */
+    if (a == b) return signbit(a) ? b : a;
     return fmaxf(a, b);
 }
 
@@ -244,11 +431,15 @@ double __wasm_f64_sqrt(double x) {
 
 double __wasm_f64_min(double a, double b) {
     if (isnan(a) || isnan(b)) return NAN;
+    /* fmin may return either zero; wasm orders -0 below +0 */
+    if (a == b) return signbit(a) ? a : b;
     return fmin(a, b);
 }
 
 double __wasm_f64_max(double a, double b) {
     if (isnan(a) || isnan(b)) return NAN;
+    /* fmax may return either zero; wasm orders -0 below +0 */
+    if (a == b) return signbit(a) ? b : a;
     return fmax(a, b);
 }
 
@@ -283,6 +474,67 @@ void __wasm_trap_out_of_bounds(void) {
     abort();
 }
 
+/*
+ * Guard-page sandboxing (--bounds-checks=guard).
+ *
+ * Memory 0 lives at the start of a PROT_NONE reservation covering every
+ * address a 32-bit load or store can form (4 GiB index + 4 GiB offset), so
+ * compiled code needs no explicit checks. Pages are committed with mprotect
+ * on grow and the memory never moves. Faults inside the reservation are
+ * turned into an out-of-bounds trap by the signal handler below.
+ */
+#define WASM_GUARD_REGION_SIZE ((size_t)8 << 30)
+
+static void __wasm_guard_fault_handler(int sig, siginfo_t *info, void *ucontext) {
+    (void)ucontext;
+    uint8_t *addr = (uint8_t *)info->si_addr;
+    if (guard_region != NULL && addr >= guard_region &&
+        addr < guard_region + WASM_GUARD_REGION_SIZE) {
+        /* Only async-signal-safe calls here */
+        static const char msg[] = "wasm trap: out of bounds memory access\n";
+        ssize_t written = write(STDERR_FILENO, msg, sizeof(msg) - 1);
+        (void)written;
+        abort();
+    }
+    /* Not a wasm memory access: re-raise with the default action */
+    signal(sig, SIG_DFL);
+    raise(sig);
+}
+
+/* Move memory 0 into a guard region; called by __wasm_memory_init */
+void __wasm_memory_guard_init(void) {
+    if (guard_region != NULL) return;
+
+    void *region = mmap(NULL, WASM_GUARD_REGION_SIZE, PROT_NONE,
+                        MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE, -1, 0);
+    if (region == MAP_FAILED) {
+        fprintf(stderr, "wasm: failed to reserve guard region\n");
+        abort();
+    }
+
+    /* Carry over any memory allocated before guard mode was enabled */
+    size_t size = (size_t)__wasm_memory_size_pages * WASM_PAGE_SIZE;
+    if (size > 0) {
+        if (mprotect(region, size, PROT_READ | PROT_WRITE) != 0) {
+            fprintf(stderr, "wasm: failed to commit memory\n");
+            abort();
+        }
+        memcpy(region, __wasm_memory, size);
+    }
+    free(__wasm_memory);
+
+    guard_region = (uint8_t *)region;
+    __wasm_memory = guard_region;
+
+    struct sigaction sa;
+    memset(&sa, 0, sizeof(sa));
+    sa.sa_sigaction = __wasm_guard_fault_handler;
+    sa.sa_flags = SA_SIGINFO;
+    sigemptyset(&sa.sa_mask);
+    sigaction(SIGSEGV, &sa, NULL);
+    sigaction(SIGBUS, &sa, NULL);
+}
+
 /* Memory operations */
 
 int32_t __wasm_memory_grow(int32_t delta) {
@@ -292,14 +544,31 @@ int32_t __wasm_memory_grow(int32_t delta) {
     uint32_t delta_u = (uint32_t)delta;
 
     /* Check for overflow BEFORE addition */
-    if (delta_u > WASM_MAX_PAGES - old_pages) return -1;
+    if (old_pages > __wasm_memory_max_pages ||
+        delta_u > __wasm_memory_max_pages - old_pages) {
+        return -1;
+    }
 
     uint32_t new_pages = old_pages + delta_u;
 
     /* Redundant check, but defensive */
-    if (new_pages > WASM_MAX_PAGES) return -1;
+    if (new_pages > __wasm_memory_max_pages) return -1;
 
     size_t new_size = (size_t)new_pages * WASM_PAGE_SIZE;
+
+    if (guard_region != NULL) {
+        /* Commit the new pages in place; fresh mappings are already zeroed */
+        size_t old_size = (size_t)old_pages * WASM_PAGE_SIZE;
+        if (delta_u > 0 &&
+            mprotect(guard_region + old_size, new_size - old_size,
+                     PROT_READ | PROT_WRITE) != 0) {
+            return -1;
+        }
+        __wasm_memory_size_pages = new_pages;
+        __wasm_memory_size_bytes = (uint64_t)new_size;
+        return (int32_t)old_pages;
+    }
+
     uint8_t *new_memory = realloc(__wasm_memory, new_size);
 
     if (new_memory == NULL && new_size > 0) return -1;
@@ -312,6 +581,7 @@ int32_t __wasm_memory_grow(int32_t delta) {
 
     __wasm_memory = new_memory;
     __wasm_memory_size_pages = new_pages;
+    __wasm_memory_size_bytes = (uint64_t)new_pages * WASM_PAGE_SIZE;
 
     return (int32_t)old_pages;
 }
@@ -332,67 +602,248 @@ void __wasm_runtime_init(uint32_t initial_pages) {
     }
 }
 
-/* Cleanup runtime */
+/* Release everything the running instance owns */
 void __wasm_runtime_cleanup(void) {
-    free(__wasm_memory);
+    if (guard_region != NULL) {
+        munmap(guard_region, WASM_GUARD_REGION_SIZE);
+        guard_region = NULL;
+    } else {
+        free(__wasm_memory);
+    }
     __wasm_memory = NULL;
     __wasm_memory_size_pages = 0;
+    __wasm_memory_size_bytes = 0;
+    __wasm_memory_max_pages = WASM_MAX_PAGES;
+    __wasm_memories_cleanup();
+    __wasm_tables_cleanup();
+    __wasm_func_sigs_cleanup();
+    __wasm_segments_cleanup();
+    __wasm_gc_cleanup();
+    free(__wasm_instance->globals);
+    __wasm_instance->globals = NULL;
 }
 
 /* Table support */
 #define WASM_MAX_TABLE_SIZE 65536
 
-/* Exported table pointer - accessed by compiled WASM code */
+/* Growing a table never needs more bytes than size_t can count */
+_Static_assert(WASM_MAX_TABLE_SIZE <= SIZE_MAX / sizeof(void *),
+               "table allocations may overflow");
+
+#ifdef WAQ_INSTANCE_MODE
+
+#define __wasm_table (__wasm_instance->table)
+#define __wasm_table_size (__wasm_instance->table_size)
+#define __wasm_table_sigs (__wasm_instance->table_sigs)
+
+#else
+
+/* Exported table 0 pointer - accessed by compiled WASM code */
 void **__wasm_table = NULL;
 uint32_t __wasm_table_size = 0;
 
-int32_t __wasm_table_grow(int32_t delta, void *init_val) {
-    if (delta < 0) return -1;
+/*
+ * Signature tags, parallel to __wasm_table. Each entry holds the canonical
+ * type id of the function stored in the slot (-1 for null or unknown).
+ * call_indirect compares it against the expected type at the call site.
+ */
+int32_t *__wasm_table_sigs = NULL;
+
+#endif /* WAQ_INSTANCE_MODE */
+
+static WasmTable *__wasm_table_at(int32_t table_idx) {
+    if (table_idx < 0 || table_idx >= WASM_MAX_TABLES) {
+        fprintf(stderr, "wasm: table index %d out of range\n", table_idx);
+        abort();
+    }
+    return &__wasm_tables[table_idx];
+}
+
+/* Refresh the table 0 globals after its storage changed */
+static void __wasm_table_sync(int32_t table_idx) {
+    if (table_idx != 0) return;
+    __wasm_table = __wasm_tables[0].elems;
+    __wasm_table_sigs = __wasm_tables[0].sigs;
+    __wasm_table_size = __wasm_tables[0].size;
+}
+
+static void __wasm_tables_cleanup(void) {
+    for (int i = 0; i < WASM_MAX_TABLES; i++) {
+        free(__wasm_tables[i].elems);
+        free(__wasm_tables[i].sigs);
+        memset(&__wasm_tables[i], 0, sizeof(WasmTable));
+    }
+    __wasm_table_sync(0);
+}
+
+void __wasm_trap_table_out_of_bounds(void) {
+    fprintf(stderr, "wasm trap: out of bounds table access\n");
+    abort();
+}
+
+void __wasm_trap_undefined_element(void) {
+    fprintf(stderr, "wasm trap: undefined element\n");
+    abort();
+}
+
+void __wasm_trap_uninitialized_element(void) {
+    fprintf(stderr, "wasm trap: uninitialized element\n");
+    abort();
+}
+
+void __wasm_trap_indirect_call_mismatch(void) {
+    fprintf(stderr, "wasm trap: indirect call type mismatch\n");
+    abort();
+}
+
+/*
+ * Function signature registry: function pointer -> canonical type id.
+ * Filled by __wasm_memory_init for every function that may be stored in a
+ * table, so tags can be derived when a funcref is written to a slot.
+ * Open addressing with linear probing; capacity is a power of two.
+ */
+struct WasmFuncSig {
+    void *func;
+    int32_t sig;
+};
+
+static size_t func_sig_slot(void *func, size_t capacity) {
+    uintptr_t h = (uintptr_t)func;
+    h ^= h >> 17;
+    h *= 0x9E3779B97F4A7C15ULL;
+    return (size_t)(h >> 7) & (capacity - 1);
+}
+
+void __wasm_func_sig_register(void *func, int32_t sig) {
+    if (func == NULL) return;
+
+    if ((func_sigs_count + 1) * 2 > func_sigs_capacity) {
+        size_t new_capacity = func_sigs_capacity ? func_sigs_capacity * 2 : 64;
+        WasmFuncSig *new_sigs = calloc(new_capacity, sizeof(WasmFuncSig));
+        if (new_sigs == NULL) {
+            fprintf(stderr, "wasm: out of memory registering signatures\n");
+            abort();
+        }
+        for (size_t i = 0; i < func_sigs_capacity; i++) {
+            if (func_sigs[i].func == NULL) continue;
+            size_t slot = func_sig_slot(func_sigs[i].func, new_capacity);
+            while (new_sigs[slot].func != NULL) {
+                slot = (slot + 1) & (new_capacity - 1);
+            }
+            new_sigs[slot] = func_sigs[i];
+        }
+        free(func_sigs);
+        func_sigs = new_sigs;
+        func_sigs_capacity = new_capacity;
+    }
+
+    size_t slot = func_sig_slot(func, func_sigs_capacity);
+    while (func_sigs[slot].func != NULL && func_sigs[slot].func != func) {
+        slot = (slot + 1) & (func_sigs_capacity - 1);
+    }
+    if (func_sigs[slot].func == NULL) func_sigs_count++;
+    func_sigs[slot].func = func;
+    func_sigs[slot].sig = sig;
+}
+
+static int32_t func_sig_lookup(void *func) {
+    if (func == NULL || func_sigs_capacity == 0) return -1;
+    size_t slot = func_sig_slot(func, func_sigs_capacity);
+    while (func_sigs[slot].func != NULL) {
+        if (func_sigs[slot].func == func) return func_sigs[slot].sig;
+        slot = (slot + 1) & (func_sigs_capacity - 1);
+    }
+    return -1;
+}
+
+static void __wasm_func_sigs_cleanup(void) {
+    free(func_sigs);
+    func_sigs = NULL;
+    func_sigs_capacity = 0;
+    func_sigs_count = 0;
+}
+
+/* Set a table's declared maximum; called by __wasm_memory_init */
+void __wasm_table_set_max(int32_t table_idx, int32_t max) {
+    WasmTable *table = __wasm_table_at(table_idx);
+    table->max = (uint32_t)max;
+    table->has_max = 1;
+}
 
-    uint32_t old_size = __wasm_table_size;
+int32_t __wasm_table_grow(int32_t table_idx, void *init_val, int32_t delta) {
+    WasmTable *table = __wasm_table_at(table_idx);
+    uint32_t old_size = table->size;
     uint32_t delta_u = (uint32_t)delta;
+    uint32_t max = WASM_MAX_TABLE_SIZE;
+    if (table->has_max && table->max < max) max = table->max;
 
     /* Check for overflow BEFORE addition */
-    if (delta_u > WASM_MAX_TABLE_SIZE - old_size) return -1;
+    if (old_size > max || delta_u > max - old_size) return -1;
 
+    /* At most WASM_MAX_TABLE_SIZE, so the allocation sizes can't overflow */
     uint32_t new_size = old_size + delta_u;
+    if (delta_u == 0) return (int32_t)old_size;
 
-    /* Redundant check, but defensive */
-    if (new_size > WASM_MAX_TABLE_SIZE) return -1;
-
-    /* Check for allocation size overflow */
-    if (new_size > SIZE_MAX / sizeof(void *)) return -1;
+    void **new_elems = realloc(table->elems, new_size * sizeof(void *));
+    if (new_elems == NULL) return -1;
+    table->elems = new_elems;
 
-    void **new_table = realloc(__wasm_table, new_size * sizeof(void *));
-    if (new_table == NULL && new_size > 0) return -1;
+    int32_t *new_sigs = realloc(table->sigs, new_size * sizeof(int32_t));
+    if (new_sigs == NULL) {
+        __wasm_table_sync(table_idx);
+        return -1;
+    }
+    table->sigs = new_sigs;
 
     /* Initialize new entries */
+    int32_t init_sig = func_sig_lookup(init_val);
     for (uint32_t i = old_size; i < new_size; i++) {
-        new_table[i] = init_val;
+        new_elems[i] = init_val;
+        new_sigs[i] = init_sig;
     }
 
-    __wasm_table = new_table;
-    __wasm_table_size = new_size;
+    table->size = new_size;
+    __wasm_table_sync(table_idx);
 
     return (int32_t)old_size;
 }
 
-int32_t __wasm_table_size_op(void) {
-    return (int32_t)__wasm_table_size;
+int32_t __wasm_table_size_op(int32_t table_idx) {
+    return (int32_t)__wasm_table_at(table_idx)->size;
 }
 
-void *__wasm_table_get(int32_t idx) {
-    if (idx < 0 || (uint32_t)idx >= __wasm_table_size) {
-        __wasm_trap_out_of_bounds();
+void *__wasm_table_get(int32_t table_idx, int32_t idx) {
+    WasmTable *table = __wasm_table_at(table_idx);
+    if ((uint32_t)idx >= table->size) {
+        __wasm_trap_table_out_of_bounds();
     }
-    return __wasm_table[idx];
+    return table->elems[(uint32_t)idx];
 }
 
-void __wasm_table_set(int32_t idx, void *val) {
-    if (idx < 0 || (uint32_t)idx >= __wasm_table_size) {
-        __wasm_trap_out_of_bounds();
+void __wasm_table_set(int32_t table_idx, int32_t idx, void *val) {
+    WasmTable *table = __wasm_table_at(table_idx);
+    if ((uint32_t)idx >= table->size) {
+        __wasm_trap_table_out_of_bounds();
+    }
+    table->elems[(uint32_t)idx] = val;
+    table->sigs[(uint32_t)idx] = func_sig_lookup(val);
+}
+
+/* Entries and signature tags of a table, for call_indirect */
+void **__wasm_table_elems(int32_t table_idx) {
+    return __wasm_table_at(table_idx)->elems;
+}
+
+int32_t *__wasm_table_sigs_idx(int32_t table_idx) {
+    return __wasm_table_at(table_idx)->sigs;
+}
+
+/* Check that `len` entries at `offset` are within a table */
+static void __wasm_check_table_range(WasmTable *table, int32_t offset,
+                                     int32_t len) {
+    if ((uint64_t)(uint32_t)offset + (uint32_t)len > table->size) {
+        __wasm_trap_table_out_of_bounds();
     }
-    __wasm_table[idx] = val;
 }
 
 /* ============================================================================
@@ -419,14 +870,20 @@ typedef struct WasmExceptionFrame {
     struct WasmExceptionFrame *prev;
     WasmException exception;
     int caught;
+    WasmInstance *instance;  /* Instance running when the handler was pushed */
+    WasmGCFrame *gc_frames;  /* Its shadow stack top (see GARBAGE COLLECTION) */
 } WasmExceptionFrame;
 
 /* Thread-local exception handler stack */
 static __thread WasmExceptionFrame *__wasm_exception_stack = NULL;
 static __thread WasmException __wasm_current_exception;
 
-/* Push a new exception handler frame */
-int32_t __wasm_push_exception_handler(void) {
+/*
+ * Push a new exception handler frame and return its jump buffer.
+ * The compiled try block calls setjmp() on it in its own stack frame, so the
+ * frame is still live when a throw longjmps back to it.
+ */
+void *__wasm_push_exception_handler(void) {
     /* Allocate frame on heap (could optimize with stack allocation) */
     WasmExceptionFrame *frame = malloc(sizeof(WasmExceptionFrame));
     if (!frame) {
@@ -435,15 +892,10 @@ int32_t __wasm_push_exception_handler(void) {
     }
     frame->prev = __wasm_exception_stack;
     frame->caught = 0;
+    frame->instance = __wasm_instance;
+    frame->gc_frames = __wasm_gc_frames;
     __wasm_exception_stack = frame;
-
-    /* Return result of setjmp (0 = normal, non-zero = exception caught) */
-    if (setjmp(frame->env) != 0) {
-        /* Exception was caught - copy exception data */
-        __wasm_current_exception = frame->exception;
-        return 1;
-    }
-    return 0;
+    return frame->env;
 }
 
 /* Pop the current exception handler */
@@ -455,66 +907,86 @@ void __wasm_pop_exception_handler(void) {
     }
 }
 
-/* Throw an exception with the given tag */
-void __wasm_throw(int32_t tag_index) {
-    if (!__wasm_exception_stack) {
-        fprintf(stderr, "wasm trap: uncaught exception (tag %d)\n", tag_index);
+/* Take the exception delivered to the top handler, and pop the handler */
+void __wasm_begin_catch(void) {
+    WasmExceptionFrame *frame = __wasm_exception_stack;
+    if (!frame || !frame->caught) {
+        fprintf(stderr, "wasm trap: no exception to catch\n");
         abort();
     }
-
+    __wasm_current_exception = frame->exception;
+    __wasm_exception_stack = frame->prev;
+    /* Leave any instance entered since, and drop the shadow stack frames of
+       the functions that were unwound */
+    __wasm_instance = frame->instance;
+    __wasm_gc_frames = frame->gc_frames;
+    free(frame);
+}
+
+/* Deliver the current exception to the top handler */
+static void __wasm_throw_current(void) __attribute__((noreturn));
+static void __wasm_throw_current(void) {
     WasmExceptionFrame *frame = __wasm_exception_stack;
-    frame->exception.tag_index = tag_index;
-    frame->exception.payload_size = 0;
+    if (!frame) {
+        fprintf(stderr, "wasm trap: uncaught exception (tag %u)\n",
+                __wasm_current_exception.tag_index);
+        abort();
+    }
+    frame->exception = __wasm_current_exception;
     frame->caught = 1;
-
     longjmp(frame->env, 1);
 }
 
-/* Throw an exception with payload */
-void __wasm_throw_with_payload(int32_t tag_index, void *payload, size_t size) {
-    if (!__wasm_exception_stack) {
-        fprintf(stderr, "wasm trap: uncaught exception (tag %d)\n", tag_index);
-        abort();
-    }
+/* Throw an exception with the given tag */
+void __wasm_throw(int32_t tag_index) {
+    __wasm_current_exception.tag_index = (uint32_t)tag_index;
+    __wasm_current_exception.payload_size = 0;
+    __wasm_throw_current();
+}
 
-    WasmExceptionFrame *frame = __wasm_exception_stack;
-    frame->exception.tag_index = tag_index;
+/* Throw an exception with payload (one 8-byte slot per tag parameter) */
+void __wasm_throw_with_payload(int32_t tag_index, void *payload, size_t size) {
     if (size > WASM_EXCEPTION_PAYLOAD_MAX) {
-        size = WASM_EXCEPTION_PAYLOAD_MAX;
+        fprintf(stderr, "wasm trap: exception payload too large (%zu bytes)\n", size);
+        abort();
     }
+    __wasm_current_exception.tag_index = (uint32_t)tag_index;
     if (payload && size > 0) {
-        memcpy(frame->exception.payload, payload, size);
+        memcpy(__wasm_current_exception.payload, payload, size);
     }
-    frame->exception.payload_size = size;
-    frame->caught = 1;
-
-    longjmp(frame->env, 1);
+    __wasm_current_exception.payload_size = size;
+    __wasm_throw_current();
 }
 
-/* Rethrow the current exception */
+/*
+ * Rethrow the current exception to the enclosing handler.
+ * Called from a catch clause (whose handler is already popped) and when no
+ * clause of a try matches.
+ */
 void __wasm_rethrow(void) {
-    if (!__wasm_exception_stack) {
-        fprintf(stderr, "wasm trap: rethrow without exception\n");
-        abort();
-    }
+    __wasm_throw_current();
+}
 
-    /* Find the previous handler and throw to it */
-    WasmExceptionFrame *current = __wasm_exception_stack;
-    WasmException exc = current->exception;
+static WasmException *__wasm_exn_alloc(void);
 
-    /* Pop current handler */
-    __wasm_exception_stack = current->prev;
-    free(current);
+/*
+ * Capture the current exception as an exnref (catch_ref, catch_all_ref).
+ * Each capture is a separate copy that stays valid after later throws, in a
+ * GC object that is collected once no longer referenced.
+ */
+void *__wasm_exn_capture(void) {
+    WasmException *exn = __wasm_exn_alloc();
+    *exn = __wasm_current_exception;
+    return exn;
+}
 
-    if (!__wasm_exception_stack) {
-        fprintf(stderr, "wasm trap: uncaught rethrown exception (tag %u)\n", exc.tag_index);
-        abort();
+/* Throw a captured exception again (throw_ref) */
+void __wasm_throw_ref(void *exn) {
+    if (!exn) {
+        __wasm_trap_null_reference();
     }
-
-    /* Throw to the outer handler */
-    __wasm_exception_stack->exception = exc;
-    __wasm_exception_stack->caught = 1;
-    longjmp(__wasm_exception_stack->env, 1);
+    __wasm_current_exception = *(WasmException *)exn;
+    __wasm_throw_current();
 }
 
 /* Get the current exception reference */
@@ -535,80 +1007,483 @@ void *__wasm_get_exception_payload(void) {
 /* ============================================================================
  * GARBAGE COLLECTION (WASM GC)
  * ============================================================================
- * Simple bump allocator with no collection (for now).
- * TODO: Integrate with Boehm GC for real garbage collection.
+ * Non-moving mark-sweep collector over segregated size classes. Small objects
+ * are carved from 64 KiB chunks of equal-sized cells (one size class per
+ * chunk); larger objects get a chunk of their own. A reference points just
+ * past the object header and stays valid until the object is collected.
+ *
+ * Roots are the shadow stack frames pushed by compiled functions (reference
+ * locals plus operand values that are live across a call or allocation),
+ * registered globals, the table and the exception being delivered. Objects
+ * are traced with the per-type layouts registered by __wasm_memory_init;
+ * captured exceptions (exnrefs) are objects too. Exception payloads are
+ * traced with the per-tag layouts registered alongside, so only the slots of
+ * reference params are marked.
+ *
+ * Each instance has a heap of its own, which only one thread may use at a
+ * time.
  */
 
-/* GC heap */
-#define WASM_GC_HEAP_SIZE (64 * 1024 * 1024)  /* 64MB initial heap */
+#define WASM_GC_CHUNK_SIZE ((size_t)64 * 1024)
+#define WASM_GC_CHUNK_HEADER 64
+
+/* Collect once this many bytes are allocated (at least twice the live size) */
+#define WASM_GC_MIN_THRESHOLD ((size_t)4 * 1024 * 1024)
 
-static uint8_t *__wasm_gc_heap = NULL;
-static size_t __wasm_gc_heap_ptr = 0;
-static size_t __wasm_gc_heap_size = 0;
+/* Object header flags */
+#define WASM_GC_ALLOCATED 1u
+#define WASM_GC_MARKED 2u
+
+/* Composite kinds passed to __wasm_gc_register_type */
+#define WASM_GC_KIND_STRUCT 0
+#define WASM_GC_KIND_ARRAY 1
+
+/* Type index in the header of a captured exception */
+#define WASM_GC_EXN_TYPE UINT32_MAX
 
 /* Object header for GC objects */
-typedef struct {
+struct WasmGCHeader {
     uint32_t type_index;  /* Type index for runtime type checking */
-    uint32_t flags;       /* GC flags (mark bit, etc.) */
-} WasmGCHeader;
+    uint32_t flags;       /* WASM_GC_ALLOCATED, WASM_GC_MARKED */
+};
 
 #define WASM_GC_HEADER_SIZE sizeof(WasmGCHeader)
 
-/* Initialize GC heap */
-void __wasm_gc_init(void) {
-    if (!__wasm_gc_heap) {
-        __wasm_gc_heap = malloc(WASM_GC_HEAP_SIZE);
-        if (!__wasm_gc_heap) {
-            fprintf(stderr, "wasm trap: failed to initialize GC heap\n");
-            abort();
+/* Array header: type_index (4) + flags (4) + length (4) + padding (4) = 16 bytes */
+typedef struct {
+    uint32_t type_index;
+    uint32_t flags;
+    uint32_t length;
+    uint32_t _padding;
+} WasmArrayHeader;
+
+#define WASM_ARRAY_HEADER_SIZE sizeof(WasmArrayHeader)
+
+/* Chunk header; cells start WASM_GC_CHUNK_HEADER bytes into the chunk */
+struct WasmGCChunk {
+    size_t cell_size;   /* 0 for a large object chunk */
+    size_t cell_count;
+    size_t size;        /* Bytes allocated for the chunk */
+};
+
+/* WASM_GC_NUM_CLASSES of them, each with its own free list */
+static const size_t __wasm_gc_size_classes[WASM_GC_NUM_CLASSES] = {
+    16, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536, 2048,
+};
+
+/* Free cells have cleared flags and are linked through their first field */
+struct WasmGCFreeCell {
+    WasmGCHeader header;
+    struct WasmGCFreeCell *next;
+};
+
+/* Per-type field layouts, indexed by type index */
+struct WasmGCTypeLayout {
+    int32_t kind;          /* WASM_GC_KIND_*, or -1 if not registered */
+    int32_t count;         /* Number of fields (1 for arrays) */
+    const uint8_t *refs;   /* Nonzero for each field holding a reference */
+    int32_t supertype;     /* Declared supertype, or -1 */
+};
+
+/* Per-tag payload layouts, indexed by tag index; only tags with reference
+   params are registered */
+struct WasmGCTagLayout {
+    int32_t count;         /* Number of params */
+    const uint8_t *refs;   /* Nonzero for each param holding a reference */
+};
+
+/* Shadow stack frame, allocated on the native stack by compiled functions */
+struct WasmGCFrame {
+    struct WasmGCFrame *prev;
+    uint64_t count;
+    int64_t slots[];
+};
+
+static void __wasm_gc_out_of_memory(void) __attribute__((noreturn));
+static void __wasm_gc_out_of_memory(void) {
+    fprintf(stderr, "wasm trap: GC heap exhausted\n");
+    abort();
+}
+
+/* Grow a runtime-owned array so it can hold `needed` items */
+static void *__wasm_gc_reserve(void *items, size_t *capacity, size_t needed,
+                               size_t item_size) {
+    if (needed <= *capacity) return items;
+    size_t new_capacity = *capacity ? *capacity * 2 : 64;
+    while (new_capacity < needed) new_capacity *= 2;
+    void *new_items = realloc(items, new_capacity * item_size);
+    if (!new_items) __wasm_gc_out_of_memory();
+    *capacity = new_capacity;
+    return new_items;
+}
+
+/* Register the field layout and supertype of a struct or array type */
+void __wasm_gc_register_type(int32_t type_idx, int32_t kind, int32_t count,
+                             const uint8_t *refs, int32_t supertype) {
+    if (type_idx < 0) return;
+    size_t needed = (size_t)type_idx + 1;
+    if (needed > __wasm_gc_type_count) {
+        WasmGCTypeLayout *types = realloc(__wasm_gc_types,
+                                          needed * sizeof(WasmGCTypeLayout));
+        if (!types) __wasm_gc_out_of_memory();
+        for (size_t i = __wasm_gc_type_count; i < needed; i++) {
+            types[i].kind = -1;
+            types[i].count = 0;
+            types[i].refs = NULL;
+            types[i].supertype = -1;
+        }
+        __wasm_gc_types = types;
+        __wasm_gc_type_count = needed;
+    }
+    __wasm_gc_types[type_idx].kind = kind;
+    __wasm_gc_types[type_idx].count = count;
+    __wasm_gc_types[type_idx].refs = refs;
+    __wasm_gc_types[type_idx].supertype = supertype;
+}
+
+/* Register which params of an exception tag hold references */
+void __wasm_gc_register_tag(int32_t tag_idx, int32_t count, const uint8_t *refs) {
+    if (tag_idx < 0) return;
+    size_t needed = (size_t)tag_idx + 1;
+    if (needed > __wasm_gc_tag_count) {
+        WasmGCTagLayout *tags = realloc(__wasm_gc_tags,
+                                        needed * sizeof(WasmGCTagLayout));
+        if (!tags) __wasm_gc_out_of_memory();
+        for (size_t i = __wasm_gc_tag_count; i < needed; i++) {
+            tags[i].count = 0;
+            tags[i].refs = NULL;
         }
-        __wasm_gc_heap_ptr = 0;
-        __wasm_gc_heap_size = WASM_GC_HEAP_SIZE;
+        __wasm_gc_tags = tags;
+        __wasm_gc_tag_count = needed;
     }
+    __wasm_gc_tags[tag_idx].count = count;
+    __wasm_gc_tags[tag_idx].refs = refs;
 }
 
-/* Allocate GC-managed memory */
-void *__wasm_gc_alloc(size_t size) {
-    if (!__wasm_gc_heap) {
-        __wasm_gc_init();
+/* Register the address of a global holding a reference */
+void __wasm_gc_add_root(int64_t *root) {
+    __wasm_gc_roots = __wasm_gc_reserve(__wasm_gc_roots, &__wasm_gc_root_capacity,
+                                        __wasm_gc_root_count + 1, sizeof(int64_t *));
+    __wasm_gc_roots[__wasm_gc_root_count++] = root;
+}
+
+/* Link a compiled function's shadow stack frame and clear its slots */
+void __wasm_gc_push_frame(WasmGCFrame *frame, int32_t count) {
+    /* Self tail calls jump back to the entry block with the frame linked */
+    if (frame == __wasm_gc_frames) return;
+    frame->prev = __wasm_gc_frames;
+    frame->count = (uint64_t)count;
+    memset(frame->slots, 0, (size_t)count * sizeof(int64_t));
+    __wasm_gc_frames = frame;
+}
+
+/* Unlink a shadow stack frame when its function returns */
+void __wasm_gc_pop_frame(WasmGCFrame *frame) {
+    __wasm_gc_frames = frame->prev;
+}
+
+/* Index of the first chunk at or above `addr` */
+static size_t __wasm_gc_chunk_position(uintptr_t addr) {
+    size_t lo = 0;
+    size_t hi = __wasm_gc_chunk_count;
+    while (lo < hi) {
+        size_t mid = lo + (hi - lo) / 2;
+        if ((uintptr_t)__wasm_gc_chunks[mid] < addr) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
     }
+    return lo;
+}
 
-    /* Align to 8 bytes */
-    size = (size + 7) & ~7;
+static WasmGCChunk *__wasm_gc_new_chunk(size_t size) {
+    WasmGCChunk *chunk = aligned_alloc(WASM_GC_CHUNK_SIZE, size);
+    if (!chunk) __wasm_gc_out_of_memory();
+    chunk->size = size;
 
-    if (__wasm_gc_heap_ptr + size > __wasm_gc_heap_size) {
-        /* Out of memory - try to expand heap */
-        size_t new_size = __wasm_gc_heap_size * 2;
-        uint8_t *new_heap = realloc(__wasm_gc_heap, new_size);
-        if (!new_heap) {
-            fprintf(stderr, "wasm trap: GC heap exhausted\n");
-            abort();
+    __wasm_gc_chunks = __wasm_gc_reserve(__wasm_gc_chunks, &__wasm_gc_chunk_capacity,
+                                         __wasm_gc_chunk_count + 1,
+                                         sizeof(WasmGCChunk *));
+    size_t pos = __wasm_gc_chunk_position((uintptr_t)chunk);
+    memmove(&__wasm_gc_chunks[pos + 1], &__wasm_gc_chunks[pos],
+            (__wasm_gc_chunk_count - pos) * sizeof(WasmGCChunk *));
+    __wasm_gc_chunks[pos] = chunk;
+    __wasm_gc_chunk_count++;
+    return chunk;
+}
+
+static void __wasm_gc_release_chunk(size_t pos) {
+    free(__wasm_gc_chunks[pos]);
+    __wasm_gc_chunk_count--;
+    memmove(&__wasm_gc_chunks[pos], &__wasm_gc_chunks[pos + 1],
+            (__wasm_gc_chunk_count - pos) * sizeof(WasmGCChunk *));
+}
+
+static uint8_t *__wasm_gc_chunk_cells(WasmGCChunk *chunk) {
+    return (uint8_t *)chunk + WASM_GC_CHUNK_HEADER;
+}
+
+/*
+ * Header of the live object `ref` points to, or NULL if it is null, an i31
+ * or anything else that is not a GC object (a function or host pointer).
+ */
+static WasmGCHeader *__wasm_gc_object(int64_t ref) {
+    uintptr_t addr = (uintptr_t)ref;
+    if (addr == 0 || (addr & 7) != 0) return NULL;
+
+    uintptr_t base = addr & ~(uintptr_t)(WASM_GC_CHUNK_SIZE - 1);
+    size_t pos = __wasm_gc_chunk_position(base);
+    if (pos == __wasm_gc_chunk_count || (uintptr_t)__wasm_gc_chunks[pos] != base) {
+        return NULL;
+    }
+    WasmGCChunk *chunk = __wasm_gc_chunks[pos];
+
+    uintptr_t cells = (uintptr_t)__wasm_gc_chunk_cells(chunk);
+    uintptr_t cell = addr - WASM_GC_HEADER_SIZE;
+    if (addr < cells + WASM_GC_HEADER_SIZE) return NULL;
+    if (chunk->cell_size == 0) {
+        if (cell != cells) return NULL;
+    } else {
+        size_t offset = cell - cells;
+        if (offset % chunk->cell_size != 0) return NULL;
+        if (offset / chunk->cell_size >= chunk->cell_count) return NULL;
+    }
+
+    WasmGCHeader *header = (WasmGCHeader *)cell;
+    return (header->flags & WASM_GC_ALLOCATED) ? header : NULL;
+}
+
+static void __wasm_gc_mark(int64_t ref) {
+    WasmGCHeader *header = __wasm_gc_object(ref);
+    if (!header || (header->flags & WASM_GC_MARKED)) return;
+    header->flags |= WASM_GC_MARKED;
+    __wasm_gc_mark_stack = __wasm_gc_reserve(
+        __wasm_gc_mark_stack, &__wasm_gc_mark_capacity, __wasm_gc_mark_count + 1,
+        sizeof(WasmGCHeader *));
+    __wasm_gc_mark_stack[__wasm_gc_mark_count++] = header;
+}
+
+/* Mark the objects referenced by the payload slots of the tag's reference
+   params */
+static void __wasm_gc_mark_exception(const WasmException *exn) {
+    if (exn->tag_index >= __wasm_gc_tag_count) return;
+    WasmGCTagLayout *layout = &__wasm_gc_tags[exn->tag_index];
+    for (int32_t i = 0; i < layout->count; i++) {
+        if ((size_t)(i + 1) * 8 > exn->payload_size) break;
+        if (!layout->refs[i]) continue;
+        int64_t value;
+        memcpy(&value, &exn->payload[(size_t)i * 8], sizeof(value));
+        __wasm_gc_mark(value);
+    }
+}
+
+/* Mark the objects referenced by the fields of a marked object */
+static void __wasm_gc_trace(WasmGCHeader *header) {
+    if (header->type_index == WASM_GC_EXN_TYPE) {
+        __wasm_gc_mark_exception((const WasmException *)(header + 1));
+        return;
+    }
+    if (header->type_index >= __wasm_gc_type_count) return;
+    WasmGCTypeLayout *layout = &__wasm_gc_types[header->type_index];
+    int64_t *fields = (int64_t *)(header + 1);
+
+    if (layout->kind == WASM_GC_KIND_STRUCT) {
+        for (int32_t i = 0; i < layout->count; i++) {
+            if (layout->refs[i]) __wasm_gc_mark(fields[i]);
+        }
+    } else if (layout->kind == WASM_GC_KIND_ARRAY && layout->refs[0]) {
+        WasmArrayHeader *array = (WasmArrayHeader *)header;
+        int64_t *elems = (int64_t *)(array + 1);
+        for (uint32_t i = 0; i < array->length; i++) {
+            __wasm_gc_mark(elems[i]);
+        }
+    }
+}
+
+static void __wasm_gc_mark_roots(void) {
+    for (WasmGCFrame *frame = __wasm_gc_frames; frame; frame = frame->prev) {
+        for (uint64_t i = 0; i < frame->count; i++) {
+            __wasm_gc_mark(frame->slots[i]);
+        }
+    }
+    for (size_t i = 0; i < __wasm_gc_root_count; i++) {
+        __wasm_gc_mark(*__wasm_gc_roots[i]);
+    }
+    for (int t = 0; t < WASM_MAX_TABLES; t++) {
+        for (uint32_t i = 0; i < __wasm_tables[t].size; i++) {
+            __wasm_gc_mark((int64_t)(uintptr_t)__wasm_tables[t].elems[i]);
+        }
+    }
+    __wasm_gc_mark_exception(&__wasm_current_exception);
+}
+
+/* Free unmarked objects, rebuild the free lists and release empty chunks */
+static void __wasm_gc_sweep(void) {
+    memset(__wasm_gc_free_lists, 0, sizeof(__wasm_gc_free_lists));
+    __wasm_gc_heap_bytes = 0;
+
+    size_t pos = 0;
+    while (pos < __wasm_gc_chunk_count) {
+        WasmGCChunk *chunk = __wasm_gc_chunks[pos];
+        uint8_t *cells = __wasm_gc_chunk_cells(chunk);
+
+        if (chunk->cell_size == 0) {
+            WasmGCHeader *header = (WasmGCHeader *)cells;
+            if (header->flags & WASM_GC_MARKED) {
+                header->flags &= ~WASM_GC_MARKED;
+                __wasm_gc_heap_bytes += chunk->size;
+                pos++;
+            } else {
+                __wasm_gc_release_chunk(pos);
+            }
+            continue;
+        }
+
+        size_t live = 0;
+        for (size_t i = 0; i < chunk->cell_count; i++) {
+            WasmGCHeader *header = (WasmGCHeader *)(cells + i * chunk->cell_size);
+            if (header->flags & WASM_GC_MARKED) {
+                header->flags &= ~WASM_GC_MARKED;
+                live++;
+            } else {
+                header->flags = 0;
+            }
         }
-        __wasm_gc_heap = new_heap;
-        __wasm_gc_heap_size = new_size;
+        if (live == 0) {
+            __wasm_gc_release_chunk(pos);
+            continue;
+        }
+
+        size_t cls = 0;
+        while (__wasm_gc_size_classes[cls] != chunk->cell_size) cls++;
+        for (size_t i = chunk->cell_count; i-- > 0;) {
+            WasmGCFreeCell *cell = (WasmGCFreeCell *)(cells + i * chunk->cell_size);
+            if (cell->header.flags == 0) {
+                cell->next = __wasm_gc_free_lists[cls];
+                __wasm_gc_free_lists[cls] = cell;
+            }
+        }
+        __wasm_gc_heap_bytes += live * chunk->cell_size;
+        pos++;
+    }
+}
+
+/* Run a full collection */
+void __wasm_gc_collect(void) {
+    __wasm_gc_mark_roots();
+    while (__wasm_gc_mark_count > 0) {
+        __wasm_gc_trace(__wasm_gc_mark_stack[--__wasm_gc_mark_count]);
+    }
+    __wasm_gc_sweep();
+
+    __wasm_gc_threshold = __wasm_gc_heap_bytes * 2;
+    if (__wasm_gc_threshold < WASM_GC_MIN_THRESHOLD) {
+        __wasm_gc_threshold = WASM_GC_MIN_THRESHOLD;
+    }
+}
+
+static void __wasm_gc_cleanup(void) {
+    for (size_t i = 0; i < __wasm_gc_chunk_count; i++) {
+        free(__wasm_gc_chunks[i]);
+    }
+    free(__wasm_gc_chunks);
+    free(__wasm_gc_types);
+    free(__wasm_gc_tags);
+    free(__wasm_gc_roots);
+    free(__wasm_gc_mark_stack);
+    memset(__wasm_gc_free_lists, 0, sizeof(__wasm_gc_free_lists));
+    __wasm_gc_chunks = NULL;
+    __wasm_gc_chunk_count = __wasm_gc_chunk_capacity = 0;
+    __wasm_gc_heap_bytes = 0;
+    __wasm_gc_threshold = WASM_GC_MIN_THRESHOLD;
+    __wasm_gc_types = NULL;
+    __wasm_gc_type_count = 0;
+    __wasm_gc_tags = NULL;
+    __wasm_gc_tag_count = 0;
+    __wasm_gc_frames = NULL;
+    __wasm_gc_roots = NULL;
+    __wasm_gc_root_count = __wasm_gc_root_capacity = 0;
+    __wasm_gc_mark_stack = NULL;
+    __wasm_gc_mark_count = __wasm_gc_mark_capacity = 0;
+}
+
+/* Bytes currently held by GC objects (live, or dead but not yet collected) */
+size_t __wasm_gc_heap_size(void) {
+    return __wasm_gc_heap_bytes;
+}
+
+/* Add a fresh chunk of cells to a size class's free list */
+static void __wasm_gc_refill(size_t cls) {
+    size_t cell_size = __wasm_gc_size_classes[cls];
+    WasmGCChunk *chunk = __wasm_gc_new_chunk(WASM_GC_CHUNK_SIZE);
+    chunk->cell_size = cell_size;
+    chunk->cell_count = (WASM_GC_CHUNK_SIZE - WASM_GC_CHUNK_HEADER) / cell_size;
+
+    uint8_t *cells = __wasm_gc_chunk_cells(chunk);
+    for (size_t i = chunk->cell_count; i-- > 0;) {
+        WasmGCFreeCell *cell = (WasmGCFreeCell *)(cells + i * cell_size);
+        cell->header.flags = 0;
+        cell->next = __wasm_gc_free_lists[cls];
+        __wasm_gc_free_lists[cls] = cell;
+    }
+}
+
+/*
+ * Allocate a zeroed GC object of `size` bytes, header included.
+ * Returns the header, marked as allocated. May run a collection first.
+ */
+void *__wasm_gc_alloc(size_t size) {
+    if (__wasm_gc_heap_bytes >= __wasm_gc_threshold) {
+        __wasm_gc_collect();
     }
 
-    void *ptr = __wasm_gc_heap + __wasm_gc_heap_ptr;
-    __wasm_gc_heap_ptr += size;
+    uint8_t *cell;
+    size_t cell_size;
+    size_t cls = 0;
+    while (cls < WASM_GC_NUM_CLASSES && __wasm_gc_size_classes[cls] < size) cls++;
+
+    if (cls == WASM_GC_NUM_CLASSES) {
+        if (size > SIZE_MAX - WASM_GC_CHUNK_HEADER - WASM_GC_CHUNK_SIZE) {
+            __wasm_gc_out_of_memory();
+        }
+        size_t chunk_size = (WASM_GC_CHUNK_HEADER + size + WASM_GC_CHUNK_SIZE - 1)
+                            & ~(WASM_GC_CHUNK_SIZE - 1);
+        WasmGCChunk *chunk = __wasm_gc_new_chunk(chunk_size);
+        chunk->cell_size = 0;
+        chunk->cell_count = 1;
+        cell = __wasm_gc_chunk_cells(chunk);
+        cell_size = size;
+        __wasm_gc_heap_bytes += chunk_size;
+    } else {
+        if (!__wasm_gc_free_lists[cls]) __wasm_gc_refill(cls);
+        WasmGCFreeCell *free_cell = __wasm_gc_free_lists[cls];
+        __wasm_gc_free_lists[cls] = free_cell->next;
+        cell = (uint8_t *)free_cell;
+        cell_size = __wasm_gc_size_classes[cls];
+        __wasm_gc_heap_bytes += cell_size;
+    }
 
-    /* Zero-initialize */
-    memset(ptr, 0, size);
+    memset(cell, 0, cell_size);
+    ((WasmGCHeader *)cell)->flags = WASM_GC_ALLOCATED;
+    return cell;
+}
 
-    return ptr;
+/* Allocate the object of a captured exception */
+static WasmException *__wasm_exn_alloc(void) {
+    WasmGCHeader *header = __wasm_gc_alloc(WASM_GC_HEADER_SIZE + sizeof(WasmException));
+    header->type_index = WASM_GC_EXN_TYPE;
+    return (WasmException *)(header + 1);
 }
 
 /* Allocate a struct */
 void *__wasm_struct_new(int32_t type_idx, int32_t num_fields) {
-    size_t size = WASM_GC_HEADER_SIZE + (size_t)num_fields * 8;
-    void *obj = __wasm_gc_alloc(size);
-
-    /* Set header */
-    WasmGCHeader *header = (WasmGCHeader *)obj;
+    size_t size = WASM_GC_HEADER_SIZE + (size_t)(uint32_t)num_fields * 8;
+    WasmGCHeader *header = __wasm_gc_alloc(size);
     header->type_index = (uint32_t)type_idx;
-    header->flags = 0;
 
     /* Return pointer past header (to field data) */
-    return (uint8_t *)obj + WASM_GC_HEADER_SIZE;
+    return header + 1;
 }
 
 /* Allocate a struct with default (zero) values */
@@ -617,33 +1492,17 @@ void *__wasm_struct_new_default(int32_t type_idx, int32_t num_fields) {
     return __wasm_struct_new(type_idx, num_fields);
 }
 
-/* Array header: type_index (4) + flags (4) + length (4) + padding (4) = 16 bytes */
-typedef struct {
-    uint32_t type_index;
-    uint32_t flags;
-    uint32_t length;
-    uint32_t _padding;
-} WasmArrayHeader;
-
-#define WASM_ARRAY_HEADER_SIZE sizeof(WasmArrayHeader)
-
 /* Allocate an array */
 void *__wasm_array_new(int32_t type_idx, int32_t length, int64_t init_value) {
     size_t elem_size = 8;  /* All elements stored as 64-bit for simplicity */
-    size_t data_size = (size_t)length * elem_size;
-    size_t total_size = WASM_ARRAY_HEADER_SIZE + data_size;
-
-    void *obj = __wasm_gc_alloc(total_size);
-
-    /* Set header */
-    WasmArrayHeader *header = (WasmArrayHeader *)obj;
+    size_t data_size = (size_t)(uint32_t)length * elem_size;
+    WasmArrayHeader *header = __wasm_gc_alloc(WASM_ARRAY_HEADER_SIZE + data_size);
     header->type_index = (uint32_t)type_idx;
-    header->flags = 0;
     header->length = (uint32_t)length;
 
     /* Initialize elements */
-    int64_t *data = (int64_t *)((uint8_t *)obj + WASM_ARRAY_HEADER_SIZE);
-    for (int32_t i = 0; i < length; i++) {
+    int64_t *data = (int64_t *)(header + 1);
+    for (uint32_t i = 0; i < header->length; i++) {
         data[i] = init_value;
     }
 
@@ -656,8 +1515,54 @@ void *__wasm_array_new_default(int32_t type_idx, int32_t length) {
     return __wasm_array_new(type_idx, length, 0);
 }
 
-/* i31 operations - encode/decode 31-bit integers in pointers */
-/* i31 is encoded as: (value << 1) | 1 */
+static void __wasm_trap_array_out_of_bounds(void) __attribute__((noreturn));
+static void __wasm_trap_array_out_of_bounds(void) {
+    fprintf(stderr, "wasm trap: out of bounds array access\n");
+    abort();
+}
+
+/* Length of a non-null array (traps on null) */
+static uint32_t __wasm_array_length(void *array) {
+    if (!array) __wasm_trap_null_reference();
+    return *(uint32_t *)array;
+}
+
+/* Element slots of an array, which follow its length field */
+static int64_t *__wasm_array_data(void *array) {
+    return (int64_t *)((uint8_t *)array + 8);
+}
+
+/* Check that [offset, offset + count) is within a sequence of `size` items */
+static int __wasm_range_ok(int32_t offset, int32_t count, uint64_t size) {
+    return (uint64_t)(uint32_t)offset + (uint32_t)count <= size;
+}
+
+/* array.fill: set `count` elements starting at `offset` */
+void __wasm_array_fill(void *array, int32_t offset, int64_t value, int32_t count) {
+    uint32_t length = __wasm_array_length(array);
+    if (!__wasm_range_ok(offset, count, length)) __wasm_trap_array_out_of_bounds();
+    int64_t *data = __wasm_array_data(array);
+    for (uint32_t i = 0; i < (uint32_t)count; i++) {
+        data[(uint32_t)offset + i] = value;
+    }
+}
+
+/* array.copy: copy `count` elements, which may overlap */
+void __wasm_array_copy(void *dest, int32_t dest_offset, void *src,
+                       int32_t src_offset, int32_t count) {
+    uint32_t dest_length = __wasm_array_length(dest);
+    uint32_t src_length = __wasm_array_length(src);
+    if (!__wasm_range_ok(dest_offset, count, dest_length) ||
+        !__wasm_range_ok(src_offset, count, src_length)) {
+        __wasm_trap_array_out_of_bounds();
+    }
+    memmove(__wasm_array_data(dest) + (uint32_t)dest_offset,
+            __wasm_array_data(src) + (uint32_t)src_offset,
+            (size_t)(uint32_t)count * sizeof(int64_t));
+}
+
+/* i31 operations - encode/decode 31-bit integers in pointers */
+/* i31 is encoded as: (value << 1) | 1 */
 
 int64_t __wasm_ref_i31(int32_t value) {
     /* Truncate to 31 bits, shift and set tag */
@@ -682,54 +1587,96 @@ int32_t __wasm_i31_get_u(int64_t ref) {
 
 /* Reference type testing and casting */
 
+/*
+ * Heap types are passed as their s33 encoding: a canonical type index, or
+ * the negative code of an abstract heap type.
+ */
+#define WASM_HEAP_NOEXN (-0x0C)
+#define WASM_HEAP_NOFUNC (-0x0D)
+#define WASM_HEAP_NOEXTERN (-0x0E)
+#define WASM_HEAP_NONE (-0x0F)
+#define WASM_HEAP_FUNC (-0x10)
+#define WASM_HEAP_EXTERN (-0x11)
+#define WASM_HEAP_ANY (-0x12)
+#define WASM_HEAP_EQ (-0x13)
+#define WASM_HEAP_I31 (-0x14)
+#define WASM_HEAP_STRUCT (-0x15)
+#define WASM_HEAP_ARRAY (-0x16)
+#define WASM_HEAP_EXN (-0x17)
+
 /* Check if a reference is an i31 */
 static int __wasm_is_i31(int64_t ref) {
     return (ref & 1) != 0;
 }
 
-/* Get header from object pointer */
-static WasmGCHeader *__wasm_get_header(void *obj) {
-    if (!obj) return NULL;
-    return (WasmGCHeader *)((uint8_t *)obj - WASM_GC_HEADER_SIZE);
+/* Check if an object's type is `type_idx` or one of its declared subtypes */
+static int __wasm_gc_is_subtype(uint32_t type_index, int32_t type_idx) {
+    int64_t current = type_index;
+    for (size_t depth = 0; depth <= __wasm_gc_type_count; depth++) {
+        if (current == type_idx) return 1;
+        if (current < 0 || (uint64_t)current >= __wasm_gc_type_count) return 0;
+        current = __wasm_gc_types[current].supertype;
+    }
+    return 0;
 }
 
-/* Test if reference is of the given type */
-int32_t __wasm_ref_test(int64_t ref, int32_t type_idx) {
-    if (ref == 0) return 0;  /* null fails test */
-    if (__wasm_is_i31(ref)) return 0;  /* i31 is not a struct/array */
+/* Kind of a GC object, or -1 if its type was not registered */
+static int32_t __wasm_gc_kind(WasmGCHeader *header) {
+    if (header->type_index >= __wasm_gc_type_count) return -1;
+    return __wasm_gc_types[header->type_index].kind;
+}
 
-    WasmGCHeader *header = __wasm_get_header((void *)ref);
-    if (!header) return 0;
+/* Test if a non-null reference is of the given heap type */
+int32_t __wasm_ref_test(int64_t ref, int32_t heap_type) {
+    if (ref == 0) return 0;  /* null fails test */
 
-    /* Simple equality check - could be extended for subtyping */
-    return header->type_index == (uint32_t)type_idx ? 1 : 0;
+    WasmGCHeader *header = __wasm_gc_object(ref);
+    switch (heap_type) {
+    case WASM_HEAP_ANY:
+    case WASM_HEAP_FUNC:
+    case WASM_HEAP_EXTERN:
+    case WASM_HEAP_EXN:
+        return 1;
+    case WASM_HEAP_EQ:
+        return __wasm_is_i31(ref) || header != NULL;
+    case WASM_HEAP_I31:
+        return __wasm_is_i31(ref);
+    case WASM_HEAP_STRUCT:
+        return header && __wasm_gc_kind(header) == WASM_GC_KIND_STRUCT;
+    case WASM_HEAP_ARRAY:
+        return header && __wasm_gc_kind(header) == WASM_GC_KIND_ARRAY;
+    default:
+        break;
+    }
+    if (heap_type < 0 || !header) return 0;  /* bottom types hold only null */
+    return __wasm_gc_is_subtype(header->type_index, heap_type);
 }
 
 /* Test if nullable reference is of the given type (null passes) */
-int32_t __wasm_ref_test_null(int64_t ref, int32_t type_idx) {
+int32_t __wasm_ref_test_null(int64_t ref, int32_t heap_type) {
     if (ref == 0) return 1;  /* null passes for nullable types */
-    return __wasm_ref_test(ref, type_idx);
+    return __wasm_ref_test(ref, heap_type);
 }
 
 /* Cast reference to given type (traps on failure) */
-int64_t __wasm_ref_cast(int64_t ref, int32_t type_idx) {
+int64_t __wasm_ref_cast(int64_t ref, int32_t heap_type) {
     if (ref == 0) {
         /* null cast to non-nullable type traps */
         fprintf(stderr, "wasm trap: null reference in ref.cast\n");
         abort();
     }
-    if (!__wasm_ref_test(ref, type_idx)) {
-        fprintf(stderr, "wasm trap: ref.cast failed (expected type %d)\n", type_idx);
+    if (!__wasm_ref_test(ref, heap_type)) {
+        fprintf(stderr, "wasm trap: ref.cast failed (expected type %d)\n", heap_type);
         abort();
     }
     return ref;
 }
 
 /* Cast nullable reference (null is ok) */
-int64_t __wasm_ref_cast_null(int64_t ref, int32_t type_idx) {
+int64_t __wasm_ref_cast_null(int64_t ref, int32_t heap_type) {
     if (ref == 0) return 0;  /* null is ok for nullable */
-    if (!__wasm_ref_test(ref, type_idx)) {
-        fprintf(stderr, "wasm trap: ref.cast failed (expected type %d)\n", type_idx);
+    if (!__wasm_ref_test(ref, heap_type)) {
+        fprintf(stderr, "wasm trap: ref.cast failed (expected type %d)\n", heap_type);
         abort();
     }
     return ref;
@@ -752,56 +1699,118 @@ void __wasm_trap_cast_failure(void) {
  * ============================================================================
  */
 
-/* Memory64 variants */
-int64_t __wasm_memory_size_pages64(int32_t mem_idx) {
-    (void)mem_idx;  /* Currently only single memory supported */
-    return (int64_t)__wasm_memory_size_pages;
+static WasmMemory *__wasm_memory_at(int32_t mem_idx) {
+    if (mem_idx < 1 || mem_idx >= WASM_MAX_MEMORIES) {
+        fprintf(stderr, "wasm: memory index %d out of range\n", mem_idx);
+        abort();
+    }
+    return &__wasm_memories[mem_idx];
 }
 
-int64_t __wasm_memory_grow64(int32_t mem_idx, int64_t delta) {
-    (void)mem_idx;
-    if (delta < 0 || delta > WASM_MAX_PAGES) return -1;
-    return (int64_t)__wasm_memory_grow((int32_t)delta);
+static void __wasm_memories_cleanup(void) {
+    for (int i = 1; i < WASM_MAX_MEMORIES; i++) {
+        free(__wasm_memories[i].data);
+        memset(&__wasm_memories[i], 0, sizeof(WasmMemory));
+    }
+}
+
+/* Set a memory's declared maximum; called by __wasm_memory_init */
+void __wasm_memory_set_max(int32_t mem_idx, int32_t max_pages) {
+    uint32_t max = (uint32_t)max_pages;
+    if (max > WASM_MAX_PAGES) max = WASM_MAX_PAGES;
+    if (mem_idx == 0) {
+        __wasm_memory_max_pages = max;
+        return;
+    }
+    WasmMemory *mem = __wasm_memory_at(mem_idx);
+    mem->max_pages = max;
+    mem->has_max = 1;
 }
 
-/* Multi-memory support - currently just wraps single memory */
 uint8_t *__wasm_memory_base_idx(int32_t mem_idx) {
-    (void)mem_idx;
-    return __wasm_memory;
+    if (mem_idx == 0) return __wasm_memory;
+    return __wasm_memory_at(mem_idx)->data;
 }
 
 int32_t __wasm_memory_size_pages_idx(int32_t mem_idx) {
-    (void)mem_idx;
-    return (int32_t)__wasm_memory_size_pages;
+    if (mem_idx == 0) return (int32_t)__wasm_memory_size_pages;
+    return (int32_t)__wasm_memory_at(mem_idx)->pages;
+}
+
+uint64_t __wasm_memory_size_bytes_idx(int32_t mem_idx) {
+    if (mem_idx == 0) return __wasm_memory_size_bytes;
+    return (uint64_t)__wasm_memory_at(mem_idx)->pages * WASM_PAGE_SIZE;
+}
+
+/* Native address of [addr, addr + len) in a memory, trapping unless the
+ * whole range is in bounds; used by import adapters (see --imports) */
+uint8_t *__wasm_memory_buffer(int32_t mem_idx, uint64_t addr, uint64_t len) {
+    uint64_t size = __wasm_memory_size_bytes_idx(mem_idx);
+    if (addr > size || len > size - addr) __wasm_trap_out_of_bounds();
+    return __wasm_memory_base_idx(mem_idx) + addr;
 }
 
 int32_t __wasm_memory_grow_idx(int32_t mem_idx, int32_t delta) {
-    (void)mem_idx;
-    return __wasm_memory_grow(delta);
+    if (mem_idx == 0) return __wasm_memory_grow(delta);
+    if (delta < 0) return -1;
+
+    WasmMemory *mem = __wasm_memory_at(mem_idx);
+    uint32_t max = mem->has_max ? mem->max_pages : WASM_MAX_PAGES;
+    uint32_t old_pages = mem->pages;
+    if (old_pages > max || (uint32_t)delta > max - old_pages) return -1;
+
+    uint32_t new_pages = old_pages + (uint32_t)delta;
+    size_t new_size = (size_t)new_pages * WASM_PAGE_SIZE;
+    uint8_t *new_memory = realloc(mem->data, new_size);
+    if (new_memory == NULL && new_size > 0) return -1;
+
+    /* Zero-initialize new pages */
+    if (new_memory != NULL && delta > 0) {
+        memset(new_memory + (size_t)old_pages * WASM_PAGE_SIZE, 0,
+               (size_t)delta * WASM_PAGE_SIZE);
+    }
+
+    mem->data = new_memory;
+    mem->pages = new_pages;
+    return (int32_t)old_pages;
 }
 
-/* Bulk memory operations */
-void __wasm_memory_copy(int32_t dest, int32_t src, int32_t len) {
-    if (!__wasm_memory) return;
-    memmove(__wasm_memory + dest, __wasm_memory + src, len);
+/* Memory64 variants */
+int64_t __wasm_memory_size_pages64(int32_t mem_idx) {
+    return (int64_t)__wasm_memory_size_pages_idx(mem_idx);
 }
 
-void __wasm_memory_fill(int32_t dest, int32_t val, int32_t len) {
-    if (!__wasm_memory) return;
-    memset(__wasm_memory + dest, val, len);
+int64_t __wasm_memory_grow64(int32_t mem_idx, int64_t delta) {
+    if (delta < 0 || delta > WASM_MAX_PAGES) return -1;
+    return (int64_t)__wasm_memory_grow_idx(mem_idx, (int32_t)delta);
 }
 
-/* Data segment support */
-typedef struct {
-    uint8_t *data;
-    size_t size;
-    int dropped;
-} WasmDataSegment;
+/* Bulk memory operations; ranges are checked by the compiled code. Addresses
+ * and lengths are zero-extended to 64 bits (memory64 passes them as is) */
+void __wasm_memory_copy_idx(int32_t dest_mem, int32_t src_mem, uint64_t dest,
+                            uint64_t src, uint64_t len) {
+    uint8_t *dest_base = __wasm_memory_base_idx(dest_mem);
+    uint8_t *src_base = __wasm_memory_base_idx(src_mem);
+    if (!dest_base || !src_base) return;
+    memmove(dest_base + dest, src_base + src, (size_t)len);
+}
 
-#define WASM_MAX_DATA_SEGMENTS 256
-static WasmDataSegment __wasm_data_segments[WASM_MAX_DATA_SEGMENTS];
-static int __wasm_data_segment_count = 0;
+void __wasm_memory_copy(uint64_t dest, uint64_t src, uint64_t len) {
+    __wasm_memory_copy_idx(0, 0, dest, src, len);
+}
+
+void __wasm_memory_fill_idx(int32_t mem_idx, uint64_t dest, int32_t val,
+                            uint64_t len) {
+    uint8_t *base = __wasm_memory_base_idx(mem_idx);
+    if (!base) return;
+    memset(base + dest, val, (size_t)len);
+}
 
+void __wasm_memory_fill(uint64_t dest, int32_t val, uint64_t len) {
+    __wasm_memory_fill_idx(0, dest, val, len);
+}
+
+/* Data segment support */
 void __wasm_register_data_segment(int32_t idx, uint8_t *data, size_t size) {
     if (idx >= 0 && idx < WASM_MAX_DATA_SEGMENTS) {
         __wasm_data_segments[idx].data = data;
@@ -813,7 +1822,9 @@ void __wasm_register_data_segment(int32_t idx, uint8_t *data, size_t size) {
     }
 }
 
-void __wasm_memory_init_seg(int32_t seg_idx, int32_t dest, int32_t src_offset, int32_t len) {
+/* The destination is checked by the compiled code, the source range here */
+void __wasm_memory_init_seg_idx(int32_t mem_idx, int32_t seg_idx, uint64_t dest,
+                                uint32_t src_offset, uint32_t len) {
     if (seg_idx < 0 || seg_idx >= __wasm_data_segment_count) {
         __wasm_trap_out_of_bounds();
     }
@@ -821,11 +1832,18 @@ void __wasm_memory_init_seg(int32_t seg_idx, int32_t dest, int32_t src_offset, i
     if (seg->dropped) {
         __wasm_trap_out_of_bounds();
     }
-    if ((size_t)(src_offset + len) > seg->size) {
+    if ((uint64_t)src_offset + len > seg->size) {
         __wasm_trap_out_of_bounds();
     }
-    if (!__wasm_memory) return;
-    memcpy(__wasm_memory + dest, seg->data + src_offset, len);
+    if (len == 0) return;
+    uint8_t *base = __wasm_memory_base_idx(mem_idx);
+    if (!base) return;
+    memcpy(base + dest, seg->data + src_offset, len);
+}
+
+void __wasm_memory_init_seg(int32_t seg_idx, uint64_t dest, uint32_t src_offset,
+                            uint32_t len) {
+    __wasm_memory_init_seg_idx(0, seg_idx, dest, src_offset, len);
 }
 
 void __wasm_data_drop(int32_t seg_idx) {
@@ -834,38 +1852,286 @@ void __wasm_data_drop(int32_t seg_idx) {
     }
 }
 
-/* Element segment support for table initialization */
+/* Element segment support; passive segments are registered at startup */
+void __wasm_register_elem_segment(int32_t idx, int32_t count) {
+    if (idx < 0 || idx >= WASM_MAX_ELEM_SEGMENTS || count <= 0) return;
+    void **funcs = calloc((size_t)count, sizeof(void *));
+    if (!funcs) {
+        fprintf(stderr, "wasm: out of memory registering element segments\n");
+        abort();
+    }
+    free(__wasm_elem_segments[idx].funcs);
+    __wasm_elem_segments[idx].funcs = funcs;
+    __wasm_elem_segments[idx].count = count;
+}
+
+void __wasm_elem_segment_set(int32_t idx, int32_t i, void *func) {
+    if (idx < 0 || idx >= WASM_MAX_ELEM_SEGMENTS) return;
+    WasmElemSegment *seg = &__wasm_elem_segments[idx];
+    if (i >= 0 && i < seg->count) {
+        seg->funcs[i] = func;
+    }
+}
+
 void __wasm_table_init(int32_t table_idx, int32_t elem_idx, int32_t dest, int32_t src, int32_t len) {
-    (void)table_idx;
-    (void)elem_idx;
-    (void)dest;
-    (void)src;
-    (void)len;
-    /* TODO: Implement element segment initialization */
+    WasmTable *table = __wasm_table_at(table_idx);
+    uint32_t seg_size = 0;
+    if (elem_idx >= 0 && elem_idx < WASM_MAX_ELEM_SEGMENTS) {
+        seg_size = (uint32_t)__wasm_elem_segments[elem_idx].count;
+    }
+    if ((uint64_t)(uint32_t)src + (uint32_t)len > seg_size) {
+        __wasm_trap_table_out_of_bounds();
+    }
+    __wasm_check_table_range(table, dest, len);
+    for (uint32_t i = 0; i < (uint32_t)len; i++) {
+        void *func = __wasm_elem_segments[elem_idx].funcs[(uint32_t)src + i];
+        table->elems[(uint32_t)dest + i] = func;
+        table->sigs[(uint32_t)dest + i] = func_sig_lookup(func);
+    }
 }
 
 void __wasm_table_copy(int32_t dest_table, int32_t src_table, int32_t dest, int32_t src, int32_t len) {
-    (void)dest_table;
-    (void)src_table;
-    if (!__wasm_table) return;
-    memmove(&__wasm_table[dest], &__wasm_table[src], len * sizeof(void *));
+    WasmTable *dst = __wasm_table_at(dest_table);
+    WasmTable *from = __wasm_table_at(src_table);
+    __wasm_check_table_range(dst, dest, len);
+    __wasm_check_table_range(from, src, len);
+    if (len == 0) return;
+    memmove(&dst->elems[(uint32_t)dest], &from->elems[(uint32_t)src],
+            (uint32_t)len * sizeof(void *));
+    memmove(&dst->sigs[(uint32_t)dest], &from->sigs[(uint32_t)src],
+            (uint32_t)len * sizeof(int32_t));
 }
 
 void __wasm_table_fill(int32_t table_idx, int32_t dest, void *val, int32_t len) {
-    (void)table_idx;
-    if (!__wasm_table) return;
-    for (int32_t i = 0; i < len; i++) {
-        if ((uint32_t)(dest + i) < __wasm_table_size) {
-            __wasm_table[dest + i] = val;
-        }
+    WasmTable *table = __wasm_table_at(table_idx);
+    __wasm_check_table_range(table, dest, len);
+    int32_t sig = func_sig_lookup(val);
+    for (uint32_t i = 0; i < (uint32_t)len; i++) {
+        table->elems[(uint32_t)dest + i] = val;
+        table->sigs[(uint32_t)dest + i] = sig;
     }
 }
 
 void __wasm_elem_drop(int32_t elem_idx) {
-    (void)elem_idx;
-    /* TODO: Mark element segment as dropped */
+    if (elem_idx < 0 || elem_idx >= WASM_MAX_ELEM_SEGMENTS) return;
+    free(__wasm_elem_segments[elem_idx].funcs);
+    __wasm_elem_segments[elem_idx].funcs = NULL;
+    __wasm_elem_segments[elem_idx].count = 0;
+}
+
+/* Data segments point into the compiled module; element segments are owned */
+static void __wasm_segments_cleanup(void) {
+    for (int i = 0; i < WASM_MAX_ELEM_SEGMENTS; i++) {
+        __wasm_elem_drop(i);
+    }
+    memset(__wasm_data_segments, 0, sizeof(__wasm_data_segments));
+    __wasm_data_segment_count = 0;
+}
+
+/* GC arrays initialized from data and element segments */
+
+/* Bytes of a data segment still available (none once dropped) */
+static size_t __wasm_data_segment_size(int32_t seg_idx) {
+    if (seg_idx < 0 || seg_idx >= __wasm_data_segment_count) return 0;
+    WasmDataSegment *seg = &__wasm_data_segments[seg_idx];
+    return seg->dropped ? 0 : seg->size;
+}
+
+/* Functions left in an element segment (none once dropped) */
+static uint32_t __wasm_elem_segment_size(int32_t elem_idx) {
+    if (elem_idx < 0 || elem_idx >= WASM_MAX_ELEM_SEGMENTS) return 0;
+    return (uint32_t)__wasm_elem_segments[elem_idx].count;
+}
+
+/* Check that `count` elements of `elem_size` bytes at `offset` are in a segment */
+static void __wasm_check_data_range(int32_t seg_idx, int32_t offset,
+                                    int32_t count, int32_t elem_size) {
+    uint64_t bytes = (uint64_t)(uint32_t)count * (uint32_t)elem_size;
+    if ((uint64_t)(uint32_t)offset + bytes > __wasm_data_segment_size(seg_idx)) {
+        __wasm_trap_out_of_bounds();
+    }
+}
+
+static void __wasm_check_elem_range(int32_t elem_idx, int32_t offset,
+                                    int32_t count) {
+    if (!__wasm_range_ok(offset, count, __wasm_elem_segment_size(elem_idx))) {
+        __wasm_trap_table_out_of_bounds();
+    }
+}
+
+/* Copy checked elements from a data segment into array slots */
+static void __wasm_array_load_data(int64_t *data, int32_t seg_idx,
+                                   int32_t offset, int32_t count,
+                                   int32_t elem_size) {
+    const uint8_t *src = __wasm_data_segments[seg_idx].data + (uint32_t)offset;
+    for (uint32_t i = 0; i < (uint32_t)count; i++) {
+        /* Elements occupy the low bytes of their 8-byte slot */
+        data[i] = 0;
+        memcpy(&data[i], src + (size_t)i * (uint32_t)elem_size, (size_t)elem_size);
+    }
+}
+
+/* Copy checked function references from an element segment into array slots */
+static void __wasm_array_load_elem(int64_t *data, int32_t elem_idx,
+                                   int32_t offset, int32_t count) {
+    void **src = __wasm_elem_segments[elem_idx].funcs;
+    for (uint32_t i = 0; i < (uint32_t)count; i++) {
+        data[i] = (int64_t)(intptr_t)src[(uint32_t)offset + i];
+    }
+}
+
+/* array.new_data */
+void *__wasm_array_new_data(int32_t type_idx, int32_t seg_idx, int32_t offset,
+                            int32_t length, int32_t elem_size) {
+    __wasm_check_data_range(seg_idx, offset, length, elem_size);
+    void *array = __wasm_array_new_default(type_idx, length);
+    __wasm_array_load_data(__wasm_array_data(array), seg_idx, offset, length,
+                           elem_size);
+    return array;
+}
+
+/* array.new_elem */
+void *__wasm_array_new_elem(int32_t type_idx, int32_t elem_idx, int32_t offset,
+                            int32_t length) {
+    __wasm_check_elem_range(elem_idx, offset, length);
+    void *array = __wasm_array_new_default(type_idx, length);
+    __wasm_array_load_elem(__wasm_array_data(array), elem_idx, offset, length);
+    return array;
+}
+
+/* array.init_data */
+void __wasm_array_init_data(void *array, int32_t dest, int32_t seg_idx,
+                            int32_t offset, int32_t count, int32_t elem_size) {
+    uint32_t length = __wasm_array_length(array);
+    if (!__wasm_range_ok(dest, count, length)) __wasm_trap_array_out_of_bounds();
+    __wasm_check_data_range(seg_idx, offset, count, elem_size);
+    __wasm_array_load_data(__wasm_array_data(array) + (uint32_t)dest, seg_idx,
+                           offset, count, elem_size);
+}
+
+/* array.init_elem */
+void __wasm_array_init_elem(void *array, int32_t dest, int32_t elem_idx,
+                            int32_t offset, int32_t count) {
+    uint32_t length = __wasm_array_length(array);
+    if (!__wasm_range_ok(dest, count, length)) __wasm_trap_array_out_of_bounds();
+    __wasm_check_elem_range(elem_idx, offset, count);
+    __wasm_array_load_elem(__wasm_array_data(array) + (uint32_t)dest, elem_idx,
+                           offset, count);
 }
 
+/* ============================================================================
+ * INSTANCES
+ * ============================================================================
+ * Standalone code runs the default instance. Code compiled with
+ * --instance-mode passes its instance to every function as a hidden first
+ * argument (vmctx) and keeps its globals in the instance; its exported
+ * functions run that instance on the calling thread until they return. A
+ * host can then create any number of instances of a module with
+ * waq_instance_new, reset or free them, and run different ones on different
+ * threads. WASI state (file descriptors, arguments, clocks) stays shared.
+ *
+ * The waq_instance_* functions need a runtime built with -DWAQ_INSTANCE_MODE.
+ */
+
+static WasmInstance __wasm_default_instance = {
+    .memory_max_pages = WASM_MAX_PAGES,
+    .gc_threshold = WASM_GC_MIN_THRESHOLD,
+};
+
+static __thread WasmInstance *__wasm_instance = &__wasm_default_instance;
+
+#ifdef WAQ_INSTANCE_MODE
+
+/* Run `instance` on this thread; returns the instance it replaces */
+WasmInstance *__wasm_instance_enter(WasmInstance *instance) {
+    WasmInstance *previous = __wasm_instance;
+    __wasm_instance = instance;
+    return previous;
+}
+
+/* Return to the instance __wasm_instance_enter replaced */
+void __wasm_instance_leave(WasmInstance *previous) {
+    __wasm_instance = previous;
+}
+
+/* Allocate zeroed slots for the running instance's globals; called by
+   __wasm_memory_init */
+int64_t *__wasm_instance_globals(int32_t count) {
+    free(__wasm_instance->globals);
+    __wasm_instance->globals = calloc(count > 0 ? (size_t)count : 1,
+                                      sizeof(int64_t));
+    if (!__wasm_instance->globals) {
+        fprintf(stderr, "wasm: out of memory allocating globals\n");
+        abort();
+    }
+    return __wasm_instance->globals;
+}
+
+/* Empty `instance`, then run the module's initialization in it */
+static void __wasm_instance_init(WasmInstance *instance,
+                                 void (*init)(WasmInstance *)) {
+    memset(instance, 0, sizeof(WasmInstance));
+    instance->init = init;
+    instance->memory_max_pages = WASM_MAX_PAGES;
+    instance->gc_threshold = WASM_GC_MIN_THRESHOLD;
+
+    WasmInstance *previous = __wasm_instance_enter(instance);
+    init(instance);
+    __wasm_instance_leave(previous);
+}
+
+/*
+ * Create an instance of a module compiled with --instance-mode, passing its
+ * __wasm_memory_init. Returns NULL if out of memory.
+ */
+WasmInstance *waq_instance_new(void (*init)(WasmInstance *)) {
+    WasmInstance *instance = malloc(sizeof(WasmInstance));
+    if (!instance) return NULL;
+    __wasm_instance_init(instance, init);
+    return instance;
+}
+
+/* Release everything an instance owns */
+static void __wasm_instance_release(WasmInstance *instance) {
+    WasmInstance *previous = __wasm_instance_enter(instance);
+    __wasm_runtime_cleanup();
+    __wasm_instance_leave(previous);
+}
+
+/* Put an instance back in its initial state, as if just created */
+void waq_instance_reset(WasmInstance *instance) {
+    __wasm_instance_release(instance);
+    __wasm_instance_init(instance, instance->init);
+}
+
+void waq_instance_free(WasmInstance *instance) {
+    if (!instance) return;
+    __wasm_instance_release(instance);
+    free(instance);
+}
+
+/* An instance's memory, for the host (see --emit header) */
+uint8_t *waq_instance_memory(WasmInstance *instance, int32_t mem_idx) {
+    WasmInstance *previous = __wasm_instance_enter(instance);
+    uint8_t *base = __wasm_memory_base_idx(mem_idx);
+    __wasm_instance_leave(previous);
+    return base;
+}
+
+uint64_t waq_instance_memory_size(WasmInstance *instance, int32_t mem_idx) {
+    WasmInstance *previous = __wasm_instance_enter(instance);
+    uint64_t size = __wasm_memory_size_bytes_idx(mem_idx);
+    __wasm_instance_leave(previous);
+    return size;
+}
+
+/* The 8-byte slots of an instance's defined globals, in index order */
+int64_t *waq_instance_globals(WasmInstance *instance) {
+    return instance->globals;
+}
+
+#endif /* WAQ_INSTANCE_MODE */
+
 /* ============================================================================
  * WASI (WebAssembly System Interface) Preview 1
  * ============================================================================
@@ -875,10 +2141,15 @@ void __wasm_elem_drop(int32_t elem_idx) {
 #include <fcntl.h>
 #include <unistd.h>
 #include <errno.h>
+#include <limits.h>
 #include <sys/stat.h>
 #include <sys/uio.h>
 #include <time.h>
 #include <dirent.h>
+#include <poll.h>
+#include <sys/ioctl.h>
+#include <sys/socket.h>
+#include <netdb.h>
 
 #ifdef __APPLE__
 #include <sys/random.h>
@@ -888,8 +2159,9 @@ void __wasm_elem_drop(int32_t elem_idx) {
 #include <sys/random.h>
 #endif
 
-/* WASI Error codes (subset) */
-typedef uint16_t __wasi_errno_t;
+/* WASI Error codes (subset). Errno values are u16, but WASI functions return
+ * them as a wasm i32, so the C type must fill the whole return register. */
+typedef int32_t __wasi_errno_t;
 #define __WASI_ERRNO_SUCCESS        0
 #define __WASI_ERRNO_2BIG           1
 #define __WASI_ERRNO_ACCES          2
@@ -985,12 +2257,52 @@ typedef uint8_t __wasi_filetype_t;
 #define __WASI_CLOCKID_PROCESS_CPUTIME_ID 2
 #define __WASI_CLOCKID_THREAD_CPUTIME_ID  3
 
+/* WASI subscription clock flags */
+#define __WASI_SUBCLOCKFLAGS_SUBSCRIPTION_CLOCK_ABSTIME (1 << 0)
+
+/* WASI event types and fd_readwrite event flags */
+#define __WASI_EVENTTYPE_CLOCK    0
+#define __WASI_EVENTTYPE_FD_READ  1
+#define __WASI_EVENTTYPE_FD_WRITE 2
+#define __WASI_EVENTRWFLAGS_FD_READWRITE_HANGUP (1 << 0)
+
+/* WASI socket flags */
+#define __WASI_RIFLAGS_RECV_PEEK            (1 << 0)
+#define __WASI_RIFLAGS_RECV_WAITALL         (1 << 1)
+#define __WASI_ROFLAGS_RECV_DATA_TRUNCATED  (1 << 0)
+#define __WASI_SDFLAGS_RD                   (1 << 0)
+#define __WASI_SDFLAGS_WR                   (1 << 1)
+
 /* WASI open flags */
 #define __WASI_OFLAGS_CREAT     (1 << 0)
 #define __WASI_OFLAGS_DIRECTORY (1 << 1)
 #define __WASI_OFLAGS_EXCL      (1 << 2)
 #define __WASI_OFLAGS_TRUNC     (1 << 3)
 
+/* WASI lookup flags */
+#define __WASI_LOOKUPFLAGS_SYMLINK_FOLLOW (1 << 0)
+
+/* WASI fd flags */
+#define __WASI_FDFLAGS_APPEND   (1 << 0)
+#define __WASI_FDFLAGS_DSYNC    (1 << 1)
+#define __WASI_FDFLAGS_NONBLOCK (1 << 2)
+#define __WASI_FDFLAGS_RSYNC    (1 << 3)
+#define __WASI_FDFLAGS_SYNC     (1 << 4)
+
+/* WASI flags of filestat_set_times */
+#define __WASI_FSTFLAGS_ATIM     (1 << 0)
+#define __WASI_FSTFLAGS_ATIM_NOW (1 << 1)
+#define __WASI_FSTFLAGS_MTIM     (1 << 2)
+#define __WASI_FSTFLAGS_MTIM_NOW (1 << 3)
+
+/* WASI file advice */
+#define __WASI_ADVICE_NORMAL     0
+#define __WASI_ADVICE_SEQUENTIAL 1
+#define __WASI_ADVICE_RANDOM     2
+#define __WASI_ADVICE_WILLNEED   3
+#define __WASI_ADVICE_DONTNEED   4
+#define __WASI_ADVICE_NOREUSE    5
+
 /* WASI rights */
 #define __WASI_RIGHTS_FD_DATASYNC             ((uint64_t)1 << 0)
 #define __WASI_RIGHTS_FD_READ                 ((uint64_t)1 << 1)
@@ -1024,7 +2336,23 @@ typedef uint8_t __wasi_filetype_t;
 #define __WASI_RIGHTS_SOCK_ACCEPT             ((uint64_t)1 << 29)
 
 /* All rights for convenience */
-#define __WASI_RIGHTS_ALL ((uint64_t)0x1FFFFFFF)
+#define __WASI_RIGHTS_ALL ((uint64_t)0x3FFFFFFF)
+
+/* Rights of read-only preopened directories and the files opened in them */
+#define __WASI_RIGHTS_READ_ONLY (__WASI_RIGHTS_FD_READ | __WASI_RIGHTS_FD_SEEK | \
+    __WASI_RIGHTS_FD_TELL | __WASI_RIGHTS_FD_ADVISE | __WASI_RIGHTS_PATH_OPEN | \
+    __WASI_RIGHTS_FD_READDIR | __WASI_RIGHTS_PATH_READLINK | \
+    __WASI_RIGHTS_PATH_FILESTAT_GET | __WASI_RIGHTS_FD_FILESTAT_GET | \
+    __WASI_RIGHTS_POLL_FD_READWRITE)
+
+/* Rights of listening sockets, and of the connections they accept */
+#define __WASI_RIGHTS_SOCKET_LISTEN (__WASI_RIGHTS_SOCK_ACCEPT | \
+    __WASI_RIGHTS_FD_FDSTAT_SET_FLAGS | __WASI_RIGHTS_FD_FILESTAT_GET | \
+    __WASI_RIGHTS_POLL_FD_READWRITE)
+#define __WASI_RIGHTS_SOCKET_CONNECTION (__WASI_RIGHTS_FD_READ | \
+    __WASI_RIGHTS_FD_WRITE | __WASI_RIGHTS_SOCK_SHUTDOWN | \
+    __WASI_RIGHTS_FD_FDSTAT_SET_FLAGS | __WASI_RIGHTS_FD_FILESTAT_GET | \
+    __WASI_RIGHTS_POLL_FD_READWRITE)
 
 /* WASI I/O vectors (in WASM linear memory) */
 typedef struct {
@@ -1048,11 +2376,19 @@ typedef struct {
 /* File descriptor table */
 #define WASI_MAX_FDS 1024
 
+/* Most iovecs one call takes; hosts limit readv and writev alike */
+#ifdef IOV_MAX
+#define WASI_MAX_IOVS IOV_MAX
+#else
+#define WASI_MAX_IOVS 1024
+#endif
+
 typedef struct {
     int host_fd;
     __wasi_filetype_t type;
     char *preopen_path;
     uint64_t rights;
+    uint64_t rights_inheriting;  /* Most rights of fds opened through this one */
 } WasiFd;
 
 static WasiFd __wasi_fd_table[WASI_MAX_FDS];
@@ -1063,6 +2399,14 @@ static char **__wasi_argv = NULL;
 static int __wasi_argc = 0;
 static char **__wasi_environ_ptr = NULL;
 
+/* Deterministic mode (see __wasi_set_deterministic): every clock reads the
+ * virtual time, which starts at 0 and advances by a fixed step per
+ * clock_time_get, and random_get draws from a seeded generator */
+#define WASI_VIRTUAL_CLOCK_STEP 1000000ULL  /* 1 ms */
+static int __wasi_deterministic = 0;
+static uint64_t __wasi_virtual_time = 0;
+static uint64_t __wasi_random_state = 0;
+
 /* Convert errno to WASI error code */
 static __wasi_errno_t errno_to_wasi(int err) {
     switch (err) {
@@ -1092,6 +2436,28 @@ static __wasi_errno_t errno_to_wasi(int err) {
         case EPIPE: return __WASI_ERRNO_PIPE;
         case EROFS: return __WASI_ERRNO_ROFS;
         case ESPIPE: return __WASI_ERRNO_SPIPE;
+        case EFBIG: return __WASI_ERRNO_FBIG;
+        case EILSEQ: return __WASI_ERRNO_ILSEQ;
+        case EMLINK: return __WASI_ERRNO_MLINK;
+        case ENOTTY: return __WASI_ERRNO_NOTTY;
+        case ENXIO: return __WASI_ERRNO_NXIO;
+        case EOVERFLOW: return __WASI_ERRNO_OVERFLOW;
+        case ETXTBSY: return __WASI_ERRNO_TXTBSY;
+        case EXDEV: return __WASI_ERRNO_XDEV;
+        case EADDRINUSE: return __WASI_ERRNO_ADDRINUSE;
+        case EADDRNOTAVAIL: return __WASI_ERRNO_ADDRNOTAVAIL;
+        case ECONNABORTED: return __WASI_ERRNO_CONNABORTED;
+        case ECONNREFUSED: return __WASI_ERRNO_CONNREFUSED;
+        case ECONNRESET: return __WASI_ERRNO_CONNRESET;
+        case EHOSTUNREACH: return __WASI_ERRNO_HOSTUNREACH;
+        case EISCONN: return __WASI_ERRNO_ISCONN;
+        case EMSGSIZE: return __WASI_ERRNO_MSGSIZE;
+        case ENETDOWN: return __WASI_ERRNO_NETDOWN;
+        case ENETUNREACH: return __WASI_ERRNO_NETUNREACH;
+        case ENOBUFS: return __WASI_ERRNO_NOBUFS;
+        case ENOTCONN: return __WASI_ERRNO_NOTCONN;
+        case ENOTSOCK: return __WASI_ERRNO_NOTSOCK;
+        case ETIMEDOUT: return __WASI_ERRNO_TIMEDOUT;
         default: return __WASI_ERRNO_IO;
     }
 }
@@ -1106,6 +2472,113 @@ static int wasi_alloc_fd(void) {
     return -1;
 }
 
+/* WASI file type of a host file mode */
+static __wasi_filetype_t wasi_filetype(mode_t mode) {
+    if (S_ISREG(mode)) return __WASI_FILETYPE_REGULAR_FILE;
+    if (S_ISDIR(mode)) return __WASI_FILETYPE_DIRECTORY;
+    if (S_ISBLK(mode)) return __WASI_FILETYPE_BLOCK_DEVICE;
+    if (S_ISCHR(mode)) return __WASI_FILETYPE_CHARACTER_DEVICE;
+    if (S_ISLNK(mode)) return __WASI_FILETYPE_SYMBOLIC_LINK;
+    if (S_ISSOCK(mode)) return __WASI_FILETYPE_SOCKET_STREAM;
+    return __WASI_FILETYPE_UNKNOWN;
+}
+
+/* Whether [ptr, ptr + len) lies in linear memory; WASI functions return
+ * FAULT for guest pointers outside it instead of trapping */
+static int wasi_in_memory(uint64_t ptr, uint64_t len) {
+    uint64_t size = __wasm_memory_size_bytes;
+    return ptr <= size && len <= size - ptr;
+}
+
+/* Write a WASI filestat structure (64 bytes) into linear memory */
+static __wasi_errno_t wasi_write_filestat(uint32_t buf_ptr, const struct stat *st) {
+    if (!wasi_in_memory(buf_ptr, 64)) return __WASI_ERRNO_FAULT;
+    uint8_t *buf = __wasm_memory + buf_ptr;
+    memset(buf, 0, 64);
+
+    /* dev (8 bytes) */
+    *(uint64_t *)(buf + 0) = (uint64_t)st->st_dev;
+    /* ino (8 bytes) */
+    *(uint64_t *)(buf + 8) = (uint64_t)st->st_ino;
+    /* filetype (1 byte) */
+    buf[16] = wasi_filetype(st->st_mode);
+    /* nlink (8 bytes at offset 24) */
+    *(uint64_t *)(buf + 24) = (uint64_t)st->st_nlink;
+    /* size (8 bytes at offset 32) */
+    *(uint64_t *)(buf + 32) = (uint64_t)st->st_size;
+    /* atim, mtim, ctim (8 bytes each at offset 40) */
+#ifdef __APPLE__
+    *(uint64_t *)(buf + 40) = (uint64_t)st->st_atimespec.tv_sec * 1000000000ULL + st->st_atimespec.tv_nsec;
+    *(uint64_t *)(buf + 48) = (uint64_t)st->st_mtimespec.tv_sec * 1000000000ULL + st->st_mtimespec.tv_nsec;
+    *(uint64_t *)(buf + 56) = (uint64_t)st->st_ctimespec.tv_sec * 1000000000ULL + st->st_ctimespec.tv_nsec;
+#else
+    *(uint64_t *)(buf + 40) = (uint64_t)st->st_atim.tv_sec * 1000000000ULL + st->st_atim.tv_nsec;
+    *(uint64_t *)(buf + 48) = (uint64_t)st->st_mtim.tv_sec * 1000000000ULL + st->st_mtim.tv_nsec;
+    *(uint64_t *)(buf + 56) = (uint64_t)st->st_ctim.tv_sec * 1000000000ULL + st->st_ctim.tv_nsec;
+#endif
+    return __WASI_ERRNO_SUCCESS;
+}
+
+/* Convert filestat_set_times arguments into utimensat/futimens times */
+static __wasi_errno_t wasi_set_times(struct timespec times[2], uint64_t atim, uint64_t mtim,
+                                     uint32_t fst_flags) {
+    if (((fst_flags & __WASI_FSTFLAGS_ATIM) && (fst_flags & __WASI_FSTFLAGS_ATIM_NOW)) ||
+        ((fst_flags & __WASI_FSTFLAGS_MTIM) && (fst_flags & __WASI_FSTFLAGS_MTIM_NOW))) {
+        return __WASI_ERRNO_INVAL;
+    }
+
+    times[0].tv_sec = 0;
+    times[0].tv_nsec = UTIME_OMIT;
+    if (fst_flags & __WASI_FSTFLAGS_ATIM_NOW) {
+        times[0].tv_nsec = UTIME_NOW;
+    } else if (fst_flags & __WASI_FSTFLAGS_ATIM) {
+        times[0].tv_sec = (time_t)(atim / 1000000000ULL);
+        times[0].tv_nsec = (long)(atim % 1000000000ULL);
+    }
+
+    times[1].tv_sec = 0;
+    times[1].tv_nsec = UTIME_OMIT;
+    if (fst_flags & __WASI_FSTFLAGS_MTIM_NOW) {
+        times[1].tv_nsec = UTIME_NOW;
+    } else if (fst_flags & __WASI_FSTFLAGS_MTIM) {
+        times[1].tv_sec = (time_t)(mtim / 1000000000ULL);
+        times[1].tv_nsec = (long)(mtim % 1000000000ULL);
+    }
+    return __WASI_ERRNO_SUCCESS;
+}
+
+/* Point host iovecs (room for WASI_MAX_IOVS) at WASI iovecs in linear
+ * memory, checking the array and every buffer */
+static __wasi_errno_t wasi_host_iovecs(struct iovec *host_iovs, uint32_t iovs_ptr,
+                                       uint32_t iovs_len) {
+    if (iovs_len > WASI_MAX_IOVS) return __WASI_ERRNO_INVAL;
+    if (!wasi_in_memory(iovs_ptr, (uint64_t)iovs_len * sizeof(__wasi_iovec_t))) {
+        return __WASI_ERRNO_FAULT;
+    }
+    __wasi_iovec_t *iovs = (__wasi_iovec_t *)(__wasm_memory + iovs_ptr);
+    for (uint32_t i = 0; i < iovs_len; i++) {
+        if (!wasi_in_memory(iovs[i].buf, iovs[i].buf_len)) return __WASI_ERRNO_FAULT;
+        host_iovs[i].iov_base = __wasm_memory + iovs[i].buf;
+        host_iovs[i].iov_len = iovs[i].buf_len;
+    }
+    return __WASI_ERRNO_SUCCESS;
+}
+
+/* Host open flags of WASI fd flags */
+static int wasi_host_fdflags(uint32_t fdflags) {
+    int flags = 0;
+    if (fdflags & __WASI_FDFLAGS_APPEND) flags |= O_APPEND;
+    if (fdflags & __WASI_FDFLAGS_NONBLOCK) flags |= O_NONBLOCK;
+    if (fdflags & __WASI_FDFLAGS_DSYNC) flags |= O_DSYNC;
+    if (fdflags & __WASI_FDFLAGS_SYNC) flags |= O_SYNC;
+#ifdef O_RSYNC
+    if (fdflags & __WASI_FDFLAGS_RSYNC) flags |= O_RSYNC;
+#else
+    if (fdflags & __WASI_FDFLAGS_RSYNC) flags |= O_SYNC;
+#endif
+    return flags;
+}
+
 /* Initialize WASI runtime */
 void __wasi_init(int argc, char **argv, char **environ) {
     if (__wasi_initialized) return;
@@ -1114,29 +2587,25 @@ void __wasi_init(int argc, char **argv, char **environ) {
     for (int i = 0; i < WASI_MAX_FDS; i++) {
         __wasi_fd_table[i].host_fd = -1;
         __wasi_fd_table[i].preopen_path = NULL;
+        __wasi_fd_table[i].rights = 0;
+        __wasi_fd_table[i].rights_inheriting = 0;
     }
 
     /* Set up standard I/O */
+    uint64_t stdio_rights = __WASI_RIGHTS_FD_FILESTAT_GET | __WASI_RIGHTS_POLL_FD_READWRITE;
     __wasi_fd_table[0].host_fd = STDIN_FILENO;
     __wasi_fd_table[0].type = __WASI_FILETYPE_CHARACTER_DEVICE;
-    __wasi_fd_table[0].rights = __WASI_RIGHTS_FD_READ;
+    __wasi_fd_table[0].rights = __WASI_RIGHTS_FD_READ | stdio_rights;
 
     __wasi_fd_table[1].host_fd = STDOUT_FILENO;
     __wasi_fd_table[1].type = __WASI_FILETYPE_CHARACTER_DEVICE;
-    __wasi_fd_table[1].rights = __WASI_RIGHTS_FD_WRITE;
+    __wasi_fd_table[1].rights = __WASI_RIGHTS_FD_WRITE | stdio_rights;
 
     __wasi_fd_table[2].host_fd = STDERR_FILENO;
     __wasi_fd_table[2].type = __WASI_FILETYPE_CHARACTER_DEVICE;
-    __wasi_fd_table[2].rights = __WASI_RIGHTS_FD_WRITE;
+    __wasi_fd_table[2].rights = __WASI_RIGHTS_FD_WRITE | stdio_rights;
 
-    /* Set up preopen for current directory */
-    int dir_fd = open(".", O_RDONLY | O_DIRECTORY);
-    if (dir_fd >= 0) {
-        __wasi_fd_table[3].host_fd = dir_fd;
-        __wasi_fd_table[3].type = __WASI_FILETYPE_DIRECTORY;
-        __wasi_fd_table[3].preopen_path = strdup(".");
-        __wasi_fd_table[3].rights = __WASI_RIGHTS_ALL;
-    }
+    /* No directories are preopened; see __wasi_preopen */
 
     /* Store args and environment */
     __wasi_argc = argc;
@@ -1146,19 +2615,136 @@ void __wasi_init(int argc, char **argv, char **environ) {
     __wasi_initialized = 1;
 }
 
+/* Preopen a host directory under a guest path, after __wasi_init.
+ * Returns the WASI fd, or -1 with errno set. */
+int __wasi_preopen(const char *host_path, const char *guest_path, int read_only) {
+    int fd = wasi_alloc_fd();
+    if (fd < 0) {
+        errno = EMFILE;
+        return -1;
+    }
+
+    int dir_fd = open(host_path, O_RDONLY | O_DIRECTORY);
+    if (dir_fd < 0) return -1;
+
+    __wasi_fd_table[fd].host_fd = dir_fd;
+    __wasi_fd_table[fd].type = __WASI_FILETYPE_DIRECTORY;
+    __wasi_fd_table[fd].preopen_path = strdup(guest_path);
+    __wasi_fd_table[fd].rights = read_only ? __WASI_RIGHTS_READ_ONLY : __WASI_RIGHTS_ALL;
+    __wasi_fd_table[fd].rights_inheriting = __wasi_fd_table[fd].rights;
+    return fd;
+}
+
+/* Listen on a TCP address, given as "host:port" ("[::1]:port" for IPv6; an
+ * empty host means every interface), and add the socket to the fd table.
+ * Returns the WASI fd, or -1 with errno set. */
+int __wasi_listen(const char *address) {
+    const char *colon = strrchr(address, ':');
+    if (!colon) {
+        errno = EINVAL;
+        return -1;
+    }
+    char host[256];
+    size_t host_len = (size_t)(colon - address);
+    if (host_len >= 2 && address[0] == '[' && address[host_len - 1] == ']') {
+        address++;
+        host_len -= 2;
+    }
+    if (host_len >= sizeof(host)) {
+        errno = ENAMETOOLONG;
+        return -1;
+    }
+    memcpy(host, address, host_len);
+    host[host_len] = '\0';
+
+    struct addrinfo hints;
+    memset(&hints, 0, sizeof(hints));
+    hints.ai_family = AF_UNSPEC;
+    hints.ai_socktype = SOCK_STREAM;
+    hints.ai_flags = AI_PASSIVE;
+    struct addrinfo *addrs;
+    if (getaddrinfo(host_len ? host : NULL, colon + 1, &hints, &addrs) != 0) {
+        errno = EADDRNOTAVAIL;
+        return -1;
+    }
+
+    int sock = -1;
+    for (struct addrinfo *ai = addrs; ai && sock < 0; ai = ai->ai_next) {
+        sock = socket(ai->ai_family, ai->ai_socktype, ai->ai_protocol);
+        if (sock < 0) continue;
+        int one = 1;
+        setsockopt(sock, SOL_SOCKET, SO_REUSEADDR, &one, sizeof(one));
+        if (bind(sock, ai->ai_addr, ai->ai_addrlen) != 0 || listen(sock, SOMAXCONN) != 0) {
+            int saved = errno;
+            close(sock);
+            errno = saved;
+            sock = -1;
+        }
+    }
+    freeaddrinfo(addrs);
+    if (sock < 0) return -1;
+
+    int fd = wasi_alloc_fd();
+    if (fd < 0) {
+        close(sock);
+        errno = EMFILE;
+        return -1;
+    }
+    __wasi_fd_table[fd].host_fd = sock;
+    __wasi_fd_table[fd].type = __WASI_FILETYPE_SOCKET_STREAM;
+    __wasi_fd_table[fd].rights = __WASI_RIGHTS_SOCKET_LISTEN;
+    __wasi_fd_table[fd].rights_inheriting = __WASI_RIGHTS_SOCKET_CONNECTION;
+    return fd;
+}
+
+/* Make clocks and randomness reproducible: the same seed gives the same
+ * random bytes and clock readings on every run */
+void __wasi_set_deterministic(uint64_t seed) {
+    __wasi_deterministic = 1;
+    __wasi_virtual_time = 0;
+    __wasi_random_state = seed;
+}
+
+/* The functions of wasi_snapshot_preview1 are defined under the qualified
+ * symbols compiled code calls them by (Z_<module>Z_<name>, see
+ * waq.compiler.imports), which can't clash with the C library or with host
+ * functions of the same name */
+#define WASI_ENTRY(name) Z_wasi_snapshot_preview1Z_##name
+
+/* Check that an fd is open and holds the rights an operation needs */
+static __wasi_errno_t wasi_check_fd(int32_t fd, uint64_t rights) {
+    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
+    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+
+    uint64_t held = __wasi_fd_table[fd].rights;
+    /* The right to seek implies the right to tell */
+    if (held & __WASI_RIGHTS_FD_SEEK) held |= __WASI_RIGHTS_FD_TELL;
+    if ((held & rights) != rights) return __WASI_ERRNO_NOTCAPABLE;
+    return __WASI_ERRNO_SUCCESS;
+}
+
 /* ---- Process Control ---- */
 
-void __wasi_proc_exit(int32_t code) {
+void WASI_ENTRY(proc_exit)(int32_t code) {
     exit(code);
 }
 
+/* Deprecated in preview1 and not supported */
+__wasi_errno_t WASI_ENTRY(proc_raise)(uint8_t sig) {
+    (void)sig;
+    return __WASI_ERRNO_NOSYS;
+}
+
 /* ---- Arguments and Environment ---- */
 
-__wasi_errno_t __wasi_args_sizes_get(uint32_t *argc_out, uint32_t *argv_buf_size_out) {
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(args_sizes_get)(uint32_t argc_out, uint32_t argv_buf_size_out) {
+    if (!wasi_in_memory(argc_out, sizeof(uint32_t)) ||
+        !wasi_in_memory(argv_buf_size_out, sizeof(uint32_t))) {
+        return __WASI_ERRNO_FAULT;
+    }
 
-    uint32_t *argc_ptr = (uint32_t *)(__wasm_memory + *argc_out);
-    uint32_t *buf_size_ptr = (uint32_t *)(__wasm_memory + *argv_buf_size_out);
+    uint32_t *argc_ptr = (uint32_t *)(__wasm_memory + argc_out);
+    uint32_t *buf_size_ptr = (uint32_t *)(__wasm_memory + argv_buf_size_out);
 
     *argc_ptr = (uint32_t)__wasi_argc;
 
@@ -1171,8 +2757,15 @@ __wasi_errno_t __wasi_args_sizes_get(uint32_t *argc_out, uint32_t *argv_buf_size
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_args_get(uint32_t argv_ptr, uint32_t argv_buf_ptr) {
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(args_get)(uint32_t argv_ptr, uint32_t argv_buf_ptr) {
+    uint64_t total_size = 0;
+    for (int i = 0; i < __wasi_argc; i++) {
+        total_size += strlen(__wasi_argv[i]) + 1;
+    }
+    if (!wasi_in_memory(argv_ptr, (uint64_t)__wasi_argc * sizeof(uint32_t)) ||
+        !wasi_in_memory(argv_buf_ptr, total_size)) {
+        return __WASI_ERRNO_FAULT;
+    }
 
     uint32_t *argv = (uint32_t *)(__wasm_memory + argv_ptr);
     uint8_t *buf = __wasm_memory + argv_buf_ptr;
@@ -1188,11 +2781,14 @@ __wasi_errno_t __wasi_args_get(uint32_t argv_ptr, uint32_t argv_buf_ptr) {
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_environ_sizes_get(uint32_t *count_out, uint32_t *buf_size_out) {
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(environ_sizes_get)(uint32_t count_out, uint32_t buf_size_out) {
+    if (!wasi_in_memory(count_out, sizeof(uint32_t)) ||
+        !wasi_in_memory(buf_size_out, sizeof(uint32_t))) {
+        return __WASI_ERRNO_FAULT;
+    }
 
-    uint32_t *count_ptr = (uint32_t *)(__wasm_memory + *count_out);
-    uint32_t *buf_size_ptr = (uint32_t *)(__wasm_memory + *buf_size_out);
+    uint32_t *count_ptr = (uint32_t *)(__wasm_memory + count_out);
+    uint32_t *buf_size_ptr = (uint32_t *)(__wasm_memory + buf_size_out);
 
     if (!__wasi_environ_ptr) {
         *count_ptr = 0;
@@ -1213,9 +2809,18 @@ __wasi_errno_t __wasi_environ_sizes_get(uint32_t *count_out, uint32_t *buf_size_
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_environ_get(uint32_t environ_ptr, uint32_t environ_buf_ptr) {
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(environ_get)(uint32_t environ_ptr, uint32_t environ_buf_ptr) {
     if (!__wasi_environ_ptr) return __WASI_ERRNO_SUCCESS;
+    uint64_t count = 0;
+    uint64_t total_size = 0;
+    for (char **e = __wasi_environ_ptr; *e != NULL; e++) {
+        count++;
+        total_size += strlen(*e) + 1;
+    }
+    if (!wasi_in_memory(environ_ptr, count * sizeof(uint32_t)) ||
+        !wasi_in_memory(environ_buf_ptr, total_size)) {
+        return __WASI_ERRNO_FAULT;
+    }
 
     uint32_t *env = (uint32_t *)(__wasm_memory + environ_ptr);
     uint8_t *buf = __wasm_memory + environ_buf_ptr;
@@ -1234,12 +2839,12 @@ __wasi_errno_t __wasi_environ_get(uint32_t environ_ptr, uint32_t environ_buf_ptr
 
 /* ---- File Descriptor Operations ---- */
 
-__wasi_errno_t __wasi_fd_close(int32_t fd) {
+__wasi_errno_t WASI_ENTRY(fd_close)(int32_t fd) {
     if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
     if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
 
     /* Don't close stdin/stdout/stderr */
-    if (fd >= 3) {
+    if (__wasi_fd_table[fd].host_fd > STDERR_FILENO) {
         close(__wasi_fd_table[fd].host_fd);
     }
 
@@ -1250,20 +2855,18 @@ __wasi_errno_t __wasi_fd_close(int32_t fd) {
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_fd_write(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len, uint32_t nwritten_ptr) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+__wasi_errno_t WASI_ENTRY(fd_write)(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len, uint32_t nwritten_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_WRITE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
     if (!__wasm_memory) return __WASI_ERRNO_FAULT;
 
-    __wasi_ciovec_t *iovs = (__wasi_ciovec_t *)(__wasm_memory + iovs_ptr);
+    if (!wasi_in_memory(nwritten_ptr, sizeof(uint32_t))) return __WASI_ERRNO_FAULT;
     uint32_t *nwritten = (uint32_t *)(__wasm_memory + nwritten_ptr);
 
     /* Convert WASM iovecs to host iovecs */
-    struct iovec *host_iovs = alloca(iovs_len * sizeof(struct iovec));
-    for (uint32_t i = 0; i < iovs_len; i++) {
-        host_iovs[i].iov_base = __wasm_memory + iovs[i].buf;
-        host_iovs[i].iov_len = iovs[i].buf_len;
-    }
+    struct iovec host_iovs[WASI_MAX_IOVS];
+    err = wasi_host_iovecs(host_iovs, iovs_ptr, iovs_len);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
     ssize_t written = writev(__wasi_fd_table[fd].host_fd, host_iovs, (int)iovs_len);
     if (written < 0) {
@@ -1274,19 +2877,17 @@ __wasi_errno_t __wasi_fd_write(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len,
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_fd_read(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len, uint32_t nread_ptr) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+__wasi_errno_t WASI_ENTRY(fd_read)(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len, uint32_t nread_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_READ);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
     if (!__wasm_memory) return __WASI_ERRNO_FAULT;
 
-    __wasi_iovec_t *iovs = (__wasi_iovec_t *)(__wasm_memory + iovs_ptr);
+    if (!wasi_in_memory(nread_ptr, sizeof(uint32_t))) return __WASI_ERRNO_FAULT;
     uint32_t *nread = (uint32_t *)(__wasm_memory + nread_ptr);
 
-    struct iovec *host_iovs = alloca(iovs_len * sizeof(struct iovec));
-    for (uint32_t i = 0; i < iovs_len; i++) {
-        host_iovs[i].iov_base = __wasm_memory + iovs[i].buf;
-        host_iovs[i].iov_len = iovs[i].buf_len;
-    }
+    struct iovec host_iovs[WASI_MAX_IOVS];
+    err = wasi_host_iovecs(host_iovs, iovs_ptr, iovs_len);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
     ssize_t read_bytes = readv(__wasi_fd_table[fd].host_fd, host_iovs, (int)iovs_len);
     if (read_bytes < 0) {
@@ -1297,10 +2898,13 @@ __wasi_errno_t __wasi_fd_read(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len,
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_fd_seek(int32_t fd, int64_t offset, uint8_t whence, uint32_t newoffset_ptr) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(fd_seek)(int32_t fd, int64_t offset, uint8_t whence, uint32_t newoffset_ptr) {
+    /* Querying the offset only needs the right to tell */
+    uint64_t needed = (whence == 1 && offset == 0) ? __WASI_RIGHTS_FD_TELL
+                                                   : __WASI_RIGHTS_FD_SEEK;
+    __wasi_errno_t err = wasi_check_fd(fd, needed);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!wasi_in_memory(newoffset_ptr, sizeof(uint64_t))) return __WASI_ERRNO_FAULT;
 
     int host_whence;
     switch (whence) {
@@ -1321,10 +2925,10 @@ __wasi_errno_t __wasi_fd_seek(int32_t fd, int64_t offset, uint8_t whence, uint32
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_fd_tell(int32_t fd, uint32_t offset_ptr) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(fd_tell)(int32_t fd, uint32_t offset_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_TELL);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!wasi_in_memory(offset_ptr, sizeof(uint64_t))) return __WASI_ERRNO_FAULT;
 
     off_t result = lseek(__wasi_fd_table[fd].host_fd, 0, SEEK_CUR);
     if (result < 0) {
@@ -1337,9 +2941,9 @@ __wasi_errno_t __wasi_fd_tell(int32_t fd, uint32_t offset_ptr) {
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_fd_sync(int32_t fd) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+__wasi_errno_t WASI_ENTRY(fd_sync)(int32_t fd) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_SYNC);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
     if (fsync(__wasi_fd_table[fd].host_fd) != 0) {
         return errno_to_wasi(errno);
@@ -1348,36 +2952,292 @@ __wasi_errno_t __wasi_fd_sync(int32_t fd) {
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_fd_fdstat_get(int32_t fd, uint32_t stat_ptr) {
+__wasi_errno_t WASI_ENTRY(fd_fdstat_get)(int32_t fd, uint32_t stat_ptr) {
     if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
     if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (!wasi_in_memory(stat_ptr, 24)) return __WASI_ERRNO_FAULT;
 
     /* WASI fdstat structure */
     uint8_t *stat = __wasm_memory + stat_ptr;
 
-    /* fs_filetype (1 byte) */
-    stat[0] = __wasi_fd_table[fd].type;
-    /* fs_flags (2 bytes) */
-    stat[1] = 0;
-    stat[2] = 0;
-    /* padding (1 byte) */
-    stat[3] = 0;
-    /* fs_rights_base (8 bytes) */
-    memcpy(stat + 8, &__wasi_fd_table[fd].rights, 8);
-    /* fs_rights_inheriting (8 bytes) */
-    memcpy(stat + 16, &__wasi_fd_table[fd].rights, 8);
+    memset(stat, 0, 24);
+    /* fs_filetype (1 byte) */
+    stat[0] = __wasi_fd_table[fd].type;
+    /* fs_flags (2 bytes at offset 2) */
+    uint16_t fs_flags = 0;
+    int host_flags = fcntl(__wasi_fd_table[fd].host_fd, F_GETFL);
+    if (host_flags >= 0) {
+        if (host_flags & O_APPEND) fs_flags |= __WASI_FDFLAGS_APPEND;
+        if (host_flags & O_NONBLOCK) fs_flags |= __WASI_FDFLAGS_NONBLOCK;
+        if ((host_flags & O_SYNC) == O_SYNC) fs_flags |= __WASI_FDFLAGS_SYNC;
+        else if (host_flags & O_DSYNC) fs_flags |= __WASI_FDFLAGS_DSYNC;
+    }
+    memcpy(stat + 2, &fs_flags, 2);
+    /* fs_rights_base (8 bytes at offset 8) */
+    memcpy(stat + 8, &__wasi_fd_table[fd].rights, 8);
+    /* fs_rights_inheriting (8 bytes) */
+    memcpy(stat + 16, &__wasi_fd_table[fd].rights_inheriting, 8);
+
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_fdstat_set_rights)(int32_t fd, uint64_t fs_rights_base,
+                                           uint64_t fs_rights_inheriting) {
+    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
+    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+
+    /* Rights can only be dropped */
+    if ((fs_rights_base & ~__wasi_fd_table[fd].rights) ||
+        (fs_rights_inheriting & ~__wasi_fd_table[fd].rights_inheriting)) {
+        return __WASI_ERRNO_NOTCAPABLE;
+    }
+
+    __wasi_fd_table[fd].rights = fs_rights_base;
+    __wasi_fd_table[fd].rights_inheriting = fs_rights_inheriting;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_fdstat_set_flags)(int32_t fd, uint16_t fdflags) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_FDSTAT_SET_FLAGS);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    /* Synchronous I/O can only be chosen when opening */
+    if (fdflags & (__WASI_FDFLAGS_DSYNC | __WASI_FDFLAGS_RSYNC | __WASI_FDFLAGS_SYNC)) {
+        return __WASI_ERRNO_NOTSUP;
+    }
+
+    int host_fd = __wasi_fd_table[fd].host_fd;
+    int flags = fcntl(host_fd, F_GETFL);
+    if (flags < 0) return errno_to_wasi(errno);
+    flags = (flags & ~(O_APPEND | O_NONBLOCK)) | wasi_host_fdflags(fdflags);
+    if (fcntl(host_fd, F_SETFL, flags) != 0) return errno_to_wasi(errno);
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_filestat_get)(int32_t fd, uint32_t buf_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_FILESTAT_GET);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+
+    struct stat st;
+    if (fstat(__wasi_fd_table[fd].host_fd, &st) != 0) {
+        return errno_to_wasi(errno);
+    }
+
+    return wasi_write_filestat(buf_ptr, &st);
+}
+
+__wasi_errno_t WASI_ENTRY(fd_filestat_set_size)(int32_t fd, uint64_t size) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_FILESTAT_SET_SIZE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (size > INT64_MAX) return __WASI_ERRNO_FBIG;
+
+    if (ftruncate(__wasi_fd_table[fd].host_fd, (off_t)size) != 0) {
+        return errno_to_wasi(errno);
+    }
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_filestat_set_times)(int32_t fd, uint64_t atim, uint64_t mtim,
+                                            uint16_t fst_flags) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_FILESTAT_SET_TIMES);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    struct timespec times[2];
+    err = wasi_set_times(times, atim, mtim, fst_flags);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    if (futimens(__wasi_fd_table[fd].host_fd, times) != 0) {
+        return errno_to_wasi(errno);
+    }
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_pread)(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len,
+                               uint64_t offset, uint32_t nread_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_READ | __WASI_RIGHTS_FD_SEEK);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (offset > INT64_MAX) return __WASI_ERRNO_INVAL;
+    if (!wasi_in_memory(nread_ptr, sizeof(uint32_t))) return __WASI_ERRNO_FAULT;
+
+    struct iovec host_iovs[WASI_MAX_IOVS];
+    err = wasi_host_iovecs(host_iovs, iovs_ptr, iovs_len);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    ssize_t read_bytes = preadv(__wasi_fd_table[fd].host_fd, host_iovs, (int)iovs_len,
+                                (off_t)offset);
+    if (read_bytes < 0) {
+        return errno_to_wasi(errno);
+    }
+
+    *(uint32_t *)(__wasm_memory + nread_ptr) = (uint32_t)read_bytes;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_pwrite)(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len,
+                                uint64_t offset, uint32_t nwritten_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_WRITE | __WASI_RIGHTS_FD_SEEK);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (offset > INT64_MAX) return __WASI_ERRNO_INVAL;
+    if (!wasi_in_memory(nwritten_ptr, sizeof(uint32_t))) return __WASI_ERRNO_FAULT;
+
+    struct iovec host_iovs[WASI_MAX_IOVS];
+    err = wasi_host_iovecs(host_iovs, iovs_ptr, iovs_len);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    ssize_t written = pwritev(__wasi_fd_table[fd].host_fd, host_iovs, (int)iovs_len,
+                              (off_t)offset);
+    if (written < 0) {
+        return errno_to_wasi(errno);
+    }
+
+    *(uint32_t *)(__wasm_memory + nwritten_ptr) = (uint32_t)written;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_readdir)(int32_t fd, uint32_t buf_ptr, uint32_t buf_len,
+                                 uint64_t cookie, uint32_t bufused_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_READDIR);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!wasi_in_memory(buf_ptr, buf_len) ||
+        !wasi_in_memory(bufused_ptr, sizeof(uint32_t))) {
+        return __WASI_ERRNO_FAULT;
+    }
+
+    /* Read through a duplicate so closedir leaves the fd open */
+    int dir_fd = dup(__wasi_fd_table[fd].host_fd);
+    if (dir_fd < 0) return errno_to_wasi(errno);
+    DIR *dir = fdopendir(dir_fd);
+    if (!dir) {
+        err = errno_to_wasi(errno);
+        close(dir_fd);
+        return err;
+    }
+    rewinddir(dir);
+
+    /* Entries are a 24-byte dirent header followed by the name; the cookie is
+     * the index of the next entry. The last entry is cut off when the buffer
+     * is full, which tells the caller to read again from its cookie. */
+    uint8_t *buf = __wasm_memory + buf_ptr;
+    uint32_t used = 0;
+    uint64_t index = 0;
+    struct dirent *entry;
+    while (used < buf_len && (entry = readdir(dir)) != NULL) {
+        if (index++ < cookie) continue;
+
+        uint32_t name_len = (uint32_t)strlen(entry->d_name);
+        uint8_t header[24] = {0};
+        uint64_t ino = (uint64_t)entry->d_ino;
+        memcpy(header, &index, 8);
+        memcpy(header + 8, &ino, 8);
+        memcpy(header + 16, &name_len, 4);
+        switch (entry->d_type) {
+            case DT_REG: header[20] = __WASI_FILETYPE_REGULAR_FILE; break;
+            case DT_DIR: header[20] = __WASI_FILETYPE_DIRECTORY; break;
+            case DT_LNK: header[20] = __WASI_FILETYPE_SYMBOLIC_LINK; break;
+            case DT_BLK: header[20] = __WASI_FILETYPE_BLOCK_DEVICE; break;
+            case DT_CHR: header[20] = __WASI_FILETYPE_CHARACTER_DEVICE; break;
+            case DT_SOCK: header[20] = __WASI_FILETYPE_SOCKET_STREAM; break;
+            default: header[20] = __WASI_FILETYPE_UNKNOWN; break;
+        }
+
+        uint32_t n = buf_len - used < 24 ? buf_len - used : 24;
+        memcpy(buf + used, header, n);
+        used += n;
+        n = buf_len - used < name_len ? buf_len - used : name_len;
+        memcpy(buf + used, entry->d_name, n);
+        used += n;
+    }
+    closedir(dir);
+
+    *(uint32_t *)(__wasm_memory + bufused_ptr) = used;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_renumber)(int32_t fd, int32_t to) {
+    __wasi_errno_t err = wasi_check_fd(fd, 0);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    err = wasi_check_fd(to, 0);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (fd == to) return __WASI_ERRNO_SUCCESS;
+
+    /* `to` is closed and takes over fd; stdin/stdout/stderr stay open on the host */
+    if (__wasi_fd_table[to].host_fd > STDERR_FILENO) {
+        close(__wasi_fd_table[to].host_fd);
+    }
+    free(__wasi_fd_table[to].preopen_path);
+    __wasi_fd_table[to] = __wasi_fd_table[fd];
+    __wasi_fd_table[fd].host_fd = -1;
+    __wasi_fd_table[fd].preopen_path = NULL;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_advise)(int32_t fd, uint64_t offset, uint64_t len, uint8_t advice) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_ADVISE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (advice > __WASI_ADVICE_NOREUSE) return __WASI_ERRNO_INVAL;
+
+#ifdef __linux__
+    static const int host_advice[] = {
+        POSIX_FADV_NORMAL, POSIX_FADV_SEQUENTIAL, POSIX_FADV_RANDOM,
+        POSIX_FADV_WILLNEED, POSIX_FADV_DONTNEED, POSIX_FADV_NOREUSE,
+    };
+    int result = posix_fadvise(__wasi_fd_table[fd].host_fd, (off_t)offset, (off_t)len,
+                               host_advice[advice]);
+    if (result != 0) return errno_to_wasi(result);
+#else
+    /* Advice is only a hint */
+    (void)offset;
+    (void)len;
+#endif
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_allocate)(int32_t fd, uint64_t offset, uint64_t len) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_ALLOCATE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (offset > INT64_MAX || len > INT64_MAX - offset) return __WASI_ERRNO_FBIG;
+
+    int host_fd = __wasi_fd_table[fd].host_fd;
+#ifdef __linux__
+    int result = posix_fallocate(host_fd, (off_t)offset, (off_t)len);
+    if (result != 0) return errno_to_wasi(result);
+#else
+    /* Extend the file without reserving blocks */
+    struct stat st;
+    if (fstat(host_fd, &st) != 0) return errno_to_wasi(errno);
+    if ((uint64_t)st.st_size < offset + len &&
+        ftruncate(host_fd, (off_t)(offset + len)) != 0) {
+        return errno_to_wasi(errno);
+    }
+#endif
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(fd_datasync)(int32_t fd) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_DATASYNC);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
+#ifdef __APPLE__
+    int result = fsync(__wasi_fd_table[fd].host_fd);
+#else
+    int result = fdatasync(__wasi_fd_table[fd].host_fd);
+#endif
+    if (result != 0) {
+        return errno_to_wasi(errno);
+    }
     return __WASI_ERRNO_SUCCESS;
 }
 
 /* ---- Preopen Support ---- */
 
-__wasi_errno_t __wasi_fd_prestat_get(int32_t fd, uint32_t prestat_ptr) {
+__wasi_errno_t WASI_ENTRY(fd_prestat_get)(int32_t fd, uint32_t prestat_ptr) {
     if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
     if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
     if (!__wasi_fd_table[fd].preopen_path) return __WASI_ERRNO_BADF;
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (!wasi_in_memory(prestat_ptr, sizeof(__wasi_prestat_t))) return __WASI_ERRNO_FAULT;
 
     __wasi_prestat_t *prestat = (__wasi_prestat_t *)(__wasm_memory + prestat_ptr);
     prestat->tag = __WASI_PREOPENTYPE_DIR;
@@ -1386,11 +3246,11 @@ __wasi_errno_t __wasi_fd_prestat_get(int32_t fd, uint32_t prestat_ptr) {
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_fd_prestat_dir_name(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
+__wasi_errno_t WASI_ENTRY(fd_prestat_dir_name)(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
     if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
     if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
     if (!__wasi_fd_table[fd].preopen_path) return __WASI_ERRNO_BADF;
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (!wasi_in_memory(path_ptr, path_len)) return __WASI_ERRNO_FAULT;
 
     size_t len = strlen(__wasi_fd_table[fd].preopen_path);
     if (len > path_len) {
@@ -1402,9 +3262,141 @@ __wasi_errno_t __wasi_fd_prestat_dir_name(int32_t fd, uint32_t path_ptr, uint32_
     return __WASI_ERRNO_SUCCESS;
 }
 
+/* ---- Path Resolution ---- */
+
+/* Symlinks followed and directories entered while resolving one path */
+#define WASI_MAX_SYMLINKS 40
+#define WASI_MAX_PATH_DEPTH 256
+
+/* A path resolved beneath a directory fd: the host directory holding its last
+ * component, and that component's name */
+typedef struct {
+    int dir_fd;
+    int owned;  /* dir_fd was opened by the resolution and must be closed */
+    char *name;
+} WasiPath;
+
+static void wasi_path_release(WasiPath *p) {
+    if (p->owned) close(p->dir_fd);
+    free(p->name);
+}
+
+/* Resolve a guest path beneath a directory fd without leaving it. Absolute
+ * paths, and ".." or symlinks leading above the fd, fail with NOTCAPABLE.
+ * Directories are entered one component at a time with O_NOFOLLOW and symlinks
+ * are expanded here, so the final host call must not follow them either
+ * (O_NOFOLLOW, AT_SYMLINK_NOFOLLOW). The last component is only expanded with
+ * `follow`. */
+static __wasi_errno_t wasi_resolve_path(int32_t fd, uint32_t path_ptr, uint32_t path_len,
+                                        int follow, WasiPath *out) {
+    if (!wasi_in_memory(path_ptr, path_len)) return __WASI_ERRNO_FAULT;
+    if (path_len == 0) return __WASI_ERRNO_NOENT;
+    if (memchr(__wasm_memory + path_ptr, '\0', path_len)) return __WASI_ERRNO_INVAL;
+
+    char *path = malloc(path_len + 1);
+    if (!path) return __WASI_ERRNO_NOMEM;
+    memcpy(path, __wasm_memory + path_ptr, path_len);
+    path[path_len] = '\0';
+
+    int dirs[WASI_MAX_PATH_DEPTH];
+    int depth = 0;
+    int links = 0;
+    dirs[0] = __wasi_fd_table[fd].host_fd;
+    __wasi_errno_t err = __WASI_ERRNO_SUCCESS;
+    char *name = NULL;
+    char *rest = path;
+
+    while (!name) {
+        if (*rest == '/') {
+            err = __WASI_ERRNO_NOTCAPABLE;
+            break;
+        }
+
+        size_t len = strcspn(rest, "/");
+        char *next = rest + len;
+        while (*next == '/') next++;
+        int last = *next == '\0';
+
+        if (len == 1 && rest[0] == '.') {
+            if (last) name = strdup(".");
+            rest = next;
+            continue;
+        }
+        if (len == 2 && rest[0] == '.' && rest[1] == '.') {
+            if (depth == 0) {
+                err = __WASI_ERRNO_NOTCAPABLE;
+                break;
+            }
+            close(dirs[depth--]);
+            if (last) name = strdup(".");
+            rest = next;
+            continue;
+        }
+
+        rest[len] = '\0';
+        if (last && !follow) {
+            name = strdup(rest);
+            break;
+        }
+
+        /* Expand a symlink in place of its component */
+        char target[PATH_MAX];
+        ssize_t target_len = readlinkat(dirs[depth], rest, target, sizeof(target));
+        if (target_len >= 0) {
+            if (++links > WASI_MAX_SYMLINKS || (size_t)target_len == sizeof(target)) {
+                err = __WASI_ERRNO_LOOP;
+                break;
+            }
+            size_t next_len = strlen(next);
+            char *expanded = malloc((size_t)target_len + next_len + 2);
+            if (!expanded) {
+                err = __WASI_ERRNO_NOMEM;
+                break;
+            }
+            memcpy(expanded, target, (size_t)target_len);
+            expanded[target_len] = '/';
+            memcpy(expanded + target_len + 1, next, next_len + 1);
+            free(path);
+            path = rest = expanded;
+            continue;
+        }
+
+        /* A missing last component is left for the operation to report */
+        if (last) {
+            name = strdup(rest);
+            break;
+        }
+
+        if (depth + 1 >= WASI_MAX_PATH_DEPTH) {
+            err = __WASI_ERRNO_NAMETOOLONG;
+            break;
+        }
+        int dir_fd = openat(dirs[depth], rest, O_RDONLY | O_DIRECTORY | O_NOFOLLOW);
+        if (dir_fd < 0) {
+            err = errno_to_wasi(errno);
+            break;
+        }
+        dirs[++depth] = dir_fd;
+        rest = next;
+    }
+    free(path);
+
+    if (err == __WASI_ERRNO_SUCCESS && !name) err = __WASI_ERRNO_NOMEM;
+    if (err != __WASI_ERRNO_SUCCESS) {
+        while (depth > 0) close(dirs[depth--]);
+        return err;
+    }
+
+    for (int i = 1; i < depth; i++) close(dirs[i]);
+    out->dir_fd = dirs[depth];
+    out->owned = depth > 0;
+    out->name = name;
+    return __WASI_ERRNO_SUCCESS;
+}
+
 /* ---- Path Operations ---- */
 
-__wasi_errno_t __wasi_path_open(
+__wasi_errno_t WASI_ENTRY(path_open)(
     int32_t dirfd,
     uint32_t dirflags,
     uint32_t path_ptr,
@@ -1415,25 +3407,33 @@ __wasi_errno_t __wasi_path_open(
     uint16_t fdflags,
     uint32_t opened_fd_ptr
 ) {
-    (void)dirflags;
-    (void)fs_rights_inheriting;
-    (void)fdflags;
-
-    if (dirfd < 0 || dirfd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[dirfd].host_fd < 0) return __WASI_ERRNO_BADF;
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
-
-    /* Copy path to null-terminated string */
-    char *path = alloca(path_len + 1);
-    memcpy(path, __wasm_memory + path_ptr, path_len);
-    path[path_len] = '\0';
-
-    /* Convert WASI flags to POSIX flags */
-    int host_flags = 0;
+    uint64_t needed = __WASI_RIGHTS_PATH_OPEN;
+    if (oflags & __WASI_OFLAGS_CREAT) needed |= __WASI_RIGHTS_PATH_CREATE_FILE;
+    if (oflags & __WASI_OFLAGS_TRUNC) needed |= __WASI_RIGHTS_PATH_FILESTAT_SET_SIZE;
+    __wasi_errno_t err = wasi_check_fd(dirfd, needed);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!wasi_in_memory(opened_fd_ptr, sizeof(uint32_t))) return __WASI_ERRNO_FAULT;
+
+    /* The new fd gets at most the rights the directory passes on; reading and
+     * writing are refused rather than silently dropped */
+    uint64_t allowed = __wasi_fd_table[dirfd].rights_inheriting;
+    uint64_t rw = __WASI_RIGHTS_FD_READ | __WASI_RIGHTS_FD_WRITE;
+    if (fs_rights_base & rw & ~allowed) return __WASI_ERRNO_NOTCAPABLE;
+    fs_rights_base &= allowed;
+    fs_rights_inheriting &= allowed;
+
+    WasiPath path;
+    err = wasi_resolve_path(dirfd, path_ptr, path_len,
+                            dirflags & __WASI_LOOKUPFLAGS_SYMLINK_FOLLOW, &path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    /* Convert WASI flags to POSIX flags; symlinks were expanded while resolving */
+    int host_flags = O_NOFOLLOW;
     if (oflags & __WASI_OFLAGS_CREAT) host_flags |= O_CREAT;
     if (oflags & __WASI_OFLAGS_EXCL) host_flags |= O_EXCL;
     if (oflags & __WASI_OFLAGS_TRUNC) host_flags |= O_TRUNC;
     if (oflags & __WASI_OFLAGS_DIRECTORY) host_flags |= O_DIRECTORY;
+    host_flags |= wasi_host_fdflags(fdflags);
 
     /* Determine read/write mode from rights */
     if ((fs_rights_base & __WASI_RIGHTS_FD_READ) &&
@@ -1445,9 +3445,11 @@ __wasi_errno_t __wasi_path_open(
         host_flags |= O_RDONLY;
     }
 
-    int host_fd = openat(__wasi_fd_table[dirfd].host_fd, path, host_flags, 0666);
+    int host_fd = openat(path.dir_fd, path.name, host_flags, 0666);
+    int open_errno = errno;
+    wasi_path_release(&path);
     if (host_fd < 0) {
-        return errno_to_wasi(errno);
+        return errno_to_wasi(open_errno);
     }
 
     /* Allocate WASI fd */
@@ -1461,16 +3463,13 @@ __wasi_errno_t __wasi_path_open(
     struct stat st;
     __wasi_filetype_t file_type = __WASI_FILETYPE_UNKNOWN;
     if (fstat(host_fd, &st) == 0) {
-        if (S_ISREG(st.st_mode)) file_type = __WASI_FILETYPE_REGULAR_FILE;
-        else if (S_ISDIR(st.st_mode)) file_type = __WASI_FILETYPE_DIRECTORY;
-        else if (S_ISBLK(st.st_mode)) file_type = __WASI_FILETYPE_BLOCK_DEVICE;
-        else if (S_ISCHR(st.st_mode)) file_type = __WASI_FILETYPE_CHARACTER_DEVICE;
-        else if (S_ISLNK(st.st_mode)) file_type = __WASI_FILETYPE_SYMBOLIC_LINK;
+        file_type = wasi_filetype(st.st_mode);
     }
 
     __wasi_fd_table[new_fd].host_fd = host_fd;
     __wasi_fd_table[new_fd].type = file_type;
     __wasi_fd_table[new_fd].rights = fs_rights_base;
+    __wasi_fd_table[new_fd].rights_inheriting = fs_rights_inheriting;
     __wasi_fd_table[new_fd].preopen_path = NULL;
 
     uint32_t *opened_fd = (uint32_t *)(__wasm_memory + opened_fd_ptr);
@@ -1479,133 +3478,211 @@ __wasi_errno_t __wasi_path_open(
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_path_create_directory(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+__wasi_errno_t WASI_ENTRY(path_create_directory)(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_PATH_CREATE_DIRECTORY);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
     if (!__wasm_memory) return __WASI_ERRNO_FAULT;
 
-    char *path = alloca(path_len + 1);
-    memcpy(path, __wasm_memory + path_ptr, path_len);
-    path[path_len] = '\0';
+    WasiPath path;
+    err = wasi_resolve_path(fd, path_ptr, path_len, 0, &path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
-    if (mkdirat(__wasi_fd_table[fd].host_fd, path, 0777) != 0) {
-        return errno_to_wasi(errno);
-    }
+    int result = mkdirat(path.dir_fd, path.name, 0777);
+    err = result != 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+    wasi_path_release(&path);
+    return err;
+}
 
-    return __WASI_ERRNO_SUCCESS;
+__wasi_errno_t WASI_ENTRY(path_unlink_file)(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_PATH_UNLINK_FILE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+
+    WasiPath path;
+    err = wasi_resolve_path(fd, path_ptr, path_len, 0, &path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    int result = unlinkat(path.dir_fd, path.name, 0);
+    err = result != 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+    wasi_path_release(&path);
+    return err;
 }
 
-__wasi_errno_t __wasi_path_unlink_file(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+__wasi_errno_t WASI_ENTRY(path_remove_directory)(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_PATH_REMOVE_DIRECTORY);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
     if (!__wasm_memory) return __WASI_ERRNO_FAULT;
 
-    char *path = alloca(path_len + 1);
-    memcpy(path, __wasm_memory + path_ptr, path_len);
-    path[path_len] = '\0';
+    WasiPath path;
+    err = wasi_resolve_path(fd, path_ptr, path_len, 0, &path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
-    if (unlinkat(__wasi_fd_table[fd].host_fd, path, 0) != 0) {
-        return errno_to_wasi(errno);
+    int result = unlinkat(path.dir_fd, path.name, AT_REMOVEDIR);
+    err = result != 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+    wasi_path_release(&path);
+    return err;
+}
+
+__wasi_errno_t WASI_ENTRY(path_rename)(
+    int32_t old_fd, uint32_t old_path_ptr, uint32_t old_path_len,
+    int32_t new_fd, uint32_t new_path_ptr, uint32_t new_path_len
+) {
+    __wasi_errno_t err = wasi_check_fd(old_fd, __WASI_RIGHTS_PATH_RENAME_SOURCE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    err = wasi_check_fd(new_fd, __WASI_RIGHTS_PATH_RENAME_TARGET);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+
+    WasiPath old_path;
+    err = wasi_resolve_path(old_fd, old_path_ptr, old_path_len, 0, &old_path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    WasiPath new_path;
+    err = wasi_resolve_path(new_fd, new_path_ptr, new_path_len, 0, &new_path);
+    if (err != __WASI_ERRNO_SUCCESS) {
+        wasi_path_release(&old_path);
+        return err;
     }
 
-    return __WASI_ERRNO_SUCCESS;
+    int result = renameat(old_path.dir_fd, old_path.name, new_path.dir_fd, new_path.name);
+    err = result != 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+    wasi_path_release(&old_path);
+    wasi_path_release(&new_path);
+    return err;
 }
 
-__wasi_errno_t __wasi_path_remove_directory(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+__wasi_errno_t WASI_ENTRY(path_filestat_get)(
+    int32_t fd, uint32_t flags, uint32_t path_ptr, uint32_t path_len, uint32_t buf_ptr
+) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_PATH_FILESTAT_GET);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
     if (!__wasm_memory) return __WASI_ERRNO_FAULT;
 
-    char *path = alloca(path_len + 1);
-    memcpy(path, __wasm_memory + path_ptr, path_len);
-    path[path_len] = '\0';
+    WasiPath path;
+    err = wasi_resolve_path(fd, path_ptr, path_len,
+                            flags & __WASI_LOOKUPFLAGS_SYMLINK_FOLLOW, &path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
-    if (unlinkat(__wasi_fd_table[fd].host_fd, path, AT_REMOVEDIR) != 0) {
-        return errno_to_wasi(errno);
+    struct stat st;
+    int result = fstatat(path.dir_fd, path.name, &st, AT_SYMLINK_NOFOLLOW);
+    int stat_errno = errno;
+    wasi_path_release(&path);
+    if (result != 0) {
+        return errno_to_wasi(stat_errno);
     }
 
-    return __WASI_ERRNO_SUCCESS;
+    return wasi_write_filestat(buf_ptr, &st);
 }
 
-__wasi_errno_t __wasi_path_rename(
-    int32_t old_fd, uint32_t old_path_ptr, uint32_t old_path_len,
-    int32_t new_fd, uint32_t new_path_ptr, uint32_t new_path_len
+__wasi_errno_t WASI_ENTRY(path_filestat_set_times)(
+    int32_t fd, uint32_t flags, uint32_t path_ptr, uint32_t path_len,
+    uint64_t atim, uint64_t mtim, uint16_t fst_flags
 ) {
-    if (old_fd < 0 || old_fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[old_fd].host_fd < 0) return __WASI_ERRNO_BADF;
-    if (new_fd < 0 || new_fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[new_fd].host_fd < 0) return __WASI_ERRNO_BADF;
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_PATH_FILESTAT_SET_TIMES);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
     if (!__wasm_memory) return __WASI_ERRNO_FAULT;
 
-    char *old_path = alloca(old_path_len + 1);
-    memcpy(old_path, __wasm_memory + old_path_ptr, old_path_len);
-    old_path[old_path_len] = '\0';
+    struct timespec times[2];
+    err = wasi_set_times(times, atim, mtim, fst_flags);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
-    char *new_path = alloca(new_path_len + 1);
-    memcpy(new_path, __wasm_memory + new_path_ptr, new_path_len);
-    new_path[new_path_len] = '\0';
+    WasiPath path;
+    err = wasi_resolve_path(fd, path_ptr, path_len,
+                            flags & __WASI_LOOKUPFLAGS_SYMLINK_FOLLOW, &path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
-    if (renameat(__wasi_fd_table[old_fd].host_fd, old_path,
-                 __wasi_fd_table[new_fd].host_fd, new_path) != 0) {
-        return errno_to_wasi(errno);
-    }
+    int result = utimensat(path.dir_fd, path.name, times, AT_SYMLINK_NOFOLLOW);
+    err = result != 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+    wasi_path_release(&path);
+    return err;
+}
 
-    return __WASI_ERRNO_SUCCESS;
+__wasi_errno_t WASI_ENTRY(path_symlink)(
+    uint32_t old_path_ptr, uint32_t old_path_len,
+    int32_t fd, uint32_t new_path_ptr, uint32_t new_path_len
+) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_PATH_SYMLINK);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!wasi_in_memory(old_path_ptr, old_path_len)) return __WASI_ERRNO_FAULT;
+
+    /* The link target is stored as is; absolute targets could never resolve
+     * inside the sandbox, and would point host programs outside it */
+    const uint8_t *target_src = __wasm_memory + old_path_ptr;
+    if (old_path_len == 0) return __WASI_ERRNO_NOENT;
+    if (target_src[0] == '/') return __WASI_ERRNO_NOTCAPABLE;
+    if (memchr(target_src, '\0', old_path_len)) return __WASI_ERRNO_INVAL;
+    char *target = malloc(old_path_len + 1);
+    if (!target) return __WASI_ERRNO_NOMEM;
+    memcpy(target, target_src, old_path_len);
+    target[old_path_len] = '\0';
+
+    WasiPath path;
+    err = wasi_resolve_path(fd, new_path_ptr, new_path_len, 0, &path);
+    if (err == __WASI_ERRNO_SUCCESS) {
+        int result = symlinkat(target, path.dir_fd, path.name);
+        err = result != 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+        wasi_path_release(&path);
+    }
+    free(target);
+    return err;
 }
 
-__wasi_errno_t __wasi_path_filestat_get(
-    int32_t fd, uint32_t flags, uint32_t path_ptr, uint32_t path_len, uint32_t buf_ptr
+__wasi_errno_t WASI_ENTRY(path_link)(
+    int32_t old_fd, uint32_t old_flags, uint32_t old_path_ptr, uint32_t old_path_len,
+    int32_t new_fd, uint32_t new_path_ptr, uint32_t new_path_len
 ) {
-    (void)flags;
-    if (fd < 0 || fd >= WASI_MAX_FDS) return __WASI_ERRNO_BADF;
-    if (__wasi_fd_table[fd].host_fd < 0) return __WASI_ERRNO_BADF;
+    __wasi_errno_t err = wasi_check_fd(old_fd, __WASI_RIGHTS_PATH_LINK_SOURCE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    err = wasi_check_fd(new_fd, __WASI_RIGHTS_PATH_LINK_TARGET);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
     if (!__wasm_memory) return __WASI_ERRNO_FAULT;
 
-    char *path = alloca(path_len + 1);
-    memcpy(path, __wasm_memory + path_ptr, path_len);
-    path[path_len] = '\0';
+    WasiPath old_path;
+    err = wasi_resolve_path(old_fd, old_path_ptr, old_path_len,
+                            old_flags & __WASI_LOOKUPFLAGS_SYMLINK_FOLLOW, &old_path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    WasiPath new_path;
+    err = wasi_resolve_path(new_fd, new_path_ptr, new_path_len, 0, &new_path);
+    if (err != __WASI_ERRNO_SUCCESS) {
+        wasi_path_release(&old_path);
+        return err;
+    }
 
-    struct stat st;
-    if (fstatat(__wasi_fd_table[fd].host_fd, path, &st, 0) != 0) {
-        return errno_to_wasi(errno);
+    int result = linkat(old_path.dir_fd, old_path.name, new_path.dir_fd, new_path.name, 0);
+    err = result != 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+    wasi_path_release(&old_path);
+    wasi_path_release(&new_path);
+    return err;
+}
+
+__wasi_errno_t WASI_ENTRY(path_readlink)(
+    int32_t fd, uint32_t path_ptr, uint32_t path_len,
+    uint32_t buf_ptr, uint32_t buf_len, uint32_t bufused_ptr
+) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_PATH_READLINK);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!wasi_in_memory(buf_ptr, buf_len) ||
+        !wasi_in_memory(bufused_ptr, sizeof(uint32_t))) {
+        return __WASI_ERRNO_FAULT;
     }
 
-    /* WASI filestat structure (64 bytes) */
-    uint8_t *buf = __wasm_memory + buf_ptr;
-    memset(buf, 0, 64);
+    WasiPath path;
+    err = wasi_resolve_path(fd, path_ptr, path_len, 0, &path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
-    /* dev (8 bytes) */
-    *(uint64_t *)(buf + 0) = (uint64_t)st.st_dev;
-    /* ino (8 bytes) */
-    *(uint64_t *)(buf + 8) = (uint64_t)st.st_ino;
-    /* filetype (1 byte) */
-    if (S_ISREG(st.st_mode)) buf[16] = __WASI_FILETYPE_REGULAR_FILE;
-    else if (S_ISDIR(st.st_mode)) buf[16] = __WASI_FILETYPE_DIRECTORY;
-    else if (S_ISBLK(st.st_mode)) buf[16] = __WASI_FILETYPE_BLOCK_DEVICE;
-    else if (S_ISCHR(st.st_mode)) buf[16] = __WASI_FILETYPE_CHARACTER_DEVICE;
-    else if (S_ISLNK(st.st_mode)) buf[16] = __WASI_FILETYPE_SYMBOLIC_LINK;
-    /* nlink (8 bytes at offset 24) */
-    *(uint64_t *)(buf + 24) = (uint64_t)st.st_nlink;
-    /* size (8 bytes at offset 32) */
-    *(uint64_t *)(buf + 32) = (uint64_t)st.st_size;
-    /* atim (8 bytes at offset 40) */
-#ifdef __APPLE__
-    *(uint64_t *)(buf + 40) = (uint64_t)st.st_atimespec.tv_sec * 1000000000ULL + st.st_atimespec.tv_nsec;
-    *(uint64_t *)(buf + 48) = (uint64_t)st.st_mtimespec.tv_sec * 1000000000ULL + st.st_mtimespec.tv_nsec;
-    *(uint64_t *)(buf + 56) = (uint64_t)st.st_ctimespec.tv_sec * 1000000000ULL + st.st_ctimespec.tv_nsec;
-#else
-    *(uint64_t *)(buf + 40) = (uint64_t)st.st_atim.tv_sec * 1000000000ULL + st.st_atim.tv_nsec;
-    *(uint64_t *)(buf + 48) = (uint64_t)st.st_mtim.tv_sec * 1000000000ULL + st.st_mtim.tv_nsec;
-    *(uint64_t *)(buf + 56) = (uint64_t)st.st_ctim.tv_sec * 1000000000ULL + st.st_ctim.tv_nsec;
-#endif
+    /* Targets longer than the buffer are truncated, like readlink(2) */
+    ssize_t len = readlinkat(path.dir_fd, path.name, (char *)__wasm_memory + buf_ptr, buf_len);
+    err = len < 0 ? errno_to_wasi(errno) : __WASI_ERRNO_SUCCESS;
+    wasi_path_release(&path);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
 
+    *(uint32_t *)(__wasm_memory + bufused_ptr) = (uint32_t)len;
     return __WASI_ERRNO_SUCCESS;
 }
 
 /* ---- Clock Functions ---- */
 
-__wasi_errno_t __wasi_clock_res_get(uint32_t clock_id, uint32_t resolution_ptr) {
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(clock_res_get)(uint32_t clock_id, uint32_t resolution_ptr) {
+    if (!wasi_in_memory(resolution_ptr, sizeof(uint64_t))) return __WASI_ERRNO_FAULT;
 
     uint64_t *resolution = (uint64_t *)(__wasm_memory + resolution_ptr);
 
@@ -1620,11 +3697,13 @@ __wasi_errno_t __wasi_clock_res_get(uint32_t clock_id, uint32_t resolution_ptr)
     }
 }
 
-__wasi_errno_t __wasi_clock_time_get(uint32_t clock_id, uint64_t precision, uint32_t time_ptr) {
-    (void)precision;
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
-
-    uint64_t *time = (uint64_t *)(__wasm_memory + time_ptr);
+/* Read a WASI clock in nanoseconds */
+static __wasi_errno_t wasi_clock_now(uint32_t clock_id, uint64_t *now) {
+    if (__wasi_deterministic) {
+        if (clock_id > __WASI_CLOCKID_THREAD_CPUTIME_ID) return __WASI_ERRNO_INVAL;
+        *now = __wasi_virtual_time;
+        return __WASI_ERRNO_SUCCESS;
+    }
 
     struct timespec ts;
 
@@ -1663,17 +3742,41 @@ __wasi_errno_t __wasi_clock_time_get(uint32_t clock_id, uint64_t precision, uint
     }
 #endif
 
-    *time = (uint64_t)ts.tv_sec * 1000000000ULL + (uint64_t)ts.tv_nsec;
+    *now = (uint64_t)ts.tv_sec * 1000000000ULL + (uint64_t)ts.tv_nsec;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(clock_time_get)(uint32_t clock_id, uint64_t precision, uint32_t time_ptr) {
+    (void)precision;
+    if (!wasi_in_memory(time_ptr, sizeof(uint64_t))) return __WASI_ERRNO_FAULT;
+
+    uint64_t now;
+    __wasi_errno_t err = wasi_clock_now(clock_id, &now);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    *(uint64_t *)(__wasm_memory + time_ptr) = now;
+    if (__wasi_deterministic) __wasi_virtual_time += WASI_VIRTUAL_CLOCK_STEP;
     return __WASI_ERRNO_SUCCESS;
 }
 
 /* ---- Random ---- */
 
-__wasi_errno_t __wasi_random_get(uint32_t buf_ptr, uint32_t buf_len) {
-    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+__wasi_errno_t WASI_ENTRY(random_get)(uint32_t buf_ptr, uint32_t buf_len) {
+    if (!wasi_in_memory(buf_ptr, buf_len)) return __WASI_ERRNO_FAULT;
 
     uint8_t *buf = __wasm_memory + buf_ptr;
 
+    if (__wasi_deterministic) {
+        /* splitmix64 */
+        for (uint64_t i = 0; i < buf_len; i += 8) {
+            uint64_t z = (__wasi_random_state += 0x9E3779B97F4A7C15ULL);
+            z = (z ^ (z >> 30)) * 0xBF58476D1CE4E5B9ULL;
+            z = (z ^ (z >> 27)) * 0x94D049BB133111EBULL;
+            z ^= z >> 31;
+            memcpy(buf + i, &z, buf_len - i < 8 ? buf_len - i : 8);
+        }
+        return __WASI_ERRNO_SUCCESS;
+    }
+
 #ifdef __APPLE__
     arc4random_buf(buf, buf_len);
     return __WASI_ERRNO_SUCCESS;
@@ -1698,23 +3801,320 @@ __wasi_errno_t __wasi_random_get(uint32_t buf_ptr, uint32_t buf_len) {
 #endif
 }
 
-/* ---- Stub functions for less common WASI calls ---- */
+/* ---- Scheduling and Polling ---- */
 
-__wasi_errno_t __wasi_sched_yield(void) {
+__wasi_errno_t WASI_ENTRY(sched_yield)(void) {
     sched_yield();
     return __WASI_ERRNO_SUCCESS;
 }
 
-__wasi_errno_t __wasi_poll_oneoff(
+/* Subscriptions are 48 bytes: userdata, the tag at 8, then either the clock
+ * (id, timeout, precision and flags at 16, 24, 32 and 40) or the fd at 16.
+ * Events are 32 bytes: userdata, error at 8, type at 10, and for fd events
+ * nbytes and flags at 16 and 24. */
+#define WASI_SUBSCRIPTION_SIZE 48
+#define WASI_EVENT_SIZE        32
+
+static void wasi_write_event(uint8_t *event, uint64_t userdata, __wasi_errno_t error,
+                             uint8_t type, uint64_t nbytes, uint16_t flags) {
+    uint16_t error16 = (uint16_t)error;
+    memset(event, 0, WASI_EVENT_SIZE);
+    memcpy(event, &userdata, 8);
+    memcpy(event + 8, &error16, 2);
+    event[10] = type;
+    memcpy(event + 16, &nbytes, 8);
+    memcpy(event + 24, &flags, 2);
+}
+
+/* Bytes that can be read from a ready host fd without blocking */
+static uint64_t wasi_readable_bytes(int host_fd) {
+    struct stat st;
+    if (fstat(host_fd, &st) == 0 && S_ISREG(st.st_mode)) {
+        off_t pos = lseek(host_fd, 0, SEEK_CUR);
+        return pos >= 0 && st.st_size > pos ? (uint64_t)(st.st_size - pos) : 0;
+    }
+    int available = 0;
+    if (ioctl(host_fd, FIONREAD, &available) == 0 && available > 0) {
+        return (uint64_t)available;
+    }
+    return 0;
+}
+
+typedef struct {
+    uint32_t sub;        /* Index of the subscription */
+    uint64_t deadline;   /* On the monotonic clock, in nanoseconds */
+} WasiPollClock;
+
+__wasi_errno_t WASI_ENTRY(poll_oneoff)(
     uint32_t in_ptr, uint32_t out_ptr, uint32_t nsubscriptions, uint32_t nevents_ptr
 ) {
-    (void)in_ptr;
-    (void)out_ptr;
-    (void)nsubscriptions;
-    (void)nevents_ptr;
-    return __WASI_ERRNO_NOSYS;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (nsubscriptions == 0) return __WASI_ERRNO_INVAL;
+    if (!wasi_in_memory(in_ptr, (uint64_t)nsubscriptions * WASI_SUBSCRIPTION_SIZE) ||
+        !wasi_in_memory(out_ptr, (uint64_t)nsubscriptions * WASI_EVENT_SIZE) ||
+        !wasi_in_memory(nevents_ptr, sizeof(uint32_t))) {
+        return __WASI_ERRNO_FAULT;
+    }
+
+    const uint8_t *subs = __wasm_memory + in_ptr;
+    uint8_t *events = __wasm_memory + out_ptr;
+    uint32_t nevents = 0;
+
+    WasiPollClock *clocks = calloc(nsubscriptions, sizeof(WasiPollClock));
+    struct pollfd *fds = calloc(nsubscriptions, sizeof(struct pollfd));
+    uint32_t *fd_subs = calloc(nsubscriptions, sizeof(uint32_t));
+    if (!clocks || !fds || !fd_subs) {
+        free(clocks);
+        free(fds);
+        free(fd_subs);
+        return __WASI_ERRNO_NOMEM;
+    }
+    uint32_t nclocks = 0;
+    nfds_t nfds = 0;
+
+    uint64_t start;
+    __wasi_errno_t err = wasi_clock_now(__WASI_CLOCKID_MONOTONIC, &start);
+
+    /* Subscriptions that fail are reported as events right away */
+    for (uint32_t i = 0; i < nsubscriptions && err == __WASI_ERRNO_SUCCESS; i++) {
+        const uint8_t *sub = subs + (size_t)i * WASI_SUBSCRIPTION_SIZE;
+        uint64_t userdata;
+        memcpy(&userdata, sub, 8);
+        uint8_t *event = events + (size_t)nevents * WASI_EVENT_SIZE;
+
+        if (sub[8] == __WASI_EVENTTYPE_CLOCK) {
+            uint32_t clock_id;
+            uint64_t timeout;
+            uint16_t flags;
+            memcpy(&clock_id, sub + 16, 4);
+            memcpy(&timeout, sub + 24, 8);
+            memcpy(&flags, sub + 40, 2);
+
+            /* Absolute timeouts are turned into a relative wait on their own clock */
+            uint64_t now;
+            __wasi_errno_t clock_err = wasi_clock_now(clock_id, &now);
+            if (clock_err != __WASI_ERRNO_SUCCESS) {
+                wasi_write_event(event, userdata, clock_err, __WASI_EVENTTYPE_CLOCK, 0, 0);
+                nevents++;
+                continue;
+            }
+            uint64_t wait = timeout;
+            if (flags & __WASI_SUBCLOCKFLAGS_SUBSCRIPTION_CLOCK_ABSTIME) {
+                wait = timeout > now ? timeout - now : 0;
+            }
+            clocks[nclocks].sub = i;
+            clocks[nclocks].deadline = wait > UINT64_MAX - start ? UINT64_MAX : start + wait;
+            nclocks++;
+        } else if (sub[8] == __WASI_EVENTTYPE_FD_READ || sub[8] == __WASI_EVENTTYPE_FD_WRITE) {
+            int32_t fd;
+            memcpy(&fd, sub + 16, 4);
+            __wasi_errno_t fd_err = wasi_check_fd(fd, __WASI_RIGHTS_POLL_FD_READWRITE);
+            if (fd_err != __WASI_ERRNO_SUCCESS) {
+                wasi_write_event(event, userdata, fd_err, sub[8], 0, 0);
+                nevents++;
+                continue;
+            }
+            fds[nfds].fd = __wasi_fd_table[fd].host_fd;
+            fds[nfds].events = sub[8] == __WASI_EVENTTYPE_FD_READ ? POLLIN : POLLOUT;
+            fd_subs[nfds] = i;
+            nfds++;
+        } else {
+            err = __WASI_ERRNO_INVAL;
+        }
+    }
+
+    /* Wait until something is ready; when a subscription already failed, only
+     * look for what is ready now */
+    int block = nevents == 0;
+    while (err == __WASI_ERRNO_SUCCESS) {
+        uint64_t now;
+        wasi_clock_now(__WASI_CLOCKID_MONOTONIC, &now);
+        uint64_t wait = block ? UINT64_MAX : 0;
+        for (uint32_t c = 0; c < nclocks; c++) {
+            uint64_t left = clocks[c].deadline > now ? clocks[c].deadline - now : 0;
+            if (left < wait) wait = left;
+        }
+
+        /* Virtual clocks never sleep: unless an fd is ready right away, time
+         * skips ahead to the first deadline */
+        int virtual_wait = __wasi_deterministic && wait != UINT64_MAX;
+
+        if (nfds == 0 && virtual_wait) {
+            __wasi_virtual_time += wait;
+        } else if (nfds == 0) {
+            /* Only clocks: sleep for exactly the shortest timeout */
+            struct timespec ts;
+            ts.tv_sec = (time_t)(wait / 1000000000ULL);
+            ts.tv_nsec = (long)(wait % 1000000000ULL);
+            nanosleep(&ts, NULL);
+        } else {
+            /* poll counts milliseconds: round up so clocks never fire early */
+            int timeout_ms = -1;
+            if (virtual_wait) {
+                timeout_ms = 0;
+            } else if (wait != UINT64_MAX) {
+                uint64_t ms = (wait + 999999) / 1000000;
+                timeout_ms = ms > INT_MAX ? INT_MAX : (int)ms;
+            }
+            if (poll(fds, nfds, timeout_ms) < 0 && errno != EINTR) {
+                err = errno_to_wasi(errno);
+                break;
+            }
+            for (nfds_t j = 0; j < nfds; j++) {
+                short revents = fds[j].revents;
+                if (!revents) continue;
+                const uint8_t *sub = subs + (size_t)fd_subs[j] * WASI_SUBSCRIPTION_SIZE;
+                uint64_t userdata;
+                memcpy(&userdata, sub, 8);
+
+                __wasi_errno_t fd_err = __WASI_ERRNO_SUCCESS;
+                if (revents & POLLNVAL) fd_err = __WASI_ERRNO_BADF;
+                else if (revents & POLLERR) fd_err = __WASI_ERRNO_IO;
+                uint16_t flags = revents & POLLHUP ? __WASI_EVENTRWFLAGS_FD_READWRITE_HANGUP : 0;
+                uint64_t nbytes = 0;
+                if (sub[8] == __WASI_EVENTTYPE_FD_READ && fd_err == __WASI_ERRNO_SUCCESS) {
+                    nbytes = wasi_readable_bytes(fds[j].fd);
+                }
+                wasi_write_event(events + (size_t)nevents * WASI_EVENT_SIZE, userdata,
+                                 fd_err, sub[8], nbytes, flags);
+                nevents++;
+            }
+            if (virtual_wait && nevents == 0) __wasi_virtual_time += wait;
+        }
+
+        wasi_clock_now(__WASI_CLOCKID_MONOTONIC, &now);
+        for (uint32_t c = 0; c < nclocks; c++) {
+            if (clocks[c].deadline > now) continue;
+            const uint8_t *sub = subs + (size_t)clocks[c].sub * WASI_SUBSCRIPTION_SIZE;
+            uint64_t userdata;
+            memcpy(&userdata, sub, 8);
+            wasi_write_event(events + (size_t)nevents * WASI_EVENT_SIZE, userdata,
+                             __WASI_ERRNO_SUCCESS, __WASI_EVENTTYPE_CLOCK, 0, 0);
+            nevents++;
+        }
+
+        if (nevents > 0 || !block) break;
+    }
+
+    free(clocks);
+    free(fds);
+    free(fd_subs);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    *(uint32_t *)(__wasm_memory + nevents_ptr) = nevents;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+/* ---- Sockets ---- */
+
+__wasi_errno_t WASI_ENTRY(sock_accept)(int32_t fd, uint16_t fdflags, uint32_t result_fd_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_SOCK_ACCEPT);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    /* Checked before accepting, so a connection is never left open */
+    if (!wasi_in_memory(result_fd_ptr, sizeof(int32_t))) return __WASI_ERRNO_FAULT;
+    if (fdflags & ~__WASI_FDFLAGS_NONBLOCK) return __WASI_ERRNO_INVAL;
+
+    int new_fd = wasi_alloc_fd();
+    if (new_fd < 0) return __WASI_ERRNO_MFILE;
+
+    int conn = accept(__wasi_fd_table[fd].host_fd, NULL, NULL);
+    if (conn < 0) return errno_to_wasi(errno);
+    if (fdflags & __WASI_FDFLAGS_NONBLOCK) {
+        int flags = fcntl(conn, F_GETFL);
+        if (flags < 0 || fcntl(conn, F_SETFL, flags | O_NONBLOCK) != 0) {
+            err = errno_to_wasi(errno);
+            close(conn);
+            return err;
+        }
+    }
+
+    __wasi_fd_table[new_fd].host_fd = conn;
+    __wasi_fd_table[new_fd].type = __WASI_FILETYPE_SOCKET_STREAM;
+    __wasi_fd_table[new_fd].rights = __wasi_fd_table[fd].rights_inheriting;
+    __wasi_fd_table[new_fd].rights_inheriting = 0;
+    *(int32_t *)(__wasm_memory + result_fd_ptr) = new_fd;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(sock_recv)(int32_t fd, uint32_t ri_data_ptr, uint32_t ri_data_len,
+                                uint16_t ri_flags, uint32_t ro_datalen_ptr,
+                                uint32_t ro_flags_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_READ);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (ri_flags & ~(__WASI_RIFLAGS_RECV_PEEK | __WASI_RIFLAGS_RECV_WAITALL)) {
+        return __WASI_ERRNO_INVAL;
+    }
+
+    if (!wasi_in_memory(ro_datalen_ptr, sizeof(uint32_t)) ||
+        !wasi_in_memory(ro_flags_ptr, sizeof(uint16_t))) {
+        return __WASI_ERRNO_FAULT;
+    }
+
+    struct iovec host_iovs[WASI_MAX_IOVS];
+    err = wasi_host_iovecs(host_iovs, ri_data_ptr, ri_data_len);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    struct msghdr msg;
+    memset(&msg, 0, sizeof(msg));
+    msg.msg_iov = host_iovs;
+    msg.msg_iovlen = ri_data_len;
+    int flags = 0;
+    if (ri_flags & __WASI_RIFLAGS_RECV_PEEK) flags |= MSG_PEEK;
+    if (ri_flags & __WASI_RIFLAGS_RECV_WAITALL) flags |= MSG_WAITALL;
+    ssize_t n = recvmsg(__wasi_fd_table[fd].host_fd, &msg, flags);
+    if (n < 0) return errno_to_wasi(errno);
+
+    uint16_t ro_flags = msg.msg_flags & MSG_TRUNC ? __WASI_ROFLAGS_RECV_DATA_TRUNCATED : 0;
+    *(uint32_t *)(__wasm_memory + ro_datalen_ptr) = (uint32_t)n;
+    memcpy(__wasm_memory + ro_flags_ptr, &ro_flags, 2);
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(sock_send)(int32_t fd, uint32_t si_data_ptr, uint32_t si_data_len,
+                                uint16_t si_flags, uint32_t so_datalen_ptr) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_FD_WRITE);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+    if (!__wasm_memory) return __WASI_ERRNO_FAULT;
+    if (si_flags != 0) return __WASI_ERRNO_INVAL;
+
+    if (!wasi_in_memory(so_datalen_ptr, sizeof(uint32_t))) return __WASI_ERRNO_FAULT;
+
+    struct iovec host_iovs[WASI_MAX_IOVS];
+    err = wasi_host_iovecs(host_iovs, si_data_ptr, si_data_len);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    struct msghdr msg;
+    memset(&msg, 0, sizeof(msg));
+    msg.msg_iov = host_iovs;
+    msg.msg_iovlen = si_data_len;
+    /* A closed peer is reported as PIPE instead of killing the process */
+    int flags = 0;
+#ifdef MSG_NOSIGNAL
+    flags |= MSG_NOSIGNAL;
+#endif
+    ssize_t n = sendmsg(__wasi_fd_table[fd].host_fd, &msg, flags);
+    if (n < 0) return errno_to_wasi(errno);
+
+    *(uint32_t *)(__wasm_memory + so_datalen_ptr) = (uint32_t)n;
+    return __WASI_ERRNO_SUCCESS;
+}
+
+__wasi_errno_t WASI_ENTRY(sock_shutdown)(int32_t fd, uint8_t how) {
+    __wasi_errno_t err = wasi_check_fd(fd, __WASI_RIGHTS_SOCK_SHUTDOWN);
+    if (err != __WASI_ERRNO_SUCCESS) return err;
+
+    int host_how;
+    switch (how) {
+        case __WASI_SDFLAGS_RD: host_how = SHUT_RD; break;
+        case __WASI_SDFLAGS_WR: host_how = SHUT_WR; break;
+        case __WASI_SDFLAGS_RD | __WASI_SDFLAGS_WR: host_how = SHUT_RDWR; break;
+        default: return __WASI_ERRNO_INVAL;
+    }
+    if (shutdown(__wasi_fd_table[fd].host_fd, host_how) != 0) return errno_to_wasi(errno);
+    return __WASI_ERRNO_SUCCESS;
 }
 
+
 /* ============================================================================
  * DETERMINISTIC PROFILE
  * ============================================================================
@@ -1763,19 +4163,19 @@ double __wasm_f64_sqrt_deterministic(double x) {
 }
 
 float __wasm_f32_min_deterministic(float a, float b) {
-    return __wasm_canon_nan_f32(fminf(a, b));
+    return __wasm_canon_nan_f32(__wasm_f32_min(a, b));
 }
 
 float __wasm_f32_max_deterministic(float a, float b) {
-    return __wasm_canon_nan_f32(fmaxf(a, b));
+    return __wasm_canon_nan_f32(__wasm_f32_max(a, b));
 }
 
 double __wasm_f64_min_deterministic(double a, double b) {
-    return __wasm_canon_nan_f64(fmin(a, b));
+    return __wasm_canon_nan_f64(__wasm_f64_min(a, b));
 }
 
 double __wasm_f64_max_deterministic(double a, double b) {
-    return __wasm_canon_nan_f64(fmax(a, b));
+    return __wasm_canon_nan_f64(__wasm_f64_max(a, b));
 }
 
 /* ============================================================================
@@ -2015,124 +4415,3 @@ void __wasm_v128_const(v128_t *result, uint64_t low, uint64_t high) {
     result->u64[0] = low;
     result->u64[1] = high;
 }
-
-/* ---- WASI Function Aliases (without __wasi_ prefix) ---- */
-/* WASM imports use short names like "proc_exit", not "__wasi_proc_exit" */
-
-void proc_exit(int32_t code) {
-    __wasi_proc_exit(code);
-}
-
-__wasi_errno_t args_sizes_get(uint32_t *argc_out, uint32_t *argv_buf_size_out) {
-    return __wasi_args_sizes_get(argc_out, argv_buf_size_out);
-}
-
-__wasi_errno_t args_get(uint32_t argv_ptr, uint32_t argv_buf_ptr) {
-    return __wasi_args_get(argv_ptr, argv_buf_ptr);
-}
-
-__wasi_errno_t environ_sizes_get(uint32_t *count_out, uint32_t *buf_size_out) {
-    return __wasi_environ_sizes_get(count_out, buf_size_out);
-}
-
-__wasi_errno_t environ_get(uint32_t environ_ptr, uint32_t environ_buf_ptr) {
-    return __wasi_environ_get(environ_ptr, environ_buf_ptr);
-}
-
-__wasi_errno_t fd_close(int32_t fd) {
-    return __wasi_fd_close(fd);
-}
-
-__wasi_errno_t fd_write(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len, uint32_t nwritten_ptr) {
-    return __wasi_fd_write(fd, iovs_ptr, iovs_len, nwritten_ptr);
-}
-
-__wasi_errno_t fd_read(int32_t fd, uint32_t iovs_ptr, uint32_t iovs_len, uint32_t nread_ptr) {
-    return __wasi_fd_read(fd, iovs_ptr, iovs_len, nread_ptr);
-}
-
-__wasi_errno_t fd_seek(int32_t fd, int64_t offset, uint8_t whence, uint32_t newoffset_ptr) {
-    return __wasi_fd_seek(fd, offset, whence, newoffset_ptr);
-}
-
-__wasi_errno_t fd_tell(int32_t fd, uint32_t offset_ptr) {
-    return __wasi_fd_tell(fd, offset_ptr);
-}
-
-__wasi_errno_t fd_sync(int32_t fd) {
-    return __wasi_fd_sync(fd);
-}
-
-__wasi_errno_t fd_fdstat_get(int32_t fd, uint32_t stat_ptr) {
-    return __wasi_fd_fdstat_get(fd, stat_ptr);
-}
-
-__wasi_errno_t fd_prestat_get(int32_t fd, uint32_t prestat_ptr) {
-    return __wasi_fd_prestat_get(fd, prestat_ptr);
-}
-
-__wasi_errno_t fd_prestat_dir_name(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
-    return __wasi_fd_prestat_dir_name(fd, path_ptr, path_len);
-}
-
-__wasi_errno_t path_open(
-    int32_t fd,
-    uint32_t dirflags,
-    uint32_t path_ptr,
-    uint32_t path_len,
-    uint32_t oflags,
-    uint64_t fs_rights_base,
-    uint64_t fs_rights_inheriting,
-    uint16_t fdflags,
-    uint32_t opened_fd_ptr
-) {
-    return __wasi_path_open(fd, dirflags, path_ptr, path_len, oflags,
-                            fs_rights_base, fs_rights_inheriting, fdflags, opened_fd_ptr);
-}
-
-__wasi_errno_t path_create_directory(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
-    return __wasi_path_create_directory(fd, path_ptr, path_len);
-}
-
-__wasi_errno_t path_unlink_file(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
-    return __wasi_path_unlink_file(fd, path_ptr, path_len);
-}
-
-__wasi_errno_t path_remove_directory(int32_t fd, uint32_t path_ptr, uint32_t path_len) {
-    return __wasi_path_remove_directory(fd, path_ptr, path_len);
-}
-
-__wasi_errno_t path_rename(
-    int32_t fd, uint32_t old_path_ptr, uint32_t old_path_len,
-    int32_t new_fd, uint32_t new_path_ptr, uint32_t new_path_len
-) {
-    return __wasi_path_rename(fd, old_path_ptr, old_path_len, new_fd, new_path_ptr, new_path_len);
-}
-
-__wasi_errno_t path_filestat_get(
-    int32_t fd,
-    uint32_t flags,
-    uint32_t path_ptr,
-    uint32_t path_len,
-    uint32_t filestat_ptr
-) {
-    return __wasi_path_filestat_get(fd, flags, path_ptr, path_len, filestat_ptr);
-}
-
-__wasi_errno_t clock_res_get(uint32_t clock_id, uint32_t resolution_ptr) {
-    return __wasi_clock_res_get(clock_id, resolution_ptr);
-}
-
-__wasi_errno_t clock_time_get(uint32_t clock_id, uint64_t precision, uint32_t time_ptr) {
-    return __wasi_clock_time_get(clock_id, precision, time_ptr);
-}
-
-__wasi_errno_t random_get(uint32_t buf_ptr, uint32_t buf_len) {
-    return __wasi_random_get(buf_ptr, buf_len);
-}
-
-/* sched_yield conflicts with system header - skip alias */
-
-__wasi_errno_t poll_oneoff(uint32_t in_ptr, uint32_t out_ptr, uint32_t nsubscriptions, uint32_t nevents_ptr) {
-    return __wasi_poll_oneoff(in_ptr, out_ptr, nsubscriptions, nevents_ptr);
-}
@@ -4,9 +4,19 @@ from __future__ import annotations
 
 from waq.parser.binary import BinaryReader
 from waq.parser.module import ExportKind, ImportKind, WasmModule
-from waq.parser.types import FuncType, ValueType
+from waq.parser.types import (
+    ArrayType,
+    BlockType,
+    FieldType,
+    FuncType,
+    GlobalType,
+    MemoryType,
+    StructType,
+    TableType,
+    ValueType,
+)
 
-from .types import ValidationContext, ValidationResult
+from .types import ValidationContext, ValidationResult, types_match
 
 
 def validate_module(module: WasmModule) -> ValidationResult:
@@ -21,6 +31,11 @@ def validate_module(module: WasmModule) -> ValidationResult:
         ValidationResult with any errors or warnings found
     """
     ctx = ValidationContext(module=module)
+    ctx.declared_funcs.update(
+        exp.index for exp in module.exports if exp.kind == ExportKind.FUNC
+    )
+    for seg in module.elements:
+        ctx.declared_funcs.update(seg.func_indices)
 
     # Validate imports
     _validate_imports(ctx)
@@ -28,10 +43,8 @@ def validate_module(module: WasmModule) -> ValidationResult:
     # Validate exports
     _validate_exports(ctx)
 
-    # Validate functions
-    _validate_functions(ctx)
-
-    # Validate globals
+    # Validate globals (before the functions: their ref.func targets are
+    # declared too)
     _validate_globals(ctx)
 
     # Validate tables
@@ -40,6 +53,16 @@ def validate_module(module: WasmModule) -> ValidationResult:
     # Validate memories
     _validate_memories(ctx)
 
+    # Validate data and element segments
+    _validate_data(ctx)
+    _validate_elements(ctx)
+
+    # Validate functions
+    _validate_functions(ctx)
+
+    # Validate tags
+    _validate_tags(ctx)
+
     # Validate start function
     _validate_start(ctx)
 
@@ -70,6 +93,7 @@ def _validate_exports(ctx: ValidationContext) -> None:
     num_tables = ctx.module.num_imported_tables() + len(ctx.module.tables)
     num_memories = ctx.module.num_imported_memories() + len(ctx.module.memories)
     num_globals = ctx.module.num_imported_globals() + len(ctx.module.globals)
+    num_tags = ctx.module.num_imported_tags() + len(ctx.module.tags)
 
     seen_names: set[str] = set()
 
@@ -103,6 +127,11 @@ def _validate_exports(ctx: ValidationContext) -> None:
                 f"export '{exp.name}': global index {exp.index} out of bounds",
                 location=f"export '{exp.name}'",
             )
+        elif exp.kind == ExportKind.TAG and exp.index >= num_tags:
+            ctx.result.add_error(
+                f"export '{exp.name}': tag index {exp.index} out of bounds",
+                location=f"export '{exp.name}'",
+            )
 
 
 def _validate_functions(ctx: ValidationContext) -> None:
@@ -150,160 +179,206 @@ def _validate_functions(ctx: ValidationContext) -> None:
         # Validate function body instructions
         _validate_function_body(ctx, body.code)
 
-
 def _validate_function_body(ctx: ValidationContext, code: bytes) -> None:
     """Validate a function body's instructions."""
-    reader = BinaryReader(code)
+    reader = BinaryReader(code, types=ctx.module.types)
 
     while not reader.at_end:
         ctx.current_offset = reader.pos
-        _validate_instruction(ctx, reader)
-
-    # Check final stack state
-    if ctx.current_func_type:
-        expected_results = len(ctx.current_func_type.results)
-        actual_depth = len(ctx.value_stack)
-
-        # Account for the implicit function block
-        if ctx.control_stack:
-            frame = ctx.control_stack[0]
-            expected_depth = frame.start_depth + expected_results
-            if actual_depth != expected_depth and not frame.unreachable:
-                ctx.error(
-                    f"function ends with wrong stack depth: expected {expected_results} values, got {actual_depth - frame.start_depth}"
-                )
+        if not ctx.control_stack:
+            ctx.error("instructions after function end")
+            return
+        if not _validate_instruction(ctx, reader):
+            # Immediates of an unknown opcode can't be skipped
+            return
+
+    if ctx.control_stack:
+        ctx.error("function body is missing end")
+
 
+def _validate_instruction(ctx: ValidationContext, reader: BinaryReader) -> bool:
+    """Validate a single instruction.
 
-def _validate_instruction(ctx: ValidationContext, reader: BinaryReader) -> None:
-    """Validate a single instruction."""
+    Returns False if the opcode is unknown, in which case the rest of the
+    function body can't be decoded.
+    """
     opcode = reader.read_byte()
 
     # nop
     if opcode == 0x01:
-        return
+        return True
 
     # unreachable
     if opcode == 0x00:
-        if ctx.control_stack:
-            ctx.control_stack[-1].unreachable = True
-        return
-
-    # block
-    if opcode == 0x02:
-        block_type = reader.read_block_type()
-        result_types = _block_type_to_results(block_type, ctx)
-        ctx.push_control("block", result_types)
-        return
-
-    # loop
-    if opcode == 0x03:
-        block_type = reader.read_block_type()
-        result_types = _block_type_to_results(block_type, ctx)
-        ctx.push_control("loop", result_types)
-        return
-
-    # if
-    if opcode == 0x04:
-        block_type = reader.read_block_type()
-        result_types = _block_type_to_results(block_type, ctx)
-        ctx.pop_expect(ValueType.I32)  # condition
-        ctx.push_control("if", result_types)
-        return
+        ctx.set_unreachable()
+        return True
+
+    # block, loop, if, try
+    if opcode in (0x02, 0x03, 0x04, 0x06):
+        params, results = _block_type(ctx, reader.read_block_type())
+        if opcode == 0x04:
+            ctx.pop_expect(ValueType.I32)  # condition
+        kind = {0x02: "block", 0x03: "loop", 0x04: "if", 0x06: "try"}[opcode]
+        ctx.push_control(kind, results, params)
+        return True
+
+    # try_table
+    if opcode == 0x1F:
+        params, results = _block_type(ctx, reader.read_block_type())
+        for _ in range(reader.read_u32_leb128()):
+            _validate_catch_clause(ctx, reader)
+        ctx.push_control("try_table", results, params)
+        return True
+
+    # throw_ref
+    if opcode == 0x0A:
+        ctx.pop_expect(ValueType.EXNREF)
+        ctx.set_unreachable()
+        return True
 
     # else
     if opcode == 0x05:
         if not ctx.control_stack or ctx.control_stack[-1].kind != "if":
             ctx.error("else without matching if")
-            return
-        # Pop values for then branch, will be regenerated for else
+            return True
         frame = ctx.control_stack[-1]
-        ctx.value_stack = ctx.value_stack[: frame.start_depth]
-        return
-
-    # end
-    if opcode == 0x0B:
-        ctx.pop_control()
-        return
+        ctx.check_frame_end(frame)
+        frame.kind = "else"
+        frame.unreachable = False
+        ctx.push_values(frame.param_types)
+        return True
+
+    # catch, catch_all
+    if opcode in (0x07, 0x19):
+        tag_type = None
+        if opcode == 0x07:
+            tag_type = _tag_type(ctx, reader.read_u32_leb128())
+        if not ctx.control_stack or ctx.control_stack[-1].kind not in ("try", "catch"):
+            ctx.error("catch without matching try")
+            return True
+        frame = ctx.control_stack[-1]
+        ctx.check_frame_end(frame)
+        frame.kind = "catch"
+        frame.unreachable = False
+        if tag_type is not None:
+            ctx.push_values(tag_type.params)
+        return True
+
+    # end, delegate
+    if opcode in (0x0B, 0x18):
+        if opcode == 0x18:
+            depth = reader.read_u32_leb128()
+            if not ctx.control_stack or ctx.control_stack[-1].kind != "try":
+                ctx.error("delegate without matching try")
+            elif depth + 1 >= len(ctx.control_stack):
+                ctx.error(f"delegate depth {depth} exceeds control stack")
+        frame = ctx.pop_control()
+        if frame is None:
+            return True
+        if frame.kind == "if" and frame.param_types != frame.result_types:
+            ctx.error("if without else must leave its parameters as results")
+        ctx.push_values(frame.result_types)
+        return True
+
+    # throw, rethrow
+    if opcode in (0x08, 0x09):
+        index = reader.read_u32_leb128()
+        if opcode == 0x09:
+            frame = ctx.get_frame(index)
+            if frame is not None and frame.kind != "catch":
+                ctx.error(f"rethrow target {index} is not a catch block")
+        else:
+            tag_type = _tag_type(ctx, index)
+            if tag_type is not None:
+                ctx.pop_values(tag_type.params)
+        ctx.set_unreachable()
+        return True
 
     # br
     if opcode == 0x0C:
-        depth = reader.read_u32_leb128()
-        if depth >= len(ctx.control_stack):
-            ctx.error(f"branch depth {depth} exceeds control stack")
-        if ctx.control_stack:
-            ctx.control_stack[-1].unreachable = True
-        return
+        frame = ctx.get_frame(reader.read_u32_leb128())
+        if frame is not None:
+            ctx.pop_values(frame.label_types)
+        ctx.set_unreachable()
+        return True
 
     # br_if
     if opcode == 0x0D:
-        _depth = reader.read_u32_leb128()
+        frame = ctx.get_frame(reader.read_u32_leb128())
         ctx.pop_expect(ValueType.I32)  # condition
-        return
+        if frame is not None:
+            ctx.pop_values(frame.label_types)
+            ctx.push_values(frame.label_types)
+        return True
 
     # br_table
     if opcode == 0x0E:
         count = reader.read_u32_leb128()
-        for _ in range(count + 1):  # +1 for default
-            reader.read_u32_leb128()
+        depths = [reader.read_u32_leb128() for _ in range(count + 1)]  # +1 for default
         ctx.pop_expect(ValueType.I32)  # index
-        if ctx.control_stack:
-            ctx.control_stack[-1].unreachable = True
-        return
+        frames = [ctx.get_frame(depth) for depth in depths]
+        default = frames[-1]
+        if default is not None:
+            arity = len(default.label_types)
+            if any(f is not None and len(f.label_types) != arity for f in frames):
+                ctx.error("br_table targets have inconsistent arity")
+            ctx.pop_values(default.label_types)
+        ctx.set_unreachable()
+        return True
 
     # return
     if opcode == 0x0F:
         if ctx.current_func_type:
-            for rtype in reversed(ctx.current_func_type.results):
-                ctx.pop_expect(rtype)
-        if ctx.control_stack:
-            ctx.control_stack[-1].unreachable = True
-        return
+            ctx.pop_values(ctx.current_func_type.results)
+        ctx.set_unreachable()
+        return True
 
-    # call
-    if opcode == 0x10:
+    # call, return_call
+    if opcode in (0x10, 0x12):
         func_idx = reader.read_u32_leb128()
         func_type = ctx.get_func_type(func_idx)
         if func_type:
-            # Pop arguments
-            for ptype in reversed(func_type.params):
-                ctx.pop_expect(ptype)
-            # Push results
-            for rtype in func_type.results:
-                ctx.push_value(rtype)
-        return
+            _validate_call(ctx, func_type, is_tail=opcode == 0x12)
+        return True
 
-    # call_indirect
-    if opcode == 0x11:
+    # call_indirect, return_call_indirect
+    if opcode in (0x11, 0x13):
         type_idx = reader.read_u32_leb128()
-        _table_idx = reader.read_u32_leb128()
-        ctx.pop_expect(ValueType.I32)  # table index
-
-        if type_idx < len(ctx.module.types):
-            type_def = ctx.module.types[type_idx]
-            if isinstance(type_def, FuncType):
-                for ptype in reversed(type_def.params):
-                    ctx.pop_expect(ptype)
-                for rtype in type_def.results:
-                    ctx.push_value(rtype)
-        else:
-            ctx.error(f"call_indirect: type index {type_idx} out of bounds")
-        return
+        table_idx = reader.read_u32_leb128()
+        _table_elem_type(ctx, table_idx)
+        ctx.pop_expect(_table_address_type(ctx, table_idx))  # element index
+        func_type = _func_type_at(ctx, type_idx, "call_indirect")
+        if func_type:
+            _validate_call(ctx, func_type, is_tail=opcode == 0x13)
+        return True
+
+    # call_ref, return_call_ref
+    if opcode in (0x14, 0x15):
+        type_idx = reader.read_u32_leb128()
+        ctx.pop_reference()
+        func_type = _func_type_at(ctx, type_idx, "call_ref")
+        if func_type:
+            _validate_call(ctx, func_type, is_tail=opcode == 0x15)
+        return True
 
     # drop
     if opcode == 0x1A:
         ctx.pop_value()
-        return
-
-    # select
-    if opcode == 0x1B:
+        return True
+
+    # select, select with type
+    if opcode in (0x1B, 0x1C):
+        if opcode == 0x1C:
+            count = reader.read_u32_leb128()
+            for _ in range(count):
+                reader.read_u32_leb128()
         ctx.pop_expect(ValueType.I32)  # condition
         val2 = ctx.pop_value()
         val1 = ctx.pop_value()
-        if val1 and val2 and val1 != val2:
+        if val1 and val2 and not types_match(val1, val2):
             ctx.error(f"select type mismatch: {val1} vs {val2}")
-        if val1:
-            ctx.push_value(val1)
-        return
+        ctx.push_value(val1 or val2)
+        return True
 
     # local.get
     if opcode == 0x20:
@@ -311,7 +386,7 @@ def _validate_instruction(ctx: ValidationContext, reader: BinaryReader) -> None:
         local_type = ctx.get_local_type(local_idx)
         if local_type:
             ctx.push_value(local_type)
-        return
+        return True
 
     # local.set
     if opcode == 0x21:
@@ -319,7 +394,7 @@ def _validate_instruction(ctx: ValidationContext, reader: BinaryReader) -> None:
         local_type = ctx.get_local_type(local_idx)
         if local_type:
             ctx.pop_expect(local_type)
-        return
+        return True
 
     # local.tee
     if opcode == 0x22:
@@ -328,217 +403,325 @@ def _validate_instruction(ctx: ValidationContext, reader: BinaryReader) -> None:
         if local_type:
             ctx.pop_expect(local_type)
             ctx.push_value(local_type)
-        return
+        return True
 
     # global.get
     if opcode == 0x23:
         global_idx = reader.read_u32_leb128()
-        num_globals = ctx.module.num_imported_globals() + len(ctx.module.globals)
-        if global_idx >= num_globals:
-            ctx.error(f"global index {global_idx} out of bounds")
-        else:
-            # Get global type
-            num_imports = ctx.module.num_imported_globals()
-            if global_idx < num_imports:
-                for imp in ctx.module.imports:
-                    if imp.kind == ImportKind.GLOBAL:
-                        if global_idx == 0:
-                            ctx.push_value(imp.desc.value_type)
-                            return
-                        global_idx -= 1
-            else:
-                local_idx = global_idx - num_imports
-                ctx.push_value(ctx.module.globals[local_idx].type.value_type)
-        return
+        global_type = _global_type(ctx, global_idx)
+        if global_type:
+            ctx.push_value(global_type.value_type)
+        return True
 
     # global.set
     if opcode == 0x24:
         global_idx = reader.read_u32_leb128()
-        num_globals = ctx.module.num_imported_globals() + len(ctx.module.globals)
-        if global_idx >= num_globals:
-            ctx.error(f"global index {global_idx} out of bounds")
+        global_type = _global_type(ctx, global_idx)
+        if global_type:
+            if not global_type.mutable:
+                ctx.error(f"cannot set immutable global {global_idx}")
+            ctx.pop_expect(global_type.value_type)
+        return True
+
+    # table.get
+    if opcode == 0x25:
+        table_idx = reader.read_u32_leb128()
+        elem_type = _table_elem_type(ctx, table_idx)
+        ctx.pop_expect(_table_address_type(ctx, table_idx))
+        ctx.push_value(elem_type)
+        return True
+
+    # table.set
+    if opcode == 0x26:
+        table_idx = reader.read_u32_leb128()
+        elem_type = _table_elem_type(ctx, table_idx)
+        if elem_type:
+            ctx.pop_expect(elem_type)
         else:
-            # Get global type and check mutability
-            num_imports = ctx.module.num_imported_globals()
-            if global_idx < num_imports:
-                for imp in ctx.module.imports:
-                    if imp.kind == ImportKind.GLOBAL:
-                        if global_idx == 0:
-                            if not imp.desc.mutable:
-                                ctx.error(f"cannot set immutable global {global_idx}")
-                            ctx.pop_expect(imp.desc.value_type)
-                            return
-                        global_idx -= 1
-            else:
-                local_idx = global_idx - num_imports
-                glob = ctx.module.globals[local_idx]
-                if not glob.type.mutable:
-                    ctx.error(f"cannot set immutable global {global_idx}")
-                ctx.pop_expect(glob.type.value_type)
-        return
+            ctx.pop_value()
+        ctx.pop_expect(_table_address_type(ctx, table_idx))
+        return True
 
     # Memory load/store instructions (0x28-0x3E)
     if 0x28 <= opcode <= 0x3E:
-        _align = reader.read_u32_leb128()
-        _offset = reader.read_u32_leb128()
-        _validate_memory_instruction(ctx, opcode)
-        return
+        addr_type = _read_memarg(ctx, reader, _NATURAL_ALIGNMENT[opcode - 0x28])
+        _validate_memory_instruction(ctx, opcode, addr_type)
+        return True
 
     # memory.size
     if opcode == 0x3F:
-        _mem_idx = reader.read_u32_leb128()
-        ctx.push_value(ValueType.I32)
-        return
+        addr_type = _memory_address_type(ctx, reader.read_u32_leb128())
+        ctx.push_value(addr_type)
+        return True
 
     # memory.grow
     if opcode == 0x40:
-        _mem_idx = reader.read_u32_leb128()
-        ctx.pop_expect(ValueType.I32)
-        ctx.push_value(ValueType.I32)
-        return
+        addr_type = _memory_address_type(ctx, reader.read_u32_leb128())
+        ctx.pop_expect(addr_type)
+        ctx.push_value(addr_type)
+        return True
 
     # i32.const
     if opcode == 0x41:
         reader.read_s32_leb128()
         ctx.push_value(ValueType.I32)
-        return
+        return True
 
     # i64.const
     if opcode == 0x42:
         reader.read_s64_leb128()
         ctx.push_value(ValueType.I64)
-        return
+        return True
 
     # f32.const
     if opcode == 0x43:
         reader.read_f32()
         ctx.push_value(ValueType.F32)
-        return
+        return True
 
     # f64.const
     if opcode == 0x44:
         reader.read_f64()
         ctx.push_value(ValueType.F64)
-        return
+        return True
 
-    # Numeric instructions - simplified validation
-    # i32 unary (0x45-0x4F, etc.)
-    if opcode in (0x45, 0x50, 0x67, 0x68, 0x69):  # eqz, clz, ctz, popcnt
-        if opcode == 0x50:  # i64.eqz returns i32
-            ctx.pop_expect(ValueType.I64)
-            ctx.push_value(ValueType.I32)
-        elif opcode == 0x45:  # i32.eqz
-            ctx.pop_expect(ValueType.I32)
-            ctx.push_value(ValueType.I32)
-        else:
-            ctx.pop_expect(ValueType.I32)
-            ctx.push_value(ValueType.I32)
-        return
+    # Numeric instructions (0x45-0xA6) and sign extension (0xC0-0xC4)
+    signature = _numeric_signature(opcode)
+    if signature is not None:
+        operands, result = signature
+        ctx.pop_values(operands)
+        ctx.push_value(result)
+        return True
 
-    # i32 binary (0x46-0x4E, 0x6A-0x78)
-    if 0x46 <= opcode <= 0x4E or 0x6A <= opcode <= 0x78:
-        ctx.pop_expect(ValueType.I32)
-        ctx.pop_expect(ValueType.I32)
-        ctx.push_value(ValueType.I32)
-        return
+    # Conversion instructions (0xA7-0xBF)
+    if 0xA7 <= opcode <= 0xBF:
+        _validate_conversion_instruction(ctx, opcode)
+        return True
 
-    # i64 comparison (0x51-0x5A)
-    if 0x51 <= opcode <= 0x5A:
-        ctx.pop_expect(ValueType.I64)
-        ctx.pop_expect(ValueType.I64)
-        ctx.push_value(ValueType.I32)  # comparisons return i32
-        return
+    # Reference instructions (0xD0-0xD6)
+    if 0xD0 <= opcode <= 0xD6:
+        _validate_reference_instruction(ctx, opcode, reader)
+        return True
 
-    # i64 binary (0x7C-0x8A)
-    if 0x7C <= opcode <= 0x8A:
-        ctx.pop_expect(ValueType.I64)
-        ctx.pop_expect(ValueType.I64)
-        ctx.push_value(ValueType.I64)
-        return
+    # GC instructions (0xFB prefix)
+    if opcode == 0xFB:
+        sub_opcode = reader.read_u32_leb128()
+        return _validate_gc_instruction(ctx, sub_opcode, reader)
 
-    # f32 comparison (0x5B-0x60)
-    if 0x5B <= opcode <= 0x60:
-        ctx.pop_expect(ValueType.F32)
-        ctx.pop_expect(ValueType.F32)
-        ctx.push_value(ValueType.I32)
-        return
+    # Extended instructions (0xFC prefix)
+    if opcode == 0xFC:
+        sub_opcode = reader.read_u32_leb128()
+        return _validate_fc_instruction(ctx, sub_opcode, reader)
 
-    # f64 comparison (0x61-0x66)
-    if 0x61 <= opcode <= 0x66:
-        ctx.pop_expect(ValueType.F64)
-        ctx.pop_expect(ValueType.F64)
-        ctx.push_value(ValueType.I32)
-        return
+    # Unknown opcode: leave it to the compiler to reject or handle
+    ctx.warning(f"unvalidated opcode 0x{opcode:02x}")
+    return False
 
-    # f32 unary (0x8B-0x91)
-    if 0x8B <= opcode <= 0x91:
-        ctx.pop_expect(ValueType.F32)
-        ctx.push_value(ValueType.F32)
-        return
 
-    # f32 binary (0x92-0x98)
-    if 0x92 <= opcode <= 0x98:
-        ctx.pop_expect(ValueType.F32)
-        ctx.pop_expect(ValueType.F32)
-        ctx.push_value(ValueType.F32)
+def _validate_call(ctx: ValidationContext, func_type: FuncType, *, is_tail: bool) -> None:
+    """Validate the operands and results of a (tail) call."""
+    ctx.pop_values(func_type.params)
+    if not is_tail:
+        ctx.push_values(func_type.results)
         return
-
-    # f64 unary (0x99-0x9F)
-    if 0x99 <= opcode <= 0x9F:
-        ctx.pop_expect(ValueType.F64)
-        ctx.push_value(ValueType.F64)
+    if ctx.current_func_type and func_type.results != ctx.current_func_type.results:
+        ctx.error(
+            f"tail call results {func_type.results} don't match function results {ctx.current_func_type.results}"
+        )
+    ctx.set_unreachable()
+
+
+def _numeric_signature(
+    opcode: int,
+) -> tuple[tuple[ValueType, ...], ValueType] | None:
+    """Operand and result types of a numeric instruction."""
+    i32, i64, f32, f64 = ValueType.I32, ValueType.I64, ValueType.F32, ValueType.F64
+    ranges = (
+        (0x45, 0x45, (i32,), i32),  # i32.eqz
+        (0x46, 0x4F, (i32, i32), i32),  # i32 comparisons
+        (0x50, 0x50, (i64,), i32),  # i64.eqz
+        (0x51, 0x5A, (i64, i64), i32),  # i64 comparisons
+        (0x5B, 0x60, (f32, f32), i32),  # f32 comparisons
+        (0x61, 0x66, (f64, f64), i32),  # f64 comparisons
+        (0x67, 0x69, (i32,), i32),  # i32 clz, ctz, popcnt
+        (0x6A, 0x78, (i32, i32), i32),  # i32 binary
+        (0x79, 0x7B, (i64,), i64),  # i64 clz, ctz, popcnt
+        (0x7C, 0x8A, (i64, i64), i64),  # i64 binary
+        (0x8B, 0x91, (f32,), f32),  # f32 unary
+        (0x92, 0x98, (f32, f32), f32),  # f32 binary
+        (0x99, 0x9F, (f64,), f64),  # f64 unary
+        (0xA0, 0xA6, (f64, f64), f64),  # f64 binary
+        (0xC0, 0xC1, (i32,), i32),  # i32.extend8_s, i32.extend16_s
+        (0xC2, 0xC4, (i64,), i64),  # i64.extend8_s, 16_s, 32_s
+    )
+    for first, last, operands, result in ranges:
+        if first <= opcode <= last:
+            return operands, result
+    return None
+
+
+# log2 of the access size of the loads and stores 0x28-0x3E
+_NATURAL_ALIGNMENT = (
+    *(2, 3, 2, 3),  # i32, i64, f32, f64 loads
+    *(0, 0, 1, 1, 0, 0, 1, 1, 2, 2),  # narrow integer loads
+    *(2, 3, 2, 3),  # i32, i64, f32, f64 stores
+    *(0, 1, 0, 1, 2),  # narrow integer stores
+)
+
+
+def _read_memarg(
+    ctx: ValidationContext, reader: BinaryReader, natural_align: int
+) -> ValueType:
+    """Read a memarg, returning the address type of the memory it names.
+
+    With multi-memory, bit 6 of the alignment field signals that an explicit
+    memory index follows. The alignment (log2) must not exceed
+    `natural_align`, that of the access size.
+    """
+    align = reader.read_u32_leb128()
+    memory_idx = reader.read_u32_leb128() if align & 0x40 else 0
+    if align & ~0x40 > natural_align:
+        ctx.error(
+            f"alignment {1 << (align & ~0x40)} exceeds the access size "
+            f"{1 << natural_align}"
+        )
+    addr_type = _memory_address_type(ctx, memory_idx)
+    if addr_type == ValueType.I64:
+        reader.read_u64_leb128()
+    else:
+        reader.read_u32_leb128()
+    return addr_type
+
+
+def _memory_address_type(ctx: ValidationContext, memory_idx: int) -> ValueType:
+    """Address type (i32, or i64 for memory64) of a memory."""
+    memories = [
+        imp.desc for imp in ctx.module.imports if imp.kind == ImportKind.MEMORY
+    ] + list(ctx.module.memories)
+    if memory_idx >= len(memories):
+        ctx.error(f"memory index {memory_idx} out of bounds")
+        return ValueType.I32
+    memory = memories[memory_idx]
+    if isinstance(memory, MemoryType) and memory.is_memory64:
+        return ValueType.I64
+    return ValueType.I32
+
+
+def _table_elem_type(ctx: ValidationContext, table_idx: int) -> ValueType | None:
+    """Element type of a table, or None if it doesn't exist."""
+    tables = [
+        imp.desc for imp in ctx.module.imports if imp.kind == ImportKind.TABLE
+    ] + list(ctx.module.tables)
+    if table_idx >= len(tables):
+        ctx.error(f"table index {table_idx} out of bounds")
+        return None
+    table = tables[table_idx]
+    return table.elem_type if isinstance(table, TableType) else None
+
+
+def _table_address_type(ctx: ValidationContext, table_idx: int) -> ValueType:
+    """Index type (i32, or i64 for table64) of a table."""
+    tables = [
+        imp.desc for imp in ctx.module.imports if imp.kind == ImportKind.TABLE
+    ] + list(ctx.module.tables)
+    if table_idx < len(tables):
+        table = tables[table_idx]
+        if isinstance(table, TableType) and table.is_table64:
+            return ValueType.I64
+    return ValueType.I32
+
+
+def _global_type(ctx: ValidationContext, global_idx: int) -> GlobalType | None:
+    """Type of a global, or None if it doesn't exist."""
+    globals_ = [
+        imp.desc for imp in ctx.module.imports if imp.kind == ImportKind.GLOBAL
+    ] + [glob.type for glob in ctx.module.globals]
+    if global_idx >= len(globals_):
+        ctx.error(f"global index {global_idx} out of bounds")
+        return None
+    return globals_[global_idx]
+
+
+def _validate_catch_clause(ctx: ValidationContext, reader: BinaryReader) -> None:
+    """Validate a try_table catch clause against its target label."""
+    kind = reader.read_byte()
+    if kind > 0x03:
+        ctx.error(f"invalid catch clause kind 0x{kind:02x}")
         return
-
-    # f64 binary (0xA0-0xA6)
-    if 0xA0 <= opcode <= 0xA6:
-        ctx.pop_expect(ValueType.F64)
-        ctx.pop_expect(ValueType.F64)
-        ctx.push_value(ValueType.F64)
+    values: tuple[ValueType, ...] = ()
+    if kind in (0x00, 0x01):  # catch, catch_ref
+        tag_type = _tag_type(ctx, reader.read_u32_leb128())
+        if tag_type is None:
+            reader.read_u32_leb128()
+            return
+        values = tag_type.params
+    if kind in (0x01, 0x03):  # catch_ref, catch_all_ref
+        values = (*values, ValueType.EXNREF)
+    frame = ctx.get_frame(reader.read_u32_leb128())
+    if frame is None:
         return
+    expected = frame.label_types
+    if len(values) != len(expected) or not all(
+        types_match(actual, label)
+        for actual, label in zip(values, expected, strict=True)
+    ):
+        ctx.error(
+            f"catch clause provides ({', '.join(map(str, values))}) "
+            f"but label expects ({', '.join(map(str, expected))})"
+        )
 
-    # Conversion instructions (0xA7-0xBF)
-    if 0xA7 <= opcode <= 0xBF:
-        _validate_conversion_instruction(ctx, opcode)
-        return
 
-    # Extended instructions (0xFC prefix)
-    if opcode == 0xFC:
-        sub_opcode = reader.read_u32_leb128()
-        _validate_fc_instruction(ctx, sub_opcode, reader)
-        return
+def _tag_type(ctx: ValidationContext, tag_idx: int) -> FuncType | None:
+    """Parameter signature of a tag, or None if it is invalid."""
+    try:
+        return ctx.module.get_tag_type(tag_idx)
+    except ValueError as e:
+        ctx.error(str(e))
+        return None
 
-    # For other opcodes, just skip operands and issue a warning
-    ctx.warning(f"unvalidated opcode 0x{opcode:02x}")
+
+def _func_type_at(ctx: ValidationContext, type_idx: int, what: str) -> FuncType | None:
+    """Function type at a type index, reporting bad indices."""
+    if type_idx >= len(ctx.module.types):
+        ctx.error(f"{what}: type index {type_idx} out of bounds")
+        return None
+    type_def = ctx.module.types[type_idx]
+    if not isinstance(type_def, FuncType):
+        ctx.error(f"{what}: type index {type_idx} is not a function type")
+        return None
+    return type_def
 
 
-def _validate_memory_instruction(ctx: ValidationContext, opcode: int) -> None:
+def _validate_memory_instruction(
+    ctx: ValidationContext, opcode: int, addr_type: ValueType
+) -> None:
     """Validate a memory load/store instruction's stack effect."""
     # Loads
     if opcode in (0x28, 0x2C, 0x2D, 0x2E, 0x2F):  # i32.load, i32.load8_s/u, i32.load16_s/u
-        ctx.pop_expect(ValueType.I32)  # address
+        ctx.pop_expect(addr_type)  # address
         ctx.push_value(ValueType.I32)
     elif opcode in (0x29, 0x30, 0x31, 0x32, 0x33, 0x34, 0x35):  # i64.load variants
-        ctx.pop_expect(ValueType.I32)  # address
+        ctx.pop_expect(addr_type)  # address
         ctx.push_value(ValueType.I64)
     elif opcode == 0x2A:  # f32.load
-        ctx.pop_expect(ValueType.I32)
+        ctx.pop_expect(addr_type)
         ctx.push_value(ValueType.F32)
     elif opcode == 0x2B:  # f64.load
-        ctx.pop_expect(ValueType.I32)
+        ctx.pop_expect(addr_type)
         ctx.push_value(ValueType.F64)
     # Stores
     elif opcode in (0x36, 0x3A, 0x3B):  # i32.store, i32.store8, i32.store16
         ctx.pop_expect(ValueType.I32)  # value
-        ctx.pop_expect(ValueType.I32)  # address
+        ctx.pop_expect(addr_type)  # address
     elif opcode in (0x37, 0x3C, 0x3D, 0x3E):  # i64.store variants
         ctx.pop_expect(ValueType.I64)  # value
-        ctx.pop_expect(ValueType.I32)  # address
+        ctx.pop_expect(addr_type)  # address
     elif opcode == 0x38:  # f32.store
         ctx.pop_expect(ValueType.F32)
-        ctx.pop_expect(ValueType.I32)
+        ctx.pop_expect(addr_type)
     elif opcode == 0x39:  # f64.store
         ctx.pop_expect(ValueType.F64)
-        ctx.pop_expect(ValueType.I32)
+        ctx.pop_expect(addr_type)
 
 
 def _validate_conversion_instruction(ctx: ValidationContext, opcode: int) -> None:
@@ -591,11 +774,264 @@ def _validate_conversion_instruction(ctx: ValidationContext, opcode: int) -> Non
         ctx.pop_expect(ValueType.I64)
         ctx.push_value(ValueType.F64)
 
+def _validate_reference_instruction(
+    ctx: ValidationContext, opcode: int, reader: BinaryReader
+) -> None:
+    """Validate a reference instruction (0xD0-0xD6)."""
+    # ref.null
+    if opcode == 0xD0:
+        ctx.push_value(_heap_value_type(ctx, reader.read_s32_leb128()))
+    # ref.is_null
+    elif opcode == 0xD1:
+        ctx.pop_reference()
+        ctx.push_value(ValueType.I32)
+    # ref.func
+    elif opcode == 0xD2:
+        func_idx = reader.read_u32_leb128()
+        if (
+            ctx.get_func_type(func_idx) is not None
+            and func_idx not in ctx.declared_funcs
+        ):
+            ctx.error(f"ref.func of undeclared function {func_idx}")
+        ctx.push_value(ValueType.FUNCREF)
+    # ref.eq
+    elif opcode == 0xD3:
+        ctx.pop_reference()
+        ctx.pop_reference()
+        ctx.push_value(ValueType.I32)
+    # ref.as_non_null
+    elif opcode == 0xD4:
+        ctx.push_value(ctx.pop_reference())
+    # br_on_null: the reference stays on the stack when not taken
+    elif opcode == 0xD5:
+        frame = ctx.get_frame(reader.read_u32_leb128())
+        ref = ctx.pop_reference()
+        if frame is not None:
+            ctx.pop_values(frame.label_types)
+            ctx.push_values(frame.label_types)
+        ctx.push_value(ref)
+    # br_on_non_null: the reference is passed to the label when taken
+    elif opcode == 0xD6:
+        frame = ctx.get_frame(reader.read_u32_leb128())
+        ctx.pop_reference()
+        if frame is not None:
+            if not frame.label_types or not frame.label_types[-1].is_reference():
+                ctx.error("br_on_non_null target must take a reference")
+            else:
+                ctx.pop_values(frame.label_types[:-1])
+                ctx.push_values(frame.label_types[:-1])
+
+
+def _heap_value_type(ctx: ValidationContext, heap_type: int) -> ValueType:
+    """Reference ValueType for a heap type immediate.
+
+    Abstract heap types are encoded as negative s33 values; concrete ones
+    are type indices.
+    """
+    if heap_type < 0:
+        try:
+            vtype = ValueType(heap_type + 0x80)
+        except ValueError:
+            vtype = None
+        if vtype is not None and vtype.is_reference():
+            return vtype
+        # func/extern/any/... without the "ref" suffix share an encoding
+        abstract = {0x70: ValueType.FUNCREF, 0x6F: ValueType.EXTERNREF}
+        return abstract.get(heap_type + 0x80, ValueType.ANYREF)
+    if heap_type < len(ctx.module.types):
+        match ctx.module.types[heap_type]:
+            case FuncType():
+                return ValueType.FUNCREF
+            case StructType():
+                return ValueType.STRUCTREF
+            case ArrayType():
+                return ValueType.ARRAYREF
+    ctx.error(f"heap type index {heap_type} out of bounds")
+    return ValueType.ANYREF
+
+
+def _storage_value_type(ctx: ValidationContext, field_type: FieldType) -> ValueType:
+    """Stack type of a struct field or array element."""
+    storage = field_type.storage_type
+    if not isinstance(storage, ValueType):
+        # Reference to a heap type
+        return _heap_value_type(ctx, storage)
+    if storage in (ValueType.I8, ValueType.I16):
+        return ValueType.I32
+    return storage
+
+
+def _struct_fields(ctx: ValidationContext, type_idx: int) -> tuple[FieldType, ...] | None:
+    """Fields of a struct type, reporting bad type indices."""
+    try:
+        return ctx.module.get_struct_type(type_idx).fields
+    except ValueError as e:
+        ctx.error(str(e))
+        return None
+
+
+def _array_element(ctx: ValidationContext, type_idx: int) -> ValueType | None:
+    """Element stack type of an array type, reporting bad type indices."""
+    try:
+        return _storage_value_type(
+            ctx, ctx.module.get_array_type(type_idx).element_type
+        )
+    except ValueError as e:
+        ctx.error(str(e))
+        return None
+
+
+def _validate_gc_instruction(
+    ctx: ValidationContext, sub_opcode: int, reader: BinaryReader
+) -> bool:
+    """Validate a 0xFB-prefixed (GC) instruction.
+
+    Returns False for unknown sub-opcodes.
+    """
+    i32 = ValueType.I32
+
+    # struct.new, struct.new_default
+    if sub_opcode in (0x00, 0x01):
+        fields = _struct_fields(ctx, reader.read_u32_leb128())
+        if sub_opcode == 0x00 and fields is not None:
+            ctx.pop_values(tuple(_storage_value_type(ctx, f) for f in fields))
+        ctx.push_value(ValueType.STRUCTREF)
+        return True
+
+    # struct.get, struct.get_s, struct.get_u, struct.set
+    if 0x02 <= sub_opcode <= 0x05:
+        fields = _struct_fields(ctx, reader.read_u32_leb128())
+        field_idx = reader.read_u32_leb128()
+        field_type = None
+        if fields is not None:
+            if field_idx < len(fields):
+                field_type = _storage_value_type(ctx, fields[field_idx])
+            else:
+                ctx.error(f"struct field index {field_idx} out of bounds")
+        if sub_opcode == 0x05:
+            if field_type:
+                ctx.pop_expect(field_type)
+            else:
+                ctx.pop_value()
+            ctx.pop_reference()
+        else:
+            ctx.pop_reference()
+            ctx.push_value(field_type)
+        return True
+
+    # array.new, array.new_default, array.new_fixed, array.new_data, array.new_elem
+    if 0x06 <= sub_opcode <= 0x0A:
+        elem = _array_element(ctx, reader.read_u32_leb128())
+        if sub_opcode == 0x08:
+            count = reader.read_u32_leb128()
+            ctx.pop_values((elem,) * count if elem else ())
+        elif sub_opcode in (0x09, 0x0A):
+            reader.read_u32_leb128()  # data or element segment
+            ctx.pop_values((i32, i32))
+        else:
+            ctx.pop_expect(i32)  # length
+            if sub_opcode == 0x06 and elem:
+                ctx.pop_expect(elem)
+        ctx.push_value(ValueType.ARRAYREF)
+        return True
+
+    # array.get, array.get_s, array.get_u
+    if 0x0B <= sub_opcode <= 0x0D:
+        elem = _array_element(ctx, reader.read_u32_leb128())
+        ctx.pop_expect(i32)
+        ctx.pop_reference()
+        ctx.push_value(elem)
+        return True
+
+    # array.set, array.fill
+    if sub_opcode in (0x0E, 0x10):
+        elem = _array_element(ctx, reader.read_u32_leb128())
+        if sub_opcode == 0x10:
+            ctx.pop_expect(i32)  # count
+        if elem:
+            ctx.pop_expect(elem)
+        else:
+            ctx.pop_value()
+        ctx.pop_expect(i32)
+        ctx.pop_reference()
+        return True
+
+    # array.len
+    if sub_opcode == 0x0F:
+        ctx.pop_reference()
+        ctx.push_value(i32)
+        return True
+
+    # array.copy
+    if sub_opcode == 0x11:
+        _array_element(ctx, reader.read_u32_leb128())
+        _array_element(ctx, reader.read_u32_leb128())
+        ctx.pop_values((i32, i32))
+        ctx.pop_reference()
+        ctx.pop_expect(i32)
+        ctx.pop_reference()
+        return True
+
+    # array.init_data, array.init_elem
+    if sub_opcode in (0x12, 0x13):
+        _array_element(ctx, reader.read_u32_leb128())
+        reader.read_u32_leb128()  # data or element segment
+        ctx.pop_values((i32, i32, i32))
+        ctx.pop_reference()
+        return True
+
+    # ref.test, ref.test null, ref.cast, ref.cast null
+    if 0x14 <= sub_opcode <= 0x17:
+        heap_type = reader.read_s32_leb128()
+        ctx.pop_reference()
+        if sub_opcode <= 0x15:
+            ctx.push_value(i32)
+        else:
+            ctx.push_value(_heap_value_type(ctx, heap_type))
+        return True
+
+    # br_on_cast, br_on_cast_fail
+    if sub_opcode in (0x18, 0x19):
+        reader.read_byte()  # nullability flags
+        frame = ctx.get_frame(reader.read_u32_leb128())
+        reader.read_s32_leb128()  # source heap type
+        reader.read_s32_leb128()  # target heap type
+        ref = ctx.pop_reference()
+        if frame is not None and frame.label_types:
+            ctx.pop_values(frame.label_types[:-1])
+            ctx.push_values(frame.label_types[:-1])
+        ctx.push_value(ref)
+        return True
+
+    # any.convert_extern, extern.convert_any
+    if sub_opcode in (0x1A, 0x1B):
+        ctx.pop_reference()
+        ctx.push_value(ValueType.ANYREF if sub_opcode == 0x1A else ValueType.EXTERNREF)
+        return True
+
+    # ref.i31
+    if sub_opcode == 0x1C:
+        ctx.pop_expect(i32)
+        ctx.push_value(ValueType.I31REF)
+        return True
+
+    # i31.get_s, i31.get_u
+    if sub_opcode in (0x1D, 0x1E):
+        ctx.pop_reference()
+        ctx.push_value(i32)
+        return True
+
+    ctx.warning(f"unvalidated 0xFB sub-opcode 0x{sub_opcode:02x}")
+    return False
+
 
 def _validate_fc_instruction(
     ctx: ValidationContext, sub_opcode: int, reader: BinaryReader
-) -> None:
-    """Validate a 0xFC-prefixed instruction."""
+) -> bool:
+    """Validate a 0xFC-prefixed instruction.
+
+    Returns False for unknown sub-opcodes.
+    """
     # Saturating conversions (0x00-0x07)
     if sub_opcode <= 0x07:
         if sub_opcode in (0x00, 0x01):  # i32.trunc_sat_f32
@@ -610,54 +1046,120 @@ def _validate_fc_instruction(
         elif sub_opcode in (0x06, 0x07):  # i64.trunc_sat_f64
             ctx.pop_expect(ValueType.F64)
             ctx.push_value(ValueType.I64)
-        return
+        return True
 
     # memory.init
     if sub_opcode == 0x08:
         _data_idx = reader.read_u32_leb128()
-        _mem_idx = reader.read_u32_leb128()
+        addr_type = _memory_address_type(ctx, reader.read_u32_leb128())
         ctx.pop_expect(ValueType.I32)  # n
         ctx.pop_expect(ValueType.I32)  # s
-        ctx.pop_expect(ValueType.I32)  # d
-        return
+        ctx.pop_expect(addr_type)  # d
+        return True
 
     # data.drop
     if sub_opcode == 0x09:
         _data_idx = reader.read_u32_leb128()
-        return
+        return True
 
     # memory.copy
     if sub_opcode == 0x0A:
-        _dst_mem = reader.read_u32_leb128()
-        _src_mem = reader.read_u32_leb128()
-        ctx.pop_expect(ValueType.I32)  # n
-        ctx.pop_expect(ValueType.I32)  # s
-        ctx.pop_expect(ValueType.I32)  # d
-        return
+        dst_type = _memory_address_type(ctx, reader.read_u32_leb128())
+        src_type = _memory_address_type(ctx, reader.read_u32_leb128())
+        # The length is i64 only if both memories are 64-bit
+        both_64 = dst_type == src_type == ValueType.I64
+        ctx.pop_expect(ValueType.I64 if both_64 else ValueType.I32)  # n
+        ctx.pop_expect(src_type)  # s
+        ctx.pop_expect(dst_type)  # d
+        return True
 
     # memory.fill
     if sub_opcode == 0x0B:
-        _mem_idx = reader.read_u32_leb128()
-        ctx.pop_expect(ValueType.I32)  # n
+        addr_type = _memory_address_type(ctx, reader.read_u32_leb128())
+        ctx.pop_expect(addr_type)  # n
         ctx.pop_expect(ValueType.I32)  # val
-        ctx.pop_expect(ValueType.I32)  # d
-        return
+        ctx.pop_expect(addr_type)  # d
+        return True
+
+    # table.init
+    if sub_opcode == 0x0C:
+        _elem_idx = reader.read_u32_leb128()
+        table_idx = reader.read_u32_leb128()
+        _table_elem_type(ctx, table_idx)
+        ctx.pop_expect(ValueType.I32)  # n
+        ctx.pop_expect(ValueType.I32)  # s
+        ctx.pop_expect(_table_address_type(ctx, table_idx))  # d
+        return True
+
+    # elem.drop
+    if sub_opcode == 0x0D:
+        _elem_idx = reader.read_u32_leb128()
+        return True
+
+    # table.copy
+    if sub_opcode == 0x0E:
+        dst_idx = reader.read_u32_leb128()
+        src_idx = reader.read_u32_leb128()
+        _table_elem_type(ctx, dst_idx)
+        _table_elem_type(ctx, src_idx)
+        dst_type = _table_address_type(ctx, dst_idx)
+        src_type = _table_address_type(ctx, src_idx)
+        # The length is i64 only if both tables are 64-bit
+        both_64 = dst_type == src_type == ValueType.I64
+        ctx.pop_expect(ValueType.I64 if both_64 else ValueType.I32)  # n
+        ctx.pop_expect(src_type)  # s
+        ctx.pop_expect(dst_type)  # d
+        return True
+
+    # table.grow
+    if sub_opcode == 0x0F:
+        table_idx = reader.read_u32_leb128()
+        _table_elem_type(ctx, table_idx)
+        addr_type = _table_address_type(ctx, table_idx)
+        ctx.pop_expect(addr_type)  # delta
+        ctx.pop_reference()  # init value
+        ctx.push_value(addr_type)
+        return True
+
+    # table.size
+    if sub_opcode == 0x10:
+        table_idx = reader.read_u32_leb128()
+        _table_elem_type(ctx, table_idx)
+        ctx.push_value(_table_address_type(ctx, table_idx))
+        return True
+
+    # table.fill
+    if sub_opcode == 0x11:
+        table_idx = reader.read_u32_leb128()
+        _table_elem_type(ctx, table_idx)
+        addr_type = _table_address_type(ctx, table_idx)
+        ctx.pop_expect(addr_type)  # n
+        ctx.pop_reference()  # value
+        ctx.pop_expect(addr_type)  # i
+        return True
 
-    # table operations (0x0C-0x11)
-    # Skip for now
     ctx.warning(f"unvalidated 0xFC sub-opcode 0x{sub_opcode:02x}")
+    return False
 
 
 def _validate_globals(ctx: ValidationContext) -> None:
     """Validate global section."""
+    num_imports = ctx.module.num_imported_globals()
     for i, glob in enumerate(ctx.module.globals):
-        # Validate init expression type matches global type
-        # (simplified - just check it's not empty)
         if not glob.init_expr:
             ctx.result.add_error(
                 f"global {i} has empty init expression",
                 location=f"global {i}",
             )
+            continue
+        # Initializers can only read the globals before them
+        _validate_const_expr(
+            ctx,
+            glob.init_expr,
+            glob.type.value_type,
+            f"global {i}",
+            num_globals=num_imports + i,
+        )
 
 
 def _validate_tables(ctx: ValidationContext) -> None:
@@ -668,6 +1170,137 @@ def _validate_tables(ctx: ValidationContext) -> None:
                 f"table {i}: min {table.limits.min} > max {table.limits.max}",
                 location=f"table {i}",
             )
+        if i in ctx.module.table_inits:
+            _validate_const_expr(
+                ctx, ctx.module.table_inits[i], table.elem_type, f"table {i}"
+            )
+
+
+def _validate_data(ctx: ValidationContext) -> None:
+    """Validate the memory index and offset of active data segments."""
+    num_memories = ctx.module.num_imported_memories() + len(ctx.module.memories)
+    for i, segment in enumerate(ctx.module.data):
+        if segment.memory_idx == -1:
+            continue  # Passive
+        if segment.memory_idx >= num_memories:
+            ctx.result.add_error(
+                f"data segment {i}: memory index {segment.memory_idx} out of bounds",
+                location=f"data segment {i}",
+            )
+            continue
+        addr_type = _memory_address_type(ctx, segment.memory_idx)
+        _validate_const_expr(ctx, segment.offset_expr, addr_type, f"data segment {i}")
+
+
+def _validate_elements(ctx: ValidationContext) -> None:
+    """Validate element segments and the table and offset of active ones."""
+    num_funcs = ctx.module.num_imported_funcs() + len(ctx.module.func_types)
+    num_tables = ctx.module.num_imported_tables() + len(ctx.module.tables)
+    for i, segment in enumerate(ctx.module.elements):
+        location = f"element segment {i}"
+        for func_idx in segment.func_indices:
+            if func_idx >= num_funcs:
+                ctx.result.add_error(
+                    f"{location}: function index {func_idx} out of bounds",
+                    location=location,
+                )
+        if segment.table_idx < 0:
+            continue  # Passive or declarative
+        if segment.table_idx >= num_tables:
+            ctx.result.add_error(
+                f"{location}: table index {segment.table_idx} out of bounds",
+                location=location,
+            )
+            continue
+        elem_type = _table_elem_type(ctx, segment.table_idx)
+        if elem_type is not None and not types_match(ValueType.FUNCREF, elem_type):
+            ctx.result.add_error(
+                f"{location}: functions cannot be stored in a table of {elem_type}",
+                location=location,
+            )
+        addr_type = _table_address_type(ctx, segment.table_idx)
+        _validate_const_expr(ctx, segment.offset_expr, addr_type, location)
+
+
+def _validate_const_expr(
+    ctx: ValidationContext,
+    expr: bytes,
+    expected: ValueType,
+    location: str,
+    num_globals: int | None = None,
+) -> None:
+    """Check that a constant expression produces one value of type `expected`.
+
+    Constant expressions are made of constants, ref.null, ref.func,
+    global.get of an immutable global (one of the first `num_globals`, if
+    given) and the extended-const integer add, sub and mul. Functions they
+    refer to are declared for ref.func in function bodies. GC instructions
+    are left to the compiler.
+    """
+    globals_ = [
+        imp.desc for imp in ctx.module.imports if imp.kind == ImportKind.GLOBAL
+    ] + [glob.type for glob in ctx.module.globals]
+    if num_globals is not None:
+        globals_ = globals_[:num_globals]
+    num_funcs = ctx.module.num_imported_funcs() + len(ctx.module.func_types)
+    i32, i64 = ValueType.I32, ValueType.I64
+    binary_ops = {0x6A: i32, 0x6B: i32, 0x6C: i32, 0x7C: i64, 0x7D: i64, 0x7E: i64}
+
+    def error(message: str) -> None:
+        ctx.result.add_error(f"{location}: {message}", location=location)
+
+    reader = BinaryReader(expr, types=ctx.module.types)
+    stack: list[ValueType] = []
+    while not reader.at_end:
+        opcode = reader.read_byte()
+        if opcode == 0x0B:  # end
+            break
+        if opcode == 0x41:  # i32.const
+            reader.read_s32_leb128()
+            stack.append(i32)
+        elif opcode == 0x42:  # i64.const
+            reader.read_s64_leb128()
+            stack.append(i64)
+        elif opcode == 0x43:  # f32.const
+            reader.read_f32()
+            stack.append(ValueType.F32)
+        elif opcode == 0x44:  # f64.const
+            reader.read_f64()
+            stack.append(ValueType.F64)
+        elif opcode == 0xD0:  # ref.null
+            stack.append(_heap_value_type(ctx, reader.read_s32_leb128()))
+        elif opcode == 0xD2:  # ref.func
+            func_idx = reader.read_u32_leb128()
+            if func_idx >= num_funcs:
+                error(f"function index {func_idx} out of bounds")
+                return
+            ctx.declared_funcs.add(func_idx)
+            stack.append(ValueType.FUNCREF)
+        elif opcode == 0x23:  # global.get
+            global_idx = reader.read_u32_leb128()
+            if global_idx >= len(globals_):
+                error(f"global index {global_idx} out of bounds")
+                return
+            global_type = globals_[global_idx]
+            assert isinstance(global_type, GlobalType)
+            if global_type.mutable:
+                error(f"global.get of mutable global {global_idx} is not constant")
+                return
+            stack.append(global_type.value_type)
+        elif opcode in binary_ops:
+            vtype = binary_ops[opcode]
+            if stack[-2:] != [vtype, vtype]:
+                error(f"type mismatch: expected two {vtype} operands")
+                return
+            stack.pop()
+        elif opcode == 0xFB:
+            return
+        else:
+            error(f"instruction 0x{opcode:02x} is not constant")
+            return
+    if len(stack) != 1 or not types_match(stack[0], expected):
+        produced = ", ".join(map(str, stack)) or "nothing"
+        error(f"type mismatch: expected {expected}, got {produced}")
 
 
 def _validate_memories(ctx: ValidationContext) -> None:
@@ -694,6 +1327,22 @@ def _validate_memories(ctx: ValidationContext) -> None:
             )
 
 
+def _validate_tags(ctx: ValidationContext) -> None:
+    """Validate tag section and tag imports."""
+    num_tags = ctx.module.num_imported_tags() + len(ctx.module.tags)
+    for i in range(num_tags):
+        try:
+            tag_type = ctx.module.get_tag_type(i)
+        except ValueError as e:
+            ctx.result.add_error(f"tag {i}: {e}", location=f"tag {i}")
+            continue
+        if tag_type.results:
+            ctx.result.add_error(
+                f"tag {i}: tag type must not have results",
+                location=f"tag {i}",
+            )
+
+
 def _validate_start(ctx: ValidationContext) -> None:
     """Validate start section."""
     if ctx.module.start is None:
@@ -720,23 +1369,18 @@ def _validate_start(ctx: ValidationContext) -> None:
             location="start section",
         )
 
-
-def _block_type_to_results(
-    block_type, ctx: ValidationContext
-) -> tuple[ValueType, ...]:
-    """Convert block type to result types."""
+def _block_type(
+    ctx: ValidationContext, block_type: BlockType
+) -> tuple[tuple[ValueType, ...], tuple[ValueType, ...]]:
+    """Convert a block type to its (params, results)."""
     if block_type is None:
-        return ()
+        return (), ()
     if isinstance(block_type, ValueType):
-        return (block_type,)
-    if isinstance(block_type, int):
-        # Type index
-        if block_type >= len(ctx.module.types):
-            ctx.error(f"block type index {block_type} out of bounds")
-            return ()
-        type_def = ctx.module.types[block_type]
-        if isinstance(type_def, FuncType):
-            return type_def.results
-        ctx.error(f"block type index {block_type} is not a function type")
-        return ()
-    return ()
+        return (), (block_type,)
+    if block_type < 0:
+        # Abstract reference types other than funcref/externref
+        return (), (_heap_value_type(ctx, block_type),)
+    func_type = _func_type_at(ctx, block_type, "block type")
+    if func_type is None:
+        return (), ()
+    return func_type.params, func_type.results
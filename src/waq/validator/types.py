@@ -5,7 +5,7 @@ from __future__ import annotations
 from dataclasses import dataclass, field
 from enum import Enum, auto
 
-from waq.parser.module import WasmModule
+from waq.parser.module import ExportKind, WasmModule
 from waq.parser.types import FuncType, ValueType
 
 
@@ -99,12 +99,48 @@ class ValidationResult:
 class ControlFrame:
     """Control frame for validation stack tracking."""
 
-    kind: str  # "block", "loop", "if"
+    # "function", "block", "loop", "if", "else", "try", "catch", "try_table"
+    kind: str
     start_depth: int
     result_types: tuple[ValueType, ...]
     param_types: tuple[ValueType, ...] = ()
     unreachable: bool = False
 
+    @property
+    def label_types(self) -> tuple[ValueType, ...]:
+        """Types a branch to this frame must provide."""
+        return self.param_types if self.kind == "loop" else self.result_types
+
+
+# Top types of the reference type hierarchies other than any's
+_REF_HIERARCHIES = {
+    ValueType.FUNCREF: ValueType.FUNCREF,
+    ValueType.NULLFUNCREF: ValueType.FUNCREF,
+    ValueType.EXTERNREF: ValueType.EXTERNREF,
+    ValueType.NULLEXTERNREF: ValueType.EXTERNREF,
+    ValueType.EXNREF: ValueType.EXNREF,
+    ValueType.NULLEXNREF: ValueType.EXNREF,
+}
+
+
+def types_match(actual: ValueType, expected: ValueType) -> bool:
+    """Check that a value of type `actual` can be used as `expected`.
+
+    Concrete GC reference types are folded into their abstract ValueType by
+    the parsers, so reference types are treated as compatible when they
+    belong to the same hierarchy (func, extern, exn or any). anyref is also
+    what forward references within a rec group are folded into, so it is
+    compatible with every reference type.
+    """
+    if actual == expected:
+        return True
+    if not (actual.is_reference() and expected.is_reference()):
+        return False
+    if ValueType.ANYREF in (actual, expected):
+        return True
+    top = _REF_HIERARCHIES.get(actual, ValueType.ANYREF)
+    return top == _REF_HIERARCHIES.get(expected, ValueType.ANYREF)
+
 
 @dataclass
 class ValidationContext:
@@ -120,8 +156,13 @@ class ValidationContext:
     # Locals for current function (params + locals)
     locals: list[ValueType] = field(default_factory=list)
 
-    # Value stack (types only, not actual values)
-    value_stack: list[ValueType] = field(default_factory=list)
+    # Functions ref.func may refer to in function bodies: those referenced
+    # outside them, by exports, element segments and initializers
+    declared_funcs: set[int] = field(default_factory=set)
+
+    # Value stack (types only; None is a value of unknown type, produced in
+    # unreachable code)
+    value_stack: list[ValueType | None] = field(default_factory=list)
 
     # Control stack
     control_stack: list[ControlFrame] = field(default_factory=list)
@@ -129,64 +170,130 @@ class ValidationContext:
     # Current instruction offset (for error reporting)
     current_offset: int = 0
 
+    def location(self) -> str | None:
+        """Human-readable location of the current instruction."""
+        if self.current_func_idx is None:
+            return None
+        name = self._func_name(self.current_func_idx)
+        if name is not None:
+            location = f"function '{name}'"
+        else:
+            location = f"function {self.current_func_idx}"
+        return f"{location}, offset 0x{self.current_offset:x}"
+
+    def _func_name(self, func_idx: int) -> str | None:
+        """Name from the name section or an export, if the function has one."""
+        if func_idx in self.module.function_names:
+            return self.module.function_names[func_idx]
+        for exp in self.module.exports:
+            if exp.kind == ExportKind.FUNC and exp.index == func_idx:
+                return exp.name
+        return None
+
     def error(self, message: str) -> None:
         """Record an error at the current location."""
-        location = None
-        if self.current_func_idx is not None:
-            location = f"function {self.current_func_idx}"
-            if self.current_offset > 0:
-                location += f", offset {self.current_offset}"
         self.result.add_error(
-            message, location, self.current_func_idx, self.current_offset
+            message, self.location(), self.current_func_idx, self.current_offset
         )
 
     def warning(self, message: str) -> None:
         """Record a warning at the current location."""
-        location = None
-        if self.current_func_idx is not None:
-            location = f"function {self.current_func_idx}"
         self.result.add_warning(
-            message, location, self.current_func_idx, self.current_offset
+            message, self.location(), self.current_func_idx, self.current_offset
         )
 
-    def push_value(self, vtype: ValueType) -> None:
+    def push_value(self, vtype: ValueType | None) -> None:
         """Push a value type onto the stack."""
         self.value_stack.append(vtype)
 
-    def pop_value(self) -> ValueType | None:
-        """Pop a value type from the stack. Returns None on underflow."""
-        if not self.value_stack:
-            # Check if we're in unreachable code
-            if self.control_stack and self.control_stack[-1].unreachable:
-                return ValueType.I32  # Dummy type for unreachable code
+    def push_values(self, vtypes: tuple[ValueType, ...]) -> None:
+        """Push several value types, first one deepest."""
+        self.value_stack.extend(vtypes)
+
+    def pop_value(self, expected: ValueType | None = None) -> ValueType | None:
+        """Pop a value type from the current frame.
+
+        Returns None for a value of unknown type (popped past the base of an
+        unreachable frame) and on underflow, which is reported.
+        """
+        frame = self.control_stack[-1] if self.control_stack else None
+        base = frame.start_depth if frame else 0
+        if len(self.value_stack) <= base:
+            if frame is None or not frame.unreachable:
+                if expected is None:
+                    self.error("stack underflow")
+                else:
+                    self.error(f"stack underflow: expected {expected}")
             return None
         return self.value_stack.pop()
 
     def pop_expect(self, expected: ValueType) -> bool:
-        """Pop and check that the type matches. Returns True if OK."""
-        actual = self.pop_value()
-        if actual is None:
-            self.error(f"stack underflow: expected {expected}")
-            return False
-        if actual != expected:
+        """Pop and check that the type matches. Returns False on a mismatch."""
+        actual = self.pop_value(expected)
+        if actual is not None and not types_match(actual, expected):
             self.error(f"type mismatch: expected {expected}, got {actual}")
             return False
         return True
 
+    def pop_values(self, expected: tuple[ValueType, ...]) -> None:
+        """Pop several value types, last one first."""
+        for vtype in reversed(expected):
+            self.pop_expect(vtype)
+
+    def pop_reference(self) -> ValueType | None:
+        """Pop a value that must be a reference."""
+        actual = self.pop_value()
+        if actual is not None and not actual.is_reference():
+            self.error(f"type mismatch: expected reference, got {actual}")
+            return None
+        return actual
+
     def peek_value(self) -> ValueType | None:
         """Peek at the top value without popping."""
         if not self.value_stack:
             return None
         return self.value_stack[-1]
 
-    def push_control(self, kind: str, result_types: tuple[ValueType, ...]) -> None:
-        """Push a control frame."""
+    def set_unreachable(self) -> None:
+        """Mark the rest of the current frame as unreachable.
+
+        The frame's operands are discarded and its stack becomes polymorphic:
+        pops past its base yield values of unknown type.
+        """
+        if not self.control_stack:
+            return
+        frame = self.control_stack[-1]
+        del self.value_stack[frame.start_depth :]
+        frame.unreachable = True
+
+    def push_control(
+        self,
+        kind: str,
+        result_types: tuple[ValueType, ...],
+        param_types: tuple[ValueType, ...] = (),
+    ) -> None:
+        """Push a control frame, moving its parameters into it."""
+        self.pop_values(param_types)
         frame = ControlFrame(
             kind=kind,
             start_depth=len(self.value_stack),
             result_types=result_types,
+            param_types=param_types,
         )
         self.control_stack.append(frame)
+        self.push_values(param_types)
+
+    def check_frame_end(self, frame: ControlFrame) -> None:
+        """Check the current frame's operands are exactly its results.
+
+        The operands are then discarded, leaving the stack at the frame base.
+        """
+        self.pop_values(frame.result_types)
+        if len(self.value_stack) > frame.start_depth and not frame.unreachable:
+            self.error(
+                f"stack has extra values at block end: expected {len(frame.result_types)}, got {len(self.value_stack) - frame.start_depth + len(frame.result_types)}"
+            )
+        del self.value_stack[frame.start_depth :]
 
     def pop_control(self) -> ControlFrame | None:
         """Pop a control frame, checking stack depth."""
@@ -194,23 +301,16 @@ class ValidationContext:
             self.error("control stack underflow")
             return None
 
-        frame = self.control_stack.pop()
+        frame = self.control_stack[-1]
+        self.check_frame_end(frame)
+        return self.control_stack.pop()
 
-        # Check that stack has exactly the result types
-        expected_depth = frame.start_depth + len(frame.result_types)
-        actual_depth = len(self.value_stack)
-
-        if not frame.unreachable:
-            if actual_depth < expected_depth:
-                self.error(
-                    f"stack underflow at block end: expected {len(frame.result_types)} values"
-                )
-            elif actual_depth > expected_depth:
-                self.error(
-                    f"stack has extra values at block end: expected {len(frame.result_types)}, got {actual_depth - frame.start_depth}"
-                )
-
-        return frame
+    def get_frame(self, depth: int) -> ControlFrame | None:
+        """Get the target frame of a branch with the given label depth."""
+        if depth >= len(self.control_stack):
+            self.error(f"branch depth {depth} exceeds control stack")
+            return None
+        return self.control_stack[-1 - depth]
 
     def get_local_type(self, idx: int) -> ValueType | None:
         """Get the type of a local variable."""
@@ -6,6 +6,7 @@ import pytest
 
 from waq.errors import ParseError
 from waq.parser.binary import BinaryReader
+from waq.parser.types import FuncType, StructType, ValueType
 
 
 class TestBinaryReader:
@@ -40,6 +41,30 @@ class TestBinaryReader:
         assert reader.at_end
 
 
+    def test_read_block_type(self):
+        assert BinaryReader(bytes([0x40])).read_block_type() is None
+        assert BinaryReader(bytes([0x69])).read_block_type() == ValueType.EXNREF
+        assert BinaryReader(bytes([0x6E])).read_block_type() == ValueType.ANYREF
+        assert BinaryReader(bytes([0x03])).read_block_type() == 3
+
+    def test_read_typed_reference(self):
+        types = [FuncType((), ()), StructType(())]
+        # (ref null func), (ref 0), (ref null 1), (ref null any)
+        reader = BinaryReader(
+            bytes([0x63, 0x70, 0x64, 0x00, 0x63, 0x01, 0x63, 0x6E]), types=types
+        )
+        assert reader.read_value_type() == ValueType.FUNCREF
+        assert reader.read_value_type() == ValueType.FUNCREF
+        assert reader.read_value_type() == ValueType.STRUCTREF
+        assert reader.read_value_type() == ValueType.ANYREF
+        block = BinaryReader(bytes([0x64, 0x00]), types=types).read_block_type()
+        assert block == ValueType.FUNCREF
+
+    def test_read_typed_reference_invalid_heap_type(self):
+        with pytest.raises(ParseError, match="invalid heap type"):
+            BinaryReader(bytes([0x64, 0x7F])).read_value_type()
+
+
 class TestLEB128:
     """Tests for LEB128 integer decoding."""
 
@@ -0,0 +1,173 @@
+"""Unit tests for inline linear-memory bounds checks."""
+
+from __future__ import annotations
+
+import re
+
+import pytest
+
+from waq.compiler import compile_module
+from waq.parser.wat import parse_wat
+
+
+def compile_wat(text: str, bounds_checks: str = "inline") -> str:
+    """Parse WAT and return the emitted QBE IL."""
+    return compile_module(parse_wat(text), bounds_checks=bounds_checks).emit()
+
+
+LOAD_STORE = """
+    (module (memory 1)
+      (func (export "f") (param i32) (result i32)
+        (i32.store offset=4 (local.get 0) (i32.const 7))
+        (i32.load8_u offset=2 (local.get 0))))
+"""
+
+
+class TestLoadStore:
+    """Tests for load and store checks."""
+
+    def test_disabled_by_default(self):
+        output = compile_module(parse_wat(LOAD_STORE)).emit()
+        assert "__wasm_trap_out_of_bounds" not in output
+        assert "__wasm_memory_size_bytes" not in output
+
+    def test_every_access_is_checked(self):
+        output = compile_wat(LOAD_STORE)
+        assert output.count("$__wasm_trap_out_of_bounds") == 2
+        assert output.count("$__wasm_memory_size_bytes") == 2
+        assert output.count("cugtl") == 2
+
+    def test_extent_includes_offset_and_size(self):
+        output = compile_wat(LOAD_STORE)
+        # i32.store offset=4 touches 8 bytes, i32.load8_u offset=2 touches 3
+        assert ", 8\n" in output
+        assert ", 3\n" in output
+
+    def test_access_is_emitted_after_check(self):
+        output = compile_wat(LOAD_STORE)
+        check = output.index("cugtl")
+        assert output.rindex("storew") > check
+        assert "hlt" in output
+
+    def test_memory64_checks_wraparound(self):
+        output = compile_wat("""
+            (module (memory i64 1)
+              (func (param i64) (result i64) (i64.load (local.get 0))))
+        """)
+        assert "cugtl" in output
+        assert "cultl" in output
+        assert "extuw" not in output
+
+    def test_multi_memory_queries_size_by_index(self):
+        output = compile_wat("""
+            (module (memory $a 1) (memory $b 1)
+              (func (param i32) (result i32) (i32.load $b (local.get 0))))
+        """)
+        assert "call $__wasm_memory_size_bytes_idx(w 1)" in output
+
+
+class TestBulkMemory:
+    """Tests for bulk memory checks."""
+
+    def test_fill_checks_destination(self):
+        output = compile_wat("""
+            (module (memory 1)
+              (func (param i32 i32)
+                (memory.fill (local.get 0) (i32.const 0) (local.get 1))))
+        """)
+        assert output.count("cugtl") == 1
+        assert output.index("cugtl") < output.index("__wasm_memory_fill")
+
+    def test_copy_checks_both_ranges(self):
+        output = compile_wat("""
+            (module (memory 1)
+              (func (param i32 i32 i32)
+                (memory.copy (local.get 0) (local.get 1) (local.get 2))))
+        """)
+        assert output.count("cugtl") == 2
+
+    def test_init_checks_destination(self):
+        output = compile_wat("""
+            (module (memory 1) (data $d "abc")
+              (func (param i32)
+                (memory.init $d (local.get 0) (i32.const 0) (i32.const 3))))
+        """)
+        assert output.count("cugtl") == 1
+
+    def test_memory32_operands_are_zero_extended(self):
+        output = compile_wat("""
+            (module (memory 1)
+              (func (param i32 i32)
+                (memory.fill (local.get 0) (i32.const 0) (local.get 1))))
+        """)
+        assert output.count("extuw") == 2
+        assert re.search(r"call \$__wasm_memory_fill\(l %\S+, w %\S+, l %", output)
+
+    def test_memory64_operands_are_passed_whole(self):
+        output = compile_wat("""
+            (module (memory i64 1) (data $d "abc")
+              (func (param i64 i64 i64)
+                (memory.copy (local.get 0) (local.get 1) (local.get 2))
+                (memory.init $d (local.get 0) (i32.const 0) (i32.const 3))))
+        """)
+        copy = re.search(r"call \$__wasm_memory_copy\((.*)\)", output)
+        assert copy
+        assert copy.group(1).count("l %") == 3
+        # The segment offset and length stay i32, only their extension is checked
+        assert re.search(
+            r"call \$__wasm_memory_init_seg\(w 0, l %\S+, w %\S+, w %", output
+        )
+
+
+class TestGuardMode:
+    """Tests for guard-page mode."""
+
+    def test_single_memory32_accesses_are_unchecked(self):
+        output = compile_wat(LOAD_STORE, bounds_checks="guard")
+        assert "cugtl" not in output
+        assert "__wasm_trap_out_of_bounds" not in output
+
+    def test_memory_init_reserves_guard_region(self):
+        output = compile_wat(LOAD_STORE, bounds_checks="guard")
+        init = output.index("$__wasm_memory_guard_init")
+        assert init < output.index("$__wasm_memory_grow")
+
+    def test_no_guard_region_without_memory(self):
+        output = compile_wat("(module (func))", bounds_checks="guard")
+        assert "__wasm_memory_guard_init" not in output
+
+    def test_memory64_falls_back_to_inline(self):
+        output = compile_wat(
+            """
+            (module (memory i64 1)
+              (func (param i64) (result i64) (i64.load (local.get 0))))
+            """,
+            bounds_checks="guard",
+        )
+        assert "cugtl" in output
+
+    def test_multi_memory_falls_back_to_inline(self):
+        output = compile_wat(
+            """
+            (module (memory 1) (memory 1)
+              (func (param i32) (result i32) (i32.load (local.get 0))))
+            """,
+            bounds_checks="guard",
+        )
+        assert "cugtl" in output
+
+    def test_bulk_memory_is_checked_up_front(self):
+        output = compile_wat(
+            """
+            (module (memory 1)
+              (func (param i32 i32)
+                (memory.fill (local.get 0) (i32.const 0) (local.get 1))))
+            """,
+            bounds_checks="guard",
+        )
+        assert output.index("cugtl") < output.index("__wasm_memory_fill")
+
+
+def test_unknown_mode_is_rejected():
+    with pytest.raises(ValueError, match="unknown bounds check mode"):
+        compile_wat("(module)", bounds_checks="bogus")
@@ -4,6 +4,7 @@ from __future__ import annotations
 
 from waq.compiler import compile_module
 from waq.parser.module import parse_module
+from waq.parser.wat import parse_wat
 
 
 def make_memory_fill_wasm() -> bytes:
@@ -218,3 +219,20 @@ class TestMemoryInit:
         qbe = compile_module(module)
         output = qbe.emit()
         assert "__wasm_data_drop" in output
+
+
+class TestActiveDataSegments:
+    """Tests for copying active data segments at instantiation."""
+
+    def test_offset_is_unsigned(self):
+        """Test that a negative i32 offset is read as a large address."""
+        module = parse_wat('(module (memory 1) (data (i32.const -1) "a"))')
+        output = compile_module(module).emit()
+        assert "call $__wasm_memory_buffer(w 0, l 4294967295, l 1)" in output
+
+    def test_empty_segment_checked(self):
+        """Test that an empty segment is still checked against the memory."""
+        module = parse_wat('(module (memory 1) (data (i32.const 8) ""))')
+        output = compile_module(module).emit()
+        assert "call $__wasm_memory_buffer(w 0, l 8, l 0)" in output
+        assert "memcpy" not in output
@@ -0,0 +1,173 @@
+"""Unit tests for checked call_indirect and canonical signature ids."""
+
+from __future__ import annotations
+
+from waq.compiler import compile_module
+from waq.compiler.signatures import canonical_type_ids, matching_type_ids
+from waq.parser.wat import parse_wat
+
+
+def compile_wat(text: str) -> str:
+    """Parse WAT and return the emitted QBE IL."""
+    return compile_module(parse_wat(text)).emit()
+
+
+class TestCanonicalTypeIds:
+    """Tests for type canonicalization."""
+
+    def test_identical_types_share_id(self):
+        module = parse_wat("""
+            (module
+              (type (func (param i32)))
+              (type (func))
+              (type (func (param i32))))
+        """)
+        assert canonical_type_ids(module) == [0, 1, 0]
+
+    def test_rec_group_members_are_distinct(self):
+        module = parse_wat("""
+            (module
+              (type (func))
+              (rec (type (func)) (type (func))))
+        """)
+        assert canonical_type_ids(module) == [0, 1, 2]
+
+    def test_equivalent_rec_groups_share_ids(self):
+        module = parse_wat("""
+            (module
+              (rec (type $a (struct (field (ref null $b)))) (type $b (func)))
+              (rec (type $c (struct (field (ref null $d)))) (type $d (func))))
+        """)
+        assert canonical_type_ids(module) == [0, 1, 0, 1]
+
+    def test_supertype_distinguishes_types(self):
+        module = parse_wat("""
+            (module
+              (type $base (sub (func)))
+              (type (sub $base (func)))
+              (type (sub (func))))
+        """)
+        assert canonical_type_ids(module) == [0, 1, 0]
+
+    def test_subtypes_match_supertype(self):
+        module = parse_wat("""
+            (module
+              (type $a (sub (func)))
+              (type $b (sub $a (func)))
+              (type $c (sub $b (func)))
+              (type $d (func (param i32))))
+        """)
+        ids = canonical_type_ids(module)
+        assert matching_type_ids(module, ids, 0) == [0, 1, 2]
+        assert matching_type_ids(module, ids, 1) == [1, 2]
+        assert matching_type_ids(module, ids, 3) == [3]
+
+
+CALL_INDIRECT = """
+    (module
+      (type $t (func (result i32)))
+      (table 2 funcref)
+      (elem (i32.const 0) $f)
+      (func $f (type $t) (i32.const 7))
+      (func (export "call") (param i32) (result i32)
+        (call_indirect (type $t) (local.get 0))))
+"""
+
+
+class TestCallIndirect:
+    """Tests for call site checks."""
+
+    def test_index_is_checked_against_table_size(self):
+        output = compile_wat(CALL_INDIRECT)
+        assert "$__wasm_table_size" in output
+        assert "cultw" in output
+        assert "$__wasm_trap_undefined_element" in output
+
+    def test_null_entry_traps(self):
+        output = compile_wat(CALL_INDIRECT)
+        assert "$__wasm_trap_uninitialized_element" in output
+
+    def test_signature_tag_is_compared(self):
+        output = compile_wat(CALL_INDIRECT)
+        assert "$__wasm_table_sigs" in output
+        assert "ceqw" in output
+        assert "$__wasm_trap_indirect_call_mismatch" in output
+
+    def test_checks_precede_call(self):
+        output = compile_wat(CALL_INDIRECT)
+        assert output.index("indirect_call_mismatch") < output.index("call %")
+
+    def test_subtypes_are_accepted(self):
+        output = compile_wat("""
+            (module
+              (type $a (sub (func)))
+              (type $b (sub $a (func)))
+              (table 1 funcref)
+              (func (param i32) (call_indirect (type $a) (local.get 0))))
+        """)
+        sig_check = output[output.index("$__wasm_table_sigs") :]
+        assert sig_check.count("ceqw") == 2
+
+    def test_return_call_indirect_is_checked(self):
+        output = compile_wat("""
+            (module
+              (type $t (func (result i32)))
+              (table 1 funcref)
+              (func (param i32) (result i32)
+                (return_call_indirect (type $t) (local.get 0))))
+        """)
+        assert "$__wasm_trap_indirect_call_mismatch" in output
+
+
+GLOBAL_REF_FUNC = """
+    (module
+      (type $t (func (result i32)))
+      (func $f (type $t) (i32.const 42))
+      (global $g funcref (ref.func $f))
+      (global $h funcref (global.get $g)))
+"""
+
+
+class TestSignatureRegistration:
+    """Tests for runtime signature registration."""
+
+    def test_element_functions_are_registered(self):
+        output = compile_wat(CALL_INDIRECT)
+        init = output[output.index("$__wasm_memory_init") :]
+        assert "__wasm_func_sig_register" in init
+        assert init.index("__wasm_func_sig_register") < init.index(
+            "__wasm_table_set"
+        )
+
+    def test_equivalent_types_register_same_id(self):
+        output = compile_wat("""
+            (module
+              (type (func))
+              (type (func))
+              (table 2 funcref)
+              (elem (i32.const 0) $f $g)
+              (func $f (type 0))
+              (func $g (type 1)))
+        """)
+        assert output.count("w 0)") >= 2
+        assert "w 1)" not in output
+
+    def test_declared_functions_are_registered(self):
+        output = compile_wat("""
+            (module
+              (elem declare func $f)
+              (func $f)
+              (func (drop (ref.func $f))))
+        """)
+        init = output[output.index("$__wasm_memory_init") :]
+        assert "__wasm_func_sig_register(l $__wasm_func_0, w 0)" in init
+        # Declarative segments are not kept for table.init
+        assert "__wasm_register_elem_segment" not in init
+
+    def test_global_ref_func_targets_are_registered(self):
+        output = compile_wat(GLOBAL_REF_FUNC)
+        init = output[output.index("$__wasm_memory_init") :]
+        assert "__wasm_func_sig_register(l $__wasm_func_0, w 0)" in init
+        assert "storel $__wasm_func_0, $__wasm_global_0" in init
+        # global.get of such a global refers to the same function
+        assert "storel $__wasm_func_0, $__wasm_global_1" in init
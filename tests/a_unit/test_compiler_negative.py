@@ -30,9 +30,9 @@ class TestStackErrors:
             0x0A, 0x04, 0x01, 0x02, 0x00, 0x0B,
         ])
         module = parse_module(wasm)
-        # Should compile but return 0 (implicit return for empty stack)
-        # This is valid WASM behavior - the result is undefined
-        qbe = compile_module(module)
+        # The validator rejects this; with validation off it should compile
+        # and return 0 (implicit return for empty stack)
+        qbe = compile_module(module, validate=False)
         assert qbe is not None
 
 
@@ -124,8 +124,9 @@ class TestMemoryOperations:
             0x0B,
         ])
         module = parse_module(wasm)
-        # Should compile (runtime will handle missing memory)
-        qbe = compile_module(module)
+        # The validator rejects this; with validation off it should compile
+        # (runtime will handle missing memory)
+        qbe = compile_module(module, validate=False)
         assert qbe is not None
 
 
@@ -4,6 +4,7 @@ from __future__ import annotations
 
 from waq.compiler import compile_module
 from waq.parser.module import parse_module
+from waq.parser.wat import parse_wat
 
 
 class TestBlock:
@@ -331,6 +332,20 @@ class TestBranch:
         assert "br_if" in output
 
 
+    def test_br_if_carries_block_result(self):
+        """Test that a taken br_if delivers its value to the block end."""
+        module = parse_wat("""
+            (module
+              (func (export "f") (param i32) (result i32)
+                (block (result i32)
+                  (drop (br_if 0 (i32.const 5) (local.get 0)))
+                  (i32.const 7))))
+        """)
+        output = compile_module(module).emit()
+        # Both the branch and the fallthrough copy into the block's result
+        assert output.count("=w copy %") == 2
+
+
 class TestReturn:
     """Tests for return instruction."""
 
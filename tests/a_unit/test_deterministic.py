@@ -0,0 +1,98 @@
+"""Unit tests for the deterministic float profile (--deterministic)."""
+
+from __future__ import annotations
+
+import pytest
+
+from waq.compiler import compile_module
+from waq.parser.wat import parse_wat
+
+
+def compile_wat(text: str, deterministic: bool = True) -> str:
+    """Parse WAT and return the emitted QBE IL."""
+    return compile_module(parse_wat(text), deterministic=deterministic).emit()
+
+
+def unary(op: str, type_: str) -> str:
+    return f"""
+        (module
+          (func (export "f") (param {type_}) (result {type_})
+            ({type_}.{op} (local.get 0))))
+    """
+
+
+def binary(op: str, type_: str) -> str:
+    return f"""
+        (module
+          (func (export "f") (param {type_} {type_}) (result {type_})
+            ({type_}.{op} (local.get 0) (local.get 1))))
+    """
+
+
+class TestHelpers:
+    """Operations replaced by a runtime helper returning the canonical NaN."""
+
+    @pytest.mark.parametrize("type_", ["f32", "f64"])
+    @pytest.mark.parametrize("op", ["div", "min", "max"])
+    def test_binary_helpers(self, op, type_):
+        output = compile_wat(binary(op, type_))
+        assert f"call $__wasm_{type_}_{op}_deterministic(" in output
+        assert "__wasm_canon_nan" not in output
+
+    @pytest.mark.parametrize("type_", ["f32", "f64"])
+    def test_sqrt_helper(self, type_):
+        output = compile_wat(unary("sqrt", type_))
+        assert f"call $__wasm_{type_}_sqrt_deterministic(" in output
+        assert f"$__wasm_{type_}_sqrt(" not in output
+
+
+class TestCanonicalization:
+    """Operations whose result is canonicalized after the fact."""
+
+    @pytest.mark.parametrize("type_", ["f32", "f64"])
+    @pytest.mark.parametrize("op", ["add", "sub", "mul"])
+    def test_arithmetic(self, op, type_):
+        output = compile_wat(binary(op, type_))
+        assert f"call $__wasm_canon_nan_{type_}(" in output
+        assert output.index(f" {op} ") < output.index("$__wasm_canon_nan")
+
+    @pytest.mark.parametrize("type_", ["f32", "f64"])
+    @pytest.mark.parametrize("op", ["ceil", "floor", "trunc", "nearest"])
+    def test_rounding(self, op, type_):
+        output = compile_wat(unary(op, type_))
+        assert output.index(f"$__wasm_{type_}_{op}(") < output.index(
+            f"$__wasm_canon_nan_{type_}("
+        )
+
+    def test_demote_and_promote(self):
+        output = compile_wat("""
+            (module
+              (func (export "f") (param f64) (result f64)
+                (f64.promote_f32 (f32.demote_f64 (local.get 0)))))
+        """)
+        assert output.index("truncd") < output.index("$__wasm_canon_nan_f32(")
+        assert output.index("exts") < output.index("$__wasm_canon_nan_f64(")
+
+    # abs, neg and copysign only change the sign bit, as the spec requires
+    @pytest.mark.parametrize("op", ["abs", "neg"])
+    def test_sign_operations_untouched(self, op):
+        assert "__wasm_canon_nan" not in compile_wat(unary(op, "f64"))
+
+    def test_copysign_untouched(self):
+        assert "__wasm_canon_nan" not in compile_wat(binary("copysign", "f32"))
+
+
+class TestDefaultProfile:
+    """Without --deterministic, float results are left alone."""
+
+    @pytest.mark.parametrize("op", ["add", "div", "min"])
+    def test_no_canonicalization(self, op):
+        output = compile_wat(binary(op, "f32"), deterministic=False)
+        assert "deterministic" not in output
+        assert "__wasm_canon_nan" not in output
+
+    @pytest.mark.parametrize("type_", ["f32", "f64"])
+    @pytest.mark.parametrize("op", ["min", "max", "copysign"])
+    def test_runtime_operations_are_calls(self, op, type_):
+        output = compile_wat(binary(op, type_), deterministic=False)
+        assert f"call $__wasm_{type_}_{op}(" in output
@@ -2,8 +2,12 @@
 
 from __future__ import annotations
 
+import pytest
+
 from waq.compiler import compile_module
+from waq.errors import CompileError
 from waq.parser.module import parse_module
+from waq.parser.wat import parse_wat
 
 
 def make_try_catch_wasm() -> bytes:
@@ -12,12 +16,15 @@ def make_try_catch_wasm() -> bytes:
     () -> (i32)
     Returns 1 if exception caught, 0 otherwise.
     """
-    # Type section: () -> (i32)
-    type_section = bytes([0x01, 0x60, 0x00, 0x01, 0x7F])
+    # Type section: () -> (i32), (i32) -> ()
+    type_section = bytes([0x02, 0x60, 0x00, 0x01, 0x7F, 0x60, 0x01, 0x7F, 0x00])
 
     # Function section
     func_section = bytes([0x01, 0x00])
 
+    # Tag section: tag 0 with an i32 payload
+    tag_section = bytes([0x01, 0x00, 0x01])
+
     # Export section
     export_section = bytes([0x01, 0x09]) + b"try_catch" + bytes([0x00, 0x00])
 
@@ -31,7 +38,7 @@ def make_try_catch_wasm() -> bytes:
         0x0F,  # return (no exception)
         0x07,
         0x00,  # catch tag 0
-        0x1A,  # drop (exception value)
+        0x1A,  # drop (exception payload)
         0x41,
         0x01,  # i32.const 1
         0x0F,  # return (caught exception)
@@ -45,6 +52,7 @@ def make_try_catch_wasm() -> bytes:
     wasm = bytes([0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00])
     wasm += bytes([0x01, len(type_section)]) + type_section
     wasm += bytes([0x03, len(func_section)]) + func_section
+    wasm += bytes([0x0D, len(tag_section)]) + tag_section
     wasm += bytes([0x07, len(export_section)]) + export_section
     wasm += bytes([0x0A, len(code_section)]) + code_section
 
@@ -63,6 +71,9 @@ def make_throw_wasm() -> bytes:
     # Function section
     func_section = bytes([0x01, 0x00])
 
+    # Tag section: tag 0 without payload
+    tag_section = bytes([0x01, 0x00, 0x00])
+
     # Export section
     export_section = bytes([0x01, 0x05]) + b"throw" + bytes([0x00, 0x00])
 
@@ -78,6 +89,7 @@ def make_throw_wasm() -> bytes:
     wasm = bytes([0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00])
     wasm += bytes([0x01, len(type_section)]) + type_section
     wasm += bytes([0x03, len(func_section)]) + func_section
+    wasm += bytes([0x0D, len(tag_section)]) + tag_section
     wasm += bytes([0x07, len(export_section)]) + export_section
     wasm += bytes([0x0A, len(code_section)]) + code_section
 
@@ -179,6 +191,9 @@ def make_rethrow_wasm() -> bytes:
     # Function section
     func_section = bytes([0x01, 0x00])
 
+    # Tag section: tag 0 without payload
+    tag_section = bytes([0x01, 0x00, 0x00])
+
     # Export section
     export_section = bytes([0x01, 0x07]) + b"rethrow" + bytes([0x00, 0x00])
 
@@ -200,6 +215,7 @@ def make_rethrow_wasm() -> bytes:
     wasm = bytes([0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00])
     wasm += bytes([0x01, len(type_section)]) + type_section
     wasm += bytes([0x03, len(func_section)]) + func_section
+    wasm += bytes([0x0D, len(tag_section)]) + tag_section
     wasm += bytes([0x07, len(export_section)]) + export_section
     wasm += bytes([0x0A, len(code_section)]) + code_section
 
@@ -267,3 +283,153 @@ class TestDelegate:
         # Delegate uses try body label and pushes exception handler
         assert "__wasm_push_exception_handler" in output
         assert "try" in output
+
+
+class TestPayloads:
+    """Tests for typed exception payloads."""
+
+    def test_throw_marshals_payload(self):
+        module = parse_wat("""
+            (module
+              (tag $e (param i32 f64))
+              (func (export "f") (throw $e (i32.const 7) (f64.const 1.5))))
+        """)
+        output = compile_module(module).emit()
+        assert "alloc8 16" in output
+        assert "storew" in output
+        assert "stored" in output
+        assert "call $__wasm_throw_with_payload(w 0, l %" in output
+        assert ", l 16)" in output
+
+    def test_catch_pushes_payload(self):
+        module = parse_wat("""
+            (module
+              (tag $e (param i64))
+              (func (export "f") (result i64)
+                (try (result i64)
+                  (do (i64.const 0))
+                  (catch $e))))
+        """)
+        output = compile_module(module).emit()
+        assert "call $setjmp(" in output
+        assert "call $__wasm_begin_catch()" in output
+        assert "call $__wasm_get_exception_payload()" in output
+        assert "=l loadl" in output
+        assert "phi" in output
+
+    def test_unmatched_exception_is_rethrown(self):
+        module = parse_wat("""
+            (module
+              (tag $e)
+              (func (export "f")
+                (try (do (nop)) (catch $e))))
+        """)
+        output = compile_module(module).emit()
+        assert "call $__wasm_rethrow()" in output
+
+    def test_branch_out_of_try_pops_handler(self):
+        module = parse_wat("""
+            (module
+              (tag $e)
+              (func (export "f") (param i32)
+                (block $out
+                  (try (do (br_if $out (local.get 0))) (catch $e)))))
+        """)
+        output = compile_module(module).emit()
+        # One pop for the branch, one for falling off the end of the body
+        assert output.count("call $__wasm_pop_exception_handler()") == 2
+
+    def test_oversized_payload_is_rejected(self):
+        module = parse_wat("""
+            (module
+              (tag $e (param i64 i64 i64 i64 i64 i64 i64 i64 i64))
+              (func (export "f") (throw $e
+                (i64.const 0) (i64.const 0) (i64.const 0)
+                (i64.const 0) (i64.const 0) (i64.const 0)
+                (i64.const 0) (i64.const 0) (i64.const 0))))
+        """)
+        with pytest.raises(CompileError, match="at most 8 are supported"):
+            compile_module(module)
+
+
+class TestTryTable:
+    """Tests for try_table, throw_ref and exnref."""
+
+    def test_catch_branches_with_payload(self):
+        module = parse_wat("""
+            (module
+              (tag $e (param i32))
+              (func (export "f") (result i32)
+                (block $h (result i32)
+                  (try_table (catch $e $h) (throw $e (i32.const 5)))
+                  (i32.const 0))))
+        """)
+        output = compile_module(module).emit()
+        assert "call $setjmp(" in output
+        assert "call $__wasm_get_exception_tag()" in output
+        assert "call $__wasm_get_exception_payload()" in output
+        assert "call $__wasm_rethrow()" in output
+        assert "jmp @block_end0" in output
+
+    def test_catch_all_ref_and_throw_ref(self):
+        module = parse_wat("""
+            (module
+              (tag $e)
+              (func (export "f")
+                (block $h (result exnref)
+                  (try_table (catch_all_ref $h) (throw $e))
+                  (return))
+                (throw_ref)))
+        """)
+        output = compile_module(module).emit()
+        assert "=l call $__wasm_exn_capture()" in output
+        assert "call $__wasm_throw_ref(l %" in output
+        # catch_all is the last clause: nothing is rethrown
+        assert "__wasm_rethrow" not in output
+
+    def test_exnref_locals_live_in_shadow_frame(self):
+        """Test captured exnrefs are rooted, since they are GC objects."""
+        module = parse_wat("""
+            (module
+              (tag $e)
+              (func (export "f")
+                (local $x exnref)
+                (local.set $x
+                  (block $h (result exnref)
+                    (try_table (catch_all_ref $h) (throw $e))
+                    (return)))
+                (throw_ref (local.get $x))))
+        """)
+        output = compile_module(module).emit()
+        assert "call $__wasm_gc_push_frame(l %gc_frame, w 1)" in output
+        assert "=l add %gc_frame, 16" in output
+
+    def test_locals_kept_in_memory_with_handlers(self):
+        """Test local slots escape, so longjmp sees stores from the try body."""
+        module = parse_wat("""
+            (module
+              (tag $e)
+              (func (export "f") (param i32) (result i32)
+                (local $x i64)
+                (try (do (local.set $x (i64.const 1))) (catch_all))
+                (local.get 0))
+              (func (export "g") (param i32) (result i32)
+                (local.get 0)))
+        """)
+        output = compile_module(module).emit()
+        f, g = output.split("function w $wasm_g")
+        assert "storel %local_addr0, %local_escape" in f
+        assert "storel %local_addr1, %local_escape" in f
+        assert "local_escape" not in g
+
+    def test_branch_out_of_try_table_pops_handler(self):
+        module = parse_wat("""
+            (module
+              (tag $e)
+              (func (export "f") (param i32)
+                (block $h
+                  (try_table (catch $e $h)
+                    (br_if 1 (local.get 0))))))
+        """)
+        output = compile_module(module).emit()
+        assert output.count("call $__wasm_pop_exception_handler()") == 2
@@ -0,0 +1,453 @@
+"""Tests for array bulk operations, cast branches and extern conversions."""
+
+from __future__ import annotations
+
+from waq.compiler import compile_module
+from waq.parser.module import parse_module
+
+
+def _assemble(*sections: tuple[int, bytes]) -> bytes:
+    """Assemble a WASM module from (section id, contents) pairs."""
+    wasm = bytes([0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00])
+    for section_id, contents in sections:
+        wasm += bytes([section_id, len(contents)]) + contents
+    return wasm
+
+
+def _code(*bodies: bytes) -> bytes:
+    """Build a code section from function bodies without locals."""
+    section = bytes([len(bodies)])
+    for body in bodies:
+        section += bytes([len(body) + 1, 0x00]) + body
+    return section
+
+
+def make_array_fill_copy_wasm() -> bytes:
+    """Create WASM with array.fill and array.copy."""
+    type_section = bytes([
+        0x02,
+        # Type 0: array of mutable i32
+        0x5E,
+        0x7F,
+        0x01,
+        # Type 1: (arrayref, arrayref) -> ()
+        0x60,
+        0x02,
+        0x6A,
+        0x6A,
+        0x00,
+    ])
+
+    # Code: array.fill 0 (a, 0, 7, 4); array.copy 0 0 (a, 1, b, 0, 2)
+    func_body = bytes([
+        0x20,
+        0x00,  # local.get 0
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x07,  # i32.const 7
+        0x41,
+        0x04,  # i32.const 4
+        0xFB,
+        0x10,
+        0x00,  # array.fill 0
+        0x20,
+        0x00,  # local.get 0
+        0x41,
+        0x01,  # i32.const 1
+        0x20,
+        0x01,  # local.get 1
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x02,  # i32.const 2
+        0xFB,
+        0x11,
+        0x00,
+        0x00,  # array.copy 0 0
+        0x0B,
+    ])
+
+    return _assemble(
+        (0x01, type_section),
+        (0x03, bytes([0x01, 0x01])),
+        (0x0A, _code(func_body)),
+    )
+
+
+def make_array_data_wasm() -> bytes:
+    """Create WASM with array.new_data and array.init_data on a passive segment."""
+    type_section = bytes([
+        0x04,
+        # Type 0: array of mutable i8
+        0x5E,
+        0x78,
+        0x01,
+        # Type 1: array of mutable i32
+        0x5E,
+        0x7F,
+        0x01,
+        # Type 2: () -> (arrayref)
+        0x60,
+        0x00,
+        0x01,
+        0x6A,
+        # Type 3: (arrayref) -> ()
+        0x60,
+        0x01,
+        0x6A,
+        0x00,
+    ])
+
+    # Code: array.new_data 0 0 (0, 3)
+    new_body = bytes([
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x03,  # i32.const 3
+        0xFB,
+        0x09,
+        0x00,
+        0x00,  # array.new_data 0 0
+        0x0B,
+    ])
+    # Code: array.init_data 1 0 (a, 0, 0, 1)
+    init_body = bytes([
+        0x20,
+        0x00,  # local.get 0
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x01,  # i32.const 1
+        0xFB,
+        0x12,
+        0x01,
+        0x00,  # array.init_data 1 0
+        0x0B,
+    ])
+
+    # Passive data segment (flags 1) holding "abcd"
+    data_section = bytes([0x01, 0x01, 0x04]) + b"abcd"
+
+    return _assemble(
+        (0x01, type_section),
+        (0x03, bytes([0x02, 0x02, 0x03])),
+        (0x0C, bytes([0x01])),
+        (0x0A, _code(new_body, init_body)),
+        (0x0B, data_section),
+    )
+
+
+def make_array_elem_wasm() -> bytes:
+    """Create WASM with array.new_elem and array.init_elem on a passive segment."""
+    type_section = bytes([
+        0x03,
+        # Type 0: array of mutable funcref
+        0x5E,
+        0x70,
+        0x01,
+        # Type 1: () -> (arrayref)
+        0x60,
+        0x00,
+        0x01,
+        0x6A,
+        # Type 2: (arrayref) -> ()
+        0x60,
+        0x01,
+        0x6A,
+        0x00,
+    ])
+
+    # Code: array.new_elem 0 0 (0, 1)
+    new_body = bytes([
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x01,  # i32.const 1
+        0xFB,
+        0x0A,
+        0x00,
+        0x00,  # array.new_elem 0 0
+        0x0B,
+    ])
+    # Code: array.init_elem 0 0 (a, 0, 0, 1)
+    init_body = bytes([
+        0x20,
+        0x00,  # local.get 0
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x00,  # i32.const 0
+        0x41,
+        0x01,  # i32.const 1
+        0xFB,
+        0x13,
+        0x00,
+        0x00,  # array.init_elem 0 0
+        0x0B,
+    ])
+
+    # Passive element segment (flags 1, elemkind funcref) holding function 0
+    elem_section = bytes([0x01, 0x01, 0x00, 0x01, 0x00])
+
+    return _assemble(
+        (0x01, type_section),
+        (0x03, bytes([0x02, 0x01, 0x02])),
+        (0x09, elem_section),
+        (0x0A, _code(new_body, init_body)),
+    )
+
+
+def make_br_on_cast_wasm(sub_opcode: int, flags: int, heap_type: int) -> bytes:
+    """Create WASM branching on a cast of an anyref param to `heap_type`."""
+    type_section = bytes([
+        0x02,
+        # Type 0: struct with one i32 field
+        0x5F,
+        0x01,
+        0x7F,
+        0x00,
+        # Type 1: (anyref) -> (i32)
+        0x60,
+        0x01,
+        0x6E,
+        0x01,
+        0x7F,
+    ])
+
+    # Code: block (result anyref) (br_on_cast[_fail] 0 any ht (local.get 0))
+    #       drop; return 0; end; drop; i32.const 1
+    func_body = bytes([
+        0x02,
+        0x6E,  # block (result anyref)
+        0x20,
+        0x00,  # local.get 0
+        0xFB,
+        sub_opcode,
+        flags,
+        0x00,  # label 0
+        0x6E,  # source: any
+        heap_type,
+        0x1A,  # drop
+        0x41,
+        0x00,  # i32.const 0
+        0x0F,  # return
+        0x0B,  # end
+        0x1A,  # drop
+        0x41,
+        0x01,  # i32.const 1
+        0x0B,
+    ])
+
+    return _assemble(
+        (0x01, type_section),
+        (0x03, bytes([0x01, 0x01])),
+        (0x0A, _code(func_body)),
+    )
+
+
+def make_subtype_wasm() -> bytes:
+    """Create WASM declaring a struct subtype of another struct."""
+    type_section = bytes([
+        0x02,
+        # Type 0: sub (struct (field i32))
+        0x50,
+        0x00,
+        0x5F,
+        0x01,
+        0x7F,
+        0x00,
+        # Type 1: sub 0 (struct (field i32) (field i32))
+        0x50,
+        0x01,
+        0x00,
+        0x5F,
+        0x02,
+        0x7F,
+        0x00,
+        0x7F,
+        0x00,
+    ])
+    return _assemble((0x01, type_section))
+
+
+def make_extern_convert_wasm() -> bytes:
+    """Create WASM with any.convert_extern and extern.convert_any."""
+    type_section = bytes([
+        0x01,
+        # Type 0: (externref) -> (externref)
+        0x60,
+        0x01,
+        0x6F,
+        0x01,
+        0x6F,
+    ])
+
+    # Code: extern.convert_any (any.convert_extern (local.get 0))
+    func_body = bytes([
+        0x20,
+        0x00,  # local.get 0
+        0xFB,
+        0x1A,  # any.convert_extern
+        0xFB,
+        0x1B,  # extern.convert_any
+        0x0B,
+    ])
+
+    return _assemble(
+        (0x01, type_section),
+        (0x03, bytes([0x01, 0x00])),
+        (0x0A, _code(func_body)),
+    )
+
+
+def make_ref_eq_wasm() -> bytes:
+    """Create WASM with ref.eq."""
+    type_section = bytes([
+        0x01,
+        # Type 0: (eqref, eqref) -> (i32)
+        0x60,
+        0x02,
+        0x6D,
+        0x6D,
+        0x01,
+        0x7F,
+    ])
+
+    func_body = bytes([
+        0x20,
+        0x00,  # local.get 0
+        0x20,
+        0x01,  # local.get 1
+        0xD3,  # ref.eq
+        0x0B,
+    ])
+
+    return _assemble(
+        (0x01, type_section),
+        (0x03, bytes([0x01, 0x00])),
+        (0x0A, _code(func_body)),
+    )
+
+
+class TestArrayBulkOperations:
+    """Tests for array.fill and array.copy."""
+
+    def test_array_fill(self):
+        """Test array.fill passes the value as a 64-bit element slot."""
+        output = compile_module(parse_module(make_array_fill_copy_wasm())).emit()
+        assert "extuw" in output
+        assert "call $__wasm_array_fill(l %t" in output
+
+    def test_array_copy(self):
+        """Test array.copy passes both arrays and offsets to the runtime."""
+        output = compile_module(parse_module(make_array_fill_copy_wasm())).emit()
+        copy = output[output.index("call $__wasm_array_copy(") :]
+        copy = copy[: copy.index("\n")]
+        assert copy.count("l %t") == 2
+        assert copy.count("w %t") == 3
+
+
+class TestArraySegments:
+    """Tests for arrays initialized from data and element segments."""
+
+    def test_array_new_data(self):
+        """Test array.new_data passes the segment and the element size."""
+        output = compile_module(parse_module(make_array_data_wasm())).emit()
+        assert "call $__wasm_array_new_data(w 0, w 0, w " in output
+        assert ", w 1)" in output
+
+    def test_array_init_data_element_size(self):
+        """Test array.init_data reads i32 elements as 4 bytes."""
+        output = compile_module(parse_module(make_array_data_wasm())).emit()
+        assert "call $__wasm_array_init_data(l %t" in output
+        assert ", w 0, w %t" in output
+        assert ", w 4)" in output
+
+    def test_passive_data_segment_registered(self):
+        """Test passive data segments are registered at startup."""
+        output = compile_module(parse_module(make_array_data_wasm())).emit()
+        assert "call $__wasm_register_data_segment(w 0, l $__wasm_data_0, l 4)" in (
+            output
+        )
+
+    def test_passive_element_segment_parsed(self):
+        """Test passive element segments keep their functions."""
+        module = parse_module(make_array_elem_wasm())
+        assert len(module.elements) == 1
+        assert module.elements[0].table_idx == -1
+        assert module.elements[0].func_indices == [0]
+
+    def test_array_new_elem(self):
+        """Test array.new_elem reads from a registered element segment."""
+        output = compile_module(parse_module(make_array_elem_wasm())).emit()
+        assert "call $__wasm_array_new_elem(w 0, w 0, w " in output
+        assert "call $__wasm_register_elem_segment(w 0, w 1)" in output
+        assert "call $__wasm_elem_segment_set(w 0, w 0, l $__wasm_func_0)" in output
+
+    def test_array_init_elem(self):
+        """Test array.init_elem compiles to a runtime call."""
+        output = compile_module(parse_module(make_array_elem_wasm())).emit()
+        assert "call $__wasm_array_init_elem(l %t" in output
+
+
+class TestCastBranches:
+    """Tests for br_on_cast and br_on_cast_fail."""
+
+    def test_br_on_cast(self):
+        """Test br_on_cast branches when the type test succeeds."""
+        wasm = make_br_on_cast_wasm(0x18, 0x00, 0x00)
+        output = compile_module(parse_module(wasm)).emit()
+        assert "=w call $__wasm_ref_test(l " in output
+        assert "@br_on_cast_branch" in output
+        test_line = next(line for line in output.splitlines() if "jnz" in line)
+        assert test_line.index("br_on_cast_branch") < test_line.index(
+            "br_on_cast_cont"
+        )
+
+    def test_br_on_cast_fail(self):
+        """Test br_on_cast_fail branches when the type test fails."""
+        wasm = make_br_on_cast_wasm(0x19, 0x00, 0x00)
+        output = compile_module(parse_module(wasm)).emit()
+        test_line = next(line for line in output.splitlines() if "jnz" in line)
+        assert test_line.index("br_on_cast_fail_cont") < test_line.index(
+            "br_on_cast_fail_branch"
+        )
+
+    def test_nullable_target(self):
+        """Test a nullable target type lets null take the cast branch."""
+        wasm = make_br_on_cast_wasm(0x18, 0x03, 0x00)
+        output = compile_module(parse_module(wasm)).emit()
+        assert "call $__wasm_ref_test_null(l " in output
+
+    def test_abstract_target(self):
+        """Test abstract heap types are passed as their s33 code."""
+        wasm = make_br_on_cast_wasm(0x18, 0x00, 0x6C)  # i31
+        output = compile_module(parse_module(wasm)).emit()
+        assert "call $__wasm_ref_test(l %t" in output
+        assert ", w -20)" in output
+
+    def test_supertype_registered(self):
+        """Test declared supertypes are registered for cast checks."""
+        output = compile_module(parse_module(make_subtype_wasm())).emit()
+        assert "call $__wasm_gc_register_type(w 0, w 0, w 1, " in output
+        assert "w -1)" in output
+        assert "call $__wasm_gc_register_type(w 1, w 0, w 2, " in output
+        assert "$__wasm_gc_layout_1, w 0)" in output
+
+
+class TestReferenceConversions:
+    """Tests for extern conversions and ref.eq."""
+
+    def test_extern_conversions(self):
+        """Test any.convert_extern and extern.convert_any keep the reference."""
+        output = compile_module(parse_module(make_extern_convert_wasm())).emit()
+        func = output[: output.index("\n}")]
+        assert func.count("=l copy %t") == 2
+
+    def test_ref_eq(self):
+        """Test ref.eq compares the references."""
+        output = compile_module(parse_module(make_ref_eq_wasm())).emit()
+        assert "=w ceql %t0, %t1" in output
@@ -4,6 +4,7 @@ from __future__ import annotations
 
 from waq.compiler import compile_module
 from waq.parser.module import parse_module
+from waq.parser.wat import parse_wat
 
 
 def make_struct_new_wasm() -> bytes:
@@ -649,3 +650,98 @@ class TestRefInstructions:
         qbe = compile_module(module)
         output = qbe.emit()
         assert "__wasm_ref_cast" in output
+
+
+GC_ROOTS_WAT = """
+(module
+  (type $node (struct (field i32) (field (ref null $node))))
+  (global $head (mut (ref null $node)) (ref.null $node))
+  (func $mk (result (ref null $node))
+    (struct.new_default $node))
+  (func $pair (export "pair") (param $tail (ref null $node)) (result i32)
+    (local $n i32)
+    (struct.new $node (i32.const 1) (call $mk))
+    (global.set $head)
+    (local.get $n))
+)
+"""
+
+
+class TestCollectorSupport:
+    """Tests for the code that lets the runtime collector find references."""
+
+    def test_type_layouts_registered(self):
+        """Test struct layouts mark their reference fields."""
+        output = compile_module(parse_wat(GC_ROOTS_WAT)).emit()
+        assert "data $__wasm_gc_layout_0 = { b 0 1 }" in output
+        assert (
+            "call $__wasm_gc_register_type(w 0, w 0, w 2, l $__wasm_gc_layout_0, w -1)"
+            in output
+        )
+
+    def test_tag_layouts_registered(self):
+        """Test tags with reference params mark them for payload tracing."""
+        wat = """
+        (module
+          (type $node (struct (field i32)))
+          (tag (param i32))
+          (tag (param i64 (ref null $node) exnref)))
+        """
+        output = compile_module(parse_wat(wat)).emit()
+        assert "data $__wasm_gc_tag_layout_1 = { b 0 1 1 }" in output
+        assert (
+            "call $__wasm_gc_register_tag(w 1, w 3, l $__wasm_gc_tag_layout_1)"
+            in output
+        )
+        # Payloads of tags without reference params are never traced
+        assert "__wasm_gc_tag_layout_0" not in output
+
+    def test_reference_global_is_root(self):
+        """Test reference globals get a slot and are registered as roots."""
+        output = compile_module(parse_wat(GC_ROOTS_WAT)).emit()
+        assert "data $__wasm_global_0 = { l 0 }" in output
+        assert "call $__wasm_gc_add_root(l $__wasm_global_0)" in output
+
+    def test_reference_locals_live_in_shadow_frame(self):
+        """Test reference params are stored in the shadow stack frame."""
+        output = compile_module(parse_wat(GC_ROOTS_WAT)).emit()
+        func = output[output.index("$wasm_pair(") :]
+        func = func[: func.index("\n}")]
+        assert "call $__wasm_gc_push_frame(l %gc_frame, w " in func
+        assert "=l add %gc_frame, 16" in func
+        assert func.count("call $__wasm_gc_pop_frame(l %gc_frame)") == 1
+        assert func.index("__wasm_gc_pop_frame") < func.index("ret ")
+
+    def test_operands_spilled_before_collection(self):
+        """Test references only held as operands are spilled before calls."""
+        wat = """
+        (module
+          (type $node (struct (field (ref null $node)) (field (ref null $node))))
+          (func $mk (result (ref null $node)) (struct.new_default $node))
+          (func (export "both") (result (ref null $node))
+            (struct.new $node (call $mk) (call $mk)))
+        )
+        """
+        output = compile_module(parse_wat(wat)).emit()
+        func = output[output.index("$wasm_both(") :]
+        lines = func[: func.index("\n}")].splitlines()
+        first_call = next(i for i, line in enumerate(lines) if "$__wasm_func_0" in line)
+        first = lines[first_call].split("=")[0].strip()
+        # The first result is stored to the frame before the second call
+        spill = next(
+            i for i, line in enumerate(lines) if line.strip().startswith(f"storel {first}")
+        )
+        second_call = next(
+            i for i, line in enumerate(lines) if i > first_call and "$__wasm_func_0" in line
+        )
+        assert first_call < spill < second_call
+
+    def test_no_shadow_frames_without_gc_types(self):
+        """Test modules without struct or array types are unaffected."""
+        wat = """
+        (module
+          (func (export "f") (param externref) (result i32) (i32.const 0)))
+        """
+        output = compile_module(parse_wat(wat)).emit()
+        assert "__wasm_gc_push_frame" not in output
+        assert "__wasm_gc_register_type" not in output
@@ -0,0 +1,182 @@
+"""Unit tests for the C header of compiled modules (--emit header)."""
+
+from __future__ import annotations
+
+import pytest
+
+from waq.cli import generate_header
+from waq.errors import CompileError
+from waq.parser.wat import parse_wat
+
+MODULE = """
+    (module
+      (import "env" "log" (func (param i32 f64)))
+      (import "env" "pair" (func (result i32 i64)))
+      (import "env" "base" (global i32))
+      (import "env" "table" (table 2 funcref))
+      (memory (export "memory") 1)
+      (global (export "counter") (mut i64) (i64.const 0))
+      (global (export "limit") i32 (i32.const 10))
+      (func (export "add") (param i32 i32) (result i32)
+        (i32.add (local.get 0) (local.get 1)))
+      (func (export "divmod") (param i32 i32) (result i32 i32)
+        (i32.div_u (local.get 0) (local.get 1))
+        (i32.rem_u (local.get 0) (local.get 1)))
+      (func (export "ref") (param externref) (result funcref) (ref.null func)))
+"""
+
+
+def header(text: str, instance_mode: bool = False) -> str:
+    return generate_header(parse_wat(text), "demo", instance_mode=instance_mode)
+
+
+class TestLayout:
+    """Tests for the parts every header has."""
+
+    def test_include_guard_and_linkage(self):
+        output = generate_header(parse_wat("(module)"), "my-lib.v2")
+        assert "#ifndef WAQ_MY_LIB_V2_H\n#define WAQ_MY_LIB_V2_H" in output
+        assert output.endswith("#endif /* WAQ_MY_LIB_V2_H */\n")
+        assert '#ifdef __cplusplus\nextern "C" {\n#endif' in output
+        assert "#include <stdint.h>" in output
+
+    def test_init_sequence_documented(self):
+        output = header(MODULE)
+        assert " *     __wasm_memory_init();" in output
+        assert "__wasi_init" not in output
+        assert "extern void __wasm_memory_init(void);" in output
+        assert "extern void __wasm_runtime_cleanup(void);" in output
+
+    def test_wasi_and_reactor_init(self):
+        module = """
+            (module
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func (param i32 i32 i32 i32) (result i32)))
+              (func (export "_initialize")))
+        """
+        output = header(module)
+        usage = output.split("*/")[0]
+        assert usage.index("__wasi_init(argc, argv, environ);") < usage.index(
+            "__wasm_memory_init();"
+        )
+        assert usage.index("__wasm_memory_init();") < usage.index(
+            "wasm__initialize();"
+        )
+        assert "extern void __wasi_init(int, char **, char **);" in output
+        assert "/* wasi_snapshot_preview1: fd_write (in the runtime) */" in output
+
+
+class TestExports:
+    """Tests for exported functions, memories and globals."""
+
+    def test_function_prototypes(self):
+        output = header(MODULE)
+        assert "extern int32_t wasm_add(int32_t, int32_t);" in output
+        assert "extern void *wasm_ref(void *);" in output
+
+    def test_multi_value_out_pointers(self):
+        output = header(MODULE)
+        assert "extern int32_t wasm_divmod(int32_t, int32_t, int32_t *result1);" in (
+            output
+        )
+
+    def test_memory_accessors(self):
+        output = header(MODULE)
+        assert "static inline uint8_t *wasm_memory_data(void) {" in output
+        assert "    return __wasm_memory_base_idx(0);" in output
+        assert "static inline uint64_t wasm_memory_size(void) {" in output
+
+    def test_global_accessors(self):
+        output = header(MODULE)
+        assert "extern int64_t counter;" in output
+        assert "static inline int64_t wasm_counter_get(void) {" in output
+        assert "static inline void wasm_counter_set(int64_t value) {" in output
+        # Immutable globals can only be read
+        assert "static inline int32_t wasm_limit_get(void) {" in output
+        assert "wasm_limit_set" not in output
+
+    def test_names_that_are_not_identifiers(self):
+        output = header("""
+            (module
+              (memory (export "heap.0") 1)
+              (func (export "my-func.x") (param i32) (result i32) (local.get 0)))
+        """)
+        assert "#define WAQ_SYMBOL(name)" in output
+        assert (
+            'extern int32_t wasm_my_func_x(int32_t) WAQ_SYMBOL("wasm_my-func.x");'
+            in output
+        )
+        assert "static inline uint8_t *wasm_heap_0_data(void) {" in output
+
+    def test_identifiers_need_no_symbol_macro(self):
+        assert "WAQ_SYMBOL" not in header(MODULE)
+
+    def test_clashing_c_names(self):
+        module = '(module (func (export "a-b")) (func (export "a_b")))'
+        with pytest.raises(CompileError, match=r"wasm_a_b \('a-b', 'a_b'\)"):
+            header(module)
+
+    def test_reexported_import(self):
+        module = '(module (import "env" "f" (func)) (export "g" (func 0)))'
+        assert "/* g re-exports the import Z_envZ_f */" in header(module)
+
+
+class TestImports:
+    """Tests for the declarations the host has to define."""
+
+    def test_functions(self):
+        output = header(MODULE)
+        assert "/* env.log */\nextern void Z_envZ_log(int32_t, double);" in output
+        assert "extern int32_t Z_envZ_pair(int64_t *result1);" in output
+
+    def test_bare_names(self):
+        output = generate_header(parse_wat(MODULE), "demo", import_names="bare")
+        assert "/* env.log */\nextern void log(int32_t, double);" in output
+
+    def test_globals(self):
+        output = header(MODULE)
+        assert "/* env.base (i32) */\nextern int32_t __wasm_global_0;" in output
+
+    def test_memories_and_tables_noted(self):
+        module = '(module (import "env" "mem" (memory 3)))'
+        assert "__wasm_memory_grow_idx(0, 3)" in header(module)
+        assert "__wasm_table_grow(0, NULL, 2)" in header(MODULE)
+
+
+class TestInstanceMode:
+    """Tests for headers of modules compiled with --instance-mode."""
+
+    def test_instance_lifecycle(self):
+        output = header(MODULE, instance_mode=True)
+        assert "typedef struct WasmInstance WasmInstance;" in output
+        assert "waq_instance_new(__wasm_memory_init);" in output
+        assert "extern void waq_instance_free(WasmInstance *);" in output
+        assert "__wasm_runtime_cleanup" not in output
+
+    def test_functions_take_the_instance(self):
+        output = header(MODULE, instance_mode=True)
+        assert "extern int32_t wasm_add(WasmInstance *, int32_t, int32_t);" in output
+        assert "extern void Z_envZ_log(WasmInstance *, int32_t, double);" in output
+
+    def test_accessors_read_the_instance(self):
+        output = header(MODULE, instance_mode=True)
+        assert "    return waq_instance_memory(instance, 0);" in output
+        assert "extern int64_t counter;" not in output
+        assert (
+            "static inline int32_t wasm_limit_get(WasmInstance *instance) {\n"
+            "    return *(int32_t *)&waq_instance_globals(instance)[1];"
+        ) in output
+        assert (
+            "    *(int64_t *)&waq_instance_globals(instance)[0] = value;" in output
+        )
+
+    def test_imported_globals_are_shared(self):
+        module = """
+            (module
+              (import "env" "g" (global (mut i32)))
+              (export "shared" (global 0)))
+        """
+        output = header(module, instance_mode=True)
+        assert "extern int32_t shared;" in output
+        assert "int32_t wasm_shared_get(WasmInstance *instance) {" in output
+        assert "    (void)instance;\n    return shared;" in output
@@ -0,0 +1,291 @@
+"""Unit tests for host import bindings (--imports manifests)."""
+
+from __future__ import annotations
+
+import re
+
+import pytest
+
+from waq.cli import generate_header
+from waq.compiler import compile_module, link_modules
+from waq.compiler.imports import (
+    ImportBinding,
+    load_import_manifest,
+    mangle_import,
+    parse_import_manifest,
+)
+from waq.errors import CompileError
+from waq.parser.wat import parse_wat
+
+MODULE = """
+    (module
+      (import "env" "log" (func $log (param i32 i32)))
+      (import "env" "now" (func $now (result i64)))
+      (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+      (memory 1)
+      (func (export "run") (result i64)
+        (call $log (i32.const 8) (i32.const 4))
+        (call $exit (i32.const 0))
+        (call $now)))
+"""
+
+BINDINGS = {
+    ("env", "log"): ImportBinding("host_log"),
+    ("env", "now"): ImportBinding("host_now"),
+}
+
+
+def compile_wat(text: str, imports, instance_mode: bool = False) -> str:
+    """Parse WAT and return the emitted QBE IL."""
+    return compile_module(
+        parse_wat(text), instance_mode=instance_mode, imports=imports
+    ).emit()
+
+
+def function(output: str, name: str) -> str:
+    """Get the text of one emitted function."""
+    match = re.search(rf"function (\w+ )?\${name}\(.*?\n}}", output, re.DOTALL)
+    assert match, f"function ${name} not emitted"
+    return match.group(0)
+
+
+def with_log(binding: ImportBinding) -> dict:
+    return {**BINDINGS, ("env", "log"): binding}
+
+
+class TestImportNames:
+    """Tests for the symbols of unbound imports."""
+
+    @pytest.mark.parametrize(
+        ("module", "name", "symbol"),
+        [
+            ("env", "log", "Z_envZ_log"),
+            ("wasi_snapshot_preview1", "fd_read", "Z_wasi_snapshot_preview1Z_fd_read"),
+            ("Zed", "aZ_b", "Z_ZZedZ_aZZ_b"),
+            ("env", "log-v2", "Z_envZ_logZ2Dv2"),
+            ("env", "é", "Z_envZ_ZC3ZA9"),
+            ("", "", "Z_Z_"),
+        ],
+    )
+    def test_mangling(self, module, name, symbol):
+        assert mangle_import(module, name) == symbol
+
+    def test_no_collisions(self):
+        pairs = [("a", "bZ_c"), ("aZ_b", "c"), ("a_", "b"), ("a", "_b"), ("a", "Z5F")]
+        assert len({mangle_import(*pair) for pair in pairs}) == len(pairs)
+
+    def test_same_name_in_different_modules(self):
+        module = """
+            (module
+              (import "env" "write" (func $a (param i32)))
+              (import "host" "write" (func $b (param i32)))
+              (import "wasi_snapshot_preview1" "sched_yield" (func (result i32)))
+              (func (export "run")
+                (call $a (i32.const 1))
+                (call $b (i32.const 2))
+                (drop (call 2))))
+        """
+        run = function(compile_wat(module, None), "wasm_run")
+        assert "call $Z_envZ_write(w " in run
+        assert "call $Z_hostZ_write(w " in run
+        assert "call $Z_wasi_snapshot_preview1Z_sched_yield()" in run
+
+    def test_bare_names(self):
+        output = compile_module(parse_wat(MODULE), import_names="bare").emit()
+        run = function(output, "wasm_run")
+        assert "call $log(w " in run
+        # The runtime only has qualified WASI functions
+        assert "call $Z_wasi_snapshot_preview1Z_proc_exit(w " in run
+
+    def test_bindings_override_names(self):
+        output = compile_module(
+            parse_wat(MODULE), import_names="bare", imports=BINDINGS
+        ).emit()
+        assert "call $host_log(w " in function(output, "wasm_run")
+
+    def test_unknown_naming(self):
+        with pytest.raises(ValueError, match="unknown import naming"):
+            compile_module(parse_wat(MODULE), import_names="short")
+
+
+class TestManifest:
+    """Tests for reading manifests."""
+
+    def test_toml(self, tmp_path):
+        path = tmp_path / "imports.toml"
+        path.write_text(
+            'env.now = "host_now"\n'
+            "[env.log]\n"
+            'symbol = "host_log"\n'
+            'context = "memory"\n'
+            "buffers = [[0, 1]]\n"
+        )
+        assert load_import_manifest(path) == {
+            ("env", "now"): ImportBinding("host_now"),
+            ("env", "log"): ImportBinding("host_log", "memory", ((0, 1),)),
+        }
+
+    def test_json(self, tmp_path):
+        path = tmp_path / "imports.json"
+        path.write_text('{"env": {"now": "host_now", "log": {"symbol": "host_log"}}}')
+        assert load_import_manifest(path) == BINDINGS
+
+    def test_syntax_error(self, tmp_path):
+        path = tmp_path / "imports.toml"
+        path.write_text("env.now = \n")
+        with pytest.raises(ValueError, match="imports.toml"):
+            load_import_manifest(path)
+
+    @pytest.mark.parametrize(
+        ("data", "message"),
+        [
+            (["env"], "maps module names"),
+            ({"env": "host"}, "module 'env'"),
+            ({"env": {"f": "not a symbol"}}, "env.f: symbol must be a C identifier"),
+            ({"env": {"f": 1}}, "expected a C symbol"),
+            ({"env": {"f": {"symbol": "f", "context": "heap"}}}, "context must be"),
+            ({"env": {"f": {"symbol": "f", "buffers": [[0]]}}}, "buffers must be"),
+            ({"env": {"f": {"symbol": "f", "args": []}}}, "unknown keys args"),
+        ],
+    )
+    def test_malformed(self, data, message):
+        with pytest.raises(ValueError, match=message):
+            parse_import_manifest(data)
+
+
+class TestBindings:
+    """Tests for calls to bound imports."""
+
+    def test_direct_call(self):
+        run = function(compile_wat(MODULE, BINDINGS), "wasm_run")
+        assert "call $host_log(w " in run
+        assert "call $host_now()" in run
+        # WASI stays with the runtime
+        assert "call $Z_wasi_snapshot_preview1Z_proc_exit(w " in run
+
+    def test_bound_wasi_function(self):
+        imports = parse_import_manifest(
+            {"env": {"log": "host_log", "now": "host_now"}}
+            | {"wasi_snapshot_preview1": {"proc_exit": "my_exit"}}
+        )
+        run = function(compile_wat(MODULE, imports), "wasm_run")
+        assert "call $my_exit(w " in run
+
+    def test_memory_context(self):
+        output = compile_wat(MODULE, with_log(ImportBinding("host_log", "memory")))
+        assert "call $__wasm_import_0(w " in function(output, "wasm_run")
+        adapter = function(output, "__wasm_import_0")
+        assert "%memory =l call $__wasm_memory_base_idx(w 0)" in adapter
+        assert "call $host_log(l %memory, w %p0, w %p1)" in adapter
+
+    def test_buffers(self):
+        binding = ImportBinding("host_log", buffers=((0, 1),))
+        adapter = function(compile_wat(MODULE, with_log(binding)), "__wasm_import_0")
+        assert "%wide0_0 =l extuw %p0" in adapter
+        assert (
+            "%buffer0 =l call $__wasm_memory_buffer(w 0, l %wide0_0, l %wide1_0)"
+        ) in adapter
+        assert "call $host_log(l %buffer0, w %p1)" in adapter
+
+    def test_multi_value_results(self):
+        module = """
+            (module
+              (import "env" "pair" (func $pair (param i32 i32) (result i32 i64)))
+              (memory 1)
+              (func (export "run") (result i32 i64)
+                (call $pair (i32.const 0) (i32.const 4))))
+        """
+        imports = {("env", "pair"): ImportBinding("host_pair", "memory")}
+        adapter = function(compile_wat(module, imports), "__wasm_import_0")
+        assert "function w $__wasm_import_0(w %p0, w %p1, l %retptr1) {" in adapter
+        assert (
+            "%result =w call $host_pair(l %memory, w %p0, w %p1, l %retptr1)"
+        ) in adapter
+
+    def test_instance_mode(self):
+        binding = ImportBinding("host_log", "memory", ((0, 1),))
+        output = compile_wat(MODULE, with_log(binding), instance_mode=True)
+        run = function(output, "__wasm_run")
+        assert "call $host_now(l %vmctx)" in run
+        assert "call $__wasm_import_0(l %vmctx, w " in run
+        adapter = function(output, "__wasm_import_0")
+        assert "function $__wasm_import_0(l %vmctx, w %p0, w %p1) {" in adapter
+        assert "call $host_log(l %memory, l %buffer0, w %p1)" in adapter
+
+    def test_no_context_in_instance_mode(self):
+        binding = ImportBinding("host_log", "none")
+        output = compile_wat(MODULE, with_log(binding), instance_mode=True)
+        adapter = function(output, "__wasm_import_0")
+        assert "call $host_log(w %p0, w %p1)" in adapter
+
+
+class TestErrors:
+    """Tests for imports and bindings that cannot be compiled."""
+
+    def test_unbound_imports_listed(self):
+        module = """
+            (module
+              (import "env" "a" (func))
+              (import "env" "b" (func))
+              (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+              (import "host" "c" (func)))
+        """
+        with pytest.raises(CompileError) as exc_info:
+            compile_wat(module, {("env", "b"): ImportBinding("b")})
+        assert str(exc_info.value) == (
+            "2 imported functions without a binding in the manifest\n"
+            "  env.a\n"
+            "  host.c"
+        )
+
+    def test_instance_context_needs_instance_mode(self):
+        binding = ImportBinding("host_log", "instance")
+        with pytest.raises(CompileError, match="needs instance mode"):
+            compile_wat(MODULE, with_log(binding))
+
+    def test_memory_context_needs_memory(self):
+        module = '(module (import "env" "log" (func (param i32 i32))))'
+        binding = ImportBinding("host_log", buffers=((0, 1),))
+        with pytest.raises(CompileError, match="env.log: the module has no memory"):
+            compile_wat(module, with_log(binding))
+
+    @pytest.mark.parametrize("buffers", [((0, 1),), ((0, 2), (5, 0))])
+    def test_buffer_parameters(self, buffers):
+        module = """
+            (module
+              (import "env" "log" (func (param i32 f64 i32)))
+              (memory 1))
+        """
+        imports = {("env", "log"): ImportBinding("host_log", buffers=buffers)}
+        with pytest.raises(CompileError, match="is not an integer parameter"):
+            compile_wat(module, imports)
+
+    def test_linked_imports_need_no_binding(self):
+        app = parse_wat('(module (import "lib" "f" (func)) (import "env" "g" (func)))')
+        lib = parse_wat('(module (func (export "f")))')
+        # lib.f is resolved by the linker, env.g is left to the host
+        imports = {("env", "g"): ImportBinding("host_g")}
+        link_modules([("app", app), ("lib", lib)], imports=imports)
+        with pytest.raises(CompileError, match="env.g"):
+            link_modules([("app", app), ("lib", lib)], imports={})
+
+
+class TestHeader:
+    """Tests for the header declarations of bound imports."""
+
+    def test_bound_symbols(self):
+        binding = ImportBinding("host_log", "memory", ((0, 1),))
+        output = generate_header(parse_wat(MODULE), "demo", imports=with_log(binding))
+        assert (
+            "/* env.log */\nextern void host_log(uint8_t *, uint8_t *, int32_t);"
+        ) in output
+        assert "extern int64_t host_now(void);" in output
+        assert "/* wasi_snapshot_preview1: proc_exit (in the runtime) */" in output
+
+    def test_instance_mode(self):
+        binding = ImportBinding("host_log", "none")
+        output = generate_header(
+            parse_wat(MODULE), "demo", instance_mode=True, imports=with_log(binding)
+        )
+        assert "extern void host_log(int32_t, int32_t);" in output
+        assert "extern int64_t host_now(WasmInstance *);" in output
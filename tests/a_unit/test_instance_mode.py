@@ -0,0 +1,197 @@
+"""Unit tests for instance mode (--instance-mode)."""
+
+from __future__ import annotations
+
+import re
+
+import pytest
+
+from waq.compiler import compile_module
+from waq.errors import CompileError
+from waq.parser.wat import parse_wat
+
+MODULE = """
+    (module
+      (import "env" "log" (func $log (param i32)))
+      (import "wasi_snapshot_preview1" "proc_exit" (func $exit (param i32)))
+      (memory 1)
+      (table 2 funcref)
+      (global $count (mut i32) (i32.const 5))
+      (global $scale f64 (f64.const 1.5))
+      (type $t (func (result i32)))
+      (elem (i32.const 0) $get)
+      (start $hello)
+      (func $hello (call $log (i32.const 1)))
+      (func $get (result i32) (global.get $count))
+      (func (export "run") (param i32) (result i32)
+        (global.set $count (i32.load (local.get 0)))
+        (call $exit (i32.const 0))
+        (i32.add (call $get) (call_indirect (type $t) (i32.const 0)))))
+"""
+
+
+def compile_wat(text: str, instance_mode: bool = True) -> str:
+    """Parse WAT and return the emitted QBE IL."""
+    return compile_module(parse_wat(text), instance_mode=instance_mode).emit()
+
+
+def function(output: str, name: str) -> str:
+    """Get the text of one emitted function."""
+    match = re.search(rf"function (\w+ )?\${name}\(.*?\n}}", output, re.DOTALL)
+    assert match, f"function ${name} not emitted"
+    return match.group(0)
+
+
+class TestCalls:
+    """Every function takes the instance and passes it on."""
+
+    def test_vmctx_parameter(self):
+        output = compile_wat(MODULE)
+        assert "function w $__wasm_func_3(l %vmctx) {" in output
+        assert "function w $__wasm_run(l %vmctx, w %p0) {" in output
+
+    def test_direct_and_indirect_calls(self):
+        run = function(compile_wat(MODULE), "__wasm_run")
+        assert "call $__wasm_func_3(l %vmctx)" in run
+        assert re.search(r"call %\w+\(l %vmctx\)", run)
+
+    def test_host_imports_get_the_instance(self):
+        hello = function(compile_wat(MODULE), "__wasm_func_2")
+        assert "call $Z_envZ_log(l %vmctx, w " in hello
+
+    def test_wasi_imports_do_not(self):
+        run = function(compile_wat(MODULE), "__wasm_run")
+        assert "call $Z_wasi_snapshot_preview1Z_proc_exit(w " in run
+
+    def test_wasi_function_references_rejected(self):
+        module = """
+            (module
+              (import "wasi_snapshot_preview1" "sched_yield" (func (result i32)))
+              (table 1 funcref)
+              (elem (i32.const 0) 0))
+        """
+        with pytest.raises(CompileError, match="Z_sched_yield cannot be"):
+            compile_wat(module)
+        compile_wat(module, instance_mode=False)
+
+
+class TestState:
+    """Memory 0, table 0 and the defined globals live in the instance."""
+
+    def test_memory_from_instance(self):
+        run = function(compile_wat(MODULE), "__wasm_run")
+        assert re.search(r"=l load %vmctx\n", run)
+        assert "$__wasm_memory" not in run
+
+    def test_table_from_instance(self):
+        run = function(compile_wat(MODULE), "__wasm_run")
+        assert "add %vmctx, 16" in run
+        assert "add %vmctx, 24" in run
+        assert "add %vmctx, 32" in run
+        assert "$__wasm_table" not in run
+
+    def test_globals_in_slots(self):
+        output = compile_wat(MODULE)
+        assert "data $__wasm_global" not in output
+        get = function(output, "__wasm_func_3")
+        assert re.search(r"%(\w+) =l add %vmctx, 40\n\t%\w+ =l load %\1", get)
+        # Each defined global has an 8-byte slot
+        module = """
+            (module
+              (import "env" "g" (global i32))
+              (global i32 (i32.const 1))
+              (global f64 (f64.const 2))
+              (func (export "f") (result f64) (global.get 2)))
+        """
+        f = function(compile_wat(module), "__wasm_f")
+        assert re.search(r"=l add %\w+, 8\n\t%\w+ =d loadd ", f)
+
+    def test_imported_globals_stay_external(self):
+        module = """
+            (module
+              (import "env" "g" (global $g i32))
+              (func (export "f") (result i32) (global.get $g)))
+        """
+        assert "loadw $__wasm_global_0" in function(compile_wat(module), "__wasm_f")
+
+    def test_standalone_unchanged(self):
+        output = compile_wat(MODULE, instance_mode=False)
+        assert "vmctx" not in output
+        assert "__wasm_instance" not in output
+        assert "data $__wasm_global_0 = { w 5 }" in output
+
+
+class TestExports:
+    """Exported functions run their instance."""
+
+    def test_wrapper_enters_instance(self):
+        output = compile_wat(MODULE)
+        assert "export function w $wasm_run(l %vmctx, w %p0)" in output
+        wrapper = function(output, "wasm_run")
+        assert "%prev =l call $__wasm_instance_enter(l %vmctx)" in wrapper
+        assert "%result =w call $__wasm_run(l %vmctx, w %p0)" in wrapper
+        assert "call $__wasm_instance_leave(l %prev)" in wrapper
+        assert "ret %result" in wrapper
+
+    def test_body_not_exported(self):
+        output = compile_wat(MODULE)
+        assert "export function w $__wasm_run(" not in output
+
+    def test_multi_value_wrapper(self):
+        module = """
+            (module
+              (func (export "pair") (result i32 i64)
+                (i32.const 1) (i64.const 2)))
+        """
+        wrapper = function(compile_wat(module), "wasm_pair")
+        assert "$wasm_pair(l %vmctx, l %retptr1)" in wrapper
+        assert "call $__wasm_pair(l %vmctx, l %retptr1)" in wrapper
+
+
+class TestInit:
+    """__wasm_memory_init sets up the instance it is called with."""
+
+    def test_signature(self):
+        output = compile_wat(MODULE)
+        assert "export function $__wasm_memory_init(l %vmctx)" in output
+
+    def test_global_slots_initialized(self):
+        init = function(compile_wat(MODULE), "__wasm_memory_init")
+        assert "%globals =l call $__wasm_instance_globals(w 2)" in init
+        assert "storew 5, %globals" in init
+        # Floats are stored as their bits
+        assert "%global_1 =l add %globals, 8" in init
+        assert "storel 4609434218613702656, %global_1" in init
+
+    def test_zero_globals_left_alone(self):
+        module = "(module (global (mut i64) (i64.const 0)))"
+        init = function(compile_wat(module), "__wasm_memory_init")
+        assert "__wasm_instance_globals(w 1)" in init
+        assert "store" not in init
+
+    def test_function_reference_globals_initialized(self):
+        module = "(module (func $f) (global funcref (ref.func $f)))"
+        init = function(compile_wat(module), "__wasm_memory_init")
+        assert "storel $__wasm_func_0, %globals" in init
+        assert "call $__wasm_func_sig_register(l $__wasm_func_0, w 0)" in init
+
+    def test_start_function_gets_instance(self):
+        init = function(compile_wat(MODULE), "__wasm_memory_init")
+        assert "call $__wasm_func_2(l %vmctx)" in init
+
+    def test_data_copied_to_instance_memory(self):
+        module = '(module (memory 1) (data (i32.const 8) "hi"))'
+        init = function(compile_wat(module), "__wasm_memory_init")
+        # The runtime resolves the buffer in the instance being initialized
+        assert "call $__wasm_memory_buffer(w 0, l 8, l 2)" in init
+
+    def test_gc_roots_are_slots(self):
+        module = """
+            (module
+              (type $s (struct (field i32)))
+              (global (mut i32) (i32.const 0))
+              (global (mut (ref null $s)) (ref.null $s)))
+        """
+        init = function(compile_wat(module), "__wasm_memory_init")
+        assert "%root_1 =l add %globals, 8" in init
+        assert "call $__wasm_gc_add_root(l %root_1)" in init
@@ -0,0 +1,328 @@
+"""Unit tests for linking several modules into one program."""
+
+from __future__ import annotations
+
+import re
+
+import pytest
+
+from waq.compiler import link_modules
+from waq.errors import LinkError
+from waq.parser.wat import parse_wat
+
+MAIN = """
+    (module
+      (import "lib" "add" (func $add (param i32 i32) (result i32)))
+      (import "lib" "memory" (memory 1))
+      (import "lib" "base" (global $base i32))
+      (data (global.get $base) "hi")
+      (func $twice (param i32) (result i32)
+        (call $add (local.get 0) (local.get 0)))
+      (func (export "main") (result i32)
+        (call $twice (i32.load8_u (global.get $base)))))
+"""
+
+LIB = """
+    (module
+      (memory (export "memory") 1)
+      (global (export "base") i32 (i32.const 64))
+      (global $count (mut i32) (i32.const 0))
+      (data (i32.const 0) "lib")
+      (func $bump (global.set $count (i32.add (global.get $count) (i32.const 1))))
+      (func (export "add") (param i32 i32) (result i32)
+        (call $bump)
+        (i32.add (local.get 0) (local.get 1))))
+"""
+
+
+def link_wat(*modules: tuple[str, str]) -> str:
+    """Link named WAT modules and return the emitted QBE IL."""
+    return link_modules([(name, parse_wat(text)) for name, text in modules]).emit()
+
+
+def function(output: str, name: str) -> str:
+    """Get the text of one emitted function."""
+    match = re.search(rf"function (\w+ )?\${name}\(.*?\n}}", output, re.DOTALL)
+    assert match, f"function ${name} not emitted"
+    return match.group(0)
+
+
+class TestSymbols:
+    """Main module symbols stay as they are; others get a prefix."""
+
+    def test_main_module_unprefixed(self):
+        output = link_wat(("main", MAIN), ("lib", LIB))
+        assert "export function w $wasm_main()" in output
+        assert "function w $__wasm_func_1(" in output
+        assert "data $__wasm_data_0 = " in output
+
+    def test_library_prefixed(self):
+        output = link_wat(("main", MAIN), ("lib", LIB))
+        assert "export function w $lib__wasm_add(" in output
+        assert "function $lib____wasm_func_0(" in output
+        assert "data $lib__base = { w 64 }" in output
+        assert "data $lib____wasm_global_1 = " in output
+        assert "data $lib____wasm_data_0 = " in output
+
+    def test_imports_resolve_to_exports(self):
+        output = link_wat(("main", MAIN), ("lib", LIB))
+        assert "call $lib__wasm_add(" in output
+        assert "loadw $lib__base" in output
+        assert "$add(" not in output.replace("wasm_add(", "")
+
+    def test_prefix_sanitized(self):
+        output = link_wat(("main", MAIN), ("lib", LIB), ("my-lib.v2", "(module)"))
+        assert "$my_lib_v2____wasm_module_init()" in output
+
+    def test_reexports_followed(self):
+        shim = """
+            (module
+              (import "lib" "add" (func (param i32 i32) (result i32)))
+              (import "lib" "memory" (memory 1))
+              (import "lib" "base" (global i32))
+              (export "add" (func 0))
+              (export "memory" (memory 0))
+              (export "base" (global 0)))
+        """
+        main = MAIN.replace('"lib"', '"shim"')
+        output = link_wat(("main", main), ("shim", shim), ("lib", LIB))
+        assert "call $lib__wasm_add(" in output
+        assert "loadw $lib__base" in output
+
+    def test_other_imports_stay_external(self):
+        main = """
+            (module
+              (import "env" "log" (func $log (param i32)))
+              (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+              (func (export "main") (call $log (i32.const 1))))
+        """
+        lib = """
+            (module
+              (import "env" "log" (func $log (param i32)))
+              (func (export "f") (call $log (i32.const 2)) (call 1)))
+        """
+        output = link_wat(("main", main), ("lib", lib))
+        assert output.count("call $Z_envZ_log(w ") == 2
+
+
+class TestIndices:
+    """Memories, tables, tags and segments share the runtime's index spaces."""
+
+    def test_imported_memory_is_the_exporters(self):
+        output = link_wat(("main", MAIN), ("lib", LIB))
+        # Both modules use memory 0, which only the library sets up
+        assert "__wasm_memory_base_idx" not in output
+        assert "__wasm_memory_grow(w 1)" in function(output, "lib____wasm_module_init")
+        assert "__wasm_memory_grow" not in function(output, "__wasm_module_init")
+
+    def test_defined_memories_numbered_in_order(self):
+        module = """
+            (module
+              (memory 1)
+              (func (export "size{0}") (result i32) (memory.size))
+              (func (export "load{0}") (result i32) (i32.load (i32.const 0))))
+        """
+        output = link_wat(("a", module.format("a")), ("b", module.format("b")))
+        assert "call $__wasm_memory_size_pages_idx(w 0)" in function(
+            output, "wasm_sizea"
+        )
+        assert "call $__wasm_memory_size_pages_idx(w 1)" in function(
+            output, "b__wasm_sizeb"
+        )
+        assert "load $__wasm_memory" in function(output, "wasm_loada")
+        assert "$__wasm_memory_base_idx(w 1)" in function(output, "b__wasm_loadb")
+        assert "__wasm_memory_grow_idx(w 1, w 1)" in output
+
+    def test_external_memory_shared_by_import_name(self):
+        module = """
+            (module
+              (import "env" "memory" (memory 1))
+              (memory 1)
+              (func (export "size{0}") (result i32) (memory.size 1)))
+        """
+        output = link_wat(("a", module.format("a")), ("b", module.format("b")))
+        assert "_size_pages_idx(w 1)" in function(output, "wasm_sizea")
+        assert "_size_pages_idx(w 2)" in function(output, "b__wasm_sizeb")
+
+    def test_segments_follow_each_other(self):
+        module = """
+            (module
+              (memory 1)
+              (data "x")
+              (data "y")
+              (func (export "drop{0}") (data.drop 1)))
+        """
+        output = link_wat(("a", module.format("a")), ("b", module.format("b")))
+        assert "call $__wasm_data_drop(w 1)" in output
+        assert "call $__wasm_data_drop(w 3)" in output
+        assert "_register_data_segment(w 3, l $b____wasm_data_1, l 1)" in output
+
+    def test_tables_and_elements(self):
+        main = """
+            (module
+              (import "lib" "table" (table 2 funcref))
+              (type $t (func (result i32)))
+              (func (export "main") (result i32)
+                (call_indirect (type $t) (i32.const 1))))
+        """
+        lib = """
+            (module
+              (table (export "other") 1 funcref)
+              (table (export "table") 2 funcref)
+              (elem (table 1) (i32.const 1) func $f)
+              (func $f (result i32) (i32.const 7)))
+        """
+        output = link_wat(("main", main), ("lib", lib))
+        # Main's table 0 is runtime table 0, even though the library defines it
+        # as its second table
+        assert "loadl $__wasm_table_sigs" in function(output, "wasm_main")
+        init = function(output, "lib____wasm_module_init")
+        assert "call $__wasm_table_grow(w 1, l 0, w 1)" in init
+        assert "call $__wasm_table_grow(w 0, l 0, w 2)" in init
+        assert "call $__wasm_table_set(w 0, w 1, l $lib____wasm_func_0)" in init
+
+    def test_tags_shared(self):
+        main = """
+            (module
+              (import "lib" "oops" (tag $oops (param i32)))
+              (import "lib" "fail" (func $fail))
+              (tag $mine)
+              (func (export "main") (result i32)
+                (try (result i32)
+                  (do (call $fail) (i32.const 0))
+                  (catch $oops)
+                  (catch $mine (i32.const 1)))))
+        """
+        lib = """
+            (module
+              (tag (export "oops") (param i32))
+              (func (export "fail") (throw 0 (i32.const 7))))
+        """
+        output = link_wat(("main", main), ("lib", lib))
+        assert "__wasm_throw_with_payload(w 0," in output
+        main_func = function(output, "wasm_main")
+        assert "ceqw %" in main_func
+        assert re.search(r"ceqw %\w+, 0\b", main_func)
+        assert re.search(r"ceqw %\w+, 1\b", main_func)
+
+    def test_equivalent_types_share_signature_ids(self):
+        main = """
+            (module
+              (type (func (param f64)))
+              (type (func (result i32)))
+              (func (export "main") (type 1) (i32.const 0)))
+        """
+        lib = """
+            (module
+              (type (func (result i32)))
+              (type (func (param i64)))
+              (func (export "f") (type 0) (i32.const 1))
+              (func (export "g") (type 1)))
+        """
+        output = link_wat(("main", main), ("lib", lib))
+        assert "__wasm_func_sig_register(l $wasm_main, w 1)" in output
+        assert "__wasm_func_sig_register(l $lib__wasm_f, w 1)" in output
+        assert "__wasm_func_sig_register(l $lib__wasm_g, w 3)" in output
+
+
+class TestInit:
+    """Initialization of the linked modules."""
+
+    def test_dependencies_initialized_first(self):
+        output = link_wat(("main", MAIN), ("lib", LIB))
+        init = function(output, "__wasm_memory_init")
+        assert init.index("$lib____wasm_module_init()") < init.index(
+            "$__wasm_module_init()"
+        )
+
+    def test_start_functions_run_in_their_init(self):
+        lib = LIB.replace("(func $bump", "(start $bump) (func $bump")
+        output = link_wat(("main", MAIN), ("lib", lib))
+        assert "call $lib____wasm_func_0()" in function(
+            output, "lib____wasm_module_init"
+        )
+
+    def test_imported_global_values(self):
+        output = link_wat(("main", MAIN), ("lib", LIB))
+        # The data segment is placed at lib's "base"
+        init = function(output, "__wasm_module_init")
+        assert "call $__wasm_memory_buffer(w 0, l 64, " in init
+
+    def test_single_module(self):
+        output = link_wat(("lib", LIB))
+        assert "$lib__" not in output
+        assert "call $__wasm_module_init()" in function(output, "__wasm_memory_init")
+
+
+class TestErrors:
+    """Imports that cannot be resolved."""
+
+    def test_missing_export(self):
+        with pytest.raises(LinkError, match=r"lib\.sub, which module 'lib'"):
+            link_wat(("main", MAIN.replace('"add"', '"sub"')), ("lib", LIB))
+
+    def test_wrong_kind(self):
+        main = '(module (import "lib" "base" (memory 1)))'
+        with pytest.raises(LinkError, match="imports memory lib.base"):
+            link_wat(("main", main), ("lib", LIB))
+
+    def test_function_type_mismatch(self):
+        main = '(module (import "lib" "add" (func (param i64))))'
+        with pytest.raises(LinkError, match="imports function lib.add as"):
+            link_wat(("main", main), ("lib", LIB))
+
+    def test_global_type_mismatch(self):
+        main = '(module (import "lib" "base" (global (mut i32))))'
+        with pytest.raises(LinkError, match="imports global lib.base as mut i32"):
+            link_wat(("main", main), ("lib", LIB))
+
+    def test_memory_index_type_mismatch(self):
+        main = '(module (import "lib" "memory" (memory i64 1)))'
+        with pytest.raises(LinkError, match="imports memory lib.memory as"):
+            link_wat(("main", main), ("lib", LIB))
+
+    def test_memory_limits_mismatch(self):
+        main = '(module (import "lib" "memory" (memory 2)))'
+        with pytest.raises(LinkError, match="as memory 2.., but it is memory 1.."):
+            link_wat(("main", main), ("lib", LIB))
+        # The library's memory has no maximum
+        main = '(module (import "lib" "memory" (memory 1 4)))'
+        with pytest.raises(LinkError, match="imports memory lib.memory"):
+            link_wat(("main", main), ("lib", LIB))
+
+    def test_table_mismatch(self):
+        lib = '(module (table (export "t") 2 8 funcref))'
+        for table in ("i64 2 funcref", "2 externref", "3 funcref", "2 4 funcref"):
+            main = f'(module (import "lib" "t" (table {table})))'
+            with pytest.raises(LinkError, match="imports table lib.t as"):
+                link_wat(("main", main), ("lib", lib))
+        # Smaller minimums and larger maximums match
+        main = '(module (import "lib" "t" (table 1 9 funcref)))'
+        link_wat(("main", main), ("lib", lib))
+
+    def test_wasi_needs_main_memory(self):
+        lib = """
+            (module
+              (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+              (memory 1))
+        """
+        with pytest.raises(LinkError, match="module 'lib' imports WASI"):
+            link_wat(("main", "(module (memory 1))"), ("lib", lib))
+        # Sharing the main module's memory is fine
+        lib = lib.replace("(memory 1)", '(import "main" "memory" (memory 1))')
+        main = '(module (memory (export "memory") 1))'
+        link_wat(("main", main), ("lib", lib))
+
+    def test_import_cycle(self):
+        a = '(module (import "b" "f" (func)) (export "f" (func 0)))'
+        b = '(module (import "a" "f" (func)) (export "f" (func 0)))'
+        with pytest.raises(LinkError, match="imported in a cycle"):
+            link_wat(("a", a), ("b", b))
+
+    def test_duplicate_names(self):
+        with pytest.raises(LinkError, match="duplicate module name 'lib'"):
+            link_wat(("lib", LIB), ("lib", LIB))
+
+    def test_colliding_prefixes(self):
+        with pytest.raises(LinkError, match="would get the same symbols"):
+            link_wat(("main", "(module)"), ("a-b", "(module)"), ("a.b", "(module)"))
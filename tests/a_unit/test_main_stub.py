@@ -0,0 +1,286 @@
+"""Unit tests for the C main() stub of executables."""
+
+from __future__ import annotations
+
+import pytest
+
+from waq.cli import (
+    Preopen,
+    WasiConfig,
+    default_entry,
+    entry_func_type,
+    generate_main_stub,
+    is_wasi_module,
+)
+from waq.compiler import compile_module
+from waq.parser.types import FuncType, ValueType
+from waq.parser.wat import parse_wat
+
+
+class TestMainStub:
+    """Tests for generate_main_stub."""
+
+    def test_i32_entry_without_params(self):
+        stub = generate_main_stub("main", FuncType((), (ValueType.I32,)))
+        assert "extern int32_t wasm_main(void);" in stub
+        assert "int32_t r0 = wasm_main();" in stub
+        assert 'printf("%" PRId32 "\\n", r0);' in stub
+        assert "if (argc != 1)" in stub
+
+    def test_params_parsed_by_type(self):
+        func_type = FuncType(
+            (ValueType.I32, ValueType.F64, ValueType.I32), (ValueType.I64,)
+        )
+        stub = generate_main_stub("f", func_type)
+        assert "extern int64_t wasm_f(int32_t, double, int32_t);" in stub
+        assert "wasm_f(arg_i32(argv[1], 1), arg_f64(argv[2], 2), " in stub
+        assert "usage: %s <i32> <f64> <i32>" in stub
+        # One parser per parameter type
+        assert stub.count("static int32_t arg_i32(") == 1
+        assert "arg_i64(" not in stub
+        assert 'printf("%" PRId64 "\\n", r0);' in stub
+
+    def test_float_results(self):
+        stub = generate_main_stub("f", FuncType((), (ValueType.F32,)))
+        assert "float r0 = wasm_f();" in stub
+        assert 'printf("%.9g\\n", (double)r0);' in stub
+        stub = generate_main_stub("f", FuncType((), (ValueType.F64,)))
+        assert 'printf("%.17g\\n", r0);' in stub
+
+    def test_multi_value_results_use_out_pointers(self):
+        func_type = FuncType(
+            (ValueType.I32,),
+            (ValueType.I32, ValueType.F64, ValueType.EXTERNREF),
+        )
+        stub = generate_main_stub("pair", func_type)
+        assert "extern int32_t wasm_pair(int32_t, double *, void **);" in stub
+        assert "double r1;" in stub
+        assert "void *r2;" in stub
+        assert "wasm_pair(arg_i32(argv[1], 1), &r1, &r2)" in stub
+        assert 'printf("%.17g\\n", r1);' in stub
+        assert 'printf("%p\\n", r2);' in stub
+
+    def test_void_entry(self):
+        stub = generate_main_stub("run", FuncType((), ()))
+        assert "extern void wasm_run(void);" in stub
+        assert "    wasm_run();" in stub
+        assert "printf(" not in stub.split("wasm_run();")[-1]
+
+    def test_no_print_returns_i32_result(self):
+        stub = generate_main_stub(
+            "main", FuncType((), (ValueType.I32,)), print_result=False
+        )
+        assert "return r0;" in stub
+        assert "PRId32" not in stub.split("int main")[1]
+
+    def test_no_print_ignores_other_results(self):
+        stub = generate_main_stub(
+            "main", FuncType((), (ValueType.F64,)), print_result=False
+        )
+        assert "return r0;" not in stub
+        assert "return 0;" in stub
+
+    def test_reference_params_rejected(self):
+        with pytest.raises(ValueError, match="cannot be passed"):
+            generate_main_stub("f", FuncType((ValueType.EXTERNREF,), ()))
+
+
+class TestWasiStub:
+    """Tests for the main() stub of WASI commands and reactors."""
+
+    def test_wasi_command(self):
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=WasiConfig())
+        assert "extern void wasm__start(void);" in stub
+        body = stub.split("int main")[1]
+        # Arguments belong to the program, so any number is accepted
+        assert "if (argc" not in body
+        assert body.index("__wasi_init(argc, argv, wasi_env);") < body.index(
+            "__wasm_memory_init();"
+        )
+        assert "    wasm__start();\n    return 0;" in body
+
+    def test_wasi_entry_with_params_allows_extra_args(self):
+        stub = generate_main_stub(
+            "f", FuncType((ValueType.I32,), ()), wasi=WasiConfig()
+        )
+        assert "if (argc < 2)" in stub
+
+    def test_reactor_initialized_before_entry(self):
+        stub = generate_main_stub(
+            "run", FuncType((), ()), wasi=WasiConfig(), initialize=True
+        )
+        assert "extern void wasm__initialize(void);" in stub
+        body = stub.split("int main")[1]
+        assert body.index("__wasm_memory_init();") < body.index(
+            "wasm__initialize();"
+        )
+        assert body.index("wasm__initialize();") < body.index("wasm_run();")
+
+    def test_sandboxed_by_default(self):
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=WasiConfig())
+        assert "static char *wasi_env[] = {NULL};" in stub
+        assert "environ" not in stub.replace("wasi_env", "")
+        assert "__wasi_preopen(" not in stub.split("int main")[1]
+
+    def test_explicit_environment(self):
+        wasi = WasiConfig(env=[("HOME", "/home/guest"), ("EMPTY", "")])
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=wasi)
+        assert 'static char *wasi_env[] = {"HOME=/home/guest", "EMPTY=", NULL};' in stub
+
+    def test_inherited_environment(self):
+        wasi = WasiConfig(env=[("LANG", "C")], inherit_env=True)
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=wasi)
+        assert "extern char **environ;" in stub
+        body = stub.split("int main")[1]
+        # Explicit variables override inherited ones
+        assert body.index('setenv("LANG", "C", 1);') < body.index(
+            "__wasi_init(argc, argv, environ);"
+        )
+
+    def test_preopened_directories(self):
+        wasi = WasiConfig(
+            dirs=[Preopen("data", "/data"), Preopen("/etc", "/etc", read_only=True)]
+        )
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=wasi)
+        body = stub.split("int main")[1]
+        assert 'if (__wasi_preopen("data", "/data", 0) < 0) {' in body
+        assert 'perror("data");' in body
+        assert '__wasi_preopen("/etc", "/etc", 1)' in body
+        assert body.index("__wasi_init(") < body.index("__wasi_preopen(")
+        assert body.index('"data"') < body.index('"/etc"')
+
+    def test_listening_sockets_come_before_directories(self):
+        wasi = WasiConfig(
+            dirs=[Preopen("data", "/data")],
+            listen=["127.0.0.1:8080", "[::1]:8081"],
+        )
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=wasi)
+        assert "extern int __wasi_listen(const char *);" in stub
+        body = stub.split("int main")[1]
+        assert 'if (__wasi_listen("127.0.0.1:8080") < 0) {' in body
+        assert 'perror("127.0.0.1:8080");' in body
+        assert body.index("__wasi_init(") < body.index('"127.0.0.1:8080"')
+        assert body.index('"127.0.0.1:8080"') < body.index('"[::1]:8081"')
+        assert body.index('"[::1]:8081"') < body.index("__wasi_preopen(")
+
+    def test_no_sockets_by_default(self):
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=WasiConfig())
+        assert "__wasi_listen" not in stub
+
+    def test_deterministic(self):
+        wasi = WasiConfig(deterministic=True, seed=42, dirs=[Preopen("d", "/d")])
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=wasi)
+        assert "extern void __wasi_set_deterministic(uint64_t);" in stub
+        body = stub.split("int main")[1]
+        assert "__wasi_set_deterministic(42ULL);" in body
+        # Clocks are virtual before anything else runs
+        assert body.index("__wasi_init(") < body.index("__wasi_set_deterministic(")
+        assert body.index("__wasi_set_deterministic(") < body.index("__wasi_preopen(")
+
+    def test_not_deterministic_by_default(self):
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=WasiConfig())
+        assert "__wasi_set_deterministic" not in stub
+
+    def test_strings_escaped(self):
+        wasi = WasiConfig(env=[("MSG", 'say "hi"\\n\u00e9')])
+        stub = generate_main_stub("_start", FuncType((), ()), wasi=wasi)
+        assert '"MSG=say \\042hi\\042\\134n\\303\\251"' in stub
+
+    def test_non_wasi_stub_skips_wasi_init(self):
+        stub = generate_main_stub("main", FuncType((), (ValueType.I32,)))
+        assert "__wasi_init" not in stub
+        assert "_initialize" not in stub
+
+
+class TestInstanceStub:
+    """Tests for the main() stub of modules compiled in instance mode."""
+
+    def test_instance_created_and_passed(self):
+        func_type = FuncType((ValueType.I32,), (ValueType.I32, ValueType.F64))
+        stub = generate_main_stub("f", func_type, instance_mode=True)
+        assert "typedef struct WasmInstance WasmInstance;" in stub
+        assert "extern void __wasm_memory_init(WasmInstance *);" in stub
+        assert "extern int32_t wasm_f(WasmInstance *, int32_t, double *);" in stub
+        body = stub.split("int main")[1]
+        assert "__wasm_memory_init();" not in body
+        assert (
+            "WasmInstance *instance = waq_instance_new(__wasm_memory_init);" in body
+        )
+        assert "if (instance == NULL) {" in body
+        assert "wasm_f(instance, arg_i32(argv[1], 1), &r1)" in body
+
+    def test_reactor_initialized_in_instance(self):
+        stub = generate_main_stub(
+            "run",
+            FuncType((), ()),
+            wasi=WasiConfig(),
+            initialize=True,
+            instance_mode=True,
+        )
+        assert "extern void wasm__initialize(WasmInstance *);" in stub
+        body = stub.split("int main")[1]
+        assert body.index("__wasi_init(") < body.index("waq_instance_new(")
+        assert body.index("waq_instance_new(") < body.index(
+            "wasm__initialize(instance);"
+        )
+        assert "    wasm_run(instance);" in body
+
+
+class TestWasiModules:
+    """Tests for recognizing WASI commands."""
+
+    COMMAND = """
+        (module
+          (import "wasi_snapshot_preview1" "proc_exit" (func (param i32)))
+          (memory (export "memory") 1)
+          (func (export "_start") (call 0 (i32.const 3))))
+    """
+
+    def test_is_wasi_module(self):
+        assert is_wasi_module(parse_wat(self.COMMAND))
+        assert not is_wasi_module(parse_wat('(module (func (export "main")))'))
+
+    def test_default_entry(self):
+        assert default_entry(parse_wat(self.COMMAND)) == "_start"
+        assert default_entry(parse_wat('(module (func (export "main")))')) == "main"
+
+    def test_start_does_not_clash_with_c_startup(self):
+        output = compile_module(parse_wat(self.COMMAND)).emit()
+        assert "$wasm__start(" in output
+        assert "$_start(" not in output
+        func_type = entry_func_type(parse_wat(self.COMMAND), "_start")
+        assert func_type == FuncType((), ())
+
+    def test_wasi_imports_use_runtime_names(self):
+        output = compile_module(parse_wat(self.COMMAND)).emit()
+        assert "call $Z_wasi_snapshot_preview1Z_proc_exit(w " in output
+        assert "$proc_exit(" not in output
+
+    def test_other_imports_are_qualified(self):
+        module = parse_wat("""
+            (module
+              (import "env" "proc_exit" (func (param i32)))
+              (func (export "main") (call 0 (i32.const 3))))
+        """)
+        output = compile_module(module).emit()
+        assert "call $Z_envZ_proc_exit(w " in output
+        assert "wasi" not in output
+
+
+class TestEntryFuncType:
+    """Tests for looking up the entry function's type."""
+
+    def test_entry_found_by_mangled_name(self):
+        module = parse_wat("""
+            (module
+              (func (export "wasm_main") (param i64) (result f32)
+                (f32.const 1)))
+        """)
+        func_type = entry_func_type(module, "main")
+        assert func_type.params == (ValueType.I64,)
+        assert func_type.results == (ValueType.F32,)
+
+    def test_missing_entry(self):
+        module = parse_wat('(module (func (export "other")))')
+        with pytest.raises(ValueError, match="'main' is not exported"):
+            entry_func_type(module, "main")
@@ -50,8 +50,8 @@ def make_load_wasm(load_opcode: int, value_type: int = 0x7F) -> bytes:
         0x20,
         0x00,  # local.get 0
         load_opcode,
-        0x02,
-        0x00,  # align=2, offset=0
+        0x00,
+        0x00,  # align=1, offset=0
         0x0B,  # end
     ])
 
@@ -102,8 +102,8 @@ def make_store_wasm(store_opcode: int, value_type: int = 0x7F) -> bytes:
         0x20,
         0x01,  # local.get 1 (value)
         store_opcode,
-        0x02,
-        0x00,  # align=2, offset=0
+        0x00,
+        0x00,  # align=1, offset=0
         0x0B,  # end
     ])
 
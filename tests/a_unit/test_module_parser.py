@@ -115,6 +115,24 @@ class TestTypeSection:
         assert module.types[0] == FuncType((), ())
         assert module.types[1] == FuncType((ValueType.I32,), (ValueType.I32,))
 
+    def test_rec_group_and_subtypes(self):
+        # (rec (type (sub (func))) (type (sub final 0 (func))))
+        # (type (sub 1 (func)))
+        # fmt: off
+        section = bytes([
+            0x02,  # two entries: a rec group and a plain subtype
+            0x4E, 0x02,  # rec group of two
+            0x50, 0x00, 0x60, 0x00, 0x00,  # sub (no supertypes) func
+            0x4F, 0x01, 0x00, 0x60, 0x00, 0x00,  # sub final 0 func
+            0x50, 0x01, 0x01, 0x60, 0x00, 0x00,  # sub 1 func
+        ])
+        # fmt: on
+        wasm = b"\x00asm\x01\x00\x00\x00" + bytes([0x01, len(section)]) + section
+        module = parse_module(wasm)
+        assert module.types == [FuncType((), ())] * 3
+        assert module.supertypes == {1: 0, 2: 1}
+        assert module.rec_groups == [(0, 2)]
+
 
 class TestFunctionSection:
     """Tests for function section parsing."""
@@ -323,6 +341,97 @@ class TestImportSection:
         assert module.imports[0].desc == 0
 
 
+class TestTagSection:
+    """Tests for tag section parsing (exception handling)."""
+
+    def test_tag_section_with_import_and_export(self):
+        wasm = bytes([
+            0x00,
+            0x61,
+            0x73,
+            0x6D,  # magic
+            0x01,
+            0x00,
+            0x00,
+            0x00,  # version
+            # Type section
+            0x01,
+            0x08,
+            0x02,
+            0x60,
+            0x00,
+            0x00,  # () -> ()
+            0x60,
+            0x01,
+            0x7F,
+            0x00,  # (i32) -> ()
+            # Import section
+            0x02,
+            0x0B,
+            0x01,  # one import
+            0x03,
+            0x65,
+            0x6E,
+            0x76,  # "env"
+            0x02,
+            0x65,
+            0x78,  # "ex"
+            0x04,  # import kind: tag
+            0x00,  # attribute: exception
+            0x01,  # type index
+            # Tag section
+            0x0D,
+            0x03,
+            0x01,  # one tag
+            0x00,  # attribute: exception
+            0x00,  # type index
+            # Export section
+            0x07,
+            0x05,
+            0x01,  # one export
+            0x01,
+            0x74,  # "t"
+            0x04,  # export kind: tag
+            0x01,  # tag index
+        ])
+        module = parse_module(wasm)
+        assert module.imports[0].kind == ImportKind.TAG
+        assert module.imports[0].desc == 1
+        assert module.tags == [0]
+        assert module.exports[0].kind == ExportKind.TAG
+        assert module.exports[0].index == 1
+        assert module.num_imported_tags() == 1
+        assert module.get_tag_type(0) == FuncType((ValueType.I32,), ())
+        assert module.get_tag_type(1) == FuncType((), ())
+
+    def test_invalid_tag_attribute(self):
+        wasm = bytes([
+            0x00,
+            0x61,
+            0x73,
+            0x6D,  # magic
+            0x01,
+            0x00,
+            0x00,
+            0x00,  # version
+            # Type section
+            0x01,
+            0x04,
+            0x01,
+            0x60,
+            0x00,
+            0x00,  # () -> ()
+            # Tag section
+            0x0D,
+            0x03,
+            0x01,
+            0x01,  # attribute: invalid
+            0x00,
+        ])
+        with pytest.raises(ParseError, match="invalid tag attribute"):
+            parse_module(wasm)
+
+
 class TestCodeSection:
     """Tests for code section parsing."""
 
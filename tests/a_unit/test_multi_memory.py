@@ -2,8 +2,12 @@
 
 from __future__ import annotations
 
+import pytest
+
 from waq.compiler import compile_module
+from waq.errors import CompileError
 from waq.parser.module import parse_module
+from waq.parser.wat import parse_wat
 
 
 def make_multi_memory_wasm() -> bytes:
@@ -211,3 +215,87 @@ class TestSingleMemoryOptimization:
         assert "$__wasm_memory" in output
         # Should NOT call __wasm_memory_base (that's for multi-memory)
         assert "__wasm_memory_base" not in output
+
+
+TWO_MEMORIES = """
+    (module
+      (memory $a 1 2)
+      (memory $b 2)
+      (data (i32.const 8) "ab")
+      (data (memory $b) (i32.const 16) "cd")
+      (func (export "load") (param i32) (result i32)
+        (i32.add
+          (i32.load8_u $a (local.get 0))
+          (i32.load8_u $b (local.get 0))))
+      (func (export "bulk") (param i32)
+        (memory.fill $b (local.get 0) (i32.const 0) (i32.const 4))
+        (memory.copy $a $b (local.get 0) (i32.const 16) (i32.const 2))
+        (memory.copy (local.get 0) (i32.const 8) (i32.const 2))))
+"""
+
+
+class TestSeparateMemories:
+    """Tests that each memory is backed by its own runtime buffer."""
+
+    def test_every_memory_initialized(self):
+        output = compile_module(parse_wat(TWO_MEMORIES)).emit()
+        assert "call $__wasm_memory_set_max(w 0, w 2)" in output
+        assert "call $__wasm_memory_grow(w 1)" in output
+        assert "call $__wasm_memory_grow_idx(w 1, w 2)" in output
+
+    def test_data_segments_routed_by_memory(self):
+        output = compile_module(parse_wat(TWO_MEMORIES)).emit()
+        assert "%dest_0 =l call $__wasm_memory_buffer(w 0, l 8, l 2)" in output
+        assert "%dest_1 =l call $__wasm_memory_buffer(w 1, l 16, l 2)" in output
+
+    def test_memory_zero_uses_global(self):
+        output = compile_module(parse_wat(TWO_MEMORIES)).emit()
+        assert "$__wasm_memory\n" in output
+        assert "call $__wasm_memory_base_idx(w 0)" not in output
+        assert "call $__wasm_memory_base_idx(w 1)" in output
+
+    def test_bulk_operations_pass_memory_indices(self):
+        output = compile_module(parse_wat(TWO_MEMORIES)).emit()
+        assert "call $__wasm_memory_fill_idx(w 1, " in output
+        assert "call $__wasm_memory_copy_idx(w 0, w 1, " in output
+        assert "call $__wasm_memory_copy(" in output
+
+    def test_defined_memory_after_imported_one(self):
+        module = parse_wat("""
+            (module
+              (import "env" "memory" (memory 1))
+              (memory 3))
+        """)
+        output = compile_module(module).emit()
+        # The defined memory is memory 1; the host sets up the imported one
+        assert "call $__wasm_memory_grow_idx(w 1, w 3)" in output
+        assert "call $__wasm_memory_grow(" not in output
+
+    def test_imported_memory64(self):
+        module = parse_wat("""
+            (module
+              (import "env" "memory" (memory i64 1))
+              (memory 1)
+              (func (param i64) (result i32) (i32.load (local.get 0))))
+        """)
+        output = compile_module(module, bounds_checks="inline").emit()
+        # The address of memory 0 is already i64, and may wrap around
+        assert "extuw" not in output
+        assert "cultl" in output
+
+    def test_imported_memory_counts_in_guard_mode(self):
+        module = parse_wat("""
+            (module
+              (import "env" "memory" (memory 1))
+              (memory 1)
+              (func (param i32) (result i32) (i32.load (local.get 0))))
+        """)
+        output = compile_module(module, bounds_checks="guard").emit()
+        # Only a single memory is covered by the guard region
+        assert "cugtl" in output
+
+    def test_runtime_memory_limit(self):
+        compile_module(parse_wat(f"(module {'(memory 0) ' * 16})"))
+        module = parse_wat(f"(module {'(memory 0) ' * 17})")
+        with pytest.raises(CompileError, match="memory 16: the runtime supports 16"):
+            compile_module(module)
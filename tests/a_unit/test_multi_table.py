@@ -0,0 +1,157 @@
+"""Unit tests for multiple tables and table64 (WASM 3.0)."""
+
+from __future__ import annotations
+
+import pytest
+
+from waq.compiler import compile_module
+from waq.errors import CompileError
+from waq.parser.binary import BinaryReader
+from waq.parser.module import parse_module
+from waq.parser.types import ValueType
+from waq.parser.wat import parse_wat
+
+TWO_TABLES = """
+    (module
+      (type $f (func (result i32)))
+      (table $funcs 2 4 funcref)
+      (table $more 3 funcref)
+      (table $refs 1 externref)
+      (func $one (type $f) (i32.const 1))
+      (func $two (type $f) (i32.const 2))
+      (elem (table $more) (i32.const 1) func $one $two)
+      (func (export "call") (param i32) (result i32)
+        (call_indirect $more (type $f) (local.get 0)))
+      (func (export "call0") (param i32) (result i32)
+        (call_indirect (type $f) (local.get 0)))
+      (func (export "ops") (param externref)
+        (table.set $refs (i32.const 0) (local.get 0))
+        (drop (table.grow $refs (ref.null extern) (i32.const 1)))
+        (table.copy $funcs $more (i32.const 0) (i32.const 1) (i32.const 2))))
+"""
+
+TABLE64 = """
+    (module
+      (type $f (func (result i32)))
+      (table $t i64 2 funcref)
+      (func $one (type $f) (i32.const 1))
+      (elem (table $t) (i64.const 0) func $one)
+      (func (export "call") (param i64) (result i32)
+        (call_indirect $t (type $f) (local.get 0)))
+      (func (export "size") (result i64) (table.size $t))
+      (func (export "grow") (param i64) (result i64)
+        (table.grow $t (ref.null func) (local.get 0))))
+"""
+
+
+class TestSeparateTables:
+    """Tests that each table is initialized and addressed by its index."""
+
+    def test_every_table_initialized(self):
+        output = compile_module(parse_wat(TWO_TABLES)).emit()
+        assert "call $__wasm_table_set_max(w 0, w 4)" in output
+        assert "call $__wasm_table_grow(w 0, l 0, w 2)" in output
+        assert "call $__wasm_table_grow(w 1, l 0, w 3)" in output
+        assert "call $__wasm_table_grow(w 2, l 0, w 1)" in output
+
+    def test_element_segments_routed_by_table(self):
+        output = compile_module(parse_wat(TWO_TABLES)).emit()
+        assert "call $__wasm_table_set(w 1, w 1, l $__wasm_func_0)" in output
+        assert "call $__wasm_table_set(w 1, w 2, l $__wasm_func_1)" in output
+
+    def test_call_indirect_on_other_table(self):
+        output = compile_module(parse_wat(TWO_TABLES)).emit()
+        call = output[output.index("export function w $wasm_call(") :]
+        call = call[: call.index("\n}")]
+        assert "call $__wasm_table_size_op(w 1)" in call
+        assert "call $__wasm_table_elems(w 1)" in call
+        assert "call $__wasm_table_sigs_idx(w 1)" in call
+        assert "$__wasm_table_size\n" not in call
+
+    def test_call_indirect_on_table_zero_uses_globals(self):
+        output = compile_module(parse_wat(TWO_TABLES)).emit()
+        call = output[output.index("export function w $wasm_call0(") :]
+        call = call[: call.index("\n}")]
+        assert "$__wasm_table_size" in call
+        assert "$__wasm_table_sigs" in call
+        assert "__wasm_table_elems" not in call
+
+    def test_table_operations_pass_table_indices(self):
+        output = compile_module(parse_wat(TWO_TABLES)).emit()
+        assert "call $__wasm_table_set(w 2, " in output
+        assert "call $__wasm_table_grow(w 2, l " in output
+        assert "call $__wasm_table_copy(w 0, w 1, " in output
+
+
+class TestTable64:
+    """Tests for tables indexed by i64."""
+
+    def test_wat_table64(self):
+        module = parse_wat(TABLE64)
+        assert module.tables[0].is_table64
+        assert module.elements[0].offset_expr == b"\x42\x00\x0b"
+
+    def test_wat_table64_abbreviation(self):
+        module = parse_wat("(module (table i64 funcref (elem $f)) (func $f))")
+        table = module.tables[0]
+        assert table.is_table64
+        assert table.limits.min == table.limits.max == 1
+
+    def test_binary_table64(self):
+        # funcref, flags 0x05 (table64 with max), min 1, max 2^32
+        data = bytes([0x70, 0x05, 0x01, 0x80, 0x80, 0x80, 0x80, 0x10])
+        table = BinaryReader(data).read_table_type()
+        assert table.is_table64
+        assert table.elem_type == ValueType.FUNCREF
+        assert table.limits.max == 1 << 32
+
+    def test_index_narrowed_to_word(self):
+        output = compile_module(parse_wat(TABLE64)).emit()
+        call = output[output.index("export function w $wasm_call(") :]
+        call = call[: call.index("\n}")]
+        # Indices past 32 bits saturate instead of wrapping into the table
+        assert "cugtl" in call
+        assert "4294967295" in call
+
+    def test_results_extended_to_i64(self):
+        output = compile_module(parse_wat(TABLE64)).emit()
+        size = output[output.index("export function l $wasm_size(") :]
+        size = size[: size.index("\n}")]
+        assert "extuw" in size
+        grow = output[output.index("export function l $wasm_grow(") :]
+        grow = grow[: grow.index("\n}")]
+        assert "extsw" in grow
+
+
+class TestTableInit:
+    """Tests for table init expressions and the runtime's table limits."""
+
+    def test_wat_init_expr(self):
+        module = parse_wat("(module (table 2 funcref (ref.func $f)) (func $f))")
+        assert module.table_inits == {0: b"\xd2\x00\x0b"}
+
+    def test_binary_init_expr(self):
+        # One table prefixed with 0x40 0x00: externref, min 1, ref.null extern
+        table_section = bytes([0x01, 0x40, 0x00, 0x6F, 0x00, 0x01, 0xD0, 0x6F, 0x0B])
+        wasm = b"\0asm\x01\0\0\0" + bytes([0x04, len(table_section)])
+        module = parse_module(wasm + table_section)
+        assert module.tables[0].elem_type == ValueType.EXTERNREF
+        assert module.table_inits == {0: b"\xd0\x6f\x0b"}
+
+    def test_entries_start_as_init_value(self):
+        output = compile_module(
+            parse_wat("(module (table 3 funcref (ref.func $f)) (func $f))")
+        ).emit()
+        assert "call $__wasm_table_grow(w 0, l $__wasm_func_0, w 3)" in output
+        # The function can be called through the table
+        assert "call $__wasm_func_sig_register(l $__wasm_func_0, w 0)" in output
+
+    def test_too_many_entries_rejected(self):
+        module = parse_wat("(module (table 65537 funcref))")
+        with pytest.raises(CompileError, match="65536 entries"):
+            compile_module(module)
+
+    def test_too_many_tables_rejected(self):
+        module = parse_wat(f"(module {'(table 1 funcref) ' * 17})")
+        with pytest.raises(CompileError, match="16 tables"):
+            compile_module(module)
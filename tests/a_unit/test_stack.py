@@ -72,6 +72,12 @@ class TestValueStack:
         assert values[1].name == "t2"
         assert stack.depth == 1
 
+    def test_pop_n_zero(self):
+        stack = ValueStack()
+        stack.new_temp(ValueType.I32)
+        assert stack.pop_n(0) == []
+        assert stack.depth == 1
+
     def test_pop_n_underflow(self):
         stack = ValueStack()
         stack.new_temp(ValueType.I32)
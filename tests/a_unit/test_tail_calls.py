@@ -259,6 +259,9 @@ class TestReturnCallRef:
         # Export section
         export_section = bytes([0x01, 0x06]) + b"caller" + bytes([0x00, 0x01])
 
+        # Element section: declare func 0 for ref.func
+        elem_section = bytes([0x01, 0x03, 0x00, 0x01, 0x00])
+
         # Code section
         # Target function: x + 1
         target_body = bytes([
@@ -287,6 +290,7 @@ class TestReturnCallRef:
         wasm += bytes([0x01, len(type_section)]) + type_section
         wasm += bytes([0x03, len(func_section)]) + func_section
         wasm += bytes([0x07, len(export_section)]) + export_section
+        wasm += bytes([0x09, len(elem_section)]) + elem_section
         wasm += bytes([0x0A, len(code_section)]) + code_section
         # fmt: on
 
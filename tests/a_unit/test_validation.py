@@ -0,0 +1,311 @@
+"""Unit tests for module validation in the compile pipeline."""
+
+from __future__ import annotations
+
+import pytest
+
+from waq.compiler import compile_module
+from waq.errors import ValidationError
+from waq.parser.wat import parse_wat
+from waq.validator import validate_module
+
+
+def validate_wat(text: str):
+    """Parse WAT and validate it."""
+    return validate_module(parse_wat(text))
+
+
+class TestCompilePipeline:
+    """Tests for validation in compile_module."""
+
+    def test_invalid_module_is_rejected(self):
+        module = parse_wat('(module (func (export "f") (result i32) (i64.const 1)))')
+        with pytest.raises(ValidationError) as exc:
+            compile_module(module)
+        assert exc.value.result is not None
+        assert len(exc.value.result.errors) == 1
+        assert "expected i32, got i64" in str(exc.value)
+
+    def test_validation_can_be_disabled(self):
+        module = parse_wat('(module (func (export "f") (result i32) (i64.const 1)))')
+        assert compile_module(module, validate=False) is not None
+
+    def test_errors_name_function_and_offset(self):
+        result = validate_wat("""
+            (module
+              (func $helper (export "helper") (param i32) (result i32)
+                (local.get 0))
+              (func $broken (export "broken")
+                (drop (i32.add (i32.const 1) (f32.const 2)))))
+        """)
+        (error,) = result.errors
+        assert error.location == "function 'broken', offset 0x7"
+        assert error.func_idx == 1
+        assert error.instr_offset == 7
+
+    def test_unnamed_function_uses_index(self):
+        result = validate_wat("(module (func (drop (i32.const 0)) (drop)))")
+        assert result.errors[0].location == "function 0, offset 0x3"
+
+
+class TestInstructions:
+    """Tests for instruction typing."""
+
+    def test_all_i32_comparisons(self):
+        result = validate_wat("""
+            (module (func (param i32 i32) (result i32)
+              (i32.ge_u (local.get 0) (local.get 1))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_code_after_branch_is_polymorphic(self):
+        result = validate_wat("""
+            (module (func (param i32) (result i32)
+              (block (result i32)
+                (br 0 (i32.const 1))
+                (i32.add))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_branch_needs_label_values(self):
+        result = validate_wat("""
+            (module (func (result i32)
+              (block (result i32) (br 0))))
+        """)
+        assert not result.is_valid
+
+    def test_blocks_cannot_pop_outer_values(self):
+        result = validate_wat("""
+            (module (func (result i32)
+              (i32.const 1)
+              (block (drop))))
+        """)
+        assert not result.is_valid
+
+    def test_block_parameters(self):
+        result = validate_wat("""
+            (module
+              (type $t (func (param i32) (result i32)))
+              (func (result i32)
+                (i32.const 1)
+                (block (type $t) (i32.const 2) (i32.add))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_memory64_addresses_are_i64(self):
+        result = validate_wat("""
+            (module (memory i64 1)
+              (func (param i64) (result i32) (i32.load (local.get 0))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_table64_indices_are_i64(self):
+        result = validate_wat("""
+            (module (table i64 1 funcref)
+              (func (param i64) (result i64)
+                (table.fill 0 (local.get 0) (ref.null func) (local.get 0))
+                (drop (table.get 0 (local.get 0)))
+                (table.grow 0 (ref.null func) (table.size 0))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_table64_rejects_i32_index(self):
+        result = validate_wat("""
+            (module (table i64 1 funcref)
+              (func (drop (table.get 0 (i32.const 0)))))
+        """)
+        assert not result.is_valid
+        assert "expected i64, got i32" in str(result)
+
+    def test_br_on_null_passes_label_values(self):
+        result = validate_wat("""
+            (module
+              (type $f (func))
+              (func (param (ref null $f)) (result i32 i64)
+                (block $null (result i32 i64)
+                  (i32.const 1)
+                  (i64.const 2)
+                  (br_on_null $null (local.get 0))
+                  (drop))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_br_on_null_needs_label_values(self):
+        result = validate_wat("""
+            (module
+              (type $f (func))
+              (func (param (ref null $f)) (result i32)
+                (block $null (result i32)
+                  (br_on_null $null (local.get 0))
+                  (drop)
+                  (i32.const 0))))
+        """)
+        assert not result.is_valid
+
+    def test_br_on_non_null_passes_reference(self):
+        result = validate_wat("""
+            (module
+              (type $f (func))
+              (func (param (ref null $f)) (result i32 (ref $f))
+                (block $some (result i32 (ref $f))
+                  (i32.const 1)
+                  (br_on_non_null $some (local.get 0))
+                  (unreachable))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_br_on_non_null_target_takes_reference(self):
+        result = validate_wat("""
+            (module
+              (type $f (func))
+              (func (param (ref null $f)) (result i32)
+                (block $some (result i32)
+                  (br_on_non_null $some (local.get 0))
+                  (i32.const 0))))
+        """)
+        assert "must take a reference" in str(result)
+
+    def test_gc_instructions_are_decoded(self):
+        result = validate_wat("""
+            (module
+              (type $point (struct (field $x (mut i32))))
+              (func (result i32)
+                (struct.get $point $x (struct.new $point (i32.const 3)))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_exception_payload_types(self):
+        result = validate_wat("""
+            (module
+              (tag $e (param i32 f64))
+              (func (result i32)
+                (try (result i32)
+                  (do (throw $e (i32.const 1) (f64.const 2)))
+                  (catch $e (drop)))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_throw_checks_payload(self):
+        result = validate_wat("""
+            (module
+              (tag $e (param i64))
+              (func (throw $e (i32.const 1))))
+        """)
+        assert "expected i64, got i32" in str(result.errors[0])
+
+    def test_try_table_clause_types(self):
+        result = validate_wat("""
+            (module
+              (tag $e (param i32))
+              (func (result i32)
+                (block $h (result i32)
+                  (try_table (catch $e $h) (throw $e (i32.const 1)))
+                  (i32.const 0))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_try_table_clause_label_mismatch(self):
+        result = validate_wat("""
+            (module
+              (tag $e (param i32))
+              (func
+                (block $h (result i64)
+                  (try_table (catch_ref $e $h))
+                  (i64.const 0)
+                  (drop))))
+        """)
+        assert "catch clause provides (i32, exnref)" in str(result.errors[0])
+
+    def test_unknown_opcode_warns(self):
+        module = parse_wat("(module (func))")
+        module.code[0].code = bytes([0xFD, 0x00, 0x0B])
+        result = validate_module(module)
+        assert result.is_valid
+        assert "unvalidated opcode 0xfd" in str(result.warnings[0])
+
+    def test_alignment_at_most_access_size(self):
+        result = validate_wat("""
+            (module (memory 1)
+              (func (param i32) (result i32) (i32.load align=8 (local.get 0))))
+        """)
+        assert "alignment 8 exceeds the access size 4" in str(result.errors[0])
+        result = validate_wat("""
+            (module (memory 1)
+              (func (param i32) (result i64) (i64.load align=8 (local.get 0))))
+        """)
+        assert result.is_valid, str(result)
+
+    def test_reference_hierarchies_are_distinct(self):
+        result = validate_wat("""
+            (module (table 1 externref)
+              (func (result funcref) (table.get 0 (i32.const 0))))
+        """)
+        assert "expected funcref, got externref" in str(result.errors[0])
+
+    def test_ref_func_needs_declaration(self):
+        result = validate_wat("(module (func $f) (func (drop (ref.func $f))))")
+        assert "ref.func of undeclared function 0" in str(result.errors[0])
+        # Exports, element segments and initializers all declare functions
+        for declaration in (
+            '(export "f" (func $f))',
+            "(elem declare func $f)",
+            "(global funcref (ref.func $f))",
+        ):
+            result = validate_wat(f"""
+                (module {declaration}
+                  (func $f) (func (drop (ref.func $f))))
+            """)
+            assert result.is_valid, str(result)
+
+
+class TestModule:
+    """Tests for module-level validation."""
+
+    def test_global_initializer_type(self):
+        result = validate_wat("(module (global i32 (i64.const 0)))")
+        assert "global 0: type mismatch: expected i32, got i64" in str(result)
+
+    def test_global_initializer_reads_immutable_earlier_globals(self):
+        result = validate_wat("""
+            (module
+              (global $a i32 (i32.const 1))
+              (global $b i32 (i32.add (global.get $a) (i32.const 2))))
+        """)
+        assert result.is_valid, str(result)
+        result = validate_wat("""
+            (module
+              (global $a (mut i32) (i32.const 1))
+              (global i32 (global.get $a))
+              (global i32 (global.get 3))
+              (global i32 (i32.const 0)))
+        """)
+        assert "global.get of mutable global 0" in str(result.errors[0])
+        assert "global index 3 out of bounds" in str(result.errors[1])
+
+    def test_data_memory_index(self):
+        result = validate_wat('(module (memory 1) (data (memory 3) (i32.const 0) "a"))')
+        assert "data segment 0: memory index 3 out of bounds" in str(result)
+
+    def test_data_offset_type(self):
+        result = validate_wat('(module (memory i64 1) (data (i32.const 0) "a"))')
+        assert "data segment 0: type mismatch: expected i64, got i32" in str(result)
+
+    def test_element_table_index(self):
+        result = validate_wat("(module (elem (i32.const 0) $f) (func $f))")
+        assert "element segment 0: table index 0 out of bounds" in str(result)
+
+    def test_element_table_type(self):
+        result = validate_wat("""
+            (module (table 1 externref) (elem (i32.const 0) $f) (func $f))
+        """)
+        assert "functions cannot be stored in a table of externref" in str(result)
+
+    def test_element_offset_type(self):
+        result = validate_wat("""
+            (module (table 1 funcref) (elem (i64.const 0) $f) (func $f))
+        """)
+        assert "expected i32, got i64" in str(result)
+
+    def test_table_initializer_type(self):
+        result = validate_wat("(module (table 1 funcref (ref.null extern)))")
+        assert "table 0: type mismatch: expected funcref, got externref" in str(result)
@@ -2,8 +2,11 @@
 
 from __future__ import annotations
 
+import re
+
 from waq.compiler import compile_module
 from waq.parser.module import parse_module
+from waq.parser.types import ValueType
 
 
 def make_select_with_type_wasm() -> bytes:
@@ -200,6 +203,104 @@ def make_br_on_non_null_wasm() -> bytes:
     return wasm
 
 
+def make_br_on_null_multi_value_wasm() -> bytes:
+    """Create WASM with br_on_null to a label taking two values.
+
+    (ref: (ref null 0)) -> (i32, i64)
+    """
+    # Type section: () -> (i32, i64); ((ref null 0)) -> (i32, i64)
+    type_section = bytes([
+        0x02,
+        0x60,
+        0x00,
+        0x02,
+        0x7F,
+        0x7E,
+        0x60,
+        0x01,
+        0x63,
+        0x00,
+        0x02,
+        0x7F,
+        0x7E,
+    ])
+
+    # Code section: block with br_on_null
+    func_body = bytes([
+        0x00,  # 0 locals
+        0x02,
+        0x00,  # block (type 0)
+        0x41,
+        0x01,  # i32.const 1
+        0x42,
+        0x02,  # i64.const 2
+        0x20,
+        0x00,  # local.get 0 (ref)
+        0xD5,
+        0x00,  # br_on_null 0 (to block end with both values)
+        0x1A,  # drop (the non-null ref)
+        0x0B,  # end block
+        0x0B,  # end function
+    ])
+    code_section = bytes([0x01, len(func_body)]) + func_body
+
+    wasm = bytes([0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00])
+    wasm += bytes([0x01, len(type_section)]) + type_section
+    wasm += bytes([0x03, 0x02, 0x01, 0x01])
+    wasm += bytes([0x0A, len(code_section)]) + code_section
+
+    return wasm
+
+
+def make_br_on_non_null_label_wasm() -> bytes:
+    """Create WASM with br_on_non_null to a label taking the reference.
+
+    (ref: (ref null 0)) -> (i32, (ref 0))
+    """
+    # Type section: () -> (i32, (ref 0)); ((ref null 0)) -> (i32, (ref 0))
+    type_section = bytes([
+        0x02,
+        0x60,
+        0x00,
+        0x02,
+        0x7F,
+        0x64,
+        0x00,
+        0x60,
+        0x01,
+        0x63,
+        0x00,
+        0x02,
+        0x7F,
+        0x64,
+        0x00,
+    ])
+
+    # Code section: block with br_on_non_null
+    func_body = bytes([
+        0x00,  # 0 locals
+        0x02,
+        0x00,  # block (type 0)
+        0x41,
+        0x01,  # i32.const 1
+        0x20,
+        0x00,  # local.get 0 (ref)
+        0xD6,
+        0x00,  # br_on_non_null 0 (to block end with 1 and the ref)
+        0x00,  # unreachable (was null)
+        0x0B,  # end block
+        0x0B,  # end function
+    ])
+    code_section = bytes([0x01, len(func_body)]) + func_body
+
+    wasm = bytes([0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00])
+    wasm += bytes([0x01, len(type_section)]) + type_section
+    wasm += bytes([0x03, 0x02, 0x01, 0x01])
+    wasm += bytes([0x0A, len(code_section)]) + code_section
+
+    return wasm
+
+
 class TestSelectWithType:
     """Tests for select with type instruction."""
 
@@ -249,6 +350,15 @@ class TestBrOnNull:
         output = qbe.emit()
         assert "br_on_null" in output
 
+    def test_br_on_null_passes_label_values(self):
+        """Test that the values below the reference reach the label."""
+        module = parse_module(make_br_on_null_multi_value_wasm())
+        assert module.types[1].params == (ValueType.FUNCREF,)
+        output = compile_module(module).emit()
+        branch = output.split("\n@br_on_null_branch")[1].split("\n@")[0]
+        assert re.search(r"=w copy %t\d+\n\t%t\d+ =l copy %t\d+", branch)
+        assert "jmp @block_end" in branch
+
 
 class TestBrOnNonNull:
     """Tests for br_on_non_null instruction."""
@@ -257,6 +367,17 @@ class TestBrOnNonNull:
         """Test that br_on_non_null compiles."""
         wasm = make_br_on_non_null_wasm()
         module = parse_module(wasm)
-        qbe = compile_module(module)
+        # The branch target takes no reference, so the module doesn't validate
+        qbe = compile_module(module, validate=False)
         output = qbe.emit()
         assert "br_on_non_null" in output
+
+    def test_br_on_non_null_passes_reference(self):
+        """Test that the reference is the label's last value."""
+        module = parse_module(make_br_on_non_null_label_wasm())
+        assert module.types[1].results == (ValueType.I32, ValueType.FUNCREF)
+        output = compile_module(module).emit()
+        ref = re.search(r"%(t\d+) =l loadl", output).group(1)
+        branch = output.split("\n@br_on_non_null_branch")[1].split("\n@")[0]
+        assert f"=l copy %{ref}" in branch
+        assert "jmp @block_end" in branch
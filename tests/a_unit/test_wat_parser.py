@@ -0,0 +1,332 @@
+"""Unit tests for the WAT text format parser."""
+
+from __future__ import annotations
+
+from pathlib import Path
+
+import pytest
+
+from waq.compiler import compile_module
+from waq.errors import ParseError
+from waq.parser.module import ExportKind, ImportKind
+from waq.parser.types import FuncType, StructType, ValueType
+from waq.parser.wat import parse_wat, tokenize
+
+FIXTURES_DIR = Path(__file__).parent.parent / "fixtures"
+
+
+class TestLexer:
+    """Tests for tokenization."""
+
+    def test_comments_are_skipped(self):
+        nodes = tokenize(";; line\n(module (; block (; nested ;) ;) $m)")
+        assert len(nodes) == 1
+        assert nodes[0].head == "module"
+        assert len(nodes[0].items) == 2
+
+    def test_string_escapes(self):
+        (node,) = tokenize(r'(data "a\n\t\\\"\41\u{e9}")')
+        assert node.items[1].value == b'a\n\t\\"A\xc3\xa9'
+
+    def test_token_positions(self):
+        (node,) = tokenize("(module\n  (func))")
+        func = node.items[1]
+        assert (func.line, func.column) == (2, 3)
+
+    def test_annotations_are_dropped(self):
+        (node,) = tokenize('(module (@custom "x") (func))')
+        assert [item.head for item in node.items[1:]] == ["func"]
+
+
+class TestModuleFields:
+    """Tests for module-level fields."""
+
+    def test_empty_module(self):
+        module = parse_wat("(module)")
+        assert module.types == []
+        assert module.code == []
+
+    def test_bare_fields(self):
+        module = parse_wat("(func (result i32) i32.const 1)")
+        assert len(module.code) == 1
+
+    def test_implicit_type_is_shared(self):
+        module = parse_wat("""
+            (module
+              (func (param i32) (result i32) local.get 0)
+              (func (param $x i32) (result i32) local.get $x))
+        """)
+        assert module.types == [FuncType((ValueType.I32,), (ValueType.I32,))]
+        assert module.func_types == [0, 0]
+
+    def test_explicit_type_use(self):
+        module = parse_wat("""
+            (module
+              (type $t (func (param i64)))
+              (func (type $t)))
+        """)
+        assert module.func_types == [0]
+
+    def test_inline_exports(self):
+        module = parse_wat('(module (func $f (export "a") (export "b")))')
+        assert [(e.name, e.kind, e.index) for e in module.exports] == [
+            ("a", ExportKind.FUNC, 0),
+            ("b", ExportKind.FUNC, 0),
+        ]
+
+    def test_imports_come_first_in_index_space(self):
+        module = parse_wat("""
+            (module
+              (import "env" "f" (func $f (param i32)))
+              (func $g (import "env" "g"))
+              (func $main (call $f (i32.const 1)) (call $g)))
+        """)
+        assert [imp.name for imp in module.imports] == ["f", "g"]
+        assert all(imp.kind == ImportKind.FUNC for imp in module.imports)
+        # call $f -> index 0, call $g -> index 1
+        assert module.code[0].code == bytes([0x41, 0x01, 0x10, 0x00, 0x10, 0x01, 0x0B])
+
+    def test_memory_with_inline_data(self):
+        module = parse_wat('(module (memory (data "hello" "!")))')
+        assert module.memories[0].limits.min == 1
+        assert module.data[0].memory_idx == 0
+        assert module.data[0].data == b"hello!"
+
+    def test_active_and_passive_data(self):
+        module = parse_wat("""
+            (module
+              (memory 1)
+              (data (i32.const 16) "ab")
+              (data $p "cd"))
+        """)
+        assert module.data[0].offset_expr == bytes([0x41, 0x10, 0x0B])
+        assert module.data[1].memory_idx == -1
+
+    def test_table_with_inline_elem(self):
+        module = parse_wat("""
+            (module
+              (table funcref (elem $a $b))
+              (func $a) (func $b))
+        """)
+        assert module.tables[0].limits.min == 2
+        assert module.elements[0].func_indices == [0, 1]
+
+    def test_tags(self):
+        module = parse_wat("""
+            (module
+              (import "env" "ex" (tag $imported (param i64)))
+              (tag (import "env" "empty"))
+              (tag $e (export "e") (param i32 f64)))
+        """)
+        assert [imp.kind for imp in module.imports] == [ImportKind.TAG] * 2
+        assert module.num_imported_tags() == 2
+        assert module.get_tag_type(0) == FuncType((ValueType.I64,), ())
+        assert module.get_tag_type(1) == FuncType((), ())
+        assert module.get_tag_type(2) == FuncType((ValueType.I32, ValueType.F64), ())
+        assert [(e.name, e.kind, e.index) for e in module.exports] == [
+            ("e", ExportKind.TAG, 2)
+        ]
+
+    def test_global_and_start(self):
+        module = parse_wat("""
+            (module
+              (global $g (mut i32) (i32.const 7))
+              (func $init (global.set $g (i32.const 1)))
+              (start $init))
+        """)
+        assert module.globals[0].type.mutable
+        assert module.globals[0].init_expr == bytes([0x41, 0x07, 0x0B])
+        assert module.start == 0
+
+
+class TestInstructions:
+    """Tests for instruction encoding."""
+
+    def test_folded_matches_flat(self):
+        folded = parse_wat(
+            "(func (result i32) (i32.add (i32.const 1) (i32.const 2)))"
+        )
+        flat = parse_wat("(func (result i32) i32.const 1 i32.const 2 i32.add)")
+        assert folded.code[0].code == flat.code[0].code
+
+    def test_named_locals(self):
+        module = parse_wat("""
+            (func (param $a i32) (local $b i32) (local f64 f64)
+              (local.set $b (local.get $a)))
+        """)
+        body = module.code[0]
+        assert body.locals == [(1, ValueType.I32), (2, ValueType.F64)]
+        assert body.code == bytes([0x20, 0x00, 0x21, 0x01, 0x0B])
+
+    def test_labels_resolve_to_depths(self):
+        module = parse_wat("""
+            (func
+              (block $out
+                (loop $top
+                  (br_if $out (i32.const 1))
+                  (br $top))))
+        """)
+        code = module.code[0].code
+        assert bytes([0x0D, 0x01]) in code
+        assert bytes([0x0C, 0x00]) in code
+
+    def test_folded_if(self):
+        module = parse_wat("""
+            (func (result i32)
+              (if (result i32) (i32.const 1)
+                (then (i32.const 2))
+                (else (i32.const 3))))
+        """)
+        assert module.code[0].code == bytes([
+            0x41, 0x01, 0x04, 0x7F, 0x41, 0x02, 0x05, 0x41, 0x03, 0x0B, 0x0B,
+        ])
+
+    def test_memarg(self):
+        module = parse_wat("""
+            (module (memory 1)
+              (func (result i32) (i32.load offset=8 align=2 (i32.const 0))))
+        """)
+        assert module.code[0].code == bytes([0x41, 0x00, 0x28, 0x01, 0x08, 0x0B])
+
+    def test_numeric_literals(self):
+        module = parse_wat("""
+            (func
+              (drop (i32.const 0xFFFF_FFFF))
+              (drop (i64.const -1))
+              (drop (f32.const -0x1p-1))
+              (drop (f64.const inf)))
+        """)
+        code = module.code[0].code
+        assert code.startswith(bytes([0x41, 0x7F, 0x1A, 0x42, 0x7F, 0x1A]))
+        assert bytes([0x43, 0x00, 0x00, 0x00, 0xBF]) in code
+        assert bytes([0x44, 0, 0, 0, 0, 0, 0, 0xF0, 0x7F]) in code
+
+    def test_call_indirect_with_inline_type(self):
+        module = parse_wat("""
+            (module (table 1 funcref)
+              (func (result i32)
+                (call_indirect (param i32) (result i32) (i32.const 5) (i32.const 0))))
+        """)
+        assert FuncType((ValueType.I32,), (ValueType.I32,)) in module.types
+        assert bytes([0x11, 0x01, 0x00]) in module.code[0].code
+
+    def test_multi_value_block_type(self):
+        module = parse_wat("""
+            (func (result i32 i32)
+              (block (result i32 i32) (i32.const 1) (i32.const 2)))
+        """)
+        assert module.code[0].code[:2] == bytes([0x02, 0x00])
+
+    def test_gc_struct_fields_by_name(self):
+        module = parse_wat("""
+            (module
+              (type $p (struct (field $x i32) (field $y (mut i64))))
+              (func (param (ref $p)) (result i64)
+                (struct.get $p $y (local.get 0))))
+        """)
+        assert isinstance(module.types[0], StructType)
+        assert module.types[1] == FuncType((ValueType.STRUCTREF,), (ValueType.I64,))
+        assert bytes([0xFB, 0x02, 0x00, 0x01]) in module.code[0].code
+
+    def test_legacy_try_catch(self):
+        module = parse_wat("""
+            (module
+              (tag $e (param i32))
+              (func
+                (try (do (throw $e (i32.const 1)))
+                     (catch $e drop)
+                     (catch_all))))
+        """)
+        assert module.code[0].code == bytes([
+            0x06, 0x40, 0x41, 0x01, 0x08, 0x00, 0x07, 0x00, 0x1A, 0x19, 0x0B, 0x0B,
+        ])
+
+
+    def test_try_table(self):
+        module = parse_wat("""
+            (module
+              (tag $e (param i32))
+              (func (result exnref)
+                (block $h (result exnref)
+                  (try_table (catch $e 0) (catch_all_ref $h)
+                    (throw $e (i32.const 1)))
+                  (ref.null exn))))
+        """)
+        assert module.types[1] == FuncType((), (ValueType.EXNREF,))
+        # Clause labels are relative to the blocks around the try_table
+        assert module.code[0].code == bytes([
+            0x02, 0x69, 0x1F, 0x40, 0x02, 0x00, 0x00, 0x00, 0x03, 0x00,
+            0x41, 0x01, 0x08, 0x00, 0x0B, 0xD0, 0x69, 0x0B, 0x0B,
+        ])
+
+    def test_flat_try_table_and_throw_ref(self):
+        module = parse_wat("""
+            (module
+              (func (param exnref)
+                try_table (catch_all 0)
+                  local.get 0
+                  throw_ref
+                end))
+        """)
+        assert module.code[0].code == bytes([
+            0x1F, 0x40, 0x01, 0x02, 0x00, 0x20, 0x00, 0x0A, 0x0B, 0x0B,
+        ])
+
+    def test_legacy_ref_null_spellings(self):
+        spellings = {
+            "funcref": 0x70,
+            "externref": 0x6F,
+            "nullref": 0x71,
+            "nullfuncref": 0x73,
+            "nullexternref": 0x72,
+            "nullexnref": 0x74,
+        }
+        for spelling, heap_type in spellings.items():
+            module = parse_wat(f"(func (drop (ref.null {spelling})))")
+            assert module.code[0].code == bytes([0xD0, heap_type, 0x1A, 0x0B])
+
+
+class TestErrors:
+    """Tests for error reporting with line/column information."""
+
+    def test_unknown_instruction(self):
+        with pytest.raises(ParseError, match="line 2, column 4: unknown instruction"):
+            parse_wat("(func\n  (i32.bogus))")
+
+    def test_unknown_identifier(self):
+        with pytest.raises(ParseError, match="unknown function \\$nope"):
+            parse_wat("(func (call $nope))")
+
+    def test_unclosed_paren(self):
+        with pytest.raises(ParseError, match="line 1, column 1: unclosed"):
+            parse_wat("(module (func)")
+
+    def test_constant_out_of_range(self):
+        with pytest.raises(ParseError, match="out of range"):
+            parse_wat("(func (drop (i32.const 0x1_0000_0000)))")
+
+    def test_unknown_heap_type(self):
+        with pytest.raises(ParseError, match="got 'bogusref'"):
+            parse_wat("(func (drop (ref.null bogusref)))")
+
+    def test_import_after_definition(self):
+        with pytest.raises(ParseError, match="imports must occur before"):
+            parse_wat('(module (func) (import "a" "b" (func)))')
+
+    def test_error_attributes(self):
+        with pytest.raises(ParseError) as exc_info:
+            parse_wat("(module\n\n   (func (local.get $x)))")
+        assert exc_info.value.line == 3
+        assert exc_info.value.column == 21
+
+
+class TestFixtures:
+    """All fixtures parse and compile without external tools."""
+
+    @pytest.mark.parametrize(
+        "name", sorted(p.name for p in FIXTURES_DIR.glob("*.wat"))
+    )
+    def test_fixture_compiles(self, name):
+        module = parse_wat((FIXTURES_DIR / name).read_text())
+        output = compile_module(module).emit()
+        assert "function" in output
@@ -629,8 +629,8 @@ class TestModuleContext:
 
         ctx = ModuleContext(module=module)
         name = ctx.get_func_name(0)
-        # Imported functions use their import name
-        assert name == "add_numbers"
+        # Imported functions use their import module and name
+        assert name == "Z_envZ_add_numbers"
 
     def test_get_func_name_after_import(self):
         """Test function index offset for functions after imports."""
@@ -642,7 +642,7 @@ class TestModuleContext:
 
         ctx = ModuleContext(module=module)
         # First function is imported
-        assert ctx.get_func_name(0) == "add_numbers"
+        assert ctx.get_func_name(0) == "Z_envZ_add_numbers"
         # Second function is exported (gets wasm_ prefix)
         assert ctx.get_func_name(1) == "wasm_my_func"
 
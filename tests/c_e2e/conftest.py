@@ -0,0 +1,64 @@
+"""Shared fixtures for the end-to-end tests of the C runtime.
+
+Runtime tests link a small C driver program against the runtime source,
+calling it the way compiled code does. Modules compiled with waq are run by
+test_execution.py from the WAT files in tests/fixtures.
+"""
+
+from __future__ import annotations
+
+import shutil
+import subprocess
+from pathlib import Path
+
+import pytest
+
+from waq.runtime import RUNTIME_C_SOURCE
+
+CC = shutil.which("clang") or shutil.which("gcc")
+
+
+@pytest.fixture(scope="module")
+def build_c(tmp_path_factory):
+    """Build C source with the runtime into an executable.
+
+    Returns a function taking the source and extra compiler arguments (such
+    as defines or object files to link), which skips the test without a C
+    compiler.
+    """
+
+    def build(source: str, *args: str, name: str = "driver") -> Path:
+        if CC is None:
+            pytest.skip("No C compiler available")
+        tmpdir = tmp_path_factory.mktemp(name)
+        source_file = tmpdir / f"{name}.c"
+        source_file.write_text(source)
+        exe = tmpdir / name
+        subprocess.run(
+            [CC, *args, "-o", str(exe), str(source_file), str(RUNTIME_C_SOURCE)]
+            + ["-lm"],
+            check=True,
+            capture_output=True,
+        )
+        return exe
+
+    return build
+
+
+@pytest.fixture(scope="module")
+def driver(request, build_c):
+    """Build the test module's DRIVER program, with its DRIVER_CFLAGS."""
+    cflags = getattr(request.module, "DRIVER_CFLAGS", ())
+    return build_c(request.module.DRIVER, *cflags)
+
+
+@pytest.fixture
+def run(driver):
+    """Run the driver with the given arguments, capturing its output."""
+
+    def run_(*args: str) -> subprocess.CompletedProcess:
+        return subprocess.run(
+            [str(driver), *args], capture_output=True, text=True, timeout=10
+        )
+
+    return run_
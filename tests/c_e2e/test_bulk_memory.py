@@ -0,0 +1,56 @@
+"""End-to-end tests for the runtime's bulk memory operations.
+
+A driver registers a data segment and copies part of it into memory with
+__wasm_memory_init_seg, as compiled memory.init instructions do. The source
+range is only checked by the runtime.
+"""
+
+from __future__ import annotations
+
+import pytest
+
+DRIVER = """
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+extern int32_t __wasm_memory_grow(int32_t);
+extern uint8_t *__wasm_memory_base_idx(int32_t);
+extern void __wasm_register_data_segment(int32_t, uint8_t *, size_t);
+extern void __wasm_memory_init_seg(int32_t, uint64_t, uint32_t, uint32_t);
+
+static uint8_t segment[] = "abcd";
+
+/* usage: driver <src_offset> <len>, copying to address 8 */
+int main(int argc, char **argv) {
+    if (argc < 3) return 2;
+    __wasm_memory_grow(1);
+    __wasm_register_data_segment(0, segment, 4);
+    uint32_t len = (uint32_t)strtol(argv[2], NULL, 0);
+    __wasm_memory_init_seg(0, 8, (uint32_t)strtol(argv[1], NULL, 0), len);
+    printf("%.*s\\n", (int)len, (const char *)__wasm_memory_base_idx(0) + 8);
+    return 0;
+}
+"""
+
+
+class TestMemoryInit:
+    """The source range must lie entirely in the segment."""
+
+    @pytest.mark.parametrize(
+        ("offset", "length", "copied"),
+        [("0", "4", "abcd"), ("1", "2", "bc"), ("4", "0", "")],
+    )
+    def test_in_bounds(self, run, offset, length, copied):
+        result = run(offset, length)
+        assert result.returncode == 0
+        assert result.stdout == copied + "\n"
+
+    @pytest.mark.parametrize(
+        ("offset", "length"),
+        [("-1", "1"), ("-4", "4"), ("0x80000000", "1"), ("2", "3"), ("5", "0")],
+    )
+    def test_out_of_bounds(self, run, offset, length):
+        result = run(offset, length)
+        assert result.returncode != 0
+        assert "out of bounds memory access" in result.stderr
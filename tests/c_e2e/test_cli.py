@@ -124,6 +124,76 @@ class TestCLICompilation:
         # Cleanup
         output_file.unlink()
 
+    def test_compile_wat_input(self, tmp_path):
+        """Test compiling a WAT file without external tools."""
+        wat_file = tmp_path / "answer.wat"
+        wat_file.write_text(
+            '(module (func (export "answer") (result i32) (i32.const 42)))'
+        )
+        output_file = tmp_path / "output.ssa"
+        result = main([str(wat_file), "-o", str(output_file)])
+        assert result == 0
+        assert "$wasm_answer" in output_file.read_text()
+
+    def test_inline_bounds_checks(self, tmp_path):
+        """Test that --bounds-checks=inline guards memory accesses."""
+        wat_file = tmp_path / "load.wat"
+        wat_file.write_text(
+            "(module (memory 1)"
+            ' (func (export "load") (param i32) (result i32)'
+            " (i32.load (local.get 0))))"
+        )
+        output_file = tmp_path / "output.ssa"
+        result = main(
+            [str(wat_file), "-o", str(output_file), "--bounds-checks=inline"]
+        )
+        assert result == 0
+        assert "__wasm_trap_out_of_bounds" in output_file.read_text()
+
+    def test_deterministic_floats(self, tmp_path):
+        """Test that --deterministic canonicalizes float NaNs."""
+        wat_file = tmp_path / "div.wat"
+        wat_file.write_text(
+            '(module (func (export "div") (param f64 f64) (result f64)'
+            " (f64.div (local.get 0) (local.get 1))))"
+        )
+        output_file = tmp_path / "output.ssa"
+        result = main([str(wat_file), "-o", str(output_file), "--deterministic"])
+        assert result == 0
+        assert "__wasm_f64_div_deterministic" in output_file.read_text()
+
+    def test_instance_mode(self, tmp_path):
+        """Test that --instance-mode passes the instance to every function."""
+        wat_file = tmp_path / "counter.wat"
+        wat_file.write_text(
+            "(module (global $n (mut i32) (i32.const 0))"
+            ' (func (export "next") (result i32)'
+            " (global.set $n (i32.add (global.get $n) (i32.const 1)))"
+            " (global.get $n)))"
+        )
+        output_file = tmp_path / "output.ssa"
+        result = main([str(wat_file), "-o", str(output_file), "--instance-mode"])
+        assert result == 0
+        output = output_file.read_text()
+        assert "export function w $wasm_next(l %vmctx)" in output
+        assert "__wasm_instance_enter" in output
+
+    def test_link_modules(self, tmp_path):
+        """Test that several inputs are linked into one program."""
+        (tmp_path / "app.wat").write_text(
+            '(module (import "mathlib" "square" (func $sq (param i32) (result i32)))'
+            ' (func (export "main") (result i32) (call $sq (i32.const 7))))'
+        )
+        (tmp_path / "lib.wat").write_text(
+            '(module (func (export "square") (param i32) (result i32)'
+            " (i32.mul (local.get 0) (local.get 0))))"
+        )
+        result = main([str(tmp_path / "app.wat"), f"mathlib={tmp_path / 'lib.wat'}"])
+        assert result == 0
+        output = (tmp_path / "app.ssa").read_text()
+        assert "export function w $mathlib__wasm_square(" in output
+        assert "call $mathlib__wasm_square(" in output
+
     def test_verbose_output(self, wasm_with_function, tmp_path, capsys):
         """Test verbose mode."""
         output_file = tmp_path / "output.ssa"
@@ -202,6 +272,49 @@ class TestCLIEmitFormats:
             # Check it's a valid object file (has some content)
             assert output_file.stat().st_size > 0
 
+    def test_emit_header(self, tmp_path):
+        """Test header output next to the input."""
+        wat_file = tmp_path / "adder.wat"
+        wat_file.write_text(
+            '(module (func (export "add") (param i32 i32) (result i32)'
+            " (i32.add (local.get 0) (local.get 1))))"
+        )
+        result = main([str(wat_file), "--emit", "header"])
+        assert result == 0
+        header = (tmp_path / "adder.h").read_text()
+        assert "#ifndef WAQ_ADDER_H" in header
+        assert "extern int32_t wasm_add(int32_t, int32_t);" in header
+
+    def test_emit_import_names(self, tmp_path):
+        """Test qualified import symbols, and bare ones for older host code."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text(
+            '(module (import "env" "exit" (func $exit (param i32)))'
+            ' (func (export "run") (call $exit (i32.const 1))))'
+        )
+        output_file = tmp_path / "module.ssa"
+        assert main([str(wat_file), "-o", str(output_file)]) == 0
+        assert "call $Z_envZ_exit(w " in output_file.read_text()
+        args = [str(wat_file), "-o", str(output_file), "--import-names", "bare"]
+        assert main(args) == 0
+        assert "call $exit(w " in output_file.read_text()
+
+    def test_emit_with_imports(self, tmp_path):
+        """Test imports bound to host functions by a manifest."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text(
+            '(module (import "env" "log" (func $log (param i32)))'
+            ' (func (export "run") (call $log (i32.const 1))))'
+        )
+        manifest = tmp_path / "imports.json"
+        manifest.write_text('{"env": {"log": "host_log"}}')
+        args = [str(wat_file), "--imports", str(manifest)]
+        assert main([*args, "-o", str(tmp_path / "module.ssa")]) == 0
+        assert "call $host_log(w " in (tmp_path / "module.ssa").read_text()
+        assert main([*args, "--emit", "header"]) == 0
+        header = (tmp_path / "module.h").read_text()
+        assert "/* env.log */\nextern void host_log(int32_t);" in header
+
     def test_emit_exe(self, tmp_path):
         """Test exe output format with fibonacci example."""
         import subprocess
@@ -214,7 +327,7 @@ class TestCLIEmitFormats:
 
         output_file = tmp_path / "fibonacci"
         result = main([str(wat_file), "-o", str(output_file), "--emit", "exe"])
-        # May fail if QBE/wat2wasm not installed
+        # May fail if QBE not installed
         if result == 0:
             assert output_file.exists()
             # Run the executable and check output
@@ -224,6 +337,264 @@ class TestCLIEmitFormats:
             assert proc.returncode == 0
             assert proc.stdout.strip() == "55"  # fib(10) = 55
 
+    def test_emit_exe_with_arguments(self, tmp_path):
+        """Test that the entry's parameters and results follow its type."""
+        import subprocess
+
+        wat_file = tmp_path / "scale.wat"
+        wat_file.write_text("""
+            (module
+              (func (export "scale") (param i64 f64) (result i64 f64 f32)
+                (i64.mul (local.get 0) (i64.const 3))
+                (f64.mul (local.get 1) (f64.const 2))
+                (f32.const 0.5)))
+        """)
+        output_file = tmp_path / "scale"
+        result = main(
+            [str(wat_file), "-o", str(output_file), "--emit", "exe", "--entry", "scale"]
+        )
+        # May fail if QBE not installed
+        if result == 0:
+            proc = subprocess.run(
+                [str(output_file), "5000000000", "1.25"],
+                capture_output=True,
+                text=True,
+                timeout=5,
+            )
+            assert proc.returncode == 0
+            assert proc.stdout.split() == ["15000000000", "2.5", "0.5"]
+
+            proc = subprocess.run(
+                [str(output_file), "1"], capture_output=True, text=True, timeout=5
+            )
+            assert proc.returncode == 2
+            assert "usage:" in proc.stderr
+
+    def test_emit_exe_wasi_command(self, tmp_path):
+        """Test that WASI commands see argv and exit through proc_exit."""
+        import subprocess
+
+        wat_file = tmp_path / "argc.wat"
+        wat_file.write_text("""
+            (module
+              (import "wasi_snapshot_preview1" "args_sizes_get"
+                (func $args_sizes_get (param i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "proc_exit"
+                (func $proc_exit (param i32)))
+              (memory (export "memory") 1)
+              (func (export "_start")
+                (drop (call $args_sizes_get (i32.const 0) (i32.const 4)))
+                (call $proc_exit (i32.load (i32.const 0)))))
+        """)
+        output_file = tmp_path / "argc"
+        result = main([str(wat_file), "-o", str(output_file), "--emit", "exe"])
+        # May fail if QBE not installed
+        if result == 0:
+            proc = subprocess.run(
+                [str(output_file), "a", "b", "c"],
+                capture_output=True,
+                text=True,
+                timeout=5,
+            )
+            assert proc.returncode == 4
+
+    def test_emit_exe_wasi_sandbox(self, tmp_path):
+        """Test that WASI executables only see the configured environment."""
+        import subprocess
+
+        wat_file = tmp_path / "envc.wat"
+        wat_file.write_text("""
+            (module
+              (import "wasi_snapshot_preview1" "environ_sizes_get"
+                (func $environ_sizes_get (param i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_prestat_get"
+                (func $fd_prestat_get (param i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "proc_exit"
+                (func $proc_exit (param i32)))
+              (memory (export "memory") 1)
+              (func (export "_start")
+                (drop (call $environ_sizes_get (i32.const 0) (i32.const 4)))
+                ;; Environment count, plus 10 if fd 3 is preopened
+                (call $proc_exit
+                  (i32.add
+                    (i32.load (i32.const 0))
+                    (select (i32.const 0) (i32.const 10)
+                      (call $fd_prestat_get (i32.const 3) (i32.const 8)))))))
+        """)
+        output_file = tmp_path / "envc"
+        result = main([str(wat_file), "-o", str(output_file), "--emit", "exe"])
+        # May fail if QBE not installed
+        if result == 0:
+            proc = subprocess.run([str(output_file)], timeout=5, check=False)
+            assert proc.returncode == 0
+
+            result = main(
+                [
+                    str(wat_file),
+                    "-o",
+                    str(output_file),
+                    "--emit",
+                    "exe",
+                    "--env",
+                    "A=1",
+                    "--env",
+                    "B=2",
+                    "--dir-ro",
+                    f"{tmp_path}:/sandbox",
+                ]
+            )
+            assert result == 0
+            proc = subprocess.run([str(output_file)], timeout=5, check=False)
+            assert proc.returncode == 12
+
+    def test_emit_exe_wasi_listen(self, tmp_path):
+        """Test that --listen gives a WASI executable a listening socket."""
+        import socket
+        import subprocess
+        import time
+
+        wat_file = tmp_path / "hello_server.wat"
+        wat_file.write_text("""
+            (module
+              (import "wasi_snapshot_preview1" "sock_accept"
+                (func $sock_accept (param i32 i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "sock_send"
+                (func $sock_send (param i32 i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 16) "hi")
+              (data (i32.const 8) "\\10\\00\\00\\00\\02\\00\\00\\00")
+              (func (export "_start")
+                (drop (call $sock_accept (i32.const 3) (i32.const 0) (i32.const 0)))
+                (drop (call $sock_send
+                  (i32.load (i32.const 0)) (i32.const 8) (i32.const 1)
+                  (i32.const 0) (i32.const 4)))))
+        """)
+        with socket.socket() as sock:
+            sock.bind(("127.0.0.1", 0))
+            port = sock.getsockname()[1]
+        output_file = tmp_path / "hello_server"
+        result = main(
+            [
+                str(wat_file),
+                "-o",
+                str(output_file),
+                "--emit",
+                "exe",
+                "--tcplisten",
+                f"127.0.0.1:{port}",
+            ]
+        )
+        # May fail if QBE not installed
+        if result == 0:
+            proc = subprocess.Popen([str(output_file)])
+            try:
+                for _ in range(500):
+                    try:
+                        client = socket.create_connection(("127.0.0.1", port))
+                        break
+                    except ConnectionRefusedError:
+                        time.sleep(0.01)
+                with client:
+                    assert client.recv(16) == b"hi"
+                assert proc.wait(timeout=5) == 0
+            finally:
+                proc.kill()
+
+
+    def test_emit_exe_wasi_deterministic(self, tmp_path):
+        """Test that --deterministic runs print the same random bytes."""
+        import subprocess
+
+        wat_file = tmp_path / "random.wat"
+        wat_file.write_text("""
+            (module
+              (import "wasi_snapshot_preview1" "random_get"
+                (func $random_get (param i32 i32) (result i32)))
+              (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "\\10\\00\\00\\00\\10\\00\\00\\00")
+              (func (export "_start")
+                (drop (call $random_get (i32.const 16) (i32.const 16)))
+                (drop (call $fd_write
+                  (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+        """)
+        outputs = []
+        for seed in ["1", "1", "2"]:
+            output_file = tmp_path / f"random{len(outputs)}"
+            args = [str(wat_file), "-o", str(output_file), "--emit", "exe"]
+            result = main([*args, "--deterministic", "--seed", seed])
+            # May fail if QBE not installed
+            if result != 0:
+                return
+            proc = subprocess.run([str(output_file)], capture_output=True, timeout=5)
+            assert proc.returncode == 0
+            outputs.append(proc.stdout)
+        assert len(outputs[0]) == 16
+        assert outputs[0] == outputs[1]
+        assert outputs[0] != outputs[2]
+
+    def test_emit_exe_instance_mode(self, tmp_path):
+        """Test an executable whose module state lives in an instance."""
+        import subprocess
+
+        wat_file = tmp_path / "counter.wat"
+        wat_file.write_text("""
+            (module
+              (memory 1)
+              (table 1 funcref)
+              (global $n (mut i32) (i32.const 40))
+              (type $t (func (result i32)))
+              (elem (i32.const 0) $bump)
+              (func $bump (result i32)
+                (global.set $n (i32.add (global.get $n) (i32.const 1)))
+                (i32.store (i32.const 8) (global.get $n))
+                (i32.load (i32.const 8)))
+              (func (export "main") (result i32)
+                (drop (call $bump))
+                (call_indirect (type $t) (i32.const 0))))
+        """)
+        output_file = tmp_path / "counter"
+        args = [str(wat_file), "-o", str(output_file), "--emit", "exe"]
+        result = main([*args, "--instance-mode"])
+        # May fail if QBE not installed
+        if result == 0:
+            proc = subprocess.run(
+                [str(output_file)], capture_output=True, text=True, timeout=5
+            )
+            assert proc.returncode == 0
+            assert proc.stdout.strip() == "42"
+
+    def test_emit_exe_linked(self, tmp_path):
+        """Test an executable linked from a main module and a library."""
+        import subprocess
+
+        (tmp_path / "main.wat").write_text("""
+            (module
+              (import "lib" "memory" (memory 1))
+              (import "lib" "sum" (func $sum (param i32) (result i32)))
+              (func (export "main") (result i32)
+                (i32.store (i32.const 8) (i32.const 30))
+                (call $sum (i32.const 12))))
+        """)
+        (tmp_path / "lib.wat").write_text("""
+            (module
+              (memory (export "memory") 1)
+              (data (i32.const 12) "\0c\00\00\00")
+              (func (export "sum") (param i32) (result i32)
+                (i32.add (i32.load (i32.const 8)) (i32.load (local.get 0)))))
+        """)
+        output_file = tmp_path / "linked"
+        args = [str(tmp_path / "main.wat"), str(tmp_path / "lib.wat")]
+        result = main([*args, "-o", str(output_file), "--emit", "exe"])
+        # May fail if QBE not installed
+        if result == 0:
+            proc = subprocess.run(
+                [str(output_file)], capture_output=True, text=True, timeout=5
+            )
+            assert proc.returncode == 0
+            assert proc.stdout.strip() == "42"
+
 
 class TestCLIErrors:
     """Tests for CLI error handling."""
@@ -245,3 +616,136 @@ class TestCLIErrors:
         assert result == 1
         captured = capsys.readouterr()
         assert "error" in captured.err.lower()
+
+    def test_wat_parse_error(self, tmp_path, capsys):
+        """Test that WAT errors report line and column."""
+        wat_file = tmp_path / "bad.wat"
+        wat_file.write_text("(module\n  (func (i32.nope)))")
+        result = main([str(wat_file)])
+        assert result == 1
+        captured = capsys.readouterr()
+        assert "Parse error: at line 2, column 10" in captured.err
+
+    def test_validation_error(self, tmp_path, capsys):
+        """Test that invalid modules are rejected with a location."""
+        wat_file = tmp_path / "invalid.wat"
+        wat_file.write_text('(module (func (export "f") (result i32) (i64.const 1)))')
+        result = main([str(wat_file), "-o", str(tmp_path / "output.ssa")])
+        assert result == 1
+        captured = capsys.readouterr()
+        assert "Validation error" in captured.err
+        assert "function 'f', offset 0x2" in captured.err
+
+    def test_link_error(self, tmp_path, capsys):
+        """Test that imports a linked module does not export are reported."""
+        (tmp_path / "app.wat").write_text('(module (import "lib" "f" (func)))')
+        (tmp_path / "lib.wat").write_text("(module)")
+        result = main([str(tmp_path / "app.wat"), str(tmp_path / "lib.wat")])
+        assert result == 1
+        captured = capsys.readouterr()
+        assert "Link error: module 'app' imports function lib.f" in captured.err
+
+    def test_invalid_env_argument(self, tmp_path, capsys):
+        """Test that --env requires KEY=VALUE."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text("(module)")
+        with pytest.raises(SystemExit):
+            main([str(wat_file), "--env", "NOVALUE"])
+        assert "expected KEY=VALUE" in capsys.readouterr().err
+
+    @pytest.mark.parametrize(
+        "address", ["8080", "localhost:", "localhost:http", "::1:8080", "h:70000"]
+    )
+    def test_invalid_listen_argument(self, tmp_path, capsys, address):
+        """Test that --listen requires HOST:PORT."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text("(module)")
+        with pytest.raises(SystemExit):
+            main([str(wat_file), "--listen", address])
+        err = capsys.readouterr().err
+        assert "expected HOST:PORT" in err or "must be bracketed" in err
+
+    def test_deterministic_rejects_inherit_env(self, tmp_path, capsys):
+        """Test that a deterministic program cannot see the host environment."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text("(module)")
+        with pytest.raises(SystemExit):
+            main([str(wat_file), "--deterministic", "--inherit-env"])
+        assert "--inherit-env cannot be used" in capsys.readouterr().err
+
+    def test_instance_mode_rejects_linking(self, tmp_path, capsys):
+        """Test that only a single module can be compiled in instance mode."""
+        (tmp_path / "app.wat").write_text("(module)")
+        (tmp_path / "lib.wat").write_text("(module)")
+        with pytest.raises(SystemExit):
+            main(
+                [
+                    str(tmp_path / "app.wat"),
+                    str(tmp_path / "lib.wat"),
+                    "--instance-mode",
+                ]
+            )
+        assert "--instance-mode cannot be used" in capsys.readouterr().err
+
+    def test_header_rejects_linking(self, tmp_path, capsys):
+        """Test that a header describes a single module."""
+        (tmp_path / "app.wat").write_text("(module)")
+        (tmp_path / "lib.wat").write_text("(module)")
+        with pytest.raises(SystemExit):
+            main(
+                [
+                    str(tmp_path / "app.wat"),
+                    str(tmp_path / "lib.wat"),
+                    "--emit",
+                    "header",
+                ]
+            )
+        assert "--emit header cannot be used" in capsys.readouterr().err
+
+    @pytest.mark.parametrize("seed", ["-1", "seven", str(2**64)])
+    def test_invalid_seed(self, tmp_path, capsys, seed):
+        """Test that --seed requires an unsigned 64-bit integer."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text("(module)")
+        with pytest.raises(SystemExit):
+            main([str(wat_file), "--deterministic", "--seed", seed])
+        assert "unsigned 64-bit integer" in capsys.readouterr().err
+
+    def test_unbound_imports(self, tmp_path, capsys):
+        """Test that every import the manifest does not bind is reported."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text(
+            '(module (import "env" "a" (func)) (import "env" "b" (func))'
+            ' (import "env" "c" (func)))'
+        )
+        manifest = tmp_path / "imports.toml"
+        manifest.write_text('env.b = "host_b"\n')
+        result = main([str(wat_file), "--imports", str(manifest)])
+        assert result == 1
+        assert (
+            "Compile error: 2 imported functions without a binding in the manifest\n"
+            "  env.a\n"
+            "  env.c\n"
+        ) in capsys.readouterr().err
+
+    def test_invalid_imports_manifest(self, tmp_path, capsys):
+        """Test that a malformed manifest is rejected."""
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text("(module)")
+        manifest = tmp_path / "imports.toml"
+        manifest.write_text('env.f = { symbol = "f", context = "heap" }\n')
+        with pytest.raises(SystemExit):
+            main([str(wat_file), "--imports", str(manifest)])
+        assert "binding of env.f: context must be" in capsys.readouterr().err
+        with pytest.raises(SystemExit):
+            main([str(wat_file), "--imports", str(tmp_path / "missing.toml")])
+        assert "cannot read" in capsys.readouterr().err
+
+    def test_no_validate(self, tmp_path):
+        """Test that --no-validate compiles invalid modules anyway."""
+        wat_file = tmp_path / "invalid.wat"
+        wat_file.write_text('(module (func (export "f") (result i32) (i64.const 1)))')
+        output_file = tmp_path / "output.ssa"
+        result = main([str(wat_file), "-o", str(output_file), "--no-validate"])
+        assert result == 0
+        assert output_file.exists()
@@ -6,13 +6,16 @@ verifying the output is correct.
 
 from __future__ import annotations
 
+import signal
 import subprocess
 import tempfile
 from pathlib import Path
 
 import pytest
 
+from waq.cli import is_wasi_module
 from waq.cli import main as waq_main
+from waq.parser.wat import parse_wat
 
 # Project root
 PROJECT_ROOT = Path(__file__).parent.parent.parent
@@ -22,7 +25,7 @@ RUNTIME_DIR = PROJECT_ROOT / "runtime"
 
 def check_tools_available():
     """Check if required tools are available."""
-    tools = ["wat2wasm", "qbe", "clang"]
+    tools = ["qbe", "clang"]
     missing = []
     for tool in tools:
         try:
@@ -64,43 +67,33 @@ def build_runtime():
     return runtime_obj
 
 
-def compile_and_run(wat_file: Path, expected_result: int | None = None) -> int:
-    """Compile a WAT file to an executable and run it.
+def build_with_harness(
+    wat_file: Path, tmpdir: Path, waq_args: list[str] | None = None
+) -> Path:
+    """Compile a WAT file and link it with the harness runtime.
 
-    Returns the exit code of the program.
+    The module's `main` export is called by a fixed main() whose exit status
+    is the returned value. Returns the executable.
     """
-    with tempfile.TemporaryDirectory() as tmpdir:
-        tmpdir = Path(tmpdir)
-
-        # Step 1: WAT -> WASM (using wat2wasm)
-        wasm_file = tmpdir / "program.wasm"
-        result = subprocess.run(
-            ["wat2wasm", str(wat_file), "-o", str(wasm_file)],
-            capture_output=True,
-            text=True,
-        )
-        if result.returncode != 0:
-            raise RuntimeError(f"wat2wasm failed: {result.stderr}")
-
-        # Step 2: WASM -> QBE IL (using waq)
-        ssa_file = tmpdir / "program.ssa"
-        exit_code = waq_main([str(wasm_file), "-o", str(ssa_file)])
-        if exit_code != 0:
-            raise RuntimeError("waq compilation failed")
-
-        # Step 3: QBE IL -> Assembly (using qbe)
-        asm_file = tmpdir / "program.s"
-        result = subprocess.run(
-            ["qbe", "-o", str(asm_file), str(ssa_file)], capture_output=True, text=True
-        )
-        if result.returncode != 0:
-            raise RuntimeError(f"qbe failed: {result.stderr}")
+    # Step 1: WAT -> QBE IL (using waq's built-in WAT parser)
+    ssa_file = tmpdir / "program.ssa"
+    exit_code = waq_main([str(wat_file), "-o", str(ssa_file), *(waq_args or [])])
+    if exit_code != 0:
+        raise RuntimeError("waq compilation failed")
+
+    # Step 2: QBE IL -> Assembly (using qbe)
+    asm_file = tmpdir / "program.s"
+    result = subprocess.run(
+        ["qbe", "-o", str(asm_file), str(ssa_file)], capture_output=True, text=True
+    )
+    if result.returncode != 0:
+        raise RuntimeError(f"qbe failed: {result.stderr}")
 
-        # Step 4: Create main.c wrapper
-        # Note: The WASM exports a function called 'main' which becomes
-        # the C symbol 'main'. We need to rename our C main to call it.
-        main_c = tmpdir / "main.c"
-        main_c.write_text("""
+    # Step 3: Create main.c wrapper
+    # Note: The WASM exports a function called 'main' which becomes
+    # the C symbol 'main'. We need to rename our C main to call it.
+    main_c = tmpdir / "main.c"
+    main_c.write_text("""
 #include <stdio.h>
 #include <stdlib.h>
 #include "wasm_runtime.h"
@@ -120,28 +113,64 @@ int main(void) {
 }
 """)
 
-        # Step 5: Compile and link
-        runtime_obj = build_runtime()
-        exe_file = tmpdir / "program"
+    # Step 4: Compile and link
+    runtime_obj = build_runtime()
+    exe_file = tmpdir / "program"
 
-        result = subprocess.run(
-            [
-                "clang",
-                "-o",
-                str(exe_file),
-                str(main_c),
-                str(asm_file),
-                str(runtime_obj),
-                f"-I{RUNTIME_DIR}",
-                "-lm",  # Math library
-            ],
-            capture_output=True,
-            text=True,
-        )
-        if result.returncode != 0:
-            raise RuntimeError(f"clang linking failed: {result.stderr}")
+    result = subprocess.run(
+        [
+            "clang",
+            "-o",
+            str(exe_file),
+            str(main_c),
+            str(asm_file),
+            str(runtime_obj),
+            f"-I{RUNTIME_DIR}",
+            "-lm",  # Math library
+        ],
+        capture_output=True,
+        text=True,
+    )
+    if result.returncode != 0:
+        raise RuntimeError(f"clang linking failed: {result.stderr}")
+    return exe_file
 
-        # Step 6: Run the program
+
+def build_with_waq(
+    wat_file: Path, tmpdir: Path, waq_args: list[str] | None = None
+) -> Path:
+    """Compile a WAT file to an executable with waq's own runtime.
+
+    The program starts from `_start` for WASI commands and from `main`
+    otherwise, with an i32 result as its exit status. Returns the executable.
+    """
+    exe_file = tmpdir / "program"
+    args = [str(wat_file), "-o", str(exe_file), "--emit", "exe", "--no-print"]
+    if waq_main([*args, *(waq_args or [])]) != 0:
+        raise RuntimeError("waq compilation failed")
+    return exe_file
+
+
+def compile_and_run(
+    wat_file: Path,
+    expected_result: int | None = None,
+    waq_args: list[str] | None = None,
+) -> int:
+    """Compile a WAT file to an executable and run it.
+
+    `waq_args` are extra command-line options passed to waq. WASI modules
+    and instances need waq's runtime; other modules link with the harness.
+    Returns the exit code of the program.
+    """
+    module = parse_wat(wat_file.read_text())
+    needs_waq_runtime = is_wasi_module(module) or "--instance-mode" in (
+        waq_args or []
+    )
+    build = build_with_waq if needs_waq_runtime else build_with_harness
+    with tempfile.TemporaryDirectory() as tmpdir:
+        exe_file = build(wat_file, Path(tmpdir), waq_args)
+
+        # Run the program
         result = subprocess.run([str(exe_file)], capture_output=True, text=True)
 
         if expected_result is not None:
@@ -203,6 +232,218 @@ class TestMemory:
         wat_file = FIXTURES_DIR / "memory.wat"
         compile_and_run(wat_file, expected_result=100)
 
+    def test_inline_bounds_checks_in_bounds(self):
+        """Test that checked accesses inside memory behave normally."""
+        wat_file = FIXTURES_DIR / "memory.wat"
+        compile_and_run(
+            wat_file, expected_result=100, waq_args=["--bounds-checks=inline"]
+        )
+
+    def test_inline_bounds_checks_trap(self):
+        """Test that an out-of-bounds load traps (runtime exits with 1)."""
+        wat_file = FIXTURES_DIR / "out_of_bounds.wat"
+        compile_and_run(
+            wat_file, expected_result=1, waq_args=["--bounds-checks=inline"]
+        )
+
+    def test_guard_pages_in_bounds(self):
+        """Test that accesses inside guarded memory behave normally."""
+        wat_file = FIXTURES_DIR / "memory.wat"
+        compile_and_run(
+            wat_file, expected_result=100, waq_args=["--bounds-checks=guard"]
+        )
+
+    def test_guard_pages_trap(self):
+        """Test that a load into the guard region traps (the handler aborts)."""
+        wat_file = FIXTURES_DIR / "out_of_bounds.wat"
+        compile_and_run(
+            wat_file,
+            expected_result=-signal.SIGABRT,
+            waq_args=["--bounds-checks=guard"],
+        )
+
+    def test_multiple_memories(self):
+        """Test that memories have separate buffers, sizes and limits."""
+        wat_file = FIXTURES_DIR / "multi_memory.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_multiple_memories_inline_bounds_checks(self):
+        """Test that each memory is checked against its own size."""
+        wat_file = FIXTURES_DIR / "multi_memory.wat"
+        compile_and_run(
+            wat_file, expected_result=42, waq_args=["--bounds-checks=inline"]
+        )
+
+    def test_bulk_memory(self):
+        """Test memory.init, memory.copy and memory.fill: 1 + 41 = 42."""
+        wat_file = FIXTURES_DIR / "bulk_memory.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_memory_init_past_segment_traps(self):
+        """Test a source offset past the segment traps (runtime exits with 1)."""
+        wat_file = FIXTURES_DIR / "bulk_memory_out_of_bounds.wat"
+        compile_and_run(wat_file, expected_result=1)
+
+    def test_data_segment_past_memory_traps(self):
+        """Test an active data segment ending past its memory traps."""
+        wat_file = FIXTURES_DIR / "data_out_of_bounds.wat"
+        compile_and_run(wat_file, expected_result=1)
+
+
+class TestCallIndirect:
+    """Indirect call tests."""
+
+    def test_call_indirect(self):
+        """Test dispatch through a table: 9 + 33 = 42."""
+        wat_file = FIXTURES_DIR / "call_indirect.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_signature_mismatch_traps(self):
+        """Test that a mismatched signature traps (runtime exits with 1)."""
+        wat_file = FIXTURES_DIR / "call_indirect_mismatch.wat"
+        compile_and_run(wat_file, expected_result=1)
+
+    def test_undefined_element_traps(self):
+        """Test that an index past the table end traps (runtime exits with 1)."""
+        wat_file = FIXTURES_DIR / "call_indirect_undefined.wat"
+        compile_and_run(wat_file, expected_result=1)
+
+    def test_global_function_reference(self):
+        """Test calling a function referenced from a global initializer."""
+        wat_file = FIXTURES_DIR / "global_func_ref.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+
+class TestTables:
+    """Multiple table and table64 tests."""
+
+    def test_multiple_tables(self):
+        """Test that tables have separate entries, sizes and limits."""
+        wat_file = FIXTURES_DIR / "multi_table.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_table64(self):
+        """Test a table indexed by i64: 40 + 2 = 42."""
+        wat_file = FIXTURES_DIR / "table64.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_table64_index_past_32_bits_traps(self):
+        """Test that a 64-bit index does not wrap (runtime exits with 1)."""
+        wat_file = FIXTURES_DIR / "table64_out_of_bounds.wat"
+        compile_and_run(wat_file, expected_result=1)
+
+    def test_table_init_expression(self):
+        """Test that a table's initial entries hold its init expression."""
+        wat_file = FIXTURES_DIR / "table_init.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+
+class TestExceptions:
+    """Exception handling tests."""
+
+    def test_catch_payload(self):
+        """Test catching a payload thrown two calls down: 40 + 2 = 42."""
+        wat_file = FIXTURES_DIR / "exceptions.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_try_table(self):
+        """Test try_table catch clauses and throw_ref: 40 + 2 = 42."""
+        wat_file = FIXTURES_DIR / "try_table.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_locals_set_before_throw(self):
+        """Test a local set in the try body is seen by the catch: 40 + 2 = 42."""
+        wat_file = FIXTURES_DIR / "exception_locals.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_uncaught_exception_traps(self):
+        """Test that an uncaught exception traps (runtime exits with 1)."""
+        wat_file = FIXTURES_DIR / "exception_uncaught.wat"
+        compile_and_run(wat_file, expected_result=1)
+
+
+class TestGarbageCollection:
+    """GC proposal tests."""
+
+    def test_collection_keeps_reachable_objects(self):
+        """Test objects reachable from operands, arrays and globals survive."""
+        wat_file = FIXTURES_DIR / "gc_collect.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_array_bulk_operations_and_casts(self):
+        """Test array segments, fill, copy and br_on_cast on subtypes."""
+        wat_file = FIXTURES_DIR / "gc_arrays.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+    def test_collection_keeps_exception_payloads(self):
+        """Test references only held by a captured exception survive."""
+        wat_file = FIXTURES_DIR / "gc_exceptions.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+
+class TestWasi:
+    """WASI programs, linked with waq's runtime."""
+
+    def test_paths_confined_to_preopen(self, tmp_path):
+        """Test a path above a preopened directory is refused: NOTCAPABLE."""
+        (tmp_path / "outside").mkdir()
+        (tmp_path / "outside" / "secret.txt").write_text("secret")
+        sandbox = tmp_path / "sandbox"
+        sandbox.mkdir()
+        (sandbox / "file.txt").write_text("file")
+        wat_file = FIXTURES_DIR / "wasi_sandbox.wat"
+        compile_and_run(
+            wat_file, expected_result=76, waq_args=["--dir", f"{sandbox}:/"]
+        )
+
+    def test_read_file(self, tmp_path):
+        """Test reading a preopened file, then an iovec array that faults."""
+        (tmp_path / "file.txt").write_text("file")
+        wat_file = FIXTURES_DIR / "wasi_fs.wat"
+        compile_and_run(
+            wat_file, expected_result=21, waq_args=["--dir", f"{tmp_path}:/"]
+        )
+
+    def test_poll_clock(self):
+        """Test poll_oneoff sleeping on a clock: 0 + 10 + 7 = 17."""
+        wat_file = FIXTURES_DIR / "wasi_poll.wat"
+        compile_and_run(wat_file, expected_result=17)
+
+    def test_deterministic(self):
+        """Test the virtual clock and canonical NaNs: 40 + 1 + 1 = 42."""
+        wat_file = FIXTURES_DIR / "wasi_deterministic.wat"
+        compile_and_run(wat_file, expected_result=42, waq_args=["--deterministic"])
+
+
+class TestTypedReferences:
+    """Typed function reference tests."""
+
+    def test_null_checks_and_branches(self):
+        """Test br_on_null, br_on_non_null and ref.as_non_null with call_ref."""
+        wat_file = FIXTURES_DIR / "typed_refs.wat"
+        compile_and_run(wat_file, expected_result=42)
+
+
+class TestInstanceMode:
+    """Modules compiled with --instance-mode, linked with waq's runtime."""
+
+    def test_memory_and_globals(self):
+        """Test memory and globals of the instance: 40 + 2 = 42."""
+        wat_file = FIXTURES_DIR / "instance_mode.wat"
+        compile_and_run(
+            wat_file,
+            expected_result=42,
+            waq_args=["--instance-mode", "--bounds-checks=inline"],
+        )
+
+    def test_inline_bounds_checks_trap(self):
+        """Test an access past the instance's memory traps (runtime aborts)."""
+        wat_file = FIXTURES_DIR / "out_of_bounds.wat"
+        compile_and_run(
+            wat_file,
+            expected_result=-signal.SIGABRT,
+            waq_args=["--instance-mode", "--bounds-checks=inline"],
+        )
 
 class TestGlobals:
     """Global variable tests."""
@@ -215,7 +456,7 @@ class TestGlobals:
 
 def mangle_export_name(name: str) -> str:
     """Mangle an export name to match compiler output."""
-    if name == "_start" or name.startswith(("wasm_", "__wasm_")):
+    if name.startswith(("wasm_", "__wasm_")):
         return name
     return f"wasm_{name}"
 
@@ -238,23 +479,13 @@ def compile_and_run_with_imports(
     with tempfile.TemporaryDirectory() as tmpdir:
         tmpdir = Path(tmpdir)
 
-        # Step 1: WAT -> WASM (using wat2wasm)
-        wasm_file = tmpdir / "program.wasm"
-        result = subprocess.run(
-            ["wat2wasm", str(wat_file), "-o", str(wasm_file)],
-            capture_output=True,
-            text=True,
-        )
-        if result.returncode != 0:
-            raise RuntimeError(f"wat2wasm failed: {result.stderr}")
-
-        # Step 2: WASM -> QBE IL (using waq)
+        # Step 1: WAT -> QBE IL (using waq's built-in WAT parser)
         ssa_file = tmpdir / "program.ssa"
-        exit_code = waq_main([str(wasm_file), "-o", str(ssa_file)])
+        exit_code = waq_main([str(wat_file), "-o", str(ssa_file)])
         if exit_code != 0:
             raise RuntimeError("waq compilation failed")
 
-        # Step 3: QBE IL -> Assembly (using qbe)
+        # Step 2: QBE IL -> Assembly (using qbe)
         asm_file = tmpdir / "program.s"
         result = subprocess.run(
             ["qbe", "-o", str(asm_file), str(ssa_file)], capture_output=True, text=True
@@ -262,11 +493,11 @@ def compile_and_run_with_imports(
         if result.returncode != 0:
             raise RuntimeError(f"qbe failed: {result.stderr}")
 
-        # Step 4: Create env.c with imported functions
+        # Step 3: Create env.c with imported functions
         env_c = tmpdir / "env.c"
         env_c.write_text(env_c_code)
 
-        # Step 5: Create main.c wrapper
+        # Step 4: Create main.c wrapper
         main_c = tmpdir / "main.c"
         main_c.write_text(f"""
 #include <stdio.h>
@@ -285,7 +516,7 @@ int main(void) {{
 }}
 """)
 
-        # Step 6: Compile and link
+        # Step 5: Compile and link
         runtime_obj = build_runtime()
         exe_file = tmpdir / "program"
 
@@ -307,7 +538,7 @@ int main(void) {{
         if result.returncode != 0:
             raise RuntimeError(f"clang linking failed: {result.stderr}")
 
-        # Step 7: Run the program
+        # Step 6: Run the program
         result = subprocess.run([str(exe_file)], capture_output=True, text=True)
 
         if expected_result is not None:
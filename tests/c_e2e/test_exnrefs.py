@@ -0,0 +1,135 @@
+"""End-to-end tests for exnrefs on the runtime's GC heap.
+
+A driver throws an exception whose payload references a struct, captures it
+with __wasm_exn_capture as compiled catch_ref clauses do, and allocates until
+the collector runs. Payload slots are traced by the layout registered for
+the tag, and a captured exception is kept alive only while something
+references it.
+"""
+
+from __future__ import annotations
+
+DRIVER = """
+#include <setjmp.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+extern void __wasm_gc_register_type(int32_t, int32_t, int32_t, const uint8_t *,
+                                    int32_t);
+extern void __wasm_gc_register_tag(int32_t, int32_t, const uint8_t *);
+extern void __wasm_gc_add_root(int64_t *);
+extern void __wasm_gc_collect(void);
+extern size_t __wasm_gc_heap_size(void);
+extern void *__wasm_struct_new(int32_t, int32_t);
+extern void *__wasm_push_exception_handler(void);
+extern void __wasm_begin_catch(void);
+extern void __wasm_throw(int32_t);
+extern void __wasm_throw_with_payload(int32_t, void *, size_t);
+extern void *__wasm_exn_capture(void);
+extern void __wasm_throw_ref(void *);
+extern int32_t __wasm_get_exception_tag(void);
+extern void *__wasm_get_exception_payload(void);
+
+/* Type 0 is (struct (field i64)), tag 0 is (param i64 (ref $node)) */
+static const uint8_t type_layout[] = {0};
+static const uint8_t tag_layout[] = {0, 1};
+static int64_t root;
+static int64_t payload[2];
+
+static int64_t *new_node(int64_t value) {
+    int64_t *node = __wasm_struct_new(0, 1);
+    node[0] = value;
+    return node;
+}
+
+/* Allocate until the heap shrinks, i.e. a collection has run */
+static void force_collection(void) {
+    size_t before = __wasm_gc_heap_size();
+    for (int i = 0; i < (1 << 20); i++) {
+        new_node(0);
+        if (__wasm_gc_heap_size() < before) return;
+        before = __wasm_gc_heap_size();
+    }
+    printf("no collection\\n");
+}
+
+/* Print the tag and the referenced node's value of the current exception */
+static void print_current(void) {
+    memcpy(payload, __wasm_get_exception_payload(), sizeof(payload));
+    printf("%d %lld\\n", __wasm_get_exception_tag(),
+           (long long)((int64_t *)payload[1])[0]);
+}
+
+/* usage: driver in-flight|dropped|rooted */
+int main(int argc, char **argv) {
+    if (argc < 2) return 2;
+    __wasm_gc_register_type(0, 0, 1, type_layout, -1);
+    __wasm_gc_register_tag(0, 2, tag_layout);
+
+    /* The i64 slot holds an object address too, which must not be traced */
+    payload[0] = (int64_t)new_node(7);
+    payload[1] = (int64_t)new_node(42);
+    if (setjmp(__wasm_push_exception_handler()) == 0) {
+        __wasm_throw_with_payload(0, payload, sizeof(payload));
+    }
+    __wasm_begin_catch();
+    memset(payload, 0, sizeof(payload));
+
+    if (strcmp(argv[1], "in-flight") == 0) {
+        force_collection();
+        print_current();
+        __wasm_gc_collect();
+        printf("%zu\\n", __wasm_gc_heap_size());
+        return 0;
+    }
+
+    root = (int64_t)__wasm_exn_capture();
+    int rooted = strcmp(argv[1], "rooted") == 0;
+    if (rooted) __wasm_gc_add_root(&root);
+
+    /* Replace the current exception with one without a payload */
+    if (setjmp(__wasm_push_exception_handler()) == 0) __wasm_throw(1);
+    __wasm_begin_catch();
+
+    force_collection();
+    __wasm_gc_collect();
+    printf("%zu\\n", __wasm_gc_heap_size());
+    if (!rooted) return 0;
+
+    if (setjmp(__wasm_push_exception_handler()) == 0) {
+        __wasm_throw_ref((void *)root);
+    }
+    __wasm_begin_catch();
+    print_current();
+    return 0;
+}
+"""
+
+
+class TestInFlightException:
+    """The exception being handled is a root."""
+
+    def test_payload_references_survive_collection(self, run):
+        result = run("in-flight")
+        assert result.returncode == 0
+        thrown, heap_size = result.stdout.splitlines()
+        assert thrown == "0 42"
+        # Only the referenced node is left: the i64 slot is not traced
+        assert heap_size == "16"
+
+
+class TestCapturedExceptions:
+    """Captured exceptions are GC objects traced through their payload."""
+
+    def test_unreferenced_exnref_is_collected(self, run):
+        result = run("dropped")
+        assert result.returncode == 0
+        assert result.stdout == "0\n"
+
+    def test_rooted_exnref_keeps_payload_alive(self, run):
+        result = run("rooted")
+        assert result.returncode == 0
+        heap_size, thrown = result.stdout.splitlines()
+        assert int(heap_size) > 0
+        assert thrown == "0 42"
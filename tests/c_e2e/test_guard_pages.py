@@ -0,0 +1,82 @@
+"""End-to-end tests for guard-page memory sandboxing.
+
+These tests link small C drivers against the runtime and access memory the
+way compiled code does (raw `__wasm_memory + addr`), checking that faults at
+the memory boundary become wasm out-of-bounds traps.
+"""
+
+from __future__ import annotations
+
+import subprocess
+import sys
+from pathlib import Path
+
+import pytest
+
+pytestmark = pytest.mark.skipif(
+    sys.platform == "win32", reason="Requires mmap/sigaction"
+)
+
+DRIVER = """
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+extern uint8_t *__wasm_memory;
+extern void __wasm_memory_guard_init(void);
+extern int32_t __wasm_memory_grow(int32_t delta);
+
+/* usage: driver <initial pages> <grow pages> <address> */
+int main(int argc, char **argv) {
+    (void)argc;
+    __wasm_memory_guard_init();
+    __wasm_memory_grow(atoi(argv[1]));
+    uint8_t *base = __wasm_memory;
+    __wasm_memory_grow(atoi(argv[2]));
+    if (__wasm_memory != base) return 2;  /* memory must not move */
+
+    uint64_t addr = strtoull(argv[3], NULL, 0);
+    volatile uint8_t *p = __wasm_memory + addr;
+    *p = 42;
+    printf("%d\\n", *p);
+    return 0;
+}
+"""
+
+PAGE = 65536
+
+
+def run_driver(driver: Path, initial: int, grow: int, addr: int):
+    return subprocess.run(
+        [str(driver), str(initial), str(grow), str(addr)],
+        capture_output=True,
+        text=True,
+        check=False,
+    )
+
+
+class TestGuardPages:
+    """Faults inside the guard region trap; accesses below the end succeed."""
+
+    def test_last_byte_is_accessible(self, driver):
+        result = run_driver(driver, 1, 0, PAGE - 1)
+        assert result.returncode == 0
+        assert result.stdout.strip() == "42"
+
+    def test_first_byte_past_end_traps(self, driver):
+        result = run_driver(driver, 1, 0, PAGE)
+        assert result.returncode != 0
+        assert "out of bounds memory access" in result.stderr
+
+    def test_grow_commits_pages_in_place(self, driver):
+        result = run_driver(driver, 1, 2, 3 * PAGE - 1)
+        assert result.returncode == 0
+
+    def test_boundary_moves_with_grow(self, driver):
+        result = run_driver(driver, 1, 2, 3 * PAGE)
+        assert "out of bounds memory access" in result.stderr
+
+    def test_maximum_offset_traps(self, driver):
+        # Largest address + offset a 32-bit load can form
+        result = run_driver(driver, 1, 0, 0xFFFF_FFFF + 0xFFFF_FFFF)
+        assert "out of bounds memory access" in result.stderr
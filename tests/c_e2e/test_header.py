@@ -0,0 +1,188 @@
+"""End-to-end tests for embedding compiled modules through --emit header.
+
+Host programs include the generated header, define the module's imports
+and call its exports. Linking them needs QBE for the module's object file;
+without it only the header is compiled.
+"""
+
+from __future__ import annotations
+
+import shutil
+import subprocess
+
+import pytest
+
+from waq.cli import main
+from waq.runtime import RUNTIME_C_SOURCE
+
+CC = shutil.which("clang") or shutil.which("gcc")
+CXX = shutil.which("clang++") or shutil.which("g++")
+
+pytestmark = pytest.mark.skipif(CC is None, reason="No C compiler available")
+
+MODULE = """
+(module
+  (import "env" "host_scale" (func $scale (param i32) (result i32)))
+  (import "env" "host_pair" (func (result i32 i64)))
+  (import "env" "offset" (global $offset i32))
+  (memory (export "memory") 1)
+  (global $total (export "total") (mut i64) (i64.const 0))
+  (global (export "limit") i32 (i32.const 10))
+  (func (export "add") (param i32 i32) (result i32)
+    (global.set $total (i64.add (global.get $total) (i64.const 1)))
+    (i32.store (i32.const 16) (local.get 0))
+    (i32.add
+      (call $scale (i32.add (local.get 0) (local.get 1)))
+      (global.get $offset)))
+  (func (export "divmod") (param i32 i32) (result i32 i32)
+    (i32.div_u (local.get 0) (local.get 1))
+    (i32.rem_u (local.get 0) (local.get 1))))
+"""
+
+HOST = """
+#include <inttypes.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "module.h"
+
+int32_t __wasm_global_0 = 100;
+
+int32_t Z_envZ_host_scale(int32_t x) {
+    return x * 2;
+}
+
+int32_t Z_envZ_host_pair(int64_t *result1) {
+    *result1 = 2;
+    return 1;
+}
+
+int main(void) {
+    __wasm_memory_init();
+    printf("%" PRId32 "\\n", wasm_add(3, 4));
+    int32_t rem;
+    int32_t quot = wasm_divmod(17, 5, &rem);
+    printf("%" PRId32 " %" PRId32 "\\n", quot, rem);
+    int32_t stored;
+    memcpy(&stored, wasm_memory_data() + 16, 4);
+    printf("%" PRId32 " %" PRIu64 "\\n", stored, wasm_memory_size());
+    wasm_total_set(wasm_total_get() + 40);
+    printf("%" PRId64 " %" PRId32 "\\n", wasm_total_get(), wasm_limit_get());
+    __wasm_runtime_cleanup();
+    return 0;
+}
+"""
+
+INSTANCE_HOST = """
+#include <inttypes.h>
+#include <stdio.h>
+
+#include "module.h"
+
+int32_t __wasm_global_0 = 100;
+
+int32_t Z_envZ_host_scale(WasmInstance *instance, int32_t x) {
+    return x * (int32_t)wasm_total_get(instance);
+}
+
+int32_t Z_envZ_host_pair(WasmInstance *instance, int64_t *result1) {
+    (void)instance;
+    *result1 = 2;
+    return 1;
+}
+
+int main(void) {
+    WasmInstance *a = waq_instance_new(__wasm_memory_init);
+    WasmInstance *b = waq_instance_new(__wasm_memory_init);
+    if (!a || !b) return 1;
+    wasm_total_set(a, 9);
+    printf("%" PRId32 "\\n", wasm_add(a, 3, 4));
+    printf("%" PRId32 "\\n", wasm_add(b, 3, 4));
+    printf("%" PRId64 " %" PRId64 "\\n", wasm_total_get(a), wasm_total_get(b));
+    waq_instance_reset(a);
+    printf("%" PRId64 " %d\\n", wasm_total_get(a), wasm_memory_data(a)[16]);
+    waq_instance_free(a);
+    waq_instance_free(b);
+    return 0;
+}
+"""
+
+
+def emit(tmp_path, *args: str) -> int:
+    wat_file = tmp_path / "module.wat"
+    wat_file.write_text(MODULE)
+    return main([str(wat_file), "-o", str(tmp_path / "module.h"), *args])
+
+
+def build_host(tmp_path, source: str, *args: str) -> subprocess.CompletedProcess:
+    host = tmp_path / "host.c"
+    host.write_text(source)
+    return subprocess.run(
+        [CC, "-Wall", "-Wextra", "-Werror", *args, str(host)],
+        capture_output=True,
+        text=True,
+        cwd=tmp_path,
+    )
+
+
+class TestHeaderCompiles:
+    """The header is valid C and C++ for the host's declarations."""
+
+    def test_c(self, tmp_path):
+        assert emit(tmp_path, "--emit", "header") == 0
+        result = build_host(tmp_path, HOST, "-c", "-o", "host.o")
+        assert result.returncode == 0, result.stderr
+
+    def test_instance_mode(self, tmp_path):
+        assert emit(tmp_path, "--emit", "header", "--instance-mode") == 0
+        result = build_host(tmp_path, INSTANCE_HOST, "-c", "-o", "host.o")
+        assert result.returncode == 0, result.stderr
+
+    @pytest.mark.skipif(CXX is None, reason="No C++ compiler available")
+    def test_cplusplus(self, tmp_path):
+        assert emit(tmp_path, "--emit", "header") == 0
+        (tmp_path / "host.cc").write_text('#include "module.h"\n')
+        result = subprocess.run(
+            [CXX, "-Wall", "-Werror", "-fsyntax-only", "host.cc"],
+            capture_output=True,
+            text=True,
+            cwd=tmp_path,
+        )
+        assert result.returncode == 0, result.stderr
+
+
+class TestEmbedding:
+    """Host programs linked with the module's object file."""
+
+    def link_and_run(self, tmp_path, source: str, *args: str) -> list[str]:
+        assert emit(tmp_path, "--emit", "header", *args) == 0
+        obj = tmp_path / "module.o"
+        wat_file = tmp_path / "module.wat"
+        # May fail if QBE not installed
+        if main([str(wat_file), "-o", str(obj), "--emit", "obj", *args]) != 0:
+            pytest.skip("QBE not available")
+        defines = ["-DWAQ_INSTANCE_MODE"] if args else []
+        result = build_host(
+            tmp_path,
+            source,
+            *defines,
+            "-o",
+            "host",
+            str(obj),
+            str(RUNTIME_C_SOURCE),
+            "-lm",
+        )
+        assert result.returncode == 0, result.stderr
+        proc = subprocess.run(
+            [str(tmp_path / "host")], capture_output=True, text=True, timeout=5
+        )
+        assert proc.returncode == 0
+        return proc.stdout.splitlines()
+
+    def test_standalone(self, tmp_path):
+        assert self.link_and_run(tmp_path, HOST) == ["114", "3 2", "3 65536", "41 10"]
+
+    def test_instances(self, tmp_path):
+        lines = self.link_and_run(tmp_path, INSTANCE_HOST, "--instance-mode")
+        # Each instance counts its own calls, and reset starts over
+        assert lines == ["170", "107", "10 1", "0 0"]
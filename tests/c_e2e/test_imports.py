@@ -0,0 +1,133 @@
+"""End-to-end tests for host import bindings (--imports).
+
+A driver checks the runtime's buffer translation for import adapters. Host
+programs then define the C functions a manifest binds, and call the module
+through its header; linking them needs QBE for the module's object file.
+"""
+
+from __future__ import annotations
+
+import subprocess
+
+import pytest
+
+from waq.cli import main
+
+DRIVER = """
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+extern int32_t __wasm_memory_grow(int32_t);
+extern uint8_t *__wasm_memory_base_idx(int32_t);
+extern uint8_t *__wasm_memory_buffer(int32_t, uint64_t, uint64_t);
+
+/* usage: driver <addr> <len> */
+int main(int argc, char **argv) {
+    if (argc < 3) return 2;
+    __wasm_memory_grow(1);
+    uint8_t *buffer = __wasm_memory_buffer(
+        0, strtoull(argv[1], NULL, 0), strtoull(argv[2], NULL, 0));
+    printf("%ld\\n", (long)(buffer - __wasm_memory_base_idx(0)));
+    return 0;
+}
+"""
+
+MODULE = """
+(module
+  (import "env" "print" (func $print (param i32 i32) (result i32)))
+  (import "env" "scale" (func $scale (param i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 16) "hello")
+  (func (export "run") (param i32) (result i32)
+    (i32.add
+      (call $print (i32.const 16) (local.get 0))
+      (call $scale (i32.const 5)))))
+"""
+
+MANIFEST = """
+env.scale = { symbol = "host_scale", context = "memory" }
+
+[env.print]
+symbol = "host_print"
+buffers = [[0, 1]]
+"""
+
+HOST = """
+#include <inttypes.h>
+#include <stdio.h>
+
+#include "module.h"
+
+int32_t host_print(uint8_t *text, int32_t len) {
+    printf("%.*s\\n", (int)len, (const char *)text);
+    return len;
+}
+
+int32_t host_scale(uint8_t *memory, int32_t x) {
+    return x * memory[16];
+}
+
+int main(int argc, char **argv) {
+    (void)argv;
+    __wasm_memory_init();
+    /* Running with an argument reads past the end of memory */
+    printf("%" PRId32 "\\n", wasm_run(argc > 1 ? 65535 : 5));
+    __wasm_runtime_cleanup();
+    return 0;
+}
+"""
+
+
+
+class TestMemoryBuffer:
+    """Buffers must lie entirely in memory."""
+
+    @pytest.mark.parametrize(
+        ("addr", "length"), [("16", "5"), ("65536", "0"), ("0", "65536")]
+    )
+    def test_in_bounds(self, run, addr, length):
+        result = run(addr, length)
+        assert result.returncode == 0
+        assert result.stdout.strip() == addr
+
+    @pytest.mark.parametrize(
+        ("addr", "length"),
+        [("65537", "0"), ("65535", "2"), ("1", "0xffffffffffffffff")],
+    )
+    def test_out_of_bounds(self, run, addr, length):
+        result = run(addr, length)
+        assert result.returncode != 0
+        assert "out of bounds memory access" in result.stderr
+
+
+class TestBoundImports:
+    """Host programs providing the C functions of a manifest."""
+
+    @pytest.fixture
+    def host(self, tmp_path, build_c):
+        wat_file = tmp_path / "module.wat"
+        wat_file.write_text(MODULE)
+        manifest = tmp_path / "imports.toml"
+        manifest.write_text(MANIFEST)
+        args = [str(wat_file), "--imports", str(manifest)]
+        assert main([*args, "-o", str(tmp_path / "module.h"), "--emit", "header"]) == 0
+        obj = tmp_path / "module.o"
+        # May fail if QBE not installed
+        if main([*args, "-o", str(obj), "--emit", "obj"]) != 0:
+            pytest.skip("QBE not available")
+        flags = ["-Wall", "-Wextra", "-Werror", f"-I{tmp_path}"]
+        return build_c(HOST, *flags, str(obj), name="host")
+
+    def test_bound_calls(self, host):
+        result = subprocess.run([str(host)], capture_output=True, text=True, timeout=10)
+        assert result.returncode == 0
+        # 5 bytes printed, plus 5 times the 'h' at address 16
+        assert result.stdout.splitlines() == ["hello", str(5 + 5 * ord("h"))]
+
+    def test_buffer_out_of_bounds(self, host):
+        result = subprocess.run(
+            [str(host), "overflow"], capture_output=True, text=True, timeout=10
+        )
+        assert result.returncode != 0
+        assert "out of bounds memory access" in result.stderr
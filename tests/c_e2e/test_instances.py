@@ -0,0 +1,141 @@
+"""End-to-end tests for the runtime's instances (--instance-mode).
+
+A C driver linked against a runtime built with WAQ_INSTANCE_MODE creates
+instances the way a host program does, with an init function standing in
+for a module's __wasm_memory_init, and reads them at the offsets compiled
+code uses.
+"""
+
+from __future__ import annotations
+
+import subprocess
+
+from waq.compiler.context import VMCTX_FIELDS, VMCTX_GLOBALS
+
+DRIVER = """
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+typedef struct WasmInstance WasmInstance;
+extern WasmInstance *waq_instance_new(void (*)(WasmInstance *));
+extern void waq_instance_reset(WasmInstance *);
+extern void waq_instance_free(WasmInstance *);
+extern WasmInstance *__wasm_instance_enter(WasmInstance *);
+extern void __wasm_instance_leave(WasmInstance *);
+extern int64_t *__wasm_instance_globals(int32_t);
+extern int32_t __wasm_memory_grow(int32_t);
+extern int32_t __wasm_memory_size(void);
+extern int32_t __wasm_table_grow(int32_t, void *, int32_t);
+
+#define FIELD(instance, type, offset) (*(type *)((char *)(instance) + (offset)))
+#define MEMORY(instance) FIELD(instance, uint8_t *, MEMORY_OFFSET)
+#define GLOBALS(instance) FIELD(instance, int64_t *, GLOBALS_OFFSET)
+
+static int inits;
+
+/* Stands in for a module's __wasm_memory_init */
+static void init(WasmInstance *instance) {
+    (void)instance;
+    inits++;
+    __wasm_memory_grow(1);
+    __wasm_table_grow(0, NULL, 2);
+    __wasm_instance_globals(1)[0] = 40;
+}
+
+static int32_t pages(WasmInstance *instance) {
+    WasmInstance *previous = __wasm_instance_enter(instance);
+    int32_t size = __wasm_memory_size();
+    __wasm_instance_leave(previous);
+    return size;
+}
+
+static void print_state(WasmInstance *instance) {
+    printf("%d %lld %d %llu %u\\n", MEMORY(instance)[0],
+           (long long)GLOBALS(instance)[0], pages(instance),
+           (unsigned long long)FIELD(instance, uint64_t, MEMORY_SIZE_OFFSET),
+           FIELD(instance, uint32_t, TABLE_SIZE_OFFSET));
+}
+
+/* usage: driver <command> */
+int main(int argc, char **argv) {
+    if (argc < 2) return 2;
+    const char *cmd = argv[1];
+    WasmInstance *a = waq_instance_new(init);
+    WasmInstance *b = waq_instance_new(init);
+    if (!a || !b) return 1;
+    if (!strcmp(cmd, "independent")) {
+        MEMORY(a)[0] = 1;
+        MEMORY(b)[0] = 2;
+        GLOBALS(a)[0]++;
+        WasmInstance *previous = __wasm_instance_enter(a);
+        __wasm_memory_grow(2);
+        __wasm_instance_leave(previous);
+        print_state(a);
+        print_state(b);
+    } else if (!strcmp(cmd, "nested")) {
+        WasmInstance *outer = __wasm_instance_enter(a);
+        __wasm_memory_grow(1);
+        WasmInstance *inner = __wasm_instance_enter(b);
+        printf("%d %d\\n", inner == a, __wasm_memory_size());
+        __wasm_instance_leave(inner);
+        printf("%d\\n", __wasm_memory_size());
+        __wasm_instance_leave(outer);
+    } else if (!strcmp(cmd, "reset")) {
+        MEMORY(a)[0] = 1;
+        GLOBALS(a)[0] = 7;
+        WasmInstance *previous = __wasm_instance_enter(a);
+        __wasm_memory_grow(3);
+        __wasm_instance_leave(previous);
+        waq_instance_reset(a);
+        print_state(a);
+        printf("%d\\n", inits);
+    } else {
+        return 2;
+    }
+    waq_instance_free(a);
+    waq_instance_free(b);
+    waq_instance_free(NULL);
+    return 0;
+}
+"""
+
+
+# The runtime built in instance mode, with the offsets compiled code reads
+_OFFSETS = {
+    "MEMORY_OFFSET": VMCTX_FIELDS["__wasm_memory"],
+    "MEMORY_SIZE_OFFSET": VMCTX_FIELDS["__wasm_memory_size_bytes"],
+    "TABLE_SIZE_OFFSET": VMCTX_FIELDS["__wasm_table_size"],
+    "GLOBALS_OFFSET": VMCTX_GLOBALS,
+}
+DRIVER_CFLAGS = [
+    "-DWAQ_INSTANCE_MODE",
+    *(f"-D{name}={offset}" for name, offset in _OFFSETS.items()),
+]
+
+
+def run_driver(driver, *args: str) -> list[str]:
+    result = subprocess.run(
+        [str(driver), *args], capture_output=True, text=True, timeout=10
+    )
+    assert result.returncode == 0
+    return result.stdout.splitlines()
+
+
+class TestInstances:
+    """Each instance has its own memory, table and globals."""
+
+    def test_independent(self, driver):
+        # memory[0], global 0, pages, memory bytes, table size
+        assert run_driver(driver, "independent") == [
+            "1 41 3 196608 2",
+            "2 40 1 65536 2",
+        ]
+
+    def test_nested_instances(self, driver):
+        # Leaving an instance returns to the one it replaced
+        assert run_driver(driver, "nested") == ["1 1", "2"]
+
+    def test_reset(self, driver):
+        # Two instances were created, and the reset one initialized again
+        assert run_driver(driver, "reset") == ["0 40 1 65536 2", "3"]
@@ -0,0 +1,211 @@
+"""End-to-end tests for the runtime's deterministic mode.
+
+A C driver linked against the runtime switches it to deterministic mode the
+way `--deterministic` executables do, then prints what random_get, the clocks
+and the float helpers return.
+"""
+
+from __future__ import annotations
+
+import subprocess
+import sys
+import time
+
+import pytest
+
+pytestmark = pytest.mark.skipif(sys.platform == "win32", reason="Requires POSIX clocks")
+
+DRIVER = """
+#include <inttypes.h>
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define REALTIME 0
+#define MONOTONIC 1
+#define PROCESS_CPUTIME 2
+#define ABSTIME 1
+#define MS 1000000ULL
+
+/* Runtime entry point of a wasi_snapshot_preview1 import */
+#define WASI(name) Z_wasi_snapshot_preview1Z_##name
+
+extern uint8_t *__wasm_memory;
+extern int32_t __wasm_memory_grow(int32_t);
+extern void __wasi_init(int argc, char **argv, char **environ);
+extern void __wasi_set_deterministic(uint64_t seed);
+extern int32_t WASI(random_get)(uint32_t, uint32_t);
+extern int32_t WASI(clock_time_get)(uint32_t, uint64_t, uint32_t);
+extern int32_t WASI(poll_oneoff)(uint32_t, uint32_t, uint32_t, uint32_t);
+extern int32_t WASI(args_sizes_get)(uint32_t, uint32_t);
+extern int32_t WASI(environ_sizes_get)(uint32_t, uint32_t);
+extern float __wasm_f32_div_deterministic(float, float);
+extern double __wasm_f64_sqrt_deterministic(double);
+extern float __wasm_f32_min_deterministic(float, float);
+extern double __wasm_canon_nan_f64(double);
+extern float __wasm_f32_min(float, float);
+extern float __wasm_f32_max(float, float);
+extern double __wasm_f64_min(double, double);
+extern double __wasm_f64_max(double, double);
+
+static uint64_t now(uint32_t clock) {
+    WASI(clock_time_get)(clock, 0, 4000);
+    uint64_t time;
+    memcpy(&time, __wasm_memory + 4000, 8);
+    return time;
+}
+
+/* One clock subscription at 1024; prints the errno and the event count */
+static void sleep_until(uint64_t timeout, uint16_t flags) {
+    uint8_t *sub = __wasm_memory + 1024;
+    uint32_t clock = MONOTONIC;
+    memcpy(sub + 16, &clock, 4);
+    memcpy(sub + 24, &timeout, 8);
+    memcpy(sub + 40, &flags, 2);
+    int32_t err = WASI(poll_oneoff)(1024, 2048, 1, 4096);
+    printf("%d %u\\n", err, *(uint32_t *)(__wasm_memory + 4096));
+}
+
+static uint32_t f32_bits(float x) {
+    uint32_t bits;
+    memcpy(&bits, &x, 4);
+    return bits;
+}
+
+static uint64_t f64_bits(double x) {
+    uint64_t bits;
+    memcpy(&bits, &x, 8);
+    return bits;
+}
+
+static double f64_from_bits(uint64_t bits) {
+    double x;
+    memcpy(&x, &bits, 8);
+    return x;
+}
+
+/* usage: driver <command> [seed] */
+int main(int argc, char **argv) {
+    if (argc < 2) return 2;
+    const char *cmd = argv[1];
+    __wasm_memory_grow(1);
+    __wasi_init(0, NULL, NULL);
+    if (strcmp(cmd, "random-host")) {
+        __wasi_set_deterministic(argc > 2 ? strtoull(argv[2], NULL, 0) : 0);
+    }
+    if (!strcmp(cmd, "random") || !strcmp(cmd, "random-host")) {
+        WASI(random_get)(8192, 13);
+        for (int i = 0; i < 13; i++) printf("%02x", __wasm_memory[8192 + i]);
+        printf("\\n");
+    } else if (!strcmp(cmd, "clocks")) {
+        printf("%" PRIu64 "\\n", now(REALTIME));
+        printf("%" PRIu64 "\\n", now(MONOTONIC));
+        printf("%" PRIu64 "\\n", now(PROCESS_CPUTIME));
+        printf("%d\\n", WASI(clock_time_get)(99, 0, 4000));
+    } else if (!strcmp(cmd, "sleep")) {
+        sleep_until(3600000 * MS, 0);
+        printf("%" PRIu64 "\\n", now(MONOTONIC));
+        sleep_until(now(MONOTONIC) + 5 * MS, ABSTIME);
+        printf("%" PRIu64 "\\n", now(REALTIME));
+    } else if (!strcmp(cmd, "faults")) {
+        /* Results past the end of memory; the clock only steps on reads */
+        printf("%d\\n", WASI(random_get)(65530, 13));
+        printf("%d\\n", WASI(random_get)(0xFFFFFFF8, 16));
+        printf("%d\\n", WASI(clock_time_get)(MONOTONIC, 0, 65532));
+        printf("%d\\n", WASI(args_sizes_get)(65534, 0));
+        printf("%d\\n", WASI(environ_sizes_get)(0, 65534));
+        printf("%" PRIu64 "\\n", now(MONOTONIC));
+    } else if (!strcmp(cmd, "floats")) {
+        double payload = f64_from_bits(0x7FF4000000000123ULL);
+        printf("%08" PRIx32 "\\n", f32_bits(__wasm_f32_div_deterministic(0, 0)));
+        printf("%016" PRIx64 "\\n", f64_bits(__wasm_f64_sqrt_deterministic(-1)));
+        printf("%08" PRIx32 "\\n", f32_bits(__wasm_f32_min_deterministic(NAN, 1)));
+        printf("%016" PRIx64 "\\n", f64_bits(__wasm_canon_nan_f64(payload)));
+        printf("%016" PRIx64 "\\n", f64_bits(__wasm_canon_nan_f64(-2.5)));
+    } else if (!strcmp(cmd, "zeros")) {
+        printf("%08" PRIx32 "\\n", f32_bits(__wasm_f32_min(0.0f, -0.0f)));
+        printf("%08" PRIx32 "\\n", f32_bits(__wasm_f32_max(-0.0f, 0.0f)));
+        printf("%016" PRIx64 "\\n", f64_bits(__wasm_f64_min(0.0, -0.0)));
+        printf("%016" PRIx64 "\\n", f64_bits(__wasm_f64_max(-0.0, 0.0)));
+    } else {
+        return 2;
+    }
+    return 0;
+}
+"""
+
+SUCCESS = 0
+FAULT = 21
+INVAL = 28
+MS = 1_000_000
+
+
+def run_driver(driver, *args: str) -> list[str]:
+    result = subprocess.run(
+        [str(driver), *args], capture_output=True, text=True, timeout=10
+    )
+    assert result.returncode == 0
+    return result.stdout.splitlines()
+
+
+class TestRandom:
+    """random_get draws from the seed."""
+
+    def test_same_seed_same_bytes(self, driver):
+        first = run_driver(driver, "random", "42")
+        assert first == run_driver(driver, "random", "42")
+        assert len(first[0]) == 26
+
+    def test_buffers_past_memory_fault(self, driver):
+        assert run_driver(driver, "faults") == [str(FAULT)] * 5 + ["0"]
+
+    def test_different_seeds(self, driver):
+        assert run_driver(driver, "random", "1") != run_driver(driver, "random", "2")
+
+    def test_host_randomness_without_deterministic_mode(self, driver):
+        first = run_driver(driver, "random-host")
+        assert first != run_driver(driver, "random-host")
+
+
+class TestClocks:
+    """Every clock reads the virtual time."""
+
+    def test_clocks_start_at_zero_and_step(self, driver):
+        lines = run_driver(driver, "clocks")
+        assert lines == ["0", str(MS), str(2 * MS), str(INVAL)]
+
+    def test_sleep_is_instant(self, driver):
+        start = time.monotonic()
+        lines = run_driver(driver, "sleep")
+        assert time.monotonic() - start < 5
+        # An hour passes in virtual time, then up to a deadline 5 ms after a
+        # reading (which itself advanced the clock by 1 ms)
+        assert lines == [
+            f"{SUCCESS} 1",
+            str(3600000 * MS),
+            f"{SUCCESS} 1",
+            str(3600000 * MS + 6 * MS),
+        ]
+
+
+class TestFloats:
+    """The runtime's canonical NaN helpers."""
+
+    def test_canonical_nans(self, driver):
+        assert run_driver(driver, "floats") == [
+            "7fc00000",
+            "7ff8000000000000",
+            "7fc00000",
+            "7ff8000000000000",
+            "c004000000000000",
+        ]
+
+    def test_min_max_order_zeros(self, driver):
+        assert run_driver(driver, "zeros") == [
+            "80000000",
+            "00000000",
+            "8000000000000000",
+            "0000000000000000",
+        ]
@@ -0,0 +1,380 @@
+"""End-to-end tests for the WASI file and directory functions.
+
+Like test_wasi_sandbox.py, these link a C driver against the runtime. Each
+driver command does what a small coreutils-style program (ls, cp, ln, touch)
+would do through WASI and prints what it observed.
+"""
+
+from __future__ import annotations
+
+import os
+import subprocess
+import sys
+from pathlib import Path
+
+import pytest
+
+pytestmark = pytest.mark.skipif(
+    sys.platform == "win32", reason="Requires POSIX *at calls"
+)
+
+DRIVER = """
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define ALL_RIGHTS ((uint64_t)0x1FFFFFFF)
+#define OFLAGS_CREAT 1
+#define OFLAGS_TRUNC 8
+#define FDFLAGS_APPEND 1
+#define FSTFLAGS_MTIM 4
+
+/* Runtime entry point of a wasi_snapshot_preview1 import */
+#define WASI(name) Z_wasi_snapshot_preview1Z_##name
+
+extern uint8_t *__wasm_memory;
+extern int32_t __wasm_memory_grow(int32_t);
+extern void __wasi_init(int argc, char **argv, char **environ);
+extern int __wasi_preopen(const char *host, const char *guest, int read_only);
+extern int32_t WASI(path_open)(int32_t, uint32_t, uint32_t, uint32_t, uint32_t,
+                               uint64_t, uint64_t, uint16_t, uint32_t);
+extern int32_t WASI(path_filestat_get)(int32_t, uint32_t, uint32_t, uint32_t,
+                                       uint32_t);
+extern int32_t WASI(path_filestat_set_times)(int32_t, uint32_t, uint32_t, uint32_t,
+                                             uint64_t, uint64_t, uint16_t);
+extern int32_t WASI(path_symlink)(uint32_t, uint32_t, int32_t, uint32_t, uint32_t);
+extern int32_t WASI(path_link)(int32_t, uint32_t, uint32_t, uint32_t, int32_t,
+                               uint32_t, uint32_t);
+extern int32_t WASI(path_readlink)(int32_t, uint32_t, uint32_t, uint32_t, uint32_t,
+                                   uint32_t);
+extern int32_t WASI(fd_close)(int32_t);
+extern int32_t WASI(fd_read)(int32_t, uint32_t, uint32_t, uint32_t);
+extern int32_t WASI(fd_write)(int32_t, uint32_t, uint32_t, uint32_t);
+extern int32_t WASI(fd_tell)(int32_t, uint32_t);
+extern int32_t WASI(fd_pread)(int32_t, uint32_t, uint32_t, uint64_t, uint32_t);
+extern int32_t WASI(fd_pwrite)(int32_t, uint32_t, uint32_t, uint64_t, uint32_t);
+extern int32_t WASI(fd_readdir)(int32_t, uint32_t, uint32_t, uint64_t, uint32_t);
+extern int32_t WASI(fd_renumber)(int32_t, int32_t);
+extern int32_t WASI(fd_fdstat_get)(int32_t, uint32_t);
+extern int32_t WASI(fd_prestat_get)(int32_t, uint32_t);
+extern int32_t WASI(fd_prestat_dir_name)(int32_t, uint32_t, uint32_t);
+extern int32_t WASI(fd_filestat_get)(int32_t, uint32_t);
+extern int32_t WASI(fd_filestat_set_size)(int32_t, uint64_t);
+extern int32_t WASI(fd_filestat_set_times)(int32_t, uint64_t, uint64_t, uint16_t);
+extern int32_t WASI(fd_advise)(int32_t, uint64_t, uint64_t, uint8_t);
+extern int32_t WASI(fd_allocate)(int32_t, uint64_t, uint64_t);
+extern int32_t WASI(fd_datasync)(int32_t);
+extern int32_t WASI(sched_yield)(void);
+extern int32_t WASI(proc_raise)(uint8_t);
+
+/* Memory layout: paths at 1024 and 2048, iovecs at 3000, results at 4096,
+ * data buffers from 8192 */
+#define M(at, type) (*(type *)(__wasm_memory + (at)))
+
+static uint32_t put_path(uint32_t at, const char *path) {
+    memcpy(__wasm_memory + at, path, strlen(path));
+    return (uint32_t)strlen(path);
+}
+
+static int32_t open_file(const char *path, uint32_t oflags, uint16_t fdflags) {
+    int32_t err = WASI(path_open)(3, 0, 1024, put_path(1024, path), oflags,
+                                  ALL_RIGHTS, ALL_RIGHTS, fdflags, 4096);
+    if (err) {
+        printf("open %s: %d\\n", path, err);
+        exit(1);
+    }
+    return M(4096, int32_t);
+}
+
+static void set_iovec(uint32_t buf, uint32_t len) {
+    M(3000, uint32_t) = buf;
+    M(3004, uint32_t) = len;
+}
+
+/* List the sandbox with a buffer too small for all entries */
+static void ls(void) {
+    uint64_t cookie = 0;
+    for (;;) {
+        uint32_t buf_len = 64;
+        if (WASI(fd_readdir)(3, 8192, buf_len, cookie, 4096)) return;
+        uint32_t used = M(4096, uint32_t);
+        uint32_t pos = 0;
+        while (pos + 24 <= used) {
+            /* Entries are packed, so fields may be unaligned */
+            uint32_t name_len;
+            memcpy(&name_len, __wasm_memory + 8192 + pos + 16, 4);
+            if (pos + 24 + name_len > used) break;
+            const char *name = (const char *)__wasm_memory + 8192 + pos + 24;
+            if (!(name_len == 1 && name[0] == '.') &&
+                !(name_len == 2 && name[0] == '.' && name[1] == '.')) {
+                int filetype = __wasm_memory[8192 + pos + 20];
+                printf("%.*s %d\\n", (int)name_len, name, filetype);
+            }
+            memcpy(&cookie, __wasm_memory + 8192 + pos, 8);
+            pos += 24 + name_len;
+        }
+        if (used < buf_len) return;
+    }
+}
+
+/* Copy file.txt to copy.txt, sizing the buffer from fd_filestat_get */
+static void cp(void) {
+    int32_t in = open_file("file.txt", 0, 0);
+    int32_t out = open_file("copy.txt", OFLAGS_CREAT | OFLAGS_TRUNC, 0);
+    WASI(fd_filestat_get)(in, 4096);
+    uint64_t size = M(4096 + 32, uint64_t);
+    set_iovec(8192, (uint32_t)size);
+    WASI(fd_read)(in, 3000, 1, 4096);
+    set_iovec(8192, M(4096, uint32_t));
+    WASI(fd_write)(out, 3000, 1, 4096);
+    printf("%" PRIu64 " %u\\n", size, M(4096, uint32_t));
+}
+
+/* Positional I/O leaves the file offset alone */
+static void positional(void) {
+    int32_t fd = open_file("file.txt", 0, 0);
+    set_iovec(8192, 3);
+    WASI(fd_pread)(fd, 3000, 1, 1, 4096);
+    printf("%.*s\\n", (int)M(4096, uint32_t), (const char *)__wasm_memory + 8192);
+    memcpy(__wasm_memory + 8192, "XY", 2);
+    set_iovec(8192, 2);
+    WASI(fd_pwrite)(fd, 3000, 1, 2, 4096);
+    WASI(fd_tell)(fd, 4096);
+    printf("%" PRIu64 "\\n", M(4096, uint64_t));
+}
+
+/* Bad iovecs and result pointers fail before any I/O */
+static void faults(void) {
+    int32_t fd = open_file("file.txt", 0, 0);
+    set_iovec(8192, 3);
+    printf("%d\\n", WASI(fd_pread)(fd, 3000, 4096, 0, 4096));
+    printf("%d\\n", WASI(fd_pread)(fd, 65532, 1, 0, 4096));
+    printf("%d\\n", WASI(fd_pread)(fd, 3000, 1, 0, 65534));
+    set_iovec(65535, 2);
+    printf("%d\\n", WASI(fd_pread)(fd, 3000, 1, 0, 4096));
+    set_iovec(0xFFFFFFFF, 2);
+    printf("%d\\n", WASI(fd_pwrite)(fd, 3000, 1, 0, 4096));
+    set_iovec(65000, 1000);
+    printf("%d\\n", WASI(fd_write)(fd, 3000, 1, 4096));
+    printf("%d\\n", WASI(fd_read)(fd, 3000, 1, 4096));
+}
+
+/* Buffers and results reaching past the end of memory fault; the file
+ * whose fd could not be stored is not left open */
+static void escapes(void) {
+    uint32_t len = put_path(1024, "file.txt");
+    WASI(path_symlink)(1024, len, 3, 2048, put_path(2048, "soft"));
+    printf("%d\\n", WASI(fd_readdir)(3, 65000, 1000, 0, 4096));
+    printf("%d\\n", WASI(fd_readdir)(3, 8192, 64, 0, 65534));
+    printf("%d\\n", WASI(path_readlink)(3, 2048, 4, 65530, 64, 4096));
+    printf("%d\\n", WASI(path_readlink)(3, 2048, 4, 8192, 64, 0xFFFFFFFF));
+    printf("%d\\n", WASI(fd_filestat_get)(3, 65500));
+    printf("%d\\n", WASI(path_filestat_get)(3, 0, 1024, len, 65500));
+    printf("%d\\n", WASI(fd_fdstat_get)(3, 65520));
+    printf("%d\\n", WASI(fd_prestat_get)(3, 65532));
+    printf("%d\\n", WASI(fd_prestat_dir_name)(3, 65535, 8));
+    printf("%d\\n", WASI(path_open)(3, 0, 1024, len, 0, ALL_RIGHTS, ALL_RIGHTS, 0,
+                                    65534));
+    printf("%d\\n", open_file("file.txt", 0, 0));
+}
+
+static void truncate_file(void) {
+    int32_t fd = open_file("file.txt", 0, 0);
+    printf("%d\\n", WASI(fd_filestat_set_size)(fd, 2));
+}
+
+/* Set mtime by path, then by fd on another file */
+static void touch(void) {
+    uint64_t mtim = 1000000000ULL * 1000000000ULL;
+    printf("%d\\n", WASI(path_filestat_set_times)(3, 0, 1024,
+                                                  put_path(1024, "file.txt"), 0,
+                                                  mtim, FSTFLAGS_MTIM));
+    int32_t fd = open_file("dir/inner.txt", 0, 0);
+    printf("%d\\n", WASI(fd_filestat_set_times)(fd, 0, mtim * 2, FSTFLAGS_MTIM));
+}
+
+static void ln(void) {
+    uint32_t len = put_path(1024, "file.txt");
+    printf("%d\\n", WASI(path_symlink)(1024, len, 3, 2048, put_path(2048, "soft")));
+    printf("%d\\n", WASI(path_readlink)(3, 2048, 4, 8192, 64, 4096));
+    printf("%.*s\\n", (int)M(4096, uint32_t), (const char *)__wasm_memory + 8192);
+    printf("%d\\n", WASI(path_link)(3, 0, 1024, len, 3, 2048, put_path(2048, "hard")));
+    WASI(path_filestat_get)(3, 0, 1024, len, 4096);
+    printf("%" PRIu64 "\\n", M(4096 + 24, uint64_t));
+    len = put_path(1024, "/etc/passwd");
+    printf("%d\\n", WASI(path_symlink)(1024, len, 3, 2048, put_path(2048, "abs")));
+}
+
+/* Move an open file onto another fd number */
+static void renumber(void) {
+    int32_t from = open_file("file.txt", 0, 0);
+    int32_t to = open_file("dir/inner.txt", 0, 0);
+    printf("%d\\n", WASI(fd_renumber)(from, to));
+    set_iovec(8192, 64);
+    WASI(fd_read)(to, 3000, 1, 4096);
+    printf("%.*s\\n", (int)M(4096, uint32_t), (const char *)__wasm_memory + 8192);
+    printf("%d\\n", WASI(fd_close)(from));
+}
+
+static void append(void) {
+    int32_t fd = open_file("file.txt", 0, FDFLAGS_APPEND);
+    WASI(fd_fdstat_get)(fd, 4096);
+    printf("%u\\n", M(4096 + 2, uint16_t));
+    memcpy(__wasm_memory + 8192, "+more", 5);
+    set_iovec(8192, 5);
+    printf("%d\\n", WASI(fd_write)(fd, 3000, 1, 4096));
+}
+
+static void misc(void) {
+    int32_t fd = open_file("file.txt", 0, 0);
+    printf("%d\\n", WASI(fd_allocate)(fd, 0, 100));
+    WASI(fd_filestat_get)(fd, 4096);
+    printf("%" PRIu64 "\\n", M(4096 + 32, uint64_t));
+    printf("%d\\n", WASI(fd_advise)(fd, 0, 0, 1));
+    printf("%d\\n", WASI(fd_advise)(fd, 0, 0, 9));
+    printf("%d\\n", WASI(fd_datasync)(fd));
+    printf("%d\\n", WASI(sched_yield)());
+    printf("%d\\n", WASI(proc_raise)(15));
+}
+
+/* usage: driver <sandbox> <command>; fd 3 is the sandbox */
+int main(int argc, char **argv) {
+    if (argc != 3) return 2;
+    __wasm_memory_grow(1);
+    __wasi_init(0, NULL, NULL);
+    if (__wasi_preopen(argv[1], "/", 0) != 3) return 2;
+    const char *cmd = argv[2];
+    if (!strcmp(cmd, "ls")) ls();
+    else if (!strcmp(cmd, "cp")) cp();
+    else if (!strcmp(cmd, "positional")) positional();
+    else if (!strcmp(cmd, "faults")) faults();
+    else if (!strcmp(cmd, "escapes")) escapes();
+    else if (!strcmp(cmd, "truncate")) truncate_file();
+    else if (!strcmp(cmd, "touch")) touch();
+    else if (!strcmp(cmd, "ln")) ln();
+    else if (!strcmp(cmd, "renumber")) renumber();
+    else if (!strcmp(cmd, "append")) append();
+    else if (!strcmp(cmd, "misc")) misc();
+    else return 2;
+    return 0;
+}
+"""
+
+SUCCESS = 0
+BADF = 8
+FAULT = 21
+INVAL = 28
+NOSYS = 52
+NOTCAPABLE = 76
+
+FILETYPE_DIRECTORY = 3
+FILETYPE_REGULAR_FILE = 4
+FILETYPE_SYMBOLIC_LINK = 7
+
+
+@pytest.fixture
+def sandbox(tmp_path):
+    """A preopened directory with a few files."""
+    root = tmp_path / "sandbox"
+    (root / "dir").mkdir(parents=True)
+    (root / "file.txt").write_text("file")
+    (root / "dir" / "inner.txt").write_text("inner")
+    return root
+
+
+def run_cmd(driver: Path, sandbox: Path, cmd: str) -> list[str]:
+    result = subprocess.run(
+        [str(driver), str(sandbox), cmd],
+        capture_output=True,
+        text=True,
+        check=True,
+    )
+    return result.stdout.splitlines()
+
+
+class TestFiles:
+    """Reading, writing and changing files through fds."""
+
+    def test_copy(self, driver, sandbox):
+        assert run_cmd(driver, sandbox, "cp") == ["4 4"]
+        assert (sandbox / "copy.txt").read_text() == "file"
+
+    def test_positional_io_keeps_offset(self, driver, sandbox):
+        assert run_cmd(driver, sandbox, "positional") == ["ile", "0"]
+        assert (sandbox / "file.txt").read_text() == "fiXY"
+
+    def test_bad_pointers(self, driver, sandbox):
+        # Too many iovecs, then ranges past the end of memory
+        assert run_cmd(driver, sandbox, "faults") == [str(INVAL)] + [str(FAULT)] * 6
+        assert (sandbox / "file.txt").read_text() == "file"
+
+    def test_results_past_memory(self, driver, sandbox):
+        # The fd of the refused path_open is free for the next file
+        assert run_cmd(driver, sandbox, "escapes") == [str(FAULT)] * 10 + ["4"]
+
+    def test_truncate(self, driver, sandbox):
+        assert run_cmd(driver, sandbox, "truncate") == [str(SUCCESS)]
+        assert (sandbox / "file.txt").read_text() == "fi"
+
+    def test_set_times(self, driver, sandbox):
+        assert run_cmd(driver, sandbox, "touch") == [str(SUCCESS), str(SUCCESS)]
+        assert (sandbox / "file.txt").stat().st_mtime == 1_000_000_000
+        assert (sandbox / "dir" / "inner.txt").stat().st_mtime == 2_000_000_000
+
+    def test_append_flag(self, driver, sandbox):
+        assert run_cmd(driver, sandbox, "append") == ["1", str(SUCCESS)]
+        assert (sandbox / "file.txt").read_text() == "file+more"
+
+    def test_renumber(self, driver, sandbox):
+        # The target now reads the first file and the old number is free
+        assert run_cmd(driver, sandbox, "renumber") == [
+            str(SUCCESS),
+            "file",
+            str(BADF),
+        ]
+
+    def test_allocate_advise_and_sync(self, driver, sandbox):
+        assert run_cmd(driver, sandbox, "misc") == [
+            str(SUCCESS),
+            "100",
+            str(SUCCESS),
+            str(INVAL),
+            str(SUCCESS),
+            str(SUCCESS),
+            str(NOSYS),
+        ]
+
+
+class TestDirectories:
+    """Listing directories and making links."""
+
+    def test_readdir_resumes_from_cookie(self, driver, sandbox):
+        for i in range(8):
+            (sandbox / f"entry-{i:02}.txt").write_text("")
+        lines = run_cmd(driver, sandbox, "ls")
+        expected = {f"entry-{i:02}.txt {FILETYPE_REGULAR_FILE}" for i in range(8)}
+        expected |= {
+            f"file.txt {FILETYPE_REGULAR_FILE}",
+            f"dir {FILETYPE_DIRECTORY}",
+        }
+        assert sorted(lines) == sorted(expected)
+
+    def test_readdir_reports_symlinks(self, driver, sandbox):
+        os.symlink("file.txt", sandbox / "link")
+        lines = run_cmd(driver, sandbox, "ls")
+        assert f"link {FILETYPE_SYMBOLIC_LINK}" in lines
+
+    def test_links(self, driver, sandbox):
+        assert run_cmd(driver, sandbox, "ln") == [
+            str(SUCCESS),
+            str(SUCCESS),
+            "file.txt",
+            str(SUCCESS),
+            "2",
+            str(NOTCAPABLE),
+        ]
+        assert os.readlink(sandbox / "soft") == "file.txt"
+        assert (sandbox / "hard").read_text() == "file"
+        assert not os.path.lexists(sandbox / "abs")
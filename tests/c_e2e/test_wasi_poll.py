@@ -0,0 +1,243 @@
+"""End-to-end tests for WASI poll_oneoff.
+
+A C driver linked against the runtime builds subscriptions in linear memory,
+calls poll_oneoff like wasi-libc's sleep() and select() do, and prints the
+events written back.
+"""
+
+from __future__ import annotations
+
+import subprocess
+import sys
+import time
+
+import pytest
+
+pytestmark = pytest.mark.skipif(sys.platform == "win32", reason="Requires poll(2)")
+
+DRIVER = """
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define CLOCK 0
+#define FD_READ 1
+#define FD_WRITE 2
+#define MONOTONIC 1
+#define ABSTIME 1
+#define MS 1000000ULL
+
+/* Runtime entry point of a wasi_snapshot_preview1 import */
+#define WASI(name) Z_wasi_snapshot_preview1Z_##name
+
+extern uint8_t *__wasm_memory;
+extern int32_t __wasm_memory_grow(int32_t);
+extern void __wasi_init(int argc, char **argv, char **environ);
+extern int32_t WASI(poll_oneoff)(uint32_t, uint32_t, uint32_t, uint32_t);
+extern int32_t WASI(clock_time_get)(uint32_t, uint64_t, uint32_t);
+
+/* Subscriptions from 1024, events from 8192 unless a command moves them,
+ * the event count at 4096 */
+static uint32_t nsubs;
+static uint32_t events_at = 8192;
+
+static uint8_t *subscribe(uint64_t userdata, uint8_t tag) {
+    uint8_t *sub = __wasm_memory + 1024 + nsubs++ * 48;
+    memcpy(sub, &userdata, 8);
+    sub[8] = tag;
+    return sub;
+}
+
+static void clock_sub(uint64_t userdata, uint32_t id, uint64_t timeout,
+                      uint16_t flags) {
+    uint8_t *sub = subscribe(userdata, CLOCK);
+    memcpy(sub + 16, &id, 4);
+    memcpy(sub + 24, &timeout, 8);
+    memcpy(sub + 40, &flags, 2);
+}
+
+static void fd_sub(uint64_t userdata, uint8_t tag, int32_t fd) {
+    memcpy(subscribe(userdata, tag) + 16, &fd, 4);
+}
+
+static uint64_t now(void) {
+    WASI(clock_time_get)(MONOTONIC, 0, 4000);
+    return *(uint64_t *)(__wasm_memory + 4000);
+}
+
+/* Print the errno, then one line per event: userdata type error nbytes flags */
+static void poll_and_print(void) {
+    int32_t err = WASI(poll_oneoff)(1024, events_at, nsubs, 4096);
+    printf("%d\\n", err);
+    if (err) return;
+    uint32_t nevents = *(uint32_t *)(__wasm_memory + 4096);
+    for (uint32_t i = 0; i < nevents; i++) {
+        uint8_t *event = __wasm_memory + 8192 + i * 32;
+        uint64_t userdata, nbytes;
+        uint16_t error, flags;
+        memcpy(&userdata, event, 8);
+        memcpy(&error, event + 8, 2);
+        memcpy(&nbytes, event + 16, 8);
+        memcpy(&flags, event + 24, 2);
+        printf("%" PRIu64 " %d %d %" PRIu64 " %d\\n", userdata, event[10], error,
+               nbytes, flags);
+    }
+}
+
+/* usage: driver <command>; prints the elapsed milliseconds last */
+int main(int argc, char **argv) {
+    if (argc != 2) return 2;
+    __wasm_memory_grow(1);
+    __wasi_init(0, NULL, NULL);
+    const char *cmd = argv[1];
+    uint64_t start = now();
+    if (!strcmp(cmd, "sleep")) {
+        clock_sub(7, MONOTONIC, 50 * MS, 0);
+    } else if (!strcmp(cmd, "sleep-abs")) {
+        clock_sub(7, MONOTONIC, start + 50 * MS, ABSTIME);
+    } else if (!strcmp(cmd, "sleep-past")) {
+        clock_sub(7, MONOTONIC, start - 50 * MS, ABSTIME);
+    } else if (!strcmp(cmd, "two-clocks")) {
+        clock_sub(1, MONOTONIC, 2000 * MS, 0);
+        clock_sub(2, MONOTONIC, 20 * MS, 0);
+    } else if (!strcmp(cmd, "bad-clock")) {
+        clock_sub(1, MONOTONIC, 2000 * MS, 0);
+        clock_sub(2, 99, 0, 0);
+    } else if (!strcmp(cmd, "stdin")) {
+        fd_sub(1, FD_READ, 0);
+        clock_sub(2, MONOTONIC, 200 * MS, 0);
+    } else if (!strcmp(cmd, "mixed")) {
+        clock_sub(1, MONOTONIC, 2000 * MS, 0);
+        fd_sub(2, FD_READ, 0);
+        fd_sub(3, FD_WRITE, 1);
+        fd_sub(4, FD_READ, 99);
+    } else if (!strcmp(cmd, "bad-tag")) {
+        subscribe(1, 9);
+    } else if (!strcmp(cmd, "too-many")) {
+        clock_sub(1, MONOTONIC, 0, 0);
+        nsubs = 0x10000000;
+    } else if (!strcmp(cmd, "events-past-end")) {
+        clock_sub(1, MONOTONIC, 0, 0);
+        events_at = 65536 - 16;
+    } else if (strcmp(cmd, "empty")) {
+        return 2;
+    }
+    poll_and_print();
+    printf("%" PRIu64 "\\n", (uint64_t)((now() - start) / MS));
+    return 0;
+}
+"""
+
+SUCCESS = 0
+BADF = 8
+FAULT = 21
+INVAL = 28
+
+CLOCK = 0
+FD_READ = 1
+FD_WRITE = 2
+HANGUP = 1
+
+
+def run_poll(driver, cmd: str, stdin: bytes | None = None) -> tuple[list, int]:
+    """Run a command, returning the errno and events, and the elapsed time.
+
+    Without stdin data, the driver's stdin is a pipe that stays open (and
+    empty) until the driver exits.
+    """
+    proc = subprocess.Popen(
+        [str(driver), cmd], stdin=subprocess.PIPE, stdout=subprocess.PIPE
+    )
+    if stdin is not None:
+        proc.stdin.write(stdin)
+        proc.stdin.flush()
+        if stdin == b"":
+            proc.stdin.close()
+    output = proc.stdout.read().decode()
+    if not proc.stdin.closed:
+        proc.stdin.close()
+    assert proc.wait(timeout=10) == 0
+    lines = output.splitlines()
+    events = [tuple(int(field) for field in line.split()) for line in lines[:-1]]
+    return events, int(lines[-1])
+
+
+class TestClocks:
+    """Clock subscriptions, as used by sleep() and nanosleep()."""
+
+    def test_relative_sleep(self, driver):
+        events, elapsed = run_poll(driver, "sleep")
+        assert events == [(SUCCESS,), (7, CLOCK, SUCCESS, 0, 0)]
+        assert elapsed >= 50
+
+    def test_absolute_sleep(self, driver):
+        events, elapsed = run_poll(driver, "sleep-abs")
+        assert events == [(SUCCESS,), (7, CLOCK, SUCCESS, 0, 0)]
+        assert elapsed >= 50
+
+    def test_deadline_in_the_past(self, driver):
+        events, elapsed = run_poll(driver, "sleep-past")
+        assert events == [(SUCCESS,), (7, CLOCK, SUCCESS, 0, 0)]
+        assert elapsed < 50
+
+    def test_earliest_clock_fires(self, driver):
+        events, elapsed = run_poll(driver, "two-clocks")
+        assert events == [(SUCCESS,), (2, CLOCK, SUCCESS, 0, 0)]
+        assert 20 <= elapsed < 2000
+
+    def test_invalid_clock_reported_without_waiting(self, driver):
+        events, elapsed = run_poll(driver, "bad-clock")
+        assert events == [(SUCCESS,), (2, CLOCK, INVAL, 0, 0)]
+        assert elapsed < 2000
+
+
+class TestFds:
+    """Fd readiness through poll(2)."""
+
+    def test_stdin_ready(self, driver):
+        events, elapsed = run_poll(driver, "stdin", stdin=b"hello")
+        assert events == [(SUCCESS,), (1, FD_READ, SUCCESS, 5, 0)]
+        assert elapsed < 200
+
+    def test_stdin_times_out(self, driver):
+        events, elapsed = run_poll(driver, "stdin")
+        assert events == [(SUCCESS,), (2, CLOCK, SUCCESS, 0, 0)]
+        assert elapsed >= 200
+
+    def test_stdin_hangup(self, driver):
+        events, _ = run_poll(driver, "stdin", stdin=b"")
+        assert events[0] == (SUCCESS,)
+        userdata, type_, error, nbytes, flags = events[1]
+        assert (userdata, type_, error, nbytes) == (1, FD_READ, SUCCESS, 0)
+        assert flags & HANGUP
+
+    def test_mixed_subscriptions(self, driver):
+        # stdout is writable and fd 99 is not open; the clock and the empty
+        # stdin do not fire
+        start = time.monotonic()
+        events, _ = run_poll(driver, "mixed")
+        assert time.monotonic() - start < 2
+        assert events[0] == (SUCCESS,)
+        assert sorted(events[1:]) == [
+            (3, FD_WRITE, SUCCESS, 0, 0),
+            (4, FD_READ, BADF, 0, 0),
+        ]
+
+
+class TestErrors:
+    """Calls that poll_oneoff rejects."""
+
+    def test_no_subscriptions(self, driver):
+        events, _ = run_poll(driver, "empty")
+        assert events == [(INVAL,)]
+
+    def test_unknown_subscription_type(self, driver):
+        events, _ = run_poll(driver, "bad-tag")
+        assert events == [(INVAL,)]
+
+    @pytest.mark.parametrize("cmd", ["too-many", "events-past-end"])
+    def test_outside_memory(self, driver, cmd):
+        events, _ = run_poll(driver, cmd)
+        assert events == [(FAULT,)]
@@ -0,0 +1,245 @@
+"""End-to-end tests for WASI path sandboxing and rights.
+
+These tests link a small C driver against the runtime and call the WASI
+functions the way compiled code does (paths in linear memory), checking that
+paths cannot leave a preopened directory and that rights are enforced.
+"""
+
+from __future__ import annotations
+
+import os
+import subprocess
+import sys
+from pathlib import Path
+
+import pytest
+
+pytestmark = pytest.mark.skipif(
+    sys.platform == "win32", reason="Requires POSIX *at calls"
+)
+
+DRIVER = """
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define FD_READ (1 << 1)
+#define FD_WRITE (1 << 6)
+#define OFLAGS_CREAT 1
+#define SYMLINK_FOLLOW 1
+
+/* Runtime entry point of a wasi_snapshot_preview1 import */
+#define WASI(name) Z_wasi_snapshot_preview1Z_##name
+
+extern uint8_t *__wasm_memory;
+extern int32_t __wasm_memory_grow(int32_t);
+extern void __wasi_init(int argc, char **argv, char **environ);
+extern int __wasi_preopen(const char *host, const char *guest, int read_only);
+extern int32_t WASI(path_open)(int32_t, uint32_t, uint32_t, uint32_t, uint32_t,
+                                uint64_t, uint64_t, uint16_t, uint32_t);
+extern int32_t WASI(path_create_directory)(int32_t, uint32_t, uint32_t);
+extern int32_t WASI(path_unlink_file)(int32_t, uint32_t, uint32_t);
+extern int32_t WASI(path_rename)(int32_t, uint32_t, uint32_t,
+                                  int32_t, uint32_t, uint32_t);
+extern int32_t WASI(path_filestat_get)(int32_t, uint32_t, uint32_t, uint32_t,
+                                        uint32_t);
+extern int32_t WASI(path_symlink)(uint32_t, uint32_t, int32_t, uint32_t, uint32_t);
+extern int32_t WASI(fd_write)(int32_t, uint32_t, uint32_t, uint32_t);
+
+/* Paths live at 1024 and 2048, results from 4096 */
+static uint32_t put_path(uint32_t at, const char *path) {
+    memcpy(__wasm_memory + at, path, strlen(path));
+    return (uint32_t)strlen(path);
+}
+
+static int open_path(int fd, uint32_t lookup, const char *path, uint32_t oflags,
+                     uint64_t rights) {
+    uint32_t len = put_path(1024, path);
+    return WASI(path_open)(fd, lookup, 1024, len, oflags, rights, rights, 0, 4096);
+}
+
+/* Write one byte through a file opened for reading only */
+static int write_read_only(const char *path) {
+    int err = open_path(3, 0, path, 0, FD_READ);
+    if (err) return err;
+    uint32_t iov[2] = {1024, 1};
+    memcpy(__wasm_memory + 3000, iov, sizeof(iov));
+    return WASI(fd_write)(*(int32_t *)(__wasm_memory + 4096), 3000, 1, 4100);
+}
+
+static int run(const char *op, const char *path) {
+    if (!strcmp(op, "open")) return open_path(3, 0, path, 0, FD_READ);
+    if (!strcmp(op, "follow")) return open_path(3, SYMLINK_FOLLOW, path, 0, FD_READ);
+    if (!strcmp(op, "create")) return open_path(3, 0, path, OFLAGS_CREAT, FD_WRITE);
+    if (!strcmp(op, "ro-open")) return open_path(4, 0, path, 0, FD_READ);
+    if (!strcmp(op, "ro-create")) return open_path(4, 0, path, OFLAGS_CREAT, FD_READ);
+    if (!strcmp(op, "ro-write")) return open_path(4, 0, path, 0, FD_WRITE);
+    if (!strcmp(op, "write-read-only")) return write_read_only(path);
+    if (!strcmp(op, "mkdir"))
+        return WASI(path_create_directory)(3, 1024, put_path(1024, path));
+    if (!strcmp(op, "unlink"))
+        return WASI(path_unlink_file)(3, 1024, put_path(1024, path));
+    if (!strcmp(op, "lstat"))
+        return WASI(path_filestat_get)(3, 0, 1024, put_path(1024, path), 4096);
+    if (!strcmp(op, "stat"))
+        return WASI(path_filestat_get)(3, SYMLINK_FOLLOW, 1024,
+                                       put_path(1024, path), 4096);
+    /* Paths of the given length starting 4 bytes before the end of memory */
+    if (!strcmp(op, "open-past-end"))
+        return WASI(path_open)(3, 0, 65532, (uint32_t)strlen(path), 0, FD_READ,
+                               FD_READ, 0, 4096);
+    if (!strcmp(op, "symlink-past-end"))
+        return WASI(path_symlink)(65532, (uint32_t)strlen(path), 3, 2048,
+                                  put_path(2048, "link"));
+    if (!strcmp(op, "rename")) {
+        const char *to = strchr(path, '>');
+        uint32_t old_len = (uint32_t)(to - path);
+        memcpy(__wasm_memory + 1024, path, old_len);
+        return WASI(path_rename)(3, 1024, old_len, 3, 2048, put_path(2048, to + 1));
+    }
+    return -1;
+}
+
+/* usage: driver <sandbox> <op> <path>; fd 3 is the sandbox, fd 4 a read-only
+ * view of it. Prints the WASI errno. */
+int main(int argc, char **argv) {
+    if (argc != 4) return 2;
+    __wasm_memory_grow(1);
+    __wasi_init(0, NULL, NULL);
+    if (__wasi_preopen(argv[1], "/", 0) != 3) return 2;
+    if (__wasi_preopen(argv[1], "/ro", 1) != 4) return 2;
+    printf("%d\\n", run(argv[2], argv[3]));
+    return 0;
+}
+"""
+
+SUCCESS = 0
+FAULT = 21
+LOOP = 32
+NOENT = 44
+NOTCAPABLE = 76
+
+
+@pytest.fixture
+def sandbox(tmp_path):
+    """A preopened directory, with a secret file next to it."""
+    outside = tmp_path / "outside"
+    outside.mkdir()
+    (outside / "secret.txt").write_text("secret")
+    root = tmp_path / "sandbox"
+    (root / "dir").mkdir(parents=True)
+    (root / "file.txt").write_text("file")
+    (root / "dir" / "inner.txt").write_text("inner")
+    os.symlink("../outside", root / "up")
+    os.symlink("/etc", root / "abs")
+    os.symlink("loop", root / "loop")
+    os.symlink("dir/inner.txt", root / "inside")
+    os.symlink("../file.txt", root / "dir" / "back")
+    os.symlink("../../outside/secret.txt", root / "dir" / "escape")
+    return root
+
+
+def run_op(driver: Path, sandbox: Path, op: str, path: str) -> int:
+    result = subprocess.run(
+        [str(driver), str(sandbox), op, path],
+        capture_output=True,
+        text=True,
+        check=True,
+    )
+    return int(result.stdout)
+
+
+class TestPathConfinement:
+    """Paths resolve beneath their directory fd and never above it."""
+
+    @pytest.mark.parametrize(
+        "path",
+        ["file.txt", "./file.txt", "dir/../file.txt", "dir//inner.txt", "dir/.."],
+    )
+    def test_paths_inside(self, driver, sandbox, path):
+        assert run_op(driver, sandbox, "open", path) == SUCCESS
+
+    @pytest.mark.parametrize(
+        "path",
+        [
+            "..",
+            "../outside/secret.txt",
+            "dir/../../outside/secret.txt",
+            "/etc/passwd",
+            "//etc/passwd",
+        ],
+    )
+    def test_dot_dot_and_absolute_paths_refused(self, driver, sandbox, path):
+        assert run_op(driver, sandbox, "open", path) == NOTCAPABLE
+
+    @pytest.mark.parametrize("path", ["up/secret.txt", "abs/passwd"])
+    def test_symlinked_directories_refused(self, driver, sandbox, path):
+        assert run_op(driver, sandbox, "open", path) == NOTCAPABLE
+
+    @pytest.mark.parametrize("path", ["dir/escape", "up", "abs"])
+    def test_followed_symlinks_refused(self, driver, sandbox, path):
+        assert run_op(driver, sandbox, "follow", path) == NOTCAPABLE
+
+    @pytest.mark.parametrize("path", ["inside", "dir/back"])
+    def test_symlinks_inside(self, driver, sandbox, path):
+        assert run_op(driver, sandbox, "follow", path) == SUCCESS
+
+    def test_symlink_not_followed_without_lookup_flag(self, driver, sandbox):
+        assert run_op(driver, sandbox, "open", "inside") == LOOP
+
+    def test_symlink_loop(self, driver, sandbox):
+        assert run_op(driver, sandbox, "follow", "loop") == LOOP
+
+    def test_missing_file(self, driver, sandbox):
+        assert run_op(driver, sandbox, "open", "missing.txt") == NOENT
+
+    def test_create_outside_refused(self, driver, sandbox):
+        assert run_op(driver, sandbox, "create", "up/new.txt") == NOTCAPABLE
+        assert not (sandbox.parent / "outside" / "new.txt").exists()
+        assert run_op(driver, sandbox, "create", "new.txt") == SUCCESS
+        assert (sandbox / "new.txt").exists()
+
+    def test_mkdir_outside_refused(self, driver, sandbox):
+        assert run_op(driver, sandbox, "mkdir", "../made") == NOTCAPABLE
+        assert run_op(driver, sandbox, "mkdir", "up/made") == NOTCAPABLE
+        assert not (sandbox.parent / "made").exists()
+        assert not (sandbox.parent / "outside" / "made").exists()
+
+    def test_unlink_outside_refused(self, driver, sandbox):
+        assert run_op(driver, sandbox, "unlink", "up/secret.txt") == NOTCAPABLE
+        assert (sandbox.parent / "outside" / "secret.txt").exists()
+        # The symlink itself lives in the sandbox
+        assert run_op(driver, sandbox, "unlink", "dir/escape") == SUCCESS
+        assert (sandbox.parent / "outside" / "secret.txt").exists()
+
+    def test_rename_outside_refused(self, driver, sandbox):
+        result = run_op(driver, sandbox, "rename", "file.txt>../stolen.txt")
+        assert result == NOTCAPABLE
+        result = run_op(driver, sandbox, "rename", "up/secret.txt>stolen.txt")
+        assert result == NOTCAPABLE
+        assert (sandbox / "file.txt").exists()
+        assert (sandbox.parent / "outside" / "secret.txt").exists()
+
+    def test_stat_of_escaping_symlink(self, driver, sandbox):
+        assert run_op(driver, sandbox, "lstat", "up") == SUCCESS
+        assert run_op(driver, sandbox, "stat", "up") == NOTCAPABLE
+
+    def test_paths_past_memory_fault(self, driver, sandbox):
+        assert run_op(driver, sandbox, "open-past-end", "file.txt") == FAULT
+        assert run_op(driver, sandbox, "symlink-past-end", "file.txt") == FAULT
+        assert not (sandbox / "link").exists()
+
+
+class TestRights:
+    """Operations need the rights of the fd they go through."""
+
+    def test_read_only_directory(self, driver, sandbox):
+        assert run_op(driver, sandbox, "ro-open", "file.txt") == SUCCESS
+        assert run_op(driver, sandbox, "ro-create", "new.txt") == NOTCAPABLE
+        assert run_op(driver, sandbox, "ro-write", "file.txt") == NOTCAPABLE
+        assert not (sandbox / "new.txt").exists()
+
+    def test_write_needs_write_right(self, driver, sandbox):
+        assert run_op(driver, sandbox, "write-read-only", "file.txt") == NOTCAPABLE
+        assert (sandbox / "file.txt").read_text() == "file"
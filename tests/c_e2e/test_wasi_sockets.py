@@ -0,0 +1,228 @@
+"""End-to-end tests for WASI sockets on localhost.
+
+A C driver linked against the runtime listens with __wasi_listen (what
+`--listen` compiles to) and serves one connection through sock_accept,
+sock_recv, sock_send and sock_shutdown; the test is the client.
+"""
+
+from __future__ import annotations
+
+import socket
+import subprocess
+import sys
+import time
+
+import pytest
+
+pytestmark = pytest.mark.skipif(sys.platform == "win32", reason="Requires BSD sockets")
+
+DRIVER = """
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#define FD_READ 1
+#define FDFLAGS_NONBLOCK 4
+#define RECV_PEEK 1
+#define SDFLAGS_WR 2
+#define SOCK_ACCEPT_RIGHT ((uint64_t)1 << 29)
+#define SOCK_SHUTDOWN_RIGHT ((uint64_t)1 << 28)
+
+/* Runtime entry point of a wasi_snapshot_preview1 import */
+#define WASI(name) Z_wasi_snapshot_preview1Z_##name
+
+extern uint8_t *__wasm_memory;
+extern int32_t __wasm_memory_grow(int32_t);
+extern void __wasi_init(int argc, char **argv, char **environ);
+extern int __wasi_listen(const char *address);
+extern int32_t WASI(sock_accept)(int32_t, uint16_t, uint32_t);
+extern int32_t WASI(sock_recv)(int32_t, uint32_t, uint32_t, uint16_t, uint32_t,
+                               uint32_t);
+extern int32_t WASI(sock_send)(int32_t, uint32_t, uint32_t, uint16_t, uint32_t);
+extern int32_t WASI(sock_shutdown)(int32_t, uint8_t);
+extern int32_t WASI(fd_fdstat_get)(int32_t, uint32_t);
+extern int32_t WASI(fd_fdstat_set_flags)(int32_t, uint16_t);
+extern int32_t WASI(poll_oneoff)(uint32_t, uint32_t, uint32_t, uint32_t);
+
+#define M(at, type) (*(type *)(__wasm_memory + (at)))
+
+/* Receive into 8192 through the iovec at 3000; prints the errno and data */
+static uint32_t recv_print(int32_t fd, uint16_t flags) {
+    M(3000, uint32_t) = 8192;
+    M(3004, uint32_t) = 64;
+    int32_t err = WASI(sock_recv)(fd, 3000, 1, flags, 4096, 4100);
+    uint32_t n = err ? 0 : M(4096, uint32_t);
+    printf("recv %d %.*s\\n", err, (int)n, (const char *)__wasm_memory + 8192);
+    return n;
+}
+
+/* Wait for a connection, then echo one message back and close the write side */
+static void echo(void) {
+    /* One fd_read subscription on the listening socket */
+    M(1024, uint64_t) = 1;
+    __wasm_memory[1024 + 8] = FD_READ;
+    M(1024 + 16, uint32_t) = 3;
+    int32_t err = WASI(poll_oneoff)(1024, 2048, 1, 4096);
+    printf("poll %d %d\\n", err, __wasm_memory[2048 + 10]);
+
+    /* A result past the end of memory leaves the connection pending */
+    printf("accept-fault %d\\n", WASI(sock_accept)(3, 0, 65534));
+    err = WASI(sock_accept)(3, 0, 4096);
+    int32_t conn = M(4096, int32_t);
+    printf("accept %d %d\\n", err, conn);
+    WASI(fd_fdstat_get)(conn, 4096);
+    uint64_t rights = M(4096 + 8, uint64_t);
+    printf("fdstat %d %d %d\\n", __wasm_memory[4096],
+           (rights & SOCK_SHUTDOWN_RIGHT) != 0, (rights & SOCK_ACCEPT_RIGHT) != 0);
+
+    recv_print(conn, RECV_PEEK);
+    uint32_t n = recv_print(conn, 0);
+    M(3004, uint32_t) = n;
+    err = WASI(sock_send)(conn, 3000, 1, 0, 4096);
+    printf("send %d %u\\n", err, M(4096, uint32_t));
+    printf("shutdown %d\\n", WASI(sock_shutdown)(conn, SDFLAGS_WR));
+    printf("bad-shutdown %d\\n", WASI(sock_shutdown)(conn, 0));
+    /* Bad iovecs and result pointers fail before any I/O */
+    printf("recv-faults %d", WASI(sock_recv)(conn, 3000, 4096, 0, 4096, 4100));
+    printf(" %d", WASI(sock_recv)(conn, 65532, 1, 0, 4096, 4100));
+    printf(" %d\\n", WASI(sock_recv)(conn, 3000, 1, 0, 4096, 65535));
+    printf("send-faults %d", WASI(sock_send)(conn, 3000, 4096, 0, 4096));
+    printf(" %d\\n", WASI(sock_send)(conn, 3000, 1, 0, 65534));
+    /* The listening socket can only accept, and stdin cannot accept */
+    printf("recv-listener %d\\n", WASI(sock_recv)(3, 3000, 1, 0, 4096, 4100));
+    printf("accept-stdin %d\\n", WASI(sock_accept)(0, 0, 4096));
+}
+
+/* usage: driver <address> <command>; prints what each call returned */
+int main(int argc, char **argv) {
+    if (argc != 3) return 2;
+    __wasm_memory_grow(1);
+    __wasi_init(0, NULL, NULL);
+    int fd = __wasi_listen(argv[1]);
+    printf("listen %d\\n", fd);
+    fflush(stdout);
+    if (fd < 0) return 0;
+    if (!strcmp(argv[2], "echo")) {
+        echo();
+    } else if (!strcmp(argv[2], "nonblocking")) {
+        printf("%d\\n", WASI(fd_fdstat_set_flags)(3, FDFLAGS_NONBLOCK));
+        printf("%d\\n", WASI(sock_accept)(3, 0, 4096));
+    } else {
+        return 2;
+    }
+    return 0;
+}
+"""
+
+SUCCESS = 0
+AGAIN = 6
+FAULT = 21
+INVAL = 28
+NOTCAPABLE = 76
+
+FD_READ = 1
+FILETYPE_SOCKET_STREAM = 6
+
+
+def free_port() -> int:
+    with socket.socket() as sock:
+        sock.bind(("127.0.0.1", 0))
+        return sock.getsockname()[1]
+
+
+def connect(port: int) -> socket.socket:
+    """Connect to the driver once it listens."""
+    deadline = time.monotonic() + 5
+    while True:
+        try:
+            return socket.create_connection(("127.0.0.1", port), timeout=5)
+        except ConnectionRefusedError:
+            if time.monotonic() > deadline:
+                raise
+            time.sleep(0.01)
+
+
+def run_driver(driver, address: str, cmd: str) -> list[str]:
+    result = subprocess.run(
+        [str(driver), address, cmd], capture_output=True, text=True, timeout=10
+    )
+    assert result.returncode == 0
+    return result.stdout.splitlines()
+
+
+class TestListen:
+    """Sockets preopened by __wasi_listen."""
+
+    def test_first_socket_is_fd_3(self, driver):
+        lines = run_driver(driver, f"127.0.0.1:{free_port()}", "nonblocking")
+        assert lines == ["listen 3", str(SUCCESS), str(AGAIN)]
+
+    @pytest.mark.parametrize("address", ["no-port", "127.0.0.1:no-such-service"])
+    def test_bad_address(self, driver, address):
+        assert run_driver(driver, address, "echo") == ["listen -1"]
+
+    def test_address_in_use(self, driver):
+        with socket.socket() as sock:
+            sock.bind(("127.0.0.1", 0))
+            sock.listen()
+            address = f"127.0.0.1:{sock.getsockname()[1]}"
+            assert run_driver(driver, address, "echo") == ["listen -1"]
+
+
+class TestConnection:
+    """Serving a client on localhost."""
+
+    def test_echo(self, driver):
+        port = free_port()
+        proc = subprocess.Popen(
+            [str(driver), f"127.0.0.1:{port}", "echo"],
+            stdout=subprocess.PIPE,
+            text=True,
+        )
+        try:
+            with connect(port) as client:
+                client.sendall(b"hello")
+                received = b""
+                while chunk := client.recv(64):
+                    received += chunk
+            output, _ = proc.communicate(timeout=10)
+        finally:
+            proc.kill()
+        assert received == b"hello"
+        assert output.splitlines() == [
+            "listen 3",
+            f"poll {SUCCESS} {FD_READ}",
+            f"accept-fault {FAULT}",
+            f"accept {SUCCESS} 4",
+            f"fdstat {FILETYPE_SOCKET_STREAM} 1 0",
+            f"recv {SUCCESS} hello",
+            f"recv {SUCCESS} hello",
+            f"send {SUCCESS} 5",
+            f"shutdown {SUCCESS}",
+            f"bad-shutdown {INVAL}",
+            f"recv-faults {INVAL} {FAULT} {FAULT}",
+            f"send-faults {INVAL} {FAULT}",
+            f"recv-listener {NOTCAPABLE}",
+            f"accept-stdin {NOTCAPABLE}",
+        ]
+
+    def test_ipv6_loopback(self, driver):
+        if not socket.has_ipv6:
+            pytest.skip("No IPv6 support")
+        port = free_port()
+        proc = subprocess.Popen(
+            [str(driver), f"[::1]:{port}", "echo"],
+            stdout=subprocess.PIPE,
+            text=True,
+        )
+        try:
+            first = proc.stdout.readline()
+            if first != "listen 3\n":
+                pytest.skip("IPv6 loopback unavailable")
+            with socket.create_connection(("::1", port), timeout=5) as client:
+                client.sendall(b"six")
+                assert client.recv(64) == b"six"
+            proc.communicate(timeout=10)
+        finally:
+            proc.kill()
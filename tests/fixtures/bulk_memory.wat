@@ -0,0 +1,15 @@
+;; Test memory.init, memory.copy and memory.fill: "bc" of the segment is
+;; copied to 8, moved to 16 and followed by a byte filled with 41, so
+;; ('c' - 'b') + 41 = 42
+(module
+  (memory 1)
+  (data $d "abcd")
+  (func $main (export "wasm_main") (result i32)
+    (memory.init $d (i32.const 8) (i32.const 1) (i32.const 2))
+    (memory.copy (i32.const 16) (i32.const 8) (i32.const 2))
+    (memory.fill (i32.const 18) (i32.const 41) (i32.const 1))
+    (i32.add
+      (i32.sub (i32.load8_u (i32.const 17)) (i32.load8_u (i32.const 16)))
+      (i32.load8_u (i32.const 18)))
+  )
+)
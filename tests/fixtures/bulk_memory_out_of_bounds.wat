@@ -0,0 +1,10 @@
+;; Test that memory.init traps when the source offset, read as unsigned,
+;; lies past the end of the segment
+(module
+  (memory 1)
+  (data $d "abcd")
+  (func $main (export "wasm_main") (result i32)
+    (memory.init $d (i32.const 8) (i32.const -1) (i32.const 1))
+    (i32.const 0)
+  )
+)
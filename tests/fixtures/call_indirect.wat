@@ -0,0 +1,16 @@
+;; Test call_indirect: dispatch through the table with matching signatures
+(module
+  (type $binop (func (param i32 i32) (result i32)))
+  (table 2 funcref)
+  (elem (i32.const 0) $add $mul)
+
+  (func $add (type $binop) (i32.add (local.get 0) (local.get 1)))
+  (func $mul (type $binop) (i32.mul (local.get 0) (local.get 1)))
+
+  (func $main (export "wasm_main") (result i32)
+    ;; add(4, 5) + mul(3, 11) = 9 + 33 = 42
+    (i32.add
+      (call_indirect (type $binop) (i32.const 4) (i32.const 5) (i32.const 0))
+      (call_indirect (type $binop) (i32.const 3) (i32.const 11) (i32.const 1)))
+  )
+)
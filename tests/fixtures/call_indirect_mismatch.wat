@@ -0,0 +1,14 @@
+;; Test call_indirect traps when the entry's signature does not match
+(module
+  (type $unary (func (param i32) (result i32)))
+  (type $nullary (func (result i32)))
+  (table 1 funcref)
+  (elem (i32.const 0) $negate)
+
+  (func $negate (type $unary) (i32.sub (i32.const 0) (local.get 0)))
+
+  (func $main (export "wasm_main") (result i32)
+    ;; $negate takes a parameter, so this must trap
+    (call_indirect (type $nullary) (i32.const 0))
+  )
+)
@@ -0,0 +1,12 @@
+;; Test call_indirect traps on an index past the end of the table
+(module
+  (type $nullary (func (result i32)))
+  (table 1 funcref)
+  (elem (i32.const 0) $one)
+
+  (func $one (type $nullary) (i32.const 1))
+
+  (func $main (export "wasm_main") (result i32)
+    (call_indirect (type $nullary) (i32.const 1000))
+  )
+)
@@ -0,0 +1,9 @@
+;; Test that instantiation traps when an active data segment ends past
+;; the end of its memory
+(module
+  (memory 1)
+  (data (i32.const 65535) "ab")
+  (func $main (export "wasm_main") (result i32)
+    (i32.const 0)
+  )
+)
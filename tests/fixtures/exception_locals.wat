@@ -0,0 +1,21 @@
+;; Test locals across a throw: a local set in the try body before a call
+;; that throws keeps its value in the catch clause, since longjmp would
+;; restore a local held in a register
+(module
+  (tag $e)
+
+  (func $raise (throw $e))
+
+  (func $main (export "wasm_main") (result i32)
+    (local $x i32)
+    (local.set $x (i32.const 1))
+    (try
+      (do
+        (local.set $x (i32.const 40))
+        (call $raise))
+      (catch $e
+        ;; 40 + 2 = 42
+        (local.set $x (i32.add (local.get $x) (i32.const 2)))))
+    (local.get $x)
+  )
+)
@@ -0,0 +1,8 @@
+;; Test that an exception with no handler traps
+(module
+  (tag $e (param i32))
+
+  (func $main (export "wasm_main") (result i32)
+    (throw $e (i32.const 42))
+  )
+)
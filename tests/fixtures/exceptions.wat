@@ -0,0 +1,23 @@
+;; Test exception handling: typed payloads cross function calls and
+;; unmatched catch clauses rethrow to the enclosing handler
+(module
+  (tag $value (param i32 i64))
+  (tag $other)
+
+  (func $raise (param i32)
+    (throw $value (local.get 0) (i64.const 2)))
+
+  ;; Only catches $other: $value propagates to the caller
+  (func $inner (param i32) (result i32)
+    (try (result i32)
+      (do (call $raise (local.get 0)) (i32.const 0))
+      (catch $other (i32.const -1))))
+
+  (func $main (export "wasm_main") (result i32)
+    ;; 40 + 2 = 42
+    (try (result i32)
+      (do (call $inner (i32.const 40)))
+      (catch $value (i32.add (i32.wrap_i64)))
+      (catch_all (i32.const 1)))
+  )
+)
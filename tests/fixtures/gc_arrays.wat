@@ -0,0 +1,60 @@
+;; Test GC array bulk operations, casts and cast branches: builds arrays from
+;; a passive data segment, fills and copies them, then classifies structs
+;; with br_on_cast
+(module
+  (type $bytes (array (mut i8)))
+  (type $ints (array (mut i32)))
+  (type $shape (sub (struct (field i32))))
+  (type $square (sub $shape (struct (field i32) (field i32))))
+
+  ;; 1, 2, 3, 4
+  (data $digits "\01\02\03\04")
+
+  ;; 10 for a square, 1 for any other shape
+  (func $weight (param $s (ref null $shape)) (result i32)
+    (block $is_square (result (ref $square))
+      (br_on_cast $is_square (ref null $shape) (ref $square) (local.get $s))
+      (drop)
+      (return (i32.const 1)))
+    (drop)
+    (i32.const 10))
+
+  (func $sum (param $a (ref null $ints)) (result i32)
+    (local $i i32)
+    (local $acc i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.eq (local.get $i) (array.len (local.get $a))))
+        (local.set $acc
+          (i32.add (local.get $acc) (array.get $ints (local.get $a) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (local.get $acc))
+
+  (func $main (export "wasm_main") (result i32)
+    (local $digits (ref null $bytes))
+    (local $ints (ref null $ints))
+    ;; [1, 2, 3, 4]
+    (local.set $digits (array.new_data $bytes $digits (i32.const 0) (i32.const 4)))
+    ;; [5, 5, 5, 5, 5, 5] then [5, 5, 2, 2, 2, 5]
+    (local.set $ints (array.new $ints (i32.const 5) (i32.const 6)))
+    (array.fill $ints (local.get $ints) (i32.const 2) (i32.const 2) (i32.const 3))
+    ;; [5, 5, 5, 2, 2, 2] after an overlapping copy to the right
+    (array.copy $ints $ints
+      (local.get $ints) (i32.const 1) (local.get $ints) (i32.const 0) (i32.const 5))
+    (array.set $ints (local.get $ints) (i32.const 0)
+      (array.get_u $bytes (local.get $digits) (i32.const 3)))
+    ;; [4, 5, 5, 2, 2, 2] sums to 20; plus a square (10), a shape (1) and
+    ;; ref.test/ref.eq checks (11) gives 42
+    (i32.add
+      (i32.add
+        (call $sum (local.get $ints))
+        (i32.add
+          (call $weight (struct.new $square (i32.const 1) (i32.const 2)))
+          (call $weight (struct.new $shape (i32.const 1)))))
+      (i32.add
+        (i32.mul (i32.const 10)
+          (ref.test (ref $shape) (struct.new $square (i32.const 3) (i32.const 4))))
+        (ref.eq (local.get $ints) (local.get $ints))))
+  )
+)
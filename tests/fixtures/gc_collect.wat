@@ -0,0 +1,60 @@
+;; Test garbage collection: allocates far more than the collection threshold
+;; while keeping a few objects reachable from locals, operands, an array and
+;; a global, then checks those objects survived
+(module
+  (type $node (struct (field $val i32) (field $next (ref null $node))))
+  (type $pair (struct (field (ref null $node)) (field (ref null $node))))
+  (type $nodes (array (mut (ref null $node))))
+
+  (global $last (mut (ref null $pair)) (ref.null $pair))
+
+  (func $cons (param $v i32) (param $next (ref null $node)) (result (ref null $node))
+    (struct.new $node (local.get $v) (local.get $next)))
+
+  ;; Builds the list n, n-1, ..., 1
+  (func $build (param $n i32) (result (ref null $node))
+    (local $list (ref null $node))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $n)))
+        (local.set $list (call $cons (local.get $n) (local.get $list)))
+        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+        (br $next)))
+    (local.get $list))
+
+  (func $sum (param $l (ref null $node)) (result i32)
+    (local $acc i32)
+    (block $done
+      (loop $next
+        (br_if $done (ref.is_null (local.get $l)))
+        (local.set $acc (i32.add (local.get $acc) (struct.get $node $val (local.get $l))))
+        (local.set $l (struct.get $node $next (local.get $l)))
+        (br $next)))
+    (local.get $acc))
+
+  (func $main (export "wasm_main") (result i32)
+    (local $i i32)
+    (local $keep (ref null $nodes))
+    (local.set $keep (array.new_default $nodes (i32.const 4)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eq (local.get $i) (i32.const 200000)))
+        ;; The first list is only held as an operand while the second is built
+        (global.set $last (struct.new $pair
+          (call $build (i32.const 8))
+          (call $build (i32.const 3))))
+        (array.set $nodes (local.get $keep)
+          (i32.and (local.get $i) (i32.const 3))
+          (call $build (i32.const 2)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    ;; 36 (sum of 1..8) + 6 (sum of 1..3) = 42, with the array entries intact
+    (i32.add
+      (i32.add
+        (call $sum (struct.get $pair 0 (global.get $last)))
+        (call $sum (struct.get $pair 1 (global.get $last))))
+      (i32.sub
+        (call $sum (array.get $nodes (local.get $keep) (i32.const 3)))
+        (i32.const 3)))
+  )
+)
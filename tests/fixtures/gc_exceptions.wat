@@ -0,0 +1,51 @@
+;; Test exceptions carrying references across a collection: a list thrown as
+;; a payload is captured with catch_all_ref, so it is only reachable through
+;; the exnref while far more than the collection threshold is allocated. The
+;; exnref is then rethrown and the list summed
+(module
+  (type $node (struct (field $val i32) (field $next (ref null $node))))
+
+  (tag $list (param i32 (ref null $node)))
+
+  (func $cons (param $v i32) (param $next (ref null $node)) (result (ref null $node))
+    (struct.new $node (local.get $v) (local.get $next)))
+
+  (func $sum (param $l (ref null $node)) (result i32)
+    (local $acc i32)
+    (block $done
+      (loop $next
+        (br_if $done (ref.is_null (local.get $l)))
+        (local.set $acc (i32.add (local.get $acc) (struct.get $node $val (local.get $l))))
+        (local.set $l (struct.get $node $next (local.get $l)))
+        (br $next)))
+    (local.get $acc))
+
+  ;; Allocates objects that are dropped right away
+  (func $churn
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.eq (local.get $i) (i32.const 200000)))
+        (drop (call $cons (local.get $i) (ref.null $node)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+
+  (func $main (export "wasm_main") (result i32)
+    (local $exn exnref)
+    (local.set $exn
+      (block $h (result exnref)
+        (try_table (catch_all_ref $h)
+          (throw $list
+            (i32.const 2)
+            (call $cons (i32.const 30) (call $cons (i32.const 10) (ref.null $node)))))
+        (unreachable)))
+    (call $churn)
+    ;; 2 + (30 + 10) = 42
+    (block $caught (result i32 (ref null $node))
+      (try_table (catch $list $caught)
+        (throw_ref (local.get $exn)))
+      (unreachable))
+    (call $sum)
+    (i32.add)
+  )
+)
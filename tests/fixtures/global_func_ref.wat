@@ -0,0 +1,13 @@
+;; Test a function referenced only from a global initializer: it holds the
+;; function and passes the call_indirect signature check once in a table
+(module
+  (type $t (func (result i32)))
+  (table 1 funcref)
+  (func $f (type $t) (i32.const 42))
+  (global $g funcref (ref.func $f))
+
+  (func $main (export "wasm_main") (result i32)
+    (table.set (i32.const 0) (global.get $g))
+    (call_indirect (type $t) (i32.const 0))
+  )
+)
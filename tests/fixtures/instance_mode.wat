@@ -0,0 +1,14 @@
+;; Test memory and globals reached through the instance: memory grows to two
+;; pages and 40 + the new size is stored past the first page, which inline
+;; bounds checks allow by reading the size from the instance: 40 + 2 = 42
+(module
+  (memory 1)
+  (global $n (mut i32) (i32.const 40))
+
+  (func $main (export "wasm_main") (result i32)
+    (drop (memory.grow (i32.const 1)))
+    (global.set $n (i32.add (global.get $n) (memory.size)))
+    (i32.store (i32.const 65536) (global.get $n))
+    (i32.load (i32.const 65536))
+  )
+)
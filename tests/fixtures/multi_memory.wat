@@ -0,0 +1,35 @@
+;; Test that each memory has its own buffer: stores to one memory are not
+;; visible in the other, and each memory grows and copies independently
+(module
+  (memory $a 1)
+  (memory $b 1 3)
+
+  (data (memory $a) (i32.const 0) "\0a")
+  (data (memory $b) (i32.const 0) "\14")
+
+  (func (export "wasm_main") (result i32)
+    ;; Same address, different memories
+    (i32.store8 $b (i32.const 1) (i32.const 7))
+    (if (i32.ne (i32.load8_u $a (i32.const 1)) (i32.const 0))
+      (then (return (i32.const -1))))
+
+    ;; Memory $b grows up to its own maximum: 1 -> 3 pages, then fails
+    (if (i32.ne (memory.grow $b (i32.const 2)) (i32.const 1))
+      (then (return (i32.const -2))))
+    (if (i32.ne (memory.grow $b (i32.const 1)) (i32.const -1))
+      (then (return (i32.const -3))))
+    (if (i32.ne (memory.size $b) (i32.const 3))
+      (then (return (i32.const -4))))
+
+    ;; Copy 20 and 7 from $b into $a, past memory $a's first byte
+    (memory.copy $a $b (i32.const 2) (i32.const 0) (i32.const 2))
+    (memory.fill $b (i32.const 0) (i32.const 0) (i32.const 2))
+
+    ;; $a holds 10, 20 and 7; $b's first bytes are cleared and the last
+    ;; byte of its new pages is writable: 10 + 20 + 7 + 0 + 5 = 42
+    (i32.store8 $b (i32.const 196607) (i32.const 5))
+    (i32.add
+      (i32.add (i32.load8_u $a (i32.const 0)) (i32.load8_u $a (i32.const 2)))
+      (i32.add
+        (i32.add (i32.load8_u $a (i32.const 3)) (i32.load8_u $b (i32.const 0)))
+        (i32.load8_u $b (i32.const 196607))))))
@@ -0,0 +1,46 @@
+;; Test that each table has its own entries, size and maximum, and that
+;; call_indirect and the table instructions honor the table index
+(module
+  (type $f (func (result i32)))
+
+  (table $a 2 funcref)
+  (table $b 1 3 funcref)
+  (table $refs 0 externref)
+
+  (func $ten (type $f) (i32.const 10))
+  (func $twelve (type $f) (i32.const 12))
+  (func $twenty (type $f) (i32.const 20))
+
+  (elem (table $a) (i32.const 0) func $ten $twelve)
+  (elem (table $b) (i32.const 0) func $twenty)
+  (elem $late func $twelve $ten)
+
+  (func (export "wasm_main") (result i32)
+    (local $sum i32)
+    ;; Same index, different tables: 10 + 20
+    (local.set $sum
+      (i32.add
+        (call_indirect $a (type $f) (i32.const 0))
+        (call_indirect $b (type $f) (i32.const 0))))
+
+    ;; Table $b grows up to its own maximum: 1 -> 3 entries, then fails
+    (if (i32.ne (table.grow $b (ref.null func) (i32.const 2)) (i32.const 1))
+      (then (return (i32.const -1))))
+    (if (i32.ne (table.grow $b (ref.null func) (i32.const 1)) (i32.const -1))
+      (then (return (i32.const -2))))
+    (if (i32.ne (table.size $a) (i32.const 2))
+      (then (return (i32.const -3))))
+
+    ;; $b[1..3] = $late = [$twelve, $ten], then $a[0] = $b[1]
+    (table.init $b $late (i32.const 1) (i32.const 0) (i32.const 2))
+    (table.copy $a $b (i32.const 0) (i32.const 1) (i32.const 1))
+
+    ;; An externref table grows and fills on its own
+    (drop (table.grow $refs (ref.null extern) (i32.const 4)))
+    (table.fill $refs (i32.const 0) (ref.null extern) (i32.const 4))
+    (if (i32.ne (table.size $refs) (i32.const 4))
+      (then (return (i32.const -4))))
+
+    ;; 30 + $a[0] (12) = 42
+    (i32.add (local.get $sum) (call_indirect $a (type $f) (i32.const 0))))
+)
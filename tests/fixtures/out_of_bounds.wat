@@ -0,0 +1,9 @@
+;; Test bounds checks: load far past the end of a one-page memory
+(module
+  (memory 1)
+
+  (func $main (export "wasm_main") (result i32)
+    ;; 16 MiB is outside any memory the runtime allocates for this module
+    (i32.load (i32.const 0x1000000))
+  )
+)
@@ -0,0 +1,22 @@
+;; Test tables with i64 indices (table64)
+(module
+  (type $f (func (result i32)))
+
+  (table $t i64 2 funcref)
+
+  (func $forty (type $f) (i32.const 40))
+  (func $two (type $f) (i32.const 2))
+
+  (elem (table $t) (i64.const 0) func $forty $two)
+
+  (func (export "wasm_main") (result i32)
+    ;; Growth returns the old size as an i64
+    (if (i64.ne (table.grow $t (ref.null func) (i64.const 1)) (i64.const 2))
+      (then (return (i32.const -1))))
+    (if (i64.ne (table.size $t) (i64.const 3))
+      (then (return (i32.const -2))))
+    (table.set $t (i64.const 2) (table.get $t (i64.const 1)))
+    (i32.add
+      (call_indirect $t (type $f) (i64.const 0))
+      (call_indirect $t (type $f) (i64.const 2))))
+)
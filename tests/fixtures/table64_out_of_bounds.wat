@@ -0,0 +1,11 @@
+;; Test that a table64 index past 32 bits traps instead of wrapping
+;; (runtime exits with 1)
+(module
+  (type $f (func (result i32)))
+  (table $t i64 1 funcref)
+  (func $one (type $f) (i32.const 1))
+  (elem (table $t) (i64.const 0) func $one)
+
+  (func (export "wasm_main") (result i32)
+    (call_indirect $t (type $f) (i64.const 0x100000000)))
+)
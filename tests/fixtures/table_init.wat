@@ -0,0 +1,10 @@
+;; Test a table init expression: every initial entry holds the function
+(module
+  (type $t (func (result i32)))
+  (func $f (type $t) (i32.const 42))
+  (table 3 funcref (ref.func $f))
+
+  (func $main (export "wasm_main") (result i32)
+    (call_indirect (type $t) (i32.const 2))
+  )
+)
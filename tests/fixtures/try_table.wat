@@ -0,0 +1,26 @@
+;; Test try_table: catch clauses branch to enclosing labels with the payload,
+;; and catch_all_ref/throw_ref rethrow a captured exception
+(module
+  (tag $value (param i32))
+
+  (func $raise (param i32)
+    (throw $value (local.get 0)))
+
+  ;; Catches $value as an exnref and throws it again
+  (func $relay (param i32)
+    (block $h (result exnref)
+      (try_table (catch_all_ref $h)
+        (call $raise (local.get 0)))
+      (return))
+    (throw_ref))
+
+  (func $main (export "wasm_main") (result i32)
+    ;; 40 + 2 = 42
+    (block $caught (result i32)
+      (try_table (catch $value $caught)
+        (call $relay (i32.const 40)))
+      (i32.const 0))
+    (i32.const 2)
+    (i32.add)
+  )
+)
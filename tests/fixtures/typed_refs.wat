@@ -0,0 +1,57 @@
+;; Test typed function references: null checks with br_on_null,
+;; br_on_non_null and ref.as_non_null, with multi-value branch labels
+(module
+  (type $binop (func (param i32 i32) (result i32)))
+
+  (func $add (type $binop) (i32.add (local.get 0) (local.get 1)))
+  (func $mul (type $binop) (i32.mul (local.get 0) (local.get 1)))
+
+  (elem declare func $add $mul)
+
+  ;; Applies $f, or returns both operands unchanged if $f is null
+  (func $apply (param $f (ref null $binop)) (param $a i32) (param $b i32)
+    (result i32 i32)
+    (block $null (result i32 i32)
+      (local.get $a)
+      (local.get $b)
+      ;; The non-null reference stays on top of the operands
+      (br_on_null $null (local.get $f))
+      (call_ref $binop)
+      (i32.const 0)
+      (return)))
+
+  ;; Calls $f if it is non-null, otherwise returns -1
+  (func $maybe_call (param $f (ref null $binop)) (result i32)
+    (block $call (result i32 (ref $binop))
+      (i32.const 6)
+      (br_on_non_null $call (local.get $f))
+      (drop)
+      (return (i32.const -1)))
+    ;; 6 is left below the reference
+    (local.set $f)
+    (i32.const 7)
+    (local.get $f)
+    (call_ref $binop))
+
+  (func (export "wasm_main") (result i32)
+    (local $x i32)
+    (local $y i32)
+    ;; null: (3, 4) passes through; sum 7
+    (call $apply (ref.null $binop) (i32.const 3) (i32.const 4))
+    (i32.add)
+    ;; add: (5 + 6, 0); sum 18
+    (call $apply (ref.func $add) (i32.const 5) (i32.const 6))
+    (i32.add)
+    (i32.add)
+    (local.set $x)
+    ;; null: -1; mul: 6 * 7 = 42; sum 41
+    (i32.add
+      (call $maybe_call (ref.null $binop))
+      (call $maybe_call (ref.func $mul)))
+    (local.set $y)
+    ;; 18 + 41 - 17 = 42, via a non-null reference
+    (call_ref $binop
+      (i32.sub (i32.add (local.get $x) (local.get $y)) (i32.const 17))
+      (i32.const 0)
+      (ref.as_non_null (ref.func $add))))
+)
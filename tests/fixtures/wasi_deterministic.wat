@@ -0,0 +1,22 @@
+;; Test --deterministic execution: the second reading of the virtual
+;; monotonic clock is 1 ms, and 0 / 0 is the canonical NaN, so the exit
+;; status is 40 + 1 + 1 = 42
+(module
+  (import "wasi_snapshot_preview1" "clock_time_get"
+    (func $clock_time_get (param i32 i64 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+
+  (func (export "_start")
+    (local $zero f32)
+    (drop (call $clock_time_get (i32.const 1) (i64.const 0) (i32.const 0)))
+    (drop (call $clock_time_get (i32.const 1) (i64.const 0) (i32.const 0)))
+    (call $proc_exit
+      (i32.add
+        (i32.add
+          (i32.const 40)
+          (i64.eq (i64.load (i32.const 0)) (i64.const 1000000)))
+        (i32.eq
+          (i32.reinterpret_f32 (f32.div (local.get $zero) (local.get $zero)))
+          (i32.const 0x7fc00000)))))
+)
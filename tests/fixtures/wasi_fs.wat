@@ -0,0 +1,31 @@
+;; Test reading a file of a preopened directory (fd 3): file.txt holds
+;; "file", so 4 bytes starting with 'f' are read. The exit status is then the
+;; errno of a read into an iovec array past the end of memory, 21 (FAULT)
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open
+      (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_read"
+    (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 16) "file.txt")
+  ;; An iovec for 64 bytes at 256
+  (data (i32.const 32) "\00\01\00\00\40\00\00\00")
+
+  (func (export "_start")
+    (local $fd i32)
+    (if (call $path_open
+          (i32.const 3) (i32.const 0) (i32.const 16) (i32.const 8)
+          (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 8))
+      (then (call $proc_exit (i32.const 1))))
+    (local.set $fd (i32.load (i32.const 8)))
+    (if (call $fd_read (local.get $fd) (i32.const 32) (i32.const 1) (i32.const 40))
+      (then (call $proc_exit (i32.const 2))))
+    (if (i32.ne (i32.load (i32.const 40)) (i32.const 4))
+      (then (call $proc_exit (i32.const 3))))
+    (if (i32.ne (i32.load8_u (i32.const 256)) (i32.const 0x66))
+      (then (call $proc_exit (i32.const 4))))
+    (call $proc_exit
+      (call $fd_read (local.get $fd) (i32.const 65532) (i32.const 1) (i32.const 40))))
+)
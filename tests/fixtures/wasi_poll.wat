@@ -0,0 +1,22 @@
+;; Test poll_oneoff sleeping on a clock subscription: a 1 ms relative timeout
+;; on the monotonic clock with userdata 7. The exit status is
+;; 100 * errno + 10 * events + the event's userdata, so 0 + 10 + 7 = 17
+(module
+  (import "wasi_snapshot_preview1" "poll_oneoff"
+    (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+
+  (func (export "_start")
+    (i64.store (i32.const 0) (i64.const 7))
+    (i32.store (i32.const 16) (i32.const 1))
+    (i64.store (i32.const 24) (i64.const 1000000))
+    (call $proc_exit
+      (i32.add
+        (i32.mul
+          (call $poll_oneoff (i32.const 0) (i32.const 64) (i32.const 1) (i32.const 128))
+          (i32.const 100))
+        (i32.add
+          (i32.mul (i32.load (i32.const 128)) (i32.const 10))
+          (i32.load (i32.const 64))))))
+)
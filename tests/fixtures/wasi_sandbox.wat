@@ -0,0 +1,22 @@
+;; Test path confinement of a preopened directory (fd 3): file.txt opens,
+;; then the exit status is the errno of opening a file above the directory,
+;; 76 (NOTCAPABLE)
+(module
+  (import "wasi_snapshot_preview1" "path_open"
+    (func $path_open
+      (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 16) "file.txt")
+  (data (i32.const 32) "../outside/secret.txt")
+
+  (func $open (param $path i32) (param $len i32) (result i32)
+    (call $path_open
+      (i32.const 3) (i32.const 0) (local.get $path) (local.get $len)
+      (i32.const 0) (i64.const 2) (i64.const 2) (i32.const 0) (i32.const 8)))
+
+  (func (export "_start")
+    (if (call $open (i32.const 16) (i32.const 8))
+      (then (call $proc_exit (i32.const 1))))
+    (call $proc_exit (call $open (i32.const 32) (i32.const 21))))
+)